@@ -0,0 +1,137 @@
+use crate::data::model::{TimeWindow, ValisError};
+use chrono::NaiveDate;
+use std::cmp::{max, min};
+
+// Let's use generic errors
+type Result<T> = std::result::Result<T, ValisError>;
+
+/// Returns the number of days two ranges have in common,
+/// the ranges include the start and exclude the end
+fn overlap(a: (&NaiveDate, &NaiveDate), b: (&NaiveDate, &NaiveDate)) -> i64 {
+    let (start, end) = (max(a.0, b.0), min(a.1, b.1));
+    max((*end - *start).num_days(), 0)
+}
+
+/// Returns the cost per day of an amount spread over a date range
+///
+/// The range includes the start date and excludes the end date,
+/// it fails if the range is empty or the amount is not a number
+pub fn per_diem(amount: f64, start: &NaiveDate, end: &NaiveDate) -> Result<f64> {
+    if !amount.is_finite() {
+        return Err(ValisError::InvalidAmount(amount.to_string()));
+    }
+    let days = (*end - *start).num_days();
+    if days <= 0 {
+        return Err(ValisError::InvalidLifetimeFormat(format!(
+            "{} is not after {}",
+            end, start
+        )));
+    }
+    Ok(amount / days as f64)
+}
+
+/// An expense whose cost is spread over its lifetime
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expense {
+    pub amount: f64,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl Expense {
+    /// Create an expense lasting from start (included) to end (excluded)
+    pub fn new(amount: f64, start: NaiveDate, end: NaiveDate) -> Result<Expense> {
+        per_diem(amount, &start, &end)?;
+        Ok(Expense { amount, start, end })
+    }
+
+    /// Create an expense lasting for a time window, eg. 3y for a laptop
+    pub fn with_lifetime(amount: f64, start: NaiveDate, lifetime: &TimeWindow) -> Result<Expense> {
+        let (start, end) = lifetime.range(&start);
+        Expense::new(amount, start, end)
+    }
+
+    /// Returns the cost per day of the expense
+    pub fn per_diem(&self) -> f64 {
+        self.amount / (self.end - self.start).num_days() as f64
+    }
+
+    /// Tells if the expense is running on a date
+    pub fn is_active(&self, date: &NaiveDate) -> bool {
+        self.start <= *date && *date < self.end
+    }
+
+    /// Returns the part of the amount that falls within a date range,
+    /// the range includes the start date and excludes the end date
+    pub fn prorate(&self, from: &NaiveDate, to: &NaiveDate) -> f64 {
+        self.per_diem() * overlap((&self.start, &self.end), (from, to)) as f64
+    }
+}
+
+/// Returns the cost per day of a set of expenses on a date
+pub fn daily_cost(expenses: &[Expense], date: &NaiveDate) -> f64 {
+    expenses
+        .iter()
+        .filter(|e| e.is_active(date))
+        .map(|e| e.per_diem())
+        .sum()
+}
+
+/// Returns the cost of a set of expenses within a date range, the
+/// expenses that only partially overlap the range are prorated
+pub fn cost_within(expenses: &[Expense], from: &NaiveDate, to: &NaiveDate) -> f64 {
+    expenses.iter().map(|e| e.prorate(from, to)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::utils;
+
+    #[test]
+    fn test_per_diem() {
+        let (s, e) = (utils::date(1, 1, 2021), utils::date(11, 1, 2021));
+        assert_eq!(per_diem(100.0, &s, &e).unwrap(), 10.0);
+        assert_eq!(per_diem(100.0, &s, &s).is_err(), true);
+        assert_eq!(per_diem(100.0, &e, &s).is_err(), true);
+        assert_eq!(per_diem(f64::NAN, &s, &e).is_err(), true);
+        // leap years count
+        let e =
+            Expense::with_lifetime(366.0, utils::date(1, 1, 2020), &TimeWindow::Year(1)).unwrap();
+        assert_eq!(e.end, utils::date(1, 1, 2021));
+        assert_eq!(e.per_diem(), 1.0);
+    }
+
+    #[test]
+    fn test_prorate() {
+        let laptop =
+            Expense::new(300.0, utils::date(1, 1, 2021), utils::date(31, 1, 2021)).unwrap();
+        let phone = Expense::new(60.0, utils::date(21, 1, 2021), utils::date(10, 2, 2021)).unwrap();
+        // partial windows
+        assert_eq!(
+            laptop.prorate(&utils::date(25, 12, 2020), &utils::date(3, 1, 2021)),
+            20.0
+        );
+        assert_eq!(
+            laptop.prorate(&utils::date(1, 2, 2021), &utils::date(3, 2, 2021)),
+            0.0
+        );
+        assert_eq!(
+            laptop.prorate(&utils::date(1, 1, 2020), &utils::date(1, 1, 2022)),
+            300.0
+        );
+        // aggregation
+        let all = vec![laptop, phone];
+        assert_eq!(daily_cost(&all, &utils::date(1, 1, 2021)), 10.0);
+        assert_eq!(daily_cost(&all, &utils::date(25, 1, 2021)), 13.0);
+        assert_eq!(daily_cost(&all, &utils::date(10, 2, 2021)), 0.0);
+        assert_eq!(
+            cost_within(&all, &utils::date(1, 1, 2021), &utils::date(1, 2, 2021)),
+            300.0 + 33.0
+        );
+        assert_eq!(
+            cost_within(&[], &utils::date(1, 1, 2021), &utils::date(1, 2, 2021)),
+            0.0
+        );
+    }
+}
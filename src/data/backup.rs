@@ -0,0 +1,228 @@
+use super::ledger::{
+    DataError, BACKUP_EXT, BACKUP_PREFIX, BACKUP_TS_FORMAT, SNAPSHOT_EXT, SNAPSHOT_PREFIX,
+};
+use chrono::{Datelike, NaiveDateTime};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Let's use generic errors
+type Result<T> = std::result::Result<T, DataError>;
+
+/// A backup file found in a backup directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub taken_at: NaiveDateTime,
+    pub size: u64,
+}
+
+/// The retention policy of the backups
+///
+/// it keeps the most recent backup of each of the last
+/// `daily` days, `weekly` weeks and `monthly` months
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            daily: 7,
+            weekly: 4,
+            monthly: 12,
+        }
+    }
+}
+
+impl Retention {
+    /// Split a list of backups in the ones to keep and
+    /// the ones to prune, the input must be sorted newest first
+    pub fn apply(&self, backups: &[Backup]) -> (Vec<Backup>, Vec<Backup>) {
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+        let mut months = HashSet::new();
+        backups.iter().cloned().partition(|b| {
+            let d = b.taken_at.date();
+            // evaluate all the buckets, a backup can fill more than one
+            let day = days.len() < self.daily && days.insert(d);
+            let week = weeks.len() < self.weekly
+                && weeks.insert((d.iso_week().year(), d.iso_week().week()));
+            let month = months.len() < self.monthly && months.insert((d.year(), d.month()));
+            day || week || month
+        })
+    }
+}
+
+/// List the backups in a directory, newest first
+pub fn list(dir: &Path) -> Result<Vec<Backup>> {
+    list_files(dir, BACKUP_PREFIX, BACKUP_EXT)
+}
+
+/// List the snapshots in a directory, newest first
+pub fn snapshots(dir: &Path) -> Result<Vec<Backup>> {
+    list_files(dir, SNAPSHOT_PREFIX, SNAPSHOT_EXT)
+}
+
+/// List the files named prefix, timestamp, ext in a directory, newest first
+fn list_files(dir: &Path, prefix: &str, ext: &str) -> Result<Vec<Backup>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(prefix)
+            || !name.ends_with(ext)
+            || name.len() < prefix.len() + ext.len()
+        {
+            continue;
+        }
+        let ts = &name[prefix.len()..name.len() - ext.len()];
+        if let Ok(taken_at) = NaiveDateTime::parse_from_str(ts, BACKUP_TS_FORMAT) {
+            backups.push(Backup {
+                path: entry.path(),
+                taken_at,
+                size: entry.metadata()?.len(),
+            });
+        }
+    }
+    backups.sort_by(|a, b| b.taken_at.cmp(&a.taken_at));
+    Ok(backups)
+}
+
+/// Remove the backups in a directory that are not
+/// retained by the policy, returns the removed ones
+pub fn prune(dir: &Path, retention: &Retention) -> Result<Vec<Backup>> {
+    let (_, pruned) = retention.apply(&list(dir)?);
+    for b in pruned.iter() {
+        fs::remove_file(&b.path)?;
+    }
+    Ok(pruned)
+}
+
+/// Remove the snapshots in a directory but the
+/// last `keep` ones, returns the removed ones
+pub fn prune_snapshots(dir: &Path, keep: usize) -> Result<Vec<Backup>> {
+    let pruned = snapshots(dir)?
+        .into_iter()
+        .skip(keep)
+        .collect::<Vec<Backup>>();
+    for b in pruned.iter() {
+        fs::remove_file(&b.path)?;
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::utils;
+
+    fn backup(d: u32, m: u32, y: i32) -> Backup {
+        let taken_at = utils::date(d, m, y).and_hms(10, 0, 0);
+        Backup {
+            path: PathBuf::from(format!(
+                "{}{}{}",
+                BACKUP_PREFIX,
+                taken_at.format(BACKUP_TS_FORMAT),
+                BACKUP_EXT
+            )),
+            taken_at,
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn test_retention() {
+        // one backup a day for 60 days, newest first
+        let mut backups = (0..60)
+            .map(|i| {
+                let d = utils::date(1, 3, 2021) - chrono::Duration::days(i);
+                backup(d.day(), d.month(), d.year())
+            })
+            .collect::<Vec<Backup>>();
+        // plus a second backup on the newest day
+        let mut extra = backups[0].clone();
+        extra.taken_at = extra.taken_at - chrono::Duration::hours(1);
+        backups.insert(1, extra);
+        let r = Retention {
+            daily: 3,
+            weekly: 2,
+            monthly: 3,
+        };
+        let (keep, prune) = r.apply(&backups);
+        assert_eq!(keep.len() + prune.len(), 61);
+        // the extra one is pruned
+        assert_eq!(keep.contains(&backups[1]), false);
+        // the last 3 days
+        assert_eq!(keep[0].taken_at.date(), utils::date(1, 3, 2021));
+        assert_eq!(keep[1].taken_at.date(), utils::date(28, 2, 2021));
+        assert_eq!(keep[2].taken_at.date(), utils::date(27, 2, 2021));
+        // 1.3.2021 is a monday, so 28.2 already falls in the previous
+        // week, and the months are march, february and january
+        assert_eq!(keep[3].taken_at.date(), utils::date(31, 1, 2021));
+        assert_eq!(keep.len(), 4);
+        // keep nothing
+        let none = Retention {
+            daily: 0,
+            weekly: 0,
+            monthly: 0,
+        };
+        assert_eq!(none.apply(&backups).0.len(), 0);
+    }
+
+    #[test]
+    fn test_list_prune() {
+        let d = tempfile::TempDir::new().unwrap();
+        for b in [backup(1, 1, 2021), backup(2, 1, 2021), backup(2, 2, 2021)].iter() {
+            fs::write(d.path().join(&b.path), "{}").unwrap();
+        }
+        fs::write(d.path().join("something.else"), "").unwrap();
+        let found = list(d.path()).unwrap();
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].taken_at.date(), utils::date(2, 2, 2021));
+        assert_eq!(found[0].size, 2);
+        // keep only the last one
+        let r = Retention {
+            daily: 1,
+            weekly: 0,
+            monthly: 0,
+        };
+        let pruned = prune(d.path(), &r).unwrap();
+        assert_eq!(pruned.len(), 2);
+        assert_eq!(list(d.path()).unwrap().len(), 1);
+        // missing dir
+        assert_eq!(list(&d.path().join("missing")).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_prune_snapshots() {
+        let d = tempfile::TempDir::new().unwrap();
+        for day in 1..=4 {
+            let ts = utils::date(day, 1, 2021).and_hms(10, 0, 0);
+            let name = format!(
+                "{}{}{}",
+                SNAPSHOT_PREFIX,
+                ts.format(BACKUP_TS_FORMAT),
+                SNAPSHOT_EXT
+            );
+            fs::write(d.path().join(name), "").unwrap();
+        }
+        // the backups are not snapshots and the other way around
+        fs::write(d.path().join(&backup(1, 1, 2021).path), "{}").unwrap();
+        assert_eq!(snapshots(d.path()).unwrap().len(), 4);
+        assert_eq!(list(d.path()).unwrap().len(), 1);
+        let pruned = prune_snapshots(d.path(), 3).unwrap();
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].taken_at.date(), utils::date(1, 1, 2021));
+        let left = snapshots(d.path()).unwrap();
+        assert_eq!(left.len(), 3);
+        assert_eq!(left[0].taken_at.date(), utils::date(4, 1, 2021));
+        assert_eq!(list(d.path()).unwrap().len(), 1);
+    }
+}
@@ -1,13 +1,16 @@
 use super::{
-    ledger::{DataError, DataStore},
-    model::{Entity, Tag, Uuid},
+    formats,
+    ledger::{DataError, DataStore, ImportReport},
+    model::{AccessRole, Entity, Tag, Uuid},
+    query::Query,
     utils,
 };
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 // Let's use generic errors
 type Result<T> = std::result::Result<T, CtxError>;
@@ -18,6 +21,12 @@ pub enum CtxError {
     DatasetNotFound,
     DatasetExists,
     DatasetInUse,
+    /// the dataset is open in another process, see DataError::Locked
+    DatasetLocked(u32),
+    /// the user is not allowed to open the dataset or to manage it
+    AccessDenied,
+    /// the dataset is protected by a passphrase and has not been unlocked
+    DatasetSealed,
     GenericError(String),
 }
 
@@ -25,7 +34,7 @@ impl Error for CtxError {}
 
 impl Display for CtxError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
@@ -42,14 +51,40 @@ impl From<DataError> for CtxError {
 }
 
 const INDEX_FILE: &str = "context.index.toml";
+const ACCESS_FILE: &str = "context.access.toml";
+const BACKUP_DIR: &str = "backups";
+const SNAPSHOT_DIR: &str = "snapshots";
+/// where the contexts merged into another one are moved
+const ARCHIVE_DIR: &str = "archive";
+/// the encrypted datastore of a protected context
+const SEALED_FILE: &str = "datastore.sealed";
+/// the name of a protected context, that is readable without the passphrase
+const NAME_FILE: &str = "context.name";
+/// the file sled writes in every database directory
+const SLED_CONF_FILE: &str = "conf";
 
 /// system keys
 const META_DATASET_NAME: &str = "DATASET_NAME";
 
-#[derive(Debug)]
 pub struct ContextManager {
     base_path: PathBuf,
     contexts: BTreeMap<String, String>,
+    // the uids of the users that can open a context, by context name
+    access: BTreeMap<String, BTreeSet<String>>,
+    // the passphrases of the unlocked contexts, by context name
+    keys: BTreeMap<String, String>,
+}
+
+// the passphrases stay out of the logs
+impl std::fmt::Debug for ContextManager {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("ContextManager")
+            .field("base_path", &self.base_path)
+            .field("contexts", &self.contexts)
+            .field("access", &self.access)
+            .field("unlocked", &self.keys.keys().collect::<Vec<&String>>())
+            .finish()
+    }
 }
 
 /// ContextManager allows to maintain
@@ -88,6 +123,8 @@ impl ContextManager {
         let mut ctx = ContextManager {
             base_path: base.to_path_buf(),
             contexts: BTreeMap::new(),
+            access: BTreeMap::new(),
+            keys: BTreeMap::new(),
         };
         // if it is not a dir then die
         if !ctx.base_path.is_dir() {
@@ -95,30 +132,235 @@ impl ContextManager {
         }
         // if does not exists try to create
         fs::create_dir_all(&ctx.base_path)?;
-        // try to load the contexts
-        let index_path = base.join(INDEX_FILE);
-        if !index_path.exists() {
+        // try to load the contexts, opening them only if needed
+        if !ctx.load_index()? {
             ctx.build_index()?;
         }
+        ctx.load_access()?;
         Ok(ctx)
     }
 
+    /// Load the index of the contexts, returns false if the
+    /// index is missing or does not match the datastores on disk
+    fn load_index(&mut self) -> Result<bool> {
+        let path = self.base_path.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(false);
+        }
+        let contexts: BTreeMap<String, String> = match toml::from_str(&fs::read_to_string(path)?) {
+            Ok(c) => c,
+            Err(_) => return Ok(false),
+        };
+        let indexed = contexts.values().cloned().collect::<BTreeSet<String>>();
+        if indexed != self.datastore_dirs()? {
+            return Ok(false);
+        }
+        self.contexts = contexts;
+        Ok(true)
+    }
+
+    /// Store the index of the contexts
+    fn save_index(&self) -> Result<()> {
+        let content =
+            toml::to_string(&self.contexts).map_err(|e| CtxError::GenericError(e.to_string()))?;
+        fs::write(self.base_path.join(INDEX_FILE), content)?;
+        Ok(())
+    }
+
+    /// Returns the names of the directories that hold a datastore
+    fn datastore_dirs(&self) -> Result<BTreeSet<String>> {
+        let mut dirs = BTreeSet::new();
+        for entry in fs::read_dir(&self.base_path)? {
+            let entry = entry?;
+            if is_datastore(&entry.path()) {
+                dirs.insert(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// Load the users that can open the contexts
+    fn load_access(&mut self) -> Result<()> {
+        let path = self.base_path.join(ACCESS_FILE);
+        if path.exists() {
+            self.access = toml::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| CtxError::GenericError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Store the users that can open the contexts
+    fn save_access(&self) -> Result<()> {
+        let content =
+            toml::to_string(&self.access).map_err(|e| CtxError::GenericError(e.to_string()))?;
+        fs::write(self.base_path.join(ACCESS_FILE), content)?;
+        Ok(())
+    }
+
+    /// Allow a user to open a context
+    pub fn grant_access(&mut self, name: &str, uid: &str) -> Result<()> {
+        if !self.contexts.contains_key(name) {
+            return Err(CtxError::DatasetNotFound);
+        }
+        if self
+            .access
+            .entry(name.to_owned())
+            .or_default()
+            .insert(uid.to_owned())
+        {
+            self.save_access()?;
+        }
+        Ok(())
+    }
+
+    /// Stop a user from opening a context
+    pub fn revoke_access(&mut self, name: &str, uid: &str) -> Result<()> {
+        if let Some(users) = self.access.get_mut(name) {
+            if users.remove(uid) {
+                self.save_access()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the uids of the users that can open a context
+    pub fn users_of(&self, name: &str) -> Vec<String> {
+        self.access
+            .get(name)
+            .map(|users| users.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Tells if a user can open a context, the contexts
+    /// without any user recorded can be opened by anyone
+    pub fn can_open(&self, name: &str, uid: &str) -> bool {
+        match self.access.get(name) {
+            Some(users) if !users.is_empty() => users.contains(uid),
+            _ => true,
+        }
+    }
+
+    /// Checks that a user can open a context and has at least a role in
+    /// its datastore, eg. to delete it, being able to open it is not
+    /// enough. A protected context has to be unlocked first
+    pub fn authorize(&self, name: &str, user: &Entity, role: AccessRole) -> Result<()> {
+        let mut ds = self.open_datastore_as(name, &user.uid(), Duration::from_secs(0))?;
+        let granted = ds.set_principal(Some(user)).is_ok() && ds.role_of(user) >= role;
+        ds.close()?;
+        match granted {
+            true => Ok(()),
+            false => Err(CtxError::AccessDenied),
+        }
+    }
+
+    /// Returns the names of the contexts a user can open
+    pub fn contexts_of(&self, uid: &str) -> Vec<String> {
+        self.contexts
+            .keys()
+            .filter(|name| self.can_open(name, uid))
+            .cloned()
+            .collect()
+    }
+
+    /// Tells if a context is protected by a passphrase
+    pub fn is_protected(&self, name: &str) -> bool {
+        match self.contexts.get(name) {
+            Some(uid) => self.base_path.join(uid).join(SEALED_FILE).exists(),
+            None => false,
+        }
+    }
+
+    /// Tells if a context is protected and has not been unlocked yet
+    pub fn is_locked(&self, name: &str) -> bool {
+        self.is_protected(name) && !self.keys.contains_key(name)
+    }
+
+    /// Unlock a protected context with its passphrase, the passphrase
+    /// is kept in memory until the context is locked again
+    pub fn unlock(&mut self, name: &str, passphrase: &str) -> Result<()> {
+        let uid = self.contexts.get(name).ok_or(CtxError::DatasetNotFound)?;
+        if !self.is_protected(name) {
+            return Ok(());
+        }
+        let sealed = fs::read(self.base_path.join(uid).join(SEALED_FILE))?;
+        if formats::decrypt(&sealed, passphrase).is_err() {
+            return Err(CtxError::AccessDenied);
+        }
+        self.keys.insert(name.to_owned(), passphrase.to_owned());
+        Ok(())
+    }
+
+    /// Forget the passphrase of a context, it has to be unlocked
+    /// again to be opened. The datastores already open are not affected
+    pub fn lock(&mut self, name: &str) {
+        self.keys.remove(name);
+    }
+
     /// Open
     pub fn open_datastore(&self, name: &str) -> Result<DataStore> {
+        self.open_datastore_wait(name, Duration::from_secs(0))
+    }
+
+    /// Open the datastore of a context for a user, see
+    /// open_datastore_wait and can_open
+    pub fn open_datastore_as(&self, name: &str, uid: &str, timeout: Duration) -> Result<DataStore> {
+        if !self.contexts.contains_key(name) {
+            return Err(CtxError::DatasetNotFound);
+        }
+        if !self.can_open(name, uid) {
+            return Err(CtxError::AccessDenied);
+        }
+        self.open_datastore_wait(name, timeout)
+    }
+
+    /// Open the datastore of a context, waiting up to a timeout
+    /// when it is in use by another process
+    pub fn open_datastore_wait(&self, name: &str, timeout: Duration) -> Result<DataStore> {
         match self.contexts.get(name) {
             Some(uid) => {
                 let path = self.base_path.join(uid);
-                if let Ok(ds) = DataStore::open(&path) {
-                    return Ok(ds);
+                let opened = match self.is_protected(name) {
+                    true => match self.keys.get(name) {
+                        Some(p) => DataStore::open_sealed_wait(&path.join(SEALED_FILE), p, timeout),
+                        None => return Err(CtxError::DatasetSealed),
+                    },
+                    false => DataStore::open_wait(&path, timeout),
+                };
+                match opened {
+                    Ok(ds) => Ok(ds),
+                    Err(DataError::Locked(pid)) => Err(CtxError::DatasetLocked(pid)),
+                    Err(_) => Err(CtxError::DatasetInUse),
                 }
-                Err(CtxError::DatasetInUse)
             }
             None => Err(CtxError::DatasetNotFound),
         }
     }
 
-    /// Setup a new datastore
-    pub fn new_datastore(&mut self, owner: &Entity, root: &Entity) -> Result<String> {
+    /// Returns the directory where the backups of a context are stored
+    pub fn backup_dir(&self, name: &str) -> Result<PathBuf> {
+        match self.contexts.get(name) {
+            Some(uid) => Ok(self.base_path.join(uid).join(BACKUP_DIR)),
+            None => Err(CtxError::DatasetNotFound),
+        }
+    }
+
+    /// Returns the directory where the snapshots of a context are stored
+    pub fn snapshot_dir(&self, name: &str) -> Result<PathBuf> {
+        match self.contexts.get(name) {
+            Some(uid) => Ok(self.base_path.join(uid).join(SNAPSHOT_DIR)),
+            None => Err(CtxError::DatasetNotFound),
+        }
+    }
+
+    /// Setup a new datastore, when a passphrase is given the context
+    /// is protected by it and it is never written to disk in clear,
+    /// the new context is unlocked
+    pub fn new_datastore(
+        &mut self,
+        owner: &Entity,
+        root: &Entity,
+        passphrase: Option<&str>,
+    ) -> Result<String> {
         if self.contexts.contains_key(&String::from(root.name())) {
             return Err(CtxError::DatasetExists);
         }
@@ -133,52 +375,198 @@ impl ContextManager {
             .clone()
             .with_sponsor(&owner)
             .with_tag(Tag::System("root".to_owned()));
-        // get the dataset name and uid
-        let ds_uid = utils::id(&Uuid::new_v4());
         let ds_name = String::from(root.name());
-        // dataset path
-        let db_path = self.base_path.join(Path::new(&ds_uid));
-        // create the datastore
-        let mut ds = DataStore::open(db_path.as_path())?;
+        let (ds_uid, mut ds) = self.create_datastore(passphrase)?;
         ds.init(&owner)?;
         ds.add(&root)?;
-        ds.set_meta(META_DATASET_NAME, root.name())?;
-        ds.close();
-        // insert the datastore to the context
-        self.contexts.insert(ds_name, ds_uid);
+        self.register(&ds_name, &ds_uid, ds, passphrase)?;
+        // the owner can open it
+        self.grant_access(&ds_name, &owner.uid())?;
         // return the dataset name
         Ok(root.name().to_owned())
     }
 
+    /// Copy a context into a new one, optionally with only the entities
+    /// matching the tags and class of a filter, see DataStore::copy_into.
+    ///
+    /// The clone can be opened by the same users and the clone
+    /// of a protected context is protected by the same passphrase
+    pub fn clone(&mut self, name: &str, new_name: &str, filter: &Query) -> Result<String> {
+        if self.contexts.contains_key(new_name) {
+            return Err(CtxError::DatasetExists);
+        }
+        let mut source = self.open_datastore(name)?;
+        let passphrase = self.keys.get(name).cloned();
+        let (ds_uid, mut ds) = self.create_datastore(passphrase.as_deref())?;
+        source.copy_into(&mut ds, filter)?;
+        source.close()?;
+        self.register(new_name, &ds_uid, ds, passphrase.as_deref())?;
+        if let Some(users) = self.access.get(name).cloned() {
+            self.access.insert(new_name.to_owned(), users);
+            self.save_access()?;
+        }
+        Ok(new_name.to_owned())
+    }
+
+    /// Merge a context into another one, see DataStore::merge_from,
+    /// then archive the source context.
+    ///
+    /// The archived context is moved out of the way in the archive
+    /// directory, where it can be recovered from by hand
+    pub fn merge(&mut self, source: &str, target: &str) -> Result<ImportReport> {
+        if source == target {
+            return Err(CtxError::GenericError(
+                "cannot merge a context into itself".to_owned(),
+            ));
+        }
+        let mut from = self.open_datastore(source)?;
+        let mut into = self.open_datastore(target)?;
+        let report = into.merge_from(&from)?;
+        from.close()?;
+        into.close()?;
+        drop((from, into));
+        self.archive(source)?;
+        Ok(report)
+    }
+
+    /// Rename a context, the new name is recorded in the datastore
+    /// so a protected context has to be unlocked first
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        if new_name.trim().is_empty() {
+            return Err(CtxError::GenericError(
+                "the context name cannot be empty".to_owned(),
+            ));
+        }
+        if self.contexts.contains_key(new_name) {
+            return Err(CtxError::DatasetExists);
+        }
+        let mut ds = self.open_datastore(name)?;
+        ds.set_meta(META_DATASET_NAME, new_name)?;
+        ds.close()?;
+        drop(ds);
+        let uid = self
+            .contexts
+            .remove(name)
+            .ok_or(CtxError::DatasetNotFound)?;
+        let path = self.base_path.join(&uid);
+        if path.join(SEALED_FILE).exists() {
+            fs::write(path.join(NAME_FILE), new_name)?;
+        }
+        self.contexts.insert(new_name.to_owned(), uid);
+        self.save_index()?;
+        if let Some(p) = self.keys.remove(name) {
+            self.keys.insert(new_name.to_owned(), p);
+        }
+        if let Some(users) = self.access.remove(name) {
+            self.access.insert(new_name.to_owned(), users);
+            self.save_access()?;
+        }
+        Ok(())
+    }
+
+    /// Delete a context, the datastore is archived like
+    /// the merged ones, see merge. A context in use by
+    /// another process cannot be deleted
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        let uid = self.contexts.get(name).ok_or(CtxError::DatasetNotFound)?;
+        // the protected ones are not opened, the lock file tells
+        let sealed = self.base_path.join(uid).join(SEALED_FILE);
+        match self.open_datastore(name) {
+            Ok(mut ds) => {
+                ds.close()?;
+                drop(ds);
+            }
+            Err(CtxError::DatasetSealed) if !DataStore::sealed_in_use(&sealed) => {}
+            Err(CtxError::DatasetSealed) => return Err(CtxError::DatasetInUse),
+            Err(e) => return Err(e),
+        }
+        self.archive(name)
+    }
+
+    /// Move the datastore of a context to the archive directory
+    /// and forget about the context
+    fn archive(&mut self, name: &str) -> Result<()> {
+        let uid = self
+            .contexts
+            .remove(name)
+            .ok_or(CtxError::DatasetNotFound)?;
+        let archive = self.base_path.join(ARCHIVE_DIR);
+        fs::create_dir_all(&archive)?;
+        fs::rename(self.base_path.join(&uid), archive.join(&uid))?;
+        self.save_index()?;
+        self.keys.remove(name);
+        if self.access.remove(name).is_some() {
+            self.save_access()?;
+        }
+        Ok(())
+    }
+
+    /// Create an empty datastore for a new context and returns
+    /// its uid, a protected one lives in memory until registered
+    fn create_datastore(&self, passphrase: Option<&str>) -> Result<(String, DataStore)> {
+        let ds_uid = utils::id(&Uuid::new_v4());
+        let ds = match passphrase {
+            Some(_) => DataStore::open_in_memory()?,
+            None => DataStore::open(&self.base_path.join(&ds_uid))?,
+        };
+        Ok((ds_uid, ds))
+    }
+
+    /// Name a datastore created with create_datastore and add
+    /// it to the contexts, sealing it if there is a passphrase
+    fn register(
+        &mut self,
+        name: &str,
+        uid: &str,
+        mut ds: DataStore,
+        passphrase: Option<&str>,
+    ) -> Result<()> {
+        ds.set_meta(META_DATASET_NAME, name)?;
+        if let Some(p) = passphrase {
+            let db_path = self.base_path.join(uid);
+            fs::create_dir_all(&db_path)?;
+            ds.seal(&db_path.join(SEALED_FILE), p)?;
+            fs::write(db_path.join(NAME_FILE), name)?;
+            self.keys.insert(name.to_owned(), p.to_owned());
+        }
+        ds.close()?;
+        self.contexts.insert(name.to_owned(), uid.to_owned());
+        self.save_index()
+    }
+
     /// Index the base directory searching for the
-    /// databases and builds the indexes
+    /// databases and builds the indexes, the index
+    /// is stored to avoid opening them on every start
     pub fn build_index(&mut self) -> Result<usize> {
-        let dirs = fs::read_dir(&self.base_path)?;
-        // cycle over files
-        for entry in dirs {
-            if let Ok(entry) = entry {
-                if let Ok(file_type) = entry.file_type() {
-                    if !file_type.is_dir() {
-                        continue;
-                    }
-                    // it's a dir and we can open it
-                    let uid = entry.file_name().to_string_lossy().to_string();
-                    let path = self.base_path.join(entry.file_name());
-                    if let Ok(mut ds) = DataStore::open(&path) {
-                        let name = ds
-                            .get_meta(META_DATASET_NAME)
-                            .unwrap_or("default".to_owned());
-                        self.contexts.insert(name, uid);
-                        // close the dataset
-                        ds.close();
-                    }
+        self.contexts.clear();
+        for uid in self.datastore_dirs()? {
+            let path = self.base_path.join(&uid);
+            // the protected contexts cannot be opened yet
+            if path.join(SEALED_FILE).exists() {
+                if let Ok(name) = fs::read_to_string(path.join(NAME_FILE)) {
+                    self.contexts.insert(name.trim().to_owned(), uid);
                 }
+                continue;
+            }
+            if let Ok(mut ds) = DataStore::open(&path) {
+                let name = ds
+                    .get_meta(META_DATASET_NAME)
+                    .unwrap_or("default".to_owned());
+                self.contexts.insert(name, uid);
+                // close the dataset
+                ds.close()?;
             }
         }
+        self.save_index()?;
         Ok(self.contexts.len())
     }
 }
 
+/// Tells if a directory holds a datastore, either sealed or not
+fn is_datastore(path: &Path) -> bool {
+    path.is_dir() && (path.join(SLED_CONF_FILE).exists() || path.join(SEALED_FILE).exists())
+}
+
 #[cfg(test)]
 mod test {
 
@@ -198,7 +586,7 @@ mod test {
         let owner = Entity::from("bob").unwrap();
         let root = Entity::from("acme").unwrap();
         // add context
-        let ds = ctx.new_datastore(&owner, &root);
+        let ds = ctx.new_datastore(&owner, &root, None);
         assert_eq!(ds.is_ok(), true);
         let _ds = ds.unwrap();
         assert_eq!(ctx.size(), 1);
@@ -210,8 +598,265 @@ mod test {
         let _ds = ctx.open_datastore(root.name());
         assert_eq!(_ds.is_err(), true);
         // add existing context
-        let _ds = ctx.new_datastore(&owner, &root);
+        let _ds = ctx.new_datastore(&owner, &root, None);
         assert_eq!(_ds.is_err(), true);
         // add
     }
+
+    #[test]
+    fn test_access() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        let owner = Entity::from("bob").unwrap();
+        let jane = Entity::from("jane").unwrap();
+        let root = Entity::from("acme").unwrap();
+        let name = ctx.new_datastore(&owner, &root, None).unwrap();
+        // only the owner can open it
+        assert_eq!(ctx.users_of(&name), vec![owner.uid()]);
+        assert_eq!(ctx.can_open(&name, &owner.uid()), true);
+        assert_eq!(ctx.can_open(&name, &jane.uid()), false);
+        assert_eq!(
+            ctx.open_datastore_as(&name, &jane.uid(), Duration::from_secs(0))
+                .err(),
+            Some(CtxError::AccessDenied)
+        );
+        assert_eq!(
+            ctx.grant_access("nope", &jane.uid()),
+            Err(CtxError::DatasetNotFound)
+        );
+        ctx.grant_access(&name, &jane.uid()).unwrap();
+        assert_eq!(ctx.contexts_of(&jane.uid()), vec![name.clone()]);
+        let mut ds = ctx
+            .open_datastore_as(&name, &jane.uid(), Duration::from_secs(0))
+            .unwrap();
+        ds.close().unwrap();
+        drop(ds);
+        // opening it is not enough to manage it
+        assert_eq!(ctx.authorize(&name, &owner, AccessRole::Owner), Ok(()));
+        assert_eq!(
+            ctx.authorize(&name, &jane, AccessRole::Viewer),
+            Err(CtxError::AccessDenied)
+        );
+        // the access survives a restart
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        assert_eq!(ctx.can_open(&name, &jane.uid()), true);
+        ctx.revoke_access(&name, &jane.uid()).unwrap();
+        assert_eq!(ctx.contexts_of(&jane.uid()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_index() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        assert_eq!(d.path().join(INDEX_FILE).exists(), true);
+        let owner = Entity::from("bob").unwrap();
+        let acme = ctx
+            .new_datastore(&owner, &Entity::from("acme").unwrap(), None)
+            .unwrap();
+        let home = ctx
+            .new_datastore(&owner, &Entity::from("home").unwrap(), Some("secret"))
+            .unwrap();
+        // the index is used, the datastores are not opened
+        let ds = ctx.open_datastore(&acme).unwrap();
+        let ctx = ContextManager::new(&d.path()).unwrap();
+        assert_eq!(ctx.size(), 2);
+        drop(ds);
+        // a directory that is not a datastore is ignored
+        fs::create_dir(d.path().join("other")).unwrap();
+        assert_eq!(
+            ContextManager::new(&d.path())
+                .unwrap()
+                .load_index()
+                .unwrap(),
+            true
+        );
+        // a missing datastore makes the index stale
+        let acme_path = PathBuf::from(&ctx.list()[0].1);
+        fs::remove_dir_all(&acme_path).unwrap();
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        assert_eq!(ctx.size(), 1);
+        assert_eq!(ctx.list()[0].0, home);
+        assert_eq!(ctx.load_index().unwrap(), true);
+        // and so does a new one
+        fs::create_dir(&acme_path).unwrap();
+        fs::write(acme_path.join(SEALED_FILE), "sealed").unwrap();
+        fs::write(acme_path.join(NAME_FILE), "restored").unwrap();
+        let ctx = ContextManager::new(&d.path()).unwrap();
+        assert_eq!(ctx.size(), 2);
+        assert_eq!(ctx.is_protected("restored"), true);
+    }
+
+    #[test]
+    fn test_clone() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        let owner = Entity::from("bob").unwrap();
+        let name = ctx
+            .new_datastore(&owner, &Entity::from("acme").unwrap(), None)
+            .unwrap();
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&owner)
+            .with_tag(Tag::Generic("consulting".to_owned()));
+        let mut ds = ctx.open_datastore(&name).unwrap();
+        ds.add(&jane).unwrap();
+        ds.close().unwrap();
+        drop(ds);
+        // only the consulting entities and the owner
+        let filter = Query {
+            tags: vec!["consulting".to_owned()],
+            ..Query::default()
+        };
+        assert_eq!(
+            ctx.clone(&name, &name, &filter).err(),
+            Some(CtxError::DatasetExists)
+        );
+        let clone = ctx.clone(&name, "consulting", &filter).unwrap();
+        assert_eq!(ctx.size(), 2);
+        assert_eq!(ctx.users_of(&clone), vec![owner.uid()]);
+        let ds = ctx.open_datastore(&clone).unwrap();
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap().is_some(), true);
+        assert_eq!(ds.iter_entities().count(), 2);
+        drop(ds);
+        // the clone survives a restart
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        assert_eq!(ctx.size(), 2);
+        // a protected context gives a protected clone
+        let home = ctx
+            .new_datastore(&owner, &Entity::from("home").unwrap(), Some("secret"))
+            .unwrap();
+        let copy = ctx.clone(&home, "home copy", &Query::default()).unwrap();
+        assert_eq!(ctx.is_protected(&copy), true);
+        ctx.lock(&copy);
+        ctx.unlock(&copy, "secret").unwrap();
+        assert_eq!(ctx.open_datastore(&copy).is_ok(), true);
+    }
+
+    #[test]
+    fn test_merge() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        let owner = Entity::from("bob").unwrap();
+        let acme = ctx
+            .new_datastore(&owner, &Entity::from("acme").unwrap(), None)
+            .unwrap();
+        let work = ctx
+            .new_datastore(&owner, &Entity::from("work").unwrap(), None)
+            .unwrap();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&owner);
+        let mut ds = ctx.open_datastore(&work).unwrap();
+        ds.add(&jane).unwrap();
+        ds.close().unwrap();
+        drop(ds);
+        assert_eq!(ctx.merge(&acme, &acme).is_err(), true);
+        assert_eq!(
+            ctx.merge("nope", &acme).err(),
+            Some(CtxError::DatasetNotFound)
+        );
+        let work_path = PathBuf::from(&ctx.list()[1].1);
+        let report = ctx.merge(&work, &acme).unwrap();
+        assert_eq!(report.imported > 0, true);
+        // the source is archived
+        assert_eq!(ctx.size(), 1);
+        assert_eq!(ctx.users_of(&work).len(), 0);
+        assert_eq!(work_path.exists(), false);
+        let archived = d
+            .path()
+            .join(ARCHIVE_DIR)
+            .join(work_path.file_name().unwrap());
+        assert_eq!(archived.exists(), true);
+        assert_eq!(ContextManager::new(&d.path()).unwrap().size(), 1);
+        let ds = ctx.open_datastore(&acme).unwrap();
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap().is_some(), true);
+    }
+
+    #[test]
+    fn test_rename_delete() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        let owner = Entity::from("bob").unwrap();
+        let acme = ctx
+            .new_datastore(&owner, &Entity::from("acme").unwrap(), None)
+            .unwrap();
+        let home = ctx
+            .new_datastore(&owner, &Entity::from("home").unwrap(), Some("secret"))
+            .unwrap();
+        assert_eq!(ctx.rename(&acme, &home), Err(CtxError::DatasetExists));
+        assert_eq!(ctx.rename("nope", "other"), Err(CtxError::DatasetNotFound));
+        assert_eq!(ctx.rename(&acme, " ").is_err(), true);
+        ctx.rename(&acme, "work").unwrap();
+        ctx.rename(&home, "family").unwrap();
+        assert_eq!(ctx.users_of("work"), vec![owner.uid()]);
+        assert_eq!(ctx.users_of(&acme).len(), 0);
+        assert_eq!(ctx.is_locked("family"), false);
+        // the names survive a restart and a rebuild of the index
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        ctx.build_index().unwrap();
+        let names = ctx.list().into_iter().map(|(n, _)| n).collect::<Vec<_>>();
+        assert_eq!(names, vec!["family", "work"]);
+        // a context in use cannot be deleted
+        let mut ds = ctx.open_datastore("work").unwrap();
+        assert_eq!(
+            ctx.delete("work"),
+            Err(CtxError::DatasetLocked(std::process::id()))
+        );
+        ds.close().unwrap();
+        drop(ds);
+        ctx.delete("work").unwrap();
+        // a locked context can be deleted
+        ctx.delete("family").unwrap();
+        assert_eq!(ctx.size(), 0);
+        assert_eq!(ctx.delete("work"), Err(CtxError::DatasetNotFound));
+        assert_eq!(fs::read_dir(d.path().join(ARCHIVE_DIR)).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_protected() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        let owner = Entity::from("bob").unwrap();
+        let root = Entity::from("acme").unwrap();
+        let name = ctx.new_datastore(&owner, &root, Some("secret")).unwrap();
+        assert_eq!(ctx.is_protected(&name), true);
+        assert_eq!(ctx.is_locked(&name), false);
+        // the changes are sealed on close
+        let jane = Entity::from("jane").unwrap().with_sponsor(&owner);
+        let mut ds = ctx.open_datastore(&name).unwrap();
+        assert_eq!(ds.is_sealed(), true);
+        ds.add(&jane).unwrap();
+        assert_eq!(
+            ctx.open_datastore(&name).err(),
+            Some(CtxError::DatasetLocked(std::process::id()))
+        );
+        ds.close().unwrap();
+        drop(ds);
+        // nothing is left in clear
+        let path = PathBuf::from(&ctx.list()[0].1);
+        let mut files = fs::read_dir(&path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        files.sort();
+        assert_eq!(files, vec![NAME_FILE, SEALED_FILE]);
+        // the passphrase is required after a restart
+        let mut ctx = ContextManager::new(&d.path()).unwrap();
+        assert_eq!(ctx.list()[0].0, name);
+        assert_eq!(ctx.is_locked(&name), true);
+        assert_eq!(
+            ctx.open_datastore(&name).err(),
+            Some(CtxError::DatasetSealed)
+        );
+        assert_eq!(ctx.unlock(&name, "wrong"), Err(CtxError::AccessDenied));
+        ctx.unlock(&name, "secret").unwrap();
+        let ds = ctx.open_datastore(&name).unwrap();
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap().is_some(), true);
+        drop(ds);
+        // locking drops the passphrase
+        ctx.lock(&name);
+        assert_eq!(ctx.is_locked(&name), true);
+        assert_eq!(
+            ctx.open_datastore(&name).err(),
+            Some(CtxError::DatasetSealed)
+        );
+    }
 }
@@ -0,0 +1,1498 @@
+use super::ledger::{DataError, ExportFormat};
+use super::model::{Actor, Entity, Event, EventType, Goal, RelQuality, RelType, Tag, Task, Uuid};
+use super::utils;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use chrono::{NaiveDate, Utc};
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Let's use generic errors
+type Result<T> = std::result::Result<T, DataError>;
+
+/// A single entry of an interaction history to be imported
+///
+/// the entity is a reference to an existing entity, that is
+/// either an handle in the form prefix:value or a name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventRecord {
+    pub date: String,
+    pub kind: String,
+    pub entity: String,
+    pub content: Option<String>,
+}
+
+impl EventRecord {
+    /// Returns the date of the record, it recognizes the
+    /// yyyy-mm-dd format and the ones supported by utils::date_from_str
+    pub fn recorded_on(&self) -> Option<NaiveDate> {
+        let d = self.date.trim();
+        match NaiveDate::parse_from_str(d, "%Y-%m-%d") {
+            Ok(date) => Some(date),
+            Err(_) => utils::date_from_str(d),
+        }
+    }
+}
+
+/// Read a list of event records from a file
+///
+/// Each record is returned with its line number and either the
+/// record itself or the reason why it could not be read, so that
+/// a broken line does not stop the whole import
+pub fn read_event_records<S: ImportSource + ?Sized>(
+    source: &S,
+    format: &ExportFormat,
+) -> Result<Vec<(usize, std::result::Result<EventRecord, String>)>> {
+    match format {
+        ExportFormat::Csv => {
+            let mut rdr = csv::Reader::from_reader(source.open()?);
+            Ok(rdr
+                .deserialize()
+                .enumerate()
+                // the first line is the header
+                .map(|(i, r)| (i + 2, r.map_err(|e| e.to_string())))
+                .collect())
+        }
+        ExportFormat::Json => Ok(source
+            .open()?
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| match l {
+                Ok(l) => !l.trim().is_empty(),
+                Err(_) => true,
+            })
+            .map(|(i, l)| {
+                let r = match l {
+                    Ok(l) => serde_json::from_str(&l).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                (i + 1, r)
+            })
+            .collect()),
+        _ => Err(DataError::NotImplemented),
+    }
+}
+
+/// The columns of an entities csv, in order
+pub const ENTITY_COLUMNS: [&str; 7] = [
+    "uid",
+    "name",
+    "class",
+    "handles",
+    "tags",
+    "next_action_date",
+    "note",
+];
+
+/// The separator of the values within the handles and tags columns
+const CSV_LIST_SEP: char = ';';
+
+/// Maps the entity fields to the columns of a csv file by header
+///
+/// The fields are the ones in ENTITY_COLUMNS, plus `handle.<prefix>`
+/// to read a column of plain values as handles with that prefix,
+/// eg. `handle.email=E-mail Address`. The unmapped fields are read
+/// from the column with the same name, if any
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CsvMapping {
+    columns: BTreeMap<String, String>,
+}
+
+impl CsvMapping {
+    /// Read a field from a column
+    pub fn with(mut self, field: &str, column: &str) -> Result<Self> {
+        let field = field.trim();
+        let known = ENTITY_COLUMNS.contains(&field)
+            || (field.starts_with("handle.") && field.len() > "handle.".len());
+        if !known {
+            return Err(DataError::GenericError(format!(
+                "unknown csv field {}",
+                field
+            )));
+        }
+        self.columns
+            .insert(field.to_owned(), column.trim().to_owned());
+        Ok(self)
+    }
+
+    /// Parse a comma separated list of field=column pairs
+    pub fn parse(spec: &str) -> Result<Self> {
+        spec.split(',')
+            .filter(|p| !p.trim().is_empty())
+            .try_fold(CsvMapping::default(), |m, p| {
+                match utils::split_once(p, '=') {
+                    Some((field, column)) => m.with(field, column),
+                    None => Err(DataError::GenericError(format!(
+                        "invalid csv mapping {}",
+                        p.trim()
+                    ))),
+                }
+            })
+    }
+
+    /// Returns the column a field is read from
+    fn column<'a>(&'a self, field: &'a str) -> &'a str {
+        self.columns.get(field).map_or(field, |c| c.as_str())
+    }
+}
+
+/// The values of an entity read from a csv row,
+/// the missing or empty columns are None
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntityRow {
+    pub uid: Option<Uuid>,
+    pub name: Option<String>,
+    pub class: Option<String>,
+    pub handles: Vec<(String, String)>,
+    pub tags: Vec<Tag>,
+    pub next_action_date: Option<NaiveDate>,
+    pub note: Option<String>,
+}
+
+/// The entity as JSON for the other tools, eg. the API and the hooks,
+/// without the password hash and with the uids in the short form
+pub fn entity_json(e: &Entity) -> serde_json::Value {
+    let mut v = serde_json::to_value(e).unwrap();
+    v["pass"] = serde_json::Value::Null;
+    v["uid"] = serde_json::json!(e.uid());
+    v["sponsor"] = serde_json::json!(e.sponsor_uid());
+    v
+}
+
+/// Returns the values of an entity in the ENTITY_COLUMNS order,
+/// the handles and tags are sorted to keep the output stable,
+/// the primary handle of each type comes first
+pub fn entity_record(e: &Entity) -> Vec<String> {
+    let sep = CSV_LIST_SEP.to_string();
+    let handles = e
+        .all_handles()
+        .iter()
+        .map(|h| format!("{}:{}", h.label, h.value))
+        .collect::<Vec<String>>();
+    let mut tags = e
+        .tags
+        .values()
+        .map(|t| t.to_string_full())
+        .collect::<Vec<String>>();
+    tags.sort();
+    vec![
+        e.uid(),
+        e.name().to_owned(),
+        e.class.to_owned(),
+        handles.join(&sep),
+        tags.join(&sep),
+        e.next_action_date.to_string(),
+        e.next_action_note.to_owned(),
+    ]
+}
+
+/// Read the entities from a csv file with a header line
+///
+/// Each row is returned with its line number and either the
+/// values read or the reason why it could not be read
+pub fn read_entity_rows<S: ImportSource + ?Sized>(
+    source: &S,
+    mapping: &CsvMapping,
+) -> Result<Vec<(usize, std::result::Result<EntityRow, String>)>> {
+    let mut rdr = csv::Reader::from_reader(source.open()?);
+    let headers = match rdr.headers() {
+        Ok(h) => h
+            .iter()
+            .map(|h| h.trim().to_owned())
+            .collect::<Vec<String>>(),
+        Err(e) => return Err(DataError::GenericError(e.to_string())),
+    };
+    let index = |field: &str| {
+        let column = mapping.column(field);
+        headers.iter().position(|h| h.eq_ignore_ascii_case(column))
+    };
+    let fields = ENTITY_COLUMNS
+        .iter()
+        .map(|f| (f.to_string(), index(f)))
+        .collect::<HashMap<String, Option<usize>>>();
+    let handle_columns = mapping
+        .columns
+        .keys()
+        .filter_map(|f| f.strip_prefix("handle.").map(|p| (p.to_owned(), index(f))))
+        .collect::<Vec<(String, Option<usize>)>>();
+    let mut rows = Vec::new();
+    for (i, r) in rdr.records().enumerate() {
+        // the first line is the header
+        let n = i + 2;
+        let record = match r {
+            Ok(r) => r,
+            Err(e) => {
+                rows.push((n, Err(e.to_string())));
+                continue;
+            }
+        };
+        let value = |i: Option<usize>| {
+            i.and_then(|i| record.get(i))
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+        };
+        let get = |field: &str| value(fields[field]);
+        let list = |field: &str| {
+            get(field)
+                .map(|v| {
+                    v.split(CSV_LIST_SEP)
+                        .map(|x| x.trim())
+                        .filter(|x| !x.is_empty())
+                        .collect::<Vec<&str>>()
+                })
+                .unwrap_or_default()
+        };
+        let mut row = EntityRow {
+            name: get("name").map(|v| v.to_owned()),
+            class: get("class").map(|v| v.to_owned()),
+            note: get("note").map(|v| v.to_owned()),
+            ..EntityRow::default()
+        };
+        if let Some(v) = get("uid") {
+            match Uuid::parse_str(v) {
+                Ok(uid) => row.uid = Some(uid),
+                Err(_) => {
+                    rows.push((n, Err(format!("invalid uid {}", v))));
+                    continue;
+                }
+            }
+        }
+        if let Some(v) = get("next_action_date") {
+            match NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                .ok()
+                .or_else(|| utils::date_from_str(v))
+            {
+                Some(d) => row.next_action_date = Some(d),
+                None => {
+                    rows.push((n, Err(format!("invalid date {}", v))));
+                    continue;
+                }
+            }
+        }
+        let mut invalid = None;
+        for h in list("handles") {
+            match utils::split_once(h, ':') {
+                Some((k, v)) => row.handles.push((k.trim().to_owned(), v.trim().to_owned())),
+                None => invalid = Some(format!("invalid handle {}, expected prefix:value", h)),
+            }
+        }
+        if let Some(reason) = invalid {
+            rows.push((n, Err(reason)));
+            continue;
+        }
+        for (prefix, i) in handle_columns.iter() {
+            if let Some(v) = value(*i) {
+                row.handles.push((prefix.to_owned(), v.to_owned()));
+            }
+        }
+        // the tag parsing never fails
+        row.tags = list("tags")
+            .iter()
+            .filter_map(|t| Tag::from_str(t).ok())
+            .collect();
+        rows.push((n, Ok(row)));
+    }
+    Ok(rows)
+}
+
+/// The marker of the trailer line of a json export
+const EXPORT_TRAILER: &str = "#checksum";
+
+/// A key to sign and verify the exports
+pub type ExportKey = [u8; 32];
+
+/// Derive an export key from a secret, eg. the content of a key file
+pub fn export_key(secret: &str) -> ExportKey {
+    let mut key = [0; 32];
+    blake3::derive_key("valis-rs export signature", secret.as_bytes(), &mut key);
+    key
+}
+
+/// Writes a json export where each line is prefixed by
+/// its checksum, followed by a trailer line with the number
+/// of records, the checksum of the whole file and, if a key
+/// is provided, its signature
+pub struct ExportWriter<'a, W: Write> {
+    out: W,
+    hasher: blake3::Hasher,
+    records: usize,
+    key: Option<&'a ExportKey>,
+}
+
+impl<'a, W: Write> ExportWriter<'a, W> {
+    pub fn new(out: W, key: Option<&'a ExportKey>) -> Self {
+        ExportWriter {
+            out,
+            hasher: blake3::Hasher::new(),
+            records: 0,
+            key,
+        }
+    }
+
+    /// Write a json record
+    pub fn write_record(&mut self, json: &str) -> Result<()> {
+        let line = format!("{}\t{}\n", blake3::hash(json.as_bytes()).to_hex(), json);
+        self.hasher.update(line.as_bytes());
+        self.out.write_all(line.as_bytes())?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Write the trailer and flush the output
+    pub fn finish(mut self) -> Result<()> {
+        let checksum = self.hasher.finalize().to_hex();
+        let mut trailer = format!("{}\t{}\t{}", EXPORT_TRAILER, self.records, checksum);
+        if let Some(key) = self.key {
+            let sig = blake3::keyed_hash(key, checksum.as_bytes());
+            trailer.push_str(&format!("\t{}", sig.to_hex()));
+        }
+        trailer.push('\n');
+        self.out.write_all(trailer.as_bytes())?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// A record of a full export, that holds the entities, the
+/// events, the system entries, the tasks and the goals of a datastore
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+pub enum FullRecord {
+    Entity(Entity),
+    Event(Event),
+    System(String, String),
+    Task(Task),
+    Goal(Goal),
+}
+
+/// The result of reading a json export
+///
+/// the records are the ones that could be read, while
+/// corrupted lists the lines that failed the verification
+#[derive(Debug)]
+pub struct ExportCheck<T = Entity> {
+    pub records: Vec<(usize, T)>,
+    pub corrupted: Vec<(usize, String)>,
+}
+
+impl<T> Default for ExportCheck<T> {
+    fn default() -> Self {
+        ExportCheck {
+            records: Vec::new(),
+            corrupted: Vec::new(),
+        }
+    }
+}
+
+impl<T> ExportCheck<T> {
+    /// Tells if the whole export passed the verification
+    pub fn is_valid(&self) -> bool {
+        self.corrupted.is_empty()
+    }
+}
+
+/// Read and verify a json export
+///
+/// The exports without checksums are still accepted, unless a
+/// key is provided, in which case the signature is mandatory
+pub fn read_export<S: ImportSource + ?Sized>(
+    source: &S,
+    key: Option<&ExportKey>,
+) -> Result<ExportCheck> {
+    read_records(source, key)
+}
+
+/// Read and verify a json export made of records of any type,
+/// see read_export
+pub fn read_records<T: DeserializeOwned, S: ImportSource + ?Sized>(
+    source: &S,
+    key: Option<&ExportKey>,
+) -> Result<ExportCheck<T>> {
+    let mut check = ExportCheck::default();
+    let mut hasher = blake3::Hasher::new();
+    let (mut records, mut checksums, mut trailer) = (0, false, None);
+    let mut last = 0;
+    for (i, l) in source.open()?.lines().enumerate() {
+        let (n, l) = (i + 1, l?);
+        last = n;
+        if l.trim().is_empty() {
+            continue;
+        }
+        if trailer.is_some() {
+            check
+                .corrupted
+                .push((n, "unexpected data after the trailer".to_owned()));
+            continue;
+        }
+        if l.starts_with(EXPORT_TRAILER) {
+            trailer = Some((n, l));
+            continue;
+        }
+        records += 1;
+        hasher.update(format!("{}\n", l).as_bytes());
+        // legacy exports have no checksum
+        let json = match utils::split_once(&l, '\t') {
+            Some((sum, json)) if !l.starts_with('{') => {
+                checksums = true;
+                if blake3::hash(json.as_bytes()).to_hex().as_str() != sum {
+                    check.corrupted.push((n, "checksum mismatch".to_owned()));
+                    continue;
+                }
+                json
+            }
+            _ => &l,
+        };
+        match serde_json::from_str::<T>(json) {
+            Ok(e) => check.records.push((n, e)),
+            Err(e) => check.corrupted.push((n, format!("invalid record: {}", e))),
+        }
+    }
+    let (n, t) = match trailer {
+        Some(t) => t,
+        None => {
+            if checksums {
+                check.corrupted.push((
+                    last + 1,
+                    "missing trailer, the file may be truncated".to_owned(),
+                ));
+            } else if key.is_some() {
+                check
+                    .corrupted
+                    .push((last + 1, "missing signature".to_owned()));
+            }
+            return Ok(check);
+        }
+    };
+    let fields = t.split('\t').collect::<Vec<&str>>();
+    if fields.len() < 3 {
+        check.corrupted.push((n, "invalid trailer".to_owned()));
+        return Ok(check);
+    }
+    if fields[1] != records.to_string() {
+        check.corrupted.push((
+            n,
+            format!("expected {} records, found {}", fields[1], records),
+        ));
+    }
+    let checksum = hasher.finalize().to_hex();
+    if fields[2] != checksum.as_str() {
+        check
+            .corrupted
+            .push((n, "file checksum mismatch".to_owned()));
+    }
+    if let Some(key) = key {
+        match fields.get(3) {
+            Some(sig) => {
+                if blake3::keyed_hash(key, fields[2].as_bytes())
+                    .to_hex()
+                    .as_str()
+                    != *sig
+                {
+                    check.corrupted.push((n, "invalid signature".to_owned()));
+                }
+            }
+            None => check.corrupted.push((n, "missing signature".to_owned())),
+        }
+    }
+    Ok(check)
+}
+
+/// The header of an encrypted export, followed by the salt,
+/// the nonce and the ciphertext
+const ENCRYPTED_HEADER: &[u8] = b"valis-encrypted-v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Derive an encryption key from a passphrase and a salt
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<chacha20poly1305::Key> {
+    let mut key = chacha20poly1305::Key::default();
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| DataError::GenericError(e.to_string()))?;
+    Ok(key)
+}
+
+/// Encrypt data with a passphrase using XChaCha20-Poly1305,
+/// the key is derived from the passphrase with argon2
+pub fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = XChaCha20Poly1305::new(&passphrase_key(passphrase, &salt)?);
+    let sealed = cipher
+        .encrypt(XNonce::from_slice(&nonce), data)
+        .map_err(|_| DataError::GenericError("encryption failed".to_owned()))?;
+    let mut out = Vec::with_capacity(ENCRYPTED_HEADER.len() + SALT_LEN + NONCE_LEN + sealed.len());
+    out.extend_from_slice(ENCRYPTED_HEADER);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+/// Decrypt data encrypted with encrypt, it fails if the
+/// passphrase is wrong or the data has been tampered with
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let invalid = || DataError::GenericError("wrong passphrase or corrupted file".to_owned());
+    if !is_encrypted_data(data) {
+        return Err(DataError::GenericError(
+            "the data is not encrypted".to_owned(),
+        ));
+    }
+    let data = &data[ENCRYPTED_HEADER.len()..];
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(invalid());
+    }
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    let cipher = XChaCha20Poly1305::new(&passphrase_key(passphrase, salt)?);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), sealed)
+        .map_err(|_| invalid())
+}
+
+/// Tells if some data has been encrypted with encrypt
+pub fn is_encrypted_data(data: &[u8]) -> bool {
+    data.starts_with(ENCRYPTED_HEADER)
+}
+
+/// Tells if a file has been encrypted with encrypt
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut header = Vec::with_capacity(ENCRYPTED_HEADER.len());
+    File::open(path)?
+        .take(ENCRYPTED_HEADER.len() as u64)
+        .read_to_end(&mut header)?;
+    Ok(is_encrypted_data(&header))
+}
+
+/// Encrypt some data with a passphrase and write it to a file,
+/// the file is replaced at once and the data never hits the disk
+/// in clear
+pub fn write_encrypted(path: &Path, data: &[u8], passphrase: &str) -> Result<()> {
+    let data = encrypt(data, passphrase)?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Where an import is read from, that is a file or the content
+/// of an encrypted file decrypted in memory
+pub trait ImportSource {
+    /// Open the content for reading from the start
+    fn open(&self) -> Result<Box<dyn BufRead + '_>>;
+}
+
+impl ImportSource for Path {
+    fn open(&self) -> Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(BufReader::new(File::open(self)?)))
+    }
+}
+
+impl ImportSource for PathBuf {
+    fn open(&self) -> Result<Box<dyn BufRead + '_>> {
+        self.as_path().open()
+    }
+}
+
+/// The decrypted content of an encrypted file, it is kept
+/// in memory so that it is never written to disk in clear
+pub struct Decrypted {
+    data: Vec<u8>,
+}
+
+impl ImportSource for Decrypted {
+    fn open(&self) -> Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(self.data.as_slice()))
+    }
+}
+
+/// Decrypt a file in memory, so that it can be imported
+/// like any other file, see ImportSource
+pub fn decrypt_file(path: &Path, passphrase: &str) -> Result<Decrypted> {
+    let data = decrypt(&std::fs::read(path)?, passphrase)?;
+    Ok(Decrypted { data })
+}
+
+/// The opening lines of an iCalendar document
+pub const ICS_BEGIN: &str = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//VALIS//valis-rs//EN\r\n";
+/// The closing line of an iCalendar document
+pub const ICS_END: &str = "END:VCALENDAR\r\n";
+
+/// Escape a text value for iCalendar
+fn ics_escape(txt: &str) -> String {
+    txt.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line at 75 octets as required by the iCalendar spec
+fn ics_fold(line: &str) -> String {
+    let mut out = String::new();
+    let mut size = 0;
+    for c in line.chars() {
+        if size + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            size = 1;
+        }
+        out.push(c);
+        size += c.len_utf8();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+/// Render an event as an iCalendar VEVENT
+///
+/// the participants are the entities referenced by the event actors,
+/// the one that recorded the event is the organizer while
+/// the others are the attendees
+pub fn ics_event(evt: &Event, participants: &[Entity]) -> String {
+    let name_of = |uid: &Uuid| {
+        participants
+            .iter()
+            .find(|e| e.uid == *uid)
+            .map(|e| (e.name().to_owned(), e.handles.get("email").cloned()))
+    };
+    let address = |uid: &Uuid, email: Option<String>| match email {
+        Some(m) => format!("mailto:{}", m),
+        None => format!("urn:uuid:{}", uid),
+    };
+    let mut attendees = Vec::new();
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_owned(),
+        format!("UID:{}@valis", evt.uid()),
+        format!(
+            "DTSTAMP:{}",
+            evt.recorded_at.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")
+        ),
+        format!(
+            "DTSTART:{}",
+            evt.recorded_at.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")
+        ),
+    ];
+    if let EventType::Interaction(_, minutes, _) = evt.kind {
+        if minutes > 0 {
+            lines.push(format!("DURATION:PT{}M", minutes));
+        }
+    }
+    for a in evt.actors.iter() {
+        let (_, uid) = a.role();
+        if let Some((name, email)) = name_of(&uid) {
+            match a {
+                // the delegation is not a participation
+                Actor::OnBehalfOf(_) => {}
+                Actor::RecordedBy(_) => lines.push(format!(
+                    "ORGANIZER;CN={}:{}",
+                    ics_escape(&name),
+                    address(&uid, email)
+                )),
+                _ => {
+                    lines.push(format!(
+                        "ATTENDEE;CN={}:{}",
+                        ics_escape(&name),
+                        address(&uid, email)
+                    ));
+                    attendees.push(name);
+                }
+            }
+        }
+    }
+    let title = match &evt.kind {
+        EventType::Action(_, title, _) => title.to_owned(),
+        EventType::Log(msg) => msg.to_owned(),
+        EventType::Interaction(c, _, _) => c.to_string(),
+    };
+    let summary = match attendees.is_empty() {
+        true => title,
+        false => format!("{} with {}", title, attendees.join(", ")),
+    };
+    lines.push(format!("SUMMARY:{}", ics_escape(&summary)));
+    if let Some(c) = &evt.content {
+        lines.push(format!("DESCRIPTION:{}", ics_escape(c)));
+    }
+    lines.push("END:VEVENT".to_owned());
+    lines.iter().map(|l| ics_fold(l)).collect()
+}
+
+/// Escape a string literal for n-quads
+fn nquad_escape(txt: &str) -> String {
+    txt.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Turn a label in a predicate name, eg. (rel, rl:ceo) becomes <rel.rl-ceo>
+fn nquad_predicate(prefix: &str, label: &str) -> String {
+    format!("<{}.{}>", prefix, utils::slugify(label))
+}
+
+/// Render an entity as a set of n-quads
+///
+/// each entity is a blank node labelled with its uid, so that the
+/// sponsorships and relationships can reference it before it is
+/// defined, as expected by the Dgraph live loader
+pub fn nquads(e: &Entity) -> String {
+    let node = |uid: &Uuid| format!("_:{}", utils::id(uid));
+    let subject = node(&e.uid);
+    let literal = |v: &str| format!("\"{}\"", nquad_escape(v));
+    let date = |d: &NaiveDate| format!("\"{}\"^^<xs:dateTime>", d);
+    let mut quads = vec![
+        ("<dgraph.type>".to_owned(), literal("Entity")),
+        ("<xid>".to_owned(), literal(&e.uid())),
+        ("<name>".to_owned(), literal(e.name())),
+        ("<class>".to_owned(), literal(&e.class)),
+        ("<created_on>".to_owned(), date(&e.created_on)),
+        ("<updated_on>".to_owned(), date(&e.updated_on)),
+        ("<next_action_date>".to_owned(), date(&e.next_action_date)),
+        ("<sponsor>".to_owned(), node(&e.sponsor)),
+    ];
+    if !e.description.is_empty() {
+        quads.push(("<description>".to_owned(), literal(&e.description)));
+    }
+    if !e.next_action_note.is_empty() {
+        quads.push((
+            "<next_action_note>".to_owned(),
+            literal(&e.next_action_note),
+        ));
+    }
+    // keep the output stable
+    for h in e.all_handles().iter() {
+        quads.push((nquad_predicate("handle", &h.label), literal(&h.value)));
+    }
+    let mut tags = e
+        .tags
+        .values()
+        .map(|t| (nquad_predicate("tag", t.prefix()), literal(&t.to_string())))
+        .collect::<Vec<_>>();
+    tags.sort();
+    quads.extend(tags);
+    for r in e.relationships.iter() {
+        quads.push((nquad_predicate("rel", &r.kind.get_label()), node(&r.target)));
+    }
+    quads
+        .iter()
+        .map(|(p, o)| format!("{} {} {} .\n", subject, p, o))
+        .collect()
+}
+
+/// The opening lines of a Graphviz DOT graph
+pub const DOT_BEGIN: &str = "digraph valis {\n  node [style=filled];\n";
+/// The closing line of a Graphviz DOT graph
+pub const DOT_END: &str = "}\n";
+
+/// Returns the color of a graph node by relationship quality
+fn quality_color(q: &RelQuality) -> &'static str {
+    match q {
+        RelQuality::Friendly(_, _) => "#b2df8a",
+        RelQuality::Formal(_, _) => "#a6cee3",
+        RelQuality::Neutral(_, _) => "#e0e0e0",
+        RelQuality::Tense(_, _) => "#fdbf6f",
+        RelQuality::Hostile(_, _) => "#fb9a99",
+    }
+}
+
+/// Returns the shape of a graph node by entity class
+fn class_shape(class: &str) -> &'static str {
+    match class {
+        "person" => "ellipse",
+        "org" => "box",
+        "project" => "hexagon",
+        _ => "note",
+    }
+}
+
+/// Returns the label of a relationship edge, eg. member of
+fn rel_label(kind: &RelType) -> String {
+    match kind {
+        RelType::RelatedTo => "related to".to_owned(),
+        RelType::Role(l, _, _) => l.to_owned(),
+        RelType::BelongsTo(_, _) => "belongs to".to_owned(),
+        RelType::MemberOf(_, _) => "member of".to_owned(),
+        RelType::WorksAt => "works at".to_owned(),
+        RelType::Employs => "employs".to_owned(),
+        RelType::ManagerOf => "manager of".to_owned(),
+        RelType::ReportsTo => "reports to".to_owned(),
+        RelType::PartnerOf => "partner of".to_owned(),
+        RelType::ClientOf => "client of".to_owned(),
+        RelType::SupplierOf => "supplier of".to_owned(),
+        RelType::HasMember(_, _) => "has member".to_owned(),
+    }
+}
+
+/// Escape a string for a quoted DOT id
+fn dot_escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render an entity as a node of a Graphviz DOT graph, followed by
+/// the edges to its sponsor (dashed) and to its relationships.
+///
+/// The nodes are identified by uid, shaped by class and
+/// colored by the quality of the relationship
+pub fn dot(e: &Entity) -> String {
+    let mut out = format!(
+        "  \"{}\" [label=\"{}\", shape={}, fillcolor=\"{}\"];\n",
+        e.uid(),
+        dot_escape(e.name()),
+        class_shape(&e.class),
+        quality_color(&e.quality)
+    );
+    if e.sponsor != e.uid {
+        out.push_str(&format!(
+            "  \"{}\" -> \"{}\" [style=dashed, label=\"sponsored\"];\n",
+            utils::id(&e.sponsor),
+            e.uid()
+        ));
+    }
+    for r in e.relationships.iter() {
+        out.push_str(&format!(
+            "  \"{}\" -> \"{}\" [label=\"{}\"];\n",
+            e.uid(),
+            utils::id(&r.target),
+            dot_escape(&rel_label(&r.kind))
+        ));
+    }
+    out
+}
+
+/// The opening lines of a GraphML document, with the node and edge attributes
+pub const GRAPHML_BEGIN: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="name" for="node" attr.name="name" attr.type="string"/>
+  <key id="class" for="node" attr.name="class" attr.type="string"/>
+  <key id="tags" for="node" attr.name="tags" attr.type="string"/>
+  <key id="quality" for="node" attr.name="quality" attr.type="string"/>
+  <key id="last_contact" for="node" attr.name="last_contact" attr.type="string"/>
+  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>
+  <key id="label" for="edge" attr.name="label" attr.type="string"/>
+  <graph id="valis" edgedefault="directed">
+"#;
+/// The closing lines of a GraphML document
+pub const GRAPHML_END: &str = "  </graph>\n</graphml>\n";
+
+/// Returns the name of a relationship quality
+fn quality_name(q: &RelQuality) -> &'static str {
+    match q {
+        RelQuality::Friendly(_, _) => "friendly",
+        RelQuality::Formal(_, _) => "formal",
+        RelQuality::Neutral(_, _) => "neutral",
+        RelQuality::Tense(_, _) => "tense",
+        RelQuality::Hostile(_, _) => "hostile",
+    }
+}
+
+/// Escape a text for XML
+fn xml_escape(v: &str) -> String {
+    v.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Render an entity as a GraphML node, followed by the edges to
+/// its sponsor and to its relationships.
+///
+/// The tags are a comma separated list of prefix:label and the
+/// last contact date is omitted when there is none
+pub fn graphml(e: &Entity, last_contact: Option<NaiveDate>) -> String {
+    let data = |k: &str, v: &str| format!("      <data key=\"{}\">{}</data>\n", k, xml_escape(v));
+    let mut tags = e
+        .tags
+        .values()
+        .map(|t| t.to_string_full())
+        .collect::<Vec<String>>();
+    tags.sort();
+    let mut out = format!("    <node id=\"{}\">\n", e.uid());
+    out.push_str(&data("name", e.name()));
+    out.push_str(&data("class", &e.class));
+    out.push_str(&data("tags", &tags.join(",")));
+    out.push_str(&data("quality", quality_name(&e.quality)));
+    if let Some(d) = last_contact {
+        out.push_str(&data("last_contact", &d.to_string()));
+    }
+    out.push_str("    </node>\n");
+    let edge = |source: String, target: String, kind: &str, label: &str| {
+        format!(
+            "    <edge source=\"{}\" target=\"{}\">\n{}{}    </edge>\n",
+            source,
+            target,
+            data("kind", kind),
+            data("label", label)
+        )
+    };
+    if e.sponsor != e.uid {
+        out.push_str(&edge(
+            utils::id(&e.sponsor),
+            e.uid(),
+            "sponsorship",
+            "sponsored",
+        ));
+    }
+    for r in e.relationships.iter() {
+        out.push_str(&edge(
+            e.uid(),
+            utils::id(&r.target),
+            "relationship",
+            &rel_label(&r.kind),
+        ));
+    }
+    out
+}
+
+/// The handle prefixes exported as vCard properties, with the
+/// property and its parameters
+const VCARD_HANDLES: [(&str, &str); 4] = [
+    ("email", "EMAIL;TYPE=INTERNET"),
+    ("mobile", "TEL;TYPE=CELL"),
+    ("phone", "TEL;TYPE=VOICE"),
+    ("url", "URL"),
+];
+
+/// Unescape a text value of a vCard or iCalendar
+fn ics_unescape(txt: &str) -> String {
+    let mut out = String::new();
+    let mut chars = txt.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(x) => out.push(x),
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+/// Render an entity as a vCard 3.0
+///
+/// the name, the description and the email, phone and
+/// url handles are exported, the other fields are dropped
+pub fn vcard(e: &Entity) -> String {
+    let mut lines = vec![
+        "BEGIN:VCARD".to_owned(),
+        "VERSION:3.0".to_owned(),
+        format!("UID:urn:uuid:{}", e.uid),
+        format!("FN:{}", ics_escape(e.name())),
+        format!("N:;{};;;", ics_escape(e.name())),
+    ];
+    for (prefix, prop) in VCARD_HANDLES.iter() {
+        for h in e.all_handles().iter().filter(|h| h.label == *prefix) {
+            lines.push(format!("{}:{}", prop, ics_escape(&h.value)));
+        }
+    }
+    if !e.description.is_empty() {
+        lines.push(format!("NOTE:{}", ics_escape(&e.description)));
+    }
+    lines.push("END:VCARD".to_owned());
+    lines.iter().map(|l| ics_fold(l)).collect()
+}
+
+/// The values of an entity read from a vCard,
+/// the missing properties are None
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VCard {
+    pub uid: Option<Uuid>,
+    pub name: Option<String>,
+    pub handles: Vec<(String, String)>,
+    pub description: Option<String>,
+}
+
+/// Read the vCards from a file
+///
+/// Each card is returned with the line number where it begins and
+/// either the values read or the reason why it could not be read.
+/// All the values of each handle prefix are kept, the first one is
+/// the primary, the phone numbers are mobile handles unless they
+/// are typed otherwise
+pub fn read_vcards<S: ImportSource + ?Sized>(
+    source: &S,
+) -> Result<Vec<(usize, std::result::Result<VCard, String>)>> {
+    // unfold the content lines keeping the number of the first one
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (i, l) in source.open()?.lines().enumerate() {
+        let l = l?;
+        match (
+            l.strip_prefix(' ').or_else(|| l.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(more), Some((_, prev))) => prev.push_str(more),
+            _ => lines.push((i + 1, l.trim_end_matches('\r').to_owned())),
+        }
+    }
+    let mut cards = Vec::new();
+    let mut current: Option<(usize, std::result::Result<VCard, String>)> = None;
+    for (n, l) in lines.into_iter() {
+        let (head, value) = match utils::split_once(&l, ':') {
+            Some((h, v)) => (h.to_uppercase(), v.trim()),
+            None => continue,
+        };
+        let mut params = head.split(';');
+        // drop the group, eg. item1.EMAIL
+        let name = params.next().unwrap_or("");
+        let name = name.rsplit('.').next().unwrap_or(name).to_owned();
+        let params = params.collect::<Vec<&str>>().join(";");
+        match (name.as_str(), value.to_uppercase().as_str()) {
+            ("BEGIN", "VCARD") => {
+                if let Some((start, _)) = current.take() {
+                    cards.push((start, Err("missing END:VCARD".to_owned())));
+                }
+                current = Some((n, Ok(VCard::default())));
+                continue;
+            }
+            ("END", "VCARD") => {
+                if let Some(c) = current.take() {
+                    cards.push(c);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let card = match current.as_mut() {
+            Some((_, Ok(c))) => c,
+            _ => continue,
+        };
+        let text = ics_unescape(value);
+        if text.is_empty() {
+            continue;
+        }
+        let prefix = match name.as_str() {
+            "UID" => {
+                match Uuid::parse_str(value.trim_start_matches("urn:uuid:")) {
+                    Ok(uid) => card.uid = Some(uid),
+                    Err(_) => {
+                        let (start, _) = current.take().unwrap();
+                        current = Some((start, Err(format!("invalid uid {}", value))));
+                    }
+                }
+                continue;
+            }
+            "FN" => {
+                card.name = Some(text);
+                continue;
+            }
+            "NOTE" => {
+                card.description = Some(text);
+                continue;
+            }
+            "EMAIL" => "email",
+            "URL" => "url",
+            "TEL" if params.contains("VOICE") || params.contains("HOME") => "phone",
+            "TEL" => "mobile",
+            _ => continue,
+        };
+        if !card.handles.iter().any(|(k, v)| k == prefix && *v == text) {
+            card.handles.push((prefix.to_owned(), text));
+        }
+    }
+    if let Some((start, _)) = current {
+        cards.push((start, Err("missing END:VCARD".to_owned())));
+    }
+    Ok(cards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::model::Rel;
+    use super::*;
+
+    #[test]
+    fn test_event_records() {
+        let d = tempfile::TempDir::new().unwrap();
+        // csv
+        let p = d.path().join("events.csv");
+        std::fs::write(
+            &p,
+            "date,kind,entity,content\n2020-10-01,call,email:bob@acme.com,about the offer\n01.02.21,meeting,Alice,\n",
+        )
+        .unwrap();
+        let r = read_event_records(&p, &ExportFormat::Csv).unwrap();
+        assert_eq!(r.len(), 2);
+        let (line, rec) = &r[0];
+        assert_eq!(*line, 2);
+        let rec = rec.as_ref().unwrap();
+        assert_eq!(rec.recorded_on(), Some(utils::date(1, 10, 2020)));
+        assert_eq!(rec.entity, "email:bob@acme.com");
+        assert_eq!(rec.content, Some("about the offer".to_owned()));
+        let rec = r[1].1.as_ref().unwrap();
+        assert_eq!(rec.recorded_on(), Some(utils::date(1, 2, 2021)));
+        assert_eq!(rec.content, None);
+        // jsonl with a broken line
+        let p = d.path().join("events.json");
+        std::fs::write(
+            &p,
+            "{\"date\":\"2020-10-01\",\"kind\":\"call\",\"entity\":\"Bob\",\"content\":null}\n{broken\n",
+        )
+        .unwrap();
+        let r = read_event_records(&p, &ExportFormat::Json).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].1.is_ok(), true);
+        assert_eq!(r[1].0, 2);
+        assert_eq!(r[1].1.is_err(), true);
+    }
+
+    #[test]
+    fn test_ics() {
+        assert_eq!(ics_escape("a, b; c\nd"), "a\\, b\\; c\\nd");
+        let long = "x".repeat(100);
+        let folded = ics_fold(&long);
+        assert_eq!(folded.split("\r\n").next().unwrap().len(), 75);
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", long));
+        // render an event
+        let bob = Entity::from("bob").unwrap();
+        let alice = Entity::from("alice")
+            .unwrap()
+            .with_handle("email", "alice@acme.com");
+        let evt = Event::action(
+            "cli",
+            "call",
+            1,
+            Some("talked, a lot".to_owned()),
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(alice.uid)],
+        );
+        let v = ics_event(&evt, &[bob.clone(), alice]);
+        assert_eq!(v.starts_with("BEGIN:VEVENT\r\n"), true);
+        assert_eq!(v.ends_with("END:VEVENT\r\n"), true);
+        assert_eq!(
+            v.contains("ATTENDEE;CN=alice:mailto:alice@acme.com\r\n"),
+            true
+        );
+        assert_eq!(
+            v.contains(&format!("ORGANIZER;CN=bob:urn:uuid:{}\r\n", bob.uid)),
+            true
+        );
+        assert_eq!(v.contains("SUMMARY:call with alice\r\n"), true);
+        assert_eq!(v.contains("DESCRIPTION:talked\\, a lot\r\n"), true);
+    }
+
+    #[test]
+    fn test_entity_rows() {
+        // mapping
+        let m = CsvMapping::parse("name=Full Name, handle.email=E-mail").unwrap();
+        assert_eq!(m.column("name"), "Full Name");
+        assert_eq!(m.column("class"), "class");
+        assert_eq!(CsvMapping::parse("age=Age").is_err(), true);
+        assert_eq!(CsvMapping::parse("name").is_err(), true);
+        assert_eq!(CsvMapping::parse("").unwrap(), CsvMapping::default());
+        // a spreadsheet export
+        let d = tempfile::TempDir::new().unwrap();
+        let p = d.path().join("contacts.csv");
+        std::fs::write(
+            &p,
+            "Full Name,E-mail,tags,next_action_date,uid\nBob,bob@acme.com,feat:rust;friends,01.02.2021,\n,nobody@acme.com,,,\nAlice,,,someday,\nCarl,,,,not-a-uid\n",
+        )
+        .unwrap();
+        let rows = read_entity_rows(&p, &m).unwrap();
+        assert_eq!(rows.len(), 4);
+        let (line, bob) = &rows[0];
+        assert_eq!(*line, 2);
+        let bob = bob.as_ref().unwrap();
+        assert_eq!(bob.name, Some("Bob".to_owned()));
+        assert_eq!(
+            bob.handles,
+            vec![("email".to_owned(), "bob@acme.com".to_owned())]
+        );
+        assert_eq!(
+            bob.tags,
+            vec![
+                Tag::Feature("rust".to_owned()),
+                Tag::Generic("friends".to_owned())
+            ]
+        );
+        assert_eq!(bob.next_action_date, Some(utils::date(1, 2, 2021)));
+        assert_eq!(bob.uid, None);
+        assert_eq!(bob.class, None);
+        // the empty columns are missing values
+        assert_eq!(rows[1].1.as_ref().unwrap().name, None);
+        assert_eq!(rows[2].1.as_ref().err().unwrap(), "invalid date someday");
+        assert_eq!(rows[3].1.as_ref().err().unwrap(), "invalid uid not-a-uid");
+        // the records follow the columns
+        let e = Entity::from("bob")
+            .unwrap()
+            .with_handle("phone", "+41 123")
+            .with_handle("email", "bob@acme.com");
+        let r = entity_record(&e);
+        assert_eq!(r.len(), ENTITY_COLUMNS.len());
+        assert_eq!(r[0], e.uid());
+        assert_eq!(r[3], "email:bob@acme.com;phone:+41123");
+    }
+
+    #[test]
+    fn test_nquads() {
+        assert_eq!(nquad_escape("say \"hi\"\n\\"), "say \\\"hi\\\"\\n\\\\");
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let alice = Entity::from("alice")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "alice@acme.com")
+            .with_tag(Tag::Generic("rust".to_owned()))
+            .with_relation(&Rel::new(&bob));
+        let q = nquads(&alice);
+        let (a, b) = (utils::id(&alice.uid), utils::id(&bob.uid));
+        let has = |line: String| q.lines().any(|l| l == line);
+        assert_eq!(has(format!("_:{} <name> \"alice\" .", a)), true);
+        assert_eq!(has(format!("_:{} <xid> \"{}\" .", a, alice.uid())), true);
+        assert_eq!(has(format!("_:{} <sponsor> _:{} .", a, b)), true);
+        assert_eq!(
+            has(format!("_:{} <handle.email> \"alice@acme.com\" .", a)),
+            true
+        );
+        assert_eq!(has(format!("_:{} <tag.tag> \"rust\" .", a)), true);
+        assert_eq!(has(format!("_:{} <rel.related-to> _:{} .", a, b)), true);
+        // the empty fields are skipped
+        assert_eq!(q.contains("<description>"), false);
+        // every line is a quad
+        assert_eq!(
+            q.lines().all(|l| l.starts_with("_:") && l.ends_with(" .")),
+            true
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        assert_eq!(dot_escape("say \"hi\"\n\\"), "say \\\"hi\\\"\\n\\\\");
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let acme = Entity::from("acme").unwrap().with_class("org");
+        let mut alice = Entity::from("alice \"al\"")
+            .unwrap()
+            .with_class("person")
+            .with_sponsor(&bob)
+            .with_relation(&Rel {
+                kind: RelType::Role("ceo".to_owned(), utils::date(1, 1, 2020), None),
+                ..Rel::new(&acme)
+            });
+        alice.set_quality(RelQuality::Hostile(utils::date(1, 1, 2020), None));
+        let (a, b, c) = (alice.uid(), bob.uid(), acme.uid());
+        let out = dot(&alice);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            format!(
+                "  \"{}\" [label=\"alice \\\"al\\\"\", shape=ellipse, fillcolor=\"#fb9a99\"];",
+                a
+            )
+        );
+        assert_eq!(
+            lines[1],
+            format!(
+                "  \"{}\" -> \"{}\" [style=dashed, label=\"sponsored\"];",
+                b, a
+            )
+        );
+        assert_eq!(
+            lines[2],
+            format!("  \"{}\" -> \"{}\" [label=\"ceo\"];", a, c)
+        );
+        // the self sponsored entities have no sponsor edge
+        assert_eq!(dot(&bob).lines().count(), 1);
+        assert_eq!(dot(&acme).contains("shape=box"), true);
+    }
+
+    #[test]
+    fn test_graphml() {
+        assert_eq!(
+            xml_escape("<a & 'b'>\""),
+            "&lt;a &amp; &apos;b&apos;&gt;&quot;"
+        );
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let alice = Entity::from("alice & co")
+            .unwrap()
+            .with_class("person")
+            .with_sponsor(&bob)
+            .with_tag(Tag::from("group", "work"))
+            .with_tag(Tag::from("feat", "rust"))
+            .with_relation(&Rel::new(&bob));
+        let out = graphml(&alice, Some(utils::date(3, 2, 2021)));
+        let has = |line: &str| out.lines().any(|l| l.trim() == line);
+        assert_eq!(has(&format!("<node id=\"{}\">", alice.uid())), true);
+        assert_eq!(has("<data key=\"name\">alice &amp; co</data>"), true);
+        assert_eq!(has("<data key=\"class\">person</data>"), true);
+        assert_eq!(has("<data key=\"tags\">feat:rust,group:work</data>"), true);
+        assert_eq!(has("<data key=\"quality\">neutral</data>"), true);
+        assert_eq!(has("<data key=\"last_contact\">2021-02-03</data>"), true);
+        assert_eq!(
+            has(&format!(
+                "<edge source=\"{}\" target=\"{}\">",
+                bob.uid(),
+                alice.uid()
+            )),
+            true
+        );
+        assert_eq!(
+            has(&format!(
+                "<edge source=\"{}\" target=\"{}\">",
+                alice.uid(),
+                bob.uid()
+            )),
+            true
+        );
+        assert_eq!(has("<data key=\"label\">related to</data>"), true);
+        assert_eq!(out.matches("<edge ").count(), 2);
+        assert_eq!(out.matches("</edge>").count(), 2);
+        // no contact and no sponsor edge
+        let out = graphml(&bob, None);
+        assert_eq!(out.contains("last_contact"), false);
+        assert_eq!(out.contains("<edge"), false);
+    }
+
+    #[test]
+    fn test_vcard() {
+        assert_eq!(ics_unescape("a\\, b\\; c\\nd\\\\"), "a, b; c\nd\\");
+        let mut bob = Entity::from("bob, jr")
+            .unwrap()
+            .with_handle("email", "bob@acme.com")
+            .with_handle("mobile", "+41 123")
+            .with_handle("telegram", "bobjr");
+        bob.description = "met at the conference\nlikes rust".to_owned();
+        let v = vcard(&bob);
+        assert_eq!(v.starts_with("BEGIN:VCARD\r\nVERSION:3.0\r\n"), true);
+        assert_eq!(v.ends_with("END:VCARD\r\n"), true);
+        assert_eq!(v.contains("FN:bob\\, jr\r\n"), true);
+        assert_eq!(v.contains("EMAIL;TYPE=INTERNET:bob@acme.com\r\n"), true);
+        assert_eq!(v.contains("TEL;TYPE=CELL:+41123\r\n"), true);
+        assert_eq!(v.contains("telegram"), false);
+        // round trip
+        let d = tempfile::TempDir::new().unwrap();
+        let p = d.path().join("contacts.vcf");
+        std::fs::write(&p, &v).unwrap();
+        let cards = read_vcards(&p).unwrap();
+        assert_eq!(cards.len(), 1);
+        let card = cards[0].1.as_ref().unwrap();
+        assert_eq!(card.uid, Some(bob.uid));
+        assert_eq!(card.name, Some("bob, jr".to_owned()));
+        assert_eq!(card.description, Some(bob.description.clone()));
+        assert_eq!(
+            card.handles,
+            vec![
+                ("email".to_owned(), "bob@acme.com".to_owned()),
+                ("mobile".to_owned(), "+41123".to_owned())
+            ]
+        );
+        // cards from another application
+        std::fs::write(
+            &p,
+            "BEGIN:VCARD\nVERSION:3.0\nFN:Alice\nitem1.EMAIL;type=INTERNET;type=pref:alice@acme.com\nitem2.EMAIL:alice@acme.org\nTEL;TYPE=HOME:555\nNOTE:a long\n  note\nEND:VCARD\nBEGIN:VCARD\nUID:nope\nFN:Carl\nEND:VCARD\nBEGIN:VCARD\nFN:Dan\n",
+        )
+        .unwrap();
+        let cards = read_vcards(&p).unwrap();
+        assert_eq!(cards.len(), 3);
+        let (line, alice) = &cards[0];
+        assert_eq!(*line, 1);
+        let alice = alice.as_ref().unwrap();
+        assert_eq!(alice.uid, None);
+        assert_eq!(alice.description, Some("a long note".to_owned()));
+        assert_eq!(
+            alice.handles,
+            vec![
+                ("email".to_owned(), "alice@acme.com".to_owned()),
+                ("email".to_owned(), "alice@acme.org".to_owned()),
+                ("phone".to_owned(), "555".to_owned())
+            ]
+        );
+        assert_eq!(cards[1].0, 10);
+        assert_eq!(cards[1].1.as_ref().err().unwrap(), "invalid uid nope");
+        assert_eq!(cards[2].1.as_ref().err().unwrap(), "missing END:VCARD");
+    }
+
+    #[test]
+    fn test_export_checks() {
+        let d = tempfile::TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        let key = export_key("secret");
+        let bob = serde_json::to_string(&Entity::from("bob").unwrap()).unwrap();
+        let alice = serde_json::to_string(&Entity::from("alice").unwrap()).unwrap();
+        let write = |key: Option<&ExportKey>| {
+            let mut w = ExportWriter::new(File::create(&p).unwrap(), key);
+            w.write_record(&bob).unwrap();
+            w.write_record(&alice).unwrap();
+            w.finish().unwrap();
+        };
+        // unsigned
+        write(None);
+        let c = read_export(&p, None).unwrap();
+        assert_eq!(c.is_valid(), true);
+        assert_eq!(c.records.len(), 2);
+        assert_eq!(c.records[1].1.name(), "alice");
+        // a key requires a signature
+        let c = read_export(&p, Some(&key)).unwrap();
+        assert_eq!(c.corrupted, vec![(3, "missing signature".to_owned())]);
+        // signed
+        write(Some(&key));
+        assert_eq!(read_export(&p, Some(&key)).unwrap().is_valid(), true);
+        let c = read_export(&p, Some(&export_key("other"))).unwrap();
+        assert_eq!(c.corrupted, vec![(3, "invalid signature".to_owned())]);
+        // tamper with a record
+        let raw = std::fs::read_to_string(&p).unwrap();
+        std::fs::write(&p, raw.replacen("alice", "mallory", 1)).unwrap();
+        let c = read_export(&p, None).unwrap();
+        assert_eq!(c.records.len(), 1);
+        assert_eq!(
+            c.corrupted,
+            vec![
+                (2, "checksum mismatch".to_owned()),
+                (3, "file checksum mismatch".to_owned())
+            ]
+        );
+        // truncated file
+        std::fs::write(&p, raw.lines().next().unwrap()).unwrap();
+        let c = read_export(&p, None).unwrap();
+        assert_eq!(c.records.len(), 1);
+        assert_eq!(c.corrupted[0].0, 2);
+        // legacy export without checksums
+        std::fs::write(&p, format!("{}\n{{broken\n", bob)).unwrap();
+        let c = read_export(&p, None).unwrap();
+        assert_eq!(c.records.len(), 1);
+        assert_eq!(c.corrupted[0].0, 2);
+    }
+
+    #[test]
+    fn test_encryption() {
+        let data = b"{\"name\":\"bob\"}\n";
+        let sealed = encrypt(data, "secret").unwrap();
+        assert_eq!(sealed.starts_with(ENCRYPTED_HEADER), true);
+        assert_eq!(decrypt(&sealed, "secret").unwrap(), data.to_vec());
+        // the salt and the nonce are random
+        assert_ne!(encrypt(data, "secret").unwrap(), sealed);
+        // wrong passphrase, tampering and plain data
+        assert_eq!(decrypt(&sealed, "other").is_err(), true);
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(decrypt(&tampered, "secret").is_err(), true);
+        assert_eq!(decrypt(&sealed[..20], "secret").is_err(), true);
+        assert_eq!(decrypt(data, "secret").is_err(), true);
+        // files, the data is written encrypted only
+        let d = tempfile::TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        let mut export = Vec::new();
+        let mut w = ExportWriter::new(&mut export, None);
+        w.write_record(&serde_json::to_string(&Entity::from("bob").unwrap()).unwrap())
+            .unwrap();
+        w.finish().unwrap();
+        std::fs::write(&p, "{}").unwrap();
+        assert_eq!(is_encrypted(&p).unwrap(), false);
+        write_encrypted(&p, &export, "secret").unwrap();
+        assert_eq!(is_encrypted(&p).unwrap(), true);
+        assert_eq!(d.path().join("export.tmp").exists(), false);
+        assert_eq!(decrypt_file(&p, "other").is_err(), true);
+        let copy = decrypt_file(&p, "secret").unwrap();
+        let c = read_export(&copy, None).unwrap();
+        assert_eq!(c.is_valid(), true);
+        assert_eq!(c.records[0].1.name(), "bob");
+    }
+}
@@ -1,13 +1,28 @@
-use super::model::{self, Entity, Event, Tag};
-use chrono::NaiveDate;
+use super::backup::Retention;
+use super::formats::{self, EventRecord, ExportKey, ExportWriter, FullRecord, ImportSource};
+use super::model::{
+    self, legacy, AccessRole, ActorRole, Attachment, AuditAction, AuditEntry, Channel, Entity,
+    Escalation, Event, EventType, Goal, Handle, ImportantDate, InteractionDirection, MetaValue,
+    Priority, RelQuality, Tag, Task, TimeWindow,
+};
+use super::query::Query;
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, Utc, Weekday};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use fs2::FileExt;
 use rand::random;
-use simsearch::SimSearch;
+use simsearch::{SearchOptions, SimSearch};
 use sled::{transaction::TransactionResult, Batch, Transactional};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, LineWriter, Write};
-use std::path::Path;
+use std::io::{LineWriter, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
 
 use super::utils;
 
@@ -15,12 +30,27 @@ const TABLE_ENTITIES: &str = "ENTITIES";
 const TABLE_TAGS: &str = "TAGS";
 const TABLE_ACL: &str = "ACL";
 const TABLE_EDGES: &str = "EDGES";
+const TABLE_REVERSE_EDGES: &str = "REVERSE_EDGES";
 const TABLE_ACTIONS: &str = "ACTIONS";
 const TABLE_IDS: &str = "IDS";
 const TABLE_SYSTEM: &str = "SYSTEM";
 const TABLE_SPONSORSHIPS: &str = "SPONSORSHIPS";
 const TABLE_EVENTS: &str = "EVENTS";
 const TABLE_ENTITY_EVENT: &str = "ENTITY_EVENT";
+const TABLE_EVENTS_TIME: &str = "EVENTS_TIME";
+const TABLE_AUDIT: &str = "AUDIT";
+const TABLE_DATES: &str = "DATES";
+const TABLE_ATTACHMENTS: &str = "ATTACHMENTS";
+const TABLE_TASKS: &str = "TASKS";
+const TABLE_TASKS_DUE: &str = "TASKS_DUE";
+const TABLE_GOALS: &str = "GOALS";
+
+/// How long a password reset token is valid
+const RESET_TOKEN_DAYS: i64 = 7;
+/// How long a session lasts after the login
+const SESSION_HOURS: i64 = 12;
+/// The prefix of the sessions in the system tree
+const SESSION_PREFIX: &str = "session:";
 
 // Let's use generic errors
 type Result<T> = std::result::Result<T, DataError>;
@@ -35,13 +65,22 @@ pub enum DataError {
     InitializationError,
     IDAlreadyTaken,
     BrokenReference,
+    PermissionDenied,
+    InvalidToken,
+    HasDependents(Vec<model::Uuid>),
+    CorruptedData(Vec<(usize, String)>),
+    /// the datastore is open in another process, with its pid
+    /// or zero when the pid is unknown
+    Locked(u32),
+    /// the value of an handle is not valid for its type
+    InvalidHandle(String),
 }
 
 impl Error for DataError {}
 
 impl fmt::Display for DataError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
@@ -61,6 +100,22 @@ impl From<std::io::Error> for DataError {
 pub enum ExportFormat {
     Json,
     NQuad,
+    Csv,
+    Ics,
+    FullJson,
+    VCard,
+    Dot,
+    GraphMl,
+}
+
+/// The outcome of an events import
+///
+/// the skipped entries are reported with their line number
+/// and the reason they have been skipped
+#[derive(Debug, Default)]
+pub struct EventImportReport {
+    pub imported: usize,
+    pub skipped: Vec<(usize, String)>,
 }
 
 #[derive(PartialEq)]
@@ -69,892 +124,10973 @@ pub enum EventFilter {
     Actions,
     LogsWithMessage(String),
     ActionWithSource(String),
+    Postponed,
+    Role(ActorRole),
     Any,
+    Interactions,
 }
 
 impl EventFilter {
+    /// Tells if an event matches the filter, the Role filter
+    /// always matches since the role is checked on the index
     pub fn matches(&self, evt: &Event) -> bool {
         match self {
             Self::Logs => evt.kind.is_log(),
             Self::LogsWithMessage(m) => evt.kind.is_log() && (evt.kind.val() == *m),
             Self::Actions => !evt.kind.is_log(),
             Self::ActionWithSource(s) => !evt.kind.is_log() && (evt.kind.val() == *s),
+            Self::Postponed => evt.kind.is_log() && (evt.kind.val() == "postponed"),
+            Self::Interactions => evt.kind.is_interaction(),
             _ => true,
         }
     }
 }
 
-fn action_key(e: &Entity) -> String {
-    format!("{}:{}", e.next_action_date, e.uid())
-}
-fn tag_key(t: &Tag, e: &Entity) -> String {
-    format!("{}:{}:{}", t.prefix(), t.slug(), e.uid())
-}
-fn handle_key(p: &str, v: &str) -> String {
-    utils::hash(&utils::slugify(format!("{}:{}", p, v)))
-}
-fn sponsor_key(e: &model::Uuid, sponsor: &model::Uuid) -> String {
-    format!("{}:{}", utils::id(sponsor), utils::id(e))
-}
-fn str(v: &sled::IVec) -> String {
-    String::from_utf8_lossy(v).to_string()
-}
-
-/// A simple datastore that can persist data on file
+/// Filter the entities of the agenda by class, tag and relationship
+/// quality, the empty filter matches all the entities.
 ///
-pub struct DataStore {
-    db: sled::Db,
-    entities: sled::Tree,
-    actions: sled::Tree,
-    ids: sled::Tree,
-    tags: sled::Tree,
-    edges: sled::Tree,
-    acl: sled::Tree,
-    system: sled::Tree,
-    events: sled::Tree,
-    entity_event: sled::Tree,
-    sponsorships: sled::Tree,
-    // search index
-    index: SimSearch<String>,
+/// An entity matches if it matches at least one of the values
+/// of each kind of predicate that has been set
+#[derive(Debug, Clone, Default)]
+pub struct AgendaFilter {
+    pub classes: Vec<String>,
+    pub tag_prefixes: Vec<String>,
+    pub qualities: Vec<RelQuality>,
 }
 
-impl DataStore {
-    /// Initialize an empty datastore
-    ///
-    pub fn open(db_path: &Path) -> Result<DataStore> {
-        let db = sled::open(db_path)?;
-        let entities = db.open_tree(TABLE_ENTITIES)?;
-        let actions = db.open_tree(TABLE_ACTIONS)?;
-        let ids = db.open_tree(TABLE_IDS)?;
-        let tags = db.open_tree(TABLE_TAGS)?;
-        let edges = db.open_tree(TABLE_EDGES)?;
-        let acl = db.open_tree(TABLE_ACL)?;
-        let system = db.open_tree(TABLE_SYSTEM)?;
-        let sponsorships = db.open_tree(TABLE_SPONSORSHIPS)?;
-        // events
-        let events = db.open_tree(TABLE_EVENTS)?;
-        let entity_event = db.open_tree(TABLE_ENTITY_EVENT)?;
-        // search index
-        let index = SimSearch::new();
-        // generate salt for passwords
-        let salt: String = (0..64).map(|_| random::<char>()).collect();
-        let salt_hash: &str = &utils::hash(&salt);
-        system.insert("password:salt", salt_hash)?;
-        // datastore
-        let mut ds = DataStore {
-            db,
-            entities,
-            actions,
-            ids,
-            tags,
-            edges,
-            acl,
-            system,
-            events,
-            entity_event,
-            sponsorships,
-            index,
-        };
-        // build the search index
-        ds.build_search_index();
-        // complete
-        Ok(ds)
+impl AgendaFilter {
+    /// Match the entities of a class, eg. person
+    pub fn with_class(mut self, class: &str) -> Self {
+        self.classes.push(class.to_owned());
+        self
     }
 
-    fn build_search_index(&mut self) {
-        self.index = SimSearch::new();
-        self.entities.iter().for_each(|r| {
-            let (_, raw) = r.unwrap();
-            let e: Entity = bincode::deserialize(&raw).unwrap();
+    /// Match the entities with a tag with a prefix, eg. group
+    /// matches all the groups while group:family matches the
+    /// family group only, the prefix aliases are accepted
+    pub fn with_tag_prefix(mut self, prefix: &str) -> Self {
+        self.tag_prefixes.push(prefix.to_owned());
+        self
+    }
 
-            let data = format!(
-                "{} {} {}",
-                e.name(),
-                e.get_tags().join(" "),
-                e.handles
-                    .iter()
-                    .map(|(_, v)| v.to_string())
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            );
-            self.index.insert(e.uid(), &data);
-        });
+    /// Match the entities with a relationship quality,
+    /// the dates of the quality are ignored
+    pub fn with_quality(mut self, quality: RelQuality) -> Self {
+        self.qualities.push(quality);
+        self
     }
 
-    /// return if the database is empty
+    /// Tells if the filter matches all the entities
     pub fn is_empty(&self) -> bool {
-        let entities = self.db.open_tree(TABLE_ENTITIES).unwrap();
-        entities.len() == 0
+        self.classes.is_empty() && self.tag_prefixes.is_empty() && self.qualities.is_empty()
     }
 
-    /// Flush and close the datastore
-    ///
-    /// be aware that the underling files may
-    /// take a moment to actually close the file
-    pub fn close(&self) {
-        self.db.flush().unwrap();
-        drop(&self.db);
+    /// Tells if an entity matches the filter
+    pub fn matches(&self, e: &Entity) -> bool {
+        let class = self.classes.is_empty() || self.classes.iter().any(|c| *c == e.class);
+        let tag = self.tag_prefixes.is_empty()
+            || self.tag_prefixes.iter().any(|p| {
+                let (prefix, slug) = utils::split_once(p, ':').unwrap_or((p, ""));
+                let t = Tag::from(prefix, slug);
+                e.tags.values().any(|et| et.is_within(&t))
+            });
+        let quality =
+            self.qualities.is_empty() || self.qualities.iter().any(|q| q.same_kind(&e.quality));
+        class && tag && quality
     }
+}
 
-    /// Export the dataset in the format expressed by the format parameter
-    ///
-    pub fn export(&self, path: &Path, format: ExportFormat) -> Result<()> {
-        let mut file = LineWriter::new(File::create(path)?);
+/// the file in the datastore directory with the pid of the process using it
+const LOCK_FILE: &str = "valis.lock";
+/// how often to check if a locked datastore has been released
+const LOCK_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// how many times to retry to open a database that sled has not released yet
+const SLED_LOCK_RETRIES: usize = 20;
 
-        if format == ExportFormat::NQuad {
-            return Err(DataError::NotImplemented);
-        }
+/// system keys for the search configuration
+const META_SEARCH_THRESHOLD: &str = "search.threshold";
+const META_SEARCH_NAME_WEIGHT: &str = "search.weight.name";
+const META_SEARCH_TAG_WEIGHT: &str = "search.weight.tag";
+const META_SEARCH_HANDLE_WEIGHT: &str = "search.weight.handle";
+const META_SEARCH_FIELD_WEIGHT: &str = "search.weight.field";
+const META_BACKUP_EVERY: &str = "backup.every";
+const META_BACKUP_LAST: &str = "backup.last";
+const META_BACKUP_KEEP_DAILY: &str = "backup.keep.daily";
+const META_BACKUP_KEEP_WEEKLY: &str = "backup.keep.weekly";
+const META_BACKUP_KEEP_MONTHLY: &str = "backup.keep.monthly";
+const META_AGENDA_BUCKETS: &str = "agenda.buckets";
+/// system keys for the context settings
+const META_SETTINGS_TIMEZONE: &str = "settings.timezone";
+const META_SETTINGS_LOCALE: &str = "settings.locale";
+const META_SETTINGS_WEEK_START: &str = "settings.week_start";
+const META_SETTINGS_REMINDER: &str = "settings.reminder";
+const META_SETTINGS_MENU: &str = "settings.menu";
+/// set once the handles stored before the normalization have been normalized
+const META_HANDLES_NORMALIZED: &str = "handles.normalized";
+/// the version of the format of the stored entities and events
+const META_SCHEMA_VERSION: &str = "schema.version";
+/// set while the indexes are rebuilt after a migration
+const META_SCHEMA_REINDEX: &str = "schema.reindex";
+/// The current version of the format of the stored records, the
+/// datastores without a version are in the format that predates it.
+/// Version 2 records the delegate in the audit entries, version 3
+/// keeps the priority in the action index keys
+const SCHEMA_VERSION: u32 = 3;
+/// the lifecycle of a class is stored as lifecycle.<class>
+const META_LIFECYCLE_PREFIX: &str = "lifecycle.";
+/// The prefix of the typed metadata keys in the system tree
+const TYPED_META_PREFIX: &str = "tmeta:";
 
-        match format {
-            ExportFormat::Json => self.entities.iter().for_each(|r| {
-                let (_, raw) = r.unwrap();
-                let e: Entity = bincode::deserialize(&raw).unwrap();
-                let j = serde_json::to_string(&e).unwrap();
-                file.write(j.as_bytes()).ok();
-                file.write("\n".as_bytes()).ok();
-            }),
-            _ => {}
-        };
-        file.flush()?;
-        Ok(())
-    }
+/// The prefix of the backup file names
+pub const BACKUP_PREFIX: &str = "valis-";
+/// The extension of the backup file names
+pub const BACKUP_EXT: &str = ".json";
+/// The timestamp format used in the backup file names
+pub const BACKUP_TS_FORMAT: &str = "%Y%m%dT%H%M%S";
+/// The prefix of the snapshot file names
+pub const SNAPSHOT_PREFIX: &str = "valis-snapshot-";
+/// The extension of the snapshot file names
+pub const SNAPSHOT_EXT: &str = ".bin.gz";
 
-    /// Import the dataset from an export
-    pub fn import(&mut self, path: &Path, format: ExportFormat) -> Result<()> {
-        if format == ExportFormat::NQuad {
-            return Err(DataError::NotImplemented);
+/// The content of a snapshot, the name and the key/value pairs of every tree
+type Snapshot = Vec<(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)>;
+
+/// Compress and encrypt a snapshot with a passphrase
+fn seal_snapshot(trees: &Snapshot, passphrase: &str) -> Result<Vec<u8>> {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    bincode::serialize_into(&mut gz, trees).map_err(|e| DataError::GenericError(e.to_string()))?;
+    formats::encrypt(&gz.finish()?, passphrase)
+}
+
+/// Decrypt and decompress a snapshot sealed with seal_snapshot
+fn unseal_snapshot(data: &[u8], passphrase: &str) -> Result<Snapshot> {
+    let raw = formats::decrypt(data, passphrase)?;
+    bincode::deserialize_from(GzDecoder::new(raw.as_slice()))
+        .map_err(|e| DataError::GenericError(format!("invalid snapshot: {}", e)))
+}
+
+/// The configuration of the entity search, stored per context
+///
+/// The threshold is the minimum similarity (from 0 to 1) for a term
+/// to match, while the weights are used to rank the results
+/// depending on which field of the entity has matched
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchConfig {
+    pub threshold: f64,
+    pub name_weight: f64,
+    pub tag_weight: f64,
+    pub handle_weight: f64,
+    pub field_weight: f64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            threshold: 0.8,
+            name_weight: 1.0,
+            tag_weight: 0.5,
+            handle_weight: 0.8,
+            field_weight: 0.6,
         }
-        // clean the database before starting
-        self.db.clear()?;
-        let file = File::open(path)?;
-        match format {
-            ExportFormat::Json => BufReader::new(file).lines().for_each(|r| {
-                let line = r.unwrap();
-                let e: Entity = serde_json::from_str(&line).unwrap();
-                self.insert(&e).unwrap();
-            }),
-            _ => {}
-        };
-        Ok(())
     }
+}
 
-    /// Set a metadata value
-    pub fn set_meta(&mut self, key: &str, val: &str) -> Result<()> {
-        let k = format!("meta:{}", key);
-        self.system.insert(&k, val)?;
-        Ok(())
-    }
+/// A time bucket of the agenda overview
+///
+/// The buckets are consecutive, each one starts where the
+/// previous one ends, the first one starts today. The window
+/// `past` collects everything before the bucket start, so it fits
+/// only as the first bucket. As a string a bucket is written as
+/// `label=window`, eg. `Next month=1m`.
+///
+/// The agenda lists a page of entries for each bucket, unless the
+/// bucket shows all of them, see with_show_all
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgendaBucket {
+    pub label: String,
+    pub window: TimeWindow,
+    pub show_all: bool,
+}
 
-    /// Get a metadata value
-    pub fn get_meta(&mut self, key: &str) -> Option<String> {
-        let k = format!("meta:{}", key);
-        if let Ok(v) = self.system.get(&k) {
-            if let Some(v) = v {
-                return Some(str(&v));
-            }
+impl AgendaBucket {
+    pub fn new(label: &str, window: TimeWindow) -> AgendaBucket {
+        AgendaBucket {
+            label: label.to_owned(),
+            window,
+            show_all: false,
         }
-        None
     }
 
-    /// Perform a search for a string in tags and transaction name
-    ///
-    pub fn search(&self, pattern: &str) -> Vec<Entity> {
-        self.index
-            .search(pattern)
-            .iter()
-            .map(|id| {
-                let raw = self.entities.get(&id).unwrap().unwrap();
-                bincode::deserialize(&raw).unwrap()
-            })
-            .collect::<Vec<Entity>>()
+    /// List all the entries of the bucket, with no page limit
+    pub fn with_show_all(mut self) -> AgendaBucket {
+        self.show_all = true;
+        self
     }
 
-    /// Get a list of events for an entity sorted
-    /// by date descending (latest first).
-    ///
-    /// Retrieve the list of events for an entity
-    pub fn events(&self, subject: &Entity, filter: EventFilter) -> Vec<Event> {
-        self.events_within(subject, filter, None, None)
+    /// The default agenda buckets
+    pub fn defaults() -> Vec<AgendaBucket> {
+        vec![
+            AgendaBucket::new("Past", TimeWindow::UpTo),
+            AgendaBucket::new("Today", TimeWindow::Day(1)),
+            AgendaBucket::new("Tomorrow", TimeWindow::Day(1)),
+            AgendaBucket::new("Within a week", TimeWindow::Day(6)),
+            AgendaBucket::new("Within 2 weeks", TimeWindow::Day(7)),
+            AgendaBucket::new("Within 4 weeks", TimeWindow::Day(14)),
+        ]
     }
 
-    /// Get a list of events for an entity sorted by date
-    /// descending (latest first) between two dates
-    pub fn events_within(
-        &self,
-        subject: &Entity,
-        filter: EventFilter,
-        since: Option<NaiveDate>,
-        until: Option<NaiveDate>,
-    ) -> Vec<Event> {
-        let prefix: &str = &subject.uid();
-        self.entity_event
-            .scan_prefix(prefix)
-            .map(|r| {
-                let (_k, v) = r.unwrap();
-                let raw = self.events.get(v).unwrap().unwrap();
-                bincode::deserialize(&raw).unwrap()
-            })
-            .filter(|e: &Event| filter.matches(e) && e.is_between(since, until))
-            .collect()
+    /// Parse a comma separated list of buckets
+    pub fn parse_list(s: &str) -> Result<Vec<AgendaBucket>> {
+        s.split(',').map(AgendaBucket::from_str).collect()
     }
 
-    /// Records an event
-    ///
-    /// An event is recorded in the tree events that is
-    /// <uid, Event>
-    /// and for all the actors in the entity_event as
-    /// <actor_uid:event_uid, event_uid>
-    pub fn record(&mut self, event: &Event) -> Result<model::Uuid> {
-        // consistency check
-        if event.actors.is_empty() {
-            return Err(DataError::GenericError("no actors for event".to_string()));
-        }
-        // serialize
-        let k: &str = &event.uid();
-        // prepare batch for entity_event
-        let mut ee_batch = Batch::default();
-        for actor in event.actors.iter() {
-            // consistency check
-            if !self.entities.contains_key(actor.uid())? {
-                return Err(DataError::BrokenReference);
-            }
-            // now insert <actor_uid:event_uid, event_uid>
-            let ak: &str = &format!(
-                "{}:{}:{}",
-                actor.uid(),
-                i64::MAX - event.recorded_at.timestamp_millis(),
-                event.uid()
-            );
-            ee_batch.insert(ak, k);
+    /// Returns the date range of the bucket starting at a date,
+    /// a window in weeks ends at the start of a week, so that
+    /// `This week=1w` ends with the current week
+    pub fn range(&self, since: &NaiveDate, settings: &Settings) -> (NaiveDate, NaiveDate) {
+        match self.window {
+            TimeWindow::Week(n) => (
+                *since,
+                settings.week_start_of(since) + Duration::days(7 * n.max(1)),
+            ),
+            _ => self.window.range(since),
         }
+    }
+}
 
-        let (e, ee) = (&self.events, &self.entity_event);
-        // start a transaction
-        let r: TransactionResult<(), DataError> = (e, ee).transaction(|(events, entity_event)| {
-            let v = bincode::serialize(event).unwrap();
-            // insert the event
-            events.insert(k, v)?;
-            // record the connection between event and entity
-            entity_event.apply_batch(&ee_batch)?;
-            Ok(())
-        });
-        match r {
-            Ok(()) => Ok(event.uid),
-            Err(_) => Err(DataError::TxError),
+impl fmt::Display for AgendaBucket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.window {
+            TimeWindow::UpTo => write!(f, "{}=past", self.label),
+            _ => write!(f, "{}={}", self.label, self.window),
         }
     }
+}
 
-    /// Retrieve an entity by one of its ids
-    pub fn get_by_id(&self, prefix: &str, id: &str) -> Result<Option<Entity>> {
-        match self.ids.get(handle_key(prefix, id))? {
-            Some(uid) => match self.entities.get(uid)? {
-                Some(v) => Ok(Some(bincode::deserialize(&v).unwrap())),
-                None => Err(DataError::BrokenReference),
+impl FromStr for AgendaBucket {
+    type Err = DataError;
+
+    fn from_str(s: &str) -> Result<AgendaBucket> {
+        let invalid = || DataError::GenericError(format!("invalid agenda bucket: {}", s.trim()));
+        let (label, window) = utils::split_once(s, '=').ok_or_else(invalid)?;
+        let (label, window) = (label.trim(), window.trim());
+        if label.is_empty() {
+            return Err(invalid());
+        }
+        match window {
+            "past" => Ok(AgendaBucket::new(label, TimeWindow::UpTo)),
+            w if w.starts_with(|c: char| c.is_ascii_digit()) => match TimeWindow::from_str(w) {
+                Ok(tw) => Ok(AgendaBucket::new(label, tw)),
+                Err(_) => Err(invalid()),
             },
-            None => Ok(None),
+            _ => Err(invalid()),
         }
     }
+}
 
-    /// Retrieve an entity its uid
-    pub fn get_by_uid(&self, uid: &str) -> Result<Option<Entity>> {
-        match self.entities.get(uid)? {
-            Some(v) => Ok(Some(bincode::deserialize(&v).unwrap())),
-            None => Ok(None),
+/// The settings of a context
+///
+/// The timezone is an offset from UTC, when missing the system
+/// timezone is used. The locale decides how the dates are shown,
+/// the reminder is the default window to set the next action and
+/// the menu lists the actions of the interactive menu in the order
+/// they are shown, when empty all the actions are shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub timezone: Option<FixedOffset>,
+    pub locale: String,
+    pub week_start: Weekday,
+    pub reminder: TimeWindow,
+    pub menu: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            timezone: None,
+            locale: "en".to_owned(),
+            week_start: Weekday::Mon,
+            reminder: TimeWindow::Week(1),
+            menu: Vec::new(),
         }
     }
+}
 
-    pub fn agenda_until(&self, until: &NaiveDate, _limit: usize, _offset: usize) -> Vec<Entity> {
-        self.actions
-            .iter()
-            .map(|r| {
-                let (_k, v) = r.unwrap();
-                let raw = self.entities.get(v).unwrap().unwrap();
-                bincode::deserialize(&raw).unwrap()
-            })
-            .filter(|e: &Entity| e.action_within(until))
-            .collect::<Vec<Entity>>()
+impl Settings {
+    /// Returns the current date in the timezone of the settings
+    pub fn today(&self) -> NaiveDate {
+        match self.timezone {
+            Some(tz) => Utc::now().with_timezone(&tz).date_naive(),
+            None => utils::today(),
+        }
     }
 
-    /// Return aggregation summary for tags
-    ///
-    pub fn agenda(
-        &self,
-        since: &NaiveDate,
-        until: &NaiveDate,
-        _limit: usize,
-        _offset: usize,
-    ) -> Vec<Entity> {
-        let prefix_str = utils::prefix(&since.to_string(), &until.pred().to_string());
-        // fetch all the stuff
-        self.actions
-            .scan_prefix(prefix_str)
-            .map(|r| {
-                let (_k, v) = r.unwrap();
-                let raw = self.entities.get(v).unwrap().unwrap();
-                bincode::deserialize(&raw).unwrap()
-            })
-            .filter(|e: &Entity| {
-                // TODO: also match disabled records
-                e.action_within_range(since, until)
-            })
-            .collect::<Vec<Entity>>()
+    /// Returns the first day of the week containing a date
+    pub fn week_start_of(&self, date: &NaiveDate) -> NaiveDate {
+        let days = (7 + date.weekday().num_days_from_monday()
+            - self.week_start.num_days_from_monday())
+            % 7;
+        *date - Duration::days(days as i64)
     }
 
-    /// Initialized the database with a principal identity.
-    ///
-    /// It requires that the database is empty and checks that the
-    /// sponsor and the user id are the same
-    pub fn init(&mut self, principal: &Entity) -> Result<model::Uuid> {
-        if !self.is_empty() {
-            return Err(DataError::InitializationError);
+    /// Returns the format of the dates for the locale, the
+    /// month comes first only for the US
+    pub fn date_format(&self) -> &'static str {
+        match self.locale.replace('_', "-").to_uppercase().as_ref() {
+            "EN-US" | "ES-US" => "%a, %m/%d/%y",
+            _ => "%a, %d.%m.%y",
         }
-        if principal.uid() != principal.sponsor_uid() {
-            return Err(DataError::InitializationError);
-        };
-        let uid = self.insert(principal)?;
-        // create a event log
-        self.record(&Event::log("init", principal, None))?;
-        // return the entity uid
-        Ok(uid)
     }
 
-    /// Adds a new entity to the database
-    pub fn add(&mut self, entity: &Entity) -> Result<model::Uuid> {
-        // search for the sponsor
-        match self.get_by_uid(&entity.sponsor_uid())? {
-            Some(sponsor) => {
-                // cannot self sponsor
-                if sponsor.uid() == entity.uid() {
-                    return Err(DataError::InvalidSponsor);
-                }
-                Ok(())
-            }
-            None => Err(DataError::InvalidSponsor),
-        }?;
-        // now check for conflicting ids
-        for (label, id) in entity.handles.iter() {
-            if self.ids.get(&handle_key(label, id))?.is_some() {
-                return Err(DataError::IDAlreadyTaken);
-            }
-        }
-        // all good
-        let uid = self.insert(entity)?;
-        // create a event log
-        self.record(&Event::log("added", entity, None))?;
-        // return the entity uid
-        Ok(uid)
+    /// Format a date according to the locale
+    pub fn human_date(&self, date: &NaiveDate) -> String {
+        date.format(self.date_format()).to_string()
     }
+}
 
-    pub fn update(&mut self, entity: &Entity) -> Result<model::Uuid> {
-        // search for the sponsor
-        match self.get_by_uid(&entity.uid())? {
-            Some(old) => {
-                // remove existing action dates if they have changed
-                if old.next_action_date != entity.next_action_date {
-                    self.actions.remove(&action_key(&old))?;
+/// Parse an offset from UTC, eg. +02:00, -0530 or UTC
+pub fn parse_utc_offset(s: &str) -> Result<FixedOffset> {
+    let invalid = || DataError::GenericError(format!("invalid timezone: {}", s));
+    let s = match s.trim() {
+        z if z.eq_ignore_ascii_case("utc") || z == "Z" => "+00:00",
+        s => s,
+    };
+    let sign = match s.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let digits = s[1..].replace(':', "");
+    if digits.len() != 2 && digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let hours = digits[..2].parse::<i32>().unwrap();
+    let minutes = match digits.len() {
+        4 => digits[2..].parse::<i32>().unwrap(),
+        _ => 0,
+    };
+    if hours > 14 || minutes > 59 {
+        return Err(invalid());
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+/// The lifecycle of the entities of a class, eg. the sales
+/// pipeline of the orgs, with the states in pipeline order
+/// and the transitions allowed between them.
+///
+/// As a string the transitions are written as chains of states
+/// separated by `>` and the chains are separated by commas, eg.
+/// `lead>prospect>client>dormant>client` allows a dormant client to
+/// become a client again, `lead>client,lead>lost` forks the lead
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lifecycle {
+    pub states: Vec<String>,
+    pub transitions: Vec<(String, String)>,
+}
+
+impl Lifecycle {
+    /// The default lifecycle of a class, if any
+    pub fn default_for(class: &str) -> Option<Lifecycle> {
+        match class {
+            "org" => "lead>prospect>client>dormant>client".parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Tells if a state is part of the lifecycle
+    pub fn has_state(&self, state: &str) -> bool {
+        self.states.iter().any(|s| s == state)
+    }
+
+    /// Returns the states reachable from a state, an
+    /// entity without a state can enter any of them
+    pub fn next_states(&self, from: Option<&str>) -> Vec<&str> {
+        match from {
+            Some(f) => self
+                .transitions
+                .iter()
+                .filter(|(a, _)| a == f)
+                .map(|(_, b)| &b[..])
+                .collect(),
+            None => self.states.iter().map(|s| &s[..]).collect(),
+        }
+    }
+
+    /// Tells if an entity can move from a state to another
+    pub fn can_move(&self, from: Option<&str>, to: &str) -> bool {
+        self.next_states(from).contains(&to)
+    }
+}
+
+impl fmt::Display for Lifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // join the transitions that follow each other in a chain
+        let mut chains: Vec<Vec<&str>> = Vec::new();
+        for (a, b) in self.transitions.iter() {
+            match chains.last_mut() {
+                Some(c) if c.last() == Some(&&a[..]) => c.push(b),
+                _ => chains.push(vec![a, b]),
+            }
+        }
+        let chains = chains.iter().map(|c| c.join(">")).collect::<Vec<_>>();
+        write!(f, "{}", chains.join(","))
+    }
+}
+
+impl FromStr for Lifecycle {
+    type Err = DataError;
+
+    fn from_str(s: &str) -> Result<Lifecycle> {
+        let invalid = || DataError::GenericError(format!("invalid lifecycle: {}", s.trim()));
+        let mut lc = Lifecycle {
+            states: Vec::new(),
+            transitions: Vec::new(),
+        };
+        for chain in s.split(',') {
+            let states = chain
+                .split('>')
+                .map(|st| st.trim().to_lowercase())
+                .collect::<Vec<String>>();
+            if states.len() < 2 || states.iter().any(|st| st.is_empty()) {
+                return Err(invalid());
+            }
+            for st in states.iter() {
+                if !lc.has_state(st) {
+                    lc.states.push(st.to_owned());
                 }
-                // remove existing sponsor
-                if old.sponsor != entity.sponsor {
-                    let sk = sponsor_key(&entity.uid, &old.sponsor);
-                    self.sponsorships.remove(&sk)?;
+            }
+            for w in states.windows(2) {
+                let t = (w[0].to_owned(), w[1].to_owned());
+                if t.0 == t.1 {
+                    return Err(invalid());
                 }
-                // remove existing tags if exists
-                for (k, t) in old.tags.iter() {
-                    if !entity.tags.contains_key(k) {
-                        self.tags.remove(&tag_key(t, entity))?;
-                    }
+                if !lc.transitions.contains(&t) {
+                    lc.transitions.push(t);
                 }
-                // remove existing ids
-                for (k, v) in old.handles.iter() {
-                    if !entity.handles.contains_key(k) {
-                        self.ids.remove(&handle_key(k, v))?;
-                    }
+            }
+        }
+        Ok(lc)
+    }
+}
+
+/// A page of results and the total number of results available
+///
+/// a limit of zero means no limit, so the page holds all
+/// the results past the offset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+}
+
+impl<T> Page<T> {
+    /// Tells if there are more results past this page
+    pub fn has_more(&self, offset: usize) -> bool {
+        offset + self.items.len() < self.total
+    }
+}
+
+/// The changes that an import would apply to the datastore
+///
+/// updated holds the pairs (current, imported) while conflicts
+/// holds the imported entities that cannot be imported as they are,
+/// skipped holds the lines that would be (partially) skipped
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    pub new: Vec<Entity>,
+    pub updated: Vec<(Entity, Entity)>,
+    pub conflicts: Vec<(Entity, String)>,
+    pub removed: Vec<Entity>,
+    pub unchanged: usize,
+    pub skipped: Vec<(usize, String)>,
+}
+
+/// A conflict found while importing an entity
+///
+/// local is the entity already known, either stored in the
+/// datastore or imported earlier from the same file
+#[derive(Debug, Clone)]
+pub struct ImportConflict {
+    pub local: Entity,
+    pub imported: Entity,
+    pub reason: String,
+}
+
+/// How to resolve an import conflict
+#[derive(Debug, Clone)]
+pub enum Resolution {
+    KeepLocal,
+    TakeImported,
+    Merge(Entity),
+    Skip,
+}
+
+/// How an import applies to the datastore
+///
+/// Replace swaps the current entities with the imported ones, while
+/// Merge inserts or updates the imported entities by uid and keeps
+/// the others
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    Replace,
+    Merge,
+}
+
+/// The outcome of an import
+///
+/// the skipped entries are reported with their line number
+/// and the reason they have been (partially) skipped
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: Vec<(usize, String)>,
+}
+
+/// The direction of a relationship seen from one of its ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// the entity is related to the other one
+    Outgoing,
+    /// the other entity is related to the entity
+    Incoming,
+}
+
+/// A broken reference found by the integrity check, with
+/// the dangling key and the uid it points to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inconsistency {
+    /// a handle registered to an entity that does not exist
+    DanglingHandle(String, String),
+    /// an event link of an entity or an event that does not exist
+    DanglingEventLink(String, String),
+    /// an action of an entity that does not exist, is archived
+    /// or has a different next action date
+    OrphanedAction(String, String),
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DanglingHandle(k, uid) => {
+                write!(f, "handle {} points to the missing entity {}", k, uid)
+            }
+            Self::DanglingEventLink(k, uid) => write!(f, "event link {} points to {}", k, uid),
+            Self::OrphanedAction(k, uid) => {
+                write!(f, "action {} does not match the entity {}", k, uid)
+            }
+        }
+    }
+}
+
+/// The outcome of an integrity check
+///
+/// repaired is true when the dangling keys have been removed
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub checked: usize,
+    pub issues: Vec<Inconsistency>,
+    pub repaired: bool,
+}
+
+impl IntegrityReport {
+    /// Tells if no broken references have been found
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// A session opened with DataStore::login
+///
+/// the token is handed to the user and never stored as is
+#[derive(Debug, Clone)]
+pub struct SessionToken {
+    pub token: String,
+    pub user: model::Uuid,
+    pub expires: DateTime<FixedOffset>,
+}
+
+/// What a purge touched, see DataStore::purge
+#[derive(Debug, Default)]
+pub struct PurgeReport {
+    pub entity: model::Uuid,
+    pub events_removed: usize,
+    pub events_scrubbed: usize,
+    pub audit_removed: usize,
+    pub audit_scrubbed: usize,
+    pub attachments_removed: usize,
+}
+
+/// What recording a note did with its labels, see DataStore::record_note
+#[derive(Debug, Default)]
+pub struct NoteReport {
+    pub event: model::Uuid,
+    pub captured: Vec<String>,
+    pub ambiguous: Vec<String>,
+}
+
+/// What a quick add did, see DataStore::quick_add
+#[derive(Debug, Default)]
+pub struct QuickAddReport {
+    pub note: NoteReport,
+    /// the entities labeled in the line, as they have been stored
+    pub entities: Vec<Entity>,
+    /// the next action date set on the entities, if any
+    pub date: Option<NaiveDate>,
+}
+
+/// A node of the sponsorship tree, an entity and
+/// the entities it introduced down to the max depth
+#[derive(Debug, Clone)]
+pub struct SponsorshipNode {
+    pub entity: Entity,
+    pub depth: usize,
+    /// the entities introduced directly or indirectly,
+    /// including the ones past the max depth
+    pub descendants: usize,
+    pub sponsored: Vec<SponsorshipNode>,
+}
+
+impl SponsorshipNode {
+    /// Tells if the node has descendants past the max depth
+    pub fn is_truncated(&self) -> bool {
+        self.sponsored.is_empty() && self.descendants > 0
+    }
+
+    /// Returns the uids of the node and of the nodes
+    /// below it, each one before its sponsored
+    pub fn uids(&self) -> Vec<String> {
+        let mut uids = vec![self.entity.uid()];
+        for n in self.sponsored.iter() {
+            uids.extend(n.uids());
+        }
+        uids
+    }
+}
+
+/// A summary of the content of a datastore
+///
+/// the tags are counted once per entity and prefix, the events
+/// are grouped by the month they were recorded (yyyy-mm) and the
+/// size of a tree is the size of its keys and values in bytes
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    pub entities: usize,
+    pub by_class: BTreeMap<String, usize>,
+    pub by_tag_prefix: BTreeMap<String, usize>,
+    pub events: usize,
+    pub events_by_month: BTreeMap<String, usize>,
+    pub overdue: usize,
+    pub tree_sizes: BTreeMap<String, usize>,
+    pub disk_size: u64,
+}
+
+/// The interactions an entity took part in within a period
+///
+/// the interactions are counted by channel (call, meeting, email),
+/// the minutes are the time spent in calls and meetings and the
+/// frequency is the number of interactions every 30 days
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InteractionStats {
+    pub total: usize,
+    pub by_channel: BTreeMap<Channel, usize>,
+    pub incoming: usize,
+    pub outgoing: usize,
+    pub minutes: u64,
+    pub last: Option<NaiveDate>,
+    pub per_month: f64,
+}
+
+/// How far a goal has come, see DataStore::goal_progress
+///
+/// the events are the ones linked to the goal and the ones its
+/// entities took part in since the goal was set, the touched
+/// entities are the ones that took part in at least one of them
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GoalProgress {
+    pub entities: usize,
+    pub touched: usize,
+    pub events: usize,
+    pub last: Option<NaiveDate>,
+}
+
+/// A change notification emitted by the datastore write paths
+///
+/// See DataStore::subscribe and DataStore::watch
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeEvent {
+    EntityAdded(model::Uuid),
+    EntityUpdated(model::Uuid),
+    EntityRemoved(model::Uuid),
+    EventRecorded(model::Uuid),
+}
+
+/// Select the changes streamed by DataStore::watch
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeFilter {
+    Any,
+    Entities,
+    Events,
+    /// the changes of an entity and the events it takes part in
+    Entity(model::Uuid),
+}
+
+/// The field of an entity that matched a search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchField {
+    Name,
+    Tag,
+    Handle,
+    Field,
+}
+
+impl fmt::Display for MatchField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Name => write!(f, "name"),
+            Self::Tag => write!(f, "tag"),
+            Self::Handle => write!(f, "handle"),
+            Self::Field => write!(f, "field"),
+        }
+    }
+}
+
+/// A search result with the field that matched and its relevance score
+///
+/// exact is true when the pattern matched exactly one of the entity handles
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub entity: Entity,
+    pub field: MatchField,
+    pub exact: bool,
+    pub score: f64,
+}
+
+/// The search index, one per searchable field
+struct SearchIndex {
+    config: SearchConfig,
+    names: SimSearch<String>,
+    tags: SimSearch<String>,
+    handles: SimSearch<String>,
+    fields: SimSearch<String>,
+}
+
+impl SearchIndex {
+    fn new(config: SearchConfig) -> SearchIndex {
+        let opts = || SearchOptions::new().threshold(config.threshold);
+        SearchIndex {
+            names: SimSearch::new_with(opts()),
+            tags: SimSearch::new_with(opts()),
+            handles: SimSearch::new_with(opts()),
+            fields: SimSearch::new_with(opts()),
+            config,
+        }
+    }
+
+    fn insert(&mut self, e: &Entity) {
+        self.names.insert(e.uid(), &name_values(e));
+        self.tags.insert(e.uid(), &e.get_tags().join(" "));
+        self.handles.insert(e.uid(), &handle_values(e));
+        self.fields.insert(e.uid(), &field_values(e));
+    }
+
+    fn search(&self, pattern: &str) -> BTreeSet<String> {
+        let mut found = BTreeSet::new();
+        found.extend(self.names.search(pattern));
+        found.extend(self.tags.search(pattern));
+        found.extend(self.handles.search(pattern));
+        found.extend(self.fields.search(pattern));
+        found
+    }
+}
+
+/// The name and the aliases of an entity
+fn name_values(e: &Entity) -> String {
+    let mut names = vec![e.name().to_owned()];
+    names.extend(e.aliases.iter().cloned());
+    names.join(" ")
+}
+
+/// Returns the best similarity of a pattern with the
+/// name or one of the aliases of an entity
+fn name_similarity(pattern: &str, e: &Entity, threshold: f64) -> f64 {
+    e.aliases
+        .iter()
+        .map(|a| similarity(pattern, a, threshold))
+        .fold(similarity(pattern, e.name(), threshold), f64::max)
+}
+
+fn handle_values(e: &Entity) -> String {
+    e.all_handles()
+        .into_iter()
+        .map(|h| h.value)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The custom fields values are searchable, the dates and
+/// numbers too, and so is the address
+fn field_values(e: &Entity) -> String {
+    let mut values = e
+        .fields
+        .values()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>();
+    if let Some(a) = &e.address {
+        values.push(a.to_string().replace(',', ""));
+    }
+    values.join(" ")
+}
+
+/// Returns the similarity of a pattern with a text: for each term of the
+/// pattern takes the best matching term of the text, terms below the
+/// threshold do not count
+fn similarity(pattern: &str, text: &str, threshold: f64) -> f64 {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+    let terms = pattern.split_whitespace().collect::<Vec<&str>>();
+    if terms.is_empty() {
+        return 0.0;
+    }
+    terms
+        .iter()
+        .map(|p| {
+            text.split_whitespace()
+                .map(|t| strsim::jaro_winkler(p, t))
+                .filter(|s| *s >= threshold)
+                .fold(0.0, f64::max)
+        })
+        .sum::<f64>()
+        / terms.len() as f64
+}
+
+/// the action keys sort by date, the priority code in the middle
+/// lets the agenda walk one priority at a time from the index alone
+fn action_key(e: &Entity) -> String {
+    format!("{}:{}:{}", e.next_action_date, e.priority as u8, e.uid())
+}
+fn action_priority(key: &[u8]) -> Option<u8> {
+    String::from_utf8_lossy(key).split(':').nth(1)?.parse().ok()
+}
+fn date_key(occurrence: &NaiveDate, e: &Entity, d: &ImportantDate) -> String {
+    format!("{}:{}:{}", occurrence, e.uid(), d.slug())
+}
+fn task_due_key(t: &Task) -> String {
+    format!("{}:{}", t.due, t.uid())
+}
+fn tag_key(t: &Tag, e: &Entity) -> String {
+    format!("{}:{}:{}", t.prefix(), t.slug(), e.uid())
+}
+/// The key of the time index, the timestamp has the sign bit flipped
+/// so that the keys sort in the same order as the dates
+fn event_time_key(evt: &Event) -> String {
+    time_key(evt.recorded_at.timestamp_millis(), &evt.uid())
+}
+/// The key linking an event to one of its actors, the most recent
+/// events of an actor come first
+fn entity_event_key(evt: &Event, actor: &model::Actor) -> String {
+    format!(
+        "{}:{}:{}:{}",
+        actor.uid(),
+        i64::MAX - evt.recorded_at.timestamp_millis(),
+        evt.uid(),
+        actor.actor_role().code()
+    )
+}
+/// Take an exclusive lock on a lock file and write the pid of the
+/// process in it, it fails with DataError::Locked when another
+/// handle holds the lock. The lock is released when the file is
+/// closed, also when the process dies, so a stale file is reused
+fn lock_file(lock: &Path) -> Result<File> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        // the pid of the process holding the lock is read below
+        .truncate(false)
+        .open(lock)?;
+    match file.try_lock_exclusive() {
+        Ok(()) => {}
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+            let pid = std::fs::read_to_string(lock)
+                .ok()
+                .and_then(|p| p.trim().parse::<u32>().ok())
+                .unwrap_or_default();
+            return Err(DataError::Locked(pid));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+    Ok(file)
+}
+/// Returns a batch that removes all the keys of a tree
+fn clear_batch(tree: &sled::Tree) -> Result<Batch> {
+    let mut batch = Batch::default();
+    for k in tree.iter().keys() {
+        batch.remove(k?);
+    }
+    Ok(batch)
+}
+fn time_key(ts: i64, uid: &str) -> String {
+    format!("{:020}:{}", (ts as u64) ^ (1 << 63), uid)
+}
+fn handle_key(p: &str, v: &str) -> String {
+    utils::hash(&utils::slugify(format!("{}:{}", p, v)))
+}
+fn sponsor_key(e: &model::Uuid, sponsor: &model::Uuid) -> String {
+    format!("{}:{}", utils::id(sponsor), utils::id(e))
+}
+fn edge_key(e: &Entity, r: &model::Rel) -> String {
+    format!("{}:{}", e.uid(), r.kind.get_label())
+}
+fn reverse_edge_key(e: &Entity, r: &model::Rel) -> String {
+    format!(
+        "{}:{}:{}",
+        utils::id(&r.target),
+        r.kind.get_label(),
+        e.uid()
+    )
+}
+fn typed_meta_key(namespace: &str, key: &str) -> Result<String> {
+    if namespace.is_empty() || namespace.contains(':') || key.is_empty() {
+        return Err(DataError::GenericError(format!(
+            "invalid metadata key {}:{}",
+            namespace, key
+        )));
+    }
+    Ok(format!("{}{}:{}", TYPED_META_PREFIX, namespace, key))
+}
+fn role_key(r: &AccessRole, uid: &str) -> String {
+    format!("role:{}:{}", r.code(), uid)
+}
+fn reset_key(uid: &str) -> String {
+    format!("reset:{}", uid)
+}
+fn session_key(token: &str) -> String {
+    format!("{}{}", SESSION_PREFIX, utils::hash(token))
+}
+fn audit_key(uid: &model::Uuid, millis: i64, seq: u64) -> String {
+    format!("{}:{:020}:{:020}", utils::id(uid), millis, seq)
+}
+
+/// The writes to store one or more entities and their
+/// indexes, grouped by tree to apply them at once
+#[derive(Default)]
+struct EntityBatch {
+    entities: Batch,
+    actions: Batch,
+    ids: Batch,
+    tags: Batch,
+    edges: Batch,
+    reverse_edges: Batch,
+    acl: Batch,
+    sponsorships: Batch,
+    audit: Batch,
+    dates: Batch,
+}
+
+impl EntityBatch {
+    /// Add an entity and its index entries to the batch
+    fn insert(&mut self, entity: &Entity) {
+        let k: &str = &entity.uid();
+        self.entities.insert(k, bincode::serialize(entity).unwrap());
+        // the archived entities have no next action nor dates
+        if !entity.is_archived() {
+            self.actions.insert(action_key(entity).as_str(), k);
+            let today = utils::today();
+            for d in entity.important_dates().iter() {
+                if let Some(o) = d.next_occurrence(&today) {
+                    self.dates.insert(date_key(&o, entity, d).as_str(), k);
                 }
-                // now check for conflicting ids
-                for (k, v) in entity.handles.iter() {
-                    println!("{:?}", k);
-                    if let Some(uid) = self.ids.get(&handle_key(k, v))? {
-                        if str(&uid) != entity.uid() {
-                            return Err(DataError::IDAlreadyTaken);
-                        }
-                    }
+            }
+        }
+        self.ids.insert(k, k);
+        for h in entity.all_handles().iter() {
+            self.ids.insert(handle_key(&h.label, &h.value).as_str(), k);
+        }
+        self.sponsorships
+            .insert(sponsor_key(&entity.uid, &entity.sponsor).as_str(), k);
+        for (_, t) in entity.tags.iter() {
+            self.tags.insert(tag_key(t, entity).as_str(), k);
+        }
+        for r in entity.relationships.iter() {
+            let v: &str = &utils::id(&r.target);
+            self.edges.insert(edge_key(entity, r).as_str(), v);
+            self.reverse_edges
+                .insert(reverse_edge_key(entity, r).as_str(), k);
+        }
+        for a in entity.visibility.iter() {
+            self.acl.insert(format!("{}:{}", a, k).as_str(), k);
+        }
+        if let Some(r) = entity.access_role() {
+            self.acl.insert(role_key(&r, k).as_str(), k);
+        }
+    }
+
+    /// Remove the index entries of the old version of an entity
+    /// that do not apply to the new one, the entries that are
+    /// inserted again after the removal are kept
+    fn unindex(&mut self, old: &Entity, entity: &Entity) {
+        let k: &str = &entity.uid();
+        // remove the existing role
+        if let Some(r) = old.access_role() {
+            self.acl.remove(role_key(&r, k).as_str());
+        }
+        // remove existing action dates if they have changed
+        if old.next_action_date != entity.next_action_date
+            || old.priority != entity.priority
+            || entity.is_archived()
+        {
+            self.actions.remove(action_key(old).as_str());
+        }
+        // remove the dates, the ones still there are inserted again
+        let today = utils::today();
+        for d in old.important_dates().iter() {
+            if let Some(o) = d.next_occurrence(&today) {
+                self.dates.remove(date_key(&o, old, d).as_str());
+            }
+        }
+        // remove existing sponsor
+        if old.sponsor != entity.sponsor {
+            self.sponsorships
+                .remove(sponsor_key(&entity.uid, &old.sponsor).as_str());
+        }
+        // remove existing tags if exists
+        for (tk, t) in old.tags.iter() {
+            if !entity.tags.contains_key(tk) {
+                self.tags.remove(tag_key(t, entity).as_str());
+            }
+        }
+        // remove existing ids
+        for h in old.all_handles().iter() {
+            if !entity.has_handle(&h.label, &h.value) {
+                self.ids.remove(handle_key(&h.label, &h.value).as_str());
+            }
+        }
+        // remove the relationships that are gone
+        for r in old.relationships.iter() {
+            let rk = reverse_edge_key(entity, r);
+            if entity
+                .relationships
+                .iter()
+                .all(|n| reverse_edge_key(entity, n) != rk)
+            {
+                self.reverse_edges.remove(rk.as_str());
+                if entity
+                    .relationships
+                    .iter()
+                    .all(|n| n.kind.get_label() != r.kind.get_label())
+                {
+                    self.edges.remove(edge_key(entity, r).as_str());
                 }
-                self.insert(entity)
             }
-            None => Err(DataError::NotFound),
         }
     }
+}
+/// Read and verify the entities of an export, for a full
+/// export the events and the system entries are ignored
+fn read_entities<S: ImportSource + ?Sized>(
+    source: &S,
+    format: ExportFormat,
+    key: Option<&ExportKey>,
+) -> Result<Vec<(usize, Entity)>> {
+    let (records, corrupted) = match format {
+        ExportFormat::Json => {
+            let check = formats::read_export(source, key)?;
+            (check.records, check.corrupted)
+        }
+        ExportFormat::FullJson => {
+            let check = formats::read_records::<FullRecord, _>(source, key)?;
+            let entities = check
+                .records
+                .into_iter()
+                .filter_map(|(n, r)| match r {
+                    FullRecord::Entity(e) => Some((n, e)),
+                    _ => None,
+                })
+                .collect();
+            (entities, check.corrupted)
+        }
+        _ => return Err(DataError::NotImplemented),
+    };
+    match corrupted.is_empty() {
+        true => Ok(records),
+        false => Err(DataError::CorruptedData(corrupted)),
+    }
+}
+fn inbox_tag() -> Tag {
+    Tag::System("inbox".to_owned())
+}
+fn str(v: &sled::IVec) -> String {
+    String::from_utf8_lossy(v).to_string()
+}
+
+/// A simple datastore that can persist data on file
+///
+pub struct DataStore {
+    db: sled::Db,
+    entities: sled::Tree,
+    actions: sled::Tree,
+    ids: sled::Tree,
+    tags: sled::Tree,
+    edges: sled::Tree,
+    reverse_edges: sled::Tree,
+    acl: sled::Tree,
+    system: sled::Tree,
+    events: sled::Tree,
+    entity_event: sled::Tree,
+    events_time: sled::Tree,
+    sponsorships: sled::Tree,
+    audit: sled::Tree,
+    dates: sled::Tree,
+    attachments: sled::Tree,
+    // tasks
+    tasks: sled::Tree,
+    tasks_due: sled::Tree,
+    goals: sled::Tree,
+    // search index
+    index: SearchIndex,
+    // change notifications
+    subscribers: Vec<Sender<ChangeEvent>>,
+    // the entity the recorder is acting for
+    delegate: Option<model::Uuid>,
+    // the entity the permissions are checked for
+    principal: Option<model::Uuid>,
+    // the session the principal comes from, if any
+    session: Option<String>,
+    // the file a sealed datastore is written back to, with its passphrase
+    sealed: Option<(PathBuf, String)>,
+    // the file holding the pid of the process using the datastore,
+    // with the handle holding the lock on it
+    lock: Option<(PathBuf, File)>,
+}
+
+impl Drop for DataStore {
+    fn drop(&mut self) {
+        // a failure cannot be reported here, see close
+        let _ = self.write_back();
+        if let Some((lock, _)) = &self.lock {
+            let _ = std::fs::remove_file(lock);
+        }
+    }
+}
+
+impl DataStore {
+    /// Initialize an empty datastore
+    ///
+    /// it fails with DataError::Locked when the datastore
+    /// is already open in another process
+    pub fn open(db_path: &Path) -> Result<DataStore> {
+        // sled reports its own lock as a generic io error, so the
+        // datastore holds a lock on the lock file to tell
+        std::fs::create_dir_all(db_path)?;
+        let lock = db_path.join(LOCK_FILE);
+        let file = lock_file(&lock)?;
+        let mut retries = 0;
+        let db = loop {
+            match sled::open(db_path) {
+                Ok(db) => break db,
+                // the threads of a handle just dropped may still hold the sled lock
+                Err(sled::Error::Io(e))
+                    if e.kind() == std::io::ErrorKind::Other && retries < SLED_LOCK_RETRIES =>
+                {
+                    retries += 1;
+                    thread::sleep(LOCK_RETRY_INTERVAL);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        DataStore::with_db(db, Some((lock, file)))
+    }
+
+    /// Initialize an empty datastore, when it is open in another
+    /// process retry until it is released or the timeout expires
+    pub fn open_wait(db_path: &Path, timeout: std::time::Duration) -> Result<DataStore> {
+        DataStore::retry_locked(timeout, || DataStore::open(db_path))
+    }
+
+    /// Open a datastore sealed with seal, see open_wait
+    pub fn open_sealed_wait(
+        path: &Path,
+        passphrase: &str,
+        timeout: std::time::Duration,
+    ) -> Result<DataStore> {
+        DataStore::retry_locked(timeout, || DataStore::open_sealed(path, passphrase))
+    }
+
+    /// Retry to open a datastore while it is open in
+    /// another process, until the timeout expires
+    fn retry_locked<F>(timeout: std::time::Duration, mut open: F) -> Result<DataStore>
+    where
+        F: FnMut() -> Result<DataStore>,
+    {
+        let start = std::time::Instant::now();
+        loop {
+            match open() {
+                Err(DataError::Locked(_)) if start.elapsed() < timeout => {
+                    thread::sleep(LOCK_RETRY_INTERVAL)
+                }
+                r => return r,
+            }
+        }
+    }
+
+    /// Open a datastore sealed with a passphrase, see seal
+    ///
+    /// The data is decrypted in a datastore kept in memory, see
+    /// open_in_memory, and sealed back on close, so nothing is written
+    /// to disk in clear. If the process dies before closing the
+    /// datastore the changes are lost and the clear copy stays in
+    /// /dev/shm until the next reboot.
+    /// It fails with DataError::Locked when the datastore is
+    /// already open in another process
+    pub fn open_sealed(path: &Path, passphrase: &str) -> Result<DataStore> {
+        // without sled the lock file is the only guard
+        let lock = path.with_extension("lock");
+        let file = lock_file(&lock)?;
+        let open = || -> Result<DataStore> {
+            let trees = unseal_snapshot(&std::fs::read(path)?, passphrase)?;
+            let mut ds = DataStore::open_in_memory()?;
+            ds.load(trees)?;
+            Ok(ds)
+        };
+        match open() {
+            Ok(mut ds) => {
+                ds.lock = Some((lock, file));
+                ds.sealed = Some((path.to_path_buf(), passphrase.to_owned()));
+                Ok(ds)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&lock);
+                Err(e)
+            }
+        }
+    }
+
+    /// Tells if a sealed datastore is open in a process, the
+    /// lock file left behind by a process that died is removed
+    pub fn sealed_in_use(path: &Path) -> bool {
+        let lock = path.with_extension("lock");
+        if !lock.exists() {
+            return false;
+        }
+        match lock_file(&lock) {
+            Ok(_) => {
+                let _ = std::fs::remove_file(&lock);
+                false
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Write the whole datastore encrypted with a passphrase
+    /// to a file, that can be opened with open_sealed.
+    /// Sealing a datastore requires the admin role
+    pub fn seal(&self, path: &Path, passphrase: &str) -> Result<()> {
+        self.authorize(AccessRole::Admin)?;
+        self.write_sealed(path, passphrase)
+    }
+
+    /// Write the encrypted copy of the datastore, see seal
+    fn write_sealed(&self, path: &Path, passphrase: &str) -> Result<()> {
+        let data = seal_snapshot(&self.dump()?, passphrase)?;
+        // replace the file at once, not to lose it on a failure
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Tells if the datastore has been opened with open_sealed
+    pub fn is_sealed(&self) -> bool {
+        self.sealed.is_some()
+    }
+
+    /// Seal a datastore opened with open_sealed back to its file
+    fn write_back(&self) -> Result<()> {
+        match &self.sealed {
+            Some((path, passphrase)) => self.write_sealed(path, passphrase),
+            None => Ok(()),
+        }
+    }
+
+    /// Initialize an empty datastore that is gone when it is dropped
+    ///
+    /// sled has no in-memory mode, the data is written to temporary
+    /// files deleted on drop: on linux they are in /dev/shm, that is
+    /// kept in memory, elsewhere they are in the temporary directory
+    pub fn open_temporary() -> Result<DataStore> {
+        DataStore::with_db(sled::Config::new().temporary(true).open()?, None)
+    }
+
+    /// Initialize an empty temporary datastore for the data that must
+    /// not be written to disk in clear, see open_temporary. It fails
+    /// where the temporary files would not be kept in memory
+    pub fn open_in_memory() -> Result<DataStore> {
+        if !cfg!(target_os = "linux") || !Path::new("/dev/shm").is_dir() {
+            return Err(DataError::GenericError(
+                "the sealed datastores need /dev/shm to stay in memory".to_string(),
+            ));
+        }
+        DataStore::open_temporary()
+    }
+
+    fn with_db(db: sled::Db, lock: Option<(PathBuf, File)>) -> Result<DataStore> {
+        let entities = db.open_tree(TABLE_ENTITIES)?;
+        let actions = db.open_tree(TABLE_ACTIONS)?;
+        let ids = db.open_tree(TABLE_IDS)?;
+        let tags = db.open_tree(TABLE_TAGS)?;
+        let edges = db.open_tree(TABLE_EDGES)?;
+        let reverse_edges = db.open_tree(TABLE_REVERSE_EDGES)?;
+        let acl = db.open_tree(TABLE_ACL)?;
+        let system = db.open_tree(TABLE_SYSTEM)?;
+        let sponsorships = db.open_tree(TABLE_SPONSORSHIPS)?;
+        // events
+        let events = db.open_tree(TABLE_EVENTS)?;
+        let entity_event = db.open_tree(TABLE_ENTITY_EVENT)?;
+        let events_time = db.open_tree(TABLE_EVENTS_TIME)?;
+        let audit = db.open_tree(TABLE_AUDIT)?;
+        let dates = db.open_tree(TABLE_DATES)?;
+        let attachments = db.open_tree(TABLE_ATTACHMENTS)?;
+        let tasks = db.open_tree(TABLE_TASKS)?;
+        let tasks_due = db.open_tree(TABLE_TASKS_DUE)?;
+        let goals = db.open_tree(TABLE_GOALS)?;
+        // search index, configured later on
+        let index = SearchIndex::new(SearchConfig::default());
+        // generate salt for passwords
+        let salt: String = (0..64).map(|_| random::<char>()).collect();
+        let salt_hash: &str = &utils::hash(&salt);
+        system.insert("password:salt", salt_hash)?;
+        // datastore
+        let mut ds = DataStore {
+            db,
+            entities,
+            actions,
+            ids,
+            tags,
+            edges,
+            reverse_edges,
+            acl,
+            system,
+            events,
+            entity_event,
+            events_time,
+            sponsorships,
+            audit,
+            dates,
+            attachments,
+            tasks,
+            tasks_due,
+            goals,
+            index,
+            subscribers: Vec::new(),
+            delegate: None,
+            principal: None,
+            session: None,
+            sealed: None,
+            lock,
+        };
+        // the datastores written in an older format are migrated first
+        ds.migrate()?;
+        // the datastores created before the reverse index need it
+        ds.build_reverse_edges()?;
+        ds.build_dates_index()?;
+        ds.build_events_time_index()?;
+        // build the search index
+        ds.build_search_index();
+        // complete
+        Ok(ds)
+    }
+
+    /// Bring the stored records to the current schema version
+    ///
+    /// The records of a datastore in an older version, or without one,
+    /// are rewritten in the current format together with the version,
+    /// at once, then the indexes are rebuilt from them. An interrupted
+    /// rebuild starts over on the next open
+    fn migrate(&mut self) -> Result<()> {
+        let version = match self.get_meta(META_SCHEMA_VERSION) {
+            Some(v) => match v.parse::<u32>() {
+                Ok(v) if v <= SCHEMA_VERSION => Some(v),
+                _ => {
+                    return Err(DataError::GenericError(format!(
+                        "the datastore schema version {} is not supported",
+                        v
+                    )))
+                }
+            },
+            None => None,
+        };
+        match version {
+            Some(SCHEMA_VERSION) => {}
+            // the records are the same, only the action keys changed
+            Some(2) => {
+                self.set_meta(META_SCHEMA_REINDEX, &utils::today().to_string())?;
+                self.set_meta(META_SCHEMA_VERSION, &SCHEMA_VERSION.to_string())?;
+            }
+            None if self.entities.is_empty() && self.events.is_empty() => {
+                self.set_meta(META_SCHEMA_VERSION, &SCHEMA_VERSION.to_string())?;
+            }
+            _ => self.migrate_legacy()?,
+        }
+        if self.get_meta(META_SCHEMA_REINDEX).is_some() {
+            self.reindex()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the entities, events and audit entries stored in an
+    /// older schema version in the current format, see model::legacy
+    fn migrate_legacy(&mut self) -> Result<()> {
+        let invalid = |what: &str, k: &sled::IVec, e: bincode::Error| {
+            DataError::GenericError(format!("cannot migrate {} {}: {}", what, str(k), e))
+        };
+        let mut entities = Batch::default();
+        for r in self.entities.iter() {
+            let (k, raw) = r?;
+            let e = legacy::entity(&raw).map_err(|e| invalid("entity", &k, e))?;
+            entities.insert(k, bincode::serialize(&e).unwrap());
+        }
+        let mut events = Batch::default();
+        for r in self.events.iter() {
+            let (k, raw) = r?;
+            let evt = legacy::event(&raw).map_err(|e| invalid("event", &k, e))?;
+            events.insert(k, bincode::serialize(&evt).unwrap());
+        }
+        let mut audit = Batch::default();
+        for r in self.audit.iter() {
+            let (k, raw) = r?;
+            let a = legacy::audit_entry(&raw).map_err(|e| invalid("audit entry", &k, e))?;
+            audit.insert(k, bincode::serialize(&a).unwrap());
+        }
+        let version = format!("meta:{}", META_SCHEMA_VERSION);
+        let reindex = format!("meta:{}", META_SCHEMA_REINDEX);
+        let r: TransactionResult<(), DataError> =
+            (&self.entities, &self.events, &self.audit, &self.system).transaction(
+                |(te, tev, tau, ts)| {
+                    te.apply_batch(&entities)?;
+                    tev.apply_batch(&events)?;
+                    tau.apply_batch(&audit)?;
+                    ts.insert(version.as_str(), SCHEMA_VERSION.to_string().as_str())?;
+                    ts.insert(reindex.as_str(), utils::today().to_string().as_str())?;
+                    Ok(())
+                },
+            );
+        match r {
+            Ok(()) => Ok(()),
+            Err(_) => Err(DataError::TxError),
+        }
+    }
+
+    /// Rebuild the indexes of the entities and the events from scratch,
+    /// the entities and the events themselves are left as they are.
+    /// The index entries in an older layout are dropped, eg. the entity
+    /// events keyed without the role that the Role filter cannot match
+    fn reindex(&mut self) -> Result<()> {
+        let mut batch = EntityBatch::default();
+        for e in self.iter_entities() {
+            batch.insert(&e?);
+        }
+        let mut events = Vec::new();
+        for r in self.events.iter() {
+            let (_, raw) = r?;
+            events.push(bincode::deserialize::<Event>(&raw).unwrap());
+        }
+        self.actions.clear()?;
+        self.ids.clear()?;
+        self.tags.clear()?;
+        self.edges.clear()?;
+        self.reverse_edges.clear()?;
+        self.acl.clear()?;
+        self.sponsorships.clear()?;
+        self.dates.clear()?;
+        self.entity_event.clear()?;
+        self.events_time.clear()?;
+        self.write(&batch)?;
+        for evt in events.iter() {
+            self.store_event(evt, false)?;
+        }
+        self.system
+            .remove(format!("meta:{}", META_SCHEMA_REINDEX))?;
+        Ok(())
+    }
+
+    /// Build the reverse relationship index when it is missing
+    fn build_reverse_edges(&mut self) -> Result<()> {
+        if !self.reverse_edges.is_empty() || self.edges.is_empty() {
+            return Ok(());
+        }
+        for r in self.entities.iter() {
+            let (_, raw) = r?;
+            let e: Entity = bincode::deserialize(&raw).unwrap();
+            for rel in e.relationships.iter() {
+                self.reverse_edges
+                    .insert(reverse_edge_key(&e, rel), e.uid().as_str())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the important dates index when it is missing,
+    /// the entities with a birthday predate it
+    fn build_dates_index(&mut self) -> Result<()> {
+        if !self.dates.is_empty() {
+            return Ok(());
+        }
+        let mut batch = EntityBatch::default();
+        for e in self.iter_entities() {
+            batch.insert(&e?);
+        }
+        self.dates.apply_batch(batch.dates)?;
+        Ok(())
+    }
+
+    /// Build the time index of the events when it is missing,
+    /// the events recorded before it was introduced need it
+    fn build_events_time_index(&mut self) -> Result<()> {
+        if !self.events_time.is_empty() || self.events.is_empty() {
+            return Ok(());
+        }
+        let mut batch = Batch::default();
+        for r in self.events.iter() {
+            let (k, raw) = r?;
+            let evt: Event = bincode::deserialize(&raw).unwrap();
+            batch.insert(event_time_key(&evt).as_str(), k);
+        }
+        self.events_time.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// Subscribe to the changes of the datastore
+    ///
+    /// The receiver gets a notification for every entity added or
+    /// updated and for every event recorded, the subscription ends
+    /// when the receiver is dropped
+    pub fn subscribe(&mut self) -> Receiver<ChangeEvent> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Returns a checksum of the recorded events, it changes when
+    /// an event is recorded, updated or removed
+    pub fn events_checksum(&self) -> Result<u32> {
+        Ok(self.events.checksum()?)
+    }
+
+    /// Watch the changes of the datastore as they are written
+    ///
+    /// Unlike subscribe, the changes are read from the trees so they
+    /// include the writes of every handle of the same database. The
+    /// entity changes come from the audit trail and the events from
+    /// the events tree, the order between the two is not guaranteed.
+    ///
+    /// The iterator blocks waiting for the next change and ends
+    /// when the database is closed
+    pub fn watch(&self, filter: ChangeFilter) -> impl Iterator<Item = ChangeEvent> {
+        let (tx, rx) = channel();
+        if filter != ChangeFilter::Events {
+            // the audit keys start with the entity uid
+            let prefix = match &filter {
+                ChangeFilter::Entity(uid) => format!("{}:", utils::id(uid)),
+                _ => String::new(),
+            };
+            let changes = self.audit.watch_prefix(prefix);
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for e in changes {
+                    let entry: AuditEntry = match e {
+                        sled::Event::Insert { value, .. } => match bincode::deserialize(&value) {
+                            Ok(a) => a,
+                            Err(_) => continue,
+                        },
+                        sled::Event::Remove { .. } => continue,
+                    };
+                    let change = match entry.action {
+                        AuditAction::Added => ChangeEvent::EntityAdded(entry.entity),
+                        AuditAction::Updated => ChangeEvent::EntityUpdated(entry.entity),
+                        AuditAction::Removed => ChangeEvent::EntityRemoved(entry.entity),
+                    };
+                    if tx.send(change).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        if filter != ChangeFilter::Entities {
+            let changes = self.events.watch_prefix(vec![]);
+            thread::spawn(move || {
+                for e in changes {
+                    let evt: Event = match e {
+                        sled::Event::Insert { value, .. } => match bincode::deserialize(&value) {
+                            Ok(evt) => evt,
+                            Err(_) => continue,
+                        },
+                        sled::Event::Remove { .. } => continue,
+                    };
+                    if let ChangeFilter::Entity(uid) = &filter {
+                        if !evt.actors.iter().any(|a| a.uid() == utils::id(uid)) {
+                            continue;
+                        }
+                    }
+                    if tx.send(ChangeEvent::EventRecorded(evt.uid)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        rx.into_iter()
+    }
+
+    /// Act on behalf of another entity
+    ///
+    /// From now on the events recorded, the logs of the entity
+    /// changes included, are also recorded as done on behalf of the
+    /// delegate, use None to go back acting for yourself.
+    /// Acting for someone else requires the editor role
+    pub fn act_as(&mut self, delegate: Option<&Entity>) -> Result<()> {
+        if delegate.is_some() {
+            self.authorize(AccessRole::Editor)?;
+        }
+        self.delegate = match delegate {
+            Some(d) => match self.entities.contains_key(d.uid())? {
+                true => Some(d.uid),
+                false => return Err(DataError::NotFound),
+            },
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Returns the entity the events are recorded for, if any
+    pub fn delegate(&self) -> Option<model::Uuid> {
+        self.delegate
+    }
+
+    /// Set the principal whose role is enforced on the writes
+    ///
+    /// Without a principal the permissions are not checked,
+    /// that is the case of a personal context. A disabled
+    /// user cannot be the principal
+    pub fn set_principal(&mut self, principal: Option<&Entity>) -> Result<()> {
+        self.session = None;
+        self.principal = match principal {
+            Some(p) => match self.get_by_uid(&p.uid())? {
+                Some(e) if e.is_disabled() => return Err(DataError::PermissionDenied),
+                Some(e) => Some(e.uid),
+                None => return Err(DataError::NotFound),
+            },
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Returns the access role of an entity as stored in the
+    /// datastore, the entities without a role are viewers
+    pub fn role_of(&self, entity: &Entity) -> AccessRole {
+        self.get_by_uid(&entity.uid())
+            .ok()
+            .flatten()
+            .and_then(|e| e.access_role())
+            .unwrap_or(AccessRole::Viewer)
+    }
+
+    /// Returns the entities that have been granted a role
+    pub fn members(&self, role: AccessRole) -> Vec<Entity> {
+        self.acl
+            .scan_prefix(format!("role:{}:", role.code()))
+            .values()
+            .filter_map(|v| self.get_by_uid(&str(&v.ok()?)).ok()?)
+            .collect()
+    }
+
+    /// Checks that the principal has at least the required role,
+    /// the users disabled after being set as principal have none
+    fn authorize(&self, required: AccessRole) -> Result<()> {
+        // a principal coming from a session lasts as long as the session
+        if let Some(token) = &self.session {
+            if self.session_user(token) != self.principal {
+                return Err(DataError::InvalidToken);
+            }
+        }
+        let role = match self.principal {
+            Some(p) => self
+                .get_by_uid(&utils::id(&p))?
+                .filter(|e| !e.is_disabled())
+                .and_then(|e| e.access_role()),
+            None => return Ok(()),
+        };
+        match role.unwrap_or(AccessRole::Viewer) >= required {
+            true => Ok(()),
+            false => Err(DataError::PermissionDenied),
+        }
+    }
+
+    /// Checks that the principal can change a role from old to new
+    ///
+    /// changing roles requires to be admin and only an
+    /// owner can grant or revoke the owner role
+    fn authorize_role_change(
+        &self,
+        old: Option<AccessRole>,
+        new: Option<AccessRole>,
+    ) -> Result<()> {
+        if old == new {
+            return Ok(());
+        }
+        match old.max(new) {
+            Some(AccessRole::Owner) => self.authorize(AccessRole::Owner),
+            _ => self.authorize(AccessRole::Admin),
+        }
+    }
+
+    /// Checks that the principal can set the password of a user,
+    /// that is the user itself or a principal with a higher role
+    fn authorize_password_change(&self, user: &Entity) -> Result<()> {
+        let role = match user.access_role() {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let principal = match self.principal {
+            Some(p) if p != user.uid => p,
+            _ => return Ok(()),
+        };
+        let principal_role = self
+            .get_by_uid(&utils::id(&principal))?
+            .filter(|e| !e.is_disabled())
+            .and_then(|e| e.access_role());
+        match principal_role {
+            Some(r) if r > role => Ok(()),
+            _ => Err(DataError::PermissionDenied),
+        }
+    }
+
+    /// Grant a role to an entity, None revokes it
+    pub fn grant(&mut self, entity: &Entity, role: Option<AccessRole>) -> Result<model::Uuid> {
+        let mut target = match self.get_by_uid(&entity.uid())? {
+            Some(t) => t,
+            None => return Err(DataError::NotFound),
+        };
+        target.set_access_role(role);
+        let uid = self.update(&target)?;
+        let msg = match role {
+            Some(r) => format!("granted {}", r),
+            None => "revoked".to_string(),
+        };
+        self.record(&Event::log("role", &target, Some(msg)))?;
+        Ok(uid)
+    }
+
+    /// Make an entity a user with a role and a password, the
+    /// entity is added if it is not in the datastore yet
+    ///
+    /// it requires the same permissions as granting the role, the
+    /// password of an existing user is set only by the user itself
+    /// or by a principal with a higher role
+    pub fn add_user(&mut self, user: &Entity, role: AccessRole, pwd: &str) -> Result<model::Uuid> {
+        self.authorize(AccessRole::Admin)?;
+        let current = self.get_by_uid(&user.uid())?;
+        let mut target = current.clone().unwrap_or_else(|| user.clone());
+        self.authorize_role_change(current.as_ref().and_then(|c| c.access_role()), Some(role))?;
+        target.set_access_role(Some(role));
+        target.set_disabled(false);
+        let target = target.with_password(Some(&pwd.to_owned()));
+        let uid = match current {
+            Some(c) => {
+                // enabling a user is like granting its role back
+                if c.is_disabled() {
+                    self.authorize_role_change(c.access_role(), None)?;
+                }
+                self.authorize_password_change(&c)?;
+                let uid = self.replace(Some(&c), &target)?;
+                self.notify(ChangeEvent::EntityUpdated(uid));
+                uid
+            }
+            None => self.add(&target)?,
+        };
+        let msg = format!("user with role {}", role);
+        self.record(&Event::log("user", &target, Some(msg)))?;
+        Ok(uid)
+    }
+
+    /// Returns the users, that is the entities that have been
+    /// granted a role, the highest roles first
+    pub fn users(&self) -> Vec<Entity> {
+        AccessRole::all()
+            .iter()
+            .flat_map(|r| self.members(*r))
+            .collect()
+    }
+
+    /// Disable a user or enable it back, a disabled user keeps
+    /// its role but cannot act with it nor be the principal.
+    ///
+    /// it requires the same permissions as revoking the role
+    /// of the user and the principal cannot disable itself
+    pub fn set_user_disabled(&mut self, user: &Entity, disabled: bool) -> Result<Entity> {
+        let mut target = match self.get_by_uid(&user.uid())? {
+            Some(t) => t,
+            None => return Err(DataError::NotFound),
+        };
+        if target.access_role().is_none() {
+            return Err(DataError::GenericError(format!(
+                "{} is not a user",
+                target.name()
+            )));
+        }
+        if disabled && self.principal == Some(target.uid) {
+            return Err(DataError::GenericError(
+                "the current user cannot be disabled".to_string(),
+            ));
+        }
+        if target.is_disabled() == disabled {
+            return Ok(target);
+        }
+        target.set_disabled(disabled);
+        self.update(&target)?;
+        if disabled {
+            self.revoke_sessions(&target)?;
+        }
+        let msg = if disabled { "disabled" } else { "enabled" };
+        self.record(&Event::log("user", &target, Some(msg.to_string())))?;
+        Ok(target)
+    }
+
+    /// Issue a one-time token to reset the password of an entity
+    ///
+    /// Only the sponsor of the entity or an owner of the context can
+    /// issue it, the issuer is the principal. The token is valid for a
+    /// week and replaces any token issued before. The datastore keeps
+    /// only the token hash
+    pub fn issue_reset_token(&mut self, target: &Entity) -> Result<String> {
+        let target = match self.get_by_uid(&target.uid())? {
+            Some(t) => t,
+            None => return Err(DataError::NotFound),
+        };
+        let required = match self.principal {
+            Some(p) if p == target.sponsor => AccessRole::Viewer,
+            _ => AccessRole::Owner,
+        };
+        self.authorize(required)?;
+        let token = utils::random_token(12);
+        let expires = utils::now_local() + chrono::Duration::days(RESET_TOKEN_DAYS);
+        let v = format!("{}\t{}", utils::hash(&token), expires.timestamp());
+        self.system.insert(reset_key(&target.uid()), v.as_bytes())?;
+        // keep track of who issued it
+        let mut evt = Event::log("password reset issued", &target, None);
+        if let Some(issuer) = self.principal {
+            evt.actors.push(model::Actor::RecordedBy(issuer));
+        }
+        self.append(&evt)?;
+        Ok(token)
+    }
+
+    /// Tells if there is a valid password reset token for an entity
+    pub fn reset_pending(&self, target: &Entity) -> bool {
+        self.reset_token(target).is_some()
+    }
+
+    /// Returns the hash of the valid reset token of an entity, if any
+    fn reset_token(&self, target: &Entity) -> Option<String> {
+        let v = self.system.get(reset_key(&target.uid())).ok()??;
+        let v = str(&v);
+        let (h, expires) = utils::split_once(&v, '\t')?;
+        match expires.parse::<i64>().ok()? > utils::now_local().timestamp() {
+            true => Some(h.to_owned()),
+            false => None,
+        }
+    }
+
+    /// Set a new password using a reset token, the token
+    /// is consumed and the updated entity is returned
+    pub fn reset_password(&mut self, target: &Entity, token: &str, pwd: &str) -> Result<Entity> {
+        match self.reset_token(target) {
+            Some(h) if h == utils::hash(token) => {}
+            _ => return Err(DataError::InvalidToken),
+        }
+        let mut target = match self.get_by_uid(&target.uid())? {
+            Some(t) => t,
+            None => return Err(DataError::NotFound),
+        };
+        self.system.remove(reset_key(&target.uid()))?;
+        self.revoke_sessions(&target)?;
+        target.pass = Some(utils::hash(pwd));
+        self.insert(&target)?;
+        self.append(&Event::log("password reset", &target, None))?;
+        Ok(target)
+    }
+
+    /// Change the password of the principal, the old password is
+    /// verified again (empty when there is none) and a pending reset
+    /// token is dropped. The updated entity is returned, the cached
+    /// credentials of the user are no longer valid
+    pub fn change_password(&mut self, principal: &Entity, old: &str, new: &str) -> Result<Entity> {
+        if self.principal.map_or(false, |p| p != principal.uid) {
+            return Err(DataError::PermissionDenied);
+        }
+        let current = match self.get_by_uid(&principal.uid())? {
+            Some(t) => t,
+            None => return Err(DataError::NotFound),
+        };
+        let old = match old.is_empty() {
+            true => None,
+            false => Some(utils::hash(old)),
+        };
+        if current.authorized(old.as_ref()).is_err() {
+            return Err(DataError::PermissionDenied);
+        }
+        if new.is_empty() {
+            return Err(DataError::GenericError(
+                "the password cannot be empty".to_string(),
+            ));
+        }
+        let target = current.clone().with_password(Some(&new.to_owned()));
+        self.system.remove(reset_key(&target.uid()))?;
+        self.revoke_sessions(&target)?;
+        self.replace(Some(&current), &target)?;
+        self.append(&Event::log("password changed", &target, None))?;
+        Ok(target)
+    }
+
+    /// Open a session for a user verifying its password, the
+    /// returned token stands for the user until it expires or the
+    /// user logs out, see resume. The datastore keeps only the token hash
+    pub fn login(&mut self, uid: &str, pwd: &str) -> Result<SessionToken> {
+        let user = match self.get_by_uid(uid)? {
+            Some(u) => u,
+            None => return Err(DataError::NotFound),
+        };
+        if user.is_disabled() || user.authorized(Some(&utils::hash(pwd))).is_err() {
+            return Err(DataError::PermissionDenied);
+        }
+        self.prune_sessions()?;
+        let session = SessionToken {
+            token: utils::random_token(32),
+            user: user.uid,
+            expires: utils::now_local() + chrono::Duration::hours(SESSION_HOURS),
+        };
+        let v = format!("{}\t{}", user.uid(), session.expires.timestamp());
+        self.system
+            .insert(session_key(&session.token), v.as_bytes())?;
+        Ok(session)
+    }
+
+    /// Set the principal from a session token, the token is checked
+    /// again on every operation that requires a role
+    pub fn resume(&mut self, token: &str) -> Result<Entity> {
+        let user = self
+            .session_user(token)
+            .and_then(|u| self.get_by_uid(&utils::id(&u)).ok()?)
+            .ok_or(DataError::InvalidToken)?;
+        self.set_principal(Some(&user))?;
+        self.session = Some(token.to_owned());
+        Ok(user)
+    }
+
+    /// Close a session, the principal it set is cleared
+    pub fn logout(&mut self, token: &str) -> Result<()> {
+        self.system.remove(session_key(token))?;
+        if self.session.as_deref() == Some(token) {
+            self.session = None;
+            self.principal = None;
+        }
+        Ok(())
+    }
+
+    /// Returns the user of a valid session token, if any
+    fn session_user(&self, token: &str) -> Option<model::Uuid> {
+        let v = self.system.get(session_key(token)).ok()??;
+        let v = str(&v);
+        let (uid, expires) = utils::split_once(&v, '\t')?;
+        match expires.parse::<i64>().ok()? > utils::now_local().timestamp() {
+            true => model::Uuid::parse_str(uid).ok(),
+            false => None,
+        }
+    }
+
+    /// Close all the sessions of a user, or the expired ones
+    /// only when no user is given, returns the sessions closed
+    fn close_sessions(&mut self, user: Option<&model::Uuid>) -> Result<usize> {
+        let now = utils::now_local().timestamp();
+        let mut batch = Batch::default();
+        let mut closed = 0;
+        for r in self.system.scan_prefix(SESSION_PREFIX) {
+            let (k, v) = r?;
+            let v = str(&v);
+            let (uid, expires) = utils::split_once(&v, '\t').unwrap_or(("", "0"));
+            let close = match user {
+                Some(u) => uid == utils::id(u),
+                None => expires.parse::<i64>().unwrap_or(0) <= now,
+            };
+            if close {
+                batch.remove(k);
+                closed += 1;
+            }
+        }
+        self.system.apply_batch(batch)?;
+        Ok(closed)
+    }
+
+    /// Remove the expired sessions
+    pub fn prune_sessions(&mut self) -> Result<usize> {
+        self.close_sessions(None)
+    }
+
+    /// Close all the sessions of a user, eg. after a password change
+    pub fn revoke_sessions(&mut self, user: &Entity) -> Result<usize> {
+        self.close_sessions(Some(&user.uid))
+    }
+
+    /// Send a change notification to the subscribers,
+    /// dropping the ones that are gone
+    fn notify(&mut self, change: ChangeEvent) {
+        self.subscribers.retain(|s| s.send(change.clone()).is_ok());
+    }
+
+    fn build_search_index(&mut self) {
+        self.index = SearchIndex::new(self.search_config());
+        self.entities.iter().for_each(|r| {
+            let (_, raw) = r.unwrap();
+            let e: Entity = bincode::deserialize(&raw).unwrap();
+            self.index.insert(&e);
+        });
+    }
+
+    /// Returns the search configuration of the datastore
+    pub fn search_config(&self) -> SearchConfig {
+        let d = SearchConfig::default();
+        let get = |k: &str, default: f64| match self.get_meta(k) {
+            Some(v) => v.parse::<f64>().unwrap_or(default),
+            None => default,
+        };
+        SearchConfig {
+            threshold: get(META_SEARCH_THRESHOLD, d.threshold),
+            name_weight: get(META_SEARCH_NAME_WEIGHT, d.name_weight),
+            tag_weight: get(META_SEARCH_TAG_WEIGHT, d.tag_weight),
+            handle_weight: get(META_SEARCH_HANDLE_WEIGHT, d.handle_weight),
+            field_weight: get(META_SEARCH_FIELD_WEIGHT, d.field_weight),
+        }
+    }
+
+    /// Store the search configuration and rebuild the search index
+    pub fn set_search_config(&mut self, cfg: &SearchConfig) -> Result<()> {
+        self.authorize(AccessRole::Admin)?;
+        if cfg.threshold < 0.0 || cfg.threshold > 1.0 {
+            return Err(DataError::GenericError(
+                "search threshold must be between 0 and 1".to_string(),
+            ));
+        }
+        self.set_meta(META_SEARCH_THRESHOLD, &cfg.threshold.to_string())?;
+        self.set_meta(META_SEARCH_NAME_WEIGHT, &cfg.name_weight.to_string())?;
+        self.set_meta(META_SEARCH_TAG_WEIGHT, &cfg.tag_weight.to_string())?;
+        self.set_meta(META_SEARCH_HANDLE_WEIGHT, &cfg.handle_weight.to_string())?;
+        self.set_meta(META_SEARCH_FIELD_WEIGHT, &cfg.field_weight.to_string())?;
+        self.build_search_index();
+        Ok(())
+    }
+
+    /// Returns the buckets of the agenda overview for the context
+    pub fn agenda_buckets(&self) -> Vec<AgendaBucket> {
+        self.get_meta(META_AGENDA_BUCKETS)
+            .and_then(|v| AgendaBucket::parse_list(&v).ok())
+            .unwrap_or_else(AgendaBucket::defaults)
+    }
+
+    /// Store the buckets of the agenda overview, an empty
+    /// list restores the default ones
+    pub fn set_agenda_buckets(&mut self, buckets: &[AgendaBucket]) -> Result<()> {
+        self.authorize(AccessRole::Admin)?;
+        let v = buckets
+            .iter()
+            .map(|b| b.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        self.set_meta(META_AGENDA_BUCKETS, &v)
+    }
+
+    /// Returns the settings of the context, the missing or
+    /// invalid ones fall back to the defaults
+    pub fn settings(&self) -> Settings {
+        let d = Settings::default();
+        Settings {
+            timezone: self
+                .get_meta(META_SETTINGS_TIMEZONE)
+                .and_then(|v| parse_utc_offset(&v).ok()),
+            locale: self.get_meta(META_SETTINGS_LOCALE).unwrap_or(d.locale),
+            week_start: self
+                .get_meta(META_SETTINGS_WEEK_START)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(d.week_start),
+            reminder: self
+                .get_meta(META_SETTINGS_REMINDER)
+                .and_then(|v| TimeWindow::from_str(&v).ok())
+                .unwrap_or(d.reminder),
+            menu: self
+                .get_meta(META_SETTINGS_MENU)
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|a| !a.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or(d.menu),
+        }
+    }
+
+    /// Store the settings of the context
+    pub fn set_settings(&mut self, settings: &Settings) -> Result<()> {
+        self.authorize(AccessRole::Admin)?;
+        if settings.locale.trim().is_empty() {
+            return Err(DataError::GenericError("the locale is empty".to_string()));
+        }
+        match settings.reminder {
+            TimeWindow::UpTo | TimeWindow::SingleDay => {
+                return Err(DataError::GenericError(format!(
+                    "invalid reminder window: {}",
+                    settings.reminder
+                )))
+            }
+            _ => {}
+        }
+        let tz = settings.timezone.map_or(String::new(), |tz| tz.to_string());
+        self.set_meta(META_SETTINGS_TIMEZONE, &tz)?;
+        self.set_meta(META_SETTINGS_LOCALE, settings.locale.trim())?;
+        self.set_meta(META_SETTINGS_WEEK_START, &settings.week_start.to_string())?;
+        self.set_meta(META_SETTINGS_REMINDER, &settings.reminder.to_string())?;
+        self.set_meta(META_SETTINGS_MENU, &settings.menu.join(","))
+    }
+
+    /// Returns the lifecycle of the entities of a class, the
+    /// stored one or the default one, see Lifecycle::default_for
+    pub fn lifecycle(&self, class: &str) -> Option<Lifecycle> {
+        match self.get_meta(&format!("{}{}", META_LIFECYCLE_PREFIX, class)) {
+            Some(v) => v.parse().ok(),
+            None => Lifecycle::default_for(class),
+        }
+    }
+
+    /// Store the lifecycle of the entities of a class, none
+    /// restores the default one. The entities already in a state
+    /// that is no longer part of the lifecycle can enter any state
+    pub fn set_lifecycle(&mut self, class: &str, lifecycle: Option<&Lifecycle>) -> Result<()> {
+        self.authorize(AccessRole::Admin)?;
+        let key = format!("{}{}", META_LIFECYCLE_PREFIX, class);
+        match lifecycle {
+            Some(lc) => self.set_meta(&key, &lc.to_string()),
+            None => {
+                self.system.remove(format!("meta:{}", key))?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Move an entity to a state of the lifecycle of its class, the
+    /// transition is recorded as an event, eg. "lead -> prospect".
+    /// Moving to the current state is a no-op
+    pub fn move_to_stage(&mut self, entity: &Entity, stage: &str) -> Result<Entity> {
+        let mut target = match self.get_by_uid(&entity.uid())? {
+            Some(e) => e,
+            None => return Err(DataError::NotFound),
+        };
+        let stage = stage.trim().to_lowercase();
+        if target.stage.as_deref() == Some(&stage[..]) {
+            return Ok(target);
+        }
+        let lc = self.lifecycle(&target.class).ok_or_else(|| {
+            DataError::GenericError(format!("no lifecycle for the class {}", target.class))
+        })?;
+        // a state that is gone from the lifecycle is like no state
+        let from = target.stage.clone().filter(|s| lc.has_state(s));
+        if !lc.can_move(from.as_deref(), &stage) {
+            return Err(DataError::GenericError(format!(
+                "cannot move from {} to {}, the lifecycle is {}",
+                from.as_deref().unwrap_or("no state"),
+                stage,
+                lc
+            )));
+        }
+        let msg = match &target.stage {
+            Some(s) => format!("{} -> {}", s, stage),
+            None => stage.to_owned(),
+        };
+        target.set_stage(&stage);
+        self.update(&target)?;
+        self.record(&Event::log("stage", &target, Some(msg)))?;
+        Ok(target)
+    }
+
+    /// Returns the entities in a lifecycle state sorted
+    /// by name, the archived entities are left out
+    pub fn by_state(&self, state: &str) -> Vec<Entity> {
+        let mut found = self
+            .entities
+            .iter()
+            .values()
+            .filter_map(|v| v.ok())
+            .map(|raw| bincode::deserialize::<Entity>(&raw).unwrap())
+            .filter(|e| !e.is_archived() && e.stage.as_deref() == Some(state))
+            .collect::<Vec<Entity>>();
+        found.sort_by(|a, b| a.name().cmp(b.name()));
+        found
+    }
+
+    /// Returns the entities located in a city sorted by name, followed
+    /// by the ones within NEAR_KM from it sorted by distance.
+    ///
+    /// The city is located at the center of the coordinates of the
+    /// entities in it, when none has coordinates only the entities in
+    /// the city are returned. The archived entities are left out
+    pub fn near(&self, city: &str) -> Vec<Entity> {
+        let all = self
+            .entities
+            .iter()
+            .values()
+            .filter_map(|v| v.ok())
+            .map(|raw| bincode::deserialize::<Entity>(&raw).unwrap())
+            .filter(|e| !e.is_archived() && e.address.is_some())
+            .collect::<Vec<Entity>>();
+        let (mut found, others): (Vec<Entity>, Vec<Entity>) =
+            all.into_iter().partition(|e| e.is_in(city));
+        found.sort_by(|a, b| a.name().cmp(b.name()));
+        let points = found
+            .iter()
+            .filter_map(|e| e.address.as_ref().and_then(|a| a.location))
+            .collect::<Vec<(f64, f64)>>();
+        if points.is_empty() {
+            return found;
+        }
+        let n = points.len() as f64;
+        let lat = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let lon = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let mut nearby = others
+            .into_iter()
+            .filter_map(|e| {
+                let d = e.address.as_ref()?.distance_from(lat, lon)?;
+                if d <= NEAR_KM {
+                    Some((d, e))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<(f64, Entity)>>();
+        nearby.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        found.extend(nearby.into_iter().map(|(_, e)| e));
+        found
+    }
+
+    /// Returns how often, in days, the datastore shall be backed up,
+    /// 0 means that the automatic backups are disabled
+    pub fn backup_every(&self) -> i64 {
+        match self.get_meta(META_BACKUP_EVERY) {
+            Some(v) => v.parse::<i64>().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Set how often, in days, the datastore shall be backed up
+    pub fn set_backup_every(&mut self, days: i64) -> Result<()> {
+        self.authorize(AccessRole::Admin)?;
+        if days < 0 {
+            return Err(DataError::GenericError(
+                "the backup interval cannot be negative".to_string(),
+            ));
+        }
+        self.set_meta(META_BACKUP_EVERY, &days.to_string())
+    }
+
+    /// Returns the time of the last backup, if any
+    pub fn last_backup(&self) -> Option<DateTime<FixedOffset>> {
+        self.get_meta(META_BACKUP_LAST)
+            .and_then(|v| DateTime::parse_from_rfc3339(&v).ok())
+    }
+
+    /// Returns the retention policy of the backups
+    pub fn backup_retention(&self) -> Retention {
+        let d = Retention::default();
+        let get = |k: &str, default: usize| match self.get_meta(k) {
+            Some(v) => v.parse::<usize>().unwrap_or(default),
+            None => default,
+        };
+        Retention {
+            daily: get(META_BACKUP_KEEP_DAILY, d.daily),
+            weekly: get(META_BACKUP_KEEP_WEEKLY, d.weekly),
+            monthly: get(META_BACKUP_KEEP_MONTHLY, d.monthly),
+        }
+    }
+
+    /// Store the retention policy of the backups
+    pub fn set_backup_retention(&mut self, r: &Retention) -> Result<()> {
+        self.authorize(AccessRole::Admin)?;
+        self.set_meta(META_BACKUP_KEEP_DAILY, &r.daily.to_string())?;
+        self.set_meta(META_BACKUP_KEEP_WEEKLY, &r.weekly.to_string())?;
+        self.set_meta(META_BACKUP_KEEP_MONTHLY, &r.monthly.to_string())
+    }
+
+    /// Tells if the automatic backups are enabled and the
+    /// last backup is older than the configured interval
+    pub fn backup_due(&self, now: &DateTime<FixedOffset>) -> bool {
+        let every = self.backup_every();
+        if every == 0 {
+            return false;
+        }
+        match self.last_backup() {
+            Some(last) => *now - last >= Duration::days(every),
+            None => true,
+        }
+    }
+
+    /// Export the datastore in a timestamped file in the
+    /// backup directory and returns the path of the file.
+    ///
+    /// The backups of a sealed datastore are encrypted with
+    /// the same passphrase, they are never written in clear
+    pub fn backup(&mut self, dir: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let now = utils::now_local();
+        let path = dir.join(format!(
+            "{}{}{}",
+            BACKUP_PREFIX,
+            now.format(BACKUP_TS_FORMAT),
+            BACKUP_EXT
+        ));
+        match &self.sealed {
+            Some((_, passphrase)) => {
+                self.export_encrypted(&path, ExportFormat::FullJson, None, None, passphrase)?
+            }
+            None => self.export(&path, ExportFormat::FullJson)?,
+        }
+        self.set_meta(META_BACKUP_LAST, &now.to_rfc3339())?;
+        Ok(path)
+    }
+
+    /// Take a compressed snapshot of all the trees of the
+    /// datastore in a directory, returns the snapshot path.
+    ///
+    /// Unlike a backup the snapshot is a raw copy of the
+    /// datastore, indexes and audit trail included. The
+    /// snapshots of a sealed datastore are sealed too. Taking
+    /// a snapshot requires the admin role
+    pub fn snapshot(&self, dir: &Path) -> Result<PathBuf> {
+        self.authorize(AccessRole::Admin)?;
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "{}{}{}",
+            SNAPSHOT_PREFIX,
+            utils::now_local().format(BACKUP_TS_FORMAT),
+            SNAPSHOT_EXT
+        ));
+        let trees = self.dump()?;
+        if let Some((_, passphrase)) = &self.sealed {
+            std::fs::write(&path, seal_snapshot(&trees, passphrase)?)?;
+            return Ok(path);
+        }
+        let mut gz = GzEncoder::new(File::create(&path)?, Compression::default());
+        bincode::serialize_into(&mut gz, &trees)
+            .map_err(|e| DataError::GenericError(e.to_string()))?;
+        gz.finish()?;
+        Ok(path)
+    }
+
+    /// Returns the content of all the trees of the datastore
+    fn dump(&self) -> Result<Snapshot> {
+        let mut trees: Snapshot = Vec::new();
+        for name in self.db.tree_names() {
+            let mut kvs = Vec::new();
+            for r in self.db.open_tree(&name)?.iter() {
+                let (k, v) = r?;
+                kvs.push((k.to_vec(), v.to_vec()));
+            }
+            trees.push((name.to_vec(), kvs));
+        }
+        Ok(trees)
+    }
+
+    /// Roll back the datastore to a snapshot, the trees that
+    /// are not in the snapshot are cleared.
+    ///
+    /// The snapshot is read completely before touching the
+    /// datastore, so a broken snapshot leaves it as it is.
+    /// The snapshot brings back the users and their roles as
+    /// they were, so only the owners can restore it
+    pub fn restore(&mut self, path: &Path) -> Result<()> {
+        self.authorize(AccessRole::Owner)?;
+        let raw = std::fs::read(path)?;
+        let trees = match (formats::is_encrypted_data(&raw), &self.sealed) {
+            (true, Some((_, passphrase))) => unseal_snapshot(&raw, passphrase)?,
+            (true, None) => {
+                return Err(DataError::GenericError(
+                    "the snapshot is sealed".to_string(),
+                ))
+            }
+            (false, _) => bincode::deserialize_from(GzDecoder::new(raw.as_slice()))
+                .map_err(|e| DataError::GenericError(format!("invalid snapshot: {}", e)))?,
+        };
+        self.load(trees)
+    }
+
+    /// Replace the content of the datastore with a snapshot
+    ///
+    /// All the trees are replaced in one transaction, so a failure
+    /// leaves the datastore as it was
+    fn load(&mut self, trees: Snapshot) -> Result<()> {
+        let mut names = self.db.tree_names();
+        for (name, _) in trees.iter() {
+            if !names.iter().any(|n| n.as_ref() == name.as_slice()) {
+                names.push(name.as_slice().into());
+            }
+        }
+        // the trees that are not in the snapshot are emptied
+        let (mut opened, mut batches) = (Vec::new(), Vec::new());
+        for name in names.iter() {
+            let tree = self.db.open_tree(name)?;
+            let mut batch = clear_batch(&tree)?;
+            if let Some((_, kvs)) = trees.iter().find(|(n, _)| n.as_slice() == name.as_ref()) {
+                for (k, v) in kvs.iter() {
+                    batch.insert(k.as_slice(), v.as_slice());
+                }
+            }
+            opened.push(tree);
+            batches.push(batch);
+        }
+        let opened = opened.iter().collect::<Vec<&sled::Tree>>();
+        let r: TransactionResult<(), DataError> = opened[..].transaction(|t| {
+            for (tree, batch) in t.iter().zip(batches.iter()) {
+                tree.apply_batch(batch)?;
+            }
+            Ok(())
+        });
+        if r.is_err() {
+            return Err(DataError::TxError);
+        }
+        self.db.flush()?;
+        // the snapshots taken before the schema version have none
+        self.migrate()?;
+        self.build_reverse_edges()?;
+        self.build_dates_index()?;
+        self.build_events_time_index()?;
+        self.build_search_index();
+        Ok(())
+    }
+
+    /// Iterate over all the entities in the datastore, the entities
+    /// are read one at a time as the iterator advances.
+    ///
+    /// A read error or an entity that cannot be decoded is returned
+    /// as an error, the iteration can go on past it
+    pub fn iter_entities(&self) -> impl Iterator<Item = Result<Entity>> {
+        self.entities.iter().map(|r| {
+            let (k, raw) = r?;
+            bincode::deserialize(&raw).map_err(|e| {
+                DataError::GenericError(format!("cannot read entity {}: {}", str(&k), e))
+            })
+        })
+    }
+
+    /// Store the content of a file in the datastore and returns the
+    /// attachment to add to an entity or an event, see Entity::attach.
+    ///
+    /// The content is keyed by its hash, so the same file is stored once
+    pub fn store_attachment(&mut self, path: &Path) -> Result<Attachment> {
+        self.authorize(AccessRole::Editor)?;
+        let content = std::fs::read(path)?;
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let a = Attachment::file(&name, &content);
+        if !self.attachments.contains_key(&a.hash)? {
+            self.attachments.insert(&a.hash, content)?;
+        }
+        Ok(a)
+    }
+
+    /// Returns the content of an attachment, None for the
+    /// references and for the content that is not stored
+    pub fn attachment_content(&self, a: &Attachment) -> Result<Option<Vec<u8>>> {
+        if a.is_reference() {
+            return Ok(None);
+        }
+        Ok(self.attachments.get(&a.hash)?.map(|v| v.to_vec()))
+    }
+
+    /// Write the content of an attachment in a directory
+    /// and returns the path of the file written
+    pub fn extract_attachment(&self, a: &Attachment, dir: &Path) -> Result<PathBuf> {
+        let content = self.attachment_content(a)?.ok_or(DataError::NotFound)?;
+        // the name may come from another system, keep only the file name
+        let name = Path::new(&a.name)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| a.hash.clone());
+        let path = dir.join(name);
+        std::fs::write(&path, content)?;
+        Ok(path)
+    }
+
+    /// Returns the attachments of an entity and of the events
+    /// it took part in, the latter with the event they belong to
+    pub fn attachments_of(&self, e: &Entity) -> Vec<(Option<Event>, Attachment)> {
+        let mut found = e
+            .attachments
+            .iter()
+            .map(|a| (None, a.clone()))
+            .collect::<Vec<_>>();
+        for evt in self.events(e, EventFilter::Any) {
+            for a in evt.attachments.iter() {
+                found.push((Some(evt.clone()), a.clone()));
+            }
+        }
+        found
+    }
+
+    /// Remove the stored content no entity or event refers to anymore,
+    /// returns the number of attachments removed
+    pub fn prune_attachments(&mut self) -> Result<usize> {
+        self.authorize(AccessRole::Admin)?;
+        let mut used = HashSet::new();
+        for e in self.iter_entities() {
+            used.extend(e?.attachments.into_iter().map(|a| a.hash));
+        }
+        for r in self.events.iter() {
+            let (_, raw) = r?;
+            let evt: Event = bincode::deserialize(&raw).unwrap();
+            used.extend(evt.attachments.into_iter().map(|a| a.hash));
+        }
+        let mut batch = Batch::default();
+        let mut removed = 0;
+        for k in self.attachments.iter().keys() {
+            let k = k?;
+            if !used.contains(&str(&k)) {
+                batch.remove(k);
+                removed += 1;
+            }
+        }
+        self.attachments.apply_batch(batch)?;
+        Ok(removed)
+    }
+
+    /// return if the database is empty
+    pub fn is_empty(&self) -> bool {
+        let entities = self.db.open_tree(TABLE_ENTITIES).unwrap();
+        entities.len() == 0
+    }
+
+    /// Flush and close the datastore, a sealed datastore is sealed
+    /// back to its file and it is not sealed again when dropped
+    ///
+    /// be aware that the underling files may
+    /// take a moment to actually close the file
+    pub fn close(&mut self) -> Result<()> {
+        self.db.flush()?;
+        self.write_back()?;
+        // sealed once, not again when dropped
+        self.sealed = None;
+        Ok(())
+    }
+
+    /// Export the dataset in the format expressed by the format parameter
+    ///
+    pub fn export(&self, path: &Path, format: ExportFormat) -> Result<()> {
+        self.export_signed(path, format, None)
+    }
+
+    /// Export the dataset signing it with a key,
+    /// the signature applies to the json format only
+    pub fn export_signed(
+        &self,
+        path: &Path,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+    ) -> Result<()> {
+        self.export_with(path, format, key, None)
+    }
+
+    /// Export only the entities updated since a date (included),
+    /// for incremental backups and syncs.
+    ///
+    /// The json and csv deltas can be merged with import. The full
+    /// format is refused, since importing it replaces the whole
+    /// datastore and a delta would wipe what it leaves out
+    pub fn export_since(&self, path: &Path, since: NaiveDate, format: ExportFormat) -> Result<()> {
+        self.export_with(path, format, None, Some(since))
+    }
+
+    /// Iterate over the entities updated since a date, or all
+    /// of them without a date, see iter_entities
+    fn entities_since(&self, since: Option<NaiveDate>) -> impl Iterator<Item = Result<Entity>> {
+        self.iter_entities().filter(move |r| match (r, since) {
+            (Ok(e), Some(d)) => e.updated_on >= d,
+            _ => true,
+        })
+    }
+
+    /// Iterate over the events recorded since a date,
+    /// or all of them without a date
+    fn events_since(&self, since: Option<NaiveDate>) -> impl Iterator<Item = Result<Event>> {
+        self.events
+            .iter()
+            .map(|r| {
+                let (_, raw) = r?;
+                Ok(bincode::deserialize::<Event>(&raw).unwrap())
+            })
+            .filter(move |r| match (r, since) {
+                (Ok(evt), Some(d)) => evt.is_after_eq(d),
+                _ => true,
+            })
+    }
+
+    /// Export the dataset, or the changes since a date, encrypted with
+    /// a passphrase, see formats::encrypt. The export is built in
+    /// memory so that only the encrypted data is written to the file
+    pub fn export_encrypted(
+        &self,
+        path: &Path,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+        since: Option<NaiveDate>,
+        passphrase: &str,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        self.export_to(&mut data, format, key, since)?;
+        formats::write_encrypted(path, &data, passphrase)
+    }
+
+    fn export_with(
+        &self,
+        path: &Path,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+        since: Option<NaiveDate>,
+    ) -> Result<()> {
+        self.export_to(File::create(path)?, format, key, since)
+    }
+
+    fn export_to<W: Write>(
+        &self,
+        out: W,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+        since: Option<NaiveDate>,
+    ) -> Result<()> {
+        if since.is_some() && format == ExportFormat::FullJson {
+            return Err(DataError::GenericError(
+                "the full format cannot be exported since a date".to_string(),
+            ));
+        }
+        let mut file = LineWriter::new(out);
+
+        match format {
+            ExportFormat::Json => {
+                let mut w = ExportWriter::new(&mut file, key);
+                for e in self.entities_since(since) {
+                    w.write_record(&serde_json::to_string(&e?).unwrap())?;
+                }
+                w.finish()?;
+            }
+            ExportFormat::Csv => {
+                let mut w = csv::Writer::from_writer(&mut file);
+                let csv_err = |e: csv::Error| DataError::GenericError(e.to_string());
+                w.write_record(&formats::ENTITY_COLUMNS).map_err(csv_err)?;
+                for e in self.entities_since(since) {
+                    w.write_record(&formats::entity_record(&e?))
+                        .map_err(csv_err)?;
+                }
+                w.flush()?;
+            }
+            ExportFormat::FullJson => {
+                // the indexes are derived from the entities and the
+                // events, so they are rebuilt on import
+                let mut w = ExportWriter::new(&mut file, key);
+                for e in self.entities_since(since) {
+                    let rec = FullRecord::Entity(e?);
+                    w.write_record(&serde_json::to_string(&rec).unwrap())?;
+                }
+                for evt in self.events_since(since) {
+                    let rec = FullRecord::Event(evt?);
+                    w.write_record(&serde_json::to_string(&rec).unwrap())?;
+                }
+                for r in self.system.iter() {
+                    let (k, v) = r?;
+                    // the sessions are bound to this datastore
+                    if k.starts_with(SESSION_PREFIX.as_bytes()) {
+                        continue;
+                    }
+                    let rec = FullRecord::System(str(&k), str(&v));
+                    w.write_record(&serde_json::to_string(&rec).unwrap())?;
+                }
+                for t in self.iter_tasks() {
+                    if since.map_or(true, |d| t.created_on >= d) {
+                        let rec = FullRecord::Task(t);
+                        w.write_record(&serde_json::to_string(&rec).unwrap())?;
+                    }
+                }
+                for g in self.goals() {
+                    if since.map_or(true, |d| g.created_on >= d) {
+                        let rec = FullRecord::Goal(g);
+                        w.write_record(&serde_json::to_string(&rec).unwrap())?;
+                    }
+                }
+                w.finish()?;
+            }
+            ExportFormat::NQuad => {
+                for e in self.entities_since(since) {
+                    file.write_all(formats::nquads(&e?).as_bytes())?;
+                }
+            }
+            ExportFormat::VCard => {
+                for e in self.entities_since(since) {
+                    file.write_all(formats::vcard(&e?).as_bytes())?;
+                }
+            }
+            ExportFormat::Dot => {
+                file.write_all(formats::DOT_BEGIN.as_bytes())?;
+                for e in self.entities_since(since) {
+                    file.write_all(formats::dot(&e?).as_bytes())?;
+                }
+                file.write_all(formats::DOT_END.as_bytes())?;
+            }
+            ExportFormat::GraphMl => {
+                file.write_all(formats::GRAPHML_BEGIN.as_bytes())?;
+                for e in self.entities_since(since) {
+                    let e = e?;
+                    let node = formats::graphml(&e, self.last_contact(&e));
+                    file.write_all(node.as_bytes())?;
+                }
+                file.write_all(formats::GRAPHML_END.as_bytes())?;
+            }
+            ExportFormat::Ics => {
+                // only the past actions end up in the calendar
+                let now = utils::now_local();
+                file.write_all(formats::ICS_BEGIN.as_bytes())?;
+                for evt in self.events_since(since) {
+                    let evt = evt?;
+                    if evt.kind.is_log() || evt.recorded_at > now {
+                        continue;
+                    }
+                    let participants = evt
+                        .actors
+                        .iter()
+                        .filter_map(|a| self.get_by_uid(&a.uid()).ok().flatten())
+                        .collect::<Vec<Entity>>();
+                    file.write_all(formats::ics_event(&evt, &participants).as_bytes())?;
+                }
+                file.write_all(formats::ICS_END.as_bytes())?;
+            }
+        };
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Import the dataset from an export
+    pub fn import<S: ImportSource + ?Sized>(
+        &mut self,
+        source: &S,
+        format: ExportFormat,
+    ) -> Result<()> {
+        self.import_verified(source, format, None)
+    }
+
+    /// Compare an export with the datastore and returns the
+    /// changes that importing it would apply, without writing anything
+    pub fn plan_import<S: ImportSource + ?Sized>(
+        &self,
+        source: &S,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+    ) -> Result<ImportPlan> {
+        let entities = read_entities(source, format, key)?;
+        let mut plan = ImportPlan::default();
+        // the owner of each handle within the export
+        let mut handles = HashMap::new();
+        let mut seen = BTreeSet::new();
+        for (line, e) in entities.into_iter() {
+            if !seen.insert(e.uid()) {
+                plan.conflicts
+                    .push((e, format!("line {}: duplicated uid", line)));
+                continue;
+            }
+            let taken = e
+                .all_handles()
+                .iter()
+                .filter(|h| match handles.get(&handle_key(&h.label, &h.value)) {
+                    Some(uid) => *uid != e.uid(),
+                    None => false,
+                })
+                .map(|h| format!("{}:{}", h.label, h.value))
+                .collect::<Vec<String>>();
+            if !taken.is_empty() {
+                plan.conflicts.push((
+                    e,
+                    format!(
+                        "line {}: handles already used by {}",
+                        line,
+                        taken.join(", ")
+                    ),
+                ));
+                continue;
+            }
+            for h in e.all_handles().iter() {
+                handles.insert(handle_key(&h.label, &h.value), e.uid());
+            }
+            match self.get_by_uid(&e.uid())? {
+                Some(current) => match current.diff(&e).is_empty() {
+                    true => plan.unchanged += 1,
+                    false => plan.updated.push((current, e)),
+                },
+                None => plan.new.push(e),
+            }
+        }
+        // the entities that are not in the export will be gone
+        for r in self.entities.iter() {
+            let (k, raw) = r?;
+            if !seen.contains(&str(&k)) {
+                plan.removed.push(bincode::deserialize(&raw).unwrap());
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Import a dataset either replacing or merging into the current one,
+    /// the imported entities win over the stored ones.
+    ///
+    /// When merging the handles already used by another entity are
+    /// dropped from the imported entity and reported, the events of
+    /// a full export are added to the existing ones
+    pub fn import_as<S: ImportSource + ?Sized>(
+        &mut self,
+        source: &S,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+        mode: ImportMode,
+    ) -> Result<ImportReport> {
+        match mode {
+            ImportMode::Replace => {
+                let imported =
+                    self.import_with(source, format, key, |_| Resolution::TakeImported)?;
+                Ok(ImportReport {
+                    imported,
+                    ..ImportReport::default()
+                })
+            }
+            ImportMode::Merge => self.merge(source, format, key),
+        }
+    }
+
+    /// Merge an export into the datastore, see import_as
+    fn merge<S: ImportSource + ?Sized>(
+        &mut self,
+        source: &S,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+    ) -> Result<ImportReport> {
+        self.authorize(AccessRole::Admin)?;
+        let (entities, events, tasks, goals) = match format {
+            ExportFormat::FullJson => {
+                let check = formats::read_records::<FullRecord, _>(source, key)?;
+                if !check.is_valid() {
+                    return Err(DataError::CorruptedData(check.corrupted));
+                }
+                let (mut entities, mut events) = (Vec::new(), Vec::new());
+                let (mut tasks, mut goals) = (Vec::new(), Vec::new());
+                for (n, r) in check.records.into_iter() {
+                    match r {
+                        FullRecord::Entity(e) => entities.push((n, e)),
+                        FullRecord::Event(evt) => events.push(evt),
+                        FullRecord::Task(t) => tasks.push(t),
+                        FullRecord::Goal(g) => goals.push(g),
+                        FullRecord::System(_, _) => {}
+                    }
+                }
+                (entities, events, tasks, goals)
+            }
+            _ => (
+                read_entities(source, format, key)?,
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+            ),
+        };
+        let mut report = ImportReport::default();
+        for (line, e) in entities.into_iter() {
+            self.upsert(line, e, &mut report)?;
+        }
+        self.merge_related(&events, &tasks, goals)?;
+        Ok(report)
+    }
+
+    /// Merge another datastore into this one, eg. when two contexts
+    /// have been used for the same purpose, see merge.
+    ///
+    /// When the same entity is in both the most recently updated
+    /// version wins, the handles already used by another entity are
+    /// dropped and reported. The attachments are copied too, the
+    /// position of the entity in the other datastore is the line reported
+    pub fn merge_from(&mut self, other: &DataStore) -> Result<ImportReport> {
+        self.authorize(AccessRole::Admin)?;
+        let mut report = ImportReport::default();
+        for (i, e) in other.iter_entities().enumerate() {
+            let e = e?;
+            match self.get_by_uid(&e.uid())? {
+                Some(c) if c.updated_on > e.updated_on => continue,
+                _ => self.upsert(i + 1, e, &mut report)?,
+            }
+        }
+        let events = other.events_since(None).collect::<Result<Vec<Event>>>()?;
+        let tasks = other.iter_tasks().collect::<Vec<Task>>();
+        self.merge_related(&events, &tasks, other.goals())?;
+        for r in other.attachments.iter() {
+            let (k, v) = r?;
+            if !self.attachments.contains_key(&k)? {
+                self.attachments.insert(k, v)?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Add the events, the tasks and the goals that are not in the
+    /// datastore yet, after the entities they refer to have been merged
+    fn merge_related(&mut self, events: &[Event], tasks: &[Task], goals: Vec<Goal>) -> Result<()> {
+        for evt in events.iter() {
+            if !self.events.contains_key(evt.uid())? {
+                self.store_event(evt, false)?;
+            }
+        }
+        // the tasks of the entities that were skipped are dropped
+        for t in tasks.iter() {
+            if !self.tasks.contains_key(t.uid().as_bytes())?
+                && self
+                    .entities
+                    .contains_key(utils::id(&t.entity).as_bytes())?
+            {
+                self.write_task(None, Some(t))?;
+            }
+        }
+        // the goals already in the datastore win
+        for mut g in goals.into_iter() {
+            if self.goals.contains_key(g.uid().as_bytes())? || self.goal(&g.name)?.is_some() {
+                continue;
+            }
+            let known = |uid: &model::Uuid| {
+                self.entities
+                    .contains_key(utils::id(uid).as_bytes())
+                    .unwrap_or(false)
+            };
+            g.entities.retain(known);
+            self.goals
+                .insert(g.uid().as_bytes(), bincode::serialize(&g).unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Insert or update an imported entity, the handles owned by
+    /// another entity are dropped and reported
+    fn upsert(&mut self, line: usize, mut e: Entity, report: &mut ImportReport) -> Result<()> {
+        let mut taken = Vec::new();
+        for h in e.all_handles().into_iter() {
+            if let Some(uid) = self.ids.get(&handle_key(&h.label, &h.value))? {
+                if str(&uid) != e.uid() {
+                    taken.push((h.label, h.value, str(&uid)));
+                }
+            }
+        }
+        for (k, v, owner) in taken.into_iter() {
+            e.remove_handle(&k, &v);
+            let owner = match self.get_by_uid(&owner)? {
+                Some(o) => o.name().to_owned(),
+                None => owner,
+            };
+            report.skipped.push((
+                line,
+                format!("{}: handle {}:{} already used by {}", e.name(), k, v, owner),
+            ));
+        }
+        let current = self.get_by_uid(&e.uid())?;
+        let role = current.as_ref().and_then(|c| c.access_role());
+        // disabling a user is like revoking its role, see update
+        let disabled = current
+            .as_ref()
+            .map_or(false, |c| c.is_disabled() != e.is_disabled());
+        if self.authorize_role_change(role, e.access_role()).is_err()
+            || (disabled && self.authorize_role_change(role, None).is_err())
+        {
+            report.skipped.push((
+                line,
+                format!("{}: your role does not allow to change its role", e.name()),
+            ));
+            return Ok(());
+        }
+        // the passwords change only with change_password and reset_password
+        if let Some(c) = &current {
+            e.pass = c.pass.clone();
+        }
+        match current {
+            Some(c) if c.diff(&e).is_empty() => return Ok(()),
+            Some(c) => self.replace(Some(&c), &e)?,
+            None => self.insert(&e)?,
+        };
+        report.imported += 1;
+        Ok(())
+    }
+
+    /// Import the entities from a csv file, the entities
+    /// are matched by uid and the new ones are sponsored by sponsor.
+    ///
+    /// Only the columns present in the file are applied, the handles
+    /// and tags are added to the existing ones. The rows that cannot
+    /// be read are reported and skipped
+    pub fn import_csv<S: ImportSource + ?Sized>(
+        &mut self,
+        source: &S,
+        mapping: &formats::CsvMapping,
+        sponsor: &Entity,
+    ) -> Result<ImportReport> {
+        self.authorize(AccessRole::Admin)?;
+        let mut report = ImportReport::default();
+        for (line, e) in self.csv_entities(source, mapping, sponsor, &mut report.skipped)? {
+            self.upsert(line, e, &mut report)?;
+        }
+        // in line order, the unreadable rows are collected first
+        report.skipped.sort_by_key(|(line, _)| *line);
+        Ok(report)
+    }
+
+    /// Import the entities from a vCard file, the cards are
+    /// matched by uid and the new ones are sponsored by sponsor.
+    ///
+    /// The name, the description and the handles in the card
+    /// replace the existing ones, the cards that cannot be read
+    /// are reported and skipped
+    pub fn import_vcard<S: ImportSource + ?Sized>(
+        &mut self,
+        source: &S,
+        sponsor: &Entity,
+    ) -> Result<ImportReport> {
+        self.authorize(AccessRole::Admin)?;
+        let mut report = ImportReport::default();
+        for (line, e) in self.vcard_entities(source, sponsor, &mut report.skipped)? {
+            self.upsert(line, e, &mut report)?;
+        }
+        // in line order, the unreadable rows are collected first
+        report.skipped.sort_by_key(|(line, _)| *line);
+        Ok(report)
+    }
+
+    /// Compare a csv or vCard file with the datastore and returns the
+    /// changes that importing it would apply, without writing anything.
+    ///
+    /// The rows are merged, so nothing is removed, the handles already
+    /// used by another entity would be dropped and are reported as skipped
+    pub fn plan_rows<S: ImportSource + ?Sized>(
+        &self,
+        source: &S,
+        format: ExportFormat,
+        mapping: &formats::CsvMapping,
+        sponsor: &Entity,
+    ) -> Result<ImportPlan> {
+        let mut plan = ImportPlan::default();
+        let entities = match format {
+            ExportFormat::Csv => self.csv_entities(source, mapping, sponsor, &mut plan.skipped)?,
+            ExportFormat::VCard => self.vcard_entities(source, sponsor, &mut plan.skipped)?,
+            _ => {
+                return Err(DataError::GenericError(
+                    "only csv and vcard files are merged by row".to_string(),
+                ))
+            }
+        };
+        // the uid and name of the owner of each handle within the file
+        let mut handles: HashMap<String, (String, String)> = HashMap::new();
+        for (line, e) in entities.into_iter() {
+            for h in e.all_handles().into_iter() {
+                let k = handle_key(&h.label, &h.value);
+                let owner = match (handles.get(&k), self.ids.get(&k)?) {
+                    (Some(o), _) => Some(o.clone()),
+                    (None, Some(uid)) => {
+                        let uid = str(&uid);
+                        let name = match self.get_by_uid(&uid)? {
+                            Some(o) => o.name().to_owned(),
+                            None => uid.clone(),
+                        };
+                        Some((uid, name))
+                    }
+                    (None, None) => None,
+                };
+                match owner {
+                    Some((uid, name)) if uid != e.uid() => plan.skipped.push((
+                        line,
+                        format!(
+                            "{}: handle {}:{} already used by {}",
+                            e.name(),
+                            h.label,
+                            h.value,
+                            name
+                        ),
+                    )),
+                    Some(_) => {}
+                    None => {
+                        handles.insert(k, (e.uid(), e.name().to_owned()));
+                    }
+                }
+            }
+            match self.get_by_uid(&e.uid())? {
+                Some(current) => match current.diff(&e).is_empty() {
+                    true => plan.unchanged += 1,
+                    false => plan.updated.push((current, e)),
+                },
+                None => plan.new.push(e),
+            }
+        }
+        plan.skipped.sort_by_key(|(line, _)| *line);
+        Ok(plan)
+    }
+
+    /// Read the entities from a csv file applying the rows to the
+    /// stored entities, the rows that cannot be read are skipped
+    fn csv_entities<S: ImportSource + ?Sized>(
+        &self,
+        source: &S,
+        mapping: &formats::CsvMapping,
+        sponsor: &Entity,
+        skipped: &mut Vec<(usize, String)>,
+    ) -> Result<Vec<(usize, Entity)>> {
+        let mut entities = Vec::new();
+        for (line, row) in formats::read_entity_rows(source, mapping)? {
+            let row = match row {
+                Ok(r) => r,
+                Err(reason) => {
+                    skipped.push((line, reason));
+                    continue;
+                }
+            };
+            let current = match row.uid {
+                Some(uid) => self.get_by_uid(&utils::id(&uid))?,
+                None => None,
+            };
+            let mut e = match (current, &row.name) {
+                (Some(c), _) => c,
+                (None, Some(name)) => match Entity::from(name) {
+                    Ok(e) => e.with_sponsor(sponsor),
+                    Err(err) => {
+                        skipped.push((line, err.to_string()));
+                        continue;
+                    }
+                },
+                (None, None) => {
+                    skipped.push((line, "missing name".to_owned()));
+                    continue;
+                }
+            };
+            if let Some(uid) = row.uid {
+                e.uid = uid;
+            }
+            if let Some(name) = row.name {
+                e.name = name;
+            }
+            if let Some(class) = row.class {
+                e.class = class;
+            }
+            for (k, v) in row.handles.into_iter() {
+                if e.add_handle(&k, &v).is_err() {
+                    skipped.push((line, format!("{}: invalid handle {}:{}", e.name(), k, v)));
+                }
+            }
+            for t in row.tags.into_iter() {
+                e.add_tag(t);
+            }
+            match (row.next_action_date, row.note) {
+                (Some(d), note) => e.next_action(d, note.unwrap_or_default()),
+                (None, Some(note)) => e.next_action(e.next_action_date, note),
+                (None, None) => {}
+            }
+            entities.push((line, e));
+        }
+        Ok(entities)
+    }
+
+    /// Read the entities from a vCard file applying the cards to the
+    /// stored entities, the cards that cannot be read are skipped
+    fn vcard_entities<S: ImportSource + ?Sized>(
+        &self,
+        source: &S,
+        sponsor: &Entity,
+        skipped: &mut Vec<(usize, String)>,
+    ) -> Result<Vec<(usize, Entity)>> {
+        let mut entities = Vec::new();
+        for (line, card) in formats::read_vcards(source)? {
+            let card = match card {
+                Ok(c) => c,
+                Err(reason) => {
+                    skipped.push((line, reason));
+                    continue;
+                }
+            };
+            let current = match card.uid {
+                Some(uid) => self.get_by_uid(&utils::id(&uid))?,
+                None => None,
+            };
+            let mut e = match (current, &card.name) {
+                (Some(c), _) => c,
+                (None, Some(name)) => match Entity::from(name) {
+                    Ok(e) => e.with_sponsor(sponsor),
+                    Err(err) => {
+                        skipped.push((line, err.to_string()));
+                        continue;
+                    }
+                },
+                (None, None) => {
+                    skipped.push((line, "missing name".to_owned()));
+                    continue;
+                }
+            };
+            if let Some(uid) = card.uid {
+                e.uid = uid;
+            }
+            if let Some(name) = card.name {
+                e.name = name;
+            }
+            if let Some(description) = card.description {
+                e.description = description;
+            }
+            for (k, v) in card.handles.into_iter() {
+                if e.add_handle(&k, &v).is_err() {
+                    skipped.push((line, format!("{}: invalid handle {}:{}", e.name(), k, v)));
+                }
+            }
+            entities.push((line, e));
+        }
+        Ok(entities)
+    }
+
+    /// Import a dataset verifying the checksums and, if a key
+    /// is provided, the signature of the export.
+    ///
+    /// Nothing is imported if the verification fails, the error
+    /// reports the lines that are corrupted
+    pub fn import_verified<S: ImportSource + ?Sized>(
+        &mut self,
+        source: &S,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+    ) -> Result<()> {
+        self.import_with(source, format, key, |_| Resolution::TakeImported)?;
+        Ok(())
+    }
+
+    /// Import a dataset replacing the current entities, calling
+    /// resolve for every uid or handle conflict.
+    ///
+    /// A uid conflict happens when an imported entity differs from
+    /// the stored one or when the same uid is imported twice, a handle
+    /// conflict when an handle is already used by another entity.
+    /// Returns the number of entities imported
+    pub fn import_with<S: ImportSource + ?Sized, F>(
+        &mut self,
+        source: &S,
+        format: ExportFormat,
+        key: Option<&ExportKey>,
+        mut resolve: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&ImportConflict) -> Resolution,
+    {
+        self.authorize(AccessRole::Admin)?;
+        // a full export restores the datastore as it was
+        if format == ExportFormat::FullJson {
+            return self.restore_export(source, key);
+        }
+        let entities = read_entities(source, format, key)?;
+        // the entities to import and the owners of uids and handles
+        let mut out: Vec<Option<Entity>> = Vec::new();
+        let mut uids: HashMap<String, usize> = HashMap::new();
+        let mut owners: HashMap<String, usize> = HashMap::new();
+        for (line, e) in entities.into_iter() {
+            // uid conflicts
+            let (local, reason) = match uids.get(&e.uid()) {
+                Some(&i) => (out[i].clone(), format!("line {}: duplicated uid", line)),
+                None => (
+                    self.get_by_uid(&e.uid())?,
+                    format!("line {}: differs from the stored one", line),
+                ),
+            };
+            let candidate = match local {
+                Some(local) if !local.diff(&e).is_empty() => {
+                    let c = ImportConflict {
+                        local: local.clone(),
+                        imported: e,
+                        reason,
+                    };
+                    match resolve(&c) {
+                        Resolution::KeepLocal => Some(local),
+                        Resolution::TakeImported => Some(c.imported),
+                        Resolution::Merge(m) => Some(m),
+                        Resolution::Skip => None,
+                    }
+                }
+                _ => Some(e),
+            };
+            let mut candidate = match candidate {
+                Some(c) => c,
+                None => continue,
+            };
+            // a duplicated uid replaces the previous one
+            if let Some(&i) = uids.get(&candidate.uid()) {
+                if let Some(prev) = out[i].take() {
+                    for h in prev.all_handles().iter() {
+                        owners.remove(&handle_key(&h.label, &h.value));
+                    }
+                }
+            }
+            // handle conflicts, one for each entity owning them
+            let mut taken = candidate
+                .all_handles()
+                .into_iter()
+                .filter_map(|h| {
+                    owners
+                        .get(&handle_key(&h.label, &h.value))
+                        .map(|&i| (i, h.label))
+                })
+                .collect::<Vec<(usize, String)>>();
+            taken.sort();
+            taken.dedup_by_key(|(i, _)| *i);
+            let mut skip = false;
+            for (i, _) in taken.iter() {
+                let holder = match &out[*i] {
+                    Some(h) => h.clone(),
+                    None => continue,
+                };
+                let shared = candidate
+                    .all_handles()
+                    .into_iter()
+                    .filter(|h| holder.has_handle(&h.label, &h.value))
+                    .map(|h| (h.label, h.value))
+                    .collect::<Vec<(String, String)>>();
+                let c = ImportConflict {
+                    local: holder,
+                    imported: candidate.clone(),
+                    reason: format!(
+                        "handles already used: {}",
+                        shared
+                            .iter()
+                            .map(|(k, v)| format!("{}:{}", k, v))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
+                };
+                match resolve(&c) {
+                    // the imported one gives up the handles
+                    Resolution::KeepLocal => shared.iter().for_each(|(k, v)| {
+                        candidate.remove_handle(k, v);
+                    }),
+                    // the holder gives up the handles
+                    Resolution::TakeImported => {
+                        if let Some(h) = out[*i].as_mut() {
+                            shared.iter().for_each(|(k, v)| {
+                                h.remove_handle(k, v);
+                            });
+                        }
+                    }
+                    // the merged entity replaces both
+                    Resolution::Merge(m) => {
+                        if let Some(h) = out[*i].take() {
+                            for h in h.all_handles().iter() {
+                                owners.remove(&handle_key(&h.label, &h.value));
+                            }
+                        }
+                        candidate = m;
+                    }
+                    Resolution::Skip => skip = true,
+                }
+                if skip {
+                    break;
+                }
+            }
+            if skip {
+                continue;
+            }
+            // handles still taken are dropped
+            let taken = candidate
+                .all_handles()
+                .into_iter()
+                .filter(|h| match owners.get(&handle_key(&h.label, &h.value)) {
+                    Some(&i) => out[i]
+                        .as_ref()
+                        .map(|o| o.has_handle(&h.label, &h.value))
+                        .unwrap_or(false),
+                    None => false,
+                })
+                .collect::<Vec<Handle>>();
+            taken.iter().for_each(|h| {
+                candidate.remove_handle(&h.label, &h.value);
+            });
+            // register the candidate
+            out.push(None);
+            let i = out.len() - 1;
+            uids.insert(candidate.uid(), i);
+            candidate.all_handles().iter().for_each(|h| {
+                owners.insert(handle_key(&h.label, &h.value), i);
+            });
+            out[i] = Some(candidate);
+        }
+        // replace the entities
+        self.authorize_replace(&out.iter().flatten().collect::<Vec<&Entity>>())?;
+        self.clear_entities()?;
+        let mut batch = EntityBatch::default();
+        let mut imported = 0;
+        for e in out.iter().flatten() {
+            batch.insert(e);
+            imported += 1;
+        }
+        self.write(&batch)?;
+        self.build_search_index();
+        Ok(imported)
+    }
+
+    /// Checks the changes of roles, of the disabled flags and of the
+    /// passwords that replacing all the entities with others would make,
+    /// the entities left out are removed. See update
+    fn authorize_replace(&self, entities: &[&Entity]) -> Result<()> {
+        let mut new = entities
+            .iter()
+            .map(|e| (e.uid, *e))
+            .collect::<HashMap<model::Uuid, &Entity>>();
+        for old in self.iter_entities() {
+            let old = old?;
+            match new.remove(&old.uid) {
+                Some(e) => {
+                    self.authorize_role_change(old.access_role(), e.access_role())?;
+                    if old.is_disabled() != e.is_disabled() {
+                        self.authorize_role_change(old.access_role(), None)?;
+                    }
+                    if old.pass != e.pass {
+                        self.authorize_password_change(&old)?;
+                    }
+                }
+                None => self.authorize_role_change(old.access_role(), None)?,
+            }
+        }
+        for e in new.values() {
+            self.authorize_role_change(None, e.access_role())?;
+        }
+        Ok(())
+    }
+
+    /// Replace the datastore content with a full export, the system
+    /// entries of the export overwrite the existing ones when the
+    /// principal is an owner, otherwise they are left out.
+    /// The content is replaced in one transaction, so a failure
+    /// leaves the datastore as it was. Returns the number of
+    /// entities imported
+    fn restore_export<S: ImportSource + ?Sized>(
+        &mut self,
+        source: &S,
+        key: Option<&ExportKey>,
+    ) -> Result<usize> {
+        let check = formats::read_records::<FullRecord, _>(source, key)?;
+        if !check.is_valid() {
+            return Err(DataError::CorruptedData(check.corrupted));
+        }
+        let entities = check
+            .records
+            .iter()
+            .filter_map(|(_, r)| match r {
+                FullRecord::Entity(e) => Some(e),
+                _ => None,
+            })
+            .collect::<Vec<&Entity>>();
+        self.authorize_replace(&entities)?;
+        // the events are linked to the imported actors only
+        let uids = entities
+            .iter()
+            .map(|e| e.uid())
+            .collect::<HashSet<String>>();
+        // the settings, the sessions and the reset tokens are the owner's
+        let system = self.authorize(AccessRole::Owner).is_ok();
+        // the existing data is removed by the same batches that
+        // write the export, so that they are applied at once
+        let mut batch = EntityBatch {
+            entities: clear_batch(&self.entities)?,
+            actions: clear_batch(&self.actions)?,
+            ids: clear_batch(&self.ids)?,
+            tags: clear_batch(&self.tags)?,
+            edges: clear_batch(&self.edges)?,
+            reverse_edges: clear_batch(&self.reverse_edges)?,
+            acl: clear_batch(&self.acl)?,
+            sponsorships: clear_batch(&self.sponsorships)?,
+            audit: Batch::default(),
+            dates: clear_batch(&self.dates)?,
+        };
+        let mut events = clear_batch(&self.events)?;
+        let mut entity_event = clear_batch(&self.entity_event)?;
+        let mut events_time = clear_batch(&self.events_time)?;
+        let mut tasks = clear_batch(&self.tasks)?;
+        let mut tasks_due = clear_batch(&self.tasks_due)?;
+        let mut goals = clear_batch(&self.goals)?;
+        let mut settings = Batch::default();
+        let mut recorded = Vec::new();
+        let mut imported = 0;
+        for (_, r) in check.records.into_iter() {
+            match r {
+                FullRecord::Entity(e) => {
+                    batch.insert(&e);
+                    imported += 1;
+                }
+                FullRecord::Event(evt) => {
+                    let k: &str = &evt.uid();
+                    for actor in evt.actors.iter().filter(|a| uids.contains(&a.uid())) {
+                        entity_event.insert(entity_event_key(&evt, actor).as_str(), k);
+                    }
+                    events_time.insert(event_time_key(&evt).as_str(), k);
+                    events.insert(k, bincode::serialize(&evt).unwrap());
+                    recorded.push(evt.uid);
+                }
+                FullRecord::System(k, v) => {
+                    if system {
+                        settings.insert(k.as_bytes(), v.as_bytes());
+                    }
+                }
+                FullRecord::Task(t) => {
+                    let k: &str = &t.uid();
+                    tasks.insert(k, bincode::serialize(&t).unwrap());
+                    if !t.done {
+                        tasks_due.insert(task_due_key(&t).as_str(), k);
+                    }
+                }
+                FullRecord::Goal(g) => {
+                    goals.insert(g.uid().as_bytes(), bincode::serialize(&g).unwrap());
+                }
+            }
+        }
+        let writes = [
+            (&self.entities, &batch.entities),
+            (&self.actions, &batch.actions),
+            (&self.ids, &batch.ids),
+            (&self.tags, &batch.tags),
+            (&self.edges, &batch.edges),
+            (&self.reverse_edges, &batch.reverse_edges),
+            (&self.acl, &batch.acl),
+            (&self.sponsorships, &batch.sponsorships),
+            (&self.dates, &batch.dates),
+            (&self.events, &events),
+            (&self.entity_event, &entity_event),
+            (&self.events_time, &events_time),
+            (&self.tasks, &tasks),
+            (&self.tasks_due, &tasks_due),
+            (&self.goals, &goals),
+            (&self.system, &settings),
+        ];
+        let trees = writes.iter().map(|(t, _)| *t).collect::<Vec<&sled::Tree>>();
+        let r: TransactionResult<(), DataError> = trees[..].transaction(|t| {
+            for (tree, (_, b)) in t.iter().zip(writes.iter()) {
+                tree.apply_batch(b)?;
+            }
+            Ok(())
+        });
+        if r.is_err() {
+            return Err(DataError::TxError);
+        }
+        for uid in recorded.into_iter() {
+            self.notify(ChangeEvent::EventRecorded(uid));
+        }
+        // the search configuration may have changed
+        self.build_search_index();
+        Ok(imported)
+    }
+
+    /// Copy the entities matching the tags and class of a filter into
+    /// another datastore, together with their events, tasks and goals,
+    /// the settings and the attachments they use.
+    ///
+    /// The sponsors of the copied entities and the users come along to
+    /// keep the sponsorship tree whole, the relationships with the
+    /// entities left behind are dropped. The audit trail, the sessions
+    /// and the reset tokens are not copied. Returns the entities copied
+    pub fn copy_into(&self, target: &mut DataStore, filter: &Query) -> Result<usize> {
+        let all = self.iter_entities().collect::<Result<Vec<Entity>>>()?;
+        let by_uid = all
+            .iter()
+            .map(|e| (e.uid, e))
+            .collect::<HashMap<model::Uuid, &Entity>>();
+        let mut keep = BTreeSet::new();
+        for e in all
+            .iter()
+            .filter(|e| filter.matches_entity(e) || e.access_role().is_some())
+        {
+            // follow the sponsors up to the root
+            let mut next = Some(e);
+            while let Some(c) = next {
+                if !keep.insert(c.uid) {
+                    break;
+                }
+                next = by_uid.get(&c.sponsor).copied();
+            }
+        }
+        let mut batch = EntityBatch::default();
+        let mut hashes = BTreeSet::new();
+        for e in all.iter().filter(|e| keep.contains(&e.uid)) {
+            let mut e = e.clone();
+            e.relationships.retain(|r| keep.contains(&r.target));
+            hashes.extend(e.attachments.iter().map(|a| a.hash.clone()));
+            batch.insert(&e);
+        }
+        target.write(&batch)?;
+        let mut copied = BTreeSet::new();
+        for evt in self.events_since(None) {
+            let evt = evt?;
+            if evt.actors.iter().any(|a| keep.contains(&a.role().1)) {
+                hashes.extend(evt.attachments.iter().map(|a| a.hash.clone()));
+                target.store_event(&evt, false)?;
+                copied.insert(evt.uid);
+            }
+        }
+        for h in hashes.iter() {
+            if let Some(content) = self.attachments.get(h)? {
+                target.attachments.insert(h.as_bytes(), content)?;
+            }
+        }
+        for mut t in self.iter_tasks().filter(|t| keep.contains(&t.entity)) {
+            if !t.assignee.map_or(true, |a| keep.contains(&a)) {
+                t.assignee = None;
+            }
+            target.write_task(None, Some(&t))?;
+        }
+        for mut g in self.goals().into_iter() {
+            g.entities.retain(|e| keep.contains(e));
+            g.events.retain(|e| copied.contains(e));
+            if !g.entities.is_empty() {
+                target
+                    .goals
+                    .insert(g.uid().as_bytes(), bincode::serialize(&g).unwrap())?;
+            }
+        }
+        for r in self.system.iter() {
+            let (k, v) = r?;
+            if k.starts_with(SESSION_PREFIX.as_bytes()) || k.starts_with(reset_key("").as_bytes()) {
+                continue;
+            }
+            target.system.insert(k, v)?;
+        }
+        target.build_search_index();
+        Ok(keep.len())
+    }
+
+    /// Remove all the entities and their indexes,
+    /// the events and the metadata are left untouched
+    fn clear_entities(&mut self) -> Result<()> {
+        self.entities.clear()?;
+        self.actions.clear()?;
+        self.ids.clear()?;
+        self.tags.clear()?;
+        self.edges.clear()?;
+        self.reverse_edges.clear()?;
+        self.acl.clear()?;
+        self.sponsorships.clear()?;
+        self.dates.clear()?;
+        Ok(())
+    }
+
+    /// Import an interaction history and attach it to the existing entities
+    ///
+    /// Every record references an entity either by handle (prefix:value)
+    /// or by name, when more than one entity matches the reference the
+    /// choose function is called to pick one (or none to skip the record).
+    /// The events are recorded by the author.
+    pub fn import_events<S: ImportSource + ?Sized, F>(
+        &mut self,
+        source: &S,
+        format: ExportFormat,
+        author: &Entity,
+        mut choose: F,
+    ) -> Result<EventImportReport>
+    where
+        F: FnMut(&EventRecord, &[Entity]) -> Option<Entity>,
+    {
+        let mut report = EventImportReport::default();
+        for (line, r) in formats::read_event_records(source, &format)? {
+            let rec = match r {
+                Ok(rec) => rec,
+                Err(e) => {
+                    report.skipped.push((line, e));
+                    continue;
+                }
+            };
+            let date = match rec.recorded_on() {
+                Some(d) => d,
+                None => {
+                    report
+                        .skipped
+                        .push((line, format!("invalid date {}", rec.date)));
+                    continue;
+                }
+            };
+            let candidates = self.resolve(&rec.entity);
+            let subject = match candidates.len() {
+                0 => None,
+                1 => Some(candidates[0].clone()),
+                _ => choose(&rec, &candidates),
+            };
+            let subject = match subject {
+                Some(s) => s,
+                None => {
+                    report
+                        .skipped
+                        .push((line, format!("no entity selected for {}", rec.entity)));
+                    continue;
+                }
+            };
+            let mut evt = Event::action(
+                "import",
+                rec.kind.trim(),
+                1,
+                rec.content.clone(),
+                &[
+                    model::Actor::RecordedBy(author.uid),
+                    model::Actor::Subject(subject.uid),
+                ],
+            );
+            evt.recorded_at = utils::datetime_local(&date);
+            self.record(&evt)?;
+            report.imported += 1;
+        }
+        Ok(report)
+    }
+
+    /// Find the entities matching a reference, that is either
+    /// an handle in the form prefix:value or a name.
+    ///
+    /// Exact name or alias matches are preferred over the similar ones
+    pub fn resolve(&self, reference: &str) -> Vec<Entity> {
+        self.resolve_with(reference, false)
+    }
+
+    /// Resolve a reference including the archived entities, see resolve
+    pub fn resolve_with(&self, reference: &str, include_archived: bool) -> Vec<Entity> {
+        if let Some((p, v)) = utils::split_once(reference, ':') {
+            if let Ok(Some(e)) = self.get_by_id(p.trim(), v.trim()) {
+                return vec![e];
+            }
+        }
+        let found = self
+            .search_with(reference, include_archived)
+            .into_iter()
+            .map(|r| r.entity)
+            .collect::<Vec<Entity>>();
+        let exact = found
+            .iter()
+            .filter(|e| e.is_named(reference))
+            .cloned()
+            .collect::<Vec<Entity>>();
+        match exact.is_empty() {
+            true => found,
+            false => exact,
+        }
+    }
+
+    /// Set a metadata value
+    pub fn set_meta(&mut self, key: &str, val: &str) -> Result<()> {
+        let k = format!("meta:{}", key);
+        self.system.insert(&k, val)?;
+        Ok(())
+    }
+
+    /// Get a metadata value
+    pub fn get_meta(&self, key: &str) -> Option<String> {
+        let k = format!("meta:{}", key);
+        if let Ok(v) = self.system.get(&k) {
+            if let Some(v) = v {
+                return Some(str(&v));
+            }
+        }
+        None
+    }
+
+    /// Set a typed metadata value in a namespace, the namespaces keep
+    /// apart the state of plugins and adapters, eg. ("sync.google", "token").
+    /// The namespace cannot be empty nor contain a colon
+    pub fn set_meta_value(&mut self, namespace: &str, key: &str, val: MetaValue) -> Result<()> {
+        let k = typed_meta_key(namespace, key)?;
+        self.system
+            .insert(k, serde_json::to_string(&val).unwrap().as_bytes())?;
+        Ok(())
+    }
+
+    /// Get a typed metadata value from a namespace
+    pub fn get_meta_value(&self, namespace: &str, key: &str) -> Option<MetaValue> {
+        let k = typed_meta_key(namespace, key).ok()?;
+        let v = self.system.get(k).ok()??;
+        serde_json::from_slice(&v).ok()
+    }
+
+    /// Remove a typed metadata value from a namespace,
+    /// returns the value removed if any
+    pub fn remove_meta_value(&mut self, namespace: &str, key: &str) -> Result<Option<MetaValue>> {
+        let k = typed_meta_key(namespace, key)?;
+        Ok(self
+            .system
+            .remove(k)?
+            .and_then(|v| serde_json::from_slice(&v).ok()))
+    }
+
+    /// List the typed metadata values whose "namespace:key" starts
+    /// with a prefix, sorted by key. A namespace followed by a
+    /// colon lists the whole namespace and nothing else
+    pub fn list_meta(&self, prefix: &str) -> Vec<(String, MetaValue)> {
+        self.system
+            .scan_prefix(format!("{}{}", TYPED_META_PREFIX, prefix))
+            .filter_map(|r| r.ok())
+            .filter_map(|(k, v)| {
+                let k = str(&k)[TYPED_META_PREFIX.len()..].to_owned();
+                serde_json::from_slice(&v).ok().map(|v| (k, v))
+            })
+            .collect()
+    }
+
+    /// Perform a search for a string in the name, tags and handles
+    ///
+    /// The results are sorted by relevance, see search_detailed
+    pub fn search(&self, pattern: &str) -> Vec<Entity> {
+        self.search_detailed(pattern)
+            .into_iter()
+            .map(|r| r.entity)
+            .collect()
+    }
+
+    /// Perform a search and returns the results with the field that
+    /// matched and the relevance score.
+    ///
+    /// The entities with an handle exactly matching the pattern come first,
+    /// then the others sorted by the similarity of their fields weighted
+    /// by the search configuration
+    pub fn search_detailed(&self, pattern: &str) -> Vec<SearchResult> {
+        self.search_with(pattern, false)
+    }
+
+    /// Perform a detailed search that may include the archived entities,
+    /// see search_detailed
+    pub fn search_with(&self, pattern: &str, include_archived: bool) -> Vec<SearchResult> {
+        let cfg = &self.index.config;
+        let pattern = pattern.trim();
+        let mut uids = self.index.search(pattern);
+        // the pattern could be a full handle (prefix:value)
+        if let Some((p, v)) = utils::split_once(pattern, ':') {
+            if let Ok(Some(e)) = self.get_by_id(p.trim(), v.trim()) {
+                uids.insert(e.uid());
+            }
+        }
+        let mut results = uids
+            .iter()
+            .filter_map(|uid| self.get_by_uid(uid).ok().flatten())
+            .filter(|e| include_archived || !e.is_archived())
+            .map(|e| {
+                let exact = e.all_handles().iter().any(|h| {
+                    h.value.eq_ignore_ascii_case(pattern)
+                        || format!("{}:{}", h.label, h.value).eq_ignore_ascii_case(pattern)
+                });
+                let scores = vec![
+                    (
+                        MatchField::Name,
+                        cfg.name_weight * name_similarity(pattern, &e, cfg.threshold),
+                    ),
+                    (
+                        MatchField::Tag,
+                        cfg.tag_weight
+                            * similarity(pattern, &e.get_tags().join(" "), cfg.threshold),
+                    ),
+                    (
+                        MatchField::Handle,
+                        cfg.handle_weight * similarity(pattern, &handle_values(&e), cfg.threshold),
+                    ),
+                    (
+                        MatchField::Field,
+                        cfg.field_weight * similarity(pattern, &field_values(&e), cfg.threshold),
+                    ),
+                ];
+                let field = match exact {
+                    true => MatchField::Handle,
+                    false => {
+                        scores
+                            .iter()
+                            .fold((MatchField::Name, -1.0), |best, (f, s)| match *s > best.1 {
+                                true => (*f, *s),
+                                false => best,
+                            })
+                            .0
+                    }
+                };
+                SearchResult {
+                    entity: e,
+                    field,
+                    exact,
+                    score: scores.iter().map(|(_, s)| s).sum(),
+                }
+            })
+            .collect::<Vec<SearchResult>>();
+        results.sort_by(|a, b| {
+            b.exact
+                .cmp(&a.exact)
+                .then(b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal))
+        });
+        results
+    }
+
+    /// Get an event by its uid
+    pub fn get_event(&self, uid: &str) -> Result<Option<Event>> {
+        match self.events.get(uid)? {
+            Some(raw) => Ok(Some(bincode::deserialize(&raw).unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    /// Get a list of events for an entity sorted
+    /// by date descending (latest first).
+    ///
+    /// Retrieve the list of events for an entity
+    pub fn events(&self, subject: &Entity, filter: EventFilter) -> Vec<Event> {
+        self.events_within(subject, filter, None, None)
+    }
+
+    /// Get a list of events for an entity sorted by date
+    /// descending (latest first) between two dates
+    pub fn events_within(
+        &self,
+        subject: &Entity,
+        filter: EventFilter,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Vec<Event> {
+        self.events_page(subject, filter, since, until, 0, 0).items
+    }
+
+    /// Get a page of the events for an entity sorted by date
+    /// descending (latest first) between two dates, a limit of
+    /// zero returns all the events past the offset.
+    ///
+    /// Only the events within the dates are read, and when the
+    /// filter can be checked on the index (Any and Role) without
+    /// dates only the events in the page are read
+    pub fn events_page(
+        &self,
+        subject: &Entity,
+        filter: EventFilter,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+        limit: usize,
+        offset: usize,
+    ) -> Page<Event> {
+        let uid = subject.uid();
+        // the key is actor_uid:ts:event_uid:role where ts is inverted,
+        // the bounds are a day wider to cover the utc offsets and the
+        // dates are checked again on the events
+        let ts = |d: NaiveDate| i64::MAX.saturating_sub(d.and_hms(0, 0, 0).timestamp_millis());
+        let from = match until {
+            Some(d) => format!("{}:{}", uid, ts(d.succ())),
+            None => format!("{}:", uid),
+        };
+        let to = match since {
+            Some(d) => format!("{}:{}", uid, ts(d.pred())),
+            None => format!("{}:~", uid),
+        };
+        let role = match &filter {
+            EventFilter::Role(r) => Some(r.code()),
+            _ => None,
+        };
+        let on_index = since.is_none()
+            && until.is_none()
+            && matches!(filter, EventFilter::Any | EventFilter::Role(_));
+        let read = |v: &sled::IVec| -> Option<Event> {
+            let raw = self.events.get(v).ok().flatten()?;
+            bincode::deserialize(&raw).ok()
+        };
+        let mut page = Page {
+            items: Vec::new(),
+            total: 0,
+        };
+        // an entity can have more than one role in the same event
+        let mut last = None;
+        for r in self.entity_event.range(from..to) {
+            let (k, v) = r.unwrap();
+            let k = str(&k);
+            if role.is_some() && k.rsplitn(2, ':').next() != role {
+                continue;
+            }
+            if last.as_ref() == Some(&v) {
+                continue;
+            }
+            last = Some(v.clone());
+            let in_page = page.total >= offset && (limit == 0 || page.items.len() < limit);
+            if on_index {
+                if in_page {
+                    match read(&v) {
+                        Some(e) => page.items.push(e),
+                        None => continue,
+                    }
+                }
+                page.total += 1;
+                continue;
+            }
+            match read(&v) {
+                Some(e) if filter.matches(&e) && e.is_between(since, until) => {
+                    if in_page {
+                        page.items.push(e);
+                    }
+                    page.total += 1;
+                }
+                _ => {}
+            }
+        }
+        page
+    }
+
+    /// Get the events between two dates sorted by date ascending
+    /// (oldest first), of one entity or of the whole datastore.
+    ///
+    /// Without an entity the events are read from the time index,
+    /// only the ones within the dates are read
+    pub fn timeline(
+        &self,
+        subject: Option<&Entity>,
+        filter: EventFilter,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Vec<Event> {
+        if let Some(e) = subject {
+            let mut events = self.events_within(e, filter, since, until);
+            events.reverse();
+            return events;
+        }
+        // the bounds are a day wider to cover the utc offsets and the
+        // dates are checked again on the events
+        let ts = |d: NaiveDate| utils::datetime_local(&d).timestamp_millis();
+        let from = match since {
+            Some(d) => time_key(ts(d - Duration::days(1)), ""),
+            None => String::new(),
+        };
+        let to = match until {
+            Some(d) => time_key(ts(d + Duration::days(1)), "~"),
+            None => "~".to_owned(),
+        };
+        self.events_time
+            .range(from..to)
+            .values()
+            .filter_map(|v| {
+                let raw = self.events.get(v.ok()?).ok().flatten()?;
+                bincode::deserialize::<Event>(&raw).ok()
+            })
+            .filter(|evt| filter.matches(evt) && evt.is_between(since, until))
+            .collect()
+    }
+
+    /// Run a query, returns the matching entities together with their
+    /// matching events, the most recently active entities first
+    pub fn query(&self, q: &Query) -> Vec<(Entity, Vec<Event>)> {
+        let mut found = self
+            .entities
+            .iter()
+            .values()
+            .filter_map(|raw| {
+                let e: Entity = bincode::deserialize(&raw.ok()?).ok()?;
+                if !q.matches_entity(&e) {
+                    return None;
+                }
+                if !q.has_event_constraints() {
+                    return Some((e, Vec::new()));
+                }
+                let events = self
+                    .events_within(&e, EventFilter::Any, q.since, q.until)
+                    .into_iter()
+                    .filter(|evt| q.matches_event(evt) && Query::took_part(&e, evt))
+                    .collect::<Vec<Event>>();
+                match events.is_empty() {
+                    true => None,
+                    false => Some((e, events)),
+                }
+            })
+            .collect::<Vec<(Entity, Vec<Event>)>>();
+        found.sort_by(|(a, ae), (b, be)| {
+            let (al, bl) = (ae.first(), be.first());
+            bl.map(|e| e.recorded_at)
+                .cmp(&al.map(|e| e.recorded_at))
+                .then_with(|| a.name().cmp(b.name()))
+        });
+        found
+    }
+
+    /// Count how many times in a row the next action
+    /// of an entity has been postponed, starting from the latest event
+    pub fn postponed_count(&self, subject: &Entity) -> usize {
+        self.events(subject, EventFilter::Logs)
+            .iter()
+            .take_while(|evt| EventFilter::Postponed.matches(evt))
+            .count()
+    }
+
+    /// Returns the escalation level of the next action of an entity at a date
+    pub fn escalation(&self, subject: &Entity, date: &NaiveDate) -> Escalation {
+        Escalation::from(subject.overdue_days(date), self.postponed_count(subject))
+    }
+
+    /// Mark the next action of an entity as done, a "done" log is
+    /// recorded with the headline of the note. The entities with a
+    /// contact cadence get the next contact as the next action,
+    /// the others get a blank note some weeks from now.
+    ///
+    /// Moving the action forward is not recorded as a postponement
+    pub fn complete_next_action(&mut self, subject: &Entity) -> Result<Entity> {
+        self.authorize(AccessRole::Editor)?;
+        let old = self
+            .get_by_uid(&subject.uid())?
+            .ok_or(DataError::NotFound)?;
+        let today = self.settings().today();
+        let headline = old.get_next_action_headline();
+        self.record(&Event::log("done", &old, Some(headline)))?;
+        let mut e = old.clone();
+        match &old.contact_every {
+            Some(every) => e.next_action(every.offset(&today), CONTACT_NOTE.to_string()),
+            None => {
+                let later = TimeWindow::from_str(&utils::random_timewindow(1, 12, Some('w')))
+                    .map_err(|err| DataError::GenericError(err.to_string()))?;
+                e.next_action(later.offset(&today), String::new());
+            }
+        }
+        let uid = self.replace(Some(&old), &e)?;
+        self.notify(ChangeEvent::EntityUpdated(uid));
+        Ok(e)
+    }
+
+    /// Move the next action of an entity by a time window from today,
+    /// keeping the note, it is recorded as postponed, see postponed_count.
+    ///
+    /// The action has to end up later than it is
+    pub fn postpone(&mut self, subject: &Entity, by: &TimeWindow) -> Result<Entity> {
+        let old = self
+            .get_by_uid(&subject.uid())?
+            .ok_or(DataError::NotFound)?;
+        let date = by.offset(&self.settings().today());
+        if date <= old.next_action_date {
+            return Err(DataError::GenericError(format!(
+                "the next action is already on {}",
+                old.next_action_date
+            )));
+        }
+        let mut e = old.clone();
+        e.next_action(date, old.next_action_note.clone());
+        self.update(&e)?;
+        Ok(e)
+    }
+
+    /// Records an event
+    ///
+    /// An event is recorded in the tree events that is
+    /// <uid, Event>
+    /// and for all the actors in the entity_event as
+    /// <actor_uid:ts:event_uid:role, event_uid>
+    pub fn record(&mut self, event: &Event) -> Result<model::Uuid> {
+        self.authorize(AccessRole::Editor)?;
+        self.append(event)
+    }
+
+    /// Record an event without checking the permissions
+    fn append(&mut self, event: &Event) -> Result<model::Uuid> {
+        // consistency check
+        if event.actors.is_empty() {
+            return Err(DataError::GenericError("no actors for event".to_string()));
+        }
+        // add the delegate to the events, the logs included
+        let mut event = event.clone();
+        if let Some(d) = self.delegate {
+            let delegated = event
+                .actors
+                .iter()
+                .any(|a| a.actor_role() == ActorRole::OnBehalfOf);
+            if !delegated {
+                event.actors.push(model::Actor::OnBehalfOf(d));
+            }
+        }
+        self.store_event(&event, true)
+    }
+
+    /// Store an event and link it to its actors, when strict
+    /// all the actors must exist, otherwise the missing ones are not linked
+    fn store_event(&mut self, event: &Event, strict: bool) -> Result<model::Uuid> {
+        // serialize
+        let k: &str = &event.uid();
+        // prepare batch for entity_event
+        let mut ee_batch = Batch::default();
+        for actor in event.actors.iter() {
+            // consistency check
+            if !self.entities.contains_key(actor.uid())? {
+                match strict {
+                    true => return Err(DataError::BrokenReference),
+                    false => continue,
+                }
+            }
+            // now insert <actor_uid:ts:event_uid:role, event_uid>
+            ee_batch.insert(entity_event_key(event, actor).as_str(), k);
+        }
+
+        let tk: &str = &event_time_key(event);
+        let (e, ee, et) = (&self.events, &self.entity_event, &self.events_time);
+        // start a transaction
+        let r: TransactionResult<(), DataError> =
+            (e, ee, et).transaction(|(events, entity_event, events_time)| {
+                let v = bincode::serialize(event).unwrap();
+                // insert the event
+                events.insert(k, v)?;
+                // record the connection between event and entity
+                entity_event.apply_batch(&ee_batch)?;
+                // and its place in time
+                events_time.insert(tk, k)?;
+                Ok(())
+            });
+        match r {
+            Ok(()) => {
+                self.notify(ChangeEvent::EventRecorded(event.uid));
+                Ok(event.uid)
+            }
+            Err(_) => Err(DataError::TxError),
+        }
+    }
+
+    /// Returns the handles of an entity that are registered
+    /// to it in the ids index, as (prefix, value) pairs
+    pub fn handles(&self, entity: &Entity) -> Result<Vec<(String, String)>> {
+        let mut handles = Vec::new();
+        for h in entity.all_handles().into_iter() {
+            if let Some(uid) = self.ids.get(&handle_key(&h.label, &h.value))? {
+                if str(&uid) == entity.uid() {
+                    handles.push((h.label, h.value));
+                }
+            }
+        }
+        Ok(handles)
+    }
+
+    /// Returns all the handles with a prefix (eg. email) together
+    /// with the entity they belong to, sorted by value
+    pub fn all_handles(&self, prefix: &str) -> Vec<(String, Entity)> {
+        let mut handles = self
+            .entities
+            .iter()
+            .map(|r| {
+                let (_, raw) = r.unwrap();
+                bincode::deserialize::<Entity>(&raw).unwrap()
+            })
+            .flat_map(|e| {
+                e.all_handles()
+                    .into_iter()
+                    .filter(|h| h.label == prefix)
+                    .map(|h| (h.value, e.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<(String, Entity)>>();
+        handles.sort_by(|(a, _), (b, _)| a.cmp(b));
+        handles
+    }
+
+    /// Returns the entities connected to an entity in both directions,
+    /// the ones it is related to first, then the ones related to it
+    /// sorted by name. When a kind is set only the relationships
+    /// with the same label are returned, the dates are ignored
+    pub fn related(
+        &self,
+        entity: &Entity,
+        kind: Option<model::RelType>,
+    ) -> Vec<(Direction, model::RelType, Entity)> {
+        let label = kind.map(|k| k.get_label());
+        let wanted = |k: &model::RelType| label.as_ref().map_or(true, |l| *l == k.get_label());
+        let mut found = entity
+            .relationships
+            .iter()
+            .filter(|r| wanted(&r.kind))
+            .filter_map(|r| {
+                let target = self.get_by_uid(&utils::id(&r.target)).ok().flatten()?;
+                Some((Direction::Outgoing, r.kind.clone(), target))
+            })
+            .collect::<Vec<_>>();
+        let mut incoming = self
+            .reverse_edges
+            .scan_prefix(format!("{}:", entity.uid()))
+            .values()
+            .filter_map(|v| v.ok().map(|v| str(&v)))
+            .collect::<BTreeSet<String>>()
+            .iter()
+            .filter_map(|uid| self.get_by_uid(uid).ok().flatten())
+            .flat_map(|source| {
+                source
+                    .relationships
+                    .iter()
+                    .filter(|r| r.target == entity.uid && wanted(&r.kind))
+                    .map(|r| (Direction::Incoming, r.kind.clone(), source.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        incoming.sort_by(|(_, _, a), (_, _, b)| a.name().cmp(b.name()));
+        found.extend(incoming);
+        found
+    }
+
+    /// Returns how complete the profile of an entity is, like
+    /// Entity::completeness_score but the relationships
+    /// pointing to the entity count as well
+    pub fn score(&self, e: &Entity) -> model::Completeness {
+        let mut c = e.completeness_score();
+        let incoming = self
+            .reverse_edges
+            .scan_prefix(format!("{}:", e.uid()))
+            .next()
+            .is_some();
+        if incoming {
+            c.fill("relationships");
+        }
+        c
+    }
+
+    /// Walk the relationships in both directions starting from an
+    /// entity up to a depth, returns the entities reached together
+    /// with their distance, the closest first
+    pub fn neighbors(&self, uid: &str, depth: usize) -> Vec<(usize, Entity)> {
+        let mut seen = BTreeSet::new();
+        seen.insert(uid.to_owned());
+        let mut frontier = match self.get_by_uid(uid).ok().flatten() {
+            Some(e) => vec![e],
+            None => return Vec::new(),
+        };
+        let mut found = Vec::new();
+        for distance in 1..=depth {
+            let mut next = Vec::new();
+            for e in frontier.iter() {
+                for (_, _, other) in self.related(e, None) {
+                    if seen.insert(other.uid()) {
+                        next.push(other);
+                    }
+                }
+            }
+            next.sort_by(|a, b| a.name().cmp(b.name()));
+            found.extend(next.iter().map(|e| (distance, e.clone())));
+            frontier = next;
+        }
+        found
+    }
+
+    /// Returns the entities tagged with prefix:slug sorted by name,
+    /// the prefix aliases are accepted (eg. skill for feat) and an
+    /// empty slug matches all the tags with the prefix.
+    ///
+    /// The nested tags match their parents, eg. skill/rust/async
+    /// is found with skill/rust and skill
+    pub fn by_tag(&self, prefix: &str, slug: &str) -> Vec<Entity> {
+        let t = Tag::from(prefix, slug);
+        let key = match t.slug().is_empty() {
+            true => format!("{}:", t.prefix()),
+            false => format!("{}:{}", t.prefix(), t.slug()),
+        };
+        // an entity appears once per matching tag, the key
+        // goes on with the uid or with the nested segments
+        let uids = self
+            .tags
+            .scan_prefix(&key)
+            .filter_map(|r| r.ok())
+            .filter(|(k, _)| {
+                t.slug().is_empty()
+                    || k[key.len()..].starts_with(b":")
+                    || k[key.len()..].starts_with(b"/")
+            })
+            .map(|(_, v)| str(&v))
+            .collect::<BTreeSet<String>>();
+        let mut found = uids
+            .iter()
+            .filter_map(|uid| self.get_by_uid(uid).ok().flatten())
+            .collect::<Vec<Entity>>();
+        found.sort_by(|a, b| a.name().cmp(b.name()));
+        found
+    }
+
+    /// Rename a tag and the tags nested within it, eg. renaming
+    /// skill to skills turns skill/rust into skills/rust, the new
+    /// tag may have another prefix. Returns the number of entities
+    /// changed
+    pub fn rename_tag(&mut self, from: &Tag, to: &Tag) -> Result<usize> {
+        self.authorize(AccessRole::Editor)?;
+        let depth = from.path().len();
+        if depth == 0 || to.path().is_empty() {
+            return Err(DataError::GenericError(
+                "the tags cannot be empty".to_string(),
+            ));
+        }
+        let found = self.by_tag(from.prefix(), &from.slug());
+        for e in found.iter() {
+            let mut e = e.clone();
+            let nested = e
+                .tags
+                .values()
+                .filter(|t| t.is_within(from))
+                .cloned()
+                .collect::<Vec<Tag>>();
+            for t in nested.iter() {
+                let mut segments = vec![to.to_string()];
+                segments.extend(t.segments().into_iter().skip(depth));
+                e.remove_tag(t);
+                e.add_tag(to.relabel(&segments.join("/")));
+            }
+            self.update(&e)?;
+        }
+        Ok(found.len())
+    }
+
+    /// Returns the tags in use as prefix:slug together with
+    /// the number of entities tagged, sorted by tag
+    pub fn tags(&self) -> Vec<(String, usize)> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for k in self.tags.iter().keys().filter_map(|k| k.ok()) {
+            // the key is prefix:slug:uid
+            let k = str(&k);
+            let tag = match k.rsplitn(2, ':').nth(1) {
+                Some(t) => t.to_owned(),
+                None => continue,
+            };
+            match counts.last_mut() {
+                Some((last, n)) if *last == tag => *n += 1,
+                _ => counts.push((tag, 1)),
+            }
+        }
+        counts
+    }
+
+    /// Returns the handles of an entity that are already taken by
+    /// another entity, together with the entity owning them.
+    ///
+    /// Use it to find out the reason of a DataError::IDAlreadyTaken
+    pub fn handle_conflicts(&self, entity: &Entity) -> Result<Vec<(String, String, Entity)>> {
+        let mut conflicts = Vec::new();
+        for h in entity.all_handles().into_iter() {
+            if let Some(uid) = self.ids.get(&handle_key(&h.label, &h.value))? {
+                if str(&uid) == entity.uid() {
+                    continue;
+                }
+                if let Some(owner) = self.get_by_uid(&str(&uid))? {
+                    conflicts.push((h.label, h.value, owner));
+                }
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Retrieve an entity by one of its ids
+    pub fn get_by_id(&self, prefix: &str, id: &str) -> Result<Option<Entity>> {
+        // the handles are stored normalized
+        let id = match Handle::normalize(prefix, id) {
+            Ok(v) if self.ids.contains_key(handle_key(prefix, &v))? => v,
+            _ => id.to_owned(),
+        };
+        match self.ids.get(handle_key(prefix, &id))? {
+            Some(uid) => match self.entities.get(uid)? {
+                Some(v) => Ok(Some(bincode::deserialize(&v).unwrap())),
+                None => Err(DataError::BrokenReference),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Find the owners of a handle without knowing its type, the
+    /// types are guessed from the value: an email address, a @nick,
+    /// a phone number or a web address, a bare word is tried as a
+    /// nick and as a web address. The value can also be in the form
+    /// type:value. Returns the handle types found with their owner
+    pub fn whois(&self, value: &str) -> Result<Vec<(String, Entity)>> {
+        let value = value.trim();
+        let phone_like = |v: &str| {
+            v.chars().filter(|c| c.is_ascii_digit()).count() > 3
+                && v.chars()
+                    .all(|c| c.is_ascii_digit() || " +-./()".contains(c))
+        };
+        let mut candidates = Vec::new();
+        match utils::split_once(value, ':') {
+            Some((label, v))
+                if !v.starts_with("//") && label.chars().all(|c| c.is_ascii_alphabetic()) =>
+            {
+                candidates.push((label.to_lowercase(), v.trim().to_owned()));
+            }
+            _ if value.contains('@') && !value.starts_with('@') => {
+                candidates.push(("email".to_owned(), value.to_owned()));
+            }
+            _ if phone_like(value) => {
+                for label in ["mobile", "phone"].iter() {
+                    candidates.push((label.to_string(), value.to_owned()));
+                }
+            }
+            _ => {
+                let nick = value.trim_start_matches('@');
+                for label in NICK_HANDLES.iter() {
+                    candidates.push((label.to_string(), nick.to_owned()));
+                    candidates.push((label.to_string(), format!("@{}", nick)));
+                }
+                if !value.starts_with('@') {
+                    candidates.push(("url".to_owned(), value.to_owned()));
+                }
+            }
+        }
+        let mut found: Vec<(String, Entity)> = Vec::new();
+        for (label, v) in candidates.iter() {
+            if found.iter().any(|(l, _)| l == label) {
+                continue;
+            }
+            if let Some(e) = self.get_by_id(label, v)? {
+                found.push((label.to_owned(), e));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Retrieve an entity its uid
+    pub fn get_by_uid(&self, uid: &str) -> Result<Option<Entity>> {
+        match self.entities.get(uid)? {
+            Some(v) => Ok(Some(bincode::deserialize(&v).unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    /// Collect a page of the entities referenced by the action index
+    /// within a range that match a filter, the entities with a higher
+    /// priority come first, the ones with the same priority are sorted
+    /// by date.
+    ///
+    /// The range is walked once per priority, without a filter only
+    /// the entities within the page are loaded
+    fn agenda_page<R>(
+        &self,
+        range: R,
+        filter: &AgendaFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Page<Entity>
+    where
+        R: RangeBounds<String> + Clone,
+    {
+        let load = |uid: &[u8]| {
+            self.entities
+                .get(uid)
+                .unwrap()
+                .map(|raw| bincode::deserialize::<Entity>(&raw).unwrap())
+        };
+        let mut page = Page {
+            items: Vec::new(),
+            total: 0,
+        };
+        for p in Priority::all().iter().rev() {
+            for r in self.actions.range(range.clone()) {
+                let (k, v) = r.unwrap();
+                if action_priority(&k) != Some(*p as u8) {
+                    continue;
+                }
+                let in_page = page.total >= offset && (limit == 0 || page.items.len() < limit);
+                if filter.is_empty() {
+                    if in_page {
+                        page.items.extend(load(&v));
+                    }
+                    page.total += 1;
+                    continue;
+                }
+                if let Some(e) = load(&v).filter(|e| filter.matches(e)) {
+                    if in_page {
+                        page.items.push(e);
+                    }
+                    page.total += 1;
+                }
+            }
+        }
+        page
+    }
+
+    /// Returns the entities matching a filter with the next action
+    /// up to a date (included), a limit of zero returns all the
+    /// entities past the offset
+    pub fn agenda_until(
+        &self,
+        until: &NaiveDate,
+        filter: &AgendaFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Page<Entity> {
+        // the action keys start with the date, so they sort by date
+        let end = until.succ().to_string();
+        self.agenda_page(..end, filter, limit, offset)
+    }
+
+    /// Count the entities with the next action on a date (due) and
+    /// the ones with the next action before it (overdue), only the
+    /// index is read so it is cheap enough for a status line
+    pub fn agenda_counts(&self, today: &NaiveDate) -> (usize, usize) {
+        let (start, end) = (today.to_string(), (*today + Duration::days(1)).to_string());
+        let overdue = self.actions.range(..start.as_str()).count();
+        let due = self.actions.range(start..end).count();
+        (due, overdue)
+    }
+
+    /// Returns the entities matching a filter with the next action
+    /// within a date range, a limit of zero returns all the entities
+    /// past the offset
+    pub fn agenda(
+        &self,
+        since: &NaiveDate,
+        until: &NaiveDate,
+        filter: &AgendaFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Page<Entity> {
+        // TODO: also match disabled records
+        let (start, end) = (since.to_string(), until.to_string());
+        self.agenda_page(start..end, filter, limit, offset)
+    }
+
+    /// Returns the next occurrence of the important dates of the
+    /// entities within a date range (since included, until excluded)
+    /// sorted by date, the archived entities are left out.
+    ///
+    /// The index keeps only the next occurrence of each date, the
+    /// ones that have passed are moved forward on the first read
+    pub fn upcoming_dates(
+        &self,
+        since: &NaiveDate,
+        until: &NaiveDate,
+    ) -> Result<Vec<(NaiveDate, ImportantDate, Entity)>> {
+        self.roll_dates(&utils::today())?;
+        let mut found = Vec::new();
+        for r in self.dates.range(since.to_string()..until.to_string()) {
+            let (k, v) = r?;
+            let (key, uid) = (str(&k), str(&v));
+            let e = match self.get_by_uid(&uid)? {
+                Some(e) => e,
+                None => continue,
+            };
+            // the key is date:uid:slug
+            let (occurrence, slug) = (&key[..10], &key[uid.len() + 12..]);
+            if let (Ok(o), Some(d)) = (
+                occurrence.parse::<NaiveDate>(),
+                e.important_dates().into_iter().find(|d| d.slug() == slug),
+            ) {
+                found.push((o, d, e));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Move the dates that have passed to their next occurrence
+    /// after a date, the ones that do not come back are dropped
+    fn roll_dates(&self, today: &NaiveDate) -> Result<()> {
+        let mut batch = Batch::default();
+        for r in self.dates.range(..today.to_string()) {
+            let (k, v) = r?;
+            batch.remove(k.clone());
+            let (key, uid) = (str(&k), str(&v));
+            let slug = &key[uid.len() + 12..];
+            if let Some(e) = self.get_by_uid(&uid)?.filter(|e| !e.is_archived()) {
+                for d in e.important_dates().iter().filter(|d| d.slug() == slug) {
+                    if let Some(o) = d.next_occurrence(today) {
+                        batch.insert(date_key(&o, &e, d).as_str(), uid.as_str());
+                    }
+                }
+            }
+        }
+        self.dates.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// Store a task, replacing the old version of it if any,
+    /// the due index keeps only the tasks that are not done
+    fn write_task(&self, old: Option<&Task>, task: Option<&Task>) -> Result<()> {
+        let r: TransactionResult<(), DataError> =
+            (&self.tasks, &self.tasks_due).transaction(|(tt, td)| {
+                if let Some(o) = old {
+                    tt.remove(o.uid().as_bytes())?;
+                    td.remove(task_due_key(o).as_bytes())?;
+                }
+                if let Some(t) = task {
+                    tt.insert(t.uid().as_bytes(), bincode::serialize(t).unwrap())?;
+                    if !t.done {
+                        td.insert(task_due_key(t).as_bytes(), t.uid().as_bytes())?;
+                    }
+                }
+                Ok(())
+            });
+        match r {
+            Ok(_) => Ok(()),
+            Err(_) => Err(DataError::TxError),
+        }
+    }
+
+    /// Add a task to an entity, both the entity and the
+    /// assignee must exist
+    pub fn add_task(&mut self, task: &Task) -> Result<model::Uuid> {
+        self.authorize(AccessRole::Editor)?;
+        if self.get_by_uid(&utils::id(&task.entity))?.is_none() {
+            return Err(DataError::NotFound);
+        }
+        if let Some(a) = &task.assignee {
+            if self.get_by_uid(&utils::id(a))?.is_none() {
+                return Err(DataError::NotFound);
+            }
+        }
+        if self.tasks.contains_key(task.uid().as_bytes())? {
+            return Err(DataError::IDAlreadyTaken);
+        }
+        self.write_task(None, Some(task))?;
+        Ok(task.uid)
+    }
+
+    /// Returns a task by its uid
+    pub fn get_task(&self, uid: &str) -> Result<Option<Task>> {
+        Ok(self
+            .tasks
+            .get(uid.as_bytes())?
+            .map(|raw| bincode::deserialize(&raw).unwrap()))
+    }
+
+    /// Update a task, eg. to change its due date or assignee
+    pub fn update_task(&mut self, task: &Task) -> Result<()> {
+        self.authorize(AccessRole::Editor)?;
+        let old = self.get_task(&task.uid())?.ok_or(DataError::NotFound)?;
+        if let Some(a) = &task.assignee {
+            if self.get_by_uid(&utils::id(a))?.is_none() {
+                return Err(DataError::NotFound);
+            }
+        }
+        self.write_task(Some(&old), Some(task))
+    }
+
+    /// Mark a task as done and record it in the entity history
+    pub fn complete_task(&mut self, uid: &str) -> Result<Task> {
+        self.authorize(AccessRole::Editor)?;
+        let old = self.get_task(uid)?.ok_or(DataError::NotFound)?;
+        if old.done {
+            return Ok(old);
+        }
+        let mut task = old.clone();
+        task.done = true;
+        self.write_task(Some(&old), Some(&task))?;
+        if let Some(e) = self.get_by_uid(&utils::id(&task.entity))? {
+            self.record(&Event::log("task done", &e, Some(task.title.clone())))?;
+        }
+        Ok(task)
+    }
+
+    /// Remove a task
+    pub fn remove_task(&mut self, uid: &str) -> Result<Task> {
+        self.authorize(AccessRole::Editor)?;
+        let old = self.get_task(uid)?.ok_or(DataError::NotFound)?;
+        self.write_task(Some(&old), None)?;
+        Ok(old)
+    }
+
+    /// Returns the tasks of an entity, the open ones first,
+    /// sorted by due date
+    pub fn tasks(&self, entity: &Entity) -> Vec<Task> {
+        let mut found = self
+            .iter_tasks()
+            .filter(|t| t.entity == entity.uid)
+            .collect::<Vec<Task>>();
+        found.sort_by(|a, b| a.done.cmp(&b.done).then(a.due.cmp(&b.due)));
+        found
+    }
+
+    /// Iterate over all the tasks
+    fn iter_tasks(&self) -> impl Iterator<Item = Task> {
+        self.tasks
+            .iter()
+            .values()
+            .filter_map(|v| v.ok())
+            .map(|raw| bincode::deserialize::<Task>(&raw).unwrap())
+    }
+
+    /// Returns the open tasks due within a date range (since
+    /// included, until excluded) with their entity, sorted by
+    /// due date, the tasks of archived entities are left out
+    pub fn tasks_due(&self, since: &NaiveDate, until: &NaiveDate) -> Result<Vec<(Task, Entity)>> {
+        let mut found = Vec::new();
+        for r in self.tasks_due.range(since.to_string()..until.to_string()) {
+            let (_, v) = r?;
+            let task = match self.get_task(&str(&v))? {
+                Some(t) => t,
+                None => continue,
+            };
+            if let Some(e) = self
+                .get_by_uid(&utils::id(&task.entity))?
+                .filter(|e| !e.is_archived())
+            {
+                found.push((task, e));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Check that the entities of a goal exist and
+    /// that no other goal has the same name
+    fn check_goal(&self, goal: &Goal) -> Result<()> {
+        for uid in goal.entities.iter() {
+            if self.get_by_uid(&utils::id(uid))?.is_none() {
+                return Err(DataError::NotFound);
+            }
+        }
+        match self.goal(&goal.name)? {
+            Some(g) if g.uid != goal.uid => Err(DataError::IDAlreadyTaken),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add a goal, its name must be unique
+    pub fn add_goal(&mut self, goal: &Goal) -> Result<model::Uuid> {
+        self.authorize(AccessRole::Editor)?;
+        if self.goals.contains_key(goal.uid().as_bytes())? {
+            return Err(DataError::IDAlreadyTaken);
+        }
+        self.check_goal(goal)?;
+        self.goals
+            .insert(goal.uid().as_bytes(), bincode::serialize(goal).unwrap())?;
+        Ok(goal.uid)
+    }
+
+    /// Update a goal, eg. to change its status or its entities
+    pub fn update_goal(&mut self, goal: &Goal) -> Result<()> {
+        self.authorize(AccessRole::Editor)?;
+        if !self.goals.contains_key(goal.uid().as_bytes())? {
+            return Err(DataError::NotFound);
+        }
+        self.check_goal(goal)?;
+        self.goals
+            .insert(goal.uid().as_bytes(), bincode::serialize(goal).unwrap())?;
+        Ok(())
+    }
+
+    /// Remove a goal, the entities and events are left untouched
+    pub fn remove_goal(&mut self, goal: &Goal) -> Result<()> {
+        self.authorize(AccessRole::Editor)?;
+        match self.goals.remove(goal.uid().as_bytes())? {
+            Some(_) => Ok(()),
+            None => Err(DataError::NotFound),
+        }
+    }
+
+    /// Returns a goal by name, the name is matched by its slug
+    pub fn goal(&self, name: &str) -> Result<Option<Goal>> {
+        let slug = utils::slugify(name);
+        Ok(self.goals().into_iter().find(|g| g.slug() == slug))
+    }
+
+    /// Returns all the goals sorted by target date
+    pub fn goals(&self) -> Vec<Goal> {
+        let mut found = self
+            .goals
+            .iter()
+            .values()
+            .filter_map(|v| v.ok())
+            .map(|raw| bincode::deserialize::<Goal>(&raw).unwrap())
+            .collect::<Vec<Goal>>();
+        found.sort_by(|a, b| a.target.cmp(&b.target).then(a.name.cmp(&b.name)));
+        found
+    }
+
+    /// Returns the progress toward a goal
+    pub fn goal_progress(&self, goal: &Goal) -> Result<GoalProgress> {
+        let mut events = HashMap::new();
+        for uid in goal.events.iter() {
+            if let Some(raw) = self.events.get(utils::id(uid).as_bytes())? {
+                let evt: Event = bincode::deserialize(&raw).unwrap();
+                events.insert(evt.uid, evt);
+            }
+        }
+        let mut entities = Vec::new();
+        for uid in goal.entities.iter() {
+            if let Some(e) = self.get_by_uid(&utils::id(uid))? {
+                for evt in self.events_within(&e, EventFilter::Any, Some(goal.created_on), None) {
+                    if Query::took_part(&e, &evt) {
+                        events.insert(evt.uid, evt);
+                    }
+                }
+                entities.push(e);
+            }
+        }
+        let touched = entities
+            .iter()
+            .filter(|e| events.values().any(|evt| Query::took_part(e, evt)))
+            .count();
+        Ok(GoalProgress {
+            entities: entities.len(),
+            touched,
+            events: events.len(),
+            last: events
+                .values()
+                .map(|evt| evt.recorded_at.naive_local().date())
+                .max(),
+        })
+    }
+
+    /// Initialized the database with a principal identity.
+    ///
+    /// It requires that the database is empty and checks that the
+    /// sponsor and the user id are the same
+    pub fn init(&mut self, principal: &Entity) -> Result<model::Uuid> {
+        if !self.is_empty() {
+            return Err(DataError::InitializationError);
+        }
+        if principal.uid() != principal.sponsor_uid() {
+            return Err(DataError::InitializationError);
+        };
+        let uid = self.insert(principal)?;
+        // create a event log
+        self.record(&Event::log("init", principal, None))?;
+        // return the entity uid
+        Ok(uid)
+    }
+
+    /// Adds a new entity to the database
+    pub fn add(&mut self, entity: &Entity) -> Result<model::Uuid> {
+        self.authorize(AccessRole::Editor)?;
+        self.authorize_role_change(None, entity.access_role())?;
+        // search for the sponsor
+        match self.get_by_uid(&entity.sponsor_uid())? {
+            Some(sponsor) => {
+                // cannot self sponsor
+                if sponsor.uid() == entity.uid() {
+                    return Err(DataError::InvalidSponsor);
+                }
+                Ok(())
+            }
+            None => Err(DataError::InvalidSponsor),
+        }?;
+        // now check for conflicting ids
+        for h in entity.all_handles().iter() {
+            if self.ids.get(&handle_key(&h.label, &h.value))?.is_some() {
+                return Err(DataError::IDAlreadyTaken);
+            }
+        }
+        // all good
+        let uid = self.insert(entity)?;
+        self.notify(ChangeEvent::EntityAdded(uid));
+        // create a event log
+        self.record(&Event::log("added", entity, None))?;
+        // return the entity uid
+        Ok(uid)
+    }
+
+    /// Adds a list of new entities to the database at once
+    ///
+    /// The entities are checked as with add, a sponsor can also be
+    /// one of the entities before it in the list. Nothing is added if
+    /// one of them is not valid, the search index is rebuilt once
+    pub fn add_batch(&mut self, entities: &[Entity]) -> Result<Vec<model::Uuid>> {
+        self.authorize(AccessRole::Editor)?;
+        let mut added: BTreeSet<String> = BTreeSet::new();
+        let mut handles: BTreeSet<String> = BTreeSet::new();
+        let mut batch = EntityBatch::default();
+        for entity in entities.iter() {
+            self.authorize_role_change(None, entity.access_role())?;
+            // cannot self sponsor
+            let sponsor = entity.sponsor_uid();
+            if sponsor == entity.uid()
+                || !(added.contains(&sponsor) || self.entities.contains_key(&sponsor)?)
+            {
+                return Err(DataError::InvalidSponsor);
+            }
+            // now check for conflicting ids, within the batch too
+            for h in entity.all_handles().iter() {
+                let hk = handle_key(&h.label, &h.value);
+                if !handles.insert(hk.clone()) || self.ids.get(&hk)?.is_some() {
+                    return Err(DataError::IDAlreadyTaken);
+                }
+            }
+            added.insert(entity.uid());
+            batch.insert(entity);
+            let entry = AuditEntry::new(None, entity, self.principal);
+            self.audit_entry(&mut batch.audit, &entry)?;
+        }
+        // all good
+        self.write(&batch)?;
+        self.build_search_index();
+        let mut uids = Vec::new();
+        for entity in entities.iter() {
+            self.notify(ChangeEvent::EntityAdded(entity.uid));
+            self.record(&Event::log("added", entity, None))?;
+            uids.push(entity.uid);
+        }
+        Ok(uids)
+    }
+
+    pub fn update(&mut self, entity: &Entity) -> Result<model::Uuid> {
+        self.authorize(AccessRole::Editor)?;
+        // search for the sponsor
+        match self.get_by_uid(&entity.uid())? {
+            Some(old) => {
+                self.authorize_role_change(old.access_role(), entity.access_role())?;
+                // disabling a user is like revoking its role
+                if old.is_disabled() != entity.is_disabled() {
+                    self.authorize_role_change(old.access_role(), None)?;
+                }
+                // see change_password and reset_password
+                if old.pass != entity.pass {
+                    return Err(DataError::GenericError(
+                        "the password cannot be changed with an update".to_string(),
+                    ));
+                }
+                // now check for conflicting ids
+                for h in entity.all_handles().iter() {
+                    if let Some(uid) = self.ids.get(&handle_key(&h.label, &h.value))? {
+                        if str(&uid) != entity.uid() {
+                            return Err(DataError::IDAlreadyTaken);
+                        }
+                    }
+                }
+                let uid = self.replace(Some(&old), entity)?;
+                self.notify(ChangeEvent::EntityUpdated(uid));
+                // keep track of the actions that have been pushed forward
+                if entity.is_postponed(&old) {
+                    let msg = format!("{} -> {}", old.next_action_date, entity.next_action_date);
+                    self.record(&Event::log("postponed", entity, Some(msg)))?;
+                }
+                // and the changes in the quality of the relationship
+                if !entity.quality.same_kind(&old.quality) {
+                    let msg = format!("{} -> {}", old.quality.emoji(), entity.quality.emoji());
+                    self.record(&Event::log("quality", entity, Some(msg)))?;
+                }
+                Ok(uid)
+            }
+            None => Err(DataError::NotFound),
+        }
+    }
+
+    /// Update several entities at once, with the same checks of update,
+    /// the changes are stored in a single transaction so either all of
+    /// them or none are. Returns the uids of the entities updated.
+    ///
+    /// The changes are not recorded as postponements nor as quality
+    /// changes, and the new relationships that need an inverse one on
+    /// their target have to go through update
+    pub fn update_many(&mut self, entities: &[Entity]) -> Result<Vec<model::Uuid>> {
+        self.authorize(AccessRole::Editor)?;
+        let mut batch = EntityBatch::default();
+        let mut uids = Vec::new();
+        for entity in entities.iter() {
+            let old = self.get_by_uid(&entity.uid())?.ok_or(DataError::NotFound)?;
+            self.authorize_role_change(old.access_role(), entity.access_role())?;
+            if old.is_disabled() != entity.is_disabled() {
+                self.authorize_role_change(old.access_role(), None)?;
+            }
+            if old.pass != entity.pass {
+                return Err(DataError::GenericError(format!(
+                    "the password of {} cannot be changed with an update",
+                    entity.name()
+                )));
+            }
+            for h in entity.all_handles().iter() {
+                if let Some(uid) = self.ids.get(&handle_key(&h.label, &h.value))? {
+                    if str(&uid) != entity.uid() {
+                        return Err(DataError::IDAlreadyTaken);
+                    }
+                }
+            }
+            if !self.inverse_edges(Some(&old), entity)?.is_empty() {
+                return Err(DataError::GenericError(format!(
+                    "the new relationships of {} cannot be updated in a batch",
+                    entity.name()
+                )));
+            }
+            batch.unindex(&old, entity);
+            batch.insert(entity);
+            let entry = AuditEntry::new(Some(&old), entity, self.principal);
+            if !entry.changes.is_empty() {
+                self.audit_entry(&mut batch.audit, &entry)?;
+            }
+            uids.push(entity.uid);
+        }
+        if uids.is_empty() {
+            return Ok(uids);
+        }
+        self.write(&batch)?;
+        for uid in uids.iter() {
+            self.notify(ChangeEvent::EntityUpdated(*uid));
+        }
+        self.build_search_index();
+        Ok(uids)
+    }
+
+    /// Archive an entity, it is removed from the agenda, the search
+    /// results and the hints but its data and history are kept
+    pub fn archive(&mut self, entity: &Entity) -> Result<model::Uuid> {
+        let mut target = match self.get_by_uid(&entity.uid())? {
+            Some(e) => e,
+            None => return Err(DataError::NotFound),
+        };
+        if target.is_archived() {
+            return Ok(target.uid);
+        }
+        target.archive(utils::today());
+        let uid = self.update(&target)?;
+        self.record(&Event::log("archived", &target, None))?;
+        Ok(uid)
+    }
+
+    /// Bring back an archived entity, it shows up again in the agenda
+    pub fn unarchive(&mut self, entity: &Entity) -> Result<model::Uuid> {
+        let mut target = match self.get_by_uid(&entity.uid())? {
+            Some(e) => e,
+            None => return Err(DataError::NotFound),
+        };
+        if !target.is_archived() {
+            return Ok(target.uid);
+        }
+        target.unarchive();
+        let uid = self.update(&target)?;
+        self.record(&Event::log("unarchived", &target, None))?;
+        Ok(uid)
+    }
+
+    /// Returns the entities that still reference an entity,
+    /// either as their sponsor or as the target of a relationship
+    pub fn dependents(&self, entity: &Entity) -> Result<Vec<model::Uuid>> {
+        let uid = entity.uid();
+        let mut deps = BTreeSet::new();
+        for r in self.sponsorships.scan_prefix(format!("{}:", uid)) {
+            let (_, v) = r?;
+            deps.insert(str(&v));
+        }
+        for r in self.reverse_edges.scan_prefix(format!("{}:", uid)) {
+            let (_, v) = r?;
+            deps.insert(str(&v));
+        }
+        // the root sponsors itself
+        deps.remove(&uid);
+        Ok(deps
+            .iter()
+            .filter_map(|d| model::Uuid::parse_str(d).ok())
+            .collect())
+    }
+
+    /// Removes an entity together with all the data derived from it
+    ///
+    /// The entity events are unlinked and the events that involve only
+    /// the removed entity are deleted, the events shared with other
+    /// entities are kept as part of their history. It fails with
+    /// HasDependents if other entities still reference the entity
+    pub fn remove(&mut self, entity: &Entity) -> Result<model::Uuid> {
+        self.remove_entity(entity, None)
+    }
+
+    /// Removes an entity as remove does, with a report the entity is
+    /// purged in the same transaction and the report filled, see purge
+    fn remove_entity(
+        &mut self,
+        entity: &Entity,
+        mut purge: Option<&mut PurgeReport>,
+    ) -> Result<model::Uuid> {
+        self.authorize(AccessRole::Admin)?;
+        // always work on the stored entity, the keys depend on it
+        let entity = match self.get_by_uid(&entity.uid())? {
+            Some(e) => e,
+            None => return Err(DataError::NotFound),
+        };
+        self.authorize_role_change(entity.access_role(), None)?;
+        let deps = self.dependents(&entity)?;
+        if !deps.is_empty() {
+            return Err(DataError::HasDependents(deps));
+        }
+        let k: &str = &entity.uid();
+        // collect the keys to remove
+        let mut ids = Batch::default();
+        ids.remove(k);
+        for h in entity.all_handles().iter() {
+            let hk = handle_key(&h.label, &h.value);
+            if let Some(owner) = self.ids.get(&hk)? {
+                if str(&owner) == k {
+                    ids.remove(hk.as_str());
+                }
+            }
+        }
+        let mut tags = Batch::default();
+        for (_, t) in entity.tags.iter() {
+            tags.remove(tag_key(t, &entity).as_str());
+        }
+        let mut edges = Batch::default();
+        for r in self.edges.scan_prefix(format!("{}:", k)) {
+            edges.remove(r?.0);
+        }
+        let mut reverse_edges = Batch::default();
+        for rel in entity.relationships.iter() {
+            reverse_edges.remove(reverse_edge_key(&entity, rel).as_str());
+        }
+        let mut acl = Batch::default();
+        for a in entity.visibility.iter() {
+            acl.remove(format!("{}:{}", a, k).as_str());
+        }
+        if let Some(r) = entity.access_role() {
+            acl.remove(role_key(&r, k).as_str());
+        }
+        let mut entity_event = Batch::default();
+        let mut events = Batch::default();
+        let mut events_time = Batch::default();
+        // an event is linked once for each role of the entity
+        let mut removed = BTreeSet::new();
+        for r in self.entity_event.scan_prefix(format!("{}:", k)) {
+            let (ek, ev) = r?;
+            entity_event.remove(ek);
+            if let Some(raw) = self.events.get(&ev)? {
+                let evt: Event = bincode::deserialize(&raw).unwrap();
+                if evt.actors.iter().all(|a| a.uid() == entity.uid()) {
+                    removed.insert(ev.to_vec());
+                    events.remove(ev);
+                    events_time.remove(event_time_key(&evt).as_str());
+                }
+            }
+        }
+        let mut dates = Batch::default();
+        let today = utils::today();
+        for d in entity.important_dates().iter() {
+            if let Some(o) = d.next_occurrence(&today) {
+                dates.remove(date_key(&o, &entity, d).as_str());
+            }
+        }
+        let ak = action_key(&entity);
+        let sk = sponsor_key(&entity.uid, &entity.sponsor);
+        let rk = reset_key(k);
+        let mut audit = Batch::default();
+        match purge.as_mut() {
+            Some(report) => {
+                report.events_removed = removed.len();
+                self.scrub_events(&entity, &mut events, report)?;
+                self.scrub_audit(&entity, &mut audit, report)?;
+            }
+            None => {
+                self.audit_entry(&mut audit, &AuditEntry::removed(&entity, self.principal))?;
+            }
+        }
+        // the goals lose the entity
+        let mut goals = Batch::default();
+        for mut g in self.goals().into_iter() {
+            if g.remove_entity(&entity.uid) {
+                goals.insert(g.uid().as_bytes(), bincode::serialize(&g).unwrap());
+            }
+        }
+        // the tasks go with the entity, the ones assigned to it
+        // are left without an assignee
+        let (mut tasks, mut tasks_due) = (Batch::default(), Batch::default());
+        for t in self.iter_tasks() {
+            if t.entity == entity.uid {
+                tasks.remove(t.uid().as_bytes());
+                tasks_due.remove(task_due_key(&t).as_bytes());
+            } else if t.assignee == Some(entity.uid) {
+                let mut u = t.clone();
+                u.assignee = None;
+                tasks.insert(u.uid().as_bytes(), bincode::serialize(&u).unwrap());
+            }
+        }
+        // remove everything at once, there are more trees
+        // than a tuple can hold so they go in a slice
+        let trees = [
+            &self.entities,
+            &self.actions,
+            &self.ids,
+            &self.tags,
+            &self.edges,
+            &self.reverse_edges,
+            &self.acl,
+            &self.sponsorships,
+            &self.entity_event,
+            &self.events,
+            &self.system,
+            &self.audit,
+            &self.dates,
+            &self.events_time,
+            &self.goals,
+            &self.tasks,
+            &self.tasks_due,
+        ];
+        let r: TransactionResult<(), DataError> = trees[..].transaction(|t| {
+            let (te, ta, ti, tt, ted, tred) = (&t[0], &t[1], &t[2], &t[3], &t[4], &t[5]);
+            let (tacl, ts, tee, tev, tsys, tau) = (&t[6], &t[7], &t[8], &t[9], &t[10], &t[11]);
+            let (td, tet, tg, ttk, ttd) = (&t[12], &t[13], &t[14], &t[15], &t[16]);
+            te.remove(k)?;
+            ta.remove(ak.as_str())?;
+            ti.apply_batch(&ids)?;
+            tt.apply_batch(&tags)?;
+            ted.apply_batch(&edges)?;
+            tred.apply_batch(&reverse_edges)?;
+            tacl.apply_batch(&acl)?;
+            ts.remove(sk.as_str())?;
+            tee.apply_batch(&entity_event)?;
+            tev.apply_batch(&events)?;
+            tsys.remove(rk.as_str())?;
+            tau.apply_batch(&audit)?;
+            td.apply_batch(&dates)?;
+            tet.apply_batch(&events_time)?;
+            tg.apply_batch(&goals)?;
+            ttk.apply_batch(&tasks)?;
+            ttd.apply_batch(&tasks_due)?;
+            Ok(())
+        });
+        if r.is_err() {
+            return Err(DataError::TxError);
+        }
+        self.build_search_index();
+        self.notify(ChangeEvent::EntityRemoved(entity.uid));
+        Ok(entity.uid)
+    }
+
+    /// Irreversibly removes an entity and every trace of it, for
+    /// when someone asks for their data to be deleted.
+    ///
+    /// The entity is removed as with remove, then its uid is replaced
+    /// with the tombstone in the actors of the remaining events and in
+    /// the audit trail of the other entities, its own trail is deleted.
+    /// All of it happens in one transaction, then the attachments that
+    /// are no longer used are deleted.
+    /// The content of the events and the backups are not scrubbed
+    pub fn purge(&mut self, uid: &str) -> Result<PurgeReport> {
+        self.authorize(AccessRole::Admin)?;
+        let entity = match self.get_by_uid(uid)? {
+            Some(e) => e,
+            None => return Err(DataError::NotFound),
+        };
+        let mut report = PurgeReport {
+            entity: entity.uid,
+            ..PurgeReport::default()
+        };
+        self.remove_entity(&entity, Some(&mut report))?;
+        report.attachments_removed = self.prune_attachments()?;
+        Ok(report)
+    }
+
+    /// Add to a batch the events shared with a purged entity with a
+    /// tombstone in place of the entity, the events that involve only
+    /// the entity are left out since they are removed with it
+    fn scrub_events(
+        &self,
+        entity: &Entity,
+        events: &mut Batch,
+        report: &mut PurgeReport,
+    ) -> Result<()> {
+        let uid: &str = &entity.uid();
+        for r in self.events.iter() {
+            let (k, raw) = r?;
+            let mut evt: Event = bincode::deserialize(&raw).unwrap();
+            let involved = evt.actors.iter().filter(|a| a.uid() == uid).count();
+            if involved == 0 || involved == evt.actors.len() {
+                continue;
+            }
+            evt.actors = evt
+                .actors
+                .iter()
+                .map(|a| match a.uid() == uid {
+                    true => a.with_uid(model::TOMBSTONE),
+                    false => a.clone(),
+                })
+                .collect();
+            events.insert(k, bincode::serialize(&evt).unwrap());
+            report.events_scrubbed += 1;
+        }
+        Ok(())
+    }
+
+    /// Add to a batch the removal of the audit trail of a purged entity
+    /// and the entries of the others with a tombstone in its place
+    fn scrub_audit(
+        &self,
+        entity: &Entity,
+        audit: &mut Batch,
+        report: &mut PurgeReport,
+    ) -> Result<()> {
+        let uid: &str = &entity.uid();
+        let tombstone = utils::id(&model::TOMBSTONE);
+        for r in self.audit.iter() {
+            let (k, raw) = r?;
+            if str(&k).starts_with(&format!("{}:", uid)) {
+                audit.remove(k);
+                report.audit_removed += 1;
+                continue;
+            }
+            let mut entry: AuditEntry = bincode::deserialize(&raw).unwrap();
+            let by = entry.changed_by == Some(entity.uid);
+            let mentioned = entry
+                .changes
+                .iter()
+                .any(|(_, o, n)| o.contains(uid) || n.contains(uid));
+            let delegate = entry.on_behalf_of == Some(entity.uid);
+            if !by && !mentioned && !delegate {
+                continue;
+            }
+            if by {
+                entry.changed_by = Some(model::TOMBSTONE);
+            }
+            if delegate {
+                entry.on_behalf_of = Some(model::TOMBSTONE);
+            }
+            for (_, o, n) in entry.changes.iter_mut() {
+                *o = o.replace(uid, &tombstone);
+                *n = n.replace(uid, &tombstone);
+            }
+            audit.insert(k, bincode::serialize(&entry).unwrap());
+            report.audit_scrubbed += 1;
+        }
+        Ok(())
+    }
+
+    /// Scan the indexes for references to missing data: handles
+    /// registered to missing entities, event links of missing
+    /// entities or events and actions that do not match an entity.
+    ///
+    /// When repair is set the dangling keys are removed, this
+    /// requires the admin role
+    pub fn check(&mut self, repair: bool) -> Result<IntegrityReport> {
+        if repair {
+            self.authorize(AccessRole::Admin)?;
+        }
+        let mut report = IntegrityReport::default();
+        let (mut ids, mut entity_event, mut actions) =
+            (Batch::default(), Batch::default(), Batch::default());
+        for r in self.ids.iter() {
+            let (k, v) = r?;
+            report.checked += 1;
+            if !self.entities.contains_key(&v)? {
+                ids.remove(k.clone());
+                report
+                    .issues
+                    .push(Inconsistency::DanglingHandle(str(&k), str(&v)));
+            }
+        }
+        for r in self.entity_event.iter() {
+            let (k, v) = r?;
+            report.checked += 1;
+            // the key is actor_uid:ts:event_uid:role
+            let key = str(&k);
+            let actor = key.split(':').next().unwrap_or_default().to_owned();
+            let missing = match self.entities.contains_key(actor.as_bytes())? {
+                false => Some(actor),
+                true if !self.events.contains_key(&v)? => Some(str(&v)),
+                true => None,
+            };
+            if let Some(uid) = missing {
+                entity_event.remove(k);
+                report
+                    .issues
+                    .push(Inconsistency::DanglingEventLink(key, uid));
+            }
+        }
+        for r in self.actions.iter() {
+            let (k, v) = r?;
+            report.checked += 1;
+            let key = str(&k);
+            let valid = match self.get_by_uid(&str(&v))? {
+                Some(e) => !e.is_archived() && action_key(&e) == key,
+                None => false,
+            };
+            if !valid {
+                actions.remove(k);
+                report
+                    .issues
+                    .push(Inconsistency::OrphanedAction(key, str(&v)));
+            }
+        }
+        if repair && !report.is_healthy() {
+            let r: TransactionResult<(), DataError> =
+                (&self.ids, &self.entity_event, &self.actions).transaction(|(ti, tee, ta)| {
+                    ti.apply_batch(&ids)?;
+                    tee.apply_batch(&entity_event)?;
+                    ta.apply_batch(&actions)?;
+                    Ok(())
+                });
+            if r.is_err() {
+                return Err(DataError::TxError);
+            }
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+
+    /// Returns the statistics of the datastore, the actions
+    /// due before today are overdue
+    pub fn stats(&self) -> Result<Stats> {
+        let mut stats = Stats::default();
+        for e in self.iter_entities() {
+            let e = e?;
+            stats.entities += 1;
+            *stats.by_class.entry(e.class.clone()).or_insert(0) += 1;
+            let prefixes = e.tags.values().map(|t| t.prefix()).collect::<BTreeSet<_>>();
+            for p in prefixes {
+                *stats.by_tag_prefix.entry(p.to_owned()).or_insert(0) += 1;
+            }
+        }
+        for r in self.events.iter() {
+            let (_k, v) = r?;
+            let evt: Event = bincode::deserialize(&v).unwrap();
+            stats.events += 1;
+            let month = evt.recorded_at.format("%Y-%m").to_string();
+            *stats.events_by_month.entry(month).or_insert(0) += 1;
+        }
+        stats.overdue = self
+            .agenda_until(&utils::today().pred(), &AgendaFilter::default(), 1, 0)
+            .total;
+        for name in self.db.tree_names() {
+            let mut size = 0;
+            for r in self.db.open_tree(&name)?.iter() {
+                let (k, v) = r?;
+                size += k.len() + v.len();
+            }
+            stats.tree_sizes.insert(str(&name), size);
+        }
+        stats.disk_size = self.db.size_on_disk()?;
+        Ok(stats)
+    }
+
+    /// Add an entry to the audit trail batch, the entries of
+    /// an entity are sorted by time
+    fn audit_entry(&self, batch: &mut Batch, entry: &AuditEntry) -> Result<()> {
+        let k = audit_key(
+            &entry.entity,
+            entry.changed_at.timestamp_millis(),
+            self.db.generate_id()?,
+        );
+        // the changes made for someone else are recorded as such
+        let mut entry = entry.clone();
+        entry.on_behalf_of = self.delegate;
+        batch.insert(k.as_str(), bincode::serialize(&entry).unwrap());
+        Ok(())
+    }
+
+    /// Returns the audit trail of an entity, oldest first,
+    /// optionally only the changes made since a date (included).
+    ///
+    /// The trail is kept after the entity is removed
+    pub fn audit(&self, entity_uid: &str, since: Option<NaiveDate>) -> Vec<AuditEntry> {
+        let uid = match model::Uuid::parse_str(entity_uid) {
+            Ok(u) => u,
+            Err(_) => return Vec::new(),
+        };
+        let from = match since {
+            Some(d) => audit_key(&uid, utils::datetime_local(&d).timestamp_millis(), 0),
+            None => format!("{}:", utils::id(&uid)),
+        };
+        self.audit
+            .range(from..format!("{}:~", utils::id(&uid)))
+            .values()
+            .filter_map(|v| bincode::deserialize(&v.ok()?).ok())
+            .collect()
+    }
+
+    /// Apply the writes of a batch of entities in a single transaction
+    fn write(&self, batch: &EntityBatch) -> Result<()> {
+        let r: TransactionResult<(), DataError> = (
+            &self.entities,
+            &self.actions,
+            &self.ids,
+            &self.tags,
+            &self.edges,
+            &self.reverse_edges,
+            &self.acl,
+            &self.sponsorships,
+            &self.audit,
+            &self.dates,
+        )
+            .transaction(|(te, ta, ti, tt, ted, tred, tacl, ts, tau, td)| {
+                te.apply_batch(&batch.entities)?;
+                ta.apply_batch(&batch.actions)?;
+                ti.apply_batch(&batch.ids)?;
+                tt.apply_batch(&batch.tags)?;
+                ted.apply_batch(&batch.edges)?;
+                tred.apply_batch(&batch.reverse_edges)?;
+                tacl.apply_batch(&batch.acl)?;
+                ts.apply_batch(&batch.sponsorships)?;
+                tau.apply_batch(&batch.audit)?;
+                td.apply_batch(&batch.dates)?;
+                Ok(())
+            });
+        match r {
+            Ok(()) => Ok(()),
+            Err(_) => Err(DataError::TxError),
+        }
+    }
+
+    /// Returns the targets of the typed relationships an entity did
+    /// not have before, before and after adding the inverse
+    /// relationship. The missing targets and the ones that already
+    /// have the inverse relationship are left out
+    fn inverse_edges(
+        &self,
+        old: Option<&Entity>,
+        entity: &Entity,
+    ) -> Result<Vec<(Entity, Entity)>> {
+        let mut targets: Vec<(Entity, Entity)> = Vec::new();
+        for r in entity.relationships.iter() {
+            let inverse = match r.kind.inverse() {
+                Some(k) => k,
+                None => continue,
+            };
+            let existing = old.map_or(false, |o| {
+                o.relationships
+                    .iter()
+                    .any(|p| p.target == r.target && p.kind == r.kind)
+            });
+            if existing || r.target == entity.uid {
+                continue;
+            }
+            // the same target may be related more than once
+            let i = match targets.iter().position(|(_, t)| t.uid == r.target) {
+                Some(i) => i,
+                None => match self.get_by_uid(&utils::id(&r.target))? {
+                    Some(t) => {
+                        targets.push((t.clone(), t));
+                        targets.len() - 1
+                    }
+                    None => continue,
+                },
+            };
+            let target = &mut targets[i].1;
+            if target
+                .relationships
+                .iter()
+                .all(|p| p.target != entity.uid || p.kind.get_label() != inverse.get_label())
+            {
+                target.add_relation(&model::Rel::new(entity).with_kind(inverse));
+            }
+        }
+        // only the targets that have changed
+        targets.retain(|(before, after)| before.relationships.len() != after.relationships.len());
+        Ok(targets)
+    }
+
+    /// Insert a new entity and associated data
+    fn insert(&mut self, entity: &Entity) -> Result<model::Uuid> {
+        self.replace(None, entity)
+    }
+
+    /// Insert an entity and associated data in a single transaction,
+    /// the index entries of the old version that do not apply to
+    /// the new one are removed in the same transaction.
+    ///
+    /// The targets of the new typed relationships get the inverse
+    /// relationship in the same transaction, see RelType::inverse
+    fn replace(&mut self, old: Option<&Entity>, entity: &Entity) -> Result<model::Uuid> {
+        let mut batch = EntityBatch::default();
+        if let Some(old) = old {
+            batch.unindex(old, entity);
+        }
+        batch.insert(entity);
+        let entry = AuditEntry::new(old, entity, self.principal);
+        if entry.action == model::AuditAction::Added || !entry.changes.is_empty() {
+            self.audit_entry(&mut batch.audit, &entry)?;
+        }
+        let targets = self.inverse_edges(old, entity)?;
+        for (before, after) in targets.iter() {
+            batch.unindex(before, after);
+            batch.insert(after);
+            let entry = AuditEntry::new(Some(before), after, self.principal);
+            self.audit_entry(&mut batch.audit, &entry)?;
+        }
+        self.write(&batch)?;
+        for (_, after) in targets.iter() {
+            self.notify(ChangeEvent::EntityUpdated(after.uid));
+        }
+        // TODO this is extremely expensive and should be changed
+        self.build_search_index();
+        // done
+        Ok(entity.uid)
+    }
+
+    /// Returns the date of the last review of an entity,
+    /// or the last update date if it has never been reviewed
+    pub fn last_review(&self, subject: &Entity) -> NaiveDate {
+        match self
+            .events(subject, EventFilter::LogsWithMessage("review".to_string()))
+            .first()
+        {
+            None => subject.updated_on,
+            Some(evt) => evt.recorded_at.naive_local().date(),
+        }
+    }
+
+    /// Returns the date of the latest action an entity took part in,
+    /// the actions it only recorded do not count
+    pub fn last_contact(&self, subject: &Entity) -> Option<NaiveDate> {
+        self.events(subject, EventFilter::Actions)
+            .iter()
+            .find(|evt| Query::took_part(subject, evt))
+            .map(|evt| evt.recorded_at.naive_local().date())
+    }
+
+    /// Returns the statistics of the interactions an entity took
+    /// part in between two dates, the until date is excluded
+    pub fn interaction_stats(
+        &self,
+        subject: &Entity,
+        since: &NaiveDate,
+        until: &NaiveDate,
+    ) -> InteractionStats {
+        let mut stats = InteractionStats::default();
+        let found = self.events_within(
+            subject,
+            EventFilter::Interactions,
+            Some(*since),
+            Some(*until),
+        );
+        for evt in found.iter().filter(|evt| Query::took_part(subject, evt)) {
+            if let EventType::Interaction(channel, minutes, direction) = evt.kind {
+                stats.total += 1;
+                *stats.by_channel.entry(channel).or_insert(0) += 1;
+                match direction {
+                    InteractionDirection::Incoming => stats.incoming += 1,
+                    InteractionDirection::Outgoing => stats.outgoing += 1,
+                }
+                stats.minutes += minutes as u64;
+                let on = evt.recorded_at.naive_local().date();
+                stats.last = stats.last.max(Some(on));
+            }
+        }
+        let days = (*until - *since).num_days().max(1);
+        stats.per_month = stats.total as f64 * 30.0 / days as f64;
+        stats
+    }
+
+    /// Returns the entities that have not been reviewed
+    /// since a date, sorted by the last review date (oldest first)
+    pub fn review_queue(&self, since: &NaiveDate) -> Vec<Entity> {
+        let mut queue = self
+            .entities
+            .iter()
+            .map(|r| {
+                let (_, raw) = r.unwrap();
+                let e: Entity = bincode::deserialize(&raw).unwrap();
+                (self.last_review(&e), e)
+            })
+            .filter(|(d, _)| d < since)
+            .collect::<Vec<(NaiveDate, Entity)>>();
+        queue.sort_by(|(a, _), (b, _)| a.cmp(b));
+        queue.into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// Returns when an entity with a contact cadence shall be
+    /// contacted next, counting from the last contact or, if it
+    /// has never been contacted, from its creation
+    pub fn contact_due(&self, subject: &Entity) -> Option<NaiveDate> {
+        let every = subject.contact_every.as_ref()?;
+        let since = self.last_contact(subject).unwrap_or(subject.created_on);
+        Some(every.offset(&since))
+    }
+
+    /// Returns the number of days an entity has slipped past
+    /// its contact cadence at a date, zero if it has not
+    pub fn out_of_touch(&self, subject: &Entity, date: &NaiveDate) -> i64 {
+        match self.contact_due(subject) {
+            Some(due) if due < *date => (*date - due).num_days(),
+            _ => 0,
+        }
+    }
+
+    /// Keep the next actions in line with the contact cadences, the
+    /// entities are due to be contacted a cadence after the last
+    /// contact. An action planned before the due date is left alone,
+    /// a later one is brought forward as a CONTACT_NOTE action and
+    /// the CONTACT_NOTE actions follow the due date as it moves.
+    ///
+    /// Moving the actions forward is not recorded as a postponement.
+    /// Returns the uids of the entities changed
+    pub fn schedule_contacts(&mut self) -> Result<Vec<model::Uuid>> {
+        self.authorize(AccessRole::Editor)?;
+        let tracked = self
+            .entities
+            .iter()
+            .values()
+            .filter_map(|v| v.ok())
+            .map(|raw| bincode::deserialize::<Entity>(&raw).unwrap())
+            .filter(|e| e.contact_every.is_some() && !e.is_archived())
+            .collect::<Vec<Entity>>();
+        let mut changed = Vec::new();
+        for old in tracked.iter() {
+            let due = match self.contact_due(old) {
+                Some(d) => d,
+                None => continue,
+            };
+            let scheduled = old.next_action_note == CONTACT_NOTE;
+            if old.next_action_date == due || (!scheduled && old.next_action_date < due) {
+                continue;
+            }
+            let mut e = old.clone();
+            e.next_action(due, CONTACT_NOTE.to_string());
+            let uid = self.replace(Some(old), &e)?;
+            self.notify(ChangeEvent::EntityUpdated(uid));
+            changed.push(uid);
+        }
+        Ok(changed)
+    }
+
+    /// Normalize the stored handles, see Handle::normalize. The pass
+    /// runs once, the later calls are no-ops.
+    ///
+    /// The invalid handles are kept as they are, and so are all the
+    /// handles of an entity when one of them, once normalized, belongs
+    /// to another entity. Returns the handles left as they are
+    pub fn normalize_handles(&mut self) -> Result<Vec<(Entity, Handle)>> {
+        self.authorize(AccessRole::Admin)?;
+        if self.get_meta(META_HANDLES_NORMALIZED).is_some() {
+            return Ok(Vec::new());
+        }
+        let all = self
+            .entities
+            .iter()
+            .values()
+            .filter_map(|v| v.ok())
+            .map(|raw| bincode::deserialize::<Entity>(&raw).unwrap())
+            .collect::<Vec<Entity>>();
+        let mut left = Vec::new();
+        for old in all.iter() {
+            let mut e = old.clone();
+            let invalid = e.normalize_handles();
+            left.extend(invalid.into_iter().map(|h| (old.clone(), h)));
+            if e.all_handles() == old.all_handles() {
+                continue;
+            }
+            let mut taken = Vec::new();
+            for h in old.all_handles().into_iter() {
+                let v = match Handle::normalize(&h.label, &h.value) {
+                    Ok(v) if v != h.value => v,
+                    _ => continue,
+                };
+                if let Some(uid) = self.ids.get(handle_key(&h.label, &v))? {
+                    if str(&uid) != e.uid() {
+                        taken.push((old.clone(), h));
+                    }
+                }
+            }
+            if !taken.is_empty() {
+                left.extend(taken);
+                continue;
+            }
+            let uid = self.replace(Some(old), &e)?;
+            self.notify(ChangeEvent::EntityUpdated(uid));
+        }
+        self.set_meta(META_HANDLES_NORMALIZED, &utils::today().to_string())?;
+        Ok(left)
+    }
+
+    /// Records that an entity has been reviewed
+    pub fn mark_reviewed(&mut self, subject: &Entity) -> Result<model::Uuid> {
+        self.record(&Event::log("review", subject, None))
+    }
+
+    /// Quickly capture an entity in the inbox, to be processed later
+    ///
+    /// The optional note is recorded as an event about the captured entity
+    pub fn capture(
+        &mut self,
+        name: &str,
+        sponsor: &Entity,
+        note: Option<String>,
+    ) -> Result<model::Uuid> {
+        let entity = match Entity::from(name) {
+            Ok(e) => e.with_sponsor(sponsor).with_tag(inbox_tag()),
+            Err(e) => return Err(DataError::GenericError(e.to_string())),
+        };
+        let uid = self.add(&entity)?;
+        if note.is_some() {
+            self.record(&Event::action(
+                "inbox",
+                "capture",
+                1,
+                note,
+                &[
+                    model::Actor::RecordedBy(sponsor.uid),
+                    model::Actor::Subject(entity.uid),
+                ],
+            ))?;
+        }
+        Ok(uid)
+    }
+
+    /// Record a note without asking anything, the entities labeled
+    /// in the text as `[[name]]` or `[[role:name]]` take part in it.
+    ///
+    /// The unknown names are captured in the inbox, the names
+    /// matching more entities are left out and reported
+    pub fn record_note(
+        &mut self,
+        author: &Entity,
+        text: &str,
+        about: Option<&Entity>,
+    ) -> Result<NoteReport> {
+        self.authorize(AccessRole::Editor)?;
+        let mut report = NoteReport::default();
+        let mut evt = Event::action(
+            "cli",
+            "note",
+            1,
+            Some(text.to_owned()),
+            &[model::Actor::RecordedBy(author.uid)],
+        );
+        if let Some(s) = about {
+            evt.actors.push(model::Actor::Subject(s.uid));
+        }
+        for label in super::find_labels(text) {
+            let (role, name) = match utils::split_once(&label, ':') {
+                Some((r, n)) if model::ActorRole::from_code(r.trim()).is_some() => {
+                    (r.trim(), n.trim())
+                }
+                _ => ("star", label.trim()),
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let found = self.resolve(name);
+            let uid = match found.len() {
+                0 => {
+                    report.captured.push(name.to_owned());
+                    self.capture(name, author, None)?
+                }
+                1 => found[0].uid,
+                _ => {
+                    report.ambiguous.push(name.to_owned());
+                    continue;
+                }
+            };
+            if evt.actors.iter().all(|a| a.uid() != utils::id(&uid)) {
+                evt.actors
+                    .push(model::Actor::from(role, &uid.to_string()).unwrap());
+            }
+        }
+        report.event = self.record(&evt)?;
+        Ok(report)
+    }
+
+    /// Add in one shot what a line like `Lunch with [[Anna]] next tuesday #friend`
+    /// says: the line is recorded as a note with record_note, then the
+    /// entities labeled in it get the hashtags and, when the line has
+    /// a date, the next action on that date with the rest of the line
+    pub fn quick_add(&mut self, author: &Entity, line: &str) -> Result<QuickAddReport> {
+        let q = super::parse_quickadd(line, &self.settings().today());
+        let tags = q
+            .tags
+            .iter()
+            .map(|t| Tag::from_str(t).map_err(|e| DataError::GenericError(e.to_string())))
+            .collect::<Result<Vec<Tag>>>()?;
+        let note = self.record_note(author, line, None)?;
+        let evt = match self.get_event(&utils::id(&note.event))? {
+            Some(evt) => evt,
+            None => return Err(DataError::NotFound),
+        };
+        let mut entities = Vec::new();
+        for a in evt.actors.iter() {
+            if a.actor_role() == model::ActorRole::RecordedBy {
+                continue;
+            }
+            let mut e = match self.get_by_uid(&a.uid())? {
+                Some(e) => e,
+                None => continue,
+            };
+            tags.iter().for_each(|t| e.add_tag(t.clone()));
+            if let Some(d) = q.date {
+                e = e.with_next_action(d, q.text.to_owned());
+            }
+            self.update(&e)?;
+            entities.push(e);
+        }
+        Ok(QuickAddReport {
+            note,
+            entities,
+            date: q.date,
+        })
+    }
+
+    /// Returns the entities waiting in the inbox to be processed
+    pub fn inbox(&self) -> Vec<Entity> {
+        let t = inbox_tag();
+        self.tags
+            .scan_prefix(format!("{}:{}:", t.prefix(), t.slug()))
+            .map(|r| {
+                let (_, v) = r.unwrap();
+                let raw = self.entities.get(&v).unwrap().unwrap();
+                bincode::deserialize(&raw).unwrap()
+            })
+            .collect::<Vec<Entity>>()
+    }
+
+    /// Remove an entity from the inbox and store its changes
+    pub fn triaged(&mut self, entity: &Entity) -> Result<model::Uuid> {
+        let mut entity = entity.clone();
+        entity.remove_tag(&inbox_tag());
+        self.update(&entity)
+    }
+
+    pub fn sponsored_by(&self, sponsor: &Entity) -> Vec<Entity> {
+        self.sponsorships
+            .scan_prefix(&sponsor.uid())
+            .map(|r| {
+                let (_, v) = r.unwrap();
+                let raw = self.entities.get(&str(&v)).unwrap().unwrap();
+                let entity: Entity = bincode::deserialize(&raw).unwrap();
+                entity
+            })
+            .collect::<Vec<Entity>>()
+    }
+
+    /// Returns who introduced whom starting from an entity, the
+    /// entities past the max depth are counted but not loaded.
+    ///
+    /// Each entity appears once, so the self sponsored
+    /// owner does not sponsor itself again
+    pub fn sponsorship_tree(&self, root_uid: &str, max_depth: usize) -> Result<SponsorshipNode> {
+        let root = self.get_by_uid(root_uid)?.ok_or(DataError::NotFound)?;
+        let mut visited = HashSet::new();
+        visited.insert(root.uid());
+        self.sponsorship_branch(root, 0, max_depth, &mut visited)
+    }
+
+    fn sponsorship_branch(
+        &self,
+        entity: Entity,
+        depth: usize,
+        max_depth: usize,
+        visited: &mut HashSet<String>,
+    ) -> Result<SponsorshipNode> {
+        let mut node = SponsorshipNode {
+            entity,
+            depth,
+            descendants: 0,
+            sponsored: Vec::new(),
+        };
+        for uid in self.sponsored_uids(&node.entity.uid())? {
+            if !visited.insert(uid.clone()) {
+                continue;
+            }
+            if depth < max_depth {
+                let e = self.get_by_uid(&uid)?.ok_or(DataError::BrokenReference)?;
+                let child = self.sponsorship_branch(e, depth + 1, max_depth, visited)?;
+                node.descendants += 1 + child.descendants;
+                node.sponsored.push(child);
+            } else {
+                node.descendants += 1 + self.count_sponsored(&uid, visited)?;
+            }
+        }
+        Ok(node)
+    }
+
+    /// Returns the pairs of entities of the same class that may be
+    /// duplicates, the most likely first. The archived entities
+    /// are skipped and each entity is compared with all the others
+    pub fn find_duplicates(&self) -> Vec<Duplicate> {
+        let entities = self
+            .iter_entities()
+            .filter_map(|e| e.ok())
+            .filter(|e| !e.is_archived())
+            .collect::<Vec<Entity>>();
+        let mut found = Vec::new();
+        for (i, a) in entities.iter().enumerate() {
+            for b in entities[i + 1..].iter().filter(|b| b.class == a.class) {
+                let d = duplicate_score(a, b);
+                if d.score >= DUPLICATE_THRESHOLD {
+                    found.push(d);
+                }
+            }
+        }
+        found.sort_by(|x, y| y.score.partial_cmp(&x.score).unwrap_or(Ordering::Equal));
+        found
+    }
+
+    /// Count the entities introduced directly or indirectly
+    /// by an entity, only the sponsorship index is read
+    fn count_sponsored(&self, uid: &str, visited: &mut HashSet<String>) -> Result<usize> {
+        let mut count = 0;
+        for s in self.sponsored_uids(uid)? {
+            if visited.insert(s.clone()) {
+                count += 1 + self.count_sponsored(&s, visited)?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns the uids of the entities sponsored by an entity
+    fn sponsored_uids(&self, uid: &str) -> Result<Vec<String>> {
+        let mut uids = Vec::new();
+        for r in self.sponsorships.scan_prefix(format!("{}:", uid)) {
+            let (_, v) = r?;
+            uids.push(str(&v));
+        }
+        Ok(uids)
+    }
+
+    /// There are eight rules for propose edits, checked in order
+    ///
+    /// ### Rule #1 - an entity has been postponed too much (avoided)
+    ///
+    /// This happens when there are are more then 5 consecutive "postponed"
+    /// log events
+    ///
+    /// ### Rule #2 - an upcoming birthday without a planned action
+    ///
+    /// This happens when the birthday is within 2 weeks and the next
+    /// action is not scheduled between today and the birthday
+    ///
+    /// ### Rule #3 - a relationship that has been tense for a while
+    ///
+    /// This happens when the quality has been Tense for over a month
+    ///
+    /// ### Rule #4 - an entity that slipped past its contact cadence
+    ///
+    /// This happens when the entity has a contact cadence and it
+    /// has not been contacted within it, see contact_due
+    ///
+    /// ### Rule #5 - an entity that has not been updated in a while
+    ///
+    /// This happens if an entity has not had a log "reviewed" in the last
+    /// 3m, or otherwise not been updated in the last 3m
+    ///
+    /// ### Rule #6 - an entity that has never been contacted
+    ///
+    /// This happens when an entity has some handles but no action
+    /// has ever been recorded about it
+    ///
+    /// ### Rule #7 - an entity that may be a duplicate
+    ///
+    /// This happens when find_duplicates pairs it with another
+    /// entity, the hint points to the most likely one
+    ///
+    /// Rule #8 - an entity misses most of fields
+    ///
+    /// Every fields (except the name) have a weight, if the
+    /// weight is below threshold then the rules apply.
+    ///
+    /// An entity is reported only for a rule at a time, the
+    /// entities with a higher priority are reported first
+    ///
+    pub fn propose_edits(&self, principal: &Entity) -> Vec<(EditType, Entity)> {
+        self.hints(principal)
+            .into_iter()
+            .map(|h| (h.kind, h.entity))
+            .collect()
+    }
+
+    /// Same as propose_edits but with the metric that triggered each rule,
+    /// see Hint for the meaning of the metric
+    pub fn hints(&self, principal: &Entity) -> Vec<Hint> {
+        let mut to_edit: Vec<Hint> = Vec::new();
+
+        // this is how much an item can be postponed in a row
+        let avoidance_limit = 5;
+        // how many days before a birthday to plan something
+        let birthday_notice = 14;
+        // how many days a relationship can stay tense
+        let tension_limit = 30;
+        let today = utils::today();
+        let hint = |kind: EditType, e: &Entity, metric: i64| Hint {
+            kind,
+            entity: e.to_owned(),
+            metric,
+        };
+        // the most likely duplicate of each entity, best first
+        let mut duplicates: HashMap<model::Uuid, (model::Uuid, f64)> = HashMap::new();
+        for d in self.find_duplicates() {
+            duplicates.entry(d.a.uid).or_insert((d.b.uid, d.score));
+            duplicates.entry(d.b.uid).or_insert((d.a.uid, d.score));
+        }
+
+        for e in self.sponsored_by(principal).iter() {
+            // the archived entities are not tracked anymore
+            if e.is_archived() {
+                continue;
+            }
+            // Rule#1
+            let postponed = self.postponed_count(e);
+            if postponed >= avoidance_limit {
+                to_edit.push(hint(EditType::Avoided, e, postponed as i64));
+                continue;
+            }
+            // Rule#2
+            if let Some(bday) = e.next_birthday(&today) {
+                let planned = e.next_action_date >= today && e.next_action_date <= bday;
+                if bday <= utils::today_plus(birthday_notice) && !planned {
+                    let days = (bday - today).num_days();
+                    to_edit.push(hint(EditType::UpcomingBirthday(bday), e, days));
+                    continue;
+                }
+            }
+            // Rule#3
+            if let Some(since) = e.tense_since() {
+                if since < utils::today_plus(-tension_limit) {
+                    let days = (today - since).num_days();
+                    to_edit.push(hint(EditType::LongTension(since), e, days));
+                    continue;
+                }
+            }
+            // Rule#4
+            let slipped = self.out_of_touch(e, &today);
+            if slipped > 0 {
+                let due = today - Duration::days(slipped);
+                to_edit.push(hint(EditType::OutOfTouch(due), e, slipped));
+                continue;
+            }
+            // Rule#5
+            let last_review = self.last_review(e);
+            if last_review < utils::today_plus(-180) {
+                let days = (today - last_review).num_days();
+                to_edit.push(hint(EditType::MaybeStale, e, days));
+                continue;
+            }
+            // Rule#6
+            if !e.handles.is_empty() && self.events(e, EventFilter::Actions).is_empty() {
+                let handles = e.handles.len() as i64;
+                to_edit.push(hint(EditType::NeverContacted, e, handles));
+                continue;
+            }
+            // Rule#7
+            if let Some((other, score)) = duplicates.get(&e.uid) {
+                let percent = (score * 100.0).round() as i64;
+                to_edit.push(hint(EditType::PossibleDuplicate(*other), e, percent));
+                continue;
+            }
+            // Rule#8
+            let score = self.score(e).score();
+            if score < 9 {
+                to_edit.push(hint(EditType::MaybeIncomplete, e, score));
+            }
+        }
+        // the sort is stable, so the order of the rules is kept
+        to_edit.sort_by(|a, b| b.entity.priority.cmp(&a.entity.priority));
+        to_edit
+    }
+}
+
+/// How urgent a hint is
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// A suggestion about an entity, the metric depends on the rule:
+/// - avoided: how many times in a row the action has been postponed
+/// - upcoming_birthday: the days until the birthday
+/// - long_tension: the days the relationship has been tense
+/// - maybe_stale: the days since the last review
+/// - never_contacted: the number of handles
+/// - out_of_touch: the days past the contact cadence
+/// - possible_duplicate: the duplicate score in percent
+/// - maybe_incomplete: the completeness score, from 0 to 15
+#[derive(Debug)]
+pub struct Hint {
+    pub kind: EditType,
+    pub entity: Entity,
+    pub metric: i64,
+}
+
+impl Hint {
+    /// Returns the name of the rule that produced the hint
+    pub fn rule(&self) -> &'static str {
+        match self.kind {
+            EditType::Avoided => "avoided",
+            EditType::UpcomingBirthday(_) => "upcoming_birthday",
+            EditType::LongTension(_) => "long_tension",
+            EditType::MaybeStale => "maybe_stale",
+            EditType::NeverContacted => "never_contacted",
+            EditType::OutOfTouch(_) => "out_of_touch",
+            EditType::PossibleDuplicate(_) => "possible_duplicate",
+            EditType::MaybeIncomplete => "maybe_incomplete",
+        }
+    }
+
+    /// Returns what to do about the hint
+    pub fn suggestion(&self) -> String {
+        match self.kind {
+            EditType::Avoided => "stop postponing, do it or drop it".to_string(),
+            EditType::UpcomingBirthday(d) => format!("plan something for the {}", d),
+            EditType::LongTension(_) => "talk it through or reassess the relationship".to_string(),
+            EditType::MaybeStale => "review it".to_string(),
+            EditType::NeverContacted => "get in touch".to_string(),
+            EditType::OutOfTouch(d) => format!("get in touch, it was due on {}", d),
+            EditType::PossibleDuplicate(uid) => format!("check if it is the same as {}", uid),
+            EditType::MaybeIncomplete => "add the missing details".to_string(),
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            EditType::Avoided => Severity::Critical,
+            EditType::UpcomingBirthday(_) if self.metric <= 3 => Severity::Warning,
+            EditType::LongTension(_) if self.metric > 90 => Severity::Critical,
+            EditType::LongTension(_) => Severity::Warning,
+            EditType::PossibleDuplicate(_) => Severity::Warning,
+            EditType::OutOfTouch(_) if self.metric > 30 => Severity::Warning,
+            _ => Severity::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditType {
+    MaybeStale,
+    MaybeIncomplete,
+    Avoided,
+    UpcomingBirthday(NaiveDate),
+    LongTension(NaiveDate),
+    NeverContacted,
+    /// the date the entity was due to be contacted
+    OutOfTouch(NaiveDate),
+    /// the entity it may be a duplicate of
+    PossibleDuplicate(model::Uuid),
+}
+
+/// Two entities that may be the same one, see DataStore::find_duplicates
+#[derive(Debug, Clone)]
+pub struct Duplicate {
+    pub a: Entity,
+    pub b: Entity,
+    /// from 0 to 1, the candidates score at least DUPLICATE_THRESHOLD
+    pub score: f64,
+    pub name_similarity: f64,
+    pub shared_handles: Vec<String>,
+    pub shared_tags: Vec<String>,
+}
+
+/// The handle types holding a nickname, see DataStore::whois
+const NICK_HANDLES: [&str; 5] = ["telegram", "twitter", "github", "instagram", "mastodon"];
+
+/// The note of the next actions planned by DataStore::schedule_contacts
+pub const CONTACT_NOTE: &str = "stay in touch";
+
+/// The score above which two entities may be duplicates
+pub const DUPLICATE_THRESHOLD: f64 = 0.9;
+
+/// How far from a city, in km, an entity is still near it
+pub const NEAR_KM: f64 = 50.0;
+
+/// Normalize a handle value to compare it across labels,
+/// eg. +39 123 456 and +39123456 are the same phone
+fn normalize_handle(h: &Handle) -> String {
+    match Handle::normalize(&h.label, &h.value) {
+        Ok(v) => v,
+        Err(_) => h
+            .value
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-' && *c != '(' && *c != ')')
+            .collect::<String>()
+            .to_lowercase(),
+    }
+}
+
+/// Scores how likely two entities are the same one
+///
+/// The score is the similarity of the names raised by the
+/// handles (with any label) and by the tags they share
+fn duplicate_score(a: &Entity, b: &Entity) -> Duplicate {
+    let name_similarity = strsim::jaro_winkler(&a.name().to_lowercase(), &b.name().to_lowercase());
+    let handles = b
+        .all_handles()
+        .iter()
+        .map(|h| normalize_handle(h))
+        .collect::<HashSet<String>>();
+    let mut shared_handles = a
+        .all_handles()
+        .into_iter()
+        .filter(|h| handles.contains(&normalize_handle(h)))
+        .map(|h| h.value)
+        .collect::<Vec<String>>();
+    shared_handles.sort();
+    let mut shared_tags = a
+        .tags
+        .values()
+        .filter(|t| b.tags.values().any(|bt| bt == *t))
+        .map(|t| t.to_string_full())
+        .collect::<Vec<String>>();
+    shared_tags.sort();
+    let all_tags = a.tags.len() + b.tags.len() - shared_tags.len();
+    let tags = match all_tags {
+        0 => 0.0,
+        n => shared_tags.len() as f64 / n as f64,
+    };
+    let handles = match shared_handles.is_empty() {
+        true => 0.0,
+        false => 0.5,
+    };
+    Duplicate {
+        a: a.clone(),
+        b: b.clone(),
+        score: (name_similarity + handles + 0.1 * tags).min(1.0),
+        name_similarity,
+        shared_handles,
+        shared_tags,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::model::*;
+    use super::utils::*;
+    use super::*;
+    use chrono::Datelike;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_csv_entities() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.csv");
+        let mut orig = DataStore::open(&d.path().join("orig")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let mut alice = Entity::from("alice")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_class("person")
+            .with_handle("email", "alice@acme.com")
+            .with_tag(Tag::Generic("rust".to_owned()));
+        alice.next_action(utils::date(1, 1, 2021), "call, then \"meet\"".to_owned());
+        orig.insert(&bob).unwrap();
+        orig.insert(&alice).unwrap();
+        orig.export(&p, ExportFormat::Csv).unwrap();
+        let raw = std::fs::read_to_string(&p).unwrap();
+        assert_eq!(
+            raw.lines().next().unwrap(),
+            "uid,name,class,handles,tags,next_action_date,note"
+        );
+        // round trip
+        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
+        copy.insert(&bob).unwrap();
+        let r = copy
+            .import_csv(&p, &formats::CsvMapping::default(), &bob)
+            .unwrap();
+        // bob is unchanged
+        assert_eq!(r.imported, 1);
+        assert_eq!(r.skipped.len(), 0);
+        let imported = copy.get_by_uid(&alice.uid()).unwrap().unwrap();
+        assert_eq!(imported.diff(&alice).is_empty(), true);
+        assert_eq!(imported.sponsor, bob.uid);
+        assert_eq!(
+            copy.get_by_id("email", "alice@acme.com").unwrap(),
+            Some(alice.clone())
+        );
+        // a spreadsheet with its own columns
+        let p = d.path().join("contacts.csv");
+        std::fs::write(
+            &p,
+            "Name,Mail,Next\nalice,alice@acme.org,02.02.2021\ncarl,alice@acme.com,\n,x@acme.com,\n",
+        )
+        .unwrap();
+        let m = formats::CsvMapping::parse("name=Name,handle.email=Mail,next_action_date=Next")
+            .unwrap();
+        // the plan matches the import and writes nothing
+        let plan = copy.plan_rows(&p, ExportFormat::Csv, &m, &bob).unwrap();
+        assert_eq!(plan.new.len(), 2);
+        assert_eq!(plan.updated.len(), 0);
+        assert_eq!(plan.skipped.len(), 2);
+        assert_eq!(
+            plan.skipped[0],
+            (
+                3,
+                "carl: handle email:alice@acme.com already used by alice".to_owned()
+            )
+        );
+        assert_eq!(copy.entities.len(), 2);
+        assert_eq!(
+            copy.plan_rows(&p, ExportFormat::Json, &m, &bob).is_err(),
+            true
+        );
+        let r = copy.import_csv(&p, &m, &bob).unwrap();
+        // without a uid the rows are new entities
+        assert_eq!(r.imported, 2);
+        assert_eq!(r.skipped.len(), 2);
+        assert_eq!(r.skipped[0].0, 3);
+        assert_eq!(r.skipped[1], (4, "missing name".to_owned()));
+        assert_eq!(copy.entities.len(), 4);
+        assert_eq!(copy.resolve("carl")[0].handles.len(), 0);
+    }
+
+    #[test]
+    fn test_graph_export() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("graph.dot");
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let alice = Entity::from("alice")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_relation(&Rel::new(&bob));
+        ds.insert(&bob).unwrap();
+        ds.insert(&alice).unwrap();
+        ds.export(&p, ExportFormat::Dot).unwrap();
+        let raw = std::fs::read_to_string(&p).unwrap();
+        assert_eq!(raw.starts_with(formats::DOT_BEGIN), true);
+        assert_eq!(raw.ends_with(formats::DOT_END), true);
+        assert_eq!(raw.matches("[label=").count(), 3);
+        assert_eq!(raw.matches(" -> ").count(), 2);
+        // graphml, with the last contact of alice
+        let call = Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(alice.uid)],
+        );
+        ds.record(&call).unwrap();
+        let contacted = call.recorded_at.naive_local().date();
+        assert_eq!(ds.last_contact(&alice), Some(contacted));
+        assert_eq!(ds.last_contact(&bob), None);
+        ds.export(&p, ExportFormat::GraphMl).unwrap();
+        let raw = std::fs::read_to_string(&p).unwrap();
+        assert_eq!(raw.starts_with(formats::GRAPHML_BEGIN), true);
+        assert_eq!(raw.ends_with(formats::GRAPHML_END), true);
+        assert_eq!(raw.matches("<node ").count(), 2);
+        assert_eq!(raw.matches("<edge ").count(), 2);
+        assert_eq!(raw.matches("key=\"last_contact\">").count(), 1);
+        assert_eq!(raw.contains(&contacted.to_string()), true);
+    }
+
+    #[test]
+    fn test_vcard_entities() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("contacts.vcf");
+        let mut orig = DataStore::open(&d.path().join("orig")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let mut alice = Entity::from("alice")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "alice@acme.com")
+            .with_handle("url", "https://acme.com");
+        alice.description = "the ceo".to_owned();
+        orig.insert(&bob).unwrap();
+        orig.insert(&alice).unwrap();
+        orig.export(&p, ExportFormat::VCard).unwrap();
+        let raw = std::fs::read_to_string(&p).unwrap();
+        assert_eq!(raw.matches("BEGIN:VCARD").count(), 2);
+        // round trip
+        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
+        copy.insert(&bob).unwrap();
+        let r = copy.import_vcard(&p, &bob).unwrap();
+        // bob is unchanged
+        assert_eq!(r.imported, 1);
+        assert_eq!(r.skipped.len(), 0);
+        let imported = copy.get_by_uid(&alice.uid()).unwrap().unwrap();
+        assert_eq!(imported.diff(&alice).is_empty(), true);
+        assert_eq!(imported.sponsor, bob.uid);
+        // new contacts, one with a handle already taken
+        std::fs::write(
+            &p,
+            "BEGIN:VCARD\nFN:carl\nEMAIL:alice@acme.com\nTEL:+41 555\nEND:VCARD\nBEGIN:VCARD\nEMAIL:x@acme.com\nEND:VCARD\n",
+        )
+        .unwrap();
+        let r = copy.import_vcard(&p, &bob).unwrap();
+        assert_eq!(r.imported, 1);
+        assert_eq!(r.skipped.len(), 2);
+        assert_eq!(r.skipped[0].0, 1);
+        assert_eq!(r.skipped[1], (6, "missing name".to_owned()));
+        let carl = copy.resolve("carl").pop().unwrap();
+        assert_eq!(carl.sponsor, bob.uid);
+        assert_eq!(carl.handles.get("mobile"), Some(&"+41555".to_owned()));
+        assert_eq!(carl.handles.get("email"), None);
+    }
+
+    #[test]
+    fn test_merge_import() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        let mut orig = DataStore::open(&d.path().join("orig")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let mut alice = Entity::from("alice")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "alice@acme.com");
+        orig.insert(&bob).unwrap();
+        orig.insert(&alice).unwrap();
+        orig.export(&p, ExportFormat::Json).unwrap();
+        // carl already uses the alice email
+        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
+        let carl = Entity::from("carl")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "alice@acme.com");
+        copy.insert(&bob).unwrap();
+        copy.insert(&carl).unwrap();
+        let r = copy
+            .import_as(&p, ExportFormat::Json, None, ImportMode::Merge)
+            .unwrap();
+        // bob is unchanged
+        assert_eq!(r.imported, 1);
+        assert_eq!(r.skipped.len(), 1);
+        assert_eq!(r.skipped[0].1.contains("email:alice@acme.com"), true);
+        assert_eq!(r.skipped[0].1.contains("carl"), true);
+        assert_eq!(copy.entities.len(), 3);
+        let merged = copy.get_by_uid(&alice.uid()).unwrap().unwrap();
+        assert_eq!(merged.handles.len(), 0);
+        assert_eq!(
+            copy.get_by_id("email", "alice@acme.com").unwrap(),
+            Some(carl.clone())
+        );
+        // updates are applied by uid
+        alice.description = "the ceo".to_owned();
+        alice.next_action(utils::date(1, 1, 2021), "call".to_owned());
+        orig.update(&alice).unwrap();
+        orig.record(&Event::log("touched", &alice, None)).unwrap();
+        orig.export(&p, ExportFormat::FullJson).unwrap();
+        let r = copy
+            .import_as(&p, ExportFormat::FullJson, None, ImportMode::Merge)
+            .unwrap();
+        assert_eq!(r.imported, 1);
+        let merged = copy.get_by_uid(&alice.uid()).unwrap().unwrap();
+        assert_eq!(merged.description, "the ceo");
+        assert_eq!(copy.entities.len(), 3);
+        assert_eq!(copy.actions.len(), 3);
+        assert_eq!(
+            copy.agenda_until(&utils::date(1, 1, 2021), &AgendaFilter::default(), 0, 0)
+                .items,
+            vec![alice.clone()]
+        );
+        // the events are merged once
+        let events = orig.events(&alice, EventFilter::Any).len();
+        assert_eq!(copy.events(&alice, EventFilter::Any).len(), events);
+        copy.import_as(&p, ExportFormat::FullJson, None, ImportMode::Merge)
+            .unwrap();
+        assert_eq!(copy.events(&alice, EventFilter::Any).len(), events);
+        // replacing drops carl
+        let r = copy
+            .import_as(&p, ExportFormat::FullJson, None, ImportMode::Replace)
+            .unwrap();
+        assert_eq!(r.imported, 2);
+        assert_eq!(copy.get_by_uid(&carl.uid()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_export_encrypted() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        ds.insert(&Entity::from("alice").unwrap().with_sponsor(&bob))
+            .unwrap();
+        ds.export_encrypted(&p, ExportFormat::FullJson, None, None, "secret")
+            .unwrap();
+        assert_eq!(formats::is_encrypted(&p).unwrap(), true);
+        assert_eq!(std::fs::read_dir(d.path()).unwrap().count(), 2);
+        // the import reads the decrypted export from memory
+        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
+        let decrypted = formats::decrypt_file(&p, "secret").unwrap();
+        let plan = copy
+            .plan_import(&decrypted, ExportFormat::FullJson, None)
+            .unwrap();
+        assert_eq!(plan.new.len(), 2);
+        let r = copy
+            .import_as(&decrypted, ExportFormat::FullJson, None, ImportMode::Merge)
+            .unwrap();
+        assert_eq!(r.imported, 2);
+        assert_eq!(copy.search("alice").len(), 1);
+    }
+
+    #[test]
+    fn test_export_since() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("delta.json");
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let since = utils::today();
+        let mut bob = Entity::from("bob").unwrap().self_sponsored();
+        bob.updated_on = date(1, 1, 2020);
+        let alice = Entity::from("alice").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&alice).unwrap();
+        let mut old = Event::log("old", &bob, None);
+        old.recorded_at = utils::datetime_local(&date(1, 1, 2020));
+        ds.record(&old).unwrap();
+        let new = Event::log("new", &alice, None);
+        ds.record(&new).unwrap();
+        // only alice has been updated
+        ds.export_since(&p, since, ExportFormat::Json).unwrap();
+        let check = formats::read_records::<Entity, _>(&p, None).unwrap();
+        assert_eq!(check.is_valid(), true);
+        let names = check
+            .records
+            .iter()
+            .map(|(_, e)| e.name().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["alice"]);
+        // a full delta would replace the datastore on import
+        assert_eq!(
+            ds.export_since(&p, since, ExportFormat::FullJson).is_err(),
+            true
+        );
+        // the whole datastore
+        ds.export_since(&p, date(1, 1, 2019), ExportFormat::Json)
+            .unwrap();
+        assert_eq!(
+            formats::read_records::<Entity, _>(&p, None)
+                .unwrap()
+                .records
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_full_export() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        let mut orig = DataStore::open(&d.path().join("orig")).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_handle("email", "bob@acme.com");
+        let alice = Entity::from("alice")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_tag(Tag::Generic("rust".to_owned()))
+            .with_relation(&Rel::new(&bob));
+        orig.insert(&bob).unwrap();
+        orig.insert(&alice).unwrap();
+        orig.grant(&alice, Some(AccessRole::Editor)).unwrap();
+        orig.record(&Event::log("touched", &alice, None)).unwrap();
+        orig.record(&Event::action(
+            "cli",
+            "call",
+            1,
+            Some("about \"rust\"".to_owned()),
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(alice.uid)],
+        ))
+        .unwrap();
+        orig.set_agenda_buckets(&[AgendaBucket::new("Soon", TimeWindow::Week(1))])
+            .unwrap();
+        orig.export(&p, ExportFormat::FullJson).unwrap();
+        // the plan only looks at the entities
+        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
+        let plan = copy.plan_import(&p, ExportFormat::FullJson, None).unwrap();
+        assert_eq!(plan.new.len(), 2);
+        // restore
+        assert_eq!(
+            copy.import_with(&p, ExportFormat::FullJson, None, |_| Resolution::Skip),
+            Ok(2)
+        );
+        // the tags are a hash map, so the entities are compared field by field
+        assert_eq!(orig.entities.len(), copy.entities.len());
+        for r in orig.entities.iter() {
+            let (k, v) = r.unwrap();
+            let a: Entity = bincode::deserialize(&v).unwrap();
+            let b: Entity = bincode::deserialize(&copy.entities.get(k).unwrap().unwrap()).unwrap();
+            assert_eq!(a.diff(&b).is_empty(), true);
+        }
+        let trees = |ds: &DataStore| {
+            vec![
+                ds.actions.clone(),
+                ds.ids.clone(),
+                ds.tags.clone(),
+                ds.edges.clone(),
+                ds.reverse_edges.clone(),
+                ds.acl.clone(),
+                ds.sponsorships.clone(),
+                ds.events.clone(),
+                ds.entity_event.clone(),
+            ]
+        };
+        for (a, b) in trees(&orig).iter().zip(trees(&copy).iter()) {
+            assert_eq!(a.len(), b.len());
+            for (x, y) in a.iter().zip(b.iter()) {
+                assert_eq!(x.unwrap(), y.unwrap());
+            }
+        }
+        assert_eq!(copy.events(&alice, EventFilter::Any).len(), 3);
+        assert_eq!(copy.role_of(&alice), AccessRole::Editor);
+        assert_eq!(copy.agenda_buckets(), orig.agenda_buckets());
+        // a plain json import cannot read a full export
+        assert_eq!(copy.import(&p, ExportFormat::Json).is_err(), true);
+        // tampering is detected
+        let data = std::fs::read_to_string(&p)
+            .unwrap()
+            .replace("alice", "alica");
+        std::fs::write(&p, data).unwrap();
+        match copy.import(&p, ExportFormat::FullJson).err().unwrap() {
+            DataError::CorruptedData(lines) => assert_eq!(lines.is_empty(), false),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(copy.events.len(), orig.events.len());
+    }
+
+    #[test]
+    fn test_import_export() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        // create a datastore
+        let mut orig = DataStore::open(&d.path().join("orig")).unwrap();
+        // insert records
+        let e = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_handle("email", "bob@acme.com");
+        assert_eq!(orig.insert(&e).is_ok(), true);
+        let e = Entity::from("alice")
+            .unwrap()
+            .with_sponsor(&e)
+            .with_handle("email", "alice@acme.com");
+        assert_eq!(orig.insert(&e).is_ok(), true);
+        // now export
+        assert_eq!(orig.export(&p, ExportFormat::Json).is_ok(), true);
+        // create a new datastore
+        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
+        // import
+        assert_eq!(copy.import(&p, ExportFormat::Json).is_ok(), true);
+        // test
+        assert_eq!(orig.entities.len(), copy.entities.len());
+        for r in orig.entities.iter() {
+            let (k, v) = r.unwrap();
+            assert_eq!(copy.entities.get(k).unwrap().unwrap(), v);
+        }
+        // signed export
+        let key = formats::export_key("secret");
+        assert_eq!(
+            orig.export_signed(&p, ExportFormat::Json, Some(&key))
+                .is_ok(),
+            true
+        );
+        assert_eq!(
+            copy.import_verified(&p, ExportFormat::Json, Some(&key))
+                .is_ok(),
+            true
+        );
+        // a corrupted export is not imported
+        let raw = std::fs::read_to_string(&p).unwrap();
+        std::fs::write(&p, raw.replace("acme.com", "acme.org")).unwrap();
+        match copy.import(&p, ExportFormat::Json).err().unwrap() {
+            DataError::CorruptedData(lines) => {
+                let lines = lines.iter().map(|(n, _)| *n).collect::<Vec<usize>>();
+                assert_eq!(lines, vec![1, 2, 3]);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert_eq!(orig.entities.len(), copy.entities.len());
+        // plan an import on a datastore that has changed
+        orig.export(&p, ExportFormat::Json).unwrap();
+        let bob = copy.get_by_id("email", "bob@acme.com").unwrap().unwrap();
+        let bob = bob.with_next_action(date(1, 1, 2021), "call".to_string());
+        copy.update(&bob).unwrap();
+        let carl = Entity::from("carl").unwrap().with_sponsor(&bob);
+        copy.insert(&carl).unwrap();
+        let plan = copy.plan_import(&p, ExportFormat::Json, None).unwrap();
+        assert_eq!(plan.new.len(), 0);
+        assert_eq!(plan.unchanged, 1);
+        assert_eq!(plan.updated.len(), 1);
+        assert_eq!(plan.updated[0].0.next_action_note, "call");
+        assert_eq!(plan.removed.len(), 1);
+        assert_eq!(plan.removed[0].name(), "carl");
+        assert_eq!(plan.conflicts.len(), 0);
+        // nothing has been written
+        assert_eq!(copy.entities.len(), 3);
+    }
+
+    #[test]
+    fn test_import_events() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        assert_eq!(ds.init(&bob).is_ok(), true);
+        let alice = Entity::from("Alice")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "alice@acme.com");
+        assert_eq!(ds.add(&alice).is_ok(), true);
+        let alice_2 = Entity::from("Alice").unwrap().with_sponsor(&bob);
+        assert_eq!(ds.add(&alice_2).is_ok(), true);
+        // resolve
+        assert_eq!(ds.resolve("email:alice@acme.com").len(), 1);
+        assert_eq!(ds.resolve("alice").len(), 2);
+        assert_eq!(ds.resolve("nobody").len(), 0);
+        // import
+        let p = d.path().join("events.csv");
+        std::fs::write(
+            &p,
+            "date,kind,entity,content\n\
+            2020-10-01,call,email:alice@acme.com,about the offer\n\
+            2020-10-02,meeting,Alice,lunch\n\
+            2020-10-03,meeting,nobody,\n\
+            not a date,meeting,Alice,\n",
+        )
+        .unwrap();
+        let mut asked = 0;
+        let r = ds
+            .import_events(&p, ExportFormat::Csv, &bob, |_, c| {
+                asked += 1;
+                assert_eq!(c.len(), 2);
+                Some(alice_2.clone())
+            })
+            .unwrap();
+        assert_eq!(asked, 1);
+        assert_eq!(r.imported, 2);
+        assert_eq!(r.skipped.len(), 2);
+        assert_eq!(r.skipped[0].0, 4);
+        // the events are attached with their date
+        let events = ds.events(&alice, EventFilter::ActionWithSource("import".to_owned()));
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].recorded_at.naive_local().date(),
+            date(1, 10, 2020)
+        );
+        assert_eq!(ds.events(&alice_2, EventFilter::Actions).len(), 1);
+    }
+
+    #[test]
+    fn test_export_ics() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.ics");
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        assert_eq!(ds.init(&bob).is_ok(), true);
+        let alice = Entity::from("alice").unwrap().with_sponsor(&bob);
+        assert_eq!(ds.add(&alice).is_ok(), true);
+        // a past and a future action
+        let actors = [Actor::RecordedBy(bob.uid), Actor::Starring(alice.uid)];
+        let mut evt = Event::action("cli", "meeting", 1, None, &actors);
+        evt.recorded_at = datetime_local(&date(1, 10, 2020));
+        ds.record(&evt).unwrap();
+        let mut evt = Event::action("cli", "call", 1, None, &actors);
+        evt.recorded_at = datetime_local(&today_plus(10));
+        ds.record(&evt).unwrap();
+        // export
+        assert_eq!(ds.export(&p, ExportFormat::Ics).is_ok(), true);
+        let ics = std::fs::read_to_string(&p).unwrap();
+        assert_eq!(ics.starts_with(formats::ICS_BEGIN), true);
+        assert_eq!(ics.ends_with(formats::ICS_END), true);
+        // logs and future events are not exported
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert_eq!(ics.contains("SUMMARY:meeting with alice"), true);
+        // cannot be imported
+        assert_eq!(
+            ds.import(&p, ExportFormat::Ics).err(),
+            Some(DataError::NotImplemented)
+        );
+    }
+
+    #[test]
+    fn test_datastore() {
+        let d = TempDir::new().unwrap();
+        println!("dir is {:?}", d);
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        // reopen should not be possible
+        assert_eq!(DataStore::open(d.path()).is_err(), true);
+        // insert a records
+        let bob = Entity::from("bob").unwrap();
+        ds.insert(&bob).unwrap();
+        assert_eq!(ds.entities.len(), 1);
+        // fetch it back
+        let bob_1 = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
+        assert_eq!(bob_1.sponsor, bob.sponsor);
+        let bob_1 = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
+        assert_eq!(bob_1.sponsor, bob.sponsor);
+        // add a custom id
+        ds.insert(&bob).unwrap();
+        // the db size should be the same
+        assert_eq!(ds.entities.len(), 1);
+    }
+
+    #[test]
+    fn test_search() {
+        let d = TempDir::new().unwrap();
+        println!("dir is {:?}", d);
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        // insert a records
+        let bob = Entity::from("Bob Marley")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(Tag::from("skill", "singing"))
+            .with_tag(Tag::from("group", "The Wailers"));
+        assert_eq!(ds.insert(&bob).is_ok(), true);
+        let alice = Entity::from("Alice")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(Tag::from("skill", "cards"))
+            .with_tag(Tag::from("address", "Wonderland"))
+            .with_tag(Tag::from("skill", "singing"));
+        assert_eq!(ds.insert(&alice).is_ok(), true);
+        // build index
+        ds.build_search_index();
+        // search for partial
+        let s = ds.search("car");
+        assert_eq!(s.len(), 1);
+        assert_eq!(s[0].uid(), alice.uid());
+        // no hit
+        let s = ds.search("truck");
+        assert_eq!(s.len(), 0);
+        // fetch alice
+        let s = ds.search("Alice");
+        assert_eq!(s.len(), 1);
+        assert_eq!(s[0].uid(), alice.uid());
+        // skill
+        let s = ds.search("singing");
+        assert_eq!(s.len(), 2);
+    }
+
+    #[test]
+    fn test_attachments() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let file = d.path().join("contract.txt");
+        std::fs::write(&file, "signed").unwrap();
+        // the same content is stored once
+        let a = ds.store_attachment(&file).unwrap();
+        assert_eq!(a.name, "contract.txt");
+        assert_eq!(a.size, 6);
+        assert_eq!(ds.store_attachment(&file).unwrap().hash, a.hash);
+        assert_eq!(ds.attachments.len(), 1);
+        // attach to an entity
+        let mut acme = Entity::from("acme").unwrap().with_sponsor(&bob);
+        acme.attach(a.clone());
+        acme.attach(Attachment::reference("site", "https://acme.com"));
+        ds.add(&acme).unwrap();
+        // and to an event
+        let photo = d.path().join("photo.jpg");
+        std::fs::write(&photo, [0u8, 1, 2]).unwrap();
+        let mut evt = Event::action("cli", "meeting", 1, None, &[Actor::Lead(acme.uid)]);
+        evt.attach(ds.store_attachment(&photo).unwrap());
+        ds.record(&evt).unwrap();
+        let found = ds.attachments_of(&acme);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[2].0.as_ref().map(|e| e.uid), Some(evt.uid));
+        // retrieve
+        assert_eq!(ds.attachment_content(&a).unwrap(), Some(b"signed".to_vec()));
+        assert_eq!(ds.attachment_content(&found[1].1).unwrap(), None);
+        let out = d.path().join("out");
+        std::fs::create_dir(&out).unwrap();
+        let p = ds.extract_attachment(&found[2].1, &out).unwrap();
+        assert_eq!(std::fs::read(p).unwrap(), vec![0u8, 1, 2]);
+        assert_eq!(ds.extract_attachment(&found[1].1, &out).is_err(), true);
+        // prune the content no longer used
+        assert_eq!(ds.prune_attachments().unwrap(), 0);
+        acme.detach(&a.hash);
+        ds.update(&acme).unwrap();
+        assert_eq!(ds.prune_attachments().unwrap(), 1);
+        assert_eq!(ds.attachment_content(&a).unwrap(), None);
+    }
+
+    #[test]
+    fn test_aliases() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let robert = Entity::from("Robert Marley")
+            .unwrap()
+            .self_sponsored()
+            .with_alias("Bob");
+        let bobby = Entity::from("Bobby").unwrap().self_sponsored();
+        ds.insert(&robert).unwrap();
+        ds.insert(&bobby).unwrap();
+        // aliases are searchable
+        let r = ds.search_detailed("bob");
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].entity.uid(), robert.uid());
+        assert_eq!(r[0].field, MatchField::Name);
+        // and resolve exactly
+        let found = ds.resolve("Bob");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].uid(), robert.uid());
+        assert_eq!(ds.resolve("marley").len(), 1);
+        // removed aliases are gone from the index
+        let mut robert = robert;
+        robert.remove_alias("bob");
+        ds.update(&robert).unwrap();
+        let found = ds.resolve("Bob");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].uid(), bobby.uid());
+    }
+
+    #[test]
+    fn test_search_config() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(ds.search_config(), SearchConfig::default());
+        let alice = Entity::from("Alice")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(Tag::from("skill", "cards"));
+        assert_eq!(ds.insert(&alice).is_ok(), true);
+        let bob = Entity::from("Bob")
+            .unwrap()
+            .self_sponsored()
+            .with_handle("nick", "alice");
+        assert_eq!(ds.insert(&bob).is_ok(), true);
+        // exact handle matches come first
+        let s = ds.search("alice");
+        assert_eq!(s.len(), 2);
+        assert_eq!(s[0].uid(), bob.uid());
+        let s = ds.search("nick:alice");
+        assert_eq!(s[0].uid(), bob.uid());
+        // match details
+        let r = ds.search_detailed("alice");
+        assert_eq!(r[0].exact, true);
+        assert_eq!(r[0].field, MatchField::Handle);
+        assert_eq!(r[1].exact, false);
+        assert_eq!(r[1].field, MatchField::Name);
+        assert_eq!(r[1].score >= 1.0, true);
+        // fuzzy match
+        let r = ds.search_detailed("car");
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].field, MatchField::Tag);
+        assert_eq!(r[0].score < 1.0, true);
+        // a stricter threshold
+        let cfg = SearchConfig {
+            threshold: 0.95,
+            ..SearchConfig::default()
+        };
+        assert_eq!(ds.set_search_config(&cfg).is_ok(), true);
+        assert_eq!(ds.search_config(), cfg);
+        assert_eq!(ds.search("car").len(), 0);
+        assert_eq!(ds.search("cards").len(), 1);
+        // invalid threshold
+        let cfg = SearchConfig {
+            threshold: 2.0,
+            ..SearchConfig::default()
+        };
+        assert_eq!(ds.set_search_config(&cfg).is_err(), true);
+        // custom fields
+        let acme = Entity::from("Acme")
+            .unwrap()
+            .self_sponsored()
+            .with_field("plan", FieldValue::Text("enterprise".to_owned()));
+        assert_eq!(ds.insert(&acme).is_ok(), true);
+        let r = ds.search_detailed("enterprise");
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].field, MatchField::Field);
+    }
+
+    // // TODO: remove
+    // assert_eq!(ds.events.len(), 2);
+    // println!("owner:{}", owner.uid());
+    // for r in ds.entity_event.iter() {
+    //     let (k, v) = r.unwrap();
+    //     println!("{}  -  {}", str(&k), str(&v));
+    // }
+
+    #[test]
+    fn test_setup() {
+        let d = TempDir::new().unwrap();
+        println!("dir is {:?}", d);
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        // owner
+        let owner = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_next_action(utils::date(3, 3, 2020), "whatever".to_string());
+        // root object
+        let root = Entity::from("acme")
+            .unwrap()
+            .with_sponsor(&owner)
+            .with_next_action(utils::date(3, 10, 2020), "whatever".to_string());
+        // init error
+        assert_eq!(
+            ds.init(&root).err().unwrap(),
+            DataError::InitializationError
+        );
+
+        // init ok
+        assert_eq!(ds.init(&owner).is_ok(), true);
+        assert_eq!(ds.add(&root).is_ok(), true);
+        // check sponsorship (itself and the sponsored)
+        assert_eq!(ds.sponsored_by(&owner).len(), 2);
+        // count events
+        let events = ds.events(&owner, EventFilter::Any);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].actors[0].uid(), owner.uid());
+
+        // insert data
+        let data = vec![
+            ("A", "person", "01.01.2021", &owner),
+            ("B", "person", "02.01.2021", &owner),
+            ("C", "person", "01.02.2021", &owner),
+            ("D", "person", "02.02.2021", &owner),
+        ];
+        data.iter().for_each(|(name, class, nad, sp)| {
+            let mut e = Entity::from(name)
+                .unwrap()
+                .with_class(class)
+                .with_sponsor(sp);
+            e.next_action(utils::date_from_str(nad).unwrap(), "yea".to_string());
+            ds.insert(&e).unwrap();
+        });
+
+        // test agenda
+        let (s, u) = TimeWindow::Day(1).range(&utils::date(1, 1, 2021));
+        let a = ds.agenda(&s, &u, &AgendaFilter::default(), 0, 0);
+        assert_eq!(a.items.len(), 1);
+
+        let (s, u) = TimeWindow::Day(2).range(&utils::date(1, 1, 2021));
+        let a = ds.agenda(&s, &u, &AgendaFilter::default(), 0, 0);
+        assert_eq!(a.items.len(), 2);
+
+        let (s, u) = TimeWindow::Year(1).range(&utils::date(1, 1, 2021));
+        let a = ds.agenda(&s, &u, &AgendaFilter::default(), 0, 0);
+        assert_eq!(a.items.len(), 4);
+
+        let (s, u) = TimeWindow::Year(1).range(&utils::date(1, 2, 2021));
+        let a = ds.agenda(&s, &u, &AgendaFilter::default(), 0, 0);
+        assert_eq!(a.items.len(), 2);
+
+        // test agenda until
+        let a = ds.agenda_until(&utils::date(31, 10, 2020), &AgendaFilter::default(), 0, 0);
+        assert_eq!(a.items.len(), 2);
+
+        let a = ds.agenda_until(&utils::date(2, 2, 2021), &AgendaFilter::default(), 0, 0);
+        assert_eq!(a.items.len(), 6);
+        assert_eq!(a.total, 6);
+
+        // test pagination
+        let a = ds.agenda_until(&utils::date(2, 2, 2021), &AgendaFilter::default(), 4, 0);
+        assert_eq!(a.items.len(), 4);
+        assert_eq!(a.total, 6);
+        assert_eq!(a.has_more(0), true);
+        let b = ds.agenda_until(&utils::date(2, 2, 2021), &AgendaFilter::default(), 4, 4);
+        assert_eq!(b.items.len(), 2);
+        assert_eq!(b.total, 6);
+        assert_eq!(b.has_more(4), false);
+        assert_eq!(b.items[1].name, "D");
+        // pages do not overlap
+        assert_eq!(a.items.iter().any(|e| b.items.contains(e)), false);
+        let (s, u) = TimeWindow::Year(1).range(&utils::date(1, 1, 2021));
+        let a = ds.agenda(&s, &u, &AgendaFilter::default(), 3, 1);
+        assert_eq!(a.items.len(), 3);
+        assert_eq!(a.total, 4);
+        let a = ds.agenda(&s, &u, &AgendaFilter::default(), 0, 10);
+        assert_eq!(a.items.len(), 0);
+        assert_eq!(a.total, 4);
+
+        ds.close().unwrap();
+
+        // // TODO: db not closed
+        // // init error db not empty
+        // let mut ds = DataStore::open(d.path()).unwrap();
+        // // init error
+        // assert_eq!(
+        //     ds.init(&owner).err().unwrap(),
+        //     DataError::InitializationError
+        // );
+    }
+
+    #[test]
+    fn test_update() {
+        let d = TempDir::new().unwrap();
+        println!("dir is {:?}", d);
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        // bob
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_next_action(date(1, 1, 2000), "something".to_string());
+
+        // update not existing
+        assert_eq!(ds.update(&bob).err().unwrap(), DataError::NotFound);
+        // insert bob
+        assert_eq!(ds.insert(&bob).is_ok(), true);
+        // now update bob next action
+        let bob = bob.with_next_action(date(11, 1, 2000), "something".to_string());
+        assert_eq!(ds.update(&bob).is_ok(), true);
+        // check that there is only one action in the db
+        assert_eq!(ds.actions.len(), 1);
+        // the action has been postponed
+        assert_eq!(ds.postponed_count(&bob), 1);
+        // a new action is not a postpone
+        let bob = bob.with_next_action(date(12, 1, 2000), "something else".to_string());
+        assert_eq!(ds.update(&bob).is_ok(), true);
+        assert_eq!(ds.postponed_count(&bob), 1);
+        // now add alice
+        let alice = Entity::from("alice")
+            .unwrap()
+            .self_sponsored()
+            .with_handle("email", "alice@acme.com");
+        assert_eq!(ds.insert(&alice).is_ok(), true);
+        // and bob tries to hijack alice
+        let bob = bob.with_handle("email", "Alice@ACME.com");
+        //assert_eq!(ds.update(&bob).is_err(), true);
+        assert_eq!(ds.update(&bob).err().unwrap(), DataError::IDAlreadyTaken);
+        // // but what if a new player arrives and tries to hijack alice?
+        let martha = Entity::from("martha")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "alice@acme.com");
+        assert_eq!(ds.add(&martha).err().unwrap(), DataError::IDAlreadyTaken);
+        // find out who owns the handle
+        let conflicts = ds.handle_conflicts(&martha).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].0, "email");
+        assert_eq!(conflicts[0].2.uid(), alice.uid());
+        assert_eq!(
+            ds.handles(&alice).unwrap(),
+            vec![("email".to_owned(), "alice@acme.com".to_owned())]
+        );
+        assert_eq!(ds.handles(&martha).unwrap().len(), 0);
+        let all = ds.all_handles("email");
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].0, "alice@acme.com");
+        assert_eq!(all[0].1.uid(), alice.uid());
+        // change alice sponsor
+        let alice = ds
+            .get_by_id("email", "alice@acme.com")
+            .unwrap()
+            .unwrap()
+            .with_sponsor(&bob);
+        assert_eq!(ds.update(&alice).is_ok(), true);
+        // a second email is indexed too
+        let alice = alice.with_handle("email", "alice@home.org");
+        assert_eq!(ds.update(&alice).is_ok(), true);
+        assert_eq!(
+            ds.get_by_id("email", "alice@home.org")
+                .unwrap()
+                .map(|e| e.uid()),
+            Some(alice.uid())
+        );
+        assert_eq!(ds.handles(&alice).unwrap().len(), 2);
+        assert_eq!(ds.all_handles("email").len(), 2);
+        // and nobody else can take it
+        let dan = Entity::from("dan")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "dan@acme.com")
+            .with_handle("email", "alice@home.org");
+        assert_eq!(ds.add(&dan).err(), Some(DataError::IDAlreadyTaken));
+        let conflicts = ds.handle_conflicts(&dan).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].1, "alice@home.org");
+        // removing the primary one promotes the other
+        let mut alice = ds.get_by_uid(&alice.uid()).unwrap().unwrap();
+        alice.remove_handle("email", "alice@acme.com");
+        assert_eq!(ds.update(&alice).is_ok(), true);
+        assert_eq!(ds.get_by_id("email", "alice@acme.com").unwrap(), None);
+        let alice = ds.get_by_uid(&alice.uid()).unwrap().unwrap();
+        assert_eq!(
+            alice.handles.get("email"),
+            Some(&"alice@home.org".to_owned())
+        );
+        assert_eq!(alice.other_handles.len(), 0);
+        // TODO tags
+    }
+
+    #[test]
+    fn test_relationships() {
+        let d = TempDir::new().unwrap();
+        println!("dir is {:?}", d);
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+
+        for i in 0..100 {
+            let name = format!("e_{}", i);
+
+            let e = Entity::from(&name)
+                .unwrap()
+                .self_sponsored()
+                .with_handle("code", &name)
+                .with_next_action(date(1, 1, 2000), "something".to_string());
+            assert_eq!(ds.insert(&e).is_ok(), true);
+        }
+        // create a new entity
+        let e = Entity::from("center")
+            .unwrap()
+            .self_sponsored()
+            .with_handle("code", "center")
+            .with_next_action(date(1, 1, 2000), "something".to_string());
+        // add relationships
+        let e = e
+            .add_relation_with(
+                &ds.get_by_id("code", "e_1").unwrap().unwrap(),
+                RelType::RelatedTo,
+            )
+            .add_relation_with(
+                &ds.get_by_id("code", "e_10").unwrap().unwrap(),
+                RelType::RelatedTo,
+            )
+            .add_relation_with(
+                &ds.get_by_id("code", "e_50").unwrap().unwrap(),
+                RelType::RelatedTo,
+            );
+        // insert
+        assert_eq!(ds.insert(&e).is_ok(), true);
+        // fetch
+        let e = ds.get_by_id("code", "center").unwrap().unwrap();
+        assert_eq!(e.relationships.len(), 3);
+        // add a new one
+        let e = e.add_relation_with(
+            &ds.get_by_id("code", "e_71").unwrap().unwrap(),
+            RelType::RelatedTo,
+        );
+        // update
+        assert_eq!(ds.update(&e).is_ok(), true);
+        // fetch
+        let e = ds.get_by_id("code", "center").unwrap().unwrap();
+        assert_eq!(e.relationships.len(), 4);
+    }
+
+    #[test]
+    fn test_postponed() {
+        let d = TempDir::new().unwrap();
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        // bob
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_next_action(date(1, 1, 2021), "something".to_string());
+        assert_eq!(ds.insert(&bob).is_ok(), true);
+        assert_eq!(ds.postponed_count(&bob), 0);
+        // postpone a few times
+        for _ in 0..3 {
+            ds.record(&Event::log("postponed", &bob, None)).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(ds.postponed_count(&bob), 3);
+        assert_eq!(ds.events(&bob, EventFilter::Postponed).len(), 3);
+        assert_eq!(ds.escalation(&bob, &date(1, 1, 2021)), Escalation::Late);
+        assert_eq!(ds.escalation(&bob, &date(1, 2, 2021)), Escalation::Critical);
+        // a different log breaks the sequence
+        ds.record(&Event::log("review", &bob, None)).unwrap();
+        assert_eq!(ds.postponed_count(&bob), 0);
+        assert_eq!(ds.escalation(&bob, &date(1, 1, 2021)), Escalation::None);
+    }
+
+    #[test]
+    fn test_update_many() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let people = ["alice", "carl", "dave"]
+            .iter()
+            .map(|n| {
+                let e = Entity::from(n).unwrap().with_sponsor(&bob);
+                ds.add(&e).unwrap();
+                e
+            })
+            .collect::<Vec<Entity>>();
+        let friend = Tag::Generic("friend".to_owned());
+        let changed = people
+            .iter()
+            .map(|e| e.clone().with_tag(friend.clone()))
+            .collect::<Vec<Entity>>();
+        assert_eq!(ds.update_many(&changed).unwrap().len(), 3);
+        assert_eq!(ds.by_tag(friend.prefix(), &friend.slug()).len(), 3);
+        assert_eq!(ds.audit(&people[0].uid(), None).len(), 2);
+        assert_eq!(ds.update_many(&[]).unwrap().len(), 0);
+        // all or nothing
+        let mut broken = people
+            .iter()
+            .map(|e| e.clone().with_tag(Tag::Generic("work".to_owned())))
+            .collect::<Vec<Entity>>();
+        broken.push(Entity::from("nobody").unwrap().with_sponsor(&bob));
+        assert_eq!(ds.update_many(&broken).err(), Some(DataError::NotFound));
+        assert_eq!(ds.by_tag("tag", "work").len(), 0);
+        // the handles are checked
+        let taken = people[1].clone().with_handle("email", "alice@acme.com");
+        ds.update(&people[0].clone().with_handle("email", "alice@acme.com"))
+            .unwrap();
+        assert_eq!(
+            ds.update_many(&[taken]).err(),
+            Some(DataError::IDAlreadyTaken)
+        );
+        // and so are the roles
+        let viewer = people[2].clone().with_tag(AccessRole::Viewer.tag());
+        ds.update(&viewer).unwrap();
+        ds.set_principal(Some(&viewer)).unwrap();
+        assert_eq!(
+            ds.update_many(&changed).err(),
+            Some(DataError::PermissionDenied)
+        );
+    }
+
+    #[test]
+    fn test_complete_postpone() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let today = utils::today();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_next_action(today, "call about the offer\nbring the numbers".to_string());
+        ds.insert(&bob).unwrap();
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_contact_every(TimeWindow::Month(1))
+            .with_next_action(today, "lunch".to_string());
+        ds.insert(&jane).unwrap();
+        // postponing keeps the note and is counted
+        let week = TimeWindow::Week(1);
+        let e = ds.postpone(&bob, &week).unwrap();
+        assert_eq!(e.next_action_date, week.offset(&today));
+        assert_eq!(e.next_action_note, bob.next_action_note);
+        assert_eq!(ds.postponed_count(&bob), 1);
+        assert_eq!(ds.postpone(&bob, &TimeWindow::Day(1)).is_err(), true);
+        // done clears the note
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let e = ds.complete_next_action(&bob).unwrap();
+        assert_eq!(e.next_action_note, "");
+        assert_eq!(e.next_action_date > today, true);
+        assert_eq!(ds.postponed_count(&bob), 0);
+        let done = ds.events(&bob, EventFilter::LogsWithMessage("done".to_string()));
+        assert_eq!(done[0].content, Some("call about the offer".to_string()));
+        // or moves to the next contact
+        let e = ds.complete_next_action(&jane).unwrap();
+        assert_eq!(e.next_action_date, TimeWindow::Month(1).offset(&today));
+        assert_eq!(e.next_action_note, CONTACT_NOTE);
+        assert_eq!(ds.postponed_count(&jane), 0);
+        // a viewer cannot do either
+        let viewer = Entity::from("viewer")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_tag(AccessRole::Viewer.tag());
+        ds.insert(&viewer).unwrap();
+        ds.set_principal(Some(&viewer)).unwrap();
+        assert_eq!(
+            ds.complete_next_action(&jane).err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(
+            ds.postpone(&jane, &TimeWindow::Year(1)).err(),
+            Some(DataError::PermissionDenied)
+        );
+    }
+
+    #[test]
+    fn test_review() {
+        let d = TempDir::new().unwrap();
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        // entities never reviewed
+        let mut bob = Entity::from("bob").unwrap().self_sponsored();
+        bob.updated_on = date(1, 1, 2020);
+        assert_eq!(ds.insert(&bob).is_ok(), true);
+        let mut alice = Entity::from("alice").unwrap().self_sponsored();
+        alice.updated_on = date(1, 1, 2019);
+        assert_eq!(ds.insert(&alice).is_ok(), true);
+        let carl = Entity::from("carl").unwrap().self_sponsored();
+        assert_eq!(ds.insert(&carl).is_ok(), true);
+        // oldest first
+        let q = ds.review_queue(&date(1, 1, 2021));
+        assert_eq!(q.len(), 2);
+        assert_eq!(q[0].uid(), alice.uid());
+        assert_eq!(q[1].uid(), bob.uid());
+        // review alice
+        assert_eq!(ds.mark_reviewed(&alice).is_ok(), true);
+        assert_eq!(ds.last_review(&alice), today());
+        let q = ds.review_queue(&date(1, 1, 2021));
+        assert_eq!(q.len(), 1);
+        assert_eq!(q[0].uid(), bob.uid());
+    }
+
+    #[test]
+    fn test_inbox() {
+        let d = TempDir::new().unwrap();
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        assert_eq!(ds.init(&bob).is_ok(), true);
+        // empty name
+        assert_eq!(ds.capture(" ", &bob, None).is_err(), true);
+        // capture
+        let uid = ds.capture("alice", &bob, None).unwrap();
+        ds.capture("acme", &bob, Some("met at the fair".to_owned()))
+            .unwrap();
+        let inbox = ds.inbox();
+        assert_eq!(inbox.len(), 2);
+        assert_eq!(inbox.iter().all(|e| e.is_inbox()), true);
+        // the note is recorded
+        let acme = ds.search("acme");
+        assert_eq!(ds.events(&acme[0], EventFilter::Actions).len(), 1);
+        // process alice
+        let alice = ds
+            .get_by_uid(&utils::id(&uid))
+            .unwrap()
+            .unwrap()
+            .with_class("person");
+        assert_eq!(ds.triaged(&alice).is_ok(), true);
+        let inbox = ds.inbox();
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].name(), "acme");
+        let alice = ds.get_by_uid(&utils::id(&uid)).unwrap().unwrap();
+        assert_eq!(alice.is_inbox(), false);
+        assert_eq!(alice.class, "person");
+    }
+
+    #[test]
+    fn test_record_note() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let (acme, mark, alice) = (
+            Entity::from("acme").unwrap().with_sponsor(&bob),
+            Entity::from("mark").unwrap().with_sponsor(&bob),
+            Entity::from("alice").unwrap().with_sponsor(&bob),
+        );
+        for e in [&acme, &mark, &alice].iter() {
+            ds.add(e).unwrap();
+        }
+        ds.add(&Entity::from("jane doe").unwrap().with_sponsor(&bob))
+            .unwrap();
+        ds.add(&Entity::from("jane roe").unwrap().with_sponsor(&bob))
+            .unwrap();
+        let text = "met [[Mark]] and [[main:alice]] about [[jane]], [[mark]] again and [[Zed]]";
+        let report = ds.record_note(&bob, text, Some(&acme)).unwrap();
+        assert_eq!(report.captured, vec!["Zed".to_owned()]);
+        assert_eq!(report.ambiguous, vec!["jane".to_owned()]);
+        // the captured entity is in the inbox
+        let inbox = ds.inbox();
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].name(), "Zed");
+        // every actor once, with its role
+        let evts = ds.events(&acme, EventFilter::Actions);
+        assert_eq!(evts.len(), 1);
+        assert_eq!(evts[0].uid, report.event);
+        assert_eq!(evts[0].content, Some(text.to_owned()));
+        assert_eq!(
+            evts[0].actors,
+            vec![
+                Actor::RecordedBy(bob.uid),
+                Actor::Subject(acme.uid),
+                Actor::Starring(mark.uid),
+                Actor::Lead(alice.uid),
+                Actor::Starring(inbox[0].uid),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let d = TempDir::new().unwrap();
+        println!("dir is {:?}", d);
+        // open the datastore
+        let mut ds = DataStore::open(d.path()).unwrap();
+        // bob
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_next_action(date(1, 1, 2000), "something".to_string());
+        // insert bob
+        assert_eq!(ds.insert(&bob).is_ok(), true);
+        // record an event without actors
+        let res = ds.record(&Event::new());
+        assert_eq!(res.err().unwrap(), DataError::BrokenReference);
+        // insert a bunch of events elements
+        let elements = 1000;
+        for i in 0..elements {
+            ds.record(&Event::action(
+                "count",
+                &format!("{}", i),
+                1,
+                None,
+                &[Actor::Lead(bob.uid.clone())],
+            ))
+            .unwrap();
+            // sleep 1ms
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let events = ds.events(&bob, EventFilter::Actions);
+        assert_eq!(events.len(), elements);
+        for (i, e) in events.iter().enumerate() {
+            assert_eq!(
+                e.kind,
+                EventType::Action("count".to_owned(), format!("{}", elements - 1 - i), 1)
+            );
+        }
+    }
+
+    #[test]
+    fn test_audit() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag());
+        ds.init(&bob).unwrap();
+        ds.set_principal(Some(&bob)).unwrap();
+        let mut jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        ds.add(&jane).unwrap();
+        jane.add_handle("email", "jane@acme.com").unwrap();
+        ds.update(&jane).unwrap();
+        // an update without changes is not recorded
+        ds.update(&jane).unwrap();
+        jane.next_action(date(1, 1, 2030), "call".to_owned());
+        ds.update(&jane).unwrap();
+        let trail = ds.audit(&jane.uid(), None);
+        assert_eq!(
+            trail.iter().map(|a| a.action).collect::<Vec<_>>(),
+            vec![
+                AuditAction::Added,
+                AuditAction::Updated,
+                AuditAction::Updated
+            ]
+        );
+        assert_eq!(trail.iter().all(|a| a.changed_by == Some(bob.uid)), true);
+        assert_eq!(
+            trail[1].changes.iter().any(|(f, o, n)| f == "handles"
+                && o == "{}"
+                && n == "{\"email\":\"jane@acme.com\"}"),
+            true
+        );
+        assert_eq!(
+            trail[2]
+                .changes
+                .iter()
+                .any(|(f, _, n)| f == "next_action_date" && n == "\"2030-01-01\""),
+            true
+        );
+        // the passwords are not kept
+        let secret = "secret".to_owned();
+        assert_eq!(
+            ds.update(&jane.clone().with_password(Some(&secret)))
+                .is_err(),
+            true
+        );
+        ds.set_principal(None).unwrap();
+        ds.change_password(&jane, "", &secret).unwrap();
+        ds.set_principal(Some(&bob)).unwrap();
+        let last = ds.audit(&jane.uid(), None).pop().unwrap();
+        assert_eq!(
+            last.changes.iter().find(|(f, _, _)| f == "pass"),
+            Some(&("pass".to_owned(), "***".to_owned(), "***".to_owned()))
+        );
+        // by date
+        assert_eq!(ds.audit(&jane.uid(), Some(today())).len(), 4);
+        assert_eq!(ds.audit(&jane.uid(), Some(today().succ())).len(), 0);
+        // the trail survives the entity
+        ds.remove(&jane).unwrap();
+        let last = ds.audit(&jane.uid(), None).pop().unwrap();
+        assert_eq!(last.action, AuditAction::Removed);
+        assert_eq!(ds.audit(&bob.uid(), None).len(), 1);
+        assert_eq!(ds.audit("not-a-uid", None).len(), 0);
+    }
+
+    #[test]
+    fn test_update_is_atomic() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_handle("email", "bob@acme.com");
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com")
+            .with_tag(Tag::Generic("friends".to_owned()))
+            .with_next_action(date(1, 1, 2021), "call".to_owned());
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        // a failed update leaves the indexes untouched
+        let mut changed = jane.clone();
+        changed.tags.clear();
+        changed.next_action(date(1, 2, 2021), "later".to_owned());
+        changed.add_handle("email", "bob@acme.com").unwrap();
+        assert_eq!(ds.update(&changed).err(), Some(DataError::IDAlreadyTaken));
+        assert_eq!(
+            ds.agenda_until(&date(1, 1, 2021), &AgendaFilter::default(), 0, 0)
+                .items,
+            vec![jane.clone()]
+        );
+        assert_eq!(ds.by_tag("tag", "friends"), vec![jane.clone()]);
+        assert_eq!(
+            ds.get_by_id("email", "jane@acme.com").unwrap(),
+            Some(jane.clone())
+        );
+        assert_eq!(ds.check(false).unwrap().is_healthy(), true);
+        // a successful one replaces them at once
+        changed.handles.clear();
+        changed.other_handles.clear();
+        changed.add_handle("email", "jane@acme.org").unwrap();
+        ds.update(&changed).unwrap();
+        assert_eq!(
+            ds.agenda_until(&date(1, 1, 2021), &AgendaFilter::default(), 0, 0)
+                .total,
+            0
+        );
+        assert_eq!(ds.by_tag("tag", "friends").len(), 0);
+        assert_eq!(ds.get_by_id("email", "jane@acme.com").unwrap(), None);
+        assert_eq!(ds.get_by_id("email", "jane@acme.org").unwrap(), Some(jane));
+        assert_eq!(ds.check(false).unwrap().is_healthy(), true);
+    }
+
+    #[test]
+    fn test_add_batch() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let acme = Entity::from("acme")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_tag(Tag::Generic("customer".to_owned()));
+        // sponsored by an entity of the same batch
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&acme)
+            .with_handle("email", "jane@acme.com")
+            .with_relation(&Rel::new(&acme));
+        let uids = ds.add_batch(&[acme.clone(), jane.clone()]).unwrap();
+        assert_eq!(uids, vec![acme.uid, jane.uid]);
+        assert_eq!(ds.search("jane").len(), 1);
+        assert_eq!(
+            ds.get_by_id("email", "jane@acme.com").unwrap(),
+            Some(jane.clone())
+        );
+        assert_eq!(ds.by_tag("tag", "customer"), vec![acme.clone()]);
+        assert_eq!(ds.dependents(&acme).unwrap().len(), 1);
+        assert_eq!(
+            ds.events(&jane, EventFilter::LogsWithMessage("added".to_owned()))
+                .len(),
+            1
+        );
+        assert_eq!(ds.check(false).unwrap().is_healthy(), true);
+        // nothing is added when one of them is not valid
+        let carl = Entity::from("carl").unwrap().with_sponsor(&bob);
+        let taken = Entity::from("dan")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com");
+        assert_eq!(
+            ds.add_batch(&[carl.clone(), taken]).err(),
+            Some(DataError::IDAlreadyTaken)
+        );
+        let twins = vec![
+            carl.clone().with_handle("email", "carl@acme.com"),
+            Entity::from("carl2")
+                .unwrap()
+                .with_sponsor(&bob)
+                .with_handle("email", "carl@acme.com"),
+        ];
+        assert_eq!(ds.add_batch(&twins).err(), Some(DataError::IDAlreadyTaken));
+        let orphan = Entity::from("orphan").unwrap();
+        assert_eq!(
+            ds.add_batch(&[carl.clone(), orphan]).err(),
+            Some(DataError::InvalidSponsor)
+        );
+        assert_eq!(ds.get_by_uid(&carl.uid()).unwrap(), None);
+        assert_eq!(ds.entities.len(), 3);
+    }
+
+    #[test]
+    fn test_events_page() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        // one event a day in january, every other one is a call with jane
+        for day in 1..=20 {
+            let mut evt = match day % 2 {
+                0 => Event::action(
+                    "cli",
+                    "call",
+                    1,
+                    None,
+                    &[Actor::RecordedBy(bob.uid), Actor::Subject(jane.uid)],
+                ),
+                _ => Event::log("note", &bob, None),
+            };
+            evt.recorded_at = datetime_local(&date(day, 1, 2021));
+            ds.record(&evt).unwrap();
+        }
+        let days = |p: &Page<Event>| {
+            p.items
+                .iter()
+                .map(|e| e.recorded_at.naive_local().date().day())
+                .collect::<Vec<u32>>()
+        };
+        // pages
+        let p = ds.events_page(&bob, EventFilter::Any, None, None, 3, 0);
+        assert_eq!(p.total, 20);
+        assert_eq!(days(&p), vec![20, 19, 18]);
+        let p = ds.events_page(&bob, EventFilter::Any, None, None, 3, 18);
+        assert_eq!(days(&p), vec![2, 1]);
+        assert_eq!(p.has_more(18), false);
+        // dates, since is included and until excluded
+        let (since, until) = (Some(date(5, 1, 2021)), Some(date(9, 1, 2021)));
+        let p = ds.events_page(&bob, EventFilter::Any, since, until, 0, 0);
+        assert_eq!(days(&p), vec![8, 7, 6, 5]);
+        let p = ds.events_page(&bob, EventFilter::Logs, since, None, 2, 1);
+        assert_eq!(p.total, 8);
+        assert_eq!(days(&p), vec![17, 15]);
+        // roles
+        let p = ds.events_page(
+            &jane,
+            EventFilter::Role(ActorRole::Subject),
+            None,
+            until,
+            0,
+            1,
+        );
+        assert_eq!(p.total, 4);
+        assert_eq!(days(&p), vec![6, 4, 2]);
+        assert_eq!(
+            ds.events_within(&jane, EventFilter::Any, until, None).len(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_quick_add() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let anna = Entity::from("anna").unwrap().with_sponsor(&bob);
+        ds.add(&anna).unwrap();
+        let today = ds.settings().today();
+        let line = "Lunch with [[Anna]] and [[Zed]] in 2 weeks #friend #food";
+        let report = ds.quick_add(&bob, line).unwrap();
+        assert_eq!(report.note.captured, vec!["Zed".to_owned()]);
+        assert_eq!(report.date, Some(today + Duration::days(14)));
+        assert_eq!(report.entities.len(), 2);
+        // both entities have the tags and the next action
+        for e in report.entities.iter() {
+            let e = ds.get_by_uid(&e.uid()).unwrap().unwrap();
+            assert_eq!(e.next_action_date, today + Duration::days(14));
+            assert_eq!(e.next_action_note, "Lunch with Anna and Zed");
+            assert_eq!(e.has_tag("tag:friend"), true);
+            assert_eq!(e.has_tag("tag:food"), true);
+        }
+        // the line is recorded as a note
+        let evt = ds.get_event(&utils::id(&report.note.event)).unwrap();
+        assert_eq!(evt.unwrap().content, Some(line.to_owned()));
+        assert_eq!(ds.events(&anna, EventFilter::Actions).len(), 1);
+        // without a date the next action is left alone
+        let report = ds.quick_add(&bob, "met [[Anna]] #work").unwrap();
+        assert_eq!(report.date, None);
+        let e = ds.get_by_uid(&anna.uid()).unwrap().unwrap();
+        assert_eq!(e.next_action_date, today + Duration::days(14));
+        assert_eq!(e.has_tag("tag:work"), true);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let tom = Entity::from("tom").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        ds.insert(&tom).unwrap();
+        // recorded out of order, one a day alternating jane and tom
+        for day in [7, 3, 5, 1, 6, 2, 4].iter() {
+            let who = match day % 2 {
+                0 => &jane,
+                _ => &tom,
+            };
+            let mut evt = Event::log("note", who, None);
+            evt.recorded_at = datetime_local(&date(*day, 1, 2021));
+            ds.record(&evt).unwrap();
+        }
+        let days = |events: &[Event]| {
+            events
+                .iter()
+                .map(|e| e.recorded_at.naive_local().date().day())
+                .collect::<Vec<u32>>()
+        };
+        let (since, until) = (Some(date(1, 1, 2021)), Some(date(1, 2, 2021)));
+        let all = ds.timeline(None, EventFilter::Any, since, until);
+        assert_eq!(days(&all), vec![1, 2, 3, 4, 5, 6, 7]);
+        // since is included and until excluded
+        let some = ds.timeline(
+            None,
+            EventFilter::Logs,
+            Some(date(3, 1, 2021)),
+            Some(date(6, 1, 2021)),
+        );
+        assert_eq!(days(&some), vec![3, 4, 5]);
+        let of_jane = ds.timeline(Some(&jane), EventFilter::Any, since, until);
+        assert_eq!(days(&of_jane), vec![2, 4, 6]);
+        assert_eq!(
+            ds.timeline(None, EventFilter::Actions, since, until).len(),
+            0
+        );
+        // the index is rebuilt when missing
+        ds.events_time.clear().unwrap();
+        ds.build_events_time_index().unwrap();
+        assert_eq!(
+            days(&ds.timeline(None, EventFilter::Any, since, until)),
+            days(&all)
+        );
+        // the events go with the entity
+        ds.remove(&tom).unwrap();
+        let all = ds.timeline(None, EventFilter::Any, since, until);
+        assert_eq!(days(&all), vec![2, 4, 6]);
+        assert_eq!(ds.events_time.len(), ds.events.len(),);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let rx = ds.subscribe();
+        let alice = Entity::from("alice").unwrap().with_sponsor(&bob);
+        let uid = ds.add(&alice).unwrap();
+        let alice = alice.with_next_action(date(1, 1, 2000), "something".to_string());
+        ds.update(&alice).unwrap();
+        let checksum = ds.events_checksum().unwrap();
+        let evt = Event::action("cli", "note", 1, None, &[Actor::RecordedBy(bob.uid)]);
+        ds.record(&evt).unwrap();
+        // the events checksum follows the recorded events
+        assert_ne!(ds.events_checksum().unwrap(), checksum);
+        let changes = rx.try_iter().collect::<Vec<ChangeEvent>>();
+        assert_eq!(
+            changes,
+            vec![
+                ChangeEvent::EntityAdded(uid),
+                // the add log event
+                changes[1].clone(),
+                ChangeEvent::EntityUpdated(uid),
+                ChangeEvent::EventRecorded(evt.uid),
+            ]
+        );
+        // dropped receivers are unsubscribed
+        drop(rx);
+        ds.record(&evt).unwrap();
+        assert_eq!(ds.subscribers.len(), 0);
+    }
+
+    #[test]
+    fn test_watch() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let entities = ds.watch(ChangeFilter::Entities);
+        let events = ds.watch(ChangeFilter::Events);
+        let mut alice = Entity::from("alice").unwrap().with_sponsor(&bob);
+        let alice_changes = ds.watch(ChangeFilter::Entity(alice.uid));
+        ds.insert(&alice).unwrap();
+        alice.next_action(date(1, 1, 2000), "call".to_string());
+        ds.update(&alice).unwrap();
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        ds.insert(&tim).unwrap();
+        let note = Event::action("cli", "note", 1, None, &[Actor::RecordedBy(bob.uid)]);
+        ds.record(&note).unwrap();
+        let call = Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(alice.uid)],
+        );
+        ds.record(&call).unwrap();
+        assert_eq!(
+            entities.take(3).collect::<Vec<_>>(),
+            vec![
+                ChangeEvent::EntityAdded(alice.uid),
+                ChangeEvent::EntityUpdated(alice.uid),
+                ChangeEvent::EntityAdded(tim.uid),
+            ]
+        );
+        assert_eq!(
+            events.take(2).collect::<Vec<_>>(),
+            vec![
+                ChangeEvent::EventRecorded(note.uid),
+                ChangeEvent::EventRecorded(call.uid),
+            ]
+        );
+        // the entity and the events it takes part in
+        let mut changes = alice_changes.take(3).collect::<Vec<_>>();
+        changes.sort_by_key(|c| format!("{:?}", c));
+        assert_eq!(
+            changes,
+            vec![
+                ChangeEvent::EntityAdded(alice.uid),
+                ChangeEvent::EntityUpdated(alice.uid),
+                ChangeEvent::EventRecorded(call.uid),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sealed_backup() {
+        let d = TempDir::new().unwrap();
+        let sealed = d.path().join("db.sealed");
+        let mut ds = DataStore::open_temporary().unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        ds.seal(&sealed, "secret").unwrap();
+        drop(ds);
+        // the backups are written encrypted only
+        let mut ds = DataStore::open_sealed(&sealed, "secret").unwrap();
+        let p = ds.backup(&d.path().join("backups")).unwrap();
+        assert_eq!(formats::is_encrypted(&p).unwrap(), true);
+        assert_eq!(std::fs::read_dir(p.parent().unwrap()).unwrap().count(), 1);
+        let decrypted = formats::decrypt_file(&p, "secret").unwrap();
+        let mut copy = DataStore::open_temporary().unwrap();
+        copy.import(&decrypted, ExportFormat::FullJson).unwrap();
+        assert_eq!(copy.get_by_uid(&bob.uid()).unwrap(), Some(bob.clone()));
+        // the datastore is sealed on close and not again when dropped
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        ds.insert(&jane).unwrap();
+        ds.close().unwrap();
+        ds.insert(&Entity::from("tim").unwrap().with_sponsor(&bob))
+            .unwrap();
+        drop(ds);
+        let mut ds = DataStore::open_sealed(&sealed, "secret").unwrap();
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap(), Some(jane.clone()));
+        assert_eq!(ds.entities.len(), 2);
+        assert_eq!(DataStore::sealed_in_use(&sealed), true);
+        // the viewers cannot seal it elsewhere but their changes are sealed back
+        ds.set_principal(Some(&jane)).unwrap();
+        assert_eq!(
+            ds.seal(&d.path().join("copy.sealed"), "other").err(),
+            Some(DataError::PermissionDenied)
+        );
+        drop(ds);
+        // a lock file left by a process that died does not lock it
+        let lock = sealed.with_extension("lock");
+        std::fs::write(&lock, "4242").unwrap();
+        assert_eq!(DataStore::sealed_in_use(&sealed), false);
+        assert_eq!(lock.exists(), false);
+        std::fs::write(&lock, "4242").unwrap();
+        let _ds = DataStore::open_sealed(&sealed, "secret").unwrap();
+        assert_eq!(
+            DataStore::open_sealed(&sealed, "secret").err(),
+            Some(DataError::Locked(std::process::id()))
+        );
+    }
+
+    #[test]
+    fn test_backup() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let now = utils::now_local();
+        // disabled by default
+        assert_eq!(ds.backup_every(), 0);
+        assert_eq!(ds.backup_due(&now), false);
+        assert_eq!(ds.set_backup_every(-1).is_err(), true);
+        // enabled and never done
+        ds.set_backup_every(7).unwrap();
+        assert_eq!(ds.backup_due(&now), true);
+        let p = ds.backup(&d.path().join("backups")).unwrap();
+        assert_eq!(p.exists(), true);
+        assert_eq!(ds.last_backup().is_some(), true);
+        assert_eq!(ds.backup_due(&utils::now_local()), false);
+        assert_eq!(
+            ds.backup_due(&(utils::now_local() + Duration::days(7))),
+            true
+        );
+        // the backup can be restored
+        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
+        copy.import(&p, ExportFormat::FullJson).unwrap();
+        assert_eq!(copy.get_by_uid(&bob.uid()).unwrap(), Some(bob.clone()));
+        assert_eq!(copy.backup_every(), 7);
+        // retention
+        assert_eq!(ds.backup_retention(), Retention::default());
+        let r = Retention {
+            daily: 1,
+            weekly: 2,
+            monthly: 3,
+        };
+        ds.set_backup_retention(&r).unwrap();
+        assert_eq!(ds.backup_retention(), r);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let mut jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com");
+        ds.insert(&jane).unwrap();
+        let p = ds.snapshot(&d.path().join("snapshots")).unwrap();
+        assert_eq!(p.exists(), true);
+        // change the data after the snapshot
+        jane.add_handle("email", "jane@example.com").unwrap();
+        ds.update(&jane).unwrap();
+        let alice = Entity::from("alice").unwrap().with_sponsor(&bob);
+        ds.insert(&alice).unwrap();
+        assert_eq!(ds.audit(&jane.uid(), None).len(), 2);
+        // roll back
+        ds.restore(&p).unwrap();
+        assert_eq!(ds.get_by_uid(&alice.uid()).unwrap(), None);
+        let restored = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        assert_eq!(restored.handles.get("email").unwrap(), "jane@acme.com");
+        assert_eq!(ds.audit(&jane.uid(), None).len(), 1);
+        assert_eq!(ds.search("alice").len(), 0);
+        assert_eq!(ds.search("jane").len(), 1);
+        // a broken snapshot leaves the data as it is
+        let broken = d.path().join("broken.bin.gz");
+        std::fs::write(&broken, "not a snapshot").unwrap();
+        assert_eq!(ds.restore(&broken).is_err(), true);
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap().is_some(), true);
+        // the admins take the snapshots, the owners restore them
+        let mut bob = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
+        bob.set_access_role(Some(AccessRole::Owner));
+        ds.update(&bob).unwrap();
+        let mut jane = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        jane.set_access_role(Some(AccessRole::Admin));
+        ds.update(&jane).unwrap();
+        ds.insert(&alice).unwrap();
+        ds.set_principal(Some(&alice)).unwrap();
+        assert_eq!(
+            ds.snapshot(&d.path().join("snapshots")).err(),
+            Some(DataError::PermissionDenied)
+        );
+        ds.set_principal(Some(&jane)).unwrap();
+        let p = ds.snapshot(&d.path().join("snapshots")).unwrap();
+        assert_eq!(ds.restore(&p).err(), Some(DataError::PermissionDenied));
+        ds.set_principal(Some(&bob)).unwrap();
+        ds.restore(&p).unwrap();
+    }
+
+    #[test]
+    fn test_import_conflicts() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_handle("email", "bob@acme.com");
+        ds.insert(&bob).unwrap();
+        ds.export(&p, ExportFormat::Json).unwrap();
+        // change bob locally
+        let local = bob
+            .clone()
+            .with_next_action(date(1, 1, 2021), "call".to_string());
+        ds.update(&local).unwrap();
+        // keep the local version
+        let mut conflicts = Vec::new();
+        let n = ds
+            .import_with(&p, ExportFormat::Json, None, |c| {
+                conflicts.push(c.clone());
+                Resolution::KeepLocal
+            })
+            .unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].local.next_action_note, "call");
+        let stored = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
+        assert_eq!(stored.next_action_note, "call");
+        // take the imported one
+        ds.import_with(&p, ExportFormat::Json, None, |_| Resolution::TakeImported)
+            .unwrap();
+        let stored = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
+        assert_eq!(stored.next_action_note, bob.next_action_note);
+        // an export where two entities share an handle
+        let mallory = Entity::from("mallory")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "bob@acme.com")
+            .with_handle("telegram", "@mallory");
+        let mut w = ExportWriter::new(File::create(&p).unwrap(), None);
+        w.write_record(&serde_json::to_string(&bob).unwrap())
+            .unwrap();
+        w.write_record(&serde_json::to_string(&mallory).unwrap())
+            .unwrap();
+        w.finish().unwrap();
+        // keeping the local one strips the handle from the imported
+        ds.import_with(&p, ExportFormat::Json, None, |c| {
+            assert_eq!(c.reason, "handles already used: email:bob@acme.com");
+            Resolution::KeepLocal
+        })
+        .unwrap();
+        let owner = ds.get_by_id("email", "bob@acme.com").unwrap().unwrap();
+        assert_eq!(owner.uid(), bob.uid());
+        let m = ds.get_by_id("telegram", "@mallory").unwrap().unwrap();
+        assert_eq!(m.handles.contains_key("email"), false);
+        // taking the imported one strips the handle from the local
+        ds.import_with(&p, ExportFormat::Json, None, |_| Resolution::TakeImported)
+            .unwrap();
+        let owner = ds.get_by_id("email", "bob@acme.com").unwrap().unwrap();
+        assert_eq!(owner.uid(), mallory.uid());
+        let b = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
+        assert_eq!(b.handles.len(), 0);
+        // skip the imported one, bob is restored from the export
+        let n = ds
+            .import_with(&p, ExportFormat::Json, None, |c| {
+                match c.reason.starts_with("handles") {
+                    true => Resolution::Skip,
+                    false => Resolution::TakeImported,
+                }
+            })
+            .unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(ds.get_by_uid(&mallory.uid()).unwrap().is_none(), true);
+        let owner = ds.get_by_id("email", "bob@acme.com").unwrap().unwrap();
+        assert_eq!(owner.uid(), bob.uid());
+        // merge mallory into bob
+        let n = ds
+            .import_with(&p, ExportFormat::Json, None, |c| {
+                let fields = vec!["handles".to_owned()];
+                Resolution::Merge(c.local.merge_fields(&c.imported, &fields))
+            })
+            .unwrap();
+        assert_eq!(n, 1);
+        let owner = ds.get_by_id("telegram", "@mallory").unwrap().unwrap();
+        assert_eq!(owner.uid(), bob.uid());
+        assert_eq!(owner.name(), "bob");
+    }
+
+    #[test]
+    fn test_events_by_role() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        // bob writes about jane
+        let about = Event::action(
+            "cli",
+            "note",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(jane.uid)],
+        );
+        ds.record(&about).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        // jane writes about herself
+        let own = Event::action(
+            "cli",
+            "note",
+            1,
+            None,
+            &[Actor::RecordedBy(jane.uid), Actor::Subject(jane.uid)],
+        );
+        ds.record(&own).unwrap();
+        let uids = |f: EventFilter| {
+            ds.events(&jane, f)
+                .iter()
+                .map(|e| e.uid)
+                .collect::<Vec<model::Uuid>>()
+        };
+        // no duplicates when an entity has two roles
+        assert_eq!(uids(EventFilter::Any), vec![own.uid, about.uid]);
+        assert_eq!(
+            uids(EventFilter::Role(ActorRole::Subject)),
+            vec![own.uid, about.uid]
+        );
+        assert_eq!(
+            uids(EventFilter::Role(ActorRole::RecordedBy)),
+            vec![own.uid]
+        );
+        assert_eq!(uids(EventFilter::Role(ActorRole::Lead)).len(), 0);
+        assert_eq!(
+            ds.events(&bob, EventFilter::Role(ActorRole::RecordedBy))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_inverse_edges() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let acme = Entity::from("acme").unwrap().with_sponsor(&bob);
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_relation(&Rel::new(&acme).with_kind(RelType::WorksAt))
+            .with_relation(&Rel::new(&bob));
+        for e in [&bob, &acme, &tim, &jane].iter() {
+            ds.insert(e).unwrap();
+        }
+        let kinds = |ds: &DataStore, uid: &str| {
+            ds.get_by_uid(uid)
+                .unwrap()
+                .unwrap()
+                .relationships
+                .iter()
+                .map(|r| (r.kind.get_label(), utils::id(&r.target)))
+                .collect::<Vec<_>>()
+        };
+        // the typed relationships get the inverse
+        assert_eq!(
+            kinds(&ds, &acme.uid()),
+            vec![("employs".to_owned(), jane.uid())]
+        );
+        assert_eq!(kinds(&ds, &bob.uid()), vec![]);
+        let acme = ds.get_by_uid(&acme.uid()).unwrap().unwrap();
+        let employees = ds.related(&acme, Some(RelType::Employs));
+        assert_eq!(employees.len(), 1);
+        assert_eq!(employees[0].2.name(), "jane");
+        // on update too, only once
+        let mut jane = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        jane.add_relation(&Rel::new(&tim).with_kind(RelType::ManagerOf));
+        ds.update(&jane).unwrap();
+        ds.update(&jane).unwrap();
+        assert_eq!(
+            kinds(&ds, &tim.uid()),
+            vec![("reports_to".to_owned(), jane.uid())]
+        );
+        assert_eq!(kinds(&ds, &acme.uid()).len(), 1);
+        // the inverse already there is not added again
+        let partner = Rel::new(&acme).with_kind(RelType::PartnerOf);
+        let mut tim = ds.get_by_uid(&tim.uid()).unwrap().unwrap();
+        tim.add_relation(&partner);
+        ds.update(&tim).unwrap();
+        assert_eq!(kinds(&ds, &acme.uid()).len(), 2);
+        tim.relationships.pop();
+        ds.update(&tim).unwrap();
+        tim.add_relation(&partner);
+        ds.update(&tim).unwrap();
+        assert_eq!(kinds(&ds, &acme.uid()).len(), 2);
+        // the changes are audited
+        assert_eq!(ds.audit(&acme.uid(), None).len(), 3);
+    }
+
+    #[test]
+    fn test_related() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let acme = Entity::from("acme").unwrap().with_sponsor(&bob);
+        let ceo = Rel {
+            kind: RelType::Role("ceo".to_owned(), utils::today(), None),
+            target: acme.uid,
+            weight: 0,
+        };
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_relation(&ceo)
+            .with_relation(&Rel::new(&bob));
+        let carl = Entity::from("carl")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_relation(&Rel::new(&acme));
+        let dan = Entity::from("dan")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_relation(&Rel::new(&carl));
+        for e in [&bob, &acme, &jane, &carl, &dan].iter() {
+            ds.insert(e).unwrap();
+        }
+        let names = |found: Vec<(Direction, RelType, Entity)>| {
+            found
+                .iter()
+                .map(|(d, _, e)| (*d, e.name().to_owned()))
+                .collect::<Vec<_>>()
+        };
+        // both directions
+        assert_eq!(
+            names(ds.related(&acme, None)),
+            vec![
+                (Direction::Incoming, "carl".to_owned()),
+                (Direction::Incoming, "jane".to_owned())
+            ]
+        );
+        assert_eq!(
+            names(ds.related(&jane, None)),
+            vec![
+                (Direction::Outgoing, "acme".to_owned()),
+                (Direction::Outgoing, "bob".to_owned())
+            ]
+        );
+        // filter by kind
+        let role = RelType::Role("ceo".to_owned(), utils::date(1, 1, 2000), None);
+        assert_eq!(
+            names(ds.related(&acme, Some(role))),
+            vec![(Direction::Incoming, "jane".to_owned())]
+        );
+        assert_eq!(ds.related(&acme, Some(RelType::RelatedTo)).len(), 1);
+        // walk
+        let walk = |depth: usize| {
+            ds.neighbors(&dan.uid(), depth)
+                .iter()
+                .map(|(n, e)| (*n, e.name().to_owned()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(walk(1), vec![(1, "carl".to_owned())]);
+        assert_eq!(
+            walk(4),
+            vec![
+                (1, "carl".to_owned()),
+                (2, "acme".to_owned()),
+                (3, "jane".to_owned()),
+                (4, "bob".to_owned())
+            ]
+        );
+        assert_eq!(ds.neighbors("missing", 2).len(), 0);
+        // the index follows the updates
+        let mut jane = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        jane.relationships.retain(|r| r.target != acme.uid);
+        ds.update(&jane).unwrap();
+        assert_eq!(
+            names(ds.related(&acme, None)),
+            vec![(Direction::Incoming, "carl".to_owned())]
+        );
+        assert_eq!(ds.dependents(&acme).unwrap(), vec![carl.uid]);
+    }
+
+    #[test]
+    fn test_score() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let acme = Entity::from("acme")
+            .unwrap()
+            .with_class("org")
+            .with_sponsor(&bob);
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_relation(&Rel::new(&acme));
+        for e in [&bob, &acme, &jane].iter() {
+            ds.insert(e).unwrap();
+        }
+        // the entity alone has no relationships
+        assert_eq!(
+            acme.completeness_score()
+                .missing()
+                .contains(&"relationships"),
+            true
+        );
+        // but jane points to it
+        let c = ds.score(&acme);
+        assert_eq!(c.missing().contains(&"relationships"), false);
+        assert_eq!(c.score(), 7);
+        assert_eq!(ds.score(&jane).score(), 2);
+        assert_eq!(ds.score(&bob).score(), 0);
+    }
+
+    #[test]
+    fn test_tags() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(Tag::from("skill", "rust"))
+            .with_tag(Tag::from("skill", "go"));
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_tag(Tag::from("feat", "Rust"))
+            .with_tag(Tag::Generic("friends".to_owned()));
+        let mut carl = Entity::from("carl").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        ds.insert(&carl).unwrap();
+        let names = |found: Vec<Entity>| {
+            found
+                .iter()
+                .map(|e| e.name().to_owned())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names(ds.by_tag("skill", "rust")), vec!["bob", "jane"]);
+        assert_eq!(names(ds.by_tag("feat", "go")), vec!["bob"]);
+        assert_eq!(names(ds.by_tag("feat", "")), vec!["bob", "jane"]);
+        assert_eq!(names(ds.by_tag("tag", "friends")), vec!["jane"]);
+        assert_eq!(ds.by_tag("group", "friends").len(), 0);
+        assert_eq!(
+            ds.tags(),
+            vec![
+                ("feat:go".to_owned(), 1),
+                ("feat:rust".to_owned(), 2),
+                ("tag:friends".to_owned(), 1)
+            ]
+        );
+        // the index follows the updates
+        let mut jane = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        jane.tags.clear();
+        ds.update(&jane).unwrap();
+        assert_eq!(names(ds.by_tag("skill", "rust")), vec!["bob"]);
+        assert_eq!(ds.tags().len(), 2);
+        // nested tags match their parents
+        jane.add_tag(Tag::from("skill", "rust/async"));
+        jane.add_tag(Tag::from("skill", "rustacean"));
+        ds.update(&jane).unwrap();
+        carl.add_tag(Tag::from("skill", "rust/embedded/arm"));
+        ds.update(&carl).unwrap();
+        assert_eq!(
+            names(ds.by_tag("skill", "rust")),
+            vec!["bob", "carl", "jane"]
+        );
+        assert_eq!(names(ds.by_tag("skill", "rust/embedded")), vec!["carl"]);
+        assert_eq!(names(ds.by_tag("skill", "rustacean")), vec!["jane"]);
+        let f = AgendaFilter::default().with_tag_prefix("skill:rust/embedded");
+        assert_eq!(f.matches(&carl), true);
+        assert_eq!(f.matches(&jane), false);
+        // renaming rewrites the nested tags
+        let n = ds
+            .rename_tag(
+                &Tag::from("skill", "rust"),
+                &Tag::from("skill", "lang/Rust"),
+            )
+            .unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(ds.by_tag("skill", "rust").len(), 0);
+        assert_eq!(
+            names(ds.by_tag("skill", "lang/rust")),
+            vec!["bob", "carl", "jane"]
+        );
+        assert_eq!(
+            names(ds.by_tag("skill", "lang/rust/embedded/arm")),
+            vec!["carl"]
+        );
+        let carl = ds.get_by_uid(&carl.uid()).unwrap().unwrap();
+        assert_eq!(carl.get_tags(), vec!["lang/Rust/embedded/arm"]);
+        // the siblings sharing a prefix are left alone
+        assert_eq!(names(ds.by_tag("skill", "rustacean")), vec!["jane"]);
+        assert_eq!(
+            ds.rename_tag(&Tag::from("skill", ""), &Tag::from("skill", "x"))
+                .is_err(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_sponsorship_tree() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        let alice = Entity::from("alice").unwrap().with_sponsor(&jane);
+        let carl = Entity::from("carl").unwrap().with_sponsor(&alice);
+        for e in [&bob, &jane, &tim, &alice, &carl].iter() {
+            ds.insert(e).unwrap();
+        }
+        // bob does not sponsor itself again
+        let tree = ds.sponsorship_tree(&bob.uid(), 10).unwrap();
+        assert_eq!(tree.descendants, 4);
+        assert_eq!(tree.sponsored.len(), 2);
+        assert_eq!(tree.is_truncated(), false);
+        let mut uids = tree.uids();
+        assert_eq!(uids.len(), 5);
+        assert_eq!(uids[0], bob.uid());
+        uids.sort();
+        uids.dedup();
+        assert_eq!(uids.len(), 5);
+        // the depth cuts the branches but not the count
+        let tree = ds.sponsorship_tree(&jane.uid(), 1).unwrap();
+        assert_eq!(tree.descendants, 2);
+        assert_eq!(tree.uids(), vec![jane.uid(), alice.uid()]);
+        assert_eq!(tree.sponsored[0].depth, 1);
+        assert_eq!(tree.sponsored[0].descendants, 1);
+        assert_eq!(tree.sponsored[0].is_truncated(), true);
+        let tree = ds.sponsorship_tree(&jane.uid(), 0).unwrap();
+        assert_eq!(tree.uids(), vec![jane.uid()]);
+        assert_eq!(tree.descendants, 2);
+        // leaves and missing entities
+        assert_eq!(ds.sponsorship_tree(&carl.uid(), 3).unwrap().descendants, 0);
+        assert_eq!(
+            ds.sponsorship_tree(&Entity::from("ghost").unwrap().uid(), 1)
+                .err(),
+            Some(DataError::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_lock() {
+        let d = TempDir::new().unwrap();
+        let ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(d.path().join(LOCK_FILE)).unwrap(),
+            std::process::id().to_string()
+        );
+        // a second handle is refused
+        assert_eq!(
+            DataStore::open(d.path()).err(),
+            Some(DataError::Locked(std::process::id()))
+        );
+        let wait = std::time::Duration::from_millis(300);
+        assert_eq!(
+            DataStore::open_wait(d.path(), wait).err(),
+            Some(DataError::Locked(std::process::id()))
+        );
+        // wait for the datastore to be released
+        let t = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(200));
+            drop(ds);
+        });
+        let ds = DataStore::open_wait(d.path(), std::time::Duration::from_secs(10)).unwrap();
+        t.join().unwrap();
+        drop(ds);
+        assert_eq!(d.path().join(LOCK_FILE).exists(), false);
+    }
+
+    #[test]
+    fn test_open_temporary() {
+        let mut ds = DataStore::open_temporary().unwrap();
+        assert_eq!(ds.is_empty(), true);
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        assert_eq!(ds.get_by_uid(&bob.uid()).unwrap(), Some(bob.clone()));
+        assert_eq!(ds.search("bob").len(), 1);
+        // each temporary datastore is a new one
+        assert_eq!(DataStore::open_temporary().unwrap().is_empty(), true);
+        // the ones for the sealed data stay in memory
+        assert_eq!(
+            DataStore::open_in_memory().is_ok(),
+            cfg!(target_os = "linux")
+        );
+    }
+
+    #[test]
+    fn test_iter_entities() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(ds.iter_entities().count(), 0);
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        for e in [&bob, &jane, &tim].iter() {
+            ds.insert(e).unwrap();
+        }
+        let mut names = ds
+            .iter_entities()
+            .map(|e| e.unwrap().name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["bob", "jane", "tim"]);
+        // a broken entity does not stop the iteration
+        ds.entities.insert(jane.uid(), "broken").unwrap();
+        let (ok, err): (Vec<_>, Vec<_>) = ds.iter_entities().partition(|e| e.is_ok());
+        assert_eq!(ok.len(), 2);
+        assert_eq!(err.len(), 1);
+    }
+
+    #[test]
+    fn test_stats() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let stats = ds.stats().unwrap();
+        assert_eq!(stats.entities, 0);
+        assert_eq!(stats.events, 0);
+        let bob = Entity::from("bob")
+            .unwrap()
+            .with_class("person")
+            .self_sponsored()
+            .with_tag(Tag::from("feat", "rust"))
+            .with_tag(Tag::from("skill", "go"));
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_class("person")
+            .with_sponsor(&bob)
+            .with_tag(Tag::from("group", "family"))
+            .with_next_action(date(1, 1, 2021), "call".to_owned());
+        let acme = Entity::from("acme")
+            .unwrap()
+            .with_class("org")
+            .with_sponsor(&bob)
+            .with_tag(Tag::from("group", "client"))
+            .with_next_action(date(1, 1, 2099), "call".to_owned());
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        ds.insert(&acme).unwrap();
+        let call = Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(jane.uid)],
+        );
+        ds.record(&call).unwrap();
+        let stats = ds.stats().unwrap();
+        assert_eq!(stats.entities, 3);
+        assert_eq!(stats.by_class.get("person"), Some(&2));
+        assert_eq!(stats.by_class.get("org"), Some(&1));
+        // once per entity and prefix
+        assert_eq!(stats.by_tag_prefix.get("feat"), Some(&1));
+        assert_eq!(stats.by_tag_prefix.get("group"), Some(&2));
+        assert_eq!(stats.events, ds.events.len());
+        let month = call.recorded_at.format("%Y-%m").to_string();
+        assert_eq!(stats.events_by_month.get(&month) > Some(&0), true);
+        assert_eq!(stats.events_by_month.values().sum::<usize>(), stats.events);
+        assert_eq!(stats.overdue, 1);
+        assert_eq!(stats.tree_sizes.get(TABLE_ENTITIES) > Some(&0), true);
+        assert_eq!(stats.tree_sizes.get(TABLE_EVENTS) > Some(&0), true);
+    }
+
+    #[test]
+    fn test_check() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com");
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        let call = Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(jane.uid)],
+        );
+        ds.record(&call).unwrap();
+        let report = ds.check(false).unwrap();
+        assert_eq!(report.is_healthy(), true);
+        assert_eq!(report.checked > 0, true);
+        // break the references behind the datastore back
+        let ghost = Entity::from("ghost").unwrap();
+        ds.ids
+            .insert(handle_key("email", "ghost@acme.com"), ghost.uid().as_str())
+            .unwrap();
+        ds.events.remove(call.uid()).unwrap();
+        let mut moved = jane.clone();
+        moved.next_action(utils::date(1, 1, 2030), "".to_owned());
+        ds.actions
+            .insert(action_key(&moved), jane.uid().as_str())
+            .unwrap();
+        let report = ds.check(false).unwrap();
+        assert_eq!(report.repaired, false);
+        assert_eq!(report.issues.len(), 4);
+        assert_eq!(
+            report.issues[0],
+            Inconsistency::DanglingHandle(handle_key("email", "ghost@acme.com"), ghost.uid())
+        );
+        assert_eq!(
+            report
+                .issues
+                .iter()
+                .filter(
+                    |i| matches!(i, Inconsistency::DanglingEventLink(_, uid) if *uid == call.uid())
+                )
+                .count(),
+            2
+        );
+        assert_eq!(
+            report.issues[3],
+            Inconsistency::OrphanedAction(action_key(&moved), jane.uid())
+        );
+        // repairing requires the admin role
+        ds.set_principal(Some(&jane)).unwrap();
+        assert_eq!(ds.check(true).err(), Some(DataError::PermissionDenied));
+        ds.set_principal(None).unwrap();
+        let report = ds.check(true).unwrap();
+        assert_eq!(report.repaired, true);
+        assert_eq!(ds.check(false).unwrap().is_healthy(), true);
+        // the valid keys are still there
+        assert_eq!(ds.get_by_id("email", "jane@acme.com").unwrap(), Some(jane));
+        assert_eq!(
+            ds.agenda_until(&utils::date(1, 1, 2030), &AgendaFilter::default(), 0, 0)
+                .total,
+            2
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com")
+            .with_tag(Tag::Generic("friends".to_owned()));
+        let acme = Entity::from("acme")
+            .unwrap()
+            .with_sponsor(&jane)
+            .with_relation(&Rel::new(&bob));
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        ds.insert(&acme).unwrap();
+        // a shared event and one about jane only
+        let call = Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(jane.uid)],
+        );
+        ds.record(&call).unwrap();
+        ds.record(&Event::log("touched", &jane, None)).unwrap();
+        // jane sponsors acme, acme is related to bob
+        assert_eq!(
+            ds.remove(&jane).err(),
+            Some(DataError::HasDependents(vec![acme.uid]))
+        );
+        assert_eq!(ds.dependents(&bob).unwrap().len(), 2);
+        // unknown entities
+        let ghost = Entity::from("ghost").unwrap().with_sponsor(&bob);
+        assert_eq!(ds.remove(&ghost).err(), Some(DataError::NotFound));
+        // remove acme, then jane
+        assert_eq!(ds.remove(&acme).unwrap(), acme.uid);
+        assert_eq!(ds.dependents(&bob).unwrap(), vec![jane.uid]);
+        assert_eq!(ds.remove(&jane).unwrap(), jane.uid);
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap(), None);
+        assert_eq!(ds.sponsored_by(&bob).len(), 1);
+        assert_eq!(ds.search("jane").len(), 0);
+        assert_eq!(
+            ds.agenda_until(&jane.next_action_date, &AgendaFilter::default(), 0, 0)
+                .total,
+            1
+        );
+        assert_eq!(ds.dependents(&bob).unwrap().len(), 0);
+        // the handles are free again
+        assert_eq!(
+            ds.ids.get(handle_key("email", "jane@acme.com")).unwrap(),
+            None
+        );
+        assert_eq!(ds.tags.scan_prefix("tag:friends").count(), 0);
+        assert_eq!(ds.edges.iter().count(), 0);
+        assert_eq!(ds.reverse_edges.iter().count(), 0);
+        // the shared event is kept in bob's history
+        let evts = ds.events(&bob, EventFilter::Any);
+        assert_eq!(evts.iter().any(|e| e.uid == call.uid), true);
+        assert_eq!(
+            ds.events.iter().count(),
+            ds.events(&bob, EventFilter::Any).len()
+        );
+        // jane is gone
+        assert_eq!(ds.events(&jane, EventFilter::Any).len(), 0);
+    }
+
+    #[test]
+    fn test_purge() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let file = d.path().join("cv.pdf");
+        std::fs::write(&file, "jane's cv").unwrap();
+        let mut jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        jane.attach(ds.store_attachment(&file).unwrap());
+        ds.add(&jane).unwrap();
+        jane.class = "person".to_owned();
+        ds.update(&jane).unwrap();
+        // a shared event, the log of the add and one about jane only
+        let call = Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(jane.uid)],
+        );
+        ds.record(&call).unwrap();
+        ds.record(&Event::log("touched", &jane, None)).unwrap();
+        assert_eq!(ds.audit(&jane.uid(), None).len(), 2);
+        // unknown entities
+        assert_eq!(
+            ds.purge(&utils::id(&model::Uuid::new_v4())).err(),
+            Some(DataError::NotFound)
+        );
+        let report = ds.purge(&jane.uid()).unwrap();
+        assert_eq!(report.entity, jane.uid);
+        assert_eq!(report.events_removed, 2);
+        assert_eq!(report.events_scrubbed, 1);
+        // the removal itself is not recorded in the deleted trail
+        assert_eq!(report.audit_removed, 2);
+        assert_eq!(report.attachments_removed, 1);
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap(), None);
+        assert_eq!(ds.audit(&jane.uid(), None).len(), 0);
+        assert_eq!(ds.attachments.len(), 0);
+        // the shared event is left with a tombstone
+        let evts = ds.events(&bob, EventFilter::Any);
+        let evt = evts.iter().find(|e| e.uid == call.uid).unwrap();
+        assert_eq!(evt.actors.len(), 2);
+        assert_eq!(evt.actors[1], Actor::Subject(model::TOMBSTONE));
+        assert_eq!(evt.actors[1].is_tombstone(), true);
+        assert_eq!(
+            ds.events.iter().count(),
+            ds.events(&bob, EventFilter::Any).len()
+        );
+        assert_eq!(ds.check(false).unwrap().is_healthy(), true);
+    }
+
+    #[test]
+    fn test_archive() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let mut jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        jane.next_action(utils::date(1, 1, 2021), "call".to_string());
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        for _ in 0..5 {
+            ds.record(&Event::log("postponed", &jane, None)).unwrap();
+        }
+        let until = utils::date(2, 1, 2021);
+        assert_eq!(
+            ds.agenda_until(&until, &AgendaFilter::default(), 0, 0)
+                .items,
+            vec![jane.clone()]
+        );
+        assert_eq!(ds.search("jane").len(), 1);
+        let hinted = |ds: &DataStore| ds.propose_edits(&bob).iter().any(|(_, e)| *e == jane);
+        assert_eq!(hinted(&ds), true);
+        // archive jane
+        ds.archive(&jane).unwrap();
+        let archived = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        assert_eq!(archived.archived, Some(utils::today()));
+        assert_eq!(
+            ds.agenda_until(&until, &AgendaFilter::default(), 0, 0)
+                .total,
+            0
+        );
+        assert_eq!(ds.search("jane").len(), 0);
+        assert_eq!(ds.search_with("jane", true).len(), 1);
+        assert_eq!(ds.resolve("jane").len(), 0);
+        assert_eq!(ds.resolve_with("jane", true).len(), 1);
+        assert_eq!(hinted(&ds), false);
+        // updating an archived entity keeps it out of the agenda
+        let mut e = archived.clone();
+        e.next_action(utils::date(1, 12, 2020), "meet".to_string());
+        ds.update(&e).unwrap();
+        assert_eq!(
+            ds.agenda_until(&until, &AgendaFilter::default(), 0, 0)
+                .total,
+            0
+        );
+        // archiving twice is a no-op
+        ds.archive(&jane).unwrap();
+        // the history is kept
+        let logs = ds.events(&jane, EventFilter::LogsWithMessage("archived".to_string()));
+        assert_eq!(logs.len(), 1);
+        let logs = ds.events(&jane, EventFilter::LogsWithMessage("postponed".to_string()));
+        assert_eq!(logs.len(), 5);
+        // bring jane back
+        ds.unarchive(&jane).unwrap();
+        assert_eq!(ds.get_by_uid(&jane.uid()).unwrap().unwrap().archived, None);
+        assert_eq!(
+            ds.agenda_until(&until, &AgendaFilter::default(), 0, 0)
+                .total,
+            1
+        );
+        assert_eq!(ds.search("jane").len(), 1);
+        // unknown entities
+        let ghost = Entity::from("ghost").unwrap().with_sponsor(&bob);
+        assert_eq!(ds.archive(&ghost).err(), Some(DataError::NotFound));
+    }
+
+    #[test]
+    fn test_interaction_stats() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        let actors = [Actor::RecordedBy(bob.uid), Actor::Lead(jane.uid)];
+        let on = |evt: Event, days: i64| {
+            let mut evt = evt;
+            evt.recorded_at = evt.recorded_at - Duration::days(days);
+            evt
+        };
+        let events = vec![
+            on(
+                Event::call(20, InteractionDirection::Incoming, None, &actors),
+                1,
+            ),
+            on(Event::meeting(60, None, &actors), 10),
+            on(
+                Event::email(InteractionDirection::Outgoing, None, &actors),
+                20,
+            ),
+            on(
+                Event::call(10, InteractionDirection::Outgoing, None, &actors),
+                40,
+            ),
+            // too old
+            on(Event::meeting(90, None, &actors), 100),
+            // not an interaction
+            Event::action("cli", "note", 1, None, &actors),
+        ];
+        for evt in events.iter() {
+            ds.record(evt).unwrap();
+        }
+        let today = utils::today();
+        let s = ds.interaction_stats(
+            &jane,
+            &(today - Duration::days(60)),
+            &(today + Duration::days(1)),
+        );
+        assert_eq!(s.total, 4);
+        assert_eq!(s.by_channel.get(&Channel::Call), Some(&2));
+        assert_eq!(s.by_channel.get(&Channel::Meeting), Some(&1));
+        assert_eq!(s.by_channel.get(&Channel::Email), Some(&1));
+        assert_eq!((s.incoming, s.outgoing), (1, 3));
+        assert_eq!(s.minutes, 90);
+        assert_eq!(s.last, Some(today - Duration::days(1)));
+        assert_eq!((s.per_month - 4.0 * 30.0 / 61.0).abs() < 1e-9, true);
+        // recording them is not taking part
+        let s = ds.interaction_stats(
+            &bob,
+            &(today - Duration::days(60)),
+            &(today + Duration::days(1)),
+        );
+        assert_eq!(s.total, 0);
+        assert_eq!(s.last, None);
+        // the interactions are contacts
+        assert_eq!(ds.last_contact(&jane), Some(today));
+        assert_eq!(ds.events(&jane, EventFilter::Interactions).len(), 5);
+    }
+
+    #[test]
+    fn test_near() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let berlin = |street: &str| Address::new(street, "Berlin", "Germany");
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_address(
+                berlin("Unter den Linden 1")
+                    .with_location(52.517, 13.389)
+                    .unwrap(),
+            );
+        let tim = Entity::from("tim")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_address(berlin(""));
+        // about 25 km away
+        let ann = Entity::from("ann")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_address(
+                Address::new("", "Potsdam", "Germany")
+                    .with_location(52.3906, 13.0645)
+                    .unwrap(),
+            );
+        // about 250 km away
+        let carl = Entity::from("carl")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_address(
+                Address::new("", "Hamburg", "Germany")
+                    .with_location(53.5511, 9.9937)
+                    .unwrap(),
+            );
+        let mut dan = Entity::from("dan")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_address(berlin(""));
+        dan.archive(today());
+        for e in [&bob, &jane, &tim, &ann, &carl, &dan].iter() {
+            ds.insert(e).unwrap();
+        }
+        let names = |found: Vec<Entity>| {
+            found
+                .iter()
+                .map(|e| e.name().to_owned())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names(ds.near("berlin")), vec!["jane", "tim", "ann"]);
+        assert_eq!(names(ds.near("Hamburg")), vec!["carl"]);
+        assert_eq!(ds.near("Paris").len(), 0);
+        // without coordinates only the city counts
+        let mut jane = jane;
+        jane.set_address(Some(berlin("")));
+        ds.update(&jane).unwrap();
+        assert_eq!(names(ds.near("berlin")), vec!["jane", "tim"]);
+        // the city is searchable
+        let r = ds.search_detailed("potsdam");
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].entity.uid(), ann.uid());
+        assert_eq!(r[0].field, MatchField::Field);
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        // parsing
+        let lc = "Lead > prospect>client>dormant>client"
+            .parse::<Lifecycle>()
+            .unwrap();
+        assert_eq!(lc.states, vec!["lead", "prospect", "client", "dormant"]);
+        assert_eq!(lc.transitions.len(), 4);
+        assert_eq!(lc.to_string(), "lead>prospect>client>dormant>client");
+        assert_eq!(Lifecycle::default_for("org"), Some(lc.clone()));
+        assert_eq!(Lifecycle::default_for("person"), None);
+        let forked = "lead>client,lead>lost".parse::<Lifecycle>().unwrap();
+        assert_eq!(forked.next_states(Some("lead")), vec!["client", "lost"]);
+        assert_eq!(forked.to_string(), "lead>client,lead>lost");
+        for spec in ["", "lead", "lead>", "lead>lead"].iter() {
+            assert_eq!(spec.parse::<Lifecycle>().is_err(), true, "{}", spec);
+        }
+        // transitions
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let acme = Entity::from("acme")
+            .unwrap()
+            .with_class("org")
+            .with_sponsor(&bob);
+        let initech = Entity::from("initech")
+            .unwrap()
+            .with_class("org")
+            .with_sponsor(&bob);
+        for e in [&bob, &acme, &initech].iter() {
+            ds.insert(e).unwrap();
+        }
+        // the first state can be any
+        assert_eq!(
+            ds.move_to_stage(&acme, "prospect").unwrap().stage,
+            Some("prospect".to_owned())
+        );
+        ds.move_to_stage(&initech, "Lead").unwrap();
+        assert_eq!(ds.move_to_stage(&acme, "lead").is_err(), true);
+        ds.move_to_stage(&acme, "client").unwrap();
+        ds.move_to_stage(&acme, "client").unwrap();
+        let names = |v: Vec<Entity>| v.into_iter().map(|e| e.name).collect::<Vec<_>>();
+        assert_eq!(names(ds.by_state("client")), vec!["acme"]);
+        assert_eq!(names(ds.by_state("lead")), vec!["initech"]);
+        assert_eq!(ds.by_state("prospect").len(), 0);
+        // recorded as events
+        let logs = ds.events(&acme, EventFilter::LogsWithMessage("stage".to_string()));
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].content, Some("prospect -> client".to_string()));
+        // no lifecycle
+        assert_eq!(ds.move_to_stage(&bob, "lead").is_err(), true);
+        // custom lifecycle, the states that are gone are like no state
+        let custom = "new>won,new>lost".parse::<Lifecycle>().unwrap();
+        ds.set_lifecycle("org", Some(&custom)).unwrap();
+        assert_eq!(ds.lifecycle("org"), Some(custom));
+        ds.move_to_stage(&acme, "won").unwrap();
+        assert_eq!(ds.move_to_stage(&acme, "lost").is_err(), true);
+        ds.set_lifecycle("org", None).unwrap();
+        assert_eq!(ds.lifecycle("org"), Lifecycle::default_for("org"));
+        // archived entities are left out
+        ds.archive(&initech).unwrap();
+        assert_eq!(ds.by_state("lead").len(), 0);
+    }
+
+    #[test]
+    fn test_contact_every() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let today = utils::today();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        // planned within the cadence
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_contact_every(TimeWindow::Week(2))
+            .with_next_action(today + Duration::days(3), "lunch".to_string());
+        // planned too late
+        let tim = Entity::from("tim")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_contact_every(TimeWindow::Week(2))
+            .with_next_action(today + Duration::days(60), "later".to_string());
+        // slipped past the cadence
+        let mut ann = Entity::from("ann")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_contact_every(TimeWindow::Week(2));
+        ann.created_on = today - Duration::days(30);
+        // no cadence
+        let carl = Entity::from("carl").unwrap().with_sponsor(&bob);
+        for e in [&bob, &jane, &tim, &ann, &carl].iter() {
+            ds.insert(e).unwrap();
+        }
+        assert_eq!(ds.contact_due(&jane), Some(today + Duration::days(14)));
+        assert_eq!(ds.contact_due(&carl), None);
+        assert_eq!(ds.out_of_touch(&ann, &today), 16);
+        assert_eq!(ds.out_of_touch(&jane, &today), 0);
+        // the late actions are brought forward
+        let changed = ds.schedule_contacts().unwrap();
+        assert_eq!(changed.len(), 2);
+        let get = |ds: &DataStore, e: &Entity| ds.get_by_uid(&e.uid()).unwrap().unwrap();
+        assert_eq!(get(&ds, &jane).next_action_note, "lunch");
+        let t = get(&ds, &tim);
+        assert_eq!(t.next_action_date, today + Duration::days(14));
+        assert_eq!(t.next_action_note, CONTACT_NOTE);
+        assert_eq!(get(&ds, &ann).next_action_date, today - Duration::days(16));
+        // nothing else to do
+        assert_eq!(ds.schedule_contacts().unwrap().len(), 0);
+        // the slipped entities are hinted
+        let hints = ds.hints(&bob);
+        let h = hints.iter().find(|h| h.entity.uid == ann.uid).unwrap();
+        assert_eq!(h.kind, EditType::OutOfTouch(today - Duration::days(16)));
+        assert_eq!(h.metric, 16);
+        // a contact moves the scheduled action along
+        ds.record(&Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Starring(ann.uid)],
+        ))
+        .unwrap();
+        assert_eq!(ds.out_of_touch(&ann, &today), 0);
+        assert_eq!(ds.schedule_contacts().unwrap(), vec![ann.uid]);
+        assert_eq!(get(&ds, &ann).next_action_date, today + Duration::days(14));
+        assert_eq!(ds.postponed_count(&ann), 0);
+        // the viewers cannot schedule
+        ds.set_principal(Some(&carl)).unwrap();
+        assert_eq!(
+            ds.schedule_contacts().err(),
+            Some(DataError::PermissionDenied)
+        );
+    }
+
+    #[test]
+    fn test_quality_changes() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let mut jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        jane.set_quality(RelQuality::Friendly(date(1, 1, 2021), None));
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        let changes = |ds: &DataStore, e: &Entity| {
+            ds.events(e, EventFilter::LogsWithMessage("quality".to_string()))
+        };
+        // a change of kind is logged
+        jane.set_quality(RelQuality::Tense(date(1, 3, 2021), None));
+        ds.update(&jane).unwrap();
+        let logs = changes(&ds, &jane);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].content, Some("🙂 -> ☹️".to_string()));
+        // the same kind is not
+        jane.set_quality(RelQuality::Tense(date(2, 3, 2021), None));
+        ds.update(&jane).unwrap();
+        assert_eq!(changes(&ds, &jane).len(), 1);
+        // the history is stored
+        let stored = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        assert_eq!(stored.quality_history().len(), 3);
+    }
+
+    #[test]
+    fn test_act_as() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let acme = Entity::from("acme").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        // the delegate must exist
+        assert_eq!(ds.act_as(Some(&acme)).err(), Some(DataError::NotFound));
+        ds.insert(&acme).unwrap();
+        // bob logs a note for jane
+        ds.act_as(Some(&jane)).unwrap();
+        assert_eq!(ds.delegate(), Some(jane.uid));
+        let note = Event::action(
+            "cli",
+            "note",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(acme.uid)],
+        );
+        ds.record(&note).unwrap();
+        let evts = ds.events(&jane, EventFilter::Role(ActorRole::OnBehalfOf));
+        assert_eq!(evts.len(), 1);
+        assert_eq!(evts[0].actors.len(), 3);
+        assert_eq!(
+            ds.events(&bob, EventFilter::Role(ActorRole::RecordedBy))
+                .len(),
+            1
+        );
+        // the logs of the changes are delegated too
+        ds.record(&Event::log("touched", &acme, None)).unwrap();
+        let mut acme = acme;
+        acme.set_quality(RelQuality::Tense(today(), None));
+        ds.update(&acme).unwrap();
+        let logs = ds.events(&jane, EventFilter::Logs);
+        assert_eq!(logs.len(), 2);
+        // and the audit trail tells for whom
+        let trail = ds.audit(&acme.uid(), None);
+        assert_eq!(trail.last().unwrap().on_behalf_of, Some(jane.uid));
+        assert_eq!(ds.events(&acme, EventFilter::Logs).len(), 2);
+        // a viewer cannot act for someone else
+        ds.act_as(None).unwrap();
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        ds.insert(&tim).unwrap();
+        ds.set_principal(Some(&tim)).unwrap();
+        assert_eq!(
+            ds.act_as(Some(&jane)).err(),
+            Some(DataError::PermissionDenied)
+        );
+        ds.set_principal(None).unwrap();
+        // back to bob
+        ds.act_as(None).unwrap();
+        let note = Event::action(
+            "cli",
+            "note",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Subject(acme.uid)],
+        );
+        ds.record(&note).unwrap();
+        assert_eq!(
+            ds.events(&jane, EventFilter::Role(ActorRole::OnBehalfOf))
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_roles() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag());
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        ds.init(&bob).unwrap();
+        ds.add(&jane).unwrap();
+        ds.add(&tim).unwrap();
+        assert_eq!(ds.role_of(&bob), AccessRole::Owner);
+        assert_eq!(ds.role_of(&jane), AccessRole::Viewer);
+        // bob is in charge
+        ds.set_principal(Some(&bob)).unwrap();
+        ds.grant(&jane, Some(AccessRole::Admin)).unwrap();
+        ds.grant(&tim, Some(AccessRole::Viewer)).unwrap();
+        assert_eq!(ds.role_of(&jane), AccessRole::Admin);
+        assert_eq!(ds.members(AccessRole::Viewer).len(), 1);
+        // tim can only read
+        ds.set_principal(Some(&tim)).unwrap();
+        let tim = ds.get_by_uid(&tim.uid()).unwrap().unwrap();
+        let mut t = tim.clone();
+        t.next_action_note = "hello".to_string();
+        assert_eq!(ds.update(&t).err(), Some(DataError::PermissionDenied));
+        let acme = Entity::from("acme").unwrap().with_sponsor(&bob);
+        assert_eq!(ds.add(&acme).err(), Some(DataError::PermissionDenied));
+        assert_eq!(
+            ds.record(&Event::log("touched", &tim, None)).err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(ds.search("tim").len(), 1);
+        // jane can promote tim but not to owner
+        ds.set_principal(Some(&jane)).unwrap();
+        assert_eq!(
+            ds.grant(&tim, Some(AccessRole::Owner)).err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(
+            ds.grant(&bob, None).err(),
+            Some(DataError::PermissionDenied)
+        );
+        ds.grant(&tim, Some(AccessRole::Editor)).unwrap();
+        assert_eq!(ds.members(AccessRole::Viewer).len(), 0);
+        // tim can now edit but cannot grant himself more
+        ds.set_principal(Some(&tim)).unwrap();
+        ds.add(&acme).unwrap();
+        let mut t = ds.get_by_uid(&tim.uid()).unwrap().unwrap();
+        t.set_access_role(Some(AccessRole::Admin));
+        assert_eq!(ds.update(&t).err(), Some(DataError::PermissionDenied));
+        assert_eq!(ds.role_of(&tim), AccessRole::Editor);
+        // the principal must exist
+        assert_eq!(
+            ds.set_principal(Some(&Entity::from("x").unwrap())).err(),
+            Some(DataError::NotFound)
+        );
+        // the settings are for the admins
+        assert_eq!(
+            ds.set_agenda_buckets(&[]).err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(
+            ds.set_backup_every(1).err(),
+            Some(DataError::PermissionDenied)
+        );
+    }
+
+    #[test]
+    fn test_import_roles() {
+        let d = TempDir::new().unwrap();
+        let p = d.path().join("export.json");
+        let mut ds = DataStore::open(&d.path().join("db")).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag());
+        let mut jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        jane.set_access_role(Some(AccessRole::Admin));
+        ds.init(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        // an export where jane is the owner and bob is not
+        let mut other = DataStore::open_temporary().unwrap();
+        let mut b = bob.clone();
+        b.set_access_role(None);
+        let mut j = jane.clone();
+        j.set_access_role(Some(AccessRole::Owner));
+        other.insert(&b).unwrap();
+        other.insert(&j).unwrap();
+        ds.set_principal(Some(&jane)).unwrap();
+        for full in [false, true].iter() {
+            let format = || match full {
+                true => ExportFormat::FullJson,
+                false => ExportFormat::Json,
+            };
+            other.export(&p, format()).unwrap();
+            assert_eq!(
+                ds.import_with(&p, format(), None, |_| Resolution::TakeImported)
+                    .err(),
+                Some(DataError::PermissionDenied)
+            );
+            assert_eq!(ds.role_of(&bob), AccessRole::Owner);
+            assert_eq!(ds.role_of(&jane), AccessRole::Admin);
+        }
+        // a merge cannot disable the owner nor set the passwords
+        let mut other = DataStore::open_temporary().unwrap();
+        let mut b = bob.clone();
+        b.set_disabled(true);
+        let mut j = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        j.pass = Some("forged".to_owned());
+        j.description = "the cto".to_owned();
+        other.insert(&b).unwrap();
+        other.insert(&j).unwrap();
+        other.export(&p, ExportFormat::Json).unwrap();
+        let r = ds
+            .import_as(&p, ExportFormat::Json, None, ImportMode::Merge)
+            .unwrap();
+        assert_eq!(r.imported, 1);
+        assert_eq!(r.skipped.len(), 1);
+        assert_eq!(
+            ds.get_by_uid(&bob.uid()).unwrap().unwrap().is_disabled(),
+            false
+        );
+        let merged = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        assert_eq!(merged.description, "the cto");
+        assert_eq!(merged.pass, jane.pass);
+        // the settings are restored by the owners only
+        ds.set_meta("motto", "kept").unwrap();
+        ds.export(&p, ExportFormat::FullJson).unwrap();
+        ds.set_meta("motto", "changed").unwrap();
+        ds.import(&p, ExportFormat::FullJson).unwrap();
+        assert_eq!(ds.get_meta("motto"), Some("changed".to_string()));
+        ds.set_principal(Some(&bob)).unwrap();
+        ds.import(&p, ExportFormat::FullJson).unwrap();
+        assert_eq!(ds.get_meta("motto"), Some("kept".to_string()));
+    }
+
+    #[test]
+    fn test_users() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag());
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        ds.init(&bob).unwrap();
+        ds.add(&tim).unwrap();
+        ds.set_principal(Some(&bob)).unwrap();
+        // a new entity and an existing one become users
+        ds.add_user(&jane, AccessRole::Admin, "secret").unwrap();
+        ds.add_user(&tim, AccessRole::Editor, "secret").unwrap();
+        let names = |ds: &DataStore| {
+            ds.users()
+                .iter()
+                .map(|u| u.name().to_owned())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names(&ds), vec!["bob", "jane", "tim"]);
+        let jane = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        assert_eq!(jane.authorized(Some(&utils::hash("secret"))).is_ok(), true);
+        // an editor cannot manage the users
+        ds.set_principal(Some(&tim)).unwrap();
+        assert_eq!(
+            ds.add_user(
+                &Entity::from("ann").unwrap().with_sponsor(&bob),
+                AccessRole::Viewer,
+                "x"
+            )
+            .err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(
+            ds.set_user_disabled(&jane, true).err(),
+            Some(DataError::PermissionDenied)
+        );
+        let mut j = jane.clone();
+        j.set_disabled(true);
+        assert_eq!(ds.update(&j).err(), Some(DataError::PermissionDenied));
+        // an admin can disable an editor but not an owner nor itself
+        ds.set_principal(Some(&jane)).unwrap();
+        assert_eq!(
+            ds.set_user_disabled(&bob, true).err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(ds.set_user_disabled(&jane, true).is_err(), true);
+        assert_eq!(
+            ds.set_user_disabled(&tim, true).unwrap().is_disabled(),
+            true
+        );
+        assert_eq!(ds.role_of(&tim), AccessRole::Editor);
+        // a disabled user cannot act nor be the principal
+        assert_eq!(
+            ds.set_principal(Some(&tim)).err(),
+            Some(DataError::PermissionDenied)
+        );
+        // and it is enabled back
+        ds.set_user_disabled(&tim, false).unwrap();
+        ds.set_principal(Some(&tim)).unwrap();
+        ds.add(&Entity::from("acme").unwrap().with_sponsor(&bob))
+            .unwrap();
+        // only users can be disabled
+        ds.set_principal(Some(&bob)).unwrap();
+        let acme = ds.search("acme")[0].clone();
+        assert_eq!(ds.set_user_disabled(&acme, true).is_err(), true);
+        // an admin cannot set the password of the owner nor of another admin
+        let ann = Entity::from("ann").unwrap().with_sponsor(&bob);
+        ds.add_user(&ann, AccessRole::Admin, "secret").unwrap();
+        ds.set_principal(Some(&jane)).unwrap();
+        assert_eq!(
+            ds.add_user(&bob, AccessRole::Owner, "taken").err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(
+            ds.add_user(&ann, AccessRole::Admin, "taken").err(),
+            Some(DataError::PermissionDenied)
+        );
+        let ann = ds.get_by_uid(&ann.uid()).unwrap().unwrap();
+        assert_eq!(ann.authorized(Some(&utils::hash("secret"))).is_ok(), true);
+        // but it can set its own and the one of an editor
+        ds.add_user(&jane, AccessRole::Admin, "mine").unwrap();
+        ds.add_user(&tim, AccessRole::Editor, "reset").unwrap();
+        let tim = ds.get_by_uid(&tim.uid()).unwrap().unwrap();
+        assert_eq!(tim.authorized(Some(&utils::hash("reset"))).is_ok(), true);
+        // an update does not change a password
+        assert_eq!(
+            ds.update(&tim.clone().with_password(Some(&"x".to_string())))
+                .is_err(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_reset_password() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag());
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_password(Some(&"forgotten".to_string()));
+        let tim = Entity::from("tim").unwrap().with_sponsor(&jane);
+        ds.init(&bob).unwrap();
+        ds.add(&jane).unwrap();
+        ds.add(&tim).unwrap();
+        // tim is not jane's sponsor
+        ds.set_principal(Some(&tim)).unwrap();
+        assert_eq!(
+            ds.issue_reset_token(&jane).err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(ds.reset_pending(&jane), false);
+        // bob is the sponsor
+        ds.set_principal(Some(&bob)).unwrap();
+        let token = ds.issue_reset_token(&jane).unwrap();
+        assert_eq!(ds.reset_pending(&jane), true);
+        assert_eq!(
+            ds.reset_password(&jane, "wrong", "new").err(),
+            Some(DataError::InvalidToken)
+        );
+        let jane = ds.reset_password(&jane, &token, "new").unwrap();
+        assert_eq!(jane.authorized(Some(&utils::hash("new"))).is_ok(), true);
+        // the token is gone
+        assert_eq!(ds.reset_pending(&jane), false);
+        assert_eq!(
+            ds.reset_password(&jane, &token, "again").err(),
+            Some(DataError::InvalidToken)
+        );
+        // both are in the audit log
+        let kinds = ds
+            .events(&jane, EventFilter::Any)
+            .into_iter()
+            .map(|e| e.kind)
+            .collect::<Vec<EventType>>();
+        assert!(kinds.contains(&EventType::Log("password reset".to_string())));
+        assert!(kinds.contains(&EventType::Log("password reset issued".to_string())));
+        assert_eq!(
+            ds.events(&bob, EventFilter::Role(ActorRole::RecordedBy))
+                .len(),
+            1
+        );
+        // bob as owner can also reset tim, that he doesn't sponsor
+        assert_eq!(ds.issue_reset_token(&tim).is_ok(), true);
+    }
+
+    #[test]
+    fn test_change_password() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag())
+            .with_password(Some(&"old".to_string()));
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        ds.init(&bob).unwrap();
+        ds.add(&jane).unwrap();
+        ds.set_principal(Some(&bob)).unwrap();
+        // the old password is checked
+        assert_eq!(
+            ds.change_password(&bob, "wrong", "new").err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(ds.change_password(&bob, "old", "").is_err(), true);
+        // a pending reset is dropped
+        ds.issue_reset_token(&bob).unwrap();
+        let bob = ds.change_password(&bob, "old", "new").unwrap();
+        assert_eq!(bob.authorized(Some(&utils::hash("new"))).is_ok(), true);
+        assert_eq!(ds.reset_pending(&bob), false);
+        let stored = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
+        assert_eq!(stored.get_pwd_hash(), Some(utils::hash("new")));
+        // the change is in the audit log and in the history
+        assert_eq!(ds.audit(&bob.uid(), None).len(), 2);
+        assert_eq!(
+            ds.events(&bob, EventFilter::Any)
+                .iter()
+                .any(|e| e.kind == EventType::Log("password changed".to_string())),
+            true
+        );
+        // only the principal can change its own password
+        assert_eq!(
+            ds.change_password(&jane, "", "new").err(),
+            Some(DataError::PermissionDenied)
+        );
+        // without a principal the entities without password need none
+        ds.set_principal(None).unwrap();
+        let jane = ds.change_password(&jane, "", "first").unwrap();
+        assert_eq!(jane.authorized(Some(&utils::hash("first"))).is_ok(), true);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag())
+            .with_password(Some(&"secret".to_string()));
+        ds.init(&bob).unwrap();
+        ds.add_user(
+            &Entity::from("jane").unwrap().with_sponsor(&bob),
+            AccessRole::Editor,
+            "pwd",
+        )
+        .unwrap();
+        let jane = ds.search("jane").pop().unwrap();
+        // the password is checked
+        assert_eq!(
+            ds.login(&bob.uid(), "wrong").err(),
+            Some(DataError::PermissionDenied)
+        );
+        assert_eq!(
+            ds.login(&utils::id(&model::Uuid::new_v4()), "secret").err(),
+            Some(DataError::NotFound)
+        );
+        let session = ds.login(&jane.uid(), "pwd").unwrap();
+        assert_eq!(session.user, jane.uid);
+        assert_eq!(session.expires > utils::now_local(), true);
+        // only the hash of the token is stored
+        assert_eq!(
+            ds.system
+                .get(session_key(&session.token))
+                .unwrap()
+                .is_some(),
+            true
+        );
+        assert_eq!(
+            ds.system
+                .iter()
+                .values()
+                .any(|v| str(&v.unwrap()).contains(&session.token)),
+            false
+        );
+        // the session sets the principal
+        assert_eq!(ds.resume("nope").err(), Some(DataError::InvalidToken));
+        assert_eq!(ds.resume(&session.token).unwrap().uid, jane.uid);
+        assert_eq!(
+            ds.add(&Entity::from("acme").unwrap().with_sponsor(&jane))
+                .is_ok(),
+            true
+        );
+        assert_eq!(
+            ds.set_search_config(&SearchConfig::default()).err(),
+            Some(DataError::PermissionDenied)
+        );
+        // the sessions are not exported
+        let p = d.path().join("export.json");
+        ds.export(&p, ExportFormat::FullJson).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&p)
+                .unwrap()
+                .contains(SESSION_PREFIX),
+            false
+        );
+        // disabling a user closes its sessions
+        let admin = ds.login(&bob.uid(), "secret").unwrap();
+        ds.resume(&admin.token).unwrap();
+        ds.set_user_disabled(&jane, true).unwrap();
+        assert_eq!(
+            ds.resume(&session.token).err(),
+            Some(DataError::InvalidToken)
+        );
+        // a closed session is checked on every operation
+        ds.logout(&admin.token).unwrap();
+        assert_eq!(ds.resume(&admin.token).err(), Some(DataError::InvalidToken));
+        let admin = ds.login(&bob.uid(), "secret").unwrap();
+        ds.resume(&admin.token).unwrap();
+        ds.system.remove(session_key(&admin.token)).unwrap();
+        assert_eq!(
+            ds.add(&Entity::from("ghost").unwrap().with_sponsor(&bob))
+                .err(),
+            Some(DataError::InvalidToken)
+        );
+        // expired sessions are pruned
+        ds.set_principal(None).unwrap();
+        let v = format!("{}\t{}", bob.uid(), utils::now_local().timestamp() - 1);
+        ds.system.insert(session_key("old"), v.as_bytes()).unwrap();
+        assert_eq!(ds.resume("old").err(), Some(DataError::InvalidToken));
+        assert_eq!(ds.prune_sessions().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_agenda_buckets() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(ds.agenda_buckets(), AgendaBucket::defaults());
+        // parse
+        let buckets = AgendaBucket::parse_list("Overdue=past, This week=1w,Later = 3m").unwrap();
+        assert_eq!(
+            buckets,
+            vec![
+                AgendaBucket::new("Overdue", TimeWindow::UpTo),
+                AgendaBucket::new("This week", TimeWindow::Week(1)),
+                AgendaBucket::new("Later", TimeWindow::Month(3)),
+            ]
+        );
+        for wrong in ["", "Today", "=1d", "Today=soon", "Today=1d,"].iter() {
+            assert_eq!(AgendaBucket::parse_list(wrong).is_err(), true, "{}", wrong);
+        }
+        // store
+        ds.set_agenda_buckets(&buckets).unwrap();
+        assert_eq!(
+            ds.get_meta("agenda.buckets").unwrap(),
+            "Overdue=past,This week=1w,Later=3m"
+        );
+        assert_eq!(ds.agenda_buckets(), buckets);
+        // reset
+        ds.set_agenda_buckets(&[]).unwrap();
+        assert_eq!(ds.agenda_buckets(), AgendaBucket::defaults());
+    }
+
+    #[test]
+    fn test_settings() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(ds.settings(), Settings::default());
+        // offsets
+        assert_eq!(
+            parse_utc_offset("+02:00").unwrap(),
+            FixedOffset::east_opt(7200).unwrap()
+        );
+        assert_eq!(
+            parse_utc_offset("-0530").unwrap(),
+            FixedOffset::west_opt(19800).unwrap()
+        );
+        assert_eq!(parse_utc_offset("UTC").unwrap().local_minus_utc(), 0);
+        for wrong in ["", "2", "+2:0", "+15:00", "+02:60", "CET"].iter() {
+            assert_eq!(parse_utc_offset(wrong).is_err(), true, "{}", wrong);
+        }
+        // store
+        let settings = Settings {
+            timezone: FixedOffset::east_opt(3600),
+            locale: "en-US".to_owned(),
+            week_start: Weekday::Sun,
+            reminder: TimeWindow::Week(2),
+            menu: vec!["agenda".to_owned(), "note".to_owned()],
+        };
+        ds.set_settings(&settings).unwrap();
+        assert_eq!(ds.get_meta("settings.week_start").unwrap(), "Sun");
+        assert_eq!(ds.settings(), settings);
+        let invalid = Settings {
+            reminder: TimeWindow::UpTo,
+            ..Settings::default()
+        };
+        assert_eq!(ds.set_settings(&invalid).is_err(), true);
+        // dates follow the locale and the week start
+        let wed = date(17, 3, 2021);
+        assert_eq!(settings.human_date(&wed), "Wed, 03/17/21");
+        assert_eq!(Settings::default().human_date(&wed), "Wed, 17.03.21");
+        assert_eq!(settings.week_start_of(&wed), date(14, 3, 2021));
+        assert_eq!(Settings::default().week_start_of(&wed), date(15, 3, 2021));
+        assert_eq!(
+            settings.week_start_of(&date(14, 3, 2021)),
+            date(14, 3, 2021)
+        );
+        let week = AgendaBucket::new("This week", TimeWindow::Week(1));
+        assert_eq!(week.range(&wed, &settings), (wed, date(21, 3, 2021)));
+        let days = AgendaBucket::new("Next days", TimeWindow::Day(3));
+        assert_eq!(days.range(&wed, &settings), (wed, date(20, 3, 2021)));
+        // reset
+        ds.set_settings(&Settings::default()).unwrap();
+        assert_eq!(ds.settings(), Settings::default());
+    }
+
+    #[test]
+    fn test_typed_meta() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        ds.set_meta_value("sync.google", "token", "abc".into())
+            .unwrap();
+        ds.set_meta_value("sync.google", "last", date(1, 2, 2021).into())
+            .unwrap();
+        ds.set_meta_value("sync.google", "enabled", true.into())
+            .unwrap();
+        ds.set_meta_value("sync.googlex", "count", MetaValue::Int(42))
+            .unwrap();
+        // typed values
+        let v = ds.get_meta_value("sync.google", "last").unwrap();
+        assert_eq!(v.as_date(), Some(date(1, 2, 2021)));
+        assert_eq!(v.as_int(), None);
+        assert_eq!(
+            ds.get_meta_value("sync.googlex", "count").unwrap(),
+            MetaValue::Int(42)
+        );
+        assert_eq!(ds.get_meta_value("sync.google", "count"), None);
+        // namespaces do not collide with plain metadata
+        ds.set_meta("sync.google", "plain").unwrap();
+        assert_eq!(ds.get_meta("sync.google:token"), None);
+        // list
+        let keys = |found: Vec<(String, MetaValue)>| {
+            found.into_iter().map(|(k, _)| k).collect::<Vec<String>>()
+        };
+        assert_eq!(
+            keys(ds.list_meta("sync.google:")),
+            vec![
+                "sync.google:enabled",
+                "sync.google:last",
+                "sync.google:token"
+            ]
+        );
+        assert_eq!(ds.list_meta("sync.").len(), 4);
+        assert_eq!(ds.list_meta("other").len(), 0);
+        // remove
+        assert_eq!(
+            ds.remove_meta_value("sync.google", "token").unwrap(),
+            Some(MetaValue::Str("abc".to_owned()))
+        );
+        assert_eq!(ds.remove_meta_value("sync.google", "token").unwrap(), None);
+        // invalid keys
+        assert_eq!(ds.set_meta_value("", "k", true.into()).is_err(), true);
+        assert_eq!(ds.set_meta_value("a:b", "k", true.into()).is_err(), true);
+        assert_eq!(ds.set_meta_value("a", "", true.into()).is_err(), true);
+    }
 
-    /// Insert a new entity and associated data
-    fn insert(&mut self, entity: &Entity) -> Result<model::Uuid> {
-        // insert data
-        let k: &str = &entity.uid();
-        let v = bincode::serialize(entity).unwrap();
-        // insert the data
-        self.entities.insert(k, v)?;
-        // insert next action date
-        let ak = action_key(entity);
-        self.actions.insert(ak, k)?;
-        // insert ids
-        // first insert the id itself
-        self.ids.insert(k, k)?;
-        // insert sponsorships
-        let ik = sponsor_key(&entity.uid, &entity.sponsor);
-        self.sponsorships.insert(ik, k)?;
-        // insert handles
-        for (m, id) in entity.handles.iter() {
-            let ik = handle_key(m, id);
-            self.ids.insert(ik, k)?;
-        }
-        // insert tags
-        for (_ts, t) in entity.tags.iter() {
-            self.tags.insert(tag_key(t, entity), k)?;
-        }
-        // insert relations
-        for r in entity.relationships.iter() {
-            let ik = format!("{}:{}", entity.uid(), r.kind.get_label());
-            let v: &str = &utils::id(&r.target);
-            self.edges.insert(ik, v)?;
-        }
-        // insert acl
-        for a in entity.visibility.iter() {
-            let ik = format!("{}:{}", a, k);
-            self.acl.insert(ik, k)?;
+    #[test]
+    fn test_copy_into() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(&d.path().join("src")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let alice = Entity::from("alice").unwrap().with_sponsor(&bob);
+        let tom = Entity::from("tom").unwrap().with_sponsor(&bob);
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&alice)
+            .with_tag(Tag::Generic("consulting".to_owned()))
+            .with_relation(&Rel::new(&tom));
+        for e in [&bob, &alice, &tom, &jane].iter() {
+            ds.insert(e).unwrap();
         }
-        // TODO this is extremely expensive and should be changed
-        self.build_search_index();
-        // done
-        Ok(entity.uid)
+        let call = Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::Lead(jane.uid), Actor::Starring(tom.uid)],
+        );
+        ds.record(&call).unwrap();
+        ds.record(&Event::log("touched", &tom, None)).unwrap();
+        let task = Task::new(&jane, "send the offer", today()).with_assignee(&tom);
+        ds.add_task(&task).unwrap();
+        ds.add_task(&Task::new(&tom, "call back", today())).unwrap();
+        let goal = Goal::new("Contract", utils::today_plus(30))
+            .with_entity(&jane)
+            .with_entity(&tom);
+        ds.add_goal(&goal).unwrap();
+        ds.add_goal(&Goal::new("Hiring", today()).with_entity(&tom))
+            .unwrap();
+        let cfg = SearchConfig {
+            threshold: 0.5,
+            ..SearchConfig::default()
+        };
+        ds.set_search_config(&cfg).unwrap();
+        // only the consulting entities and their sponsors
+        let mut target = DataStore::open(&d.path().join("dst")).unwrap();
+        let filter = Query::parse("who tagged consulting", &today()).unwrap();
+        assert_eq!(ds.copy_into(&mut target, &filter).unwrap(), 3);
+        assert_eq!(target.get_by_uid(&tom.uid()).unwrap(), None);
+        let copied = target.get_by_uid(&jane.uid()).unwrap().unwrap();
+        assert_eq!(copied.relationships.len(), 0);
+        assert_eq!(target.sponsored_by(&alice).len(), 1);
+        // the shared event comes along, without linking tom
+        let evts = target.events(&jane, EventFilter::Any);
+        assert_eq!(evts.iter().any(|e| e.uid == call.uid), true);
+        assert_eq!(target.events(&tom, EventFilter::Any).len(), 0);
+        let tasks = target.tasks(&jane);
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].assignee, None);
+        let goals = target.goals();
+        assert_eq!(goals.len(), 1);
+        assert_eq!(goals[0].entities, vec![jane.uid]);
+        assert_eq!(target.search_config().threshold, 0.5);
+        assert_eq!(target.check(false).unwrap().is_healthy(), true);
+        // everything without a filter
+        let mut all = DataStore::open_temporary().unwrap();
+        assert_eq!(ds.copy_into(&mut all, &Query::default()).unwrap(), 4);
+        assert_eq!(all.tasks(&tom).len(), 1);
+        assert_eq!(all.goals().len(), 2);
     }
 
-    pub fn sponsored_by(&self, sponsor: &Entity) -> Vec<Entity> {
-        self.sponsorships
-            .scan_prefix(&sponsor.uid())
-            .map(|r| {
-                let (_, v) = r.unwrap();
-                let raw = self.entities.get(&str(&v)).unwrap().unwrap();
-                let entity: Entity = bincode::deserialize(&raw).unwrap();
-                entity
-            })
-            .collect::<Vec<Entity>>()
+    #[test]
+    fn test_merge_from() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(&d.path().join("a")).unwrap();
+        let mut other = DataStore::open(&d.path().join("b")).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let mut jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com");
+        let tim = Entity::from("tim")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com");
+        let ann = Entity::from("ann").unwrap().with_sponsor(&bob);
+        ds.insert(&bob).unwrap();
+        ds.insert(&jane).unwrap();
+        // the same people in the other context, with some changes
+        other.insert(&bob).unwrap();
+        jane.updated_on = utils::today_plus(-10);
+        jane.class = "org".to_owned();
+        other.insert(&jane).unwrap();
+        other.insert(&tim).unwrap();
+        let mut newer = ann.clone();
+        newer.class = "person".to_owned();
+        ds.insert(&newer).unwrap();
+        let mut older = ann.clone();
+        older.updated_on = utils::today_plus(-1);
+        other.insert(&older).unwrap();
+        let call = Event::action("cli", "call", 1, None, &[Actor::Lead(tim.uid)]);
+        other.record(&call).unwrap();
+        other
+            .add_task(&Task::new(&tim, "follow up", today()))
+            .unwrap();
+        let report = ds.merge_from(&other).unwrap();
+        // tim is new but the handle is taken by jane
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].1.contains("jane@acme.com"), true);
+        let merged = ds.get_by_uid(&tim.uid()).unwrap().unwrap();
+        assert_eq!(merged.handles.get("email"), None);
+        // the newer versions win
+        let stored = |uid: &str| ds.get_by_uid(uid).unwrap().unwrap().class;
+        assert_eq!(stored(&jane.uid()), Entity::from("x").unwrap().class);
+        assert_eq!(stored(&ann.uid()), "person");
+        // the events and the tasks come along
+        assert_eq!(ds.events(&merged, EventFilter::Any).len(), 1);
+        assert_eq!(ds.tasks(&merged).len(), 1);
+        assert_eq!(ds.check(false).unwrap().is_healthy(), true);
     }
 
-    /// There are three main rules for propose edits
-    ///
-    /// ### Rule #1 - an entity has been postponed too much (avoided)
-    ///
-    /// This happens when there are are more then 5 consecutive "postponed"
-    /// log events
-    ///
-    /// ### Rule #2 - an entity that has not been updated in a while
-    ///
-    /// This happens if an entity has not had a log "reviewed" in the last
-    /// 3m, or otherwise not been updated in the last 3m
-    ///
-    /// Rule #3 - an entity misses most of fields
-    ///
-    /// Every fields (except the name) have a weight, if the
-    /// weight is below threshold then the rules apply.
-    ///
-    /// An entity is reported only for a rule at a time
-    ///
-    pub fn propose_edits(&self, principal: &Entity) -> Vec<(EditType, Entity)> {
-        let mut to_edit: Vec<(EditType, Entity)> = Vec::new();
-
-        // this is how much an item can be postponed in a row
-        let avoidance_limit = 5;
-
-        'main: for e in self.sponsored_by(principal).iter() {
-            // Rule#1
-            let mut consequent_postponed_times = 0;
-            // get the last events
-            for evt in self.events(e, EventFilter::Logs).iter() {
-                if !EventFilter::LogsWithMessage("postponed".to_owned()).matches(evt) {
-                    break;
-                }
-                consequent_postponed_times += 1;
-                if consequent_postponed_times >= avoidance_limit {
-                    to_edit.push((EditType::Avoided, e.to_owned()));
-                    continue 'main;
-                }
-            }
-            // Rule#2
-            let last_update = match self
-                .events(e, EventFilter::LogsWithMessage("review".to_string()))
-                .first()
-            {
-                None => e.updated_on,
-                Some(evt) => evt.recorded_at.naive_local().date(),
-            };
-            if last_update < utils::today_plus(-180) {
-                to_edit.push((EditType::MaybeStale, e.to_owned()));
-                continue;
-            }
-            // Rule#3
-            let mut score = 15;
-            if !e.is_classified() {
-                score -= 5;
-            }
-            if e.description.is_empty() {
-                score -= 1;
-            }
-            if e.handles.is_empty() {
-                score -= 3;
-            }
-            if e.tags.is_empty() {
-                score -= 3;
-            }
-            if e.updated_on == e.created_on {
-                score -= 1;
-            }
-            if e.relationships.is_empty() {
-                score -= 2;
-            }
-            if score < 9 {
-                to_edit.push((EditType::MaybeIncomplete, e.to_owned()));
-            }
+    #[test]
+    fn test_goals() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        let ann = Entity::from("ann").unwrap().with_sponsor(&bob);
+        for e in [&bob, &jane, &tim, &ann].iter() {
+            ds.insert(e).unwrap();
         }
-        to_edit
+        let mut seed = Goal::new("Seed round", utils::today_plus(30))
+            .with_entity(&jane)
+            .with_entity(&tim);
+        ds.add_goal(&seed).unwrap();
+        ds.add_goal(&Goal::new("Hiring", utils::today_plus(10)))
+            .unwrap();
+        // the names are unique and the entities must exist
+        assert_eq!(
+            ds.add_goal(&Goal::new("seed Round", today())),
+            Err(DataError::IDAlreadyTaken)
+        );
+        let ghost = Entity::from("ghost").unwrap();
+        assert_eq!(
+            ds.add_goal(&Goal::new("other", today()).with_entity(&ghost)),
+            Err(DataError::NotFound)
+        );
+        let names = |ds: &DataStore| ds.goals().into_iter().map(|g| g.name).collect::<Vec<_>>();
+        assert_eq!(names(&ds), vec!["Hiring", "Seed round"]);
+        assert_eq!(
+            ds.goal("seed-round").unwrap().map(|g| g.uid),
+            Some(seed.uid)
+        );
+        // no events yet
+        let p = ds.goal_progress(&seed).unwrap();
+        assert_eq!((p.entities, p.touched, p.events, p.last), (2, 0, 0, None));
+        // the events of the entities count, the linked events too
+        ds.record(&Event::meeting(30, None, &[Actor::Lead(jane.uid)]))
+            .unwrap();
+        ds.record(&Event::meeting(30, None, &[Actor::Lead(ann.uid)]))
+            .unwrap();
+        let pitch = Event::log("pitch", &ann, None);
+        ds.record(&pitch).unwrap();
+        seed.link_event(&pitch);
+        ds.update_goal(&seed).unwrap();
+        let p = ds.goal_progress(&seed).unwrap();
+        assert_eq!(
+            (p.entities, p.touched, p.events, p.last),
+            (2, 1, 2, Some(today()))
+        );
+        // the goals lose the removed entities
+        ds.remove(&tim).unwrap();
+        assert_eq!(
+            ds.goal("seed round").unwrap().unwrap().entities,
+            vec![jane.uid]
+        );
+        // the goals survive an export
+        let p = d.path().join("export.json");
+        ds.export(&p, ExportFormat::FullJson).unwrap();
+        ds.remove_goal(&seed).unwrap();
+        assert_eq!(ds.remove_goal(&seed), Err(DataError::NotFound));
+        assert_eq!(names(&ds), vec!["Hiring"]);
+        ds.import_as(&p, ExportFormat::FullJson, None, ImportMode::Replace)
+            .unwrap();
+        assert_eq!(names(&ds), vec!["Hiring", "Seed round"]);
     }
-}
-
-#[derive(Debug)]
-pub enum EditType {
-    MaybeStale,
-    MaybeIncomplete,
-    Avoided,
-}
-
-#[cfg(test)]
-mod tests {
-    use super::model::*;
-    use super::utils::*;
-    use super::*;
-    use tempfile::TempDir;
 
     #[test]
-    fn test_import_export() {
+    fn test_tasks() {
         let d = TempDir::new().unwrap();
-        let p = d.path().join("export.json");
-        // create a datastore
-        let mut orig = DataStore::open(&d.path().join("orig")).unwrap();
-        // insert records
-        let e = Entity::from("bob")
-            .unwrap()
-            .self_sponsored()
-            .with_handle("email", "bob@acme.com");
-        assert_eq!(orig.insert(&e).is_ok(), true);
-        let e = Entity::from("alice")
-            .unwrap()
-            .with_sponsor(&e)
-            .with_handle("email", "alice@acme.com");
-        assert_eq!(orig.insert(&e).is_ok(), true);
-        // now export
-        assert_eq!(orig.export(&p, ExportFormat::Json).is_ok(), true);
-        // create a new datastore
-        let mut copy = DataStore::open(&d.path().join("copy")).unwrap();
-        // import
-        assert_eq!(copy.import(&p, ExportFormat::Json).is_ok(), true);
-        // test
-        assert_eq!(orig.entities.len(), copy.entities.len());
-        for r in orig.entities.iter() {
-            let (k, v) = r.unwrap();
-            assert_eq!(copy.entities.get(k).unwrap().unwrap(), v);
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        for e in [&bob, &jane, &tim].iter() {
+            ds.insert(e).unwrap();
         }
+        let offer = Task::new(&jane, "send the offer", utils::today_plus(3)).with_assignee(&tim);
+        let call = Task::new(&jane, "call back", utils::today_plus(1));
+        let report = Task::new(&tim, "write the report", utils::today_plus(10));
+        for t in [&offer, &call, &report].iter() {
+            ds.add_task(t).unwrap();
+        }
+        // the entity and the assignee must exist
+        let ghost = Entity::from("ghost").unwrap();
+        assert_eq!(
+            ds.add_task(&Task::new(&ghost, "nothing", today())),
+            Err(DataError::NotFound)
+        );
+        assert_eq!(
+            ds.add_task(&Task::new(&jane, "nothing", today()).with_assignee(&ghost)),
+            Err(DataError::NotFound)
+        );
+        // the tasks of an entity, sorted by due date
+        let titles = |tasks: Vec<Task>| tasks.into_iter().map(|t| t.title).collect::<Vec<_>>();
+        assert_eq!(titles(ds.tasks(&jane)), vec!["call back", "send the offer"]);
+        // the tasks due within a range
+        let due = |ds: &DataStore, days: i64| {
+            ds.tasks_due(&today(), &utils::today_plus(days))
+                .unwrap()
+                .into_iter()
+                .map(|(t, e)| format!("{}:{}", e.name(), t.title))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(due(&ds, 2), vec!["jane:call back"]);
+        assert_eq!(
+            due(&ds, 30),
+            vec![
+                "jane:call back",
+                "jane:send the offer",
+                "tim:write the report"
+            ]
+        );
+        // moving the due date moves the index
+        let mut later = call.clone();
+        later.due = utils::today_plus(20);
+        ds.update_task(&later).unwrap();
+        assert_eq!(due(&ds, 2), Vec::<String>::new());
+        // done tasks leave the index and end up in the history
+        let done = ds.complete_task(&offer.uid()).unwrap();
+        assert_eq!(done.done, true);
+        assert_eq!(due(&ds, 30), vec!["tim:write the report", "jane:call back"]);
+        assert_eq!(titles(ds.tasks(&jane)), vec!["call back", "send the offer"]);
+        assert_eq!(
+            ds.events(&jane, EventFilter::Any)
+                .iter()
+                .any(|e| e.kind == EventType::Log("task done".to_owned())),
+            true
+        );
+        // removing a task
+        ds.remove_task(&report.uid()).unwrap();
+        assert_eq!(ds.get_task(&report.uid()).unwrap(), None);
+        assert_eq!(ds.remove_task(&report.uid()), Err(DataError::NotFound));
+        // the tasks go with the entity, the assignments are dropped
+        ds.add_task(&Task::new(&jane, "meet", today()).with_assignee(&tim))
+            .unwrap();
+        ds.remove(&tim).unwrap();
+        assert_eq!(ds.tasks(&jane).iter().all(|t| t.assignee.is_none()), true);
+        ds.remove(&jane).unwrap();
+        assert_eq!(ds.tasks.len(), 0);
+        assert_eq!(ds.tasks_due.len(), 0);
     }
 
     #[test]
-    fn test_datastore() {
+    fn test_upcoming_dates() {
         let d = TempDir::new().unwrap();
-        println!("dir is {:?}", d);
-        // open the datastore
         let mut ds = DataStore::open(d.path()).unwrap();
-        // reopen should not be possible
-        assert_eq!(DataStore::open(d.path()).is_err(), true);
-        // insert a records
-        let bob = Entity::from("bob").unwrap();
-        ds.insert(&bob).unwrap();
-        assert_eq!(ds.entities.len(), 1);
-        // fetch it back
-        let bob_1 = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
-        assert_eq!(bob_1.sponsor, bob.sponsor);
-        let bob_1 = ds.get_by_uid(&bob.uid()).unwrap().unwrap();
-        assert_eq!(bob_1.sponsor, bob.sponsor);
-        // add a custom id
+        let bob = Entity::from("bob").unwrap().self_sponsored();
         ds.insert(&bob).unwrap();
-        // the db size should be the same
-        assert_eq!(ds.entities.len(), 1);
+        let (soon, later) = (utils::today_plus(10), utils::today_plus(5));
+        let born = date(soon.day(), soon.month(), soon.year() - 20);
+        let jane = Entity::from("jane")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_birthday(born)
+            .with_date(ImportantDate::new("launch", later, Recurrence::Once));
+        let carl = Entity::from("carl")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_date(ImportantDate::new(
+                "renewal",
+                utils::today_plus(-3),
+                Recurrence::Monthly,
+            ));
+        ds.add(&jane).unwrap();
+        ds.add(&carl).unwrap();
+        let found = |ds: &DataStore, days: i64| {
+            ds.upcoming_dates(&utils::today(), &utils::today_plus(days))
+                .unwrap()
+                .into_iter()
+                .map(|(o, d, e)| (o, d.label, e.name().to_owned()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            found(&ds, 15),
+            vec![
+                (later, "launch".to_owned(), "jane".to_owned()),
+                (soon, "birthday".to_owned(), "jane".to_owned()),
+            ]
+        );
+        assert_eq!(found(&ds, 40).len(), 3);
+        // updates, archived and removed entities
+        let mut carl = ds.get_by_uid(&carl.uid()).unwrap().unwrap();
+        carl.remove_date("renewal");
+        ds.update(&carl).unwrap();
+        assert_eq!(ds.dates.len(), 2);
+        ds.archive(&jane).unwrap();
+        assert_eq!(ds.dates.len(), 0);
+        let dan = Entity::from("dan")
+            .unwrap()
+            .with_sponsor(&bob)
+            .with_birthday(born);
+        ds.add(&dan).unwrap();
+        assert_eq!(ds.dates.len(), 1);
+        ds.remove(&dan).unwrap();
+        assert_eq!(ds.dates.len(), 0);
+        // the birthdays stored before the index
+        let mut legacy = Entity::from("eve").unwrap().with_sponsor(&bob);
+        legacy.birthday = Some(born);
+        ds.add(&legacy).unwrap();
+        ds.dates.clear().unwrap();
+        ds.build_dates_index().unwrap();
+        assert_eq!(
+            found(&ds, 15),
+            vec![(soon, "birthday".to_owned(), "eve".to_owned())]
+        );
+        // the dates that have passed move forward
+        let next_month = utils::today_plus(40);
+        ds.roll_dates(&next_month).unwrap();
+        let keys = ds
+            .dates
+            .iter()
+            .keys()
+            .map(|k| str(&k.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(
+            keys.iter().all(|k| k[..10] >= next_month.to_string()[..]),
+            true
+        );
     }
 
     #[test]
-    fn test_search() {
+    fn test_whois() {
         let d = TempDir::new().unwrap();
-        println!("dir is {:?}", d);
-        // open the datastore
         let mut ds = DataStore::open(d.path()).unwrap();
-        // insert a records
-        let bob = Entity::from("Bob Marley")
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let jane = Entity::from("jane")
             .unwrap()
-            .self_sponsored()
-            .with_tag(Tag::from("skill", "singing"))
-            .with_tag(Tag::from("group", "The Wailers"));
-        assert_eq!(ds.insert(&bob).is_ok(), true);
-        let alice = Entity::from("Alice")
+            .with_sponsor(&bob)
+            .with_handle("email", "jane@acme.com")
+            .with_handle("telegram", "jane_doe")
+            .with_handle("github", "janed")
+            .with_handle("mobile", "+41 79 123 45 67")
+            .with_handle("url", "https://jane.dev");
+        ds.add(&jane).unwrap();
+        let carl = Entity::from("carl")
             .unwrap()
-            .self_sponsored()
-            .with_tag(Tag::from("skill", "cards"))
-            .with_tag(Tag::from("address", "Wonderland"))
-            .with_tag(Tag::from("skill", "singing"));
-        assert_eq!(ds.insert(&alice).is_ok(), true);
-        // build index
-        ds.build_search_index();
-        // search for partial
-        let s = ds.search("car");
-        assert_eq!(s.len(), 1);
-        assert_eq!(s[0].uid(), alice.uid());
-        // no hit
-        let s = ds.search("truck");
-        assert_eq!(s.len(), 0);
-        // fetch alice
-        let s = ds.search("Alice");
-        assert_eq!(s.len(), 1);
-        assert_eq!(s[0].uid(), alice.uid());
-        // skill
-        let s = ds.search("singing");
-        assert_eq!(s.len(), 2);
+            .with_sponsor(&bob)
+            .with_handle("phone", "+41791234567");
+        ds.add(&carl).unwrap();
+        let found = |v: &str| {
+            ds.whois(v)
+                .unwrap()
+                .into_iter()
+                .map(|(l, e)| (l, e.name().to_owned()))
+                .collect::<Vec<(String, String)>>()
+        };
+        let pair = |l: &str, n: &str| (l.to_owned(), n.to_owned());
+        assert_eq!(found("Jane@ACME.com"), vec![pair("email", "jane")]);
+        assert_eq!(found("@Jane_Doe"), vec![pair("telegram", "jane")]);
+        assert_eq!(found("janed"), vec![pair("github", "jane")]);
+        assert_eq!(found("@janed"), vec![pair("github", "jane")]);
+        assert_eq!(
+            found("0041 79 123 45 67"),
+            vec![pair("mobile", "jane"), pair("phone", "carl")]
+        );
+        assert_eq!(found("https://jane.dev/"), vec![pair("url", "jane")]);
+        assert_eq!(found("email:jane@acme.com"), vec![pair("email", "jane")]);
+        assert_eq!(found("email:janed").len(), 0);
+        assert_eq!(found("nobody@acme.com").len(), 0);
     }
 
-    // // TODO: remove
-    // assert_eq!(ds.events.len(), 2);
-    // println!("owner:{}", owner.uid());
-    // for r in ds.entity_event.iter() {
-    //     let (k, v) = r.unwrap();
-    //     println!("{}  -  {}", str(&k), str(&v));
-    // }
-
     #[test]
-    fn test_setup() {
+    fn test_normalize_handles() {
         let d = TempDir::new().unwrap();
-        println!("dir is {:?}", d);
-        // open the datastore
         let mut ds = DataStore::open(d.path()).unwrap();
-        // owner
-        let owner = Entity::from("bob")
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        // handles stored before they were checked
+        let mut jane = Entity::from("jane").unwrap().with_sponsor(&bob);
+        jane.handles
+            .insert("mobile".to_owned(), "0041 79 123 45 67".to_owned());
+        jane.other_handles
+            .push(("mobile".to_owned(), "+41791234567".to_owned()));
+        jane.handles
+            .insert("telegram".to_owned(), "@Jane_Doe".to_owned());
+        jane.handles.insert("phone".to_owned(), "555".to_owned());
+        // the same number of tim once normalized
+        let tim = Entity::from("tim")
             .unwrap()
-            .self_sponsored()
-            .with_next_action(utils::date(3, 3, 2020), "whatever".to_string());
-        // root object
-        let root = Entity::from("acme")
+            .with_sponsor(&bob)
+            .with_handle("mobile", "+39 333 123 456");
+        let mut ann = Entity::from("ann").unwrap().with_sponsor(&bob);
+        ann.handles
+            .insert("mobile".to_owned(), "+39 333 123456".to_owned());
+        for e in [&bob, &jane, &tim, &ann].iter() {
+            ds.insert(e).unwrap();
+        }
+        let mut left = ds
+            .normalize_handles()
             .unwrap()
-            .with_sponsor(&owner)
-            .with_next_action(utils::date(3, 10, 2020), "whatever".to_string());
-        // init error
+            .into_iter()
+            .map(|(e, h)| (e.name().to_owned(), h.to_string()))
+            .collect::<Vec<(String, String)>>();
+        left.sort();
         assert_eq!(
-            ds.init(&root).err().unwrap(),
-            DataError::InitializationError
+            left,
+            vec![
+                (
+                    "ann".to_owned(),
+                    "mobile:+39 333 123456 (primary)".to_owned()
+                ),
+                ("jane".to_owned(), "phone:555 (primary)".to_owned()),
+            ]
         );
+        let jane = ds.get_by_uid(&jane.uid()).unwrap().unwrap();
+        assert_eq!(jane.handles.get("mobile"), Some(&"+41791234567".to_owned()));
+        assert_eq!(jane.other_handles.len(), 0);
+        assert_eq!(jane.handles.get("telegram"), Some(&"jane_doe".to_owned()));
+        assert_eq!(jane.handles.get("phone"), Some(&"555".to_owned()));
+        assert_eq!(
+            ds.get_by_id("mobile", "+41 79 123 45 67")
+                .unwrap()
+                .map(|e| e.uid()),
+            Some(jane.uid())
+        );
+        assert_eq!(ds.get_by_id("telegram", "@jane_doe").unwrap(), Some(jane));
+        let ann = ds.get_by_uid(&ann.uid()).unwrap().unwrap();
+        assert_eq!(
+            ann.handles.get("mobile"),
+            Some(&"+39 333 123456".to_owned())
+        );
+        assert_eq!(ds.check(false).unwrap().is_healthy(), true);
+        // the pass runs once
+        assert_eq!(ds.normalize_handles().unwrap().len(), 0);
+    }
 
-        // init ok
-        assert_eq!(ds.init(&owner).is_ok(), true);
-        assert_eq!(ds.add(&root).is_ok(), true);
-        // check sponsorship (itself and the sponsored)
-        assert_eq!(ds.sponsored_by(&owner).len(), 2);
-        // count events
-        let events = ds.events(&owner, EventFilter::Any);
-        assert_eq!(events.len(), 1);
-        assert_eq!(events[0].actors[0].uid(), owner.uid());
-
-        // insert data
+    #[test]
+    fn test_agenda_filter() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let since = date(1, 1, 2021);
         let data = vec![
-            ("A", "person", "01.01.2021", &owner),
-            ("B", "person", "02.01.2021", &owner),
-            ("C", "person", "01.02.2021", &owner),
-            ("D", "person", "02.02.2021", &owner),
+            (
+                "jane",
+                "person",
+                "group:client",
+                RelQuality::Hostile(since, None),
+            ),
+            (
+                "tim",
+                "person",
+                "group:family",
+                RelQuality::Friendly(since, None),
+            ),
+            (
+                "acme",
+                "org",
+                "group:client",
+                RelQuality::Tense(since, None),
+            ),
+            (
+                "valis",
+                "project",
+                "feat:rust",
+                RelQuality::Neutral(since, None),
+            ),
         ];
-        data.iter().for_each(|(name, class, nad, sp)| {
+        for (i, (name, class, tag, quality)) in data.into_iter().enumerate() {
+            let (prefix, label) = utils::split_once(tag, ':').unwrap();
             let mut e = Entity::from(name)
                 .unwrap()
                 .with_class(class)
-                .with_sponsor(sp);
-            e.next_action(utils::date_from_str(nad).unwrap(), "yea".to_string());
+                .with_sponsor(&bob)
+                .with_tag(Tag::from(prefix, label))
+                .with_next_action(date(1 + i as u32, 2, 2021), "call".to_string());
+            e.set_quality(quality);
             ds.insert(&e).unwrap();
-        });
-
-        // test agenda
-        let (s, u) = TimeWindow::Day(1).range(&utils::date(1, 1, 2021));
-        let a = ds.agenda(&s, &u, 0, 0);
-        assert_eq!(a.len(), 1);
-
-        let (s, u) = TimeWindow::Day(2).range(&utils::date(1, 1, 2021));
-        let a = ds.agenda(&s, &u, 0, 0);
-        assert_eq!(a.len(), 2);
-
-        let (s, u) = TimeWindow::Year(1).range(&utils::date(1, 1, 2021));
-        let a = ds.agenda(&s, &u, 0, 0);
-        assert_eq!(a.len(), 4);
-
-        let (s, u) = TimeWindow::Year(1).range(&utils::date(1, 2, 2021));
-        let a = ds.agenda(&s, &u, 0, 0);
-        assert_eq!(a.len(), 2);
-
-        // test agenda until
-        let a = ds.agenda_until(&utils::date(31, 10, 2020), 0, 0);
-        assert_eq!(a.len(), 2);
-
-        let a = ds.agenda_until(&utils::date(2, 2, 2021), 0, 0);
-        assert_eq!(a.len(), 6);
-
-        ds.close();
+        }
+        let names = |p: Page<Entity>| p.items.into_iter().map(|e| e.name).collect::<Vec<_>>();
+        let until = date(28, 2, 2021);
+        // no filter
+        let all = AgendaFilter::default();
+        assert_eq!(ds.agenda_until(&until, &all, 0, 0).total, 4);
+        // the counts ignore the filters
+        assert_eq!(ds.agenda_counts(&date(3, 2, 2021)), (1, 2));
+        assert_eq!(ds.agenda_counts(&date(1, 1, 2021)), (0, 0));
+        // class
+        let people = AgendaFilter::default().with_class("person");
+        assert_eq!(
+            names(ds.agenda_until(&until, &people, 0, 0)),
+            vec!["jane", "tim"]
+        );
+        // tag prefix, the full tag or the prefix only
+        let f = AgendaFilter::default().with_tag_prefix("group:client");
+        assert_eq!(
+            names(ds.agenda_until(&until, &f, 0, 0)),
+            vec!["jane", "acme"]
+        );
+        let f = AgendaFilter::default().with_tag_prefix("group");
+        assert_eq!(ds.agenda_until(&until, &f, 0, 0).total, 3);
+        // quality, the dates do not matter
+        let f = AgendaFilter::default()
+            .with_quality(RelQuality::Hostile(until, None))
+            .with_quality(RelQuality::Tense(until, None));
+        assert_eq!(
+            names(ds.agenda_until(&until, &f, 0, 0)),
+            vec!["jane", "acme"]
+        );
+        // combined
+        let f = f.with_class("org");
+        assert_eq!(names(ds.agenda_until(&until, &f, 0, 0)), vec!["acme"]);
+        // paging counts the matching entities only
+        let p = ds.agenda(&date(1, 2, 2021), &until, &people, 1, 1);
+        assert_eq!(p.total, 2);
+        assert_eq!(names(p), vec!["tim"]);
+        let p = ds.agenda(&date(3, 2, 2021), &until, &people, 0, 0);
+        assert_eq!(p.total, 0);
+    }
 
-        // // TODO: db not closed
-        // // init error db not empty
-        // let mut ds = DataStore::open(d.path()).unwrap();
-        // // init error
-        // assert_eq!(
-        //     ds.init(&owner).err().unwrap(),
-        //     DataError::InitializationError
-        // );
+    #[test]
+    fn test_priority() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let data = vec![
+            ("jane", Priority::Low),
+            ("tim", Priority::Normal),
+            ("acme", Priority::Critical),
+            ("valis", Priority::High),
+            ("ann", Priority::Normal),
+        ];
+        for (i, (name, priority)) in data.into_iter().enumerate() {
+            let e = Entity::from(name)
+                .unwrap()
+                .with_sponsor(&bob)
+                .with_class("person")
+                .with_tag(Tag::Generic("friend".to_string()))
+                .with_relation(&Rel::new(&bob))
+                .with_handle("email", &format!("{}@acme.com", name))
+                .with_priority(priority)
+                .with_next_action(date(1 + i as u32, 2, 2021), "call".to_string());
+            ds.insert(&e).unwrap();
+        }
+        let names = |p: Page<Entity>| p.items.into_iter().map(|e| e.name).collect::<Vec<_>>();
+        let (all, until) = (AgendaFilter::default(), date(28, 2, 2021));
+        // higher priority first, then by date
+        assert_eq!(
+            names(ds.agenda_until(&until, &all, 0, 0)),
+            vec!["acme", "valis", "tim", "ann", "jane"]
+        );
+        // paging applies after sorting
+        let p = ds.agenda_until(&until, &all, 2, 1);
+        assert_eq!(p.total, 5);
+        assert_eq!(names(p), vec!["valis", "tim"]);
+        // the hints follow the same order
+        let hinted = ds
+            .propose_edits(&bob)
+            .into_iter()
+            .map(|(_, e)| e.name)
+            .filter(|n| n != "bob")
+            .collect::<Vec<_>>();
+        assert_eq!(hinted.len(), 5);
+        assert_eq!(hinted[..2], ["acme", "valis"]);
+        assert_eq!(hinted[4], "jane");
     }
 
     #[test]
-    fn test_update() {
+    fn test_agenda_streaming() {
         let d = TempDir::new().unwrap();
-        println!("dir is {:?}", d);
-        // open the datastore
         let mut ds = DataStore::open(d.path()).unwrap();
-        // bob
-        let bob = Entity::from("bob")
-            .unwrap()
-            .self_sponsored()
-            .with_next_action(date(1, 1, 2000), "something".to_string());
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let mut data = Vec::new();
+        for (i, priority) in Priority::all().iter().enumerate() {
+            let e = Entity::from(&format!("e{}", i))
+                .unwrap()
+                .with_sponsor(&bob)
+                .with_priority(*priority)
+                .with_next_action(date(10 - i as u32, 2, 2021), "call".to_string());
+            ds.insert(&e).unwrap();
+            data.push(e);
+        }
+        // a change of priority moves the action key
+        let mut e = data[0].clone();
+        e.priority = Priority::Critical;
+        ds.update(&e).unwrap();
+        assert_eq!(ds.actions.len(), 5);
+        ds.update(&data[0]).unwrap();
+        // break the entities outside the page, the page does not read them
+        for e in data.iter().take(2) {
+            ds.entities.insert(e.uid(), vec![0xff; 4]).unwrap();
+        }
+        let (all, until) = (AgendaFilter::default(), date(28, 2, 2021));
+        let p = ds.agenda_until(&until, &all, 2, 0);
+        assert_eq!(p.total, 4);
+        assert_eq!(p.items, vec![data[3].clone(), data[2].clone()]);
+        // the action keys of version 2 are rebuilt
+        ds.entities
+            .insert(data[0].uid(), bincode::serialize(&data[0]).unwrap())
+            .unwrap();
+        ds.entities
+            .insert(data[1].uid(), bincode::serialize(&data[1]).unwrap())
+            .unwrap();
+        ds.actions.clear().unwrap();
+        for e in data.iter() {
+            let k = format!("{}:{}", e.next_action_date, e.uid());
+            ds.actions.insert(k.as_str(), e.uid().as_str()).unwrap();
+        }
+        ds.set_meta(META_SCHEMA_VERSION, "2").unwrap();
+        drop(ds);
+        let ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(ds.actions.len(), 5);
+        let p = ds.agenda_until(&until, &all, 0, 0);
+        assert_eq!(p.items[0], data[3]);
+        assert_eq!(p.items[3], data[0]);
+    }
 
-        // update not existing
-        assert_eq!(ds.update(&bob).err().unwrap(), DataError::NotFound);
-        // insert bob
-        assert_eq!(ds.insert(&bob).is_ok(), true);
-        // now update bob next action
-        let bob = bob.with_next_action(date(11, 1, 2000), "something".to_string());
-        assert_eq!(ds.update(&bob).is_ok(), true);
-        // check that there is only one action in the db
-        assert_eq!(ds.actions.len(), 1);
-        // now add alice
-        let alice = Entity::from("alice")
-            .unwrap()
-            .self_sponsored()
-            .with_handle("email", "alice@acme.com");
-        assert_eq!(ds.insert(&alice).is_ok(), true);
-        // and bob tries to hijack alice
-        let bob = bob.with_handle("email", "alice&acme.com");
-        //assert_eq!(ds.update(&bob).is_err(), true);
-        assert_eq!(ds.update(&bob).err().unwrap(), DataError::IDAlreadyTaken);
-        // // but what if a new player arrives and tries to hijack alice?
-        let martha = Entity::from("martha")
+    #[test]
+    fn test_query() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let jane = Entity::from("jane")
             .unwrap()
+            .with_class("person")
             .with_sponsor(&bob)
-            .with_handle("email", "alice@acme.com");
-        assert_eq!(ds.add(&martha).err().unwrap(), DataError::IDAlreadyTaken);
-        // change alice sponsor
-        let alice = ds
-            .get_by_id("email", "alice@acme.com")
+            .with_tag(Tag::Generic("conference".to_string()));
+        let acme = Entity::from("acme")
             .unwrap()
+            .with_class("org")
+            .with_sponsor(&bob)
+            .with_tag(Tag::Generic("conference".to_string()));
+        let tim = Entity::from("tim").unwrap().with_sponsor(&bob);
+        for e in [&bob, &jane, &acme, &tim].iter() {
+            ds.insert(e).unwrap();
+        }
+        let meet = |who: &Entity, kind: &str, d: NaiveDate| {
+            let mut evt = Event::action(
+                "cli",
+                kind,
+                1,
+                None,
+                &[Actor::RecordedBy(bob.uid), Actor::Starring(who.uid)],
+            );
+            evt.recorded_at = datetime_local(&d);
+            evt
+        };
+        ds.record(&meet(&jane, "meeting", date(10, 2, 2021)))
+            .unwrap();
+        ds.record(&meet(&acme, "call", date(11, 2, 2021))).unwrap();
+        ds.record(&meet(&tim, "meeting", date(12, 2, 2021)))
+            .unwrap();
+        ds.record(&meet(&tim, "meeting", date(12, 3, 2021)))
+            .unwrap();
+        let today = date(17, 3, 2021);
+        let ask = |question: &str| {
+            ds.query(&Query::parse(question, &today).unwrap())
+                .iter()
+                .map(|(e, _)| e.name().to_owned())
+                .collect::<Vec<String>>()
+        };
+        // bob recorded the meetings but he did not take part
+        assert_eq!(ask("who did I meet last month"), vec!["tim", "jane"]);
+        assert_eq!(
+            ask("who did I meet last month tagged conference"),
+            vec!["jane"]
+        );
+        assert_eq!(ask("who tagged conference"), vec!["acme", "jane"]);
+        assert_eq!(ask("which orgs did I call"), vec!["acme"]);
+        assert_eq!(ask("who did I meet this month"), vec!["tim"]);
+        assert_eq!(ask("who did I meet yesterday").len(), 0);
+        // the events are returned as well
+        let found = ds.query(&Query::parse("what meetings since 01.01.2021", &today).unwrap());
+        assert_eq!(found[0].1.len(), 2);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        let person = |name: &str| {
+            Entity::from(name)
+                .unwrap()
+                .with_class("person")
+                .with_sponsor(&bob)
+        };
+        // similar names
+        let john = person("John Smith").with_tag(Tag::from("group", "work"));
+        let jon = person("Jon Smith").with_tag(Tag::from("group", "work"));
+        // different names, same phone with another label
+        let alice = person("Alice Doe").with_handle("mobile", "+39 123 456");
+        let ally = person("Ally").with_handle("phone", "+39123456");
+        // same name, another class
+        let smith = Entity::from("John Smith")
             .unwrap()
+            .with_class("org")
             .with_sponsor(&bob);
-        assert_eq!(ds.update(&alice).is_ok(), true);
-        // TODO handles
-        // TODO tags
+        let carl = person("Carl");
+        for e in [&bob, &john, &jon, &alice, &ally, &smith, &carl].iter() {
+            ds.insert(e).unwrap();
+        }
+        let found = ds.find_duplicates();
+        assert_eq!(found.len(), 2);
+        let pair = |d: &Duplicate| {
+            let mut names = vec![d.a.name().to_owned(), d.b.name().to_owned()];
+            names.sort();
+            names
+        };
+        assert_eq!(found.iter().all(|d| d.score >= DUPLICATE_THRESHOLD), true);
+        let names = found.iter().map(pair).collect::<Vec<_>>();
+        assert_eq!(
+            names.contains(&vec!["John Smith".to_owned(), "Jon Smith".to_owned()]),
+            true
+        );
+        assert_eq!(
+            names.contains(&vec!["Alice Doe".to_owned(), "Ally".to_owned()]),
+            true
+        );
+        let by_handle = found.iter().find(|d| !d.shared_handles.is_empty()).unwrap();
+        assert_eq!(by_handle.shared_handles.len(), 1);
+        let by_name = found.iter().find(|d| d.shared_handles.is_empty()).unwrap();
+        assert_eq!(by_name.shared_tags, vec!["group:work"]);
+        assert_eq!(by_name.name_similarity > 0.9, true);
+        // the archived entities are skipped
+        ds.archive(&ally).unwrap();
+        assert_eq!(ds.find_duplicates().len(), 1);
+        // the duplicates show up in the hints
+        let hints = ds
+            .propose_edits(&bob)
+            .into_iter()
+            .map(|(t, e)| (e.uid, t))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            hints.get(&jon.uid),
+            Some(&EditType::PossibleDuplicate(john.uid))
+        );
+        assert_eq!(
+            hints.get(&john.uid),
+            Some(&EditType::PossibleDuplicate(jon.uid))
+        );
+        assert_eq!(hints.get(&carl.uid), Some(&EditType::MaybeIncomplete));
     }
 
     #[test]
-    fn test_relationships() {
+    fn test_propose_edits() {
         let d = TempDir::new().unwrap();
-        println!("dir is {:?}", d);
-        // open the datastore
         let mut ds = DataStore::open(d.path()).unwrap();
-
-        for i in 0..100 {
-            let name = format!("e_{}", i);
-
-            let e = Entity::from(&name)
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.insert(&bob).unwrap();
+        let today = utils::today();
+        // a complete entity, to only trigger the rules under test
+        let complete = |name: &str| {
+            Entity::from(name)
                 .unwrap()
-                .self_sponsored()
-                .with_handle("code", &name)
-                .with_next_action(date(1, 1, 2000), "something".to_string());
-            assert_eq!(ds.insert(&e).is_ok(), true);
+                .with_sponsor(&bob)
+                .with_class("person")
+                .with_tag(Tag::Generic("friend".to_string()))
+                .with_relation(&Rel::new(&bob))
+        };
+        // birthday in a week, nothing planned, born in a leap year
+        let b = today + Duration::days(7);
+        let born = utils::date(b.day(), b.month(), 1988);
+        let jane = complete("jane")
+            .with_birthday(born)
+            .with_next_action(today + Duration::days(30), "later".to_string());
+        // same but with something planned before
+        let tim = complete("tim")
+            .with_birthday(born)
+            .with_next_action(today + Duration::days(3), "gift".to_string());
+        // tense since two months
+        let mut ann = complete("ann");
+        ann.set_quality(RelQuality::Tense(today - Duration::days(60), None));
+        // tense since a week
+        let mut joe = complete("joe");
+        joe.set_quality(RelQuality::Tense(today - Duration::days(7), None));
+        // with handles and never contacted
+        let max = complete("max").with_handle("email", "max@acme.com");
+        let sam = complete("sam").with_handle("email", "sam@acme.com");
+        for e in [&jane, &tim, &ann, &joe, &max, &sam].iter() {
+            ds.insert(e).unwrap();
         }
-        // create a new entity
-        let e = Entity::from("center")
+        ds.record(&Event::action(
+            "cli",
+            "call",
+            1,
+            None,
+            &[Actor::RecordedBy(bob.uid), Actor::Starring(sam.uid)],
+        ))
+        .unwrap();
+        let hints = ds
+            .propose_edits(&bob)
+            .into_iter()
+            .map(|(t, e)| (e.name().to_owned(), t))
+            .collect::<HashMap<String, EditType>>();
+        assert_eq!(
+            hints.get("jane"),
+            Some(&EditType::UpcomingBirthday(today + Duration::days(7)))
+        );
+        assert_eq!(hints.get("tim"), None);
+        assert_eq!(
+            hints.get("ann"),
+            Some(&EditType::LongTension(today - Duration::days(60)))
+        );
+        assert_eq!(hints.get("joe"), None);
+        assert_eq!(hints.get("max"), Some(&EditType::NeverContacted));
+        assert_eq!(hints.get("sam"), None);
+        // the metrics
+        for h in ds.hints(&bob).iter() {
+            match h.entity.name() {
+                "jane" => {
+                    assert_eq!(h.metric, 7);
+                    assert_eq!(h.rule(), "upcoming_birthday");
+                    assert_eq!(h.severity(), Severity::Info);
+                }
+                "ann" => {
+                    assert_eq!(h.metric, 60);
+                    assert_eq!(h.severity(), Severity::Warning);
+                }
+                "max" => assert_eq!(h.metric, 1),
+                _ => {}
+            }
+        }
+    }
+
+    /// bob and alice, related to bob with an email, a tag and a next action,
+    /// and a note about alice, as they were stored before the schema version
+    const LEGACY_BOB: &str = concat!(
+        "10000000000000000b0b0b0b000040008000000000000001000300000000000000626f620000",
+        "0000000000000000000000000000000000000000000003000000000000006e2f610000000000",
+        "0000000a00000000000000323032312d30332d30310010000000000000000b0b0b0b00004000",
+        "80000000000000010a00000000000000323032312d30332d30310a0000000000000032303231",
+        "2d30332d30310a00000000000000323032312d30332d30310a00000000000000323032312d30",
+        "342d30310900000000000000746f2075706461746500000000000000000000000000000000",
+    );
+    const LEGACY_ALICE: &str = concat!(
+        "1000000000000000a11ce000000040008000000000000002000500000000000000616c696365",
+        "01000000000000000800000000000000673a667269656e640200000006000000000000006672",
+        "69656e64000000000000000001000000000000000500000000000000656d61696c0e00000000",
+        "000000616c6963654061636d652e636f6d03000000000000006e2f61010000000a0000000000",
+        "0000323032312d30332d303200020000000a00000000000000323032312d30332d3032001000",
+        "0000000000000b0b0b0b0000400080000000000000010a00000000000000323032312d30332d",
+        "30320a00000000000000323032312d30332d30320a00000000000000323032312d30332d3032",
+        "0a00000000000000323032312d30332d3230080000000000000063616c6c2068657201000000",
+        "000000000000000010000000000000000b0b0b0b000040008000000000000001010000000000",
+        "000001000000",
+    );
+    const LEGACY_NOTE: &str = concat!(
+        "1000000000000000e0e0e0e00000400080000000000000031900000000000000323032312d30",
+        "332d30325431303a30303a30302b30313a30300000000005000000000000006164646564010f",
+        "000000000000006d657420617420746865206661697202000000000000000000000010000000",
+        "000000000b0b0b0b000040008000000000000001010000001000000000000000a11ce0000000",
+        "400080000000000000020000000000000000",
+    );
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Write the legacy records in a datastore directory
+    fn legacy_datastore(path: &Path) -> sled::Db {
+        let db = sled::open(path).unwrap();
+        let entities = db.open_tree(TABLE_ENTITIES).unwrap();
+        entities
+            .insert("0b0b0b0b000040008000000000000001", unhex(LEGACY_BOB))
+            .unwrap();
+        entities
+            .insert("a11ce000000040008000000000000002", unhex(LEGACY_ALICE))
+            .unwrap();
+        db.open_tree(TABLE_EVENTS)
             .unwrap()
-            .self_sponsored()
-            .with_handle("code", "center")
-            .with_next_action(date(1, 1, 2000), "something".to_string());
-        // add relationships
-        let e = e
-            .add_relation_with(
-                &ds.get_by_id("code", "e_1").unwrap().unwrap(),
-                RelType::RelatedTo,
-            )
-            .add_relation_with(
-                &ds.get_by_id("code", "e_10").unwrap().unwrap(),
-                RelType::RelatedTo,
-            )
-            .add_relation_with(
-                &ds.get_by_id("code", "e_50").unwrap().unwrap(),
-                RelType::RelatedTo,
+            .insert("e0e0e0e0000040008000000000000003", unhex(LEGACY_NOTE))
+            .unwrap();
+        // the entity events were keyed actor_uid:ts:event_uid, without the role
+        let entity_event = db.open_tree(TABLE_ENTITY_EVENT).unwrap();
+        for actor in [
+            "0b0b0b0b000040008000000000000001",
+            "a11ce000000040008000000000000002",
+        ]
+        .iter()
+        {
+            let k = format!(
+                "{}:{}:e0e0e0e0000040008000000000000003",
+                actor,
+                i64::MAX - 1614675600000
             );
-        // insert
-        assert_eq!(ds.insert(&e).is_ok(), true);
-        // fetch
-        let e = ds.get_by_id("code", "center").unwrap().unwrap();
-        assert_eq!(e.relationships.len(), 3);
-        // add a new one
-        let e = e.add_relation_with(
-            &ds.get_by_id("code", "e_71").unwrap().unwrap(),
-            RelType::RelatedTo,
+            entity_event
+                .insert(k.as_str(), "e0e0e0e0000040008000000000000003")
+                .unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_migrate_legacy() {
+        let d = TempDir::new().unwrap();
+        drop(legacy_datastore(d.path()));
+        let ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(
+            ds.get_meta(META_SCHEMA_VERSION),
+            Some(SCHEMA_VERSION.to_string())
         );
-        // update
-        assert_eq!(ds.update(&e).is_ok(), true);
-        // fetch
-        let e = ds.get_by_id("code", "center").unwrap().unwrap();
-        assert_eq!(e.relationships.len(), 4);
+        assert_eq!(ds.get_meta(META_SCHEMA_REINDEX), None);
+        let alice = ds
+            .get_by_uid("a11ce000000040008000000000000002")
+            .unwrap()
+            .unwrap();
+        assert_eq!(alice.name(), "alice");
+        assert_eq!(alice.next_action_note, "call her");
+        assert_eq!(alice.relationships.len(), 1);
+        assert_eq!(alice.relationships[0].weight, 0);
+        assert_eq!(alice.priority, Priority::Normal);
+        assert_eq!(alice.quality_history.len(), 0);
+        // the stored handles are the primary ones
+        assert_eq!(alice.other_handles.len(), 0);
+        assert_eq!(
+            ds.handles(&alice).unwrap(),
+            vec![("email".to_string(), "alice@acme.com".to_string())]
+        );
+        assert_eq!(ds.all_handles("email").len(), 1);
+        // the indexes are rebuilt
+        let agenda = ds.agenda_until(&date(31, 3, 2021), &AgendaFilter::default(), 0, 0);
+        assert_eq!(agenda.items, vec![alice.clone()]);
+        assert_eq!(ds.search("alice").len(), 1);
+        let bob = ds
+            .get_by_uid("0b0b0b0b000040008000000000000001")
+            .unwrap()
+            .unwrap();
+        assert_eq!(ds.related(&bob, None).len(), 1);
+        let notes = ds.events(&alice, EventFilter::Any);
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].content, Some("met at the fair".to_string()));
+        assert_eq!(ds.timeline(None, EventFilter::Any, None, None).len(), 1);
+        // the migration runs once
+        drop(ds);
+        let ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(ds.events(&alice, EventFilter::Any).len(), 1);
+        // a newer version is not opened
+        let mut ds = ds;
+        ds.set_meta(META_SCHEMA_VERSION, "99").unwrap();
+        drop(ds);
+        assert_eq!(DataStore::open(d.path()).is_err(), true);
     }
 
     #[test]
-    fn test_events() {
+    fn test_migrate_entity_event_keys() {
         let d = TempDir::new().unwrap();
-        println!("dir is {:?}", d);
-        // open the datastore
-        let mut ds = DataStore::open(d.path()).unwrap();
-        // bob
-        let bob = Entity::from("bob")
+        drop(legacy_datastore(d.path()));
+        let ds = DataStore::open(d.path()).unwrap();
+        // the keys without the role are replaced
+        assert_eq!(ds.entity_event.len(), 2);
+        let alice = ds
+            .get_by_uid("a11ce000000040008000000000000002")
             .unwrap()
-            .self_sponsored()
-            .with_next_action(date(1, 1, 2000), "something".to_string());
-        // insert bob
-        assert_eq!(ds.insert(&bob).is_ok(), true);
-        // record an event without actors
-        let res = ds.record(&Event::new());
-        assert_eq!(res.err().unwrap(), DataError::BrokenReference);
-        // insert a bunch of events elements
-        let elements = 1000;
-        for i in 0..elements {
-            ds.record(&Event::action(
-                "count",
-                &format!("{}", i),
-                1,
-                None,
-                &[Actor::Lead(bob.uid.clone())],
-            ))
             .unwrap();
-            // sleep 1ms
-            std::thread::sleep(std::time::Duration::from_millis(1));
-        }
-        let events = ds.events(&bob, EventFilter::Actions);
-        assert_eq!(events.len(), elements);
-        for (i, e) in events.iter().enumerate() {
-            assert_eq!(
-                e.kind,
-                EventType::Action("count".to_owned(), format!("{}", elements - 1 - i), 1)
-            );
-        }
+        let bob = ds
+            .get_by_uid("0b0b0b0b000040008000000000000001")
+            .unwrap()
+            .unwrap();
+        let role = |r: ActorRole| EventFilter::Role(r);
+        assert_eq!(ds.events(&alice, role(ActorRole::Subject)).len(), 1);
+        assert_eq!(ds.events(&bob, role(ActorRole::Subject)).len(), 0);
+        assert_eq!(ds.events(&bob, role(ActorRole::RecordedBy)).len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_audit_entries() {
+        let d = TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        // an audit entry as stored in version 1, without the delegate
+        let changes = vec![("name".to_string(), "rob".to_string(), "bob".to_string())];
+        let v1 = (
+            bob.uid,
+            utils::now_local(),
+            Some(bob.uid),
+            AuditAction::Updated,
+            changes,
+        );
+        ds.audit
+            .insert(
+                audit_key(&bob.uid, 0, 0).as_str(),
+                bincode::serialize(&v1).unwrap(),
+            )
+            .unwrap();
+        ds.set_meta(META_SCHEMA_VERSION, "1").unwrap();
+        drop(ds);
+        let ds = DataStore::open(d.path()).unwrap();
+        assert_eq!(
+            ds.get_meta(META_SCHEMA_VERSION),
+            Some(SCHEMA_VERSION.to_string())
+        );
+        let trail = ds.audit(&bob.uid(), None);
+        assert_eq!(trail[0].changes[0].1, "rob");
+        assert_eq!(trail[0].on_behalf_of, None);
     }
 }
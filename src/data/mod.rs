@@ -2,12 +2,21 @@ pub mod context;
 
 /// The ledger module provide access to a database
 pub mod ledger;
-pub use ledger::{DataStore, EventFilter, ExportFormat};
+pub use ledger::{
+    AgendaBucket, AgendaFilter, ChangeEvent, ChangeFilter, DataStore, Direction, Duplicate,
+    EventFilter, ExportFormat, GoalProgress, ImportConflict, ImportMode, ImportPlan, ImportReport,
+    Inconsistency, IntegrityReport, InteractionStats, Lifecycle, MatchField, NoteReport, Page,
+    PurgeReport, QuickAddReport, Resolution, SearchConfig, SearchResult, SessionToken, Settings,
+    SponsorshipNode, Stats,
+};
 
 /// The model contains all the data structures for VALIS
 pub mod model;
 pub use model::{
-    Actor, Entity, Event, EventType, RelQuality, RelState, RelType, Tag, TimeWindow, ACL,
+    AccessRole, Actor, ActorRole, Address, Attachment, AuditAction, AuditEntry, Channel,
+    Completeness, Entity, Escalation, Event, EventType, FieldValue, Goal, GoalStatus, Handle,
+    ImportantDate, InteractionDirection, MetaValue, Priority, Recurrence, RelQuality, RelState,
+    RelType, Tag, Task, TimeWindow, ACL,
 };
 
 /// The utils module provides utilities to work with
@@ -15,7 +24,26 @@ pub use model::{
 pub mod utils;
 pub use utils::*;
 
+/// The formats module provides readers and writers
+/// for the supported import and export formats
+pub mod formats;
+pub use formats::EventRecord;
+
+/// The backup module lists and rotates
+/// the backups of a context
+pub mod backup;
+pub use backup::Retention;
+
+/// The query module turns questions into
+/// queries over entities and events
+pub mod query;
+pub use query::Query;
+
+/// The trend module computes the evolution of
+/// the relationships and renders it as sparklines
+pub mod trend;
+
 /// This is for text manipulation
 /// like entity extraction
 pub mod parser;
-pub use parser::find_labels;
+pub use parser::{find_hashtags, find_labels, parse_quickadd, QuickAdd};
@@ -9,12 +9,13 @@ use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
 use std::fmt;
 use std::str::FromStr;
 pub use uuid::Uuid;
 
+use super::ledger::DataError;
 use super::utils;
 
 // Let's use generic errors
@@ -32,7 +33,7 @@ pub enum ValisError {
 
 impl fmt::Display for ValisError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        write!(f, "{:?}", self)
     }
 }
 
@@ -61,7 +62,7 @@ fn extract_timewindow(text: &str) -> (&str, i64) {
 
 /// A time range with duration and repetition
 ///
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TimeWindow {
     UpTo,
     SingleDay,
@@ -201,6 +202,102 @@ impl RelState {
     }
 }
 
+/// The Escalation describes how urgent an overdue next action has become
+///
+/// It is computed from the number of days the action is overdue
+/// and how many times in a row it has been postponed
+/// - None: the action is not overdue
+/// - Overdue: the action is overdue
+/// - Late: the action is overdue for more than a week or postponed 3 times
+/// - Critical: the action is overdue for more than 2 weeks or postponed 5 times
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Escalation {
+    None,
+    Overdue,
+    Late,
+    Critical,
+}
+
+impl Escalation {
+    pub fn from(overdue_days: i64, postponed_times: usize) -> Self {
+        if overdue_days > 14 || postponed_times >= 5 {
+            return Self::Critical;
+        }
+        if overdue_days > 7 || postponed_times >= 3 {
+            return Self::Late;
+        }
+        if overdue_days > 0 {
+            return Self::Overdue;
+        }
+        Self::None
+    }
+
+    pub fn emoji(&self) -> String {
+        match self {
+            Self::None => "".to_owned(),
+            Self::Overdue => "🟡".to_owned(),
+            Self::Late => "🟠".to_owned(),
+            Self::Critical => "🔴".to_owned(),
+        }
+    }
+}
+
+/// How important an entity is, the higher the priority
+/// the sooner the entity shows up in the agenda and in the hints
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+    Critical,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl Priority {
+    pub fn all() -> [Priority; 4] {
+        [Self::Low, Self::Normal, Self::High, Self::Critical]
+    }
+
+    pub fn emoji(&self) -> String {
+        match self {
+            Self::Low => "⬇️".to_owned(),
+            Self::Normal => "".to_owned(),
+            Self::High => "⬆️".to_owned(),
+            Self::Critical => "⏫".to_owned(),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = ValisError;
+
+    fn from_str(s: &str) -> Result<Priority> {
+        match s.trim().to_lowercase().as_str() {
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            "critical" => Ok(Self::Critical),
+            _ => Err(ValisError::InputError(format!("unknown priority {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Low => write!(f, "low"),
+            Self::Normal => write!(f, "normal"),
+            Self::High => write!(f, "high"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Tag {
     Generic(String), // simple tag
@@ -229,8 +326,86 @@ impl Tag {
         }
     }
 
+    /// Returns the slug of the tag, the segments of a nested
+    /// tag are kept apart, eg. skill/rust/async
     pub fn slug(&self) -> String {
-        utils::slugify(self.to_string())
+        self.path().join("/")
+    }
+
+    /// Returns the labels of the segments of a nested tag, from the
+    /// outermost, eg. [skill, Rust, async], the links are not nested
+    pub fn segments(&self) -> Vec<String> {
+        match self {
+            Self::Link(label) => vec![label.trim().to_owned()],
+            _ => self
+                .to_string()
+                .split('/')
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Returns the slugs of the segments of a nested tag,
+    /// from the outermost, eg. [skill, rust, async]
+    pub fn path(&self) -> Vec<String> {
+        self.segments()
+            .iter()
+            .map(utils::slugify)
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// Tells if the tag is nested within a slug or is the same,
+    /// regardless of the prefix, eg. skill/rust is within skill
+    /// but not within ski. Every tag is within the empty slug
+    pub fn nested_in(&self, slug: &str) -> bool {
+        let own = self.slug();
+        slug.is_empty() || own == slug || own.starts_with(&format!("{}/", slug))
+    }
+
+    /// Tells if the tag is nested within another tag with the same prefix
+    pub fn is_within(&self, other: &Tag) -> bool {
+        self.prefix() == other.prefix() && self.nested_in(&other.slug())
+    }
+
+    /// Returns the same kind of tag with another label
+    pub fn relabel(&self, label: &str) -> Tag {
+        let label = label.to_owned();
+        match self {
+            Self::Feature(_) => Self::Feature(label),
+            Self::Group(_) => Self::Group(label),
+            Self::Link(_) => Self::Link(label),
+            Self::Generic(_) => Self::Generic(label),
+            Self::Role(_) => Self::Role(label),
+            Self::System(_) => Self::System(label),
+        }
+    }
+
+    /// Arrange the labels of a list of tags as a tree, the nested
+    /// tags share their parents. Returns the depth and the label of
+    /// each node in display order, eg. (0, skill), (1, rust), (2, async)
+    pub fn tree(tags: &[Tag]) -> Vec<(usize, String)> {
+        let mut paths = tags.iter().map(|t| t.segments()).collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        let mut nodes = Vec::new();
+        let mut previous: Vec<String> = Vec::new();
+        for p in paths.into_iter() {
+            let shared = previous
+                .iter()
+                .zip(p.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            nodes.extend(
+                p.iter()
+                    .enumerate()
+                    .skip(shared)
+                    .map(|(d, s)| (d, s.clone())),
+            );
+            previous = p;
+        }
+        nodes
     }
 
     pub fn to_string_full(&self) -> String {
@@ -315,6 +490,135 @@ impl FromStr for ACL {
     }
 }
 
+/// The access level of an entity within a shared context
+///
+/// The roles are ordered, each one can do whatever
+/// the ones below can do:
+/// - Viewer can only read the records
+/// - Editor can add and modify the records
+/// - Admin can also grant roles and import data
+/// - Owner can also grant and revoke the owner role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessRole {
+    Viewer,
+    Editor,
+    Admin,
+    Owner,
+}
+
+impl AccessRole {
+    /// Returns the label of the system tag backing the role
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+
+    /// All the roles, from the highest to the lowest
+    pub fn all() -> [AccessRole; 4] {
+        [Self::Owner, Self::Admin, Self::Editor, Self::Viewer]
+    }
+
+    /// Returns the system tag backing the role
+    pub fn tag(&self) -> Tag {
+        Tag::System(self.code().to_owned())
+    }
+}
+
+impl fmt::Display for AccessRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for AccessRole {
+    type Err = ValisError;
+
+    fn from_str(s: &str) -> Result<AccessRole> {
+        match s.trim().to_lowercase().as_str() {
+            "viewer" => Ok(Self::Viewer),
+            "editor" => Ok(Self::Editor),
+            "admin" => Ok(Self::Admin),
+            "owner" => Ok(Self::Owner),
+            _ => Err(ValisError::InputError(format!("unknown role {}", s))),
+        }
+    }
+}
+
+/// The system tag of the disabled users
+fn disabled_tag() -> Tag {
+    Tag::System("disabled".to_owned())
+}
+
+/// How an interaction took place
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Channel {
+    Call,
+    Meeting,
+    Email,
+}
+
+impl Channel {
+    pub fn all() -> Vec<Channel> {
+        vec![Self::Call, Self::Meeting, Self::Email]
+    }
+}
+
+impl FromStr for Channel {
+    type Err = ValisError;
+
+    fn from_str(s: &str) -> Result<Channel> {
+        match s.trim().to_lowercase().as_str() {
+            "call" | "phone" => Ok(Self::Call),
+            "meeting" | "meet" => Ok(Self::Meeting),
+            "email" | "mail" => Ok(Self::Email),
+            _ => Err(ValisError::InputError(format!("unknown channel {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Call => write!(f, "call"),
+            Self::Meeting => write!(f, "meeting"),
+            Self::Email => write!(f, "email"),
+        }
+    }
+}
+
+/// Who started an interaction, seen from the
+/// one recording it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum InteractionDirection {
+    Incoming,
+    Outgoing,
+}
+
+impl FromStr for InteractionDirection {
+    type Err = ValisError;
+
+    fn from_str(s: &str) -> Result<InteractionDirection> {
+        match s.trim().to_lowercase().as_str() {
+            "incoming" | "in" => Ok(Self::Incoming),
+            "outgoing" | "out" => Ok(Self::Outgoing),
+            _ => Err(ValisError::InputError(format!("unknown direction {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for InteractionDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Incoming => write!(f, "incoming"),
+            Self::Outgoing => write!(f, "outgoing"),
+        }
+    }
+}
+
 /// EventType describe an event
 ///
 /// ### Log(Message)
@@ -331,10 +635,15 @@ impl FromStr for ACL {
 /// The other use for the weight (with the derived metric of event frequency)
 /// is to monitor entities activity to get alarms about trends.
 ///
+/// ### Interaction(Channel, Minutes, Direction)
+/// Describes a call, a meeting or an email with how long it
+/// lasted, in minutes, and who started it.
+///
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum EventType {
     Log(String),
     Action(String, String, usize),
+    Interaction(Channel, u32, InteractionDirection),
 }
 
 impl fmt::Display for EventType {
@@ -342,6 +651,7 @@ impl fmt::Display for EventType {
         match self {
             Self::Log(v) => write!(f, "log:{}", v),
             Self::Action(src, title, s) => write!(f, "action:{}:{}:{}", src, title, s),
+            Self::Interaction(c, m, d) => write!(f, "interaction:{}:{}:{}", c, m, d),
         }
     }
 }
@@ -353,20 +663,33 @@ impl EventType {
             _ => false,
         }
     }
+
+    pub fn is_interaction(&self) -> bool {
+        match self {
+            Self::Interaction(_, _, _) => true,
+            _ => false,
+        }
+    }
+
     /// this returns the first component for the log type data
     pub fn val(&self) -> String {
         match self {
             Self::Log(m) => m.to_owned(),
             Self::Action(src, _, _) => src.to_owned(),
+            Self::Interaction(c, _, _) => c.to_string(),
         }
     }
 }
 
+/// The uid that replaces a purged entity among the actors of the events
+pub const TOMBSTONE: Uuid = Uuid::nil();
+
 /// The Actor is a participant of an event
 ///
 /// The Lead is the one triggering the action
 /// The Starring are entities mentioned of an action
 /// The Background are entities object of te action
+/// The OnBehalfOf is the entity the recorder is acting for
 ///
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Actor {
@@ -375,6 +698,45 @@ pub enum Actor {
     Lead(Uuid),       // meetings, events, etc
     Starring(Uuid),   // entities attending
     Background(Uuid), // context
+    OnBehalfOf(Uuid), // the entity the recorder is acting for
+}
+
+/// The role of an actor in an event, without the entity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActorRole {
+    RecordedBy,
+    Subject,
+    Lead,
+    Starring,
+    Background,
+    OnBehalfOf,
+}
+
+impl ActorRole {
+    /// Returns the short code of the role, the same
+    /// used as prefix when parsing an actor
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::RecordedBy => "auth",
+            Self::Subject => "subj",
+            Self::Lead => "main",
+            Self::Starring => "star",
+            Self::Background => "back",
+            Self::OnBehalfOf => "obo",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<ActorRole> {
+        match code {
+            "auth" => Some(Self::RecordedBy),
+            "subj" => Some(Self::Subject),
+            "main" => Some(Self::Lead),
+            "star" => Some(Self::Starring),
+            "back" => Some(Self::Background),
+            "obo" => Some(Self::OnBehalfOf),
+            _ => None,
+        }
+    }
 }
 
 impl Actor {
@@ -391,6 +753,7 @@ impl Actor {
             "main" => Ok(Actor::Lead(Uuid::from_str(uid)?)),
             "back" => Ok(Actor::Background(Uuid::from_str(uid)?)),
             "subj" => Ok(Actor::Subject(Uuid::from_str(uid)?)),
+            "obo" => Ok(Actor::OnBehalfOf(Uuid::from_str(uid)?)),
             _ => Ok(Actor::Starring(Uuid::from_str(uid)?)),
         }
     }
@@ -402,9 +765,39 @@ impl Actor {
             Self::Background(uid) => utils::id(uid),
             Self::RecordedBy(uid) => utils::id(uid),
             Self::Subject(uid) => utils::id(uid),
+            Self::OnBehalfOf(uid) => utils::id(uid),
+        }
+    }
+
+    /// Returns the role of the actor
+    pub fn actor_role(&self) -> ActorRole {
+        match self {
+            Self::Lead(_) => ActorRole::Lead,
+            Self::Starring(_) => ActorRole::Starring,
+            Self::Background(_) => ActorRole::Background,
+            Self::RecordedBy(_) => ActorRole::RecordedBy,
+            Self::Subject(_) => ActorRole::Subject,
+            Self::OnBehalfOf(_) => ActorRole::OnBehalfOf,
+        }
+    }
+
+    /// Returns the same actor with a different entity
+    pub fn with_uid(&self, uid: Uuid) -> Actor {
+        match self {
+            Self::Lead(_) => Self::Lead(uid),
+            Self::Starring(_) => Self::Starring(uid),
+            Self::Background(_) => Self::Background(uid),
+            Self::RecordedBy(_) => Self::RecordedBy(uid),
+            Self::Subject(_) => Self::Subject(uid),
+            Self::OnBehalfOf(_) => Self::OnBehalfOf(uid),
         }
     }
 
+    /// Tells if the actor stands for a purged entity
+    pub fn is_tombstone(&self) -> bool {
+        self.role().1 == TOMBSTONE
+    }
+
     pub fn role(&self) -> (String, Uuid) {
         match self {
             Self::Lead(uid) => ("Main".to_string(), *uid),
@@ -412,6 +805,7 @@ impl Actor {
             Self::Background(uid) => ("Background".to_string(), *uid),
             Self::RecordedBy(uid) => ("RecordedBy".to_string(), *uid),
             Self::Subject(uid) => ("Subject".to_string(), *uid),
+            Self::OnBehalfOf(uid) => ("OnBehalfOf".to_string(), *uid),
         }
     }
 }
@@ -423,10 +817,69 @@ impl fmt::Display for Actor {
             Self::Starring(uid) => write!(f, "star:{}", utils::id(uid)),
             Self::Background(uid) => write!(f, "back:{}", utils::id(uid)),
             Self::Subject(uid) => write!(f, "subj:{}", utils::id(uid)),
+            Self::OnBehalfOf(uid) => write!(f, "obo:{}", utils::id(uid)),
+        }
+    }
+}
+
+/// A file that lives with an entity or an event, eg. a contract or a photo
+///
+/// The content is stored in the datastore keyed by its hash, see
+/// DataStore::store_attachment. A reference points to a file kept
+/// somewhere else (eg. a url) and the datastore has no content for it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+    pub added_on: NaiveDate,
+    pub reference: Option<String>,
+}
+
+impl Attachment {
+    /// Create an attachment for the content of a file
+    pub fn file(name: &str, content: &[u8]) -> Attachment {
+        Attachment {
+            hash: utils::hash_bytes(content),
+            name: name.trim().to_owned(),
+            size: content.len() as u64,
+            added_on: utils::today(),
+            reference: None,
+        }
+    }
+
+    /// Create an attachment pointing to a file kept somewhere else
+    pub fn reference(name: &str, location: &str) -> Attachment {
+        Attachment {
+            hash: utils::hash(location.trim()),
+            name: name.trim().to_owned(),
+            size: 0,
+            added_on: utils::today(),
+            reference: Some(location.trim().to_owned()),
+        }
+    }
+
+    /// Tells if the attachment points to a file kept somewhere else
+    pub fn is_reference(&self) -> bool {
+        self.reference.is_some()
+    }
+}
+
+impl fmt::Display for Attachment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reference {
+            Some(r) => write!(f, "{} -> {}", self.name, r),
+            None => write!(f, "{} ({} bytes)", self.name, self.size),
         }
     }
 }
 
+/// Add an attachment to a list, replacing the one with the same content
+fn attach_to(attachments: &mut Vec<Attachment>, a: Attachment) {
+    attachments.retain(|x| x.hash != a.hash);
+    attachments.push(a);
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Event {
     pub uid: Uuid,
@@ -437,6 +890,9 @@ pub struct Event {
     pub actors: Vec<Actor>,
     // ACL
     visibility: Vec<ACL>,
+    // files
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
 }
 
 impl Event {
@@ -448,6 +904,7 @@ impl Event {
             content: None,
             actors: vec![Actor::Lead(Uuid::new_v4())],
             visibility: vec![],
+            attachments: vec![],
         }
     }
 
@@ -459,6 +916,7 @@ impl Event {
             content: msg,
             actors: vec![Actor::Lead(subject.uid)],
             visibility: vec![],
+            attachments: vec![],
         }
     }
 
@@ -476,13 +934,70 @@ impl Event {
             content: content,
             actors: actors.to_owned(),
             visibility: vec![],
+            attachments: vec![],
+        }
+    }
+
+    /// An interaction lasting some minutes, the actors are the
+    /// ones taking part in it and the one recording it
+    pub fn interaction(
+        channel: Channel,
+        minutes: u32,
+        direction: InteractionDirection,
+        content: Option<String>,
+        actors: &[Actor],
+    ) -> Event {
+        Event {
+            uid: Uuid::new_v4(),
+            recorded_at: utils::now_local(),
+            kind: EventType::Interaction(channel, minutes, direction),
+            content,
+            actors: actors.to_owned(),
+            visibility: vec![],
+            attachments: vec![],
         }
     }
 
+    /// A phone call, see interaction
+    pub fn call(
+        minutes: u32,
+        direction: InteractionDirection,
+        content: Option<String>,
+        actors: &[Actor],
+    ) -> Event {
+        Event::interaction(Channel::Call, minutes, direction, content, actors)
+    }
+
+    /// A meeting, the one recording it is the one who set it up
+    pub fn meeting(minutes: u32, content: Option<String>, actors: &[Actor]) -> Event {
+        Event::interaction(
+            Channel::Meeting,
+            minutes,
+            InteractionDirection::Outgoing,
+            content,
+            actors,
+        )
+    }
+
+    /// An email, it takes no time
+    pub fn email(
+        direction: InteractionDirection,
+        content: Option<String>,
+        actors: &[Actor],
+    ) -> Event {
+        Event::interaction(Channel::Email, 0, direction, content, actors)
+    }
+
     pub fn uid(&self) -> String {
         utils::id(&self.uid)
     }
 
+    /// Attach a file to the event, it replaces
+    /// the attachment with the same content if any
+    pub fn attach(&mut self, a: Attachment) {
+        attach_to(&mut self.attachments, a);
+    }
+
     /// Check whenever the recorded date of an event is after of equals to a date
     pub fn is_after_eq(&self, date: NaiveDate) -> bool {
         self.recorded_at.naive_local() >= date.and_hms(0, 0, 0)
@@ -505,6 +1020,154 @@ impl Event {
     }
 }
 
+/// What happened to an entity in an audit entry
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Added,
+    Updated,
+    Removed,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added => write!(f, "added"),
+            Self::Updated => write!(f, "updated"),
+            Self::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+/// An entry of the audit trail, a change to an entity and who made it
+///
+/// The changes are the fields that differ as (field, old value,
+/// new value), see Entity::diff, the password values are never kept
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub entity: Uuid,
+    pub changed_at: DateTime<FixedOffset>,
+    pub changed_by: Option<Uuid>,
+    /// the entity the change was made for, see DataStore::act_as
+    pub on_behalf_of: Option<Uuid>,
+    pub action: AuditAction,
+    pub changes: Vec<(String, String, String)>,
+}
+
+impl AuditEntry {
+    /// Create the entry for a change from old to new, by
+    /// the principal, an entity without old version is added
+    pub fn new(old: Option<&Entity>, new: &Entity, by: Option<Uuid>) -> AuditEntry {
+        let (action, changes) = match old {
+            Some(old) => (AuditAction::Updated, old.diff(new)),
+            None => (AuditAction::Added, Vec::new()),
+        };
+        let changes = changes
+            .into_iter()
+            .map(|(f, o, n)| match f.as_str() {
+                "pass" => (f, "***".to_owned(), "***".to_owned()),
+                _ => (f, o, n),
+            })
+            .collect();
+        AuditEntry {
+            entity: new.uid,
+            changed_at: utils::now_local(),
+            changed_by: by,
+            on_behalf_of: None,
+            action,
+            changes,
+        }
+    }
+
+    /// Create the entry for a removed entity
+    pub fn removed(e: &Entity, by: Option<Uuid>) -> AuditEntry {
+        AuditEntry {
+            entity: e.uid,
+            changed_at: utils::now_local(),
+            changed_by: by,
+            on_behalf_of: None,
+            action: AuditAction::Removed,
+            changes: Vec::new(),
+        }
+    }
+}
+
+/// A typed metadata value, see DataStore::set_meta_value
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MetaValue {
+    Str(String),
+    Int(i64),
+    Date(NaiveDate),
+    Bool(bool),
+}
+
+impl MetaValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Str(v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+    pub fn as_date(&self) -> Option<NaiveDate> {
+        match self {
+            Self::Date(v) => Some(*v),
+            _ => None,
+        }
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MetaValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Str(v) => write!(f, "{}", v),
+            Self::Int(v) => write!(f, "{}", v),
+            Self::Date(v) => write!(f, "{}", v),
+            Self::Bool(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl From<&str> for MetaValue {
+    fn from(v: &str) -> Self {
+        Self::Str(v.to_owned())
+    }
+}
+
+impl From<String> for MetaValue {
+    fn from(v: String) -> Self {
+        Self::Str(v)
+    }
+}
+
+impl From<i64> for MetaValue {
+    fn from(v: i64) -> Self {
+        Self::Int(v)
+    }
+}
+
+impl From<NaiveDate> for MetaValue {
+    fn from(v: NaiveDate) -> Self {
+        Self::Date(v)
+    }
+}
+
+impl From<bool> for MetaValue {
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+
 /// The RelQuality describes the quality of a relationship in a moment in time.
 ///
 /// it is bound to a thing and it's relative to the root entity
@@ -538,14 +1201,49 @@ impl RelQuality {
             _ => None,
         }
     }
-}
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    /// Returns the date range of the quality, the end is excluded
+    pub fn range(&self) -> (NaiveDate, Option<NaiveDate>) {
+        match self {
+            Self::Neutral(s, u)
+            | Self::Formal(s, u)
+            | Self::Friendly(s, u)
+            | Self::Tense(s, u)
+            | Self::Hostile(s, u) => (*s, *u),
+        }
+    }
+
+    /// Returns the same quality ending at a date
+    pub fn ended_on(&self, date: NaiveDate) -> Self {
+        match self {
+            Self::Neutral(s, _) => Self::Neutral(*s, Some(date)),
+            Self::Formal(s, _) => Self::Formal(*s, Some(date)),
+            Self::Friendly(s, _) => Self::Friendly(*s, Some(date)),
+            Self::Tense(s, _) => Self::Tense(*s, Some(date)),
+            Self::Hostile(s, _) => Self::Hostile(*s, Some(date)),
+        }
+    }
+
+    /// Tells if two qualities are of the same kind, the dates are ignored
+    pub fn same_kind(&self, other: &RelQuality) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum RelType {
     RelatedTo,                                  // generic link
     Role(String, NaiveDate, Option<NaiveDate>), // this is the main context
     BelongsTo(NaiveDate, NaiveDate),            // this a context root
     MemberOf(NaiveDate, NaiveDate),             // indicate the context of the thing
+    WorksAt,                                    // a person working for an org
+    Employs,                                    // an org a person works for
+    ManagerOf,                                  // managing someone
+    ReportsTo,                                  // managed by someone
+    PartnerOf,                                  // works together, both ways
+    ClientOf,                                   // buys from
+    SupplierOf,                                 // sells to
+    HasMember(NaiveDate, NaiveDate),            // the members of a context
 }
 
 impl RelType {
@@ -555,6 +1253,32 @@ impl RelType {
             Self::Role(l, _s, _u) => format!("rl:{}", l),
             Self::BelongsTo(_s, _u) => "bt".to_string(),
             Self::MemberOf(_s, _u) => "mo".to_string(),
+            Self::WorksAt => "works_at".to_string(),
+            Self::Employs => "employs".to_string(),
+            Self::ManagerOf => "manager_of".to_string(),
+            Self::ReportsTo => "reports_to".to_string(),
+            Self::PartnerOf => "partner_of".to_string(),
+            Self::ClientOf => "client_of".to_string(),
+            Self::SupplierOf => "supplier_of".to_string(),
+            Self::HasMember(_s, _u) => "hm".to_string(),
+        }
+    }
+
+    /// Returns the relationship seen from the target, eg. the
+    /// inverse of works at is employs. The generic relationships
+    /// and the roles have no inverse
+    pub fn inverse(&self) -> Option<RelType> {
+        match self {
+            Self::RelatedTo | Self::Role(_, _, _) | Self::BelongsTo(_, _) => None,
+            Self::MemberOf(s, u) => Some(Self::HasMember(*s, *u)),
+            Self::HasMember(s, u) => Some(Self::MemberOf(*s, *u)),
+            Self::WorksAt => Some(Self::Employs),
+            Self::Employs => Some(Self::WorksAt),
+            Self::ManagerOf => Some(Self::ReportsTo),
+            Self::ReportsTo => Some(Self::ManagerOf),
+            Self::PartnerOf => Some(Self::PartnerOf),
+            Self::ClientOf => Some(Self::SupplierOf),
+            Self::SupplierOf => Some(Self::ClientOf),
         }
     }
 }
@@ -566,6 +1290,8 @@ impl fmt::Display for RelType {
             Self::Role(l, s, u) => write!(f, ":{}:{:?}:{:?}", l, s, u),
             Self::BelongsTo(s, u) => write!(f, "bt:{:?}:{:?}", s, u),
             Self::MemberOf(s, u) => write!(f, "mo:{:?}:{:?}", s, u),
+            Self::HasMember(s, u) => write!(f, "hm:{:?}:{:?}", s, u),
+            k => write!(f, "{}", k.get_label()),
         }
     }
 }
@@ -574,6 +1300,10 @@ impl fmt::Display for RelType {
 pub struct Rel {
     pub kind: RelType,
     pub target: Uuid,
+    /// the importance of the relationship, the higher the
+    /// weight the sooner the relationship is listed
+    #[serde(default)]
+    pub weight: u32,
 }
 
 impl Rel {
@@ -581,6 +1311,642 @@ impl Rel {
         Rel {
             target: target.uid,
             kind: RelType::RelatedTo,
+            weight: 0,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: RelType) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+}
+
+/// An handle of an entity, eg. an email, the primary handle
+/// is the one to use when only one is needed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handle {
+    pub label: String,
+    pub value: String,
+    pub primary: bool,
+}
+
+impl Handle {
+    pub fn new(label: &str, value: &str, primary: bool) -> Handle {
+        Handle {
+            label: label.to_owned(),
+            value: value.to_owned(),
+            primary,
+        }
+    }
+
+    /// Check an handle value and returns it in its canonical form:
+    /// - email: lowercase
+    /// - mobile and phone: E.164, eg. +41791234567
+    /// - url: with the scheme and a lowercase host
+    /// - telegram: lowercase and without the leading @
+    ///
+    /// the values of the other types are only trimmed
+    pub fn normalize(label: &str, value: &str) -> std::result::Result<String, DataError> {
+        let v = value.trim();
+        let invalid = |reason: &str| {
+            Err(DataError::InvalidHandle(format!(
+                "{}:{} {}",
+                label, v, reason
+            )))
+        };
+        if v.is_empty() {
+            return invalid("is empty");
+        }
+        match label {
+            "email" => {
+                let v = v.to_lowercase();
+                match utils::split_once(&v, '@') {
+                    Some((user, domain))
+                        if !user.is_empty()
+                            && !domain.contains('@')
+                            && domain.contains('.')
+                            && !domain.starts_with('.')
+                            && !domain.ends_with('.')
+                            && !v.contains(char::is_whitespace) =>
+                    {
+                        Ok(v)
+                    }
+                    _ => invalid("is not an email address"),
+                }
+            }
+            "mobile" | "phone" => {
+                let n = v
+                    .chars()
+                    .filter(|c| !c.is_whitespace() && !"-./()".contains(*c))
+                    .collect::<String>();
+                // 00 is the international prefix in most countries
+                let n = match n.strip_prefix("00") {
+                    Some(rest) => format!("+{}", rest),
+                    None => n,
+                };
+                match n.strip_prefix('+') {
+                    Some(d)
+                        if (2..=15).contains(&d.len())
+                            && !d.starts_with('0')
+                            && d.chars().all(|c| c.is_ascii_digit()) =>
+                    {
+                        Ok(n)
+                    }
+                    Some(_) => invalid("is not a phone number"),
+                    None => invalid("misses the country code, eg. +41"),
+                }
+            }
+            "url" => {
+                let (scheme, rest) = match v.find("://") {
+                    Some(i) => (v[..i].to_lowercase(), &v[i + 3..]),
+                    None => ("https".to_owned(), v),
+                };
+                let (host, path) = match rest.find(|c| c == '/' || c == '?' || c == '#') {
+                    Some(i) => rest.split_at(i),
+                    None => (rest, ""),
+                };
+                if (scheme != "http" && scheme != "https")
+                    || !host.contains('.')
+                    || v.contains(char::is_whitespace)
+                {
+                    return invalid("is not a web address");
+                }
+                let path = if path == "/" { "" } else { path };
+                Ok(format!("{}://{}{}", scheme, host.to_lowercase(), path))
+            }
+            "telegram" => {
+                let nick = v.trim_start_matches('@').to_lowercase();
+                match (5..=32).contains(&nick.len())
+                    && nick.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    true => Ok(nick),
+                    false => invalid("is not a telegram username"),
+                }
+            }
+            _ => Ok(v.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.primary {
+            true => write!(f, "{}:{} (primary)", self.label, self.value),
+            false => write!(f, "{}:{}", self.label, self.value),
+        }
+    }
+}
+
+/// How often an important date comes back
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Recurrence {
+    Once,
+    Monthly,
+    Yearly,
+}
+
+impl FromStr for Recurrence {
+    type Err = ValisError;
+
+    fn from_str(s: &str) -> Result<Recurrence> {
+        match s.trim().to_lowercase().as_str() {
+            "once" | "never" => Ok(Self::Once),
+            "monthly" | "month" | "m" => Ok(Self::Monthly),
+            "yearly" | "year" | "y" => Ok(Self::Yearly),
+            _ => Err(ValisError::InputError(format!("unknown recurrence {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Once => write!(f, "once"),
+            Self::Monthly => write!(f, "monthly"),
+            Self::Yearly => write!(f, "yearly"),
+        }
+    }
+}
+
+/// The mean radius of the earth, in km
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// A postal address, the coordinates are optional
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Address {
+    pub street: String,
+    pub city: String,
+    pub country: String,
+    // latitude and longitude, in degrees
+    pub location: Option<(f64, f64)>,
+}
+
+impl Address {
+    pub fn new(street: &str, city: &str, country: &str) -> Address {
+        Address {
+            street: street.trim().to_owned(),
+            city: city.trim().to_owned(),
+            country: country.trim().to_owned(),
+            location: None,
+        }
+    }
+
+    /// Set the coordinates, fails if they are out of range
+    pub fn with_location(mut self, lat: f64, lon: f64) -> Result<Address> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(ValisError::InputError(format!(
+                "invalid coordinates {},{}",
+                lat, lon
+            )));
+        }
+        self.location = Some((lat, lon));
+        Ok(self)
+    }
+
+    /// Tells if the address is in a city, the case does not matter
+    pub fn is_in(&self, city: &str) -> bool {
+        !self.city.is_empty() && self.city.to_lowercase() == city.trim().to_lowercase()
+    }
+
+    /// Returns the distance in km from a point, as the crow
+    /// flies, or None if the address has no coordinates
+    pub fn distance_from(&self, lat: f64, lon: f64) -> Option<f64> {
+        let (a_lat, a_lon) = self.location?;
+        let (p1, p2) = (a_lat.to_radians(), lat.to_radians());
+        let (dp, dl) = ((lat - a_lat).to_radians(), (lon - a_lon).to_radians());
+        let h = (dp / 2.0).sin().powi(2) + p1.cos() * p2.cos() * (dl / 2.0).sin().powi(2);
+        Some(2.0 * EARTH_RADIUS_KM * h.sqrt().asin())
+    }
+}
+
+impl FromStr for Address {
+    type Err = ValisError;
+
+    /// Parse an address in the form "street, city, country", the
+    /// street and the country may be omitted, eg. "Berlin" or
+    /// "Berlin, Germany"
+    fn from_str(s: &str) -> Result<Address> {
+        let parts = s.split(',').map(|p| p.trim()).collect::<Vec<&str>>();
+        let a = match parts.len() {
+            1 => Address::new("", parts[0], ""),
+            2 => Address::new("", parts[0], parts[1]),
+            n => Address::new(&parts[..n - 2].join(", "), parts[n - 2], parts[n - 1]),
+        };
+        match a.city.is_empty() {
+            true => Err(ValisError::InputError(format!("missing city in {}", s))),
+            false => Ok(a),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = [&self.street, &self.city, &self.country]
+            .iter()
+            .filter(|p| !p.is_empty())
+            .map(|p| p.as_str())
+            .collect::<Vec<&str>>();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// A date worth remembering about an entity,
+/// eg. a birthday, an anniversary or a renewal
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportantDate {
+    pub label: String,
+    pub date: NaiveDate,
+    pub recurrence: Recurrence,
+}
+
+impl ImportantDate {
+    pub fn new(label: &str, date: NaiveDate, recurrence: Recurrence) -> ImportantDate {
+        ImportantDate {
+            label: label.trim().to_owned(),
+            date,
+            recurrence,
+        }
+    }
+
+    /// A birthday, it comes back every year
+    pub fn birthday(date: NaiveDate) -> ImportantDate {
+        ImportantDate::new(BIRTHDAY, date, Recurrence::Yearly)
+    }
+
+    /// Tells if the date is a birthday
+    pub fn is_birthday(&self) -> bool {
+        self.slug() == BIRTHDAY
+    }
+
+    /// The label as used in keys, two dates
+    /// of an entity cannot have the same slug
+    pub fn slug(&self) -> String {
+        utils::slugify(&self.label)
+    }
+
+    /// Returns the next occurrence on or after a date, if any.
+    ///
+    /// A date in the future is the first occurrence of itself.
+    /// The yearly dates on the 29th of February fall on the 1st of
+    /// March in the years that are not leap years, the monthly dates
+    /// past the end of a month fall on the last day of the month
+    pub fn next_occurrence(&self, from: &NaiveDate) -> Option<NaiveDate> {
+        if self.date >= *from {
+            return Some(self.date);
+        }
+        match self.recurrence {
+            Recurrence::Once => None,
+            Recurrence::Yearly => {
+                let d = self.date;
+                let on = |y: i32| d.with_year(y).unwrap_or_else(|| utils::date(1, 3, y));
+                match on(from.year()) {
+                    x if x >= *from => Some(x),
+                    _ => Some(on(from.year() + 1)),
+                }
+            }
+            Recurrence::Monthly => {
+                let day = self.date.day();
+                let on = |y: i32, m: u32| {
+                    (0..4)
+                        .find_map(|i| NaiveDate::from_ymd_opt(y, m, day - i))
+                        .unwrap()
+                };
+                match on(from.year(), from.month()) {
+                    x if x >= *from => Some(x),
+                    _ if from.month() == 12 => Some(on(from.year() + 1, 1)),
+                    _ => Some(on(from.year(), from.month() + 1)),
+                }
+            }
+        }
+    }
+
+    /// Returns how many times the date has come back at an
+    /// occurrence, eg. the age on a birthday
+    pub fn count_at(&self, occurrence: &NaiveDate) -> i64 {
+        let (y, m) = (
+            (occurrence.year() - self.date.year()) as i64,
+            occurrence.month() as i64 - self.date.month() as i64,
+        );
+        match self.recurrence {
+            Recurrence::Once => 0,
+            Recurrence::Yearly => y,
+            Recurrence::Monthly => y * 12 + m,
+        }
+    }
+}
+
+impl fmt::Display for ImportantDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on {} ({})",
+            self.label,
+            utils::human_date(&self.date),
+            self.recurrence
+        )
+    }
+}
+
+/// The label of the birthdays
+const BIRTHDAY: &str = "birthday";
+
+/// Something to do about an entity by a date, unlike the next
+/// action an entity can have any number of tasks
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Task {
+    pub uid: Uuid,
+    // the entity the task is about
+    pub entity: Uuid,
+    pub title: String,
+    pub due: NaiveDate,
+    pub done: bool,
+    // the entity in charge of the task, if not the owner
+    pub assignee: Option<Uuid>,
+    pub created_on: NaiveDate,
+}
+
+impl Task {
+    pub fn new(entity: &Entity, title: &str, due: NaiveDate) -> Task {
+        Task {
+            uid: Uuid::new_v4(),
+            entity: entity.uid,
+            title: title.trim().to_owned(),
+            due,
+            done: false,
+            assignee: None,
+            created_on: utils::today(),
+        }
+    }
+
+    pub fn with_assignee(mut self, assignee: &Entity) -> Task {
+        self.assignee = Some(assignee.uid);
+        self
+    }
+
+    pub fn uid(&self) -> String {
+        utils::id(&self.uid)
+    }
+
+    /// Tells if the task is still open past its due date
+    pub fn is_overdue(&self, date: &NaiveDate) -> bool {
+        !self.done && self.due < *date
+    }
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = if self.done { "x" } else { " " };
+        write!(
+            f,
+            "[{}] {} by {}",
+            mark,
+            self.title,
+            utils::human_date(&self.due)
+        )
+    }
+}
+
+/// Where a goal stands
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum GoalStatus {
+    Active,
+    Achieved,
+    Abandoned,
+}
+
+impl FromStr for GoalStatus {
+    type Err = ValisError;
+
+    fn from_str(s: &str) -> Result<GoalStatus> {
+        match s.trim().to_lowercase().as_str() {
+            "active" => Ok(Self::Active),
+            "achieved" | "done" => Ok(Self::Achieved),
+            "abandoned" | "dropped" => Ok(Self::Abandoned),
+            _ => Err(ValisError::InputError(format!("unknown goal status {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for GoalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Active => write!(f, "active"),
+            Self::Achieved => write!(f, "achieved"),
+            Self::Abandoned => write!(f, "abandoned"),
+        }
+    }
+}
+
+/// Something to achieve by a date that involves
+/// several entities, eg. closing a funding round
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Goal {
+    pub uid: Uuid,
+    pub name: String,
+    pub target: NaiveDate,
+    pub status: GoalStatus,
+    pub entities: Vec<Uuid>,
+    pub events: Vec<Uuid>,
+    pub created_on: NaiveDate,
+}
+
+impl Goal {
+    pub fn new(name: &str, target: NaiveDate) -> Goal {
+        Goal {
+            uid: Uuid::new_v4(),
+            name: name.trim().to_owned(),
+            target,
+            status: GoalStatus::Active,
+            entities: Vec::new(),
+            events: Vec::new(),
+            created_on: utils::today(),
+        }
+    }
+
+    pub fn uid(&self) -> String {
+        utils::id(&self.uid)
+    }
+
+    /// The name as used to look up a goal, two
+    /// goals cannot have the same slug
+    pub fn slug(&self) -> String {
+        utils::slugify(&self.name)
+    }
+
+    pub fn with_entity(mut self, e: &Entity) -> Goal {
+        self.add_entity(e);
+        self
+    }
+
+    /// Add an entity to the goal, once
+    pub fn add_entity(&mut self, e: &Entity) {
+        if !self.entities.contains(&e.uid) {
+            self.entities.push(e.uid);
+        }
+    }
+
+    /// Remove an entity from the goal, returns true if it was there
+    pub fn remove_entity(&mut self, uid: &Uuid) -> bool {
+        let n = self.entities.len();
+        self.entities.retain(|x| x != uid);
+        n != self.entities.len()
+    }
+
+    /// Link an event to the goal, once
+    pub fn link_event(&mut self, evt: &Event) {
+        if !self.events.contains(&evt.uid) {
+            self.events.push(evt.uid);
+        }
+    }
+
+    /// Tells if the goal involves an entity
+    pub fn involves(&self, e: &Entity) -> bool {
+        self.entities.contains(&e.uid)
+    }
+
+    /// Tells if the goal is still active past its target date
+    pub fn is_overdue(&self, date: &NaiveDate) -> bool {
+        self.status == GoalStatus::Active && self.target < *date
+    }
+}
+
+impl fmt::Display for Goal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} by {} ({})",
+            self.name,
+            utils::human_date(&self.target),
+            self.status
+        )
+    }
+}
+
+/// The value of a custom field of an entity
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Text(String),
+    Number(f64),
+    Date(NaiveDate),
+    Url(String),
+}
+
+impl FieldValue {
+    /// The names of the field kinds, see parse
+    pub fn kinds() -> [&'static str; 4] {
+        ["text", "number", "date", "url"]
+    }
+
+    /// Parse a value of a kind (text, number, date or url),
+    /// the dates are in the formats of utils::date_from_str
+    /// and the urls must start with http:// or https://
+    pub fn parse(kind: &str, value: &str) -> Result<FieldValue> {
+        let value = value.trim();
+        let invalid = || ValisError::InputError(format!("{} is not a valid {}", value, kind));
+        match kind {
+            "text" if !value.is_empty() => Ok(Self::Text(value.to_owned())),
+            "number" => match value.parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(Self::Number(n)),
+                _ => Err(invalid()),
+            },
+            "date" => utils::date_from_str(value)
+                .map(Self::Date)
+                .ok_or_else(invalid),
+            "url" if value.starts_with("http://") || value.starts_with("https://") => {
+                Ok(Self::Url(value.to_owned()))
+            }
+            "text" | "url" => Err(invalid()),
+            _ => Err(ValisError::InputError(format!(
+                "unknown field kind {}",
+                kind
+            ))),
+        }
+    }
+
+    /// Returns the name of the kind of the value
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Text(_) => "text",
+            Self::Number(_) => "number",
+            Self::Date(_) => "date",
+            Self::Url(_) => "url",
+        }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text(v) | Self::Url(v) => write!(f, "{}", v),
+            Self::Number(v) => write!(f, "{}", v),
+            Self::Date(v) => write!(f, "{}", utils::human_date(v)),
+        }
+    }
+}
+
+/// How complete the profile of an entity is, field by field
+///
+/// Every field has a weight that counts towards the score
+/// only when the field is filled
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completeness {
+    /// the fields as (name, weight, filled)
+    pub fields: Vec<(&'static str, i64, bool)>,
+}
+
+impl Completeness {
+    /// Returns the sum of the weights of the filled fields
+    pub fn score(&self) -> i64 {
+        self.fields.iter().filter(|f| f.2).map(|f| f.1).sum()
+    }
+
+    /// Returns the highest score possible
+    pub fn max(&self) -> i64 {
+        self.fields.iter().map(|f| f.1).sum()
+    }
+
+    /// Returns the score as a percentage of the highest score
+    pub fn percent(&self) -> u8 {
+        match self.max() {
+            0 => 100,
+            m => (self.score() * 100 / m) as u8,
+        }
+    }
+
+    /// Returns the names of the fields that are not filled,
+    /// the heaviest first
+    pub fn missing(&self) -> Vec<&'static str> {
+        let mut missing = self.fields.iter().filter(|f| !f.2).collect::<Vec<_>>();
+        missing.sort_by(|a, b| b.1.cmp(&a.1));
+        missing.into_iter().map(|f| f.0).collect()
+    }
+
+    /// Mark a field as filled
+    pub fn fill(&mut self, field: &str) {
+        self.fields
+            .iter_mut()
+            .filter(|f| f.0 == field)
+            .for_each(|f| f.2 = true);
+    }
+}
+
+impl fmt::Display for Completeness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.missing().as_slice() {
+            [] => write!(f, "{}% complete", self.percent()),
+            [m] => write!(f, "{}% complete, missing {}", self.percent(), m),
+            [ms @ .., m] => write!(
+                f,
+                "{}% complete, missing {} and {}",
+                self.percent(),
+                ms.join(", "),
+                m
+            ),
         }
     }
 }
@@ -593,7 +1959,7 @@ pub struct Entity {
     pub name: String, // Ada, Kitchen Table, Google
     pub tags: HashMap<String, Tag>,
     pub description: String,
-    pub handles: HashMap<String, String>, // email, telegram, phone
+    pub handles: HashMap<String, String>, // email, telegram, phone, the primary ones
     // contextual data
     pub class: String, // person / object / company / project
     pub state: RelState,
@@ -610,6 +1976,45 @@ pub struct Entity {
     pub relationships: Vec<Rel>,
     // ACL
     pub visibility: Vec<ACL>,
+    // the fields below came after the first stored format, the entities
+    // stored in that format are migrated on open, see legacy
+    //
+    // moments, the birthday is kept to read the
+    // existing data, the new ones go in the dates
+    #[serde(default)]
+    pub birthday: Option<NaiveDate>,
+    // the date the entity has been archived
+    #[serde(default)]
+    pub archived: Option<NaiveDate>,
+    // custom fields, eg. company size or renewal date
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldValue>,
+    // birthdays, anniversaries and other dates to remember
+    #[serde(default)]
+    pub dates: Vec<ImportantDate>,
+    // nicknames, maiden names and other names the entity goes by
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    // files, eg. contracts and photos
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub priority: Priority,
+    // the previous qualities, oldest first, the current one is quality
+    #[serde(default)]
+    pub quality_history: Vec<RelQuality>,
+    // the lifecycle state, eg. lead or client, see ledger::Lifecycle
+    #[serde(default)]
+    pub stage: Option<String>,
+    // how often to get in touch, eg. every 2 weeks
+    #[serde(default)]
+    pub contact_every: Option<TimeWindow>,
+    // the handles besides the primary ones, eg. a work email
+    #[serde(default)]
+    pub other_handles: Vec<(String, String)>,
+    // where the entity lives or is located
+    #[serde(default)]
+    pub address: Option<Address>,
 }
 
 /// Holds a transaction information
@@ -649,11 +2054,63 @@ impl Entity {
         self.tags.contains_key(&utils::slugify(&tag))
     }
 
+    /// Returns the highest access role granted to the entity, if any
+    pub fn access_role(&self) -> Option<AccessRole> {
+        AccessRole::all()
+            .iter()
+            .find(|r| {
+                self.tags
+                    .contains_key(&utils::slugify(&r.tag().to_string_full()))
+            })
+            .copied()
+    }
+
+    /// Replace the access role of the entity, None removes it
+    pub fn set_access_role(&mut self, role: Option<AccessRole>) {
+        for r in AccessRole::all().iter() {
+            self.remove_tag(&r.tag());
+        }
+        if let Some(r) = role {
+            self.add_tag(r.tag());
+        }
+    }
+
+    /// Tells if the entity is a user that has been disabled, a
+    /// disabled user keeps its role but cannot act with it
+    pub fn is_disabled(&self) -> bool {
+        self.tags
+            .contains_key(&utils::slugify(&disabled_tag().to_string_full()))
+    }
+
+    /// Disable or enable back a user
+    pub fn set_disabled(&mut self, disabled: bool) {
+        match disabled {
+            true => self.add_tag(disabled_tag()),
+            false => self.remove_tag(&disabled_tag()),
+        }
+    }
+
     /// Tells wherever the entity has a class set
     pub fn is_classified(&self) -> bool {
         !self.class.is_empty() && self.class != "n/a"
     }
 
+    /// Returns how complete the entity is, the name is not counted
+    /// since it is always set. See DataStore::score to count the
+    /// relationships pointing to the entity as well
+    pub fn completeness_score(&self) -> Completeness {
+        Completeness {
+            fields: vec![
+                ("class", 5, self.is_classified()),
+                ("description", 1, !self.description.is_empty()),
+                ("handles", 3, !self.handles.is_empty()),
+                ("tags", 3, !self.tags.is_empty()),
+                ("updates", 1, self.updated_on != self.created_on),
+                ("relationships", 2, !self.relationships.is_empty()),
+            ],
+        }
+    }
+
     /// actions
     pub fn action_within(&self, date: &NaiveDate) -> bool {
         self.next_action_date <= *date
@@ -664,6 +2121,23 @@ impl Entity {
         self.next_action_date >= *from && self.next_action_date < *to
     }
 
+    /// Returns the number of days the next action is overdue at a date,
+    /// zero if it is not overdue
+    pub fn overdue_days(&self, date: &NaiveDate) -> i64 {
+        match self.next_action_date < *date {
+            true => (*date - self.next_action_date).num_days(),
+            false => 0,
+        }
+    }
+
+    /// Tells if the next action has been pushed forward in time
+    /// compared to a previous version of the entity without being
+    /// completed (the note is still the same)
+    pub fn is_postponed(&self, previous: &Entity) -> bool {
+        self.next_action_date > previous.next_action_date
+            && self.next_action_note == previous.next_action_note
+    }
+
     /// Get the progress of the transaction at date
     ///
     /// None will use today as a data
@@ -710,6 +2184,35 @@ impl Entity {
         self.next_action_updated_on = utils::today();
     }
 
+    /// Set how often to get in touch (chainable version)
+    pub fn with_contact_every(mut self, every: TimeWindow) -> Self {
+        self.contact_every = Some(every);
+        self.touch()
+    }
+
+    /// Set how often to get in touch, none to stop tracking it
+    pub fn set_contact_every(&mut self, every: Option<TimeWindow>) {
+        self.contact_every = every;
+        self.touch_as_ref();
+    }
+
+    /// Set the address (chainable version)
+    pub fn with_address(mut self, address: Address) -> Self {
+        self.address = Some(address);
+        self.touch()
+    }
+
+    /// Set the address, none to remove it
+    pub fn set_address(&mut self, address: Option<Address>) {
+        self.address = address;
+        self.touch_as_ref();
+    }
+
+    /// Tells if the entity is located in a city
+    pub fn is_in(&self, city: &str) -> bool {
+        self.address.as_ref().map_or(false, |a| a.is_in(city))
+    }
+
     pub fn with_next_action(mut self, date: NaiveDate, note: String) -> Self {
         self.next_action_date = date;
         self.next_action_note = note;
@@ -717,16 +2220,128 @@ impl Entity {
         self
     }
 
-    pub fn with_handle(mut self, label: &str, id: &str) -> Self {
-        self.handles.insert(label.to_owned(), id.to_owned());
-        self.touch()
+    /// Add an handle (chainable version), the invalid
+    /// handles are ignored, see add_handle
+    pub fn with_handle(mut self, label: &str, id: &str) -> Self {
+        self.add_handle(label, id).ok();
+        self
+    }
+
+    /// Add an handle, the value is checked and normalized according
+    /// to its type (see Handle::normalize). The first one of a type
+    /// is the primary one, adding an handle twice is a no-op
+    pub fn add_handle(&mut self, label: &str, id: &str) -> std::result::Result<(), DataError> {
+        let id = Handle::normalize(label, id)?;
+        if self.push_handle(label, id) {
+            self.touch_as_ref();
+        }
+        Ok(())
+    }
+
+    /// Append an handle as it is, it becomes the primary one
+    /// if there is none of its type. Returns false if the
+    /// entity has the handle already
+    fn push_handle(&mut self, label: &str, id: String) -> bool {
+        if self.has_handle(label, &id) {
+            return false;
+        }
+        match self.handles.contains_key(label) {
+            true => self.other_handles.push((label.to_owned(), id)),
+            false => {
+                self.handles.insert(label.to_owned(), id);
+            }
+        }
+        true
+    }
+
+    /// Make an handle the primary one of its type, the previous
+    /// primary one is kept. The handle is added if missing
+    pub fn set_primary_handle(
+        &mut self,
+        label: &str,
+        id: &str,
+    ) -> std::result::Result<(), DataError> {
+        let id = Handle::normalize(label, id)?;
+        if self.handles.get(label) == Some(&id) {
+            return Ok(());
+        }
+        self.other_handles
+            .retain(|(l, v)| !(l == label && *v == id));
+        if let Some(previous) = self.handles.insert(label.to_owned(), id) {
+            self.other_handles.insert(0, (label.to_owned(), previous));
+        }
+        self.touch_as_ref();
+        Ok(())
+    }
+
+    /// Normalize the handles, see Handle::normalize, the handles that
+    /// become the same are merged. The invalid handles are kept as
+    /// they are and returned
+    pub fn normalize_handles(&mut self) -> Vec<Handle> {
+        let handles = self.all_handles();
+        let mut invalid = Vec::new();
+        self.handles.clear();
+        self.other_handles.clear();
+        for h in handles.iter() {
+            let v = match Handle::normalize(&h.label, &h.value) {
+                Ok(v) => v,
+                Err(_) => {
+                    invalid.push(h.clone());
+                    h.value.to_owned()
+                }
+            };
+            self.push_handle(&h.label, v);
+        }
+        if self.all_handles() != handles {
+            self.touch_as_ref();
+        }
+        invalid
     }
 
-    pub fn add_handle(&mut self, label: &str, id: &str) {
-        self.handles.insert(label.to_owned(), id.to_owned());
+    /// Remove an handle, when the primary one is removed
+    /// the next one of the same type takes its place
+    pub fn remove_handle(&mut self, label: &str, id: &str) {
+        if !self.has_handle(label, id) {
+            return;
+        }
+        self.other_handles.retain(|(l, v)| !(l == label && v == id));
+        if self.handles.get(label).map(|v| &v[..]) == Some(id) {
+            self.handles.remove(label);
+            if let Some(i) = self.other_handles.iter().position(|(l, _)| l == label) {
+                let (_, next) = self.other_handles.remove(i);
+                self.handles.insert(label.to_owned(), next);
+            }
+        }
         self.touch_as_ref();
     }
 
+    /// Tells if the entity has an handle, primary or not
+    pub fn has_handle(&self, label: &str, id: &str) -> bool {
+        self.handles.get(label).map(|v| &v[..]) == Some(id)
+            || self
+                .other_handles
+                .iter()
+                .any(|(l, v)| l == label && v == id)
+    }
+
+    /// Returns all the handles sorted by type,
+    /// the primary one first within a type
+    pub fn all_handles(&self) -> Vec<Handle> {
+        let mut all = self
+            .handles
+            .iter()
+            .map(|(l, v)| Handle::new(l, v, true))
+            .chain(
+                self.other_handles
+                    .iter()
+                    .map(|(l, v)| Handle::new(l, v, false)),
+            )
+            .collect::<Vec<Handle>>();
+        // the sort is stable, so the other handles keep their order
+        all.sort_by(|a, b| a.label.cmp(&b.label).then(b.primary.cmp(&a.primary)));
+        all
+    }
+
     /// add a tag to an entity (chainable version)
     pub fn with_tag(mut self, tag: Tag) -> Self {
         self.tags.insert(utils::slugify(&tag.to_string_full()), tag);
@@ -739,6 +2354,53 @@ impl Entity {
         self.touch_as_ref();
     }
 
+    /// remove a tag from an entity
+    pub fn remove_tag(&mut self, tag: &Tag) {
+        if self
+            .tags
+            .remove(&utils::slugify(&tag.to_string_full()))
+            .is_some()
+        {
+            self.touch_as_ref();
+        }
+    }
+
+    /// Tells if the entity has been captured and waits in the inbox to be processed
+    pub fn is_inbox(&self) -> bool {
+        self.has_tag("sys:inbox")
+    }
+
+    /// Returns the fields that differ between two entities,
+    /// as (field, value in self, value in other)
+    pub fn diff(&self, other: &Entity) -> Vec<(String, String, String)> {
+        let a = serde_json::to_value(self).unwrap();
+        let b = serde_json::to_value(other).unwrap();
+        let (a, b) = match (a.as_object(), b.as_object()) {
+            (Some(a), Some(b)) => (a.clone(), b.clone()),
+            _ => return Vec::new(),
+        };
+        a.iter()
+            .filter(|(k, v)| b.get(*k) != Some(v))
+            .map(|(k, v)| {
+                let o = b.get(k).map(|o| o.to_string()).unwrap_or_default();
+                (k.to_owned(), v.to_string(), o)
+            })
+            .collect()
+    }
+
+    /// Returns a copy of the entity with the listed fields
+    /// taken from another entity, see diff for the field names
+    pub fn merge_fields(&self, other: &Entity, fields: &[String]) -> Entity {
+        let mut a = serde_json::to_value(self).unwrap();
+        let b = serde_json::to_value(other).unwrap();
+        for f in fields {
+            if let (Some(a), Some(v)) = (a.as_object_mut(), b.get(f)) {
+                a.insert(f.to_owned(), v.clone());
+            }
+        }
+        serde_json::from_value(a).unwrap()
+    }
+
     pub fn with_sponsor(mut self, sponsor: &Entity) -> Self {
         self.sponsor = sponsor.uid.clone();
         self.touch()
@@ -764,14 +2426,224 @@ impl Entity {
         self.touch()
     }
 
-    /// Update the relationship quality
+    /// Attach a file to the entity, it replaces
+    /// the attachment with the same content if any
+    pub fn attach(&mut self, a: Attachment) {
+        attach_to(&mut self.attachments, a);
+        self.touch_as_ref();
+    }
+
+    /// Remove an attachment by hash, returns the attachment removed if any
+    pub fn detach(&mut self, hash: &str) -> Option<Attachment> {
+        let i = self.attachments.iter().position(|a| a.hash == hash)?;
+        self.touch_as_ref();
+        Some(self.attachments.remove(i))
+    }
+
+    /// Add an alternate name (chainable version)
+    pub fn with_alias(mut self, alias: &str) -> Self {
+        self.add_alias(alias);
+        self
+    }
+
+    /// Add an alternate name, the empty ones and the ones
+    /// the entity already goes by are ignored
+    pub fn add_alias(&mut self, alias: &str) {
+        let alias = alias.trim();
+        if alias.is_empty() || self.is_named(alias) {
+            return;
+        }
+        self.aliases.push(alias.to_owned());
+        self.touch_as_ref();
+    }
+
+    /// Remove an alternate name, tells if it was there
+    pub fn remove_alias(&mut self, alias: &str) -> bool {
+        let n = self.aliases.len();
+        self.aliases
+            .retain(|a| !a.eq_ignore_ascii_case(alias.trim()));
+        if n == self.aliases.len() {
+            return false;
+        }
+        self.touch_as_ref();
+        true
+    }
+
+    /// Tells if the entity goes by a name, either
+    /// its own or an alias, ignoring the case
+    pub fn is_named(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+
+    /// Set a custom field (chainable version)
+    pub fn with_field(mut self, name: &str, value: FieldValue) -> Self {
+        self.fields.insert(name.trim().to_owned(), value);
+        self.touch()
+    }
+
+    /// Set a custom field
+    pub fn set_field(&mut self, name: &str, value: FieldValue) {
+        self.fields.insert(name.trim().to_owned(), value);
+        self.touch_as_ref();
+    }
+
+    /// Remove a custom field, returns the value removed if any
+    pub fn remove_field(&mut self, name: &str) -> Option<FieldValue> {
+        let v = self.fields.remove(name.trim());
+        if v.is_some() {
+            self.touch_as_ref();
+        }
+        v
+    }
+
+    /// Get the value of a custom field
+    pub fn get_field(&self, name: &str) -> Option<&FieldValue> {
+        self.fields.get(name.trim())
+    }
+
+    /// Set the priority of the entity
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self.touch()
+    }
+
+    /// Set the birthday of the entity
+    pub fn with_birthday(self, date: NaiveDate) -> Self {
+        self.with_date(ImportantDate::birthday(date))
+    }
+
+    /// Add an important date (chainable version),
+    /// it replaces the one with the same label if any
+    pub fn with_date(mut self, date: ImportantDate) -> Self {
+        self.set_date(date);
+        self
+    }
+
+    /// Add an important date, it replaces the one with the same label if any
+    pub fn set_date(&mut self, date: ImportantDate) {
+        if date.is_birthday() {
+            self.birthday = None;
+        }
+        let slug = date.slug();
+        self.dates.retain(|d| d.slug() != slug);
+        self.dates.push(date);
+        self.touch_as_ref();
+    }
+
+    /// Remove an important date by label, returns the date removed if any
+    pub fn remove_date(&mut self, label: &str) -> Option<ImportantDate> {
+        let slug = utils::slugify(label);
+        let found = self
+            .important_dates()
+            .into_iter()
+            .find(|d| d.slug() == slug)?;
+        if found.is_birthday() {
+            self.birthday = None;
+        }
+        self.dates.retain(|d| d.slug() != slug);
+        self.touch_as_ref();
+        Some(found)
+    }
+
+    /// Returns the important dates of the entity, including
+    /// the birthday stored before the dates were introduced
+    pub fn important_dates(&self) -> Vec<ImportantDate> {
+        let mut dates = self.dates.clone();
+        if let Some(b) = self.birthday {
+            if !dates.iter().any(|d| d.is_birthday()) {
+                dates.insert(0, ImportantDate::birthday(b));
+            }
+        }
+        dates
+    }
+
+    /// Returns the occurrences of the important dates within a
+    /// date range (since included, until excluded) sorted by date
+    pub fn upcoming_dates(
+        &self,
+        since: &NaiveDate,
+        until: &NaiveDate,
+    ) -> Vec<(NaiveDate, ImportantDate)> {
+        let mut found = self
+            .important_dates()
+            .into_iter()
+            .filter_map(|d| d.next_occurrence(since).map(|o| (o, d)))
+            .filter(|(o, _)| o < until)
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        found
+    }
+
+    /// Returns the next birthday on or after a date, see
+    /// ImportantDate::next_occurrence
+    pub fn next_birthday(&self, from: &NaiveDate) -> Option<NaiveDate> {
+        self.important_dates()
+            .iter()
+            .find(|d| d.is_birthday())?
+            .next_occurrence(from)
+    }
+
+    /// Returns since when the relationship has been tense, if it is
+    pub fn tense_since(&self) -> Option<NaiveDate> {
+        match self.quality {
+            RelQuality::Tense(since, None) => Some(since),
+            _ => None,
+        }
+    }
+
+    /// Tells if the entity has been archived
+    pub fn is_archived(&self) -> bool {
+        self.archived.is_some()
+    }
+
+    /// Archive the entity, it is no longer tracked but its history is kept
+    pub fn archive(&mut self, date: NaiveDate) {
+        if self.archived.is_none() {
+            self.archived = Some(date);
+            self.touch_as_ref();
+        }
+    }
+
+    /// Bring back an archived entity
+    pub fn unarchive(&mut self) {
+        if self.archived.take().is_some() {
+            self.touch_as_ref();
+        }
+    }
+
+    /// Update the relationship quality, when the kind of quality
+    /// changes the previous one is ended and kept in the history
     pub fn set_quality(&mut self, new: RelQuality) {
-        if self.quality != new {
-            self.quality = new;
+        if self.quality == new {
+            return;
+        }
+        if !self.quality.same_kind(&new) {
+            let (since, _) = new.range();
+            self.quality_history.push(self.quality.ended_on(since));
+        }
+        self.quality = new;
+        self.touch_as_ref();
+    }
+
+    /// Set the lifecycle state, the transition is not checked,
+    /// see DataStore::move_to_stage
+    pub fn set_stage(&mut self, stage: &str) {
+        if self.stage.as_deref() != Some(stage) {
+            self.stage = Some(stage.to_owned());
             self.touch_as_ref();
         }
     }
 
+    /// Returns the qualities of the relationship over
+    /// time, oldest first, the last one is the current
+    pub fn quality_history(&self) -> Vec<RelQuality> {
+        let mut history = self.quality_history.clone();
+        history.push(self.quality.clone());
+        history
+    }
+
     /// Add a relation to the entity (chainable version)
     pub fn with_relation(mut self, rel: &Rel) -> Self {
         self.relationships.push(rel.to_owned());
@@ -788,10 +2660,29 @@ impl Entity {
         self.relationships.push(Rel {
             target: target.uid.clone(),
             kind,
+            weight: 0,
         });
         self
     }
 
+    /// Returns the relationships sorted by weight, the heaviest
+    /// first, the ones with the same weight keep their order
+    pub fn sorted_relations(&self) -> Vec<&Rel> {
+        let mut rels = self.relationships.iter().collect::<Vec<&Rel>>();
+        rels.sort_by(|a, b| b.weight.cmp(&a.weight));
+        rels
+    }
+
+    /// Set the weight of a relationship by its position
+    pub fn set_relation_weight(&mut self, idx: usize, weight: u32) {
+        if let Some(r) = self.relationships.get_mut(idx) {
+            if r.weight != weight {
+                r.weight = weight;
+                self.touch_as_ref();
+            }
+        }
+    }
+
     pub fn authorized(&self, pwd: Option<&String>) -> Result<()> {
         match &self.pass {
             Some(ph) => match pwd.is_some() && pwd.unwrap() == ph {
@@ -864,6 +2755,18 @@ impl Entity {
             next_action_note: next_action_note.to_string(),
             relationships,
             visibility,
+            birthday: None,
+            archived: None,
+            fields: BTreeMap::new(),
+            dates: Vec::new(),
+            aliases: Vec::new(),
+            attachments: Vec::new(),
+            priority: Priority::Normal,
+            quality_history: Vec::new(),
+            stage: None,
+            contact_every: None,
+            other_handles: Vec::new(),
+            address: None,
         }
     }
 
@@ -939,6 +2842,170 @@ pub fn id(prefix: &str, value: &str) -> String {
     format!("{}:{}", prefix, value)
 }
 
+/// The records in the format they were stored in the older schema
+/// versions, frozen to read and migrate the datastores written in
+/// those formats: the entities and events before the datastore
+/// recorded a schema version and the audit entries of version 1
+pub(crate) mod legacy {
+    use super::{Actor, AuditAction, EventType, Priority};
+    use super::{RelQuality, RelState, RelType, Tag, Uuid, ACL};
+    use bincode::Options;
+    use chrono::{DateTime, FixedOffset, NaiveDate};
+    use serde::Deserialize;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[derive(Deserialize)]
+    struct Rel {
+        kind: RelType,
+        target: Uuid,
+    }
+
+    #[derive(Deserialize)]
+    struct Entity {
+        uid: Uuid,
+        pass: Option<String>,
+        name: String,
+        tags: HashMap<String, Tag>,
+        description: String,
+        handles: HashMap<String, String>,
+        class: String,
+        state: RelState,
+        quality: RelQuality,
+        sponsor: Uuid,
+        created_on: NaiveDate,
+        updated_on: NaiveDate,
+        next_action_updated_on: NaiveDate,
+        next_action_date: NaiveDate,
+        next_action_note: String,
+        relationships: Vec<Rel>,
+        visibility: Vec<ACL>,
+    }
+
+    #[derive(Deserialize)]
+    struct Event {
+        uid: Uuid,
+        recorded_at: DateTime<FixedOffset>,
+        kind: EventType,
+        content: Option<String>,
+        actors: Vec<Actor>,
+        visibility: Vec<ACL>,
+    }
+
+    #[derive(Deserialize)]
+    struct AuditEntry {
+        entity: Uuid,
+        changed_at: DateTime<FixedOffset>,
+        changed_by: Option<Uuid>,
+        action: AuditAction,
+        changes: Vec<(String, String, String)>,
+    }
+
+    impl From<Entity> for super::Entity {
+        fn from(e: Entity) -> Self {
+            super::Entity {
+                uid: e.uid,
+                pass: e.pass,
+                name: e.name,
+                tags: e.tags,
+                description: e.description,
+                handles: e.handles,
+                class: e.class,
+                state: e.state,
+                quality: e.quality,
+                sponsor: e.sponsor,
+                created_on: e.created_on,
+                updated_on: e.updated_on,
+                next_action_updated_on: e.next_action_updated_on,
+                next_action_date: e.next_action_date,
+                next_action_note: e.next_action_note,
+                relationships: e
+                    .relationships
+                    .into_iter()
+                    .map(|r| super::Rel {
+                        kind: r.kind,
+                        target: r.target,
+                        weight: 0,
+                    })
+                    .collect(),
+                visibility: e.visibility,
+                birthday: None,
+                archived: None,
+                fields: BTreeMap::new(),
+                dates: vec![],
+                aliases: vec![],
+                attachments: vec![],
+                priority: Priority::default(),
+                quality_history: vec![],
+                stage: None,
+                contact_every: None,
+                other_handles: vec![],
+                address: None,
+            }
+        }
+    }
+
+    impl From<Event> for super::Event {
+        fn from(evt: Event) -> Self {
+            super::Event {
+                uid: evt.uid,
+                recorded_at: evt.recorded_at,
+                kind: evt.kind,
+                content: evt.content,
+                actors: evt.actors,
+                visibility: evt.visibility,
+                attachments: vec![],
+            }
+        }
+    }
+
+    impl From<AuditEntry> for super::AuditEntry {
+        fn from(a: AuditEntry) -> Self {
+            super::AuditEntry {
+                entity: a.entity,
+                changed_at: a.changed_at,
+                changed_by: a.changed_by,
+                on_behalf_of: None,
+                action: a.action,
+                changes: a.changes,
+            }
+        }
+    }
+
+    /// Decode a value that must fill the whole record,
+    /// unlike bincode::deserialize that ignores what is left
+    fn decode<'a, T: Deserialize<'a>>(raw: &'a [u8]) -> bincode::Result<T> {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+            .deserialize(raw)
+    }
+
+    /// Decode an entity stored in an older schema version, the ones
+    /// already in the current format are read as they are
+    pub(crate) fn entity(raw: &[u8]) -> bincode::Result<super::Entity> {
+        match decode::<Entity>(raw) {
+            Ok(e) => Ok(e.into()),
+            Err(_) => decode(raw),
+        }
+    }
+
+    /// Decode an event stored in an older schema version, see entity
+    pub(crate) fn event(raw: &[u8]) -> bincode::Result<super::Event> {
+        match decode::<Event>(raw) {
+            Ok(evt) => Ok(evt.into()),
+            Err(_) => decode(raw),
+        }
+    }
+
+    /// Decode an audit entry stored in an older schema version, see entity
+    pub(crate) fn audit_entry(raw: &[u8]) -> bincode::Result<super::AuditEntry> {
+        match decode::<AuditEntry>(raw) {
+            Ok(a) => Ok(a.into()),
+            Err(_) => decode(raw),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1137,6 +3204,537 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_interaction() {
+        let (jane, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let actors = [Actor::RecordedBy(bob), Actor::Lead(jane)];
+        let evt = Event::call(25, InteractionDirection::Incoming, None, &actors);
+        assert_eq!(
+            evt.kind,
+            EventType::Interaction(Channel::Call, 25, InteractionDirection::Incoming)
+        );
+        assert_eq!(evt.kind.to_string(), "interaction:call:25:incoming");
+        assert_eq!(evt.kind.is_interaction(), true);
+        assert_eq!(evt.kind.is_log(), false);
+        assert_eq!(evt.kind.val(), "call");
+        assert_eq!(evt.actors.len(), 2);
+        let evt = Event::meeting(60, Some("lunch".to_owned()), &actors);
+        assert_eq!(
+            evt.kind,
+            EventType::Interaction(Channel::Meeting, 60, InteractionDirection::Outgoing)
+        );
+        assert_eq!(evt.content, Some("lunch".to_owned()));
+        let evt = Event::email(InteractionDirection::Outgoing, None, &actors);
+        assert_eq!(evt.kind.to_string(), "interaction:email:0:outgoing");
+        // parsing
+        assert_eq!(Channel::from_str(" Meet ").unwrap(), Channel::Meeting);
+        assert_eq!(Channel::from_str("fax").is_err(), true);
+        for c in Channel::all() {
+            assert_eq!(Channel::from_str(&c.to_string()).unwrap(), c);
+        }
+        assert_eq!(
+            InteractionDirection::from_str("in").unwrap(),
+            InteractionDirection::Incoming
+        );
+        assert_eq!(InteractionDirection::from_str("sideways").is_err(), true);
+    }
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(Priority::default(), Priority::Normal);
+        assert_eq!(Priority::Critical > Priority::High, true);
+        assert_eq!(Priority::Low < Priority::Normal, true);
+        for p in Priority::all().iter() {
+            assert_eq!(p.to_string().parse::<Priority>().unwrap(), *p);
+        }
+        assert_eq!(" HIGH ".parse::<Priority>().unwrap(), Priority::High);
+        assert_eq!("urgent".parse::<Priority>().is_err(), true);
+        let e = Entity::from("acme").unwrap();
+        assert_eq!(e.priority, Priority::Normal);
+        assert_eq!(e.with_priority(Priority::High).priority, Priority::High);
+    }
+
+    #[test]
+    fn test_rel_inverse() {
+        let (s, u) = (date(1, 1, 2020), date(1, 1, 2021));
+        let kinds = vec![
+            RelType::WorksAt,
+            RelType::Employs,
+            RelType::ManagerOf,
+            RelType::ReportsTo,
+            RelType::PartnerOf,
+            RelType::ClientOf,
+            RelType::SupplierOf,
+            RelType::MemberOf(s, u),
+            RelType::HasMember(s, u),
+        ];
+        for k in kinds.iter() {
+            let inverse = k.inverse().unwrap();
+            assert_eq!(inverse.inverse(), Some(k.clone()));
+        }
+        assert_eq!(RelType::WorksAt.inverse(), Some(RelType::Employs));
+        assert_eq!(RelType::PartnerOf.inverse(), Some(RelType::PartnerOf));
+        assert_eq!(RelType::RelatedTo.inverse(), None);
+        assert_eq!(RelType::Role("ceo".to_owned(), s, None).inverse(), None);
+        assert_eq!(RelType::ClientOf.to_string(), "client_of");
+    }
+
+    #[test]
+    fn test_quality_history() {
+        let mut e = Entity::from("jane").unwrap();
+        let first = e.quality.clone();
+        assert_eq!(e.quality_history(), vec![first.clone()]);
+        e.set_quality(RelQuality::Tense(date(1, 3, 2021), None));
+        e.set_quality(RelQuality::Friendly(date(1, 7, 2021), None));
+        // the same kind replaces the current quality
+        e.set_quality(RelQuality::Friendly(date(2, 7, 2021), None));
+        assert_eq!(
+            e.quality_history(),
+            vec![
+                first.ended_on(date(1, 3, 2021)),
+                RelQuality::Tense(date(1, 3, 2021), Some(date(1, 7, 2021))),
+                RelQuality::Friendly(date(2, 7, 2021), None),
+            ]
+        );
+        assert_eq!(e.quality.range(), (date(2, 7, 2021), None));
+        assert_eq!(
+            e.quality
+                .same_kind(&RelQuality::Friendly(date(1, 1, 2020), None)),
+            true
+        );
+        assert_eq!(
+            e.quality
+                .same_kind(&RelQuality::Tense(date(2, 7, 2021), None)),
+            false
+        );
+    }
+
+    #[test]
+    fn test_escalation() {
+        let tests = vec![
+            (date(1, 1, 2021), 0, 0, Escalation::None),
+            (date(10, 1, 2021), 0, 0, Escalation::None),
+            (date(1, 1, 2021), 1, 0, Escalation::Overdue),
+            (date(1, 1, 2021), 8, 0, Escalation::Late),
+            (date(1, 1, 2021), 1, 3, Escalation::Late),
+            (date(1, 1, 2021), 15, 0, Escalation::Critical),
+            (date(10, 1, 2021), 0, 5, Escalation::Critical),
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            println!("test_escalation#{}", i);
+            let (nad, days, postponed, exp) = t;
+            let e = Entity::from("bob")
+                .unwrap()
+                .with_next_action(*nad, "whatever".to_string());
+            let at = date(1, 1, 2021) + Duration::days(*days);
+            let overdue = e.overdue_days(&at);
+            assert_eq!(Escalation::from(overdue, *postponed), *exp);
+        }
+        assert_eq!(Escalation::Critical > Escalation::Late, true);
+    }
+
+    #[test]
+    fn test_next_birthday() {
+        let e = Entity::from("bob").unwrap();
+        assert_eq!(e.next_birthday(&utils::date(1, 1, 2021)), None);
+        let e = e.with_birthday(utils::date(10, 5, 1980));
+        let tests = vec![
+            (utils::date(1, 1, 2021), utils::date(10, 5, 2021)),
+            (utils::date(10, 5, 2021), utils::date(10, 5, 2021)),
+            (utils::date(11, 5, 2021), utils::date(10, 5, 2022)),
+        ];
+        for (from, exp) in tests {
+            assert_eq!(e.next_birthday(&from), Some(exp));
+        }
+        // leap day
+        let e = e.with_birthday(utils::date(29, 2, 1988));
+        assert_eq!(
+            e.next_birthday(&utils::date(1, 1, 2021)),
+            Some(utils::date(1, 3, 2021))
+        );
+        assert_eq!(
+            e.next_birthday(&utils::date(1, 1, 2024)),
+            Some(utils::date(29, 2, 2024))
+        );
+    }
+
+    #[test]
+    fn test_important_dates() {
+        let d = |day, m, y| utils::date(day, m, y);
+        let tests = vec![
+            // (date, recurrence, from, next occurrence)
+            (
+                d(10, 5, 2010),
+                Recurrence::Yearly,
+                d(1, 6, 2021),
+                Some(d(10, 5, 2022)),
+            ),
+            (
+                d(10, 5, 2030),
+                Recurrence::Yearly,
+                d(1, 6, 2021),
+                Some(d(10, 5, 2030)),
+            ),
+            (d(10, 5, 2010), Recurrence::Once, d(1, 6, 2021), None),
+            (
+                d(10, 5, 2010),
+                Recurrence::Once,
+                d(1, 6, 2009),
+                Some(d(10, 5, 2010)),
+            ),
+            (
+                d(31, 1, 2021),
+                Recurrence::Monthly,
+                d(1, 2, 2021),
+                Some(d(28, 2, 2021)),
+            ),
+            (
+                d(15, 1, 2021),
+                Recurrence::Monthly,
+                d(16, 12, 2021),
+                Some(d(15, 1, 2022)),
+            ),
+        ];
+        for (date, recurrence, from, expected) in tests {
+            let i = ImportantDate::new("x", date, recurrence);
+            assert_eq!(i.next_occurrence(&from), expected, "{} from {}", i, from);
+        }
+        let wedding = ImportantDate::new("Wedding day", d(20, 6, 2015), Recurrence::Yearly);
+        assert_eq!(wedding.count_at(&d(20, 6, 2021)), 6);
+        // the legacy birthday is part of the dates
+        let mut e = Entity::from("bob").unwrap();
+        e.birthday = Some(d(10, 5, 1980));
+        e.set_date(wedding.clone());
+        assert_eq!(e.important_dates().len(), 2);
+        assert_eq!(e.important_dates()[0].is_birthday(), true);
+        let e = e.with_birthday(d(11, 5, 1980));
+        assert_eq!(e.birthday, None);
+        assert_eq!(e.important_dates().len(), 2);
+        // upcoming
+        let up = e.upcoming_dates(&d(1, 5, 2021), &d(1, 7, 2021));
+        assert_eq!(
+            up.iter().map(|(o, i)| (*o, i.slug())).collect::<Vec<_>>(),
+            vec![
+                (d(11, 5, 2021), "birthday".to_owned()),
+                (d(20, 6, 2021), "wedding-day".to_owned())
+            ]
+        );
+        assert_eq!(e.upcoming_dates(&d(1, 5, 2021), &d(11, 5, 2021)).len(), 0);
+        // remove
+        let mut e = e;
+        assert_eq!(e.remove_date("wedding day"), Some(wedding));
+        assert_eq!(e.remove_date("wedding day"), None);
+        assert_eq!(e.remove_date("Birthday").is_some(), true);
+        assert_eq!(e.important_dates().len(), 0);
+    }
+
+    #[test]
+    fn test_relation_weight() {
+        let a = Entity::from("a").unwrap();
+        let b = Entity::from("b").unwrap();
+        let c = Entity::from("c").unwrap();
+        let mut e = Entity::from("e")
+            .unwrap()
+            .with_relation(&Rel::new(&a))
+            .with_relation(&Rel::new(&b))
+            .with_relation(&Rel::new(&c).with_weight(2));
+        let order = |e: &Entity| {
+            e.sorted_relations()
+                .iter()
+                .map(|r| r.target)
+                .collect::<Vec<Uuid>>()
+        };
+        assert_eq!(order(&e), vec![c.uid, a.uid, b.uid]);
+        e.set_relation_weight(1, 3);
+        assert_eq!(order(&e), vec![b.uid, c.uid, a.uid]);
+        // out of range is ignored
+        e.set_relation_weight(10, 3);
+        assert_eq!(e.relationships.len(), 3);
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = Entity::from("bob").unwrap();
+        assert_eq!(a.diff(&a).len(), 0);
+        let b = a
+            .clone()
+            .with_handle("email", "bob@acme.com")
+            .with_next_action(date(1, 1, 2021), "call".to_string());
+        let d = a.diff(&b);
+        let fields = d.iter().map(|(f, _, _)| &f[..]).collect::<Vec<&str>>();
+        assert_eq!(fields.contains(&"handles"), true);
+        assert_eq!(fields.contains(&"next_action_note"), true);
+        assert_eq!(fields.contains(&"name"), false);
+        let (_, old, new) = d.iter().find(|(f, _, _)| f == "next_action_note").unwrap();
+        assert_eq!(*old, format!("{:?}", a.next_action_note));
+        assert_eq!(new, "\"call\"");
+        // merge
+        let m = a.merge_fields(&b, &["handles".to_owned()]);
+        assert_eq!(m.handles, b.handles);
+        assert_eq!(m.next_action_note, a.next_action_note);
+        assert_eq!(m.diff(&b).len(), d.len() - 1);
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut e = Entity::from("Robert Marley")
+            .unwrap()
+            .with_alias("Bob")
+            .with_alias(" bob ")
+            .with_alias("robert marley")
+            .with_alias("");
+        assert_eq!(e.aliases, vec!["Bob"]);
+        assert_eq!(e.is_named("BOB"), true);
+        assert_eq!(e.is_named("Robert Marley"), true);
+        assert_eq!(e.is_named("Rob"), false);
+        assert_eq!(e.remove_alias("bob"), true);
+        assert_eq!(e.remove_alias("bob"), false);
+        assert_eq!(e.is_named("bob"), false);
+    }
+
+    #[test]
+    fn test_normalize_handle() {
+        let tests = vec![
+            ("email", " Jane@ACME.com ", Some("jane@acme.com")),
+            ("email", "jane.acme.com", None),
+            ("email", "jane@acme", None),
+            ("email", "jane doe@acme.com", None),
+            ("mobile", "+41 79 123 45 67", Some("+41791234567")),
+            ("phone", "0039 (02) 123-456", Some("+3902123456")),
+            ("mobile", "079 123 45 67", None),
+            ("mobile", "+41 79 CALL ME", None),
+            ("mobile", "+1234567890123456", None),
+            ("url", "acme.com", Some("https://acme.com")),
+            ("url", "HTTP://Acme.COM/", Some("http://acme.com")),
+            (
+                "url",
+                "https://acme.com/About?q=1",
+                Some("https://acme.com/About?q=1"),
+            ),
+            ("url", "ftp://acme.com", None),
+            ("url", "localhost", None),
+            ("telegram", "@Jane_Doe", Some("jane_doe")),
+            ("telegram", "@jd", None),
+            ("telegram", "jane-doe", None),
+            ("linkedin", " janedoe ", Some("janedoe")),
+            ("nick", "  ", None),
+        ];
+        for (label, value, expected) in tests {
+            assert_eq!(
+                Handle::normalize(label, value).ok(),
+                expected.map(|v| v.to_owned()),
+                "{}:{}",
+                label,
+                value
+            );
+        }
+        // invalid handles are not added
+        let mut e = Entity::from("jane").unwrap();
+        assert_eq!(
+            e.add_handle("email", "jane"),
+            Err(DataError::InvalidHandle(
+                "email:jane is not an email address".to_owned()
+            ))
+        );
+        assert_eq!(e.add_handle("email", "JANE@acme.com"), Ok(()));
+        assert_eq!(e.add_handle("email", "jane@acme.com "), Ok(()));
+        assert_eq!(e.all_handles().len(), 1);
+        assert_eq!(e.with_handle("telegram", "@j").all_handles().len(), 1);
+    }
+
+    #[test]
+    fn test_address() {
+        let a = Address::from_str("Unter den Linden 1, Berlin, Germany").unwrap();
+        assert_eq!(a, Address::new("Unter den Linden 1", "Berlin", "Germany"));
+        assert_eq!(a.to_string(), "Unter den Linden 1, Berlin, Germany");
+        let a = Address::from_str(" berlin ").unwrap();
+        assert_eq!(a.city, "berlin");
+        assert_eq!(a.to_string(), "berlin");
+        assert_eq!(
+            Address::from_str("Kurfürstendamm 21, 2nd floor, Berlin, DE")
+                .unwrap()
+                .street,
+            "Kurfürstendamm 21, 2nd floor"
+        );
+        assert_eq!(Address::from_str(", Germany").is_err(), true);
+        // the city does not care about the case
+        let e = Entity::from("jane").unwrap().with_address(a);
+        assert_eq!(e.is_in("Berlin"), true);
+        assert_eq!(e.is_in("Bern"), false);
+        assert_eq!(Entity::from("tim").unwrap().is_in("Berlin"), false);
+        // coordinates
+        assert_eq!(Address::default().with_location(91.0, 0.0).is_err(), true);
+        let berlin = Address::new("", "Berlin", "")
+            .with_location(52.52, 13.405)
+            .unwrap();
+        assert_eq!(Address::default().distance_from(52.52, 13.405), None);
+        assert_eq!(berlin.distance_from(52.52, 13.405), Some(0.0));
+        // berlin to potsdam is about 27 km
+        let d = berlin.distance_from(52.3906, 13.0645).unwrap();
+        assert_eq!((d - 27.0).abs() < 1.0, true, "{}", d);
+    }
+
+    #[test]
+    fn test_task() {
+        let jane = Entity::from("jane").unwrap();
+        let tim = Entity::from("tim").unwrap();
+        let mut t =
+            Task::new(&jane, " send the offer ", utils::date(1, 3, 2021)).with_assignee(&tim);
+        assert_eq!(t.entity, jane.uid);
+        assert_eq!(t.assignee, Some(tim.uid));
+        assert_eq!(t.title, "send the offer");
+        assert_eq!(t.to_string(), "[ ] send the offer by Mon, 01.03.21");
+        // overdue only after the due date
+        assert_eq!(t.is_overdue(&utils::date(1, 3, 2021)), false);
+        assert_eq!(t.is_overdue(&utils::date(2, 3, 2021)), true);
+        t.done = true;
+        assert_eq!(t.is_overdue(&utils::date(2, 3, 2021)), false);
+        assert_eq!(t.to_string(), "[x] send the offer by Mon, 01.03.21");
+    }
+
+    #[test]
+    fn test_goal() {
+        let jane = Entity::from("jane").unwrap();
+        let tim = Entity::from("tim").unwrap();
+        let mut g = Goal::new(" Seed Round ", utils::date(1, 3, 2021))
+            .with_entity(&jane)
+            .with_entity(&tim)
+            .with_entity(&jane);
+        assert_eq!(g.slug(), "seed-round");
+        assert_eq!(g.entities, vec![jane.uid, tim.uid]);
+        assert_eq!(g.involves(&tim), true);
+        assert_eq!(g.remove_entity(&tim.uid), true);
+        assert_eq!(g.remove_entity(&tim.uid), false);
+        assert_eq!(g.involves(&tim), false);
+        let evt = Event::log("pitch", &jane, None);
+        g.link_event(&evt);
+        g.link_event(&evt);
+        assert_eq!(g.events, vec![evt.uid]);
+        assert_eq!(g.to_string(), "Seed Round by Mon, 01.03.21 (active)");
+        // overdue only while active
+        assert_eq!(g.is_overdue(&utils::date(2, 3, 2021)), true);
+        g.status = GoalStatus::from_str("done").unwrap();
+        assert_eq!(g.status, GoalStatus::Achieved);
+        assert_eq!(g.is_overdue(&utils::date(2, 3, 2021)), false);
+        assert_eq!(GoalStatus::from_str("maybe").is_err(), true);
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut e = Entity::from("jane")
+            .unwrap()
+            .with_handle("email", "jane@acme.com")
+            .with_handle("mobile", "+41 555 12 34")
+            .with_handle("email", "jane@home.org")
+            .with_handle("email", "jane@acme.com");
+        // the first one is the primary one
+        assert_eq!(e.handles.get("email"), Some(&"jane@acme.com".to_owned()));
+        assert_eq!(e.has_handle("email", "jane@home.org"), true);
+        assert_eq!(e.has_handle("mobile", "jane@home.org"), false);
+        assert_eq!(
+            e.all_handles()
+                .iter()
+                .map(|h| h.to_string())
+                .collect::<Vec<String>>(),
+            vec![
+                "email:jane@acme.com (primary)",
+                "email:jane@home.org",
+                "mobile:+415551234 (primary)"
+            ]
+        );
+        // swap the primary one
+        e.set_primary_handle("email", "jane@home.org").unwrap();
+        assert_eq!(e.handles.get("email"), Some(&"jane@home.org".to_owned()));
+        assert_eq!(
+            e.other_handles,
+            vec![("email".to_owned(), "jane@acme.com".to_owned())]
+        );
+        // removing the primary one promotes the next
+        e.remove_handle("email", "jane@home.org");
+        assert_eq!(e.handles.get("email"), Some(&"jane@acme.com".to_owned()));
+        assert_eq!(e.other_handles.len(), 0);
+        e.remove_handle("mobile", "+415551234");
+        assert_eq!(e.handles.get("mobile"), None);
+        assert_eq!(e.all_handles().len(), 1);
+    }
+
+    #[test]
+    fn test_fields() {
+        let e = Entity::from("acme")
+            .unwrap()
+            .with_field("company size", FieldValue::Number(120.0))
+            .with_field(
+                " website ",
+                FieldValue::parse("url", "https://acme.com").unwrap(),
+            );
+        assert_eq!(
+            e.get_field("company size"),
+            Some(&FieldValue::Number(120.0))
+        );
+        assert_eq!(e.get_field("website").unwrap().kind(), "url");
+        // parse
+        let tests = vec![
+            ("text", "hello", Some(FieldValue::Text("hello".to_owned()))),
+            ("text", " ", None),
+            ("number", "1.5", Some(FieldValue::Number(1.5))),
+            ("number", "lots", None),
+            ("number", "NaN", None),
+            (
+                "date",
+                "01.02.2021",
+                Some(FieldValue::Date(utils::date(1, 2, 2021))),
+            ),
+            ("date", "tomorrow", None),
+            (
+                "url",
+                "http://x.com",
+                Some(FieldValue::Url("http://x.com".to_owned())),
+            ),
+            ("url", "x.com", None),
+            ("color", "red", None),
+        ];
+        for (kind, value, expected) in tests {
+            assert_eq!(FieldValue::parse(kind, value).ok(), expected, "{}", value);
+        }
+        // remove
+        let mut e = e;
+        assert_eq!(e.remove_field("website").is_some(), true);
+        assert_eq!(e.remove_field("website"), None);
+        assert_eq!(e.fields.len(), 1);
+        // serialization
+        let raw = bincode::serialize(&e).unwrap();
+        let d: Entity = bincode::deserialize(&raw).unwrap();
+        assert_eq!(d.diff(&e).len(), 0);
+    }
+
+    #[test]
+    fn test_completeness() {
+        let e = Entity::from("bob").unwrap();
+        let c = e.completeness_score();
+        assert_eq!(c.score(), 0);
+        assert_eq!(c.max(), 15);
+        assert_eq!(c.percent(), 0);
+        assert_eq!(c.missing()[0], "class");
+        let e = e
+            .with_class("person")
+            .with_handle("email", "bob@acme.com")
+            .with_tag(Tag::from("skill", "rust"));
+        let mut c = e.completeness_score();
+        assert_eq!(c.score(), 11);
+        assert_eq!(c.percent(), 73);
+        assert_eq!(c.missing(), vec!["relationships", "description", "updates"]);
+        assert_eq!(
+            c.to_string(),
+            "73% complete, missing relationships, description and updates"
+        );
+        c.fill("relationships");
+        c.fill("description");
+        assert_eq!(c.to_string(), "93% complete, missing updates");
+        c.fill("updates");
+        assert_eq!(c.to_string(), "100% complete");
+    }
+
     #[test]
     fn test_tags() {
         let tests = vec![
@@ -1183,6 +3781,11 @@ mod tests {
                 Tag::System("Admin".to_string()),
                 ("admin", "Admin", "sys:Admin"),
             ),
+            (
+                "skill:Rust / Async IO",
+                Tag::Feature("Rust / Async IO".to_string()),
+                ("rust/async-io", "Rust / Async IO", "feat:Rust / Async IO"),
+            ),
         ];
 
         for (i, t) in tests.iter().enumerate() {
@@ -1196,6 +3799,41 @@ mod tests {
             assert_eq!(tag_exp.to_string_full(), *full);
         }
     }
+
+    #[test]
+    fn test_nested_tags() {
+        let t = Tag::Generic("skill/Rust/async".to_string());
+        assert_eq!(t.path(), vec!["skill", "rust", "async"]);
+        assert_eq!(t.nested_in("skill"), true);
+        assert_eq!(t.nested_in("skill/rust"), true);
+        assert_eq!(t.nested_in("skill/rust/async"), true);
+        assert_eq!(t.nested_in("ski"), false);
+        assert_eq!(t.nested_in("skill/go"), false);
+        assert_eq!(t.is_within(&Tag::Generic("Skill".to_string())), true);
+        assert_eq!(t.is_within(&Tag::Feature("skill".to_string())), false);
+        assert_eq!(t.relabel("x"), Tag::Generic("x".to_string()));
+        // the links are not nested
+        let link = Tag::Link("https://meetvalis.com/docs".to_string());
+        assert_eq!(link.path().len(), 1);
+        assert_eq!(link.nested_in("https"), false);
+        // the tree shares the parents
+        let tags = vec![
+            t.clone(),
+            Tag::Generic("skill/go".to_string()),
+            Tag::Group("friends".to_string()),
+            Tag::Generic("skill/Rust".to_string()),
+        ];
+        assert_eq!(
+            Tag::tree(&tags),
+            vec![
+                (0, "friends".to_string()),
+                (0, "skill".to_string()),
+                (1, "Rust".to_string()),
+                (2, "async".to_string()),
+                (1, "go".to_string()),
+            ]
+        );
+    }
 }
 
 #[test]
@@ -1,3 +1,8 @@
+use super::model::TimeWindow;
+use super::utils;
+use chrono::{Datelike, Duration, NaiveDate};
+use std::str::FromStr;
+
 ///advance in a string search for the last consecutive index  of a search string
 fn last_consecutive_index(txt: &str, from: usize, search: &str) -> usize {
     let mut index = from + 1;
@@ -51,6 +56,140 @@ pub fn find_labels(txt: &str) -> Vec<String> {
         .collect()
 }
 
+/// Parse a text and extract the hashtags, the words starting with #,
+/// without the # and the trailing punctuation
+pub fn find_hashtags(txt: &str) -> Vec<String> {
+    txt.split_whitespace()
+        .filter_map(hashtag)
+        .map(|t| t.to_owned())
+        .collect()
+}
+
+fn hashtag(word: &str) -> Option<&str> {
+    let tag = word
+        .strip_prefix('#')?
+        .trim_end_matches(|c: char| c.is_ascii_punctuation());
+    match tag.starts_with(|c: char| c.is_alphanumeric()) {
+        true => Some(tag),
+        false => None,
+    }
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// Parse a text and find the first date in it, returns the date
+/// with the index and the number of the words it spans.
+///
+/// The dates can be:
+/// - today, tomorrow
+/// - a weekday, optionally after next or on, the first one after today
+/// - next week|month|year
+/// - in N days|weeks|months|years
+/// - a date like 01.02.2021, optionally after on, or a time window like +2w
+pub fn find_date(txt: &str, today: &NaiveDate) -> Option<(NaiveDate, usize, usize)> {
+    let words = txt
+        .split_whitespace()
+        .map(|w| {
+            w.trim_end_matches(|c: char| c == ',' || c == '.' || c == '!' || c == '?')
+                .to_lowercase()
+        })
+        .collect::<Vec<String>>();
+    let window = |amount: &str, unit: &str| {
+        let unit = match unit.trim_end_matches('s') {
+            "day" => "d",
+            "week" => "w",
+            "month" => "m",
+            "year" => "y",
+            _ => return None,
+        };
+        match amount.parse::<u32>() {
+            Ok(n) if n > 0 => TimeWindow::from_str(&format!("{}{}", n, unit)).ok(),
+            _ => None,
+        }
+    };
+    for (i, w) in words.iter().enumerate() {
+        let next = words.get(i + 1).map(|s| s.as_str()).unwrap_or("");
+        let found = match w.as_str() {
+            "today" => Some((*today, 1)),
+            "tomorrow" => Some((*today + Duration::days(1), 1)),
+            "next" | "on" if weekday_after(next, today).is_some() => {
+                weekday_after(next, today).map(|d| (d, 2))
+            }
+            "on" if utils::date_from_str(next).is_some() => {
+                utils::date_from_str(next).map(|d| (d, 2))
+            }
+            "next" => window("1", next).map(|tw| (tw.offset(today), 2)),
+            "in" => window(next, words.get(i + 2).map(|s| s.as_str()).unwrap_or(""))
+                .map(|tw| (tw.offset(today), 3)),
+            d if d.starts_with('+') && d[1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                TimeWindow::from_str(&d[1..])
+                    .ok()
+                    .map(|tw| (tw.offset(today), 1))
+            }
+            d => weekday_after(d, today)
+                .or_else(|| utils::date_from_str(d))
+                .map(|d| (d, 1)),
+        };
+        if let Some((date, len)) = found {
+            return Some((date, i, len));
+        }
+    }
+    None
+}
+
+/// The first day after today falling on a weekday
+fn weekday_after(name: &str, today: &NaiveDate) -> Option<NaiveDate> {
+    let wd = WEEKDAYS.iter().position(|d| *d == name)? as i64;
+    let days = (wd - today.weekday().num_days_from_monday() as i64 + 6) % 7 + 1;
+    Some(*today + Duration::days(days))
+}
+
+/// What a quick add line says, see parse_quickadd
+#[derive(Debug, Default, PartialEq)]
+pub struct QuickAdd {
+    /// the line without the date and the hashtags
+    pub text: String,
+    pub labels: Vec<String>,
+    pub tags: Vec<String>,
+    pub date: Option<NaiveDate>,
+}
+
+/// Parse a line like `Lunch with [[Anna]] next tuesday #friend`, the
+/// labels, the hashtags and the first date are extracted and the
+/// date and the hashtags are removed from the text
+pub fn parse_quickadd(txt: &str, today: &NaiveDate) -> QuickAdd {
+    let found = find_date(txt, today);
+    let text = txt
+        .split_whitespace()
+        .enumerate()
+        .filter(|(i, w)| {
+            hashtag(w).is_none()
+                && match found {
+                    Some((_, at, len)) => *i < at || *i >= at + len,
+                    None => true,
+                }
+        })
+        .map(|(_, w)| w)
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace("[[", "")
+        .replace("]]", "");
+    QuickAdd {
+        text,
+        labels: find_labels(txt),
+        tags: find_hashtags(txt),
+        date: found.map(|(d, _, _)| d),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -92,4 +231,78 @@ mod tests {
             assert_eq!(r, *labels);
         }
     }
+
+    #[test]
+    fn test_find_hashtags() {
+        let tests = vec![
+            (
+                "Lunch with #friends, and #work/acme!",
+                vec!["friends", "work/acme"],
+            ),
+            ("no # tags here, not even #!", vec![]),
+            ("#first and #last", vec!["first", "last"]),
+        ];
+        for (text, tags) in tests {
+            assert_eq!(find_hashtags(text), tags, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_quickadd() {
+        // today is a wednesday
+        let today = utils::date(17, 3, 2021);
+        let tests = vec![
+            (
+                "Lunch with [[Anna]] next Tuesday #friend",
+                "Lunch with Anna",
+                Some(utils::date(23, 3, 2021)),
+            ),
+            (
+                "call [[Bob]] on wednesday",
+                "call Bob",
+                Some(utils::date(24, 3, 2021)),
+            ),
+            (
+                "ping [[Bob]] tomorrow",
+                "ping Bob",
+                Some(utils::date(18, 3, 2021)),
+            ),
+            (
+                "review the offer in 2 weeks #acme",
+                "review the offer",
+                Some(utils::date(31, 3, 2021)),
+            ),
+            (
+                "drinks next month",
+                "drinks",
+                Some(utils::date(17, 4, 2021)),
+            ),
+            (
+                "the demo on 01.04.2021 with [[Tom]]",
+                "the demo with Tom",
+                Some(utils::date(1, 4, 2021)),
+            ),
+            ("follow up +3d", "follow up", Some(utils::date(20, 3, 2021))),
+            ("next steps with [[Tom]]", "next steps with Tom", None),
+            ("in the office", "in the office", None),
+        ];
+        for (line, text, date) in tests {
+            let q = parse_quickadd(line, &today);
+            assert_eq!(q.text, text, "{}", line);
+            assert_eq!(q.date, date, "{}", line);
+        }
+        let q = parse_quickadd(
+            "Lunch with [[Anna]] and [[Tom]] friday #friend #food",
+            &today,
+        );
+        assert_eq!(
+            q,
+            QuickAdd {
+                text: "Lunch with Anna and Tom".to_owned(),
+                labels: vec!["Anna".to_owned(), "Tom".to_owned()],
+                tags: vec!["friend".to_owned(), "food".to_owned()],
+                date: Some(utils::date(19, 3, 2021)),
+            }
+        );
+    }
 }
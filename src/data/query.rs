@@ -0,0 +1,400 @@
+use super::ledger::DataError;
+use super::model::{ActorRole, Entity, Event, EventType, Tag};
+use super::utils;
+use chrono::{Datelike, Duration, NaiveDate};
+
+// Let's use generic errors
+type Result<T> = std::result::Result<T, DataError>;
+
+/// What a query is looking for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Entities,
+    Events,
+}
+
+/// A structured query over the entities and their events
+///
+/// The entity constraints (tags and class) filter the entities, while
+/// the event constraints (dates and kind) require the entity to have
+/// taken part in at least one matching event, recording it does not count
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub target: Target,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    pub kind: Option<String>,
+    pub tags: Vec<String>,
+    pub class: Option<String>,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Query {
+            target: Target::Entities,
+            since: None,
+            until: None,
+            kind: None,
+            tags: Vec::new(),
+            class: None,
+        }
+    }
+}
+
+/// The words recognized as event kinds and the kind they stand for
+const KINDS: &[(&str, &str)] = &[
+    ("met", "meeting"),
+    ("meet", "meeting"),
+    ("meeting", "meeting"),
+    ("meetings", "meeting"),
+    ("call", "call"),
+    ("called", "call"),
+    ("calls", "call"),
+    ("phoned", "call"),
+    ("email", "email"),
+    ("emails", "email"),
+    ("emailed", "email"),
+    ("mailed", "email"),
+    ("note", "note"),
+    ("notes", "note"),
+    ("noted", "note"),
+    ("wrote", "note"),
+    ("postponed", "postponed"),
+    ("review", "review"),
+    ("reviewed", "review"),
+];
+
+/// The words recognized as entity classes and the class they stand for
+const CLASSES: &[(&str, &str)] = &[
+    ("person", "person"),
+    ("people", "person"),
+    ("persons", "person"),
+    ("org", "org"),
+    ("orgs", "org"),
+    ("organization", "org"),
+    ("organizations", "org"),
+    ("project", "project"),
+    ("projects", "project"),
+    ("thing", "thing"),
+    ("things", "thing"),
+];
+
+fn lookup(table: &[(&str, &str)], word: &str) -> Option<String> {
+    table
+        .iter()
+        .find(|(w, _)| *w == word)
+        .map(|(_, v)| v.to_string())
+}
+
+/// Tells if a word has a meaning in the grammar
+fn is_keyword(word: &str) -> bool {
+    let words = [
+        "today",
+        "yesterday",
+        "this",
+        "last",
+        "past",
+        "since",
+        "before",
+        "until",
+        "on",
+        "tagged",
+        "tag",
+    ];
+    words.contains(&word) || lookup(KINDS, word).is_some() || lookup(CLASSES, word).is_some()
+}
+
+/// Returns the first day of the period containing a date
+fn period_start(unit: &str, date: &NaiveDate) -> Option<NaiveDate> {
+    match unit {
+        "week" => Some(*date - Duration::days(date.weekday().num_days_from_monday() as i64)),
+        "month" => Some(utils::date(1, date.month(), date.year())),
+        "year" => Some(utils::date(1, 1, date.year())),
+        _ => None,
+    }
+}
+
+/// Returns the first day of the period after the one starting at a date
+fn period_next(unit: &str, start: &NaiveDate) -> NaiveDate {
+    match unit {
+        "week" => *start + Duration::days(7),
+        "month" if start.month() == 12 => utils::date(1, 1, start.year() + 1),
+        "month" => utils::date(1, start.month() + 1, start.year()),
+        _ => utils::date(1, 1, start.year() + 1),
+    }
+}
+
+/// Returns the number of days in n units, months and years are approximated
+fn days_in(unit: &str, n: i64) -> Option<i64> {
+    match unit.trim_end_matches('s') {
+        "day" => Some(n),
+        "week" => Some(n * 7),
+        "month" => Some(n * 30),
+        "year" => Some(n * 365),
+        _ => None,
+    }
+}
+
+impl Query {
+    /// Parse a question in a constrained natural language
+    ///
+    /// The question starts with who/which (entities) or what/when (events)
+    /// and may contain, in any order:
+    /// - a time range: today, yesterday, this/last week|month|year,
+    ///   last N days|weeks|months, since/before/on dd.mm.yyyy
+    /// - an event kind: met, called, noted, postponed, reviewed
+    /// - tags: tagged x and y, #x
+    /// - a class: people, orgs, projects, things
+    ///
+    /// the other words are ignored, the dates are relative to today
+    pub fn parse(question: &str, today: &NaiveDate) -> Result<Query> {
+        let text = question
+            .to_lowercase()
+            .replace(|c: char| c == '?' || c == '!' || c == ',', " ");
+        let words = text.split_whitespace().collect::<Vec<&str>>();
+        let mut q = Query::default();
+        let mut understood = false;
+        let invalid = |msg: &str| Err(DataError::GenericError(msg.to_owned()));
+        let mut i = 0;
+        while i < words.len() {
+            let w = words[i];
+            let next = words.get(i + 1).copied().unwrap_or("");
+            match w {
+                "who" | "whom" | "which" if i == 0 => q.target = Target::Entities,
+                "what" | "when" if i == 0 => q.target = Target::Events,
+                "today" => {
+                    q.since = Some(*today);
+                    q.until = Some(*today + Duration::days(1));
+                }
+                "yesterday" => {
+                    q.since = Some(*today - Duration::days(1));
+                    q.until = Some(*today);
+                }
+                "this" | "last" | "past" => {
+                    if let Some(start) = period_start(next, today) {
+                        // this week or the week before
+                        let start = match w {
+                            "this" => start,
+                            _ => period_start(next, &start.pred()).unwrap(),
+                        };
+                        q.since = Some(start);
+                        q.until = Some(period_next(next, &start));
+                        i += 1;
+                    } else if let Ok(n) = next.parse::<i64>() {
+                        // last N days
+                        match days_in(words.get(i + 2).copied().unwrap_or(""), n) {
+                            Some(days) => {
+                                q.since = Some(*today - Duration::days(days));
+                                q.until = None;
+                                i += 2;
+                            }
+                            None => return invalid(&format!("unknown period after {} {}", w, n)),
+                        }
+                    } else if w == "this" {
+                        // a plain "this", nothing to do
+                        i += 1;
+                        continue;
+                    } else {
+                        return invalid(&format!("unknown period after {}", w));
+                    }
+                }
+                "since" | "before" | "until" | "on" => match utils::date_from_str(next) {
+                    Some(d) => {
+                        match w {
+                            "since" => q.since = Some(d),
+                            "on" => {
+                                q.since = Some(d);
+                                q.until = Some(d + Duration::days(1));
+                            }
+                            _ => q.until = Some(d),
+                        }
+                        i += 1;
+                    }
+                    // "on" is also a plain preposition
+                    None if w == "on" => {
+                        i += 1;
+                        continue;
+                    }
+                    None => return invalid(&format!("expected a date after {}", w)),
+                },
+                "tagged" | "tag" => {
+                    if next.is_empty() || is_keyword(next) {
+                        return invalid(&format!("expected a tag after {}", w));
+                    }
+                    q.tags.push(next.to_owned());
+                    i += 1;
+                    // more tags joined by and
+                    while words.get(i + 1) == Some(&"and")
+                        && words.get(i + 2).map_or(false, |t| !is_keyword(t))
+                    {
+                        q.tags.push(words[i + 2].to_owned());
+                        i += 2;
+                    }
+                }
+                t if t.len() > 1 && t.starts_with('#') => q.tags.push(t[1..].to_owned()),
+                w => {
+                    if let Some(k) = lookup(KINDS, w) {
+                        q.kind = Some(k);
+                    } else if let Some(c) = lookup(CLASSES, w) {
+                        q.class = Some(c);
+                    } else {
+                        i += 1;
+                        continue;
+                    }
+                }
+            }
+            understood = true;
+            i += 1;
+        }
+        match understood {
+            true => Ok(q),
+            false => invalid("cannot understand the question"),
+        }
+    }
+
+    /// Tells if the query has constraints on the events
+    pub fn has_event_constraints(&self) -> bool {
+        self.target == Target::Events
+            || self.since.is_some()
+            || self.until.is_some()
+            || self.kind.is_some()
+    }
+
+    /// Tells if an entity matches the tags and class of the query,
+    /// the nested tags match their parents, eg. skill/rust matches skill
+    pub fn matches_entity(&self, e: &Entity) -> bool {
+        let class = match &self.class {
+            Some(c) => e.class == *c,
+            None => true,
+        };
+        class
+            && self.tags.iter().all(|t| {
+                let t = Tag::Generic(t.to_owned()).slug();
+                e.tags.values().any(|et| et.nested_in(&t))
+            })
+    }
+
+    /// Tells if an event matches the date range and kind of the query
+    pub fn matches_event(&self, evt: &Event) -> bool {
+        let kind = match (&self.kind, &evt.kind) {
+            (None, _) => true,
+            (Some(k), EventType::Action(_, name, _)) => name == k,
+            (Some(k), EventType::Log(msg)) => msg == k,
+            (Some(k), EventType::Interaction(c, _, _)) => c.to_string() == *k,
+        };
+        kind && evt.is_between(self.since, self.until)
+    }
+
+    /// Tells if an entity took part in an event, not just recorded it
+    pub fn took_part(e: &Entity, evt: &Event) -> bool {
+        evt.actors.iter().any(|a| {
+            a.uid() == e.uid()
+                && a.actor_role() != ActorRole::RecordedBy
+                && a.actor_role() != ActorRole::OnBehalfOf
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // today is a wednesday
+        let today = utils::date(17, 3, 2021);
+        let tests = vec![
+            (
+                "who did I meet last month tagged conference?",
+                Query {
+                    since: Some(utils::date(1, 2, 2021)),
+                    until: Some(utils::date(1, 3, 2021)),
+                    kind: Some("meeting".to_string()),
+                    tags: vec!["conference".to_string()],
+                    ..Query::default()
+                },
+            ),
+            (
+                "what notes did I write this week",
+                Query {
+                    target: Target::Events,
+                    since: Some(utils::date(15, 3, 2021)),
+                    until: Some(utils::date(22, 3, 2021)),
+                    kind: Some("note".to_string()),
+                    ..Query::default()
+                },
+            ),
+            (
+                "who called me in the last 10 days",
+                Query {
+                    since: Some(utils::date(7, 3, 2021)),
+                    kind: Some("call".to_string()),
+                    ..Query::default()
+                },
+            ),
+            (
+                "which people tagged rust and go are #friends",
+                Query {
+                    class: Some("person".to_string()),
+                    tags: vec!["rust".to_string(), "go".to_string(), "friends".to_string()],
+                    ..Query::default()
+                },
+            ),
+            (
+                "who did I meet on 01.02.2021",
+                Query {
+                    since: Some(utils::date(1, 2, 2021)),
+                    until: Some(utils::date(2, 2, 2021)),
+                    kind: Some("meeting".to_string()),
+                    ..Query::default()
+                },
+            ),
+            (
+                "who emailed me yesterday",
+                Query {
+                    since: Some(utils::date(16, 3, 2021)),
+                    until: Some(utils::date(17, 3, 2021)),
+                    kind: Some("email".to_string()),
+                    ..Query::default()
+                },
+            ),
+            (
+                "what happened last year",
+                Query {
+                    target: Target::Events,
+                    since: Some(utils::date(1, 1, 2020)),
+                    until: Some(utils::date(1, 1, 2021)),
+                    ..Query::default()
+                },
+            ),
+        ];
+        for (question, expected) in tests {
+            assert_eq!(
+                Query::parse(question, &today).unwrap(),
+                expected,
+                "{}",
+                question
+            );
+        }
+        // december rolls over
+        let q = Query::parse("who did I meet last month", &utils::date(3, 1, 2021)).unwrap();
+        assert_eq!(q.since, Some(utils::date(1, 12, 2020)));
+        assert_eq!(q.until, Some(utils::date(1, 1, 2021)));
+        // errors
+        for question in [
+            "hello there",
+            "who since yesterday",
+            "who tagged",
+            "what last 3 ages",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Query::parse(question, &today).is_err(),
+                true,
+                "{}",
+                question
+            );
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use super::model::{Entity, RelQuality};
+use chrono::{Duration, NaiveDate};
+
+/// The bars of a sparkline, from the lowest to the highest
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// The placeholder for the points without a value
+const GAP: char = ' ';
+
+/// Returns the score of a relationship quality,
+/// from 1 (hostile) to 5 (friendly)
+pub fn quality_score(q: &RelQuality) -> u8 {
+    match q {
+        RelQuality::Hostile(_, _) => 1,
+        RelQuality::Tense(_, _) => 2,
+        RelQuality::Neutral(_, _) => 3,
+        RelQuality::Formal(_, _) => 4,
+        RelQuality::Friendly(_, _) => 5,
+    }
+}
+
+/// Returns the quality in place at a date, if any
+fn quality_at<'a>(history: &'a [RelQuality], date: &NaiveDate) -> Option<&'a RelQuality> {
+    history
+        .iter()
+        .filter(|q| {
+            let (since, until) = q.range();
+            since <= *date && until.map_or(true, |u| u > *date)
+        })
+        .last()
+}
+
+/// Sample the quality scores of a history at evenly spaced points
+/// over the year ending at a date, the last point is the date itself.
+///
+/// When more qualities overlap the latest in the history wins,
+/// the points before the history starts have no value
+pub fn quality_trend(history: &[RelQuality], until: &NaiveDate, points: usize) -> Vec<Option<u8>> {
+    let step = 365 / points.max(1) as i64;
+    (0..points)
+        .rev()
+        .map(|i| {
+            let date = *until - Duration::days(step * i as i64);
+            quality_at(history, &date).map(quality_score)
+        })
+        .collect()
+}
+
+/// Returns the quality trend of an entity over the last year,
+/// one point per month
+pub fn entity_trend(e: &Entity, until: &NaiveDate) -> Vec<Option<u8>> {
+    quality_trend(&e.quality_history(), until, 12)
+}
+
+/// Render a series of scores from 1 to 5 as a unicode sparkline
+pub fn sparkline(values: &[Option<u8>]) -> String {
+    values
+        .iter()
+        .map(|v| match v {
+            Some(v) => BARS[(((*v).max(1).min(5) - 1) as usize * (BARS.len() - 1)) / 4],
+            None => GAP,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::utils;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(
+            sparkline(&[Some(1), Some(2), Some(3), Some(4), Some(5)]),
+            "▁▂▄▆█"
+        );
+        assert_eq!(sparkline(&[None, Some(3), Some(9)]), " ▄█");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_quality_trend() {
+        let until = utils::date(31, 12, 2021);
+        let history = vec![
+            RelQuality::Tense(utils::date(1, 3, 2021), Some(utils::date(1, 7, 2021))),
+            RelQuality::Friendly(utils::date(1, 7, 2021), None),
+        ];
+        let trend = quality_trend(&history, &until, 12);
+        assert_eq!(trend.len(), 12);
+        // 30 days apart starting from 4.2.2021
+        assert_eq!(trend[0], None);
+        assert_eq!(trend[1], Some(2));
+        assert_eq!(trend[4], Some(2));
+        assert_eq!(trend[5], Some(5));
+        assert_eq!(trend[11], Some(5));
+        assert_eq!(sparkline(&trend), " ▂▂▂▂███████");
+        // a single point is the date itself
+        assert_eq!(quality_trend(&history, &until, 1), vec![Some(5)]);
+    }
+
+    #[test]
+    fn test_entity_trend() {
+        let mut e = Entity::from("jane").unwrap();
+        e.quality = RelQuality::Neutral(utils::date(1, 1, 2021), None);
+        e.set_quality(RelQuality::Hostile(utils::date(1, 9, 2021), None));
+        let trend = entity_trend(&e, &utils::date(31, 12, 2021));
+        assert_eq!(sparkline(&trend), "▄▄▄▄▄▄▄▁▁▁▁▁");
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, TimeZone, Utc};
 use rand::Rng;
 pub use slug::slugify;
 
@@ -29,6 +29,25 @@ pub fn now_local() -> DateTime<FixedOffset> {
     DateTime::from(Local::now())
 }
 
+/// Returns the datetime with the local timezone at the start of a date,
+/// when the midnight does not exist locally (a DST gap) it is taken in UTC
+pub fn datetime_local(date: &NaiveDate) -> DateTime<FixedOffset> {
+    let midnight = date.and_hms(0, 0, 0);
+    match Local.from_local_datetime(&midnight).earliest() {
+        Some(dt) => DateTime::from(dt),
+        None => DateTime::from(Utc.from_utc_datetime(&midnight)),
+    }
+}
+
+/// Generates a random alphanumeric token of the given length
+pub fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
 pub fn random_timewindow(start: usize, limit: usize, unit: Option<char>) -> String {
     let mut rng = rand::thread_rng();
     match unit {
@@ -45,7 +64,12 @@ pub fn random_timewindow(start: usize, limit: usize, unit: Option<char>) -> Stri
 }
 
 pub fn hash(data: &str) -> String {
-    blake3::hash(data.as_bytes()).to_hex().to_lowercase()
+    hash_bytes(data.as_bytes())
+}
+
+/// Returns the hex encoded hash of some binary data
+pub fn hash_bytes(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_lowercase()
 }
 
 /// Builds a date from day/month/year numeric
@@ -0,0 +1,156 @@
+use ::valis::data::{
+    formats::entity_json,
+    ledger::{ChangeEvent, DataStore},
+    model::{Event, EventType},
+    utils,
+};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Receiver;
+
+/// The hook run for every entity added
+pub const POST_ADD: &str = "post-add";
+/// The hook run for every note recorded
+pub const POST_NOTE: &str = "post-note";
+/// The hook run before an export, when it fails there is no export
+pub const PRE_EXPORT: &str = "pre-export";
+
+/// The hooks are the executables in the hooks directory named after
+/// what happened, eg. post-add, to wire VALIS to other tools.
+///
+/// A hook gets a JSON payload on stdin with the hook name, the context
+/// and what the hook is about, the hook and the context are also in
+/// the VALIS_HOOK and VALIS_CONTEXT environment variables.
+///
+/// The hooks run one at a time and VALIS waits for them, the post
+/// hooks run when the command is done
+pub struct Hooks {
+    dir: PathBuf,
+}
+
+impl Hooks {
+    pub fn new(dir: &Path) -> Hooks {
+        Hooks {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Tells if there is a hook with a name
+    pub fn has(&self, name: &str) -> bool {
+        self.dir.join(name).is_file()
+    }
+
+    /// Run a hook with a payload, a missing hook is not an error,
+    /// a hook that cannot start or exits with a failure is
+    pub fn run(&self, name: &str, context: &str, mut payload: Value) -> io::Result<()> {
+        if !self.has(name) {
+            return Ok(());
+        }
+        payload["hook"] = json!(name);
+        payload["context"] = json!(context);
+        let mut child = Command::new(self.dir.join(name))
+            .env("VALIS_HOOK", name)
+            .env("VALIS_CONTEXT", context)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // the hook may exit without reading it
+            let _ = stdin.write_all(payload.to_string().as_bytes());
+        }
+        let status = child.wait()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("the {} hook failed, {}", name, status),
+            )),
+        }
+    }
+
+    /// Run the post hooks for the changes received so far, a hook
+    /// that fails is reported and does not stop the others
+    pub fn run_changes(&self, ds: &DataStore, context: &str, changes: &Receiver<ChangeEvent>) {
+        for c in changes.try_iter() {
+            let (name, payload) = match c {
+                ChangeEvent::EntityAdded(uid) if self.has(POST_ADD) => {
+                    match ds.get_by_uid(&utils::id(&uid)) {
+                        Ok(Some(e)) => (POST_ADD, json!({ "entity": entity_json(&e) })),
+                        _ => continue,
+                    }
+                }
+                ChangeEvent::EventRecorded(uid) if self.has(POST_NOTE) => {
+                    match ds.get_event(&utils::id(&uid)) {
+                        Ok(Some(evt)) if is_note(&evt) => (POST_NOTE, json!({ "event": evt })),
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            if let Err(err) = self.run(name, context, payload) {
+                eprintln!("{}", err);
+            }
+        }
+    }
+}
+
+fn is_note(evt: &Event) -> bool {
+    match &evt.kind {
+        EventType::Action(_, name, _) => name == "note",
+        _ => false,
+    }
+}
+
+// the hooks in the tests are shell scripts
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use ::valis::data::model::Entity;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script(dir: &Path, name: &str, body: &str) {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_hooks() {
+        let d = tempfile::TempDir::new().unwrap();
+        let out = d.path().join("out");
+        let hooks = Hooks::new(d.path());
+        // the missing hooks are skipped
+        assert_eq!(hooks.run(PRE_EXPORT, "acme", json!({})).is_ok(), true);
+        // a failure stops the pre hooks
+        script(d.path(), PRE_EXPORT, "exit 1");
+        assert_eq!(hooks.run(PRE_EXPORT, "acme", json!({})).is_err(), true);
+        // the post hooks get the changes
+        let body = format!(
+            "echo $VALIS_HOOK >> {0}; cat >> {0}; echo >> {0}",
+            out.display()
+        );
+        script(d.path(), POST_ADD, &body);
+        script(d.path(), POST_NOTE, &body);
+        let mut ds = DataStore::open_temporary().unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let changes = ds.subscribe();
+        ds.add(&Entity::from("alice").unwrap().with_sponsor(&bob))
+            .unwrap();
+        ds.record_note(&bob, "met [[alice]]", None).unwrap();
+        hooks.run_changes(&ds, "acme", &changes);
+        let lines = fs::read_to_string(&out).unwrap();
+        let lines = lines.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], POST_ADD);
+        let added: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(added["context"], "acme");
+        assert_eq!(added["entity"]["name"], "alice");
+        assert_eq!(added["entity"]["pass"], Value::Null);
+        assert_eq!(lines[2], POST_NOTE);
+        let noted: Value = serde_json::from_str(lines[3]).unwrap();
+        assert_eq!(noted["event"]["content"], "met [[alice]]");
+    }
+}
@@ -5,4 +5,6 @@
 //!
 //! [`CostOf.Life`]: http://thecostof.life
 
+/// The per diem calculator
+pub mod costof;
 pub mod data;
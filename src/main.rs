@@ -1,19 +1,42 @@
 use ::valis::data::{
+    backup::{self, Retention},
     context::{ContextManager, CtxError},
-    ledger::{DataError, DataStore, EventFilter, ExportFormat},
-    model::{Actor, Entity, Event, TimeWindow},
-    utils,
+    formats,
+    ledger::{
+        parse_utc_offset, AgendaBucket, AgendaFilter, DataError, DataStore, Direction, EventFilter,
+        ExportFormat, ImportMode, ImportPlan, Lifecycle, Resolution, SearchConfig, Settings,
+        SponsorshipNode,
+    },
+    model::{
+        AccessRole, Actor, ActorRole, Attachment, Channel, Entity, Escalation, Event, Goal,
+        GoalStatus, ImportantDate, InteractionDirection, Priority, Tag, Task, TimeWindow,
+    },
+    query::{Query, Target},
+    trend, utils,
 };
+mod hooks;
 mod prompts;
-use prompts::{PolarAnswer::*, UserConfig};
+mod serve;
+mod tui;
+use hooks::Hooks;
+use prompts::{PolarAnswer::*, TriageKey, UserConfig};
+mod watch;
+use watch::Watch;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use directories_next::ProjectDirs;
 use pad::{Alignment, PadStr};
+use serde_json::json;
 
+use std::collections::{HashMap, HashSet};
 use std::error;
 use std::fs;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::NaiveDate;
 use Alignment::*;
@@ -24,6 +47,10 @@ const QUALIFIER: &str = "com";
 const ORGANIZATION: &str = "farcast";
 const APPLICATION: &str = "valis";
 const CFG_USER: &str = "user.toml";
+/// The directory of the hooks, in the config dir
+const HOOKS_DIR: &str = "hooks";
+/// The max number of entries listed for each agenda bucket
+const AGENDA_PAGE_SIZE: usize = 50;
 
 fn main() -> Result<(), Box<dyn error::Error>> {
     //println!("Welcome to CostOf.Life!");
@@ -41,9 +68,1116 @@ fn main() -> Result<(), Box<dyn error::Error>> {
                 .about("Sets a custom config file")
                 .takes_value(true),
         )
-        .subcommand(App::new("export").about("export the database"))
-        .subcommand(App::new("import").about("import the database"))
+        .arg(
+            Arg::new("wait")
+                .long("wait")
+                .value_name("SECONDS")
+                .about("wait for the context to be released when another valis process uses it")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("context")
+                .long("context")
+                .value_name("NAME")
+                .about("use a context for this run only, the configured one stays the default")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("as")
+                .long("as")
+                .value_name("ENTITY")
+                .about("act on behalf of another entity, by name or handle")
+                .global(true)
+                .takes_value(true),
+        )
+        .subcommand(
+            App::new("export")
+                .about("export the database")
+                .arg(Arg::new("path").about("the export file path").index(1))
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .about("the export format, full includes the events, ics exports the recorded events, nquad the entities graph, vcard the contacts, dot and graphml the graph for graphviz and gephi")
+                        .possible_values(&["json", "full", "csv", "ics", "nquad", "vcard", "dot", "graphml"])
+                        .default_value("json")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("key")
+                        .long("sign-key")
+                        .value_name("FILE")
+                        .about("sign the export with the secret in FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("DATE")
+                        .about("only the entities updated since DATE, eg. 01.02.2021, not for the full format")
+                        .conflicts_with("key")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("encrypt")
+                        .long("encrypt")
+                        .about("encrypt the export with a passphrase, import detects and decrypts it"),
+                ),
+        )
+        .subcommand(
+            App::new("import")
+                .about("import an export or a contact list into the current context")
+                .arg(Arg::new("path").about("the export file path").index(1))
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .about("only show what the import would change"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .about("the export format, full restores the events too, csv and vcard merge the entities")
+                        .possible_values(&["json", "full", "csv", "vcard"])
+                        .default_value("json")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("columns")
+                        .long("columns")
+                        .value_name("MAPPING")
+                        .about("the csv columns to read the fields from, eg. name=Full Name,handle.email=E-mail")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("merge")
+                        .long("merge")
+                        .about("merge the import into the current context instead of replacing it"),
+                )
+                .arg(
+                    Arg::new("on-conflict")
+                        .long("on-conflict")
+                        .about("how to resolve the uid and handle conflicts")
+                        .possible_values(&["ask", "keep-local", "take-imported", "skip"])
+                        .default_value("ask")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("key")
+                        .long("sign-key")
+                        .value_name("FILE")
+                        .about("verify the export signature with the secret in FILE")
+                        .takes_value(true),
+                ),
+        )
         .subcommand(App::new("summary").about("prints the agenda summary"))
+        .subcommand(
+            App::new("status")
+                .about("print the agenda counts without asking anything, for prompts and status bars")
+                .arg(
+                    Arg::new("short")
+                        .long("short")
+                        .about("print a single line, eg. \"3 due · 1 overdue · ctx:acme\""),
+                ),
+        )
+        .subcommand(
+            App::new("anniversaries")
+                .about("list the birthdays and the other important dates coming up")
+                .arg(
+                    Arg::new("days")
+                        .long("days")
+                        .value_name("DAYS")
+                        .about("how many days to look ahead")
+                        .default_value("30")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("capture")
+                .about("capture an entity in the inbox to process it later")
+                .arg(
+                    Arg::new("name")
+                        .about("the name of the entity")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .long("message")
+                        .value_name("NOTE")
+                        .about("a note about the entity")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(App::new("inbox").about("process the captured entities"))
+        .subcommand(
+            App::new("tui")
+                .about("browse the agenda, the entities and their events full screen"),
+        )
+        .subcommand(
+            App::new("agenda")
+                .about("print the agenda, by default in the buckets of the context")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("DATE")
+                        .about("the first day, eg. today, +1w or 15.03.2021")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("DATE")
+                        .about("the day the agenda stops at, excluded, eg. +2w")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("class")
+                        .long("class")
+                        .about("show only the entities of a class, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .long("tag")
+                        .about("show only the entities with a tag, eg. client, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                ),
+        )
+        .subcommand(
+            App::new("note")
+                .about("record a note read from stdin, the [[name]] labels become actors")
+                .arg(
+                    Arg::new("about")
+                        .long("about")
+                        .value_name("ENTITY")
+                        .about("the entity the note is about")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .long("message")
+                        .value_name("NOTE")
+                        .about("the note, instead of reading it from stdin")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("quickadd")
+                .about("add in one line, eg. \"Lunch with [[Anna]] next tuesday #friend\"")
+                .arg(
+                    Arg::new("line")
+                        .about("the note, the [[name]] labels get the #tags and the date as next action")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("log")
+                .about("record a call, a meeting or an email with an entity")
+                .arg(
+                    Arg::new("channel")
+                        .about("how the interaction took place")
+                        .possible_values(&["call", "meet", "email"])
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .long("message")
+                        .value_name("NOTE")
+                        .about("what it was about")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("when")
+                        .long("when")
+                        .value_name("DAY")
+                        .about("when it took place, eg. yesterday or a date, today by default")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("minutes")
+                        .long("minutes")
+                        .value_name("MINUTES")
+                        .about("how long it lasted")
+                        .default_value("0")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("incoming")
+                        .long("incoming")
+                        .about("the interaction was started by the entity"),
+                )
+                .arg(
+                    Arg::new("next")
+                        .long("next")
+                        .value_name("DAY")
+                        .about("set the next action to follow up, eg. +2w")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("done")
+                .about("mark the next action of an entity as done")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("postpone")
+                .about("move the next action of an entity later, it counts as a postponement")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("by")
+                        .long("by")
+                        .value_name("WINDOW")
+                        .about("how much from today, eg. 3d or 2w, the reminder setting by default")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("import-events")
+                .about("import an interaction history from a csv or jsonl file")
+                .arg(
+                    Arg::new("path")
+                        .about("the file with the events (date, kind, entity, content)")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .about("the format of the file")
+                        .possible_values(&["csv", "json"])
+                        .default_value("csv")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("search-settings")
+                .about("show or tune the search settings of the current context")
+                .arg(
+                    Arg::new("threshold")
+                        .long("threshold")
+                        .value_name("0..1")
+                        .about("the minimum similarity for a term to match")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name-weight")
+                        .value_name("WEIGHT")
+                        .about("the weight of a match on the name")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .long("tag-weight")
+                        .value_name("WEIGHT")
+                        .about("the weight of a match on the tags")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("handle")
+                        .long("handle-weight")
+                        .value_name("WEIGHT")
+                        .about("the weight of a match on the handles")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("field")
+                        .long("field-weight")
+                        .value_name("WEIGHT")
+                        .about("the weight of a match on the custom fields")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("review")
+                .about("review the entities not reviewed in a while")
+                .arg(
+                    Arg::new("months")
+                        .short('m')
+                        .long("months")
+                        .value_name("N")
+                        .about("review entities not reviewed in the last N months")
+                        .default_value("3")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("whois")
+                .about("look up an entity by one of its handles, exits with 1 if not found")
+                .arg(
+                    Arg::new("handle")
+                        .about("an email, a @nick, a phone number, a web address or prefix:value, eg. github:jane")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("grant")
+                .about("grant a role in the current context to an entity")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("role")
+                        .about("the role to grant, none to revoke it")
+                        .possible_values(&["owner", "admin", "editor", "viewer", "none"])
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            App::new("contexts")
+                .about("manage the contexts")
+                .subcommand(App::new("list").about("list the contexts you can open"))
+                .subcommand(
+                    App::new("create")
+                        .about("create a new context owned by the current user")
+                        .arg(
+                            Arg::new("name")
+                                .about("the context name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("class")
+                                .long("class")
+                                .about("the class of the root entity of the context")
+                                .possible_values(&["org", "private", "general"])
+                                .default_value("general")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::new("protect")
+                                .long("protect")
+                                .about("protect the context with a passphrase, it is asked for"),
+                        ),
+                )
+                .subcommand(
+                    App::new("rename")
+                        .about("rename a context")
+                        .arg(
+                            Arg::new("name")
+                                .about("the context name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("new_name")
+                                .about("the new context name")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    App::new("delete")
+                        .about("delete a context, the data is moved to the archive directory")
+                        .arg(
+                            Arg::new("name")
+                                .about("the context name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("yes")
+                                .short('y')
+                                .long("yes")
+                                .about("do not ask for confirmation"),
+                        ),
+                )
+                .subcommand(
+                    App::new("use")
+                        .about("make a context the default one")
+                        .arg(
+                            Arg::new("name")
+                                .about("the context name")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("clone")
+                        .about("copy a context into a new one, optionally filtered by tag or class")
+                        .arg(
+                            Arg::new("name")
+                                .about("the context name")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("new_name")
+                                .about("the name of the copy")
+                                .required(true)
+                                .index(2),
+                        )
+                        .arg(
+                            Arg::new("tag")
+                                .long("tag")
+                                .about("copy only the entities with a tag")
+                                .takes_value(true)
+                                .multiple_occurrences(true),
+                        )
+                        .arg(
+                            Arg::new("class")
+                                .long("class")
+                                .about("copy only the entities of a class")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    App::new("merge")
+                        .about("merge a context into another one and archive it")
+                        .arg(
+                            Arg::new("name")
+                                .about("the context to merge")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("into")
+                                .long("into")
+                                .value_name("NAME")
+                                .about("the context to merge into, the current one by default")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("users")
+                .about("manage the users of the current context")
+                .subcommand(App::new("list").about("list the users and their roles"))
+                .subcommand(
+                    App::new("add")
+                        .about("make an entity a user, it is created if it does not exist")
+                        .arg(
+                            Arg::new("entity")
+                                .about("the entity name or handle")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("role")
+                                .about("the role of the user")
+                                .possible_values(&["owner", "admin", "editor", "viewer"])
+                                .default_value("viewer")
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    App::new("disable")
+                        .about("stop a user from acting in the current context")
+                        .arg(
+                            Arg::new("entity")
+                                .about("the user name or handle")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("enable")
+                        .about("let a disabled user act again")
+                        .arg(
+                            Arg::new("entity")
+                                .about("the user name or handle")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("archive")
+                .about("archive an entity, it is kept but no longer tracked")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("undo")
+                        .long("undo")
+                        .about("bring back an archived entity"),
+                ),
+        )
+        .subcommand(
+            App::new("stage")
+                .about("show or change the lifecycle state of an entity, eg. lead or client")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("stage")
+                        .about("the state to move the entity to")
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            App::new("near")
+                .about("list the entities in a city or near it")
+                .arg(
+                    Arg::new("city")
+                        .about("the city, eg. Berlin")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("task")
+                .about("list the tasks of an entity or add one")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("title")
+                        .about("the title of the task to add")
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("due")
+                        .long("due")
+                        .value_name("DATE")
+                        .about("the due date of the task, eg. 01.02.2021, defaults to today")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("ENTITY")
+                        .about("the entity in charge of the task")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("done")
+                        .long("done")
+                        .value_name("N")
+                        .about("mark the task number N of the list as done")
+                        .conflicts_with("title")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("goal")
+                .about("list the goals with their progress, or add and change one")
+                .arg(
+                    Arg::new("name")
+                        .about("the name of the goal, eg. \"seed round\"")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("by")
+                        .long("by")
+                        .value_name("DATE")
+                        .about("the target date, required for a new goal, eg. 01.02.2021")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("with")
+                        .long("with")
+                        .value_name("ENTITY")
+                        .about("add an entity to the goal, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("without")
+                        .long("without")
+                        .value_name("ENTITY")
+                        .about("remove an entity from the goal, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("status")
+                        .long("status")
+                        .value_name("STATUS")
+                        .about("set the status: active, achieved or abandoned")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("remove")
+                        .long("remove")
+                        .about("remove the goal")
+                        .requires("name"),
+                ),
+        )
+        .subcommand(
+            App::new("pipeline")
+                .about("show the entities of a class by lifecycle state")
+                .arg(
+                    Arg::new("class")
+                        .about("the class of the entities")
+                        .default_value("org")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("set")
+                        .long("set")
+                        .value_name("LIFECYCLE")
+                        .about("change the lifecycle, eg. \"lead>prospect>client>dormant>client\"")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("reset")
+                        .long("reset")
+                        .about("restore the default lifecycle")
+                        .conflicts_with("set"),
+                ),
+        )
+        .subcommand(
+            App::new("attach")
+                .about("attach a file or a link to an entity")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("file")
+                        .about("the file to attach, or a url to keep as a link")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .about("the name of the link, defaults to the url")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("attachments")
+                .about("list the attachments of an entity and of its events")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("extract")
+                        .long("extract")
+                        .value_name("DIR")
+                        .about("write the attached files in DIR")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("reset-token")
+                .about("issue a one-time token to reset the password of an entity you sponsor")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(App::new("passwd").about("change your password"))
+        .subcommand(
+            App::new("buckets")
+                .about("show or change the time buckets of the agenda")
+                .arg(
+                    Arg::new("buckets")
+                        .about("comma separated label=window, eg. \"Overdue=past,This week=1w,Later=1m\"")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("reset")
+                        .long("reset")
+                        .about("restore the default buckets")
+                        .conflicts_with("buckets"),
+                ),
+        )
+        .subcommand(
+            App::new("settings")
+                .about("show or change the settings of the context")
+                .arg(
+                    Arg::new("timezone")
+                        .long("timezone")
+                        .about("the offset from UTC, eg. +02:00, empty for the system one")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("locale")
+                        .long("locale")
+                        .about("the locale of the dates, eg. en-US")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("week_start")
+                        .long("week-start")
+                        .about("the first day of the week, eg. sun")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("reminder")
+                        .long("reminder")
+                        .about("the default window to set the next action, eg. 2w")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("menu")
+                        .long("menu")
+                        .about("comma separated actions of the menu, empty to show them all")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("reset")
+                        .long("reset")
+                        .about("restore the default settings")
+                        .conflicts_with_all(&["timezone", "locale", "week_start", "reminder", "menu"]),
+                ),
+        )
+        .subcommand(
+            App::new("ask")
+                .about("ask a question, eg. \"who did I meet last month tagged conference?\"")
+                .arg(
+                    Arg::new("question")
+                        .about("the question, the words that are not understood are ignored")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("hint")
+                .about("suggest what to do")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .about("the output format")
+                        .possible_values(&["text", "json"])
+                        .default_value("text")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("handles")
+                .about("list the handles registered with a prefix")
+                .arg(
+                    Arg::new("prefix")
+                        .about("the handle prefix, eg. email")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("watch")
+                .about("keep running and notify the due actions")
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .value_name("SECONDS")
+                        .about("how often to check for due actions")
+                        .default_value("60")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("webhook")
+                        .long("webhook")
+                        .value_name("URL")
+                        .about("an url to POST the due actions to")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("feed")
+                        .long("feed")
+                        .value_name("FILE")
+                        .about("an ics file to keep up to date with the recorded events")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("serve")
+                .about("serve a JSON API over the current context, a login is required")
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .value_name("ADDRESS")
+                        .about("the address to listen to")
+                        .default_value("127.0.0.1:7878")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("insecure")
+                        .long("insecure")
+                        .about("allow to listen on an address reachable from other hosts, the traffic is not encrypted"),
+                ),
+        )
+        .subcommand(
+            App::new("backups")
+                .about("manage the backups of the current context")
+                .subcommand(App::new("now").about("take a backup right away"))
+                .subcommand(App::new("list").about("list the existing backups"))
+                .subcommand(
+                    App::new("prune")
+                        .about("remove the backups not retained by the retention policy")
+                        .arg(
+                            Arg::new("daily")
+                                .long("daily")
+                                .value_name("N")
+                                .about("keep the last backup of the last N days")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::new("weekly")
+                                .long("weekly")
+                                .value_name("N")
+                                .about("keep the last backup of the last N weeks")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::new("monthly")
+                                .long("monthly")
+                                .value_name("N")
+                                .about("keep the last backup of the last N months")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    App::new("schedule")
+                        .about("show or set how often to take a backup")
+                        .arg(
+                            Arg::new("every")
+                                .long("every")
+                                .value_name("DAYS")
+                                .about("take a backup every DAYS days, 0 to disable")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("snapshots")
+                .about("manage the point-in-time snapshots of the current context")
+                .subcommand(
+                    App::new("take")
+                        .about("take a snapshot of the datastore")
+                        .arg(
+                            Arg::new("keep")
+                                .long("keep")
+                                .value_name("N")
+                                .about("keep only the last N snapshots")
+                                .default_value("10")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(App::new("list").about("list the existing snapshots"))
+                .subcommand(
+                    App::new("restore")
+                        .about("roll back the datastore to a snapshot")
+                        .arg(
+                            Arg::new("file")
+                                .about("the snapshot file")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("related")
+                .about("show who an entity is related to, in both directions")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("depth")
+                        .short('d')
+                        .long("depth")
+                        .value_name("N")
+                        .about("also show the entities up to N relationships away")
+                        .default_value("1")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("tags")
+                .about("show the tags in use, or the entities with a tag")
+                .arg(
+                    Arg::new("tag")
+                        .about("the tag as prefix:label, eg. skill:rust, or prefix: for all the tags with the prefix")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("rename")
+                        .long("rename")
+                        .value_name("TAG")
+                        .about("rename the tag and the ones nested within it, eg. skill:rust to skill:lang/rust")
+                        .requires("tag")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("tag")
+                .about("add or remove a tag on all the entities matching a filter")
+                .subcommand(
+                    App::new("add")
+                        .about("add a tag to the entities matching a filter")
+                        .arg(
+                            Arg::new("tag")
+                                .about("the tag as prefix:label, eg. group:family")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("filter")
+                                .long("filter")
+                                .value_name("FILTER")
+                                .about("the entities to change, class:<class>, tag:<tag> or a search, the filters add up")
+                                .required(true)
+                                .takes_value(true)
+                                .multiple_occurrences(true),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .about("show the entities that would change without changing them"),
+                        ),
+                )
+                .subcommand(
+                    App::new("rm")
+                        .about("remove a tag from the entities matching a filter")
+                        .arg(
+                            Arg::new("tag")
+                                .about("the tag as prefix:label, eg. group:family")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::new("filter")
+                                .long("filter")
+                                .value_name("FILTER")
+                                .about("the entities to change, class:<class>, tag:<tag> or a search, the filters add up")
+                                .required(true)
+                                .takes_value(true)
+                                .multiple_occurrences(true),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .about("show the entities that would change without changing them"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("audit")
+                .about("show who changed an entity and when")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("DATE")
+                        .about("only the changes since DATE, eg. 01.02.2021")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("events")
+                .about("print the events day by day, of an entity or of everyone")
+                .arg(
+                    Arg::new("entity")
+                        .about("the entity name or handle")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("WHEN")
+                        .about("only the events since a date or a time window ago, eg. 1m")
+                        .default_value("1m")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("kind")
+                        .long("kind")
+                        .value_name("KIND")
+                        .about("only the events of a kind")
+                        .possible_values(&[
+                            "note",
+                            "call",
+                            "meeting",
+                            "email",
+                            "log",
+                            "postponed",
+                            "review",
+                        ])
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("doctor")
+                .about("check the current context for broken references")
+                .arg(
+                    Arg::new("repair")
+                        .long("repair")
+                        .about("remove the dangling keys that have been found"),
+                ),
+        )
+        .subcommand(App::new("stats").about("show the statistics of the current context"))
+        .subcommand(
+            App::new("tree")
+                .about("show who introduced whom, starting from an entity")
+                .arg(
+                    Arg::new("entity")
+                        .about("the name or handle of the entity, default to yourself")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("depth")
+                        .short('d')
+                        .long("depth")
+                        .value_name("N")
+                        .about("show at most N levels")
+                        .default_value("5")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
     // first, see if there is the config dir
@@ -65,21 +1199,6 @@ fn main() -> Result<(), Box<dyn error::Error>> {
     }
     // Open the context
     let mut ctxm = ContextManager::new(dirs.data_dir())?;
-    //let mut ds = DataStore::open(db_path.as_path())?;
-    // TODO: import
-    // import command first of all
-    // if let Some(("import", c)) = matches.subcommand() {
-    //     let default_path = dirs
-    //         .data_dir()
-    //         .join("export.json")
-    //         .to_string_lossy()
-    //         .to_string();
-    //     let export_path = c.value_of("path").unwrap_or(&default_path);
-    //     ds.import(Path::new(export_path), ExportFormat::Json)?;
-    //     println!("dataset imported from {}", export_path);
-    //     return Ok(());
-    // }
-
     // this is instead the config path
     let cfg_path = dirs.config_dir().join(CFG_USER);
     // if the context manager is empty then setup
@@ -95,7 +1214,8 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         // ask about the root entity
         let root = prompts::root_entity();
         // add the context to the database
-        let context_name = ctxm.new_datastore(&principal, &root)?;
+        let passphrase = ask_passphrase();
+        let context_name = ctxm.new_datastore(&principal, &root, passphrase.as_deref())?;
         // now create a new user config and store it
         let cfg = UserConfig::new(principal.uid(), context_name);
         cfg.save(&cfg_path)?;
@@ -109,8 +1229,46 @@ fn main() -> Result<(), Box<dyn error::Error>> {
             &cfg_path
         ),
     };
-    // open the datastore
-    let mut ds = ctxm.open_datastore(&cfg.ctx)?;
+    // the passwords cached in the config move to the keyring
+    if cfg.migrate_pwd() {
+        cfg.save(&cfg_path)?;
+    }
+    // a context can be picked for a single run
+    let mut ctx = match matches.value_of("context") {
+        Some(name) if !ctxm.list().iter().any(|(n, _)| n == name) => {
+            eprintln!("there is no {} context", name);
+            std::process::exit(1);
+        }
+        Some(name) => name.to_owned(),
+        None => cfg.ctx.clone(),
+    };
+    // open the datastore, waiting for other processes if asked to
+    let wait = match matches.value_of("wait") {
+        Some(w) => w.parse::<u64>()?,
+        None => 0,
+    };
+    // the status is printed as fast as possible, without prompts
+    if let Some(("status", c)) = matches.subcommand() {
+        print_status(&ctxm, &ctx, &cfg.uid, c.is_present("short"));
+        return Ok(());
+    }
+    unlock_context(&mut ctxm, &ctx)?;
+    let mut ds = match ctxm.open_datastore_as(&ctx, &cfg.uid, Duration::from_secs(wait)) {
+        Ok(ds) => ds,
+        Err(CtxError::AccessDenied) => {
+            eprintln!("you are not allowed to open the {} context", ctx);
+            std::process::exit(1);
+        }
+        Err(CtxError::DatasetLocked(pid)) => {
+            let by = match pid {
+                0 => "another valis process".to_owned(),
+                p => format!("another valis process (pid {})", p),
+            };
+            eprintln!("the {} context is in use by {}, retry with --wait", ctx, by);
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     // load the current user
     let principal = match ds.get_by_uid(&cfg.uid)? {
@@ -118,72 +1276,1509 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         None => panic!("your configured user does not match in the database"),
     };
     // current user must have the password but it can be cached
-    let cached_pwd = cfg.pwd.as_ref();
+    let cached_pwd = cfg.cached_pwd();
     // check login
-    match cached_pwd {
+    let authorized = match cached_pwd.as_ref() {
         Some(pwd) => principal.authorized(Some(pwd)),
         None => {
             let pwd = prompts::password("please enter your password");
             principal.authorized(Some(&utils::hash(&pwd)))
         }
-    }
-    .expect("invalid credentials!");
-    // ask for caching
-    if cached_pwd.is_none() {
-        if let Yes = prompts::confirm("would you like to cache your password?", Yes) {
-            cfg.pwd = principal.get_pwd_hash();
-            cfg.save(&cfg_path)?;
-        };
-    };
-
-    // command line
-    match matches.subcommand() {
-        Some(("export", c)) => {
-            let default_path = dirs
-                .data_dir()
-                .join("export.json")
-                .to_string_lossy()
-                .to_string();
-            let export_path = c.value_of("path").unwrap_or(&default_path);
-            ds.export(Path::new(export_path), ExportFormat::Json)?;
-            println!("dataset exported in {}", export_path);
+    };
+    // a forgotten password can be reset with the token issued by the sponsor
+    let principal = match authorized {
+        Ok(()) => principal,
+        Err(_) if ds.reset_pending(&principal) => {
+            let token = prompts::password("a password reset is pending, enter the reset token");
+            let pwd = prompts::new_password("now choose a new password");
+            let principal = match ds.reset_password(&principal, &token, &pwd) {
+                Ok(p) => p,
+                Err(err) => {
+                    eprintln!("the password cannot be reset: {}", err);
+                    ds.close()?;
+                    std::process::exit(1);
+                }
+            };
+            // the cached password is not valid anymore
+            cfg.forget_pwd();
+            cfg.save(&cfg_path)?;
+            principal
+        }
+        Err(_) => panic!("invalid credentials!"),
+    };
+    // ask for caching
+    if cfg.cached_pwd().is_none() {
+        if let Yes = prompts::confirm("would you like to cache your password?", Yes) {
+            if let Some(pwd) = principal.get_pwd_hash() {
+                cfg.cache_pwd(&pwd);
+            }
+            cfg.save(&cfg_path)?;
+        };
+    };
+
+    // the permissions are checked against the current user
+    if let Err(DataError::PermissionDenied) = ds.set_principal(Some(&principal)) {
+        eprintln!("your user has been disabled in the {} context", ctx);
+        ds.close()?;
+        std::process::exit(1);
+    }
+
+    // act on behalf of someone else
+    if let Some(r) = matches.value_of("as") {
+        let found = ds.resolve(r);
+        let delegate = match found.len() {
+            0 => None,
+            1 => Some(found[0].clone()),
+            _ => prompts::select_entity("on behalf of whom?", &found).cloned(),
+        };
+        match delegate {
+            Some(d) => {
+                ds.act_as(Some(&d))?;
+                eprintln!("acting on behalf of {}", d.name());
+            }
+            None => {
+                eprintln!("no entity found for {}", r);
+                ds.close()?;
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // take a backup if it is due
+    if ds.backup_due(&utils::now_local()) {
+        let dir = ctxm.backup_dir(&ctx)?;
+        let p = ds.backup(&dir)?;
+        backup::prune(&dir, &ds.backup_retention())?;
+        eprintln!("backup saved in {}", p.to_string_lossy());
+    }
+
+    // normalize the handles stored before they were checked,
+    // it happens once and only an admin can do it
+    match ds.normalize_handles() {
+        Ok(left) => {
+            for (e, h) in left.iter() {
+                eprintln!("the handle {} of {} cannot be normalized", h, e.name());
+            }
+        }
+        Err(DataError::PermissionDenied) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    // keep the next actions in line with the contact cadences,
+    // the viewers cannot change them
+    match ds.schedule_contacts() {
+        Ok(_) | Err(DataError::PermissionDenied) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    // the changes made by the command are passed to the hooks
+    let hooks = Hooks::new(&dirs.config_dir().join(HOOKS_DIR));
+    let mut changes = ds.subscribe();
+
+    // command line
+    match matches.subcommand() {
+        Some(("export", c)) => {
+            let (format, ext) = match c.value_of("format") {
+                Some("ics") => (ExportFormat::Ics, "ics"),
+                Some("full") => (ExportFormat::FullJson, "json"),
+                Some("csv") => (ExportFormat::Csv, "csv"),
+                Some("nquad") => (ExportFormat::NQuad, "nq"),
+                Some("vcard") => (ExportFormat::VCard, "vcf"),
+                Some("dot") => (ExportFormat::Dot, "dot"),
+                Some("graphml") => (ExportFormat::GraphMl, "graphml"),
+                _ => (ExportFormat::Json, "json"),
+            };
+            let default_path = dirs
+                .data_dir()
+                .join(format!("export.{}", ext))
+                .to_string_lossy()
+                .to_string();
+            let export_path = c.value_of("path").unwrap_or(&default_path);
+            let key = match c.value_of("key") {
+                Some(k) => Some(formats::export_key(&fs::read_to_string(k)?)),
+                None => None,
+            };
+            // ask before exporting, not to leave a cleartext export behind
+            let passphrase = match c.is_present("encrypt") {
+                true => Some(prompts::new_password("passphrase")),
+                false => None,
+            };
+            let payload = json!({ "path": export_path, "format": ext });
+            if let Err(err) = hooks.run(hooks::PRE_EXPORT, &ctx, payload) {
+                eprintln!("{}, nothing exported", err);
+                ds.close()?;
+                std::process::exit(1);
+            }
+            let since = match c.value_of("since") {
+                Some(d) => match utils::date_from_str(d) {
+                    Some(since) => Some(since),
+                    None => {
+                        eprintln!("invalid date {}", d);
+                        ds.close()?;
+                        std::process::exit(1);
+                    }
+                },
+                None => None,
+            };
+            let path = Path::new(export_path);
+            match (passphrase, since) {
+                (Some(p), _) => {
+                    ds.export_encrypted(path, format, key.as_ref(), since, &p)?;
+                    println!("dataset exported and encrypted in {}", export_path);
+                }
+                (None, Some(since)) => {
+                    ds.export_since(path, since, format)?;
+                    println!("dataset exported in {}", export_path);
+                }
+                (None, None) => {
+                    ds.export_signed(path, format, key.as_ref())?;
+                    println!("dataset exported in {}", export_path);
+                }
+            }
+        }
+        Some(("import", c)) => {
+            let default_path = dirs
+                .data_dir()
+                .join("export.json")
+                .to_string_lossy()
+                .to_string();
+            let source = PathBuf::from(c.value_of("path").unwrap_or(&default_path));
+            // an encrypted export is decrypted in memory
+            let decrypted = match formats::is_encrypted(&source)? {
+                true => Some(formats::decrypt_file(
+                    &source,
+                    &prompts::password("passphrase"),
+                )?),
+                false => None,
+            };
+            let import_source: &dyn formats::ImportSource = match &decrypted {
+                Some(d) => d,
+                None => &source,
+            };
+            let key = match c.value_of("key") {
+                Some(k) => Some(formats::export_key(&fs::read_to_string(k)?)),
+                None => None,
+            };
+            let format = || match c.value_of("format") {
+                Some("full") => ExportFormat::FullJson,
+                Some("csv") => ExportFormat::Csv,
+                Some("vcard") => ExportFormat::VCard,
+                _ => ExportFormat::Json,
+            };
+            let merge = c.is_present("merge");
+            // the csv rows and the vcards are always merged
+            if format() == ExportFormat::Csv || format() == ExportFormat::VCard {
+                let name = c.value_of("format").unwrap();
+                let mapping = formats::CsvMapping::parse(c.value_of("columns").unwrap_or(""))?;
+                if c.is_present("dry-run") {
+                    let plan = ds.plan_rows(import_source, format(), &mapping, &principal)?;
+                    print_import_plan(&plan);
+                    println!("dry run, nothing has been imported");
+                } else if let Yes =
+                    prompts::confirm(&format!("merge the {} into the {} context?", name, ctx), No)
+                {
+                    let report = match format() {
+                        ExportFormat::VCard => ds.import_vcard(import_source, &principal)?,
+                        _ => ds.import_csv(import_source, &mapping, &principal)?,
+                    };
+                    for (line, reason) in report.skipped.iter() {
+                        println!("line {}: {}", line, reason);
+                    }
+                    println!(
+                        "{} entities imported from {}",
+                        report.imported,
+                        source.to_string_lossy()
+                    );
+                }
+                ds.close()?;
+                return Ok(());
+            }
+            let mut plan = ds.plan_import(import_source, format(), key.as_ref())?;
+            // a merge keeps the entities that are not in the import
+            if merge {
+                plan.removed.clear();
+            }
+            print_import_plan(&plan);
+            if c.is_present("dry-run") {
+                println!("dry run, nothing has been imported");
+            } else if merge {
+                if let Yes =
+                    prompts::confirm(&format!("merge the import into the {} context?", ctx), No)
+                {
+                    let report =
+                        ds.import_as(import_source, format(), key.as_ref(), ImportMode::Merge)?;
+                    for (line, reason) in report.skipped.iter() {
+                        println!("line {}: {}", line, reason);
+                    }
+                    println!(
+                        "{} entities merged from {}",
+                        report.imported,
+                        source.to_string_lossy()
+                    );
+                }
+            } else if let Yes =
+                prompts::confirm(&format!("replace the {} context with the import?", ctx), No)
+            {
+                let policy = c.value_of("on-conflict").unwrap();
+                let n =
+                    ds.import_with(import_source, format(), key.as_ref(), |cf| match policy {
+                        "keep-local" => Resolution::KeepLocal,
+                        "take-imported" => Resolution::TakeImported,
+                        "skip" => Resolution::Skip,
+                        _ => prompts::resolve_conflict(cf),
+                    })?;
+                println!("{} entities imported from {}", n, source.to_string_lossy());
+            }
+        }
+        Some(("anniversaries", c)) => {
+            show_anniversaries(&ds, c.value_of_t::<i64>("days")?)?;
+        }
+        Some(("summary", _)) => {
+            // only the count is needed
+            let todo = ds
+                .agenda_until(&ds.settings().today(), &AgendaFilter::default(), 1, 0)
+                .total;
+            println!(
+                "There are {} points for the agenda today for the {} context",
+                todo, ctx
+            );
+        }
+        Some(("capture", c)) => {
+            let name = c.value_of("name").unwrap();
+            let note = c.value_of("message").map(|m| m.to_owned());
+            ds.capture(name, &principal, note)?;
+            println!("{} added to the inbox", name);
+        }
+        Some(("inbox", _)) => triage(&mut ds)?,
+        Some(("tui", _)) => tui::run(&mut ds)?,
+        Some(("agenda", c)) => {
+            let today = ds.settings().today();
+            let from = match c.value_of("from") {
+                Some(d) => Some(parse_day(d, &today)?),
+                None => None,
+            };
+            let to = match c.value_of("to") {
+                Some(d) => Some(parse_day(d, &today)?),
+                None => None,
+            };
+            if let Some(to) = to {
+                if to <= from.unwrap_or(today) {
+                    return Err(DataError::GenericError(
+                        "the agenda must end after it starts".to_string(),
+                    )
+                    .into());
+                }
+            }
+            let mut filter = AgendaFilter::default();
+            for class in c.values_of("class").into_iter().flatten() {
+                filter = filter.with_class(class);
+            }
+            for tag in c.values_of("tag").into_iter().flatten() {
+                filter = filter.with_tag_prefix(tag);
+            }
+            show_agenda(&ds, &filter, from, to)?;
+        }
+        Some(("note", c)) => {
+            let about = match c.value_of("about") {
+                Some(reference) => {
+                    let found = ds.resolve(reference);
+                    let target = match found.len() {
+                        0 => None,
+                        1 => Some(found[0].clone()),
+                        _ => prompts::select_entity("which one?", &found).cloned(),
+                    };
+                    match target {
+                        Some(t) => Some(t),
+                        None => {
+                            eprintln!("no entity found for {}", reference);
+                            ds.close()?;
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                None => None,
+            };
+            let text = match c.value_of("message") {
+                Some(m) => m.to_owned(),
+                None => {
+                    let mut buf = String::new();
+                    std::io::stdin().read_to_string(&mut buf)?;
+                    buf
+                }
+            };
+            if text.trim().is_empty() {
+                eprintln!("the note is empty, nothing recorded");
+                ds.close()?;
+                std::process::exit(1);
+            }
+            let report = ds.record_note(&principal, text.trim_end(), about.as_ref())?;
+            for name in report.captured.iter() {
+                println!("{} added to the inbox", name);
+            }
+            for name in report.ambiguous.iter() {
+                println!("{} matches more entities, left out", name);
+            }
+            println!("note recorded");
+        }
+        Some(("quickadd", c)) => {
+            let line = c
+                .values_of("line")
+                .unwrap()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            let report = ds.quick_add(&principal, &line)?;
+            for name in report.note.captured.iter() {
+                println!("{} added to the inbox", name);
+            }
+            for name in report.note.ambiguous.iter() {
+                println!("{} matches more entities, left out", name);
+            }
+            for e in report.entities.iter() {
+                match report.date {
+                    Some(d) => println!(
+                        "{} next action on {}: {}",
+                        e.name(),
+                        utils::human_date(&d),
+                        e.next_action_note
+                    ),
+                    None => println!("{} updated", e.name()),
+                }
+            }
+            println!("note recorded");
+        }
+        Some(("log", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let subject = match find_entity(&ds, reference) {
+                Some(e) => e,
+                None => {
+                    eprintln!("no entity found for {}", reference);
+                    ds.close()?;
+                    std::process::exit(1);
+                }
+            };
+            let today = ds.settings().today();
+            let on = match c.value_of("when") {
+                Some(d) => parse_day(d, &today)?,
+                None => today,
+            };
+            if on > today {
+                eprintln!("cannot log an interaction in the future");
+                ds.close()?;
+                std::process::exit(1);
+            }
+            let channel = Channel::from_str(c.value_of("channel").unwrap())?;
+            let direction = match c.is_present("incoming") {
+                true => InteractionDirection::Incoming,
+                false => InteractionDirection::Outgoing,
+            };
+            let mut evt = Event::interaction(
+                channel,
+                c.value_of_t::<u32>("minutes")?,
+                direction,
+                c.value_of("message").map(String::from),
+                &[
+                    Actor::RecordedBy(principal.uid),
+                    Actor::Subject(subject.uid),
+                ],
+            );
+            if on != today {
+                evt.recorded_at = utils::datetime_local(&on);
+            }
+            ds.record(&evt)?;
+            println!("{} with {} recorded", channel, subject.name());
+            if let Some(d) = c.value_of("next") {
+                // a new note, so it does not count as a postponement
+                let mut e = subject.clone();
+                let note = format!("follow up on the {} of {}", channel, utils::human_date(&on));
+                e.next_action(parse_day(d, &today)?, note);
+                ds.update(&e)?;
+                println!("next action on {}", utils::human_date(&e.next_action_date));
+            }
+        }
+        Some((cmd, c)) if cmd == "done" || cmd == "postpone" => {
+            let reference = c.value_of("entity").unwrap();
+            let target = match find_entity(&ds, reference) {
+                Some(e) => e,
+                None => {
+                    eprintln!("no entity found for {}", reference);
+                    ds.close()?;
+                    std::process::exit(1);
+                }
+            };
+            let r = match cmd {
+                "done" => ds.complete_next_action(&target),
+                _ => match c.value_of("by") {
+                    Some(w) if !w.starts_with(|c: char| c.is_ascii_digit()) => Err(
+                        DataError::GenericError(format!("invalid time window: {}", w)),
+                    ),
+                    Some(w) => ds.postpone(&target, &TimeWindow::from_str(w)?),
+                    None => {
+                        let reminder = ds.settings().reminder;
+                        ds.postpone(&target, &reminder)
+                    }
+                },
+            };
+            match r {
+                Ok(e) => println!(
+                    "the next action for {} is on {}",
+                    e.name(),
+                    utils::human_date(&e.next_action_date)
+                ),
+                Err(err) => print_error(&ds, &target, err)?,
+            }
+        }
+        Some(("import-events", c)) => {
+            let path = c.value_of("path").unwrap();
+            let format = match c.value_of("format") {
+                Some("json") => ExportFormat::Json,
+                _ => ExportFormat::Csv,
+            };
+            let report = ds.import_events(Path::new(path), format, &principal, |rec, found| {
+                let q = format!(
+                    "which one is {} on {}? (esc/q to skip)",
+                    rec.entity, rec.date
+                );
+                prompts::select_entity(&q, found).cloned()
+            })?;
+            for (line, reason) in report.skipped.iter() {
+                println!("line {} skipped: {}", line, reason);
+            }
+            println!("{} events imported from {}", report.imported, path);
+        }
+        Some(("search-settings", c)) => {
+            let cur = ds.search_config();
+            let get = |k: &str, v: f64| -> Result<f64, Box<dyn error::Error>> {
+                match c.value_of(k) {
+                    Some(x) => Ok(x.parse::<f64>()?),
+                    None => Ok(v),
+                }
+            };
+            let sc = SearchConfig {
+                threshold: get("threshold", cur.threshold)?,
+                name_weight: get("name", cur.name_weight)?,
+                tag_weight: get("tag", cur.tag_weight)?,
+                handle_weight: get("handle", cur.handle_weight)?,
+                field_weight: get("field", cur.field_weight)?,
+            };
+            if sc != cur {
+                ds.set_search_config(&sc)?;
+            }
+            println!("search settings for the {} context:", ctx);
+            println!("{:15}{}", "threshold", sc.threshold);
+            println!("{:15}{}", "name weight", sc.name_weight);
+            println!("{:15}{}", "tag weight", sc.tag_weight);
+            println!("{:15}{}", "handle weight", sc.handle_weight);
+            println!("{:15}{}", "field weight", sc.field_weight);
+        }
+        Some(("review", c)) => {
+            let months = c.value_of_t::<i64>("months")?;
+            review(&mut ds, months)?;
+        }
+        Some(("whois", c)) => {
+            let handle = c.value_of("handle").unwrap();
+            let found = ds.whois(handle)?;
+            if found.is_empty() {
+                eprintln!("no entity found for {}", handle);
+                ds.close()?;
+                std::process::exit(1);
+            }
+            for (label, e) in found.iter() {
+                if found.len() > 1 {
+                    println!("as {}:", label);
+                }
+                print_entity(&ds, e, Some(0));
+            }
+        }
+        Some(("grant", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let found = ds.resolve(reference);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            let role = match c.value_of("role").unwrap() {
+                "none" => None,
+                r => Some(r.parse::<AccessRole>()?),
+            };
+            match target {
+                Some(t) => match ds.grant(&t, role) {
+                    Ok(_) => println!("{} is now {}", t.name(), ds.role_of(&t)),
+                    Err(err) => print_error(&ds, &t, err)?,
+                },
+                None => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("contexts", c)) => {
+            // the contexts are managed with the datastore closed
+            ds.close()?;
+            drop(ds);
+            manage_contexts(&mut ctxm, &mut cfg, &cfg_path, &principal, &ctx, c)?;
+            return Ok(());
+        }
+        Some(("users", c)) => match c.subcommand() {
+            Some(("add", a)) => {
+                let reference = a.value_of("entity").unwrap();
+                let role = a.value_of("role").unwrap().parse::<AccessRole>()?;
+                let found = ds.resolve(reference);
+                let user = match found.len() {
+                    0 => Entity::from(reference)?.with_sponsor(&principal),
+                    1 => found[0].clone(),
+                    _ => match prompts::select_entity("which one?", &found) {
+                        Some(e) => e.clone(),
+                        None => Entity::from(reference)?.with_sponsor(&principal),
+                    },
+                };
+                let pwd = prompts::new_password(&format!("choose a password for {}", user.name()));
+                match ds.add_user(&user, role, &pwd) {
+                    Ok(uid) => {
+                        ctxm.grant_access(&ctx, &utils::id(&uid))?;
+                        println!(
+                            "{} is now {} in the {} context, the user id is {}",
+                            user.name(),
+                            role,
+                            ctx,
+                            utils::id(&uid)
+                        );
+                    }
+                    Err(err) => print_error(&ds, &user, err)?,
+                }
+            }
+            Some((cmd, a)) if cmd == "disable" || cmd == "enable" => {
+                let reference = a.value_of("entity").unwrap();
+                let found = ds
+                    .resolve(reference)
+                    .into_iter()
+                    .filter(|e| e.access_role().is_some())
+                    .collect::<Vec<Entity>>();
+                let target = match found.len() {
+                    0 => None,
+                    1 => Some(found[0].clone()),
+                    _ => prompts::select_entity("which one?", &found).cloned(),
+                };
+                match target {
+                    Some(t) => match ds.set_user_disabled(&t, cmd == "disable") {
+                        Ok(_) => println!("{} is now {}d", t.name(), cmd),
+                        Err(err) => print_error(&ds, &t, err)?,
+                    },
+                    None => println!("no user found for {}", reference),
+                }
+            }
+            _ => {
+                for u in ds.users() {
+                    let status = match (u.is_disabled(), ctxm.can_open(&ctx, &u.uid())) {
+                        (true, _) => "disabled",
+                        (false, true) => "",
+                        (false, false) => "no access to the context",
+                    };
+                    println!(
+                        "{:30} {:8} {:34} {}",
+                        u.name(),
+                        ds.role_of(&u),
+                        u.uid(),
+                        status
+                    );
+                }
+            }
+        },
+        Some(("archive", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let undo = c.is_present("undo");
+            let found = ds
+                .resolve_with(reference, true)
+                .into_iter()
+                .filter(|e| e.is_archived() == undo)
+                .collect::<Vec<Entity>>();
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            match target {
+                Some(t) => {
+                    let r = match undo {
+                        true => ds.unarchive(&t),
+                        false => ds.archive(&t),
+                    };
+                    match r {
+                        Ok(_) if undo => println!("{} is back", t.name()),
+                        Ok(_) => println!("{} has been archived", t.name()),
+                        Err(err) => print_error(&ds, &t, err)?,
+                    }
+                }
+                None => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("stage", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let found = ds.resolve(reference);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            match (target, c.value_of("stage")) {
+                (Some(t), Some(stage)) => match ds.move_to_stage(&t, stage) {
+                    Ok(e) => println!("{} is now {}", e.name(), stage),
+                    Err(err) => print_error(&ds, &t, err)?,
+                },
+                (Some(t), None) => {
+                    println!(
+                        "{} is {}",
+                        t.name(),
+                        t.stage.as_deref().unwrap_or("in no state")
+                    );
+                    match ds.lifecycle(&t.class) {
+                        Some(lc) => println!(
+                            "it can move to {}",
+                            lc.next_states(t.stage.as_deref()).join(", ")
+                        ),
+                        None => println!("there is no lifecycle for the class {}", t.class),
+                    }
+                }
+                (None, _) => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("near", c)) => {
+            let city = c.value_of("city").unwrap();
+            let found = ds.near(city);
+            if found.is_empty() {
+                println!("nobody in or near {}", city);
+            }
+            for e in found.iter() {
+                let address = e
+                    .address
+                    .as_ref()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default();
+                println!("{:30} {}", e.name(), address);
+            }
+        }
+        Some(("task", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let found = ds.resolve(reference);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            let due = match c.value_of("due") {
+                Some(d) => utils::date_from_str(d).ok_or_else(|| format!("invalid date {}", d)),
+                None => Ok(utils::today()),
+            };
+            let assignee = match c.value_of("to") {
+                Some(to) => {
+                    let found = ds.resolve(to);
+                    let a = match found.len() {
+                        0 => None,
+                        1 => Some(found[0].clone()),
+                        _ => prompts::select_entity("assign to?", &found).cloned(),
+                    };
+                    a.map(Some)
+                        .ok_or_else(|| format!("no entity found for {}", to))
+                }
+                None => Ok(None),
+            };
+            match (target, c.value_of("title"), c.value_of("done")) {
+                (None, _, _) => println!("no entity found for {}", reference),
+                (Some(t), Some(title), _) => match (due, assignee) {
+                    (Ok(due), Ok(assignee)) => {
+                        let mut task = Task::new(&t, title, due);
+                        if let Some(a) = assignee {
+                            task = task.with_assignee(&a);
+                        }
+                        ds.add_task(&task)?;
+                        println!("{} added to {}", task, t.name());
+                    }
+                    (Err(msg), _) | (_, Err(msg)) => println!("{}", msg),
+                },
+                (Some(t), None, Some(n)) => {
+                    let tasks = ds.tasks(&t);
+                    match n
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| tasks.get(n.max(1) - 1))
+                    {
+                        Some(task) => println!("{}", ds.complete_task(&task.uid())?),
+                        None => println!("there is no task number {}", n),
+                    }
+                }
+                (Some(t), None, None) => {
+                    let tasks = ds.tasks(&t);
+                    if tasks.is_empty() {
+                        println!("{} has no tasks", t.name());
+                    }
+                    let today = utils::today();
+                    for (i, task) in tasks.iter().enumerate() {
+                        let overdue = if task.is_overdue(&today) { " ⏰" } else { "" };
+                        println!("{:>3}. {}{}", i + 1, task_line(&ds, task), overdue);
+                    }
+                }
+            }
+        }
+        Some(("goal", c)) => {
+            let today = utils::today();
+            let name = match c.value_of("name") {
+                Some(n) => n,
+                None => {
+                    let goals = ds.goals();
+                    if goals.is_empty() {
+                        println!("there are no goals, add one with: goal NAME --by DATE");
+                    }
+                    for g in goals.iter() {
+                        print_goal(&ds, g, &today)?;
+                    }
+                    ds.close()?;
+                    return Ok(());
+                }
+            };
+            let (mut goal, existing) = match (ds.goal(name)?, c.value_of("by")) {
+                (Some(g), _) => (g, true),
+                (None, Some(_)) => (Goal::new(name, today), false),
+                (None, None) => {
+                    println!("no goal named {}, add it with a target date (--by)", name);
+                    ds.close()?;
+                    return Ok(());
+                }
+            };
+            if c.is_present("remove") {
+                match existing {
+                    true => {
+                        ds.remove_goal(&goal)?;
+                        println!("goal {} removed", goal.name);
+                    }
+                    false => println!("no goal named {}", name),
+                }
+                ds.close()?;
+                return Ok(());
+            }
+            if let Some(d) = c.value_of("by") {
+                match utils::date_from_str(d) {
+                    Some(target) => goal.target = target,
+                    None => {
+                        eprintln!("invalid date {}", d);
+                        ds.close()?;
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if let Some(status) = c.value_of("status") {
+                goal.status = status.parse::<GoalStatus>()?;
+            }
+            for (arg, add) in [("with", true), ("without", false)].iter() {
+                for reference in c.values_of(arg).into_iter().flatten() {
+                    let found = ds.resolve(reference);
+                    let target = match found.len() {
+                        0 => None,
+                        1 => Some(found[0].clone()),
+                        _ => prompts::select_entity("which one?", &found).cloned(),
+                    };
+                    match (target, add) {
+                        (Some(e), true) => goal.add_entity(&e),
+                        (Some(e), false) => {
+                            goal.remove_entity(&e.uid);
+                        }
+                        (None, _) => println!("no entity found for {}", reference),
+                    }
+                }
+            }
+            match existing {
+                true => ds.update_goal(&goal)?,
+                false => {
+                    ds.add_goal(&goal)?;
+                }
+            }
+            print_goal(&ds, &goal, &today)?;
+            for uid in goal.entities.iter() {
+                if let Some(e) = ds.get_by_uid(&utils::id(uid))? {
+                    println!("  {}", e.name());
+                }
+            }
+        }
+        Some(("pipeline", c)) => {
+            let class = c.value_of("class").unwrap();
+            if c.is_present("reset") {
+                ds.set_lifecycle(class, None)?;
+            } else if let Some(spec) = c.value_of("set") {
+                ds.set_lifecycle(class, Some(&spec.parse::<Lifecycle>()?))?;
+            }
+            match ds.lifecycle(class) {
+                Some(lc) => {
+                    println!("{}", lc);
+                    for state in lc.states.iter() {
+                        let found = ds
+                            .by_state(state)
+                            .into_iter()
+                            .filter(|e| e.class == class)
+                            .collect::<Vec<Entity>>();
+                        println!("{} ({})", state, found.len());
+                        found.iter().for_each(|e| println!("  {}", e.name()));
+                    }
+                }
+                None => println!("there is no lifecycle for the class {}", class),
+            }
+        }
+        Some(("attach", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let found = ds.resolve(reference);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            let file = c.value_of("file").unwrap();
+            match target {
+                Some(mut t) => {
+                    let a = match file.starts_with("http://") || file.starts_with("https://") {
+                        true => Attachment::reference(c.value_of("name").unwrap_or(file), file),
+                        false => ds.store_attachment(Path::new(file))?,
+                    };
+                    println!("{} attached to {}", a, t.name());
+                    t.attach(a);
+                    if let Err(err) = ds.update(&t) {
+                        print_error(&ds, &t, err)?;
+                    }
+                }
+                None => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("attachments", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let found = ds.resolve(reference);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            match target {
+                Some(t) => {
+                    let attachments = ds.attachments_of(&t);
+                    if attachments.is_empty() {
+                        println!("{} has no attachments", t.name());
+                    }
+                    for (evt, a) in attachments.iter() {
+                        match evt {
+                            Some(evt) => {
+                                println!("{} (from {} on {})", a, evt.kind, evt.recorded_at)
+                            }
+                            None => println!("{}", a),
+                        }
+                        if let (Some(dir), false) = (c.value_of("extract"), a.is_reference()) {
+                            let p = ds.extract_attachment(a, Path::new(dir))?;
+                            println!("  saved to {}", p.display());
+                        }
+                    }
+                }
+                None => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("reset-token", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let found = ds.resolve(reference);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            match target {
+                Some(t) => match ds.issue_reset_token(&t) {
+                    Ok(token) => {
+                        println!("reset token for {}: {}", t.name(), token);
+                        println!("it is valid for a week and can be used only once");
+                    }
+                    Err(err) => print_error(&ds, &t, err)?,
+                },
+                None => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("passwd", _)) => {
+            let old = match principal.get_pwd_hash() {
+                Some(_) => prompts::password("enter your current password"),
+                None => String::new(),
+            };
+            let new = prompts::new_password("choose a new password");
+            match ds.change_password(&principal, &old, &new) {
+                Ok(_) => {
+                    // the cached password is not valid anymore
+                    cfg.forget_pwd();
+                    cfg.save(&cfg_path)?;
+                    println!("password changed");
+                }
+                Err(DataError::PermissionDenied) => println!("invalid password"),
+                Err(err) => print_error(&ds, &principal, err)?,
+            }
+        }
+        Some(("buckets", c)) => {
+            if c.is_present("reset") {
+                ds.set_agenda_buckets(&[])?;
+            } else if let Some(spec) = c.value_of("buckets") {
+                ds.set_agenda_buckets(&AgendaBucket::parse_list(spec)?)?;
+            }
+            for b in ds.agenda_buckets() {
+                println!("{}", b);
+            }
+        }
+        Some(("settings", c)) => {
+            let mut settings = match c.is_present("reset") {
+                true => Settings::default(),
+                false => ds.settings(),
+            };
+            if let Some(tz) = c.value_of("timezone") {
+                settings.timezone = match tz.trim() {
+                    "" => None,
+                    tz => Some(parse_utc_offset(tz)?),
+                };
+            }
+            if let Some(locale) = c.value_of("locale") {
+                settings.locale = locale.to_owned();
+            }
+            if let Some(day) = c.value_of("week_start") {
+                settings.week_start = day
+                    .parse()
+                    .map_err(|_| DataError::GenericError(format!("invalid week day: {}", day)))?;
+            }
+            if let Some(tw) = c.value_of("reminder") {
+                if !tw.starts_with(|c: char| c.is_ascii_digit()) {
+                    return Err(DataError::GenericError(format!("invalid window: {}", tw)).into());
+                }
+                settings.reminder = TimeWindow::from_str(tw)?;
+            }
+            if let Some(menu) = c.value_of("menu") {
+                settings.menu = menu
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+                for a in settings.menu.iter() {
+                    if !prompts::MENU.iter().any(|(_, v)| v == a) {
+                        println!("unknown menu action {}, it will not be shown", a);
+                    }
+                }
+            }
+            if settings != ds.settings() {
+                ds.set_settings(&settings)?;
+            }
+            let tz = settings
+                .timezone
+                .map_or("system".to_string(), |tz| tz.to_string());
+            println!("timezone:   {}", tz);
+            println!("locale:     {}", settings.locale);
+            println!("week start: {}", settings.week_start);
+            println!("reminder:   {}", settings.reminder);
+            match settings.menu.is_empty() {
+                true => println!("menu:       all"),
+                false => println!("menu:       {}", settings.menu.join(",")),
+            }
+        }
+        Some(("ask", c)) => {
+            let question = c.values_of("question").unwrap().collect::<Vec<&str>>();
+            let q = Query::parse(&question.join(" "), &utils::today())?;
+            let found = ds.query(&q);
+            match q.target {
+                Target::Entities => found.iter().for_each(|(e, events)| match events.first() {
+                    Some(last) => println!(
+                        "{:40} {:>3} events, last on {}",
+                        e.name(),
+                        events.len(),
+                        utils::human_date(&last.recorded_at.naive_local().date())
+                    ),
+                    None => println!("{}", e.name()),
+                }),
+                Target::Events => {
+                    // an event may involve more than one of the entities
+                    let mut seen = HashSet::new();
+                    let mut events = found
+                        .iter()
+                        .flat_map(|(e, events)| events.iter().map(move |evt| (e, evt)))
+                        .filter(|(_, evt)| seen.insert(evt.uid()))
+                        .collect::<Vec<_>>();
+                    events.sort_by(|(_, a), (_, b)| b.recorded_at.cmp(&a.recorded_at));
+                    for (e, evt) in events {
+                        println!(
+                            "{} {:20} {:30} {}",
+                            evt.recorded_at.format("%Y-%m-%d"),
+                            evt.kind.to_string(),
+                            e.name(),
+                            evt.content
+                                .as_deref()
+                                .unwrap_or("")
+                                .lines()
+                                .next()
+                                .unwrap_or("")
+                        );
+                    }
+                }
+            }
+            if found.is_empty() {
+                println!("nothing found");
+            }
+        }
+        Some(("hint", c)) => match c.value_of("output") {
+            Some("json") => {
+                let hints = ds
+                    .hints(&principal)
+                    .iter()
+                    .map(|h| {
+                        json!({
+                            "rule": h.rule(),
+                            "uid": h.entity.uid(),
+                            "name": h.entity.name(),
+                            "metric": h.metric,
+                            "suggestion": h.suggestion(),
+                            "severity": h.severity().to_string(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", serde_json::Value::Array(hints));
+            }
+            _ => hint(&ds, &principal)?,
+        },
+        Some(("handles", c)) => {
+            let prefix = c.value_of("prefix").unwrap();
+            for (v, e) in ds.all_handles(prefix) {
+                println!("{:40} {}", v, e.name());
+            }
+        }
+        Some(("watch", c)) => {
+            let interval = Duration::from_secs(c.value_of_t::<u64>("interval")?);
+            let running = Arc::new(AtomicBool::new(true));
+            let r = running.clone();
+            ctrlc::set_handler(move || r.store(false, Ordering::SeqCst))?;
+            println!(
+                "watching the {} context every {}s, ctrl-c to stop",
+                ctx,
+                interval.as_secs()
+            );
+            // the watch opens the datastore only while checking
+            ds.close()?;
+            drop(ds);
+            Watch::new(interval)
+                .with_webhook(c.value_of("webhook"))
+                .with_feed(c.value_of("feed"))
+                .with_backups(ctxm.backup_dir(&ctx)?)
+                .run(
+                    || ctxm.open_datastore_as(&ctx, &cfg.uid, Duration::from_secs(0)),
+                    running,
+                )?;
+            return Ok(());
+        }
+        Some(("serve", c)) => {
+            let listen = c.value_of("listen").unwrap();
+            let running = Arc::new(AtomicBool::new(true));
+            let r = running.clone();
+            ctrlc::set_handler(move || r.store(false, Ordering::SeqCst))?;
+            println!(
+                "serving the {} context on http://{}/api, ctrl-c to stop",
+                ctx, listen
+            );
+            if c.is_present("insecure") {
+                eprintln!("warning: the traffic is not encrypted, the tokens and the data can be read on the network");
+            }
+            serve::Server::new(listen)
+                .with_insecure(c.is_present("insecure"))
+                .run(&mut ds, running)?;
+        }
+        Some(("backups", c)) => match c.subcommand() {
+            Some(("now", _)) => {
+                let p = ds.backup(&ctxm.backup_dir(&ctx)?)?;
+                println!("backup saved in {}", p.to_string_lossy());
+            }
+            Some(("list", _)) => {
+                let dir = ctxm.backup_dir(&ctx)?;
+                let (keep, _) = ds.backup_retention().apply(&backup::list(&dir)?);
+                for b in backup::list(&dir)? {
+                    let mark = match keep.contains(&b) {
+                        true => "",
+                        false => "(to be pruned)",
+                    };
+                    println!(
+                        "{}  {:>10} bytes  {}",
+                        b.taken_at.format("%Y-%m-%d %H:%M:%S"),
+                        b.size,
+                        mark
+                    );
+                }
+                println!("backups are stored in {}", dir.to_string_lossy());
+            }
+            Some(("prune", p)) => {
+                let cur = ds.backup_retention();
+                let get = |k: &str, v: usize| -> Result<usize, Box<dyn error::Error>> {
+                    match p.value_of(k) {
+                        Some(x) => Ok(x.parse::<usize>()?),
+                        None => Ok(v),
+                    }
+                };
+                let r = Retention {
+                    daily: get("daily", cur.daily)?,
+                    weekly: get("weekly", cur.weekly)?,
+                    monthly: get("monthly", cur.monthly)?,
+                };
+                if r != cur {
+                    ds.set_backup_retention(&r)?;
+                }
+                let pruned = backup::prune(&ctxm.backup_dir(&ctx)?, &r)?;
+                println!(
+                    "keeping {} daily, {} weekly and {} monthly backups, {} pruned",
+                    r.daily,
+                    r.weekly,
+                    r.monthly,
+                    pruned.len()
+                );
+            }
+            Some(("schedule", s)) => {
+                if let Some(every) = s.value_of("every") {
+                    ds.set_backup_every(every.parse::<i64>()?)?;
+                }
+                match ds.backup_every() {
+                    0 => println!("automatic backups are disabled for the {} context", ctx),
+                    d => println!("the {} context is backed up every {} days", ctx, d),
+                }
+                if let Some(last) = ds.last_backup() {
+                    println!("last backup on {}", last.format("%Y-%m-%d %H:%M"));
+                }
+            }
+            _ => println!("backups are stored in {:?}", ctxm.backup_dir(&ctx)?),
+        },
+        Some(("snapshots", c)) => match c.subcommand() {
+            Some(("take", t)) => {
+                let dir = ctxm.snapshot_dir(&ctx)?;
+                let p = ds.snapshot(&dir)?;
+                let pruned = backup::prune_snapshots(&dir, t.value_of_t::<usize>("keep")?)?;
+                println!(
+                    "snapshot saved in {}, {} pruned",
+                    p.to_string_lossy(),
+                    pruned.len()
+                );
+            }
+            Some(("list", _)) => {
+                let dir = ctxm.snapshot_dir(&ctx)?;
+                for b in backup::snapshots(&dir)? {
+                    println!(
+                        "{}  {:>10} bytes  {}",
+                        b.taken_at.format("%Y-%m-%d %H:%M:%S"),
+                        b.size,
+                        b.path.to_string_lossy()
+                    );
+                }
+                println!("snapshots are stored in {}", dir.to_string_lossy());
+            }
+            Some(("restore", r)) => {
+                let p = Path::new(r.value_of("file").unwrap());
+                if Yes
+                    == prompts::confirm(
+                        &format!(
+                            "the {} context will be rolled back to {}, continue?",
+                            ctx,
+                            p.to_string_lossy()
+                        ),
+                        No,
+                    )
+                {
+                    ds.restore(p)?;
+                    println!("{} restored", p.to_string_lossy());
+                }
+            }
+            _ => println!("snapshots are stored in {:?}", ctxm.snapshot_dir(&ctx)?),
+        },
+        Some(("tree", c)) => {
+            let depth = c.value_of_t::<usize>("depth")?;
+            let root = match c.value_of("entity") {
+                Some(r) => {
+                    let found = ds.resolve(r);
+                    match found.len() {
+                        0 => None,
+                        1 => Some(found[0].clone()),
+                        _ => prompts::select_entity("which one?", &found).cloned(),
+                    }
+                }
+                None => Some(principal.clone()),
+            };
+            match root {
+                Some(root) => print_tree(&ds, &root, depth)?,
+                None => println!("no entity found"),
+            }
+        }
+        Some(("related", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let depth = c.value_of_t::<usize>("depth")?;
+            let found = ds.resolve(reference);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            match target {
+                Some(t) if depth > 1 => {
+                    for (n, e) in ds.neighbors(&t.uid(), depth) {
+                        println!("{:>3} {}", n, e.name());
+                    }
+                }
+                Some(t) => {
+                    for (dir, kind, e) in ds.related(&t, None) {
+                        let arrow = match dir {
+                            Direction::Outgoing => "->",
+                            Direction::Incoming => "<-",
+                        };
+                        println!("{} {:20} {}", arrow, kind.get_label(), e.name());
+                    }
+                }
+                None => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("tags", c)) => match c.value_of("tag") {
+            Some(t) if c.is_present("rename") => {
+                let (from, to) = (t.parse::<Tag>()?, c.value_of_t::<Tag>("rename")?);
+                let n = ds.rename_tag(&from, &to)?;
+                println!("{} renamed to {} on {} entities", t, to.to_string_full(), n);
+            }
+            Some(t) => {
+                let tag = t.parse::<Tag>()?;
+                let found = ds.by_tag(tag.prefix(), &tag.slug());
+                for e in found.iter() {
+                    println!("{}", e.name());
+                }
+                if found.is_empty() {
+                    println!("nothing tagged {}", t);
+                }
+            }
+            None => {
+                for (tag, n) in ds.tags() {
+                    println!("{:40} {:>5}", tag, n);
+                }
+            }
+        },
+        Some(("tag", c)) => match c.subcommand() {
+            Some((cmd, a)) => {
+                let tag = a.value_of_t::<Tag>("tag")?;
+                let filters = a.values_of("filter").into_iter().flatten();
+                let mut changed = Vec::new();
+                for e in filter_entities(&ds, filters)? {
+                    let mut e = e.clone();
+                    match (cmd, e.tags.values().any(|t| t == &tag)) {
+                        ("add", false) => e.add_tag(tag.clone()),
+                        ("rm", true) => e.remove_tag(&tag),
+                        _ => continue,
+                    }
+                    changed.push(e);
+                }
+                for e in changed.iter() {
+                    println!("{}", e.name());
+                }
+                let verb = match cmd {
+                    "add" => "added to",
+                    _ => "removed from",
+                };
+                match a.is_present("dry-run") {
+                    true => println!("{} would be {} {} entities", tag, verb, changed.len()),
+                    false => {
+                        let n = ds.update_many(&changed)?.len();
+                        println!("{} {} {} entities", tag, verb, n);
+                    }
+                }
+            }
+            None => println!("use tag add or tag rm, see --help"),
+        },
+        Some(("audit", c)) => {
+            let reference = c.value_of("entity").unwrap();
+            let since = match c.value_of("since") {
+                Some(d) => match utils::date_from_str(d) {
+                    Some(d) => Some(d),
+                    None => {
+                        eprintln!("invalid date {}", d);
+                        ds.close()?;
+                        std::process::exit(1);
+                    }
+                },
+                None => None,
+            };
+            let found = ds.resolve_with(reference, true);
+            let target = match found.len() {
+                0 => None,
+                1 => Some(found[0].clone()),
+                _ => prompts::select_entity("which one?", &found).cloned(),
+            };
+            match target {
+                Some(t) => {
+                    for a in ds.audit(&t.uid(), since) {
+                        let by = match a.changed_by {
+                            Some(uid) => match ds.get_by_uid(&utils::id(&uid))? {
+                                Some(e) => e.name().to_owned(),
+                                None => utils::id(&uid),
+                            },
+                            None => "system".to_owned(),
+                        };
+                        let by = match a.on_behalf_of {
+                            Some(uid) => match ds.get_by_uid(&utils::id(&uid))? {
+                                Some(e) => format!("{} for {}", by, e.name()),
+                                None => format!("{} for {}", by, utils::id(&uid)),
+                            },
+                            None => by,
+                        };
+                        println!(
+                            "{} {:8} by {}",
+                            a.changed_at.format("%Y-%m-%d %H:%M"),
+                            a.action.to_string(),
+                            by
+                        );
+                        for (field, old, new) in a.changes.iter() {
+                            println!("    {:20} {} -> {}", field, old, new);
+                        }
+                    }
+                }
+                None => println!("no entity found for {}", reference),
+            }
+        }
+        Some(("events", c)) => {
+            let subject = match c.value_of("entity") {
+                Some(reference) => match find_entity(&ds, reference) {
+                    Some(e) => Some(e),
+                    None => {
+                        eprintln!("no entity found for {}", reference);
+                        ds.close()?;
+                        std::process::exit(1);
+                    }
+                },
+                None => None,
+            };
+            let today = ds.settings().today();
+            let since = parse_since(c.value_of("since").unwrap(), &today)?;
+            // the logs are a family of their own, the other kinds
+            // are matched as in the questions
+            let (filter, kind) = match c.value_of("kind") {
+                Some("log") => (EventFilter::Logs, None),
+                Some(k) => (EventFilter::Any, Some(k.to_owned())),
+                None => (EventFilter::Any, None),
+            };
+            let q = Query {
+                kind,
+                ..Query::default()
+            };
+            let events = ds
+                .timeline(subject.as_ref(), filter, Some(since), None)
+                .into_iter()
+                .filter(|evt| q.matches_event(evt))
+                .collect::<Vec<Event>>();
+            let mut names = HashMap::new();
+            let mut day = None;
+            for evt in events.iter() {
+                let at = evt.recorded_at.naive_local();
+                if day != Some(at.date()) {
+                    day = Some(at.date());
+                    println!("{}", utils::human_date(&at.date()));
+                }
+                // the entity the event is about, or the one that recorded it
+                let actor = evt
+                    .actors
+                    .iter()
+                    .find(|a| a.actor_role() != ActorRole::RecordedBy)
+                    .or_else(|| evt.actors.first());
+                let name = match actor {
+                    Some(a) => names
+                        .entry(a.uid())
+                        .or_insert_with(|| match ds.get_by_uid(&a.uid()) {
+                            Ok(Some(e)) => e.name().to_owned(),
+                            _ => a.uid(),
+                        })
+                        .clone(),
+                    None => String::new(),
+                };
+                println!(
+                    "  {} {:20} {:30} {}",
+                    at.format("%H:%M"),
+                    evt.kind.to_string(),
+                    name,
+                    evt.content
+                        .as_deref()
+                        .unwrap_or("")
+                        .lines()
+                        .next()
+                        .unwrap_or("")
+                );
+            }
+            if events.is_empty() {
+                println!("no events since {}", utils::human_date(&since));
+            }
         }
-        Some(("summary", _)) => {
-            let todo = ds.agenda_until(&utils::today(), 0, 0).len();
+        Some(("stats", _)) => {
+            let stats = ds.stats()?;
             println!(
-                "There are {} points for the agenda today for the {} context",
-                todo, cfg.ctx
+                "{} entities, {} events, {} overdue actions",
+                stats.entities, stats.events, stats.overdue
             );
+            let sections = vec![
+                ("classes", &stats.by_class),
+                ("tags", &stats.by_tag_prefix),
+                ("events per month", &stats.events_by_month),
+                ("tree sizes (bytes)", &stats.tree_sizes),
+            ];
+            for (title, counts) in sections {
+                println!("\n{}", title);
+                for (k, n) in counts.iter() {
+                    println!("  {:<20} {:>10}", k, n);
+                }
+            }
+            println!("\n{} bytes on disk", stats.disk_size);
+        }
+        Some(("doctor", c)) => {
+            let report = ds.check(c.is_present("repair"))?;
+            for issue in report.issues.iter() {
+                println!("{}", issue);
+            }
+            match (report.is_healthy(), report.repaired) {
+                (true, _) => println!("{} keys checked, no issues found", report.checked),
+                (false, true) => println!("{} issues repaired", report.issues.len()),
+                (false, false) => println!(
+                    "{} issues found, run with --repair to fix them",
+                    report.issues.len()
+                ),
+            }
         }
         Some((&_, _)) | None => {
             println!("Welcome back {}", principal);
-            println!("you are using the {} context", cfg.ctx);
-            while let Some(action) = prompts::menu() {
+            println!("you are using the {} context", ctx);
+            while let Some(action) = prompts::menu(&ds.settings()) {
                 let out = match action.as_ref() {
                     "note" => add_note(&mut ds, &principal, None),
-                    "agenda" => show_agenda(&ds),
+                    "agenda" => show_agenda(&ds, &AgendaFilter::default(), None, None),
                     "today" => edit_today(&mut ds, &principal),
+                    "rapid" => rapid_triage(&mut ds, &principal),
                     "add" => add_entity(&mut ds, &principal),
                     "update" => update_entity(&mut ds, &principal),
                     "inspect" => inspect(&ds),
+                    "review" => review(&mut ds, 3),
+                    "inbox" => triage(&mut ds),
                     "hint" => hint(&ds, &principal),
                     "change_context" => {
                         // ask for the name
-                        cfg.ctx = prompts::select_context(&ctxm);
+                        ctx = prompts::select_context(&ctxm);
+                        cfg.ctx = ctx.clone();
                         cfg.save(&cfg_path)?;
                         // close current datastore
-                        ds.close();
-                        ds = ctxm.open_datastore(&cfg.ctx)?;
-                        println!("switched to {} context", cfg.ctx);
+                        ds.close()?;
+                        unlock_context(&mut ctxm, &ctx)?;
+                        ds = ctxm.open_datastore(&ctx)?;
+                        changes = ds.subscribe();
+                        println!("switched to {} context", ctx);
                         Ok(())
                     }
+                    "lock" => {
+                        // seal the context and forget the passphrase
+                        ctxm.lock(&ctx);
+                        if ctxm.is_protected(&ctx) {
+                            println!("the {} context is locked", ctx);
+                        }
+                        break;
+                    }
                     "new_context" => {
-                        cfg.ctx = new_context(&mut ctxm, &principal)?;
+                        ctx = new_context(&mut ctxm, &principal)?;
+                        cfg.ctx = ctx.clone();
                         cfg.save(&cfg_path)?;
                         // close current and open the new one
-                        ds.close();
-                        ds = ctxm.open_datastore(&cfg.ctx)?;
-                        println!("switched to {} context", cfg.ctx);
+                        ds.close()?;
+                        ds = ctxm.open_datastore(&ctx)?;
+                        changes = ds.subscribe();
+                        println!("switched to {} context", ctx);
                         Ok(())
                     }
                     _ => Ok(()),
@@ -194,11 +2789,13 @@ fn main() -> Result<(), Box<dyn error::Error>> {
                     }
                     _ => {}
                 }
+                hooks.run_changes(&ds, &ctx, &changes);
             }
         }
     }
 
-    ds.close();
+    hooks.run_changes(&ds, &ctx, &changes);
+    ds.close()?;
     Ok(())
 }
 
@@ -207,7 +2804,184 @@ fn new_context(ctxm: &mut ContextManager, principal: &Entity) -> Result<String,
     // ask about the root entity
     let root = prompts::root_entity();
     // add the context to the database
-    ctxm.new_datastore(&principal, &root)
+    let passphrase = ask_passphrase();
+    ctxm.new_datastore(&principal, &root, passphrase.as_deref())
+}
+
+// Ask whenever a new context shall be protected by a passphrase
+fn ask_passphrase() -> Option<String> {
+    match prompts::confirm("protect the context with a passphrase?", No) {
+        Yes => Some(prompts::new_password("passphrase")),
+        No => None,
+    }
+}
+
+// Ask for the passphrase of a protected context until it is unlocked
+fn unlock_context(ctxm: &mut ContextManager, name: &str) -> Result<(), CtxError> {
+    while ctxm.is_locked(name) {
+        let passphrase = prompts::password(&format!("passphrase for the {} context", name));
+        match ctxm.unlock(name, &passphrase) {
+            Err(CtxError::AccessDenied) => eprintln!("wrong passphrase"),
+            r => r?,
+        }
+    }
+    Ok(())
+}
+
+/// Print the agenda counts of a context, when the context cannot be
+/// opened right away (protected or in use) only its name is printed
+fn print_status(ctxm: &ContextManager, ctx: &str, uid: &str, short: bool) {
+    let counts = match ctxm.is_locked(ctx) {
+        true => None,
+        false => match ctxm.open_datastore_as(ctx, uid, Duration::from_secs(0)) {
+            Ok(mut ds) => {
+                let counts = ds.agenda_counts(&ds.settings().today());
+                if let Err(err) = ds.close() {
+                    eprintln!("{}", err);
+                }
+                Some(counts)
+            }
+            Err(_) => None,
+        },
+    };
+    match (short, counts) {
+        (true, Some((due, overdue))) => {
+            println!("{} due · {} overdue · ctx:{}", due, overdue, ctx)
+        }
+        (true, None) => println!("ctx:{}", ctx),
+        (false, Some((due, overdue))) => {
+            println!("context: {}", ctx);
+            println!("due:     {}", due);
+            println!("overdue: {}", overdue);
+        }
+        (false, None) => println!("context: {} (not available)", ctx),
+    }
+}
+
+/// Run the contexts subcommands, current is the context of this run
+fn manage_contexts(
+    ctxm: &mut ContextManager,
+    cfg: &mut UserConfig,
+    cfg_path: &Path,
+    principal: &Entity,
+    current: &str,
+    c: &ArgMatches,
+) -> Result<(), Box<dyn error::Error>> {
+    // the contexts the user cannot open are not there
+    let target = |name: &str| -> Result<String, CtxError> {
+        match ctxm.can_open(name, &principal.uid()) && ctxm.list().iter().any(|(n, _)| n == name) {
+            true => Ok(name.to_owned()),
+            false => Err(CtxError::DatasetNotFound),
+        }
+    };
+    match c.subcommand() {
+        Some(("create", a)) => {
+            let root =
+                Entity::from(a.value_of("name").unwrap())?.with_class(a.value_of("class").unwrap());
+            let passphrase = match a.is_present("protect") {
+                true => Some(prompts::new_password("passphrase")),
+                false => None,
+            };
+            let name = ctxm.new_datastore(principal, &root, passphrase.as_deref())?;
+            println!("the {} context has been created", name);
+        }
+        Some(("rename", a)) => {
+            let name = target(a.value_of("name").unwrap())?;
+            let new_name = a.value_of("new_name").unwrap();
+            unlock_context(ctxm, &name)?;
+            ctxm.authorize(&name, principal, AccessRole::Owner)?;
+            ctxm.rename(&name, new_name)?;
+            if cfg.ctx == name {
+                cfg.ctx = new_name.to_owned();
+                cfg.save(cfg_path)?;
+            }
+            println!("the {} context is now {}", name, new_name);
+        }
+        Some(("delete", a)) => {
+            let name = target(a.value_of("name").unwrap())?;
+            if cfg.ctx == name {
+                eprintln!(
+                    "the {} context is the default one, use another one first",
+                    name
+                );
+                std::process::exit(1);
+            }
+            unlock_context(ctxm, &name)?;
+            ctxm.authorize(&name, principal, AccessRole::Owner)?;
+            if !a.is_present("yes") {
+                let q = format!("delete the {} context?", name);
+                if let No = prompts::confirm(&q, No) {
+                    return Ok(());
+                }
+            }
+            ctxm.delete(&name)?;
+            println!("the {} context has been deleted", name);
+        }
+        Some(("use", a)) => {
+            cfg.ctx = target(a.value_of("name").unwrap())?;
+            cfg.save(cfg_path)?;
+            println!("the {} context is now the default one", cfg.ctx);
+        }
+        Some(("clone", a)) => {
+            let name = target(a.value_of("name").unwrap())?;
+            let filter = Query {
+                tags: a
+                    .values_of("tag")
+                    .into_iter()
+                    .flatten()
+                    .map(String::from)
+                    .collect(),
+                class: a.value_of("class").map(String::from),
+                ..Query::default()
+            };
+            unlock_context(ctxm, &name)?;
+            // the copy takes the users and the settings along
+            ctxm.authorize(&name, principal, AccessRole::Admin)?;
+            let clone = ctxm.clone(&name, a.value_of("new_name").unwrap(), &filter)?;
+            println!("the {} context has been copied into {}", name, clone);
+        }
+        Some(("merge", a)) => {
+            let source = target(a.value_of("name").unwrap())?;
+            let into = target(a.value_of("into").unwrap_or(current))?;
+            if cfg.ctx == source {
+                eprintln!(
+                    "the {} context is the default one, use another one first",
+                    source
+                );
+                std::process::exit(1);
+            }
+            unlock_context(ctxm, &source)?;
+            unlock_context(ctxm, &into)?;
+            // the source is archived, the target imports the records
+            ctxm.authorize(&source, principal, AccessRole::Owner)?;
+            ctxm.authorize(&into, principal, AccessRole::Admin)?;
+            let report = ctxm.merge(&source, &into)?;
+            println!(
+                "{} records merged into the {} context, {} archived",
+                report.imported, into, source
+            );
+        }
+        _ => {
+            for (name, path) in ctxm.list() {
+                if !ctxm.can_open(&name, &principal.uid()) {
+                    continue;
+                }
+                let mark = match name == current {
+                    true => "*",
+                    false => " ",
+                };
+                let mut status = Vec::new();
+                if name == cfg.ctx {
+                    status.push("default");
+                }
+                if ctxm.is_protected(&name) {
+                    status.push("protected");
+                }
+                println!("{} {:30} {:20} {}", mark, name, status.join(", "), path);
+            }
+        }
+    }
+    Ok(())
 }
 
 fn hint(ds: &DataStore, principal: &Entity) -> Result<(), DataError> {
@@ -217,45 +2991,160 @@ fn hint(ds: &DataStore, principal: &Entity) -> Result<(), DataError> {
     Ok(())
 }
 
-fn show_agenda(ds: &DataStore) -> Result<(), DataError> {
-    let mut p = Printer::new(vec![30, 3, 3, 4, 13, 80]);
-
-    let ranges = vec![
-        ("Past", TimeWindow::UpTo),
-        ("Today", TimeWindow::Day(1)),
-        ("Tomorrow", TimeWindow::Day(1)),
-        ("Within a week", TimeWindow::Day(6)),
-        ("Within 2 weeks", TimeWindow::Day(7)),
-        ("Within 4 weeks", TimeWindow::Day(14)),
-    ];
+/// Print the agenda within a range, the range starts today by default
+/// and is split in the buckets of the context, the past bucket is
+/// shown only when the range starts by today
+fn show_agenda(
+    ds: &DataStore,
+    filter: &AgendaFilter,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<(), DataError> {
+    let settings = ds.settings();
+    let mut p =
+        Printer::new(vec![30, 3, 3, 3, 12, 4, 13, 80]).with_date_format(settings.date_format());
 
-    p.head(vec!["Name", "", "", "#Evt", "Next Date", "Message"]);
+    p.head(vec![
+        "Name",
+        "",
+        "",
+        "",
+        "Trend",
+        "#Evt",
+        "Next Date",
+        "Message",
+    ]);
     p.sep();
 
-    let mut target_date = utils::today();
-    for range in ranges {
-        let (label, r) = range;
-        let (since, until) = r.range(&target_date);
-        let items = ds.agenda(&since, &until, 0, 0);
-        if items.is_empty() {
+    let today = settings.today();
+    let start = from.unwrap_or(today);
+    // split the range in buckets, the last one takes what is left
+    let mut ranges = Vec::new();
+    let mut target_date = start;
+    for bucket in ds.agenda_buckets().iter() {
+        let past = matches!(bucket.window, TimeWindow::UpTo);
+        if past && start > today {
             continue;
         }
-        // print header
-        p.head(vec![&format!(" 📅 {} / {} entries", label, items.len())]);
-        p.sep();
-        // print stuff
-        items.iter().for_each(|e| {
-            p.row(vec![
-                Str(e.name.to_string()),
-                Str(e.state.emoji()),
-                Str(e.quality.emoji()),
-                Cnt(ds.events(e, EventFilter::Actions).len()),
-                Date(e.next_action_date),
-                Str(e.get_next_action_headline()),
-            ])
-        });
+        let (since, until) = bucket.range(&target_date, &settings);
+        let until = to.map_or(until, |to| until.min(to));
+        if !past && since >= until {
+            break;
+        }
+        ranges.push((bucket.label.clone(), past, bucket.show_all, since, until));
         target_date = until;
-        p.sep();
+    }
+    if let Some(to) = to {
+        if target_date < to {
+            ranges.push(("Later".to_owned(), false, false, target_date, to));
+        }
+    }
+    // the critical items are taken from the page of the past bucket
+    let critical_bucket = AgendaBucket::new("Critical", TimeWindow::UpTo).with_show_all();
+    for (label, past, show_all, since, until) in ranges.iter() {
+        let (label, past, show_all) = (&label[..], *past, *show_all);
+        let limit = if show_all { 0 } else { AGENDA_PAGE_SIZE };
+        let page = ds.agenda(since, until, filter, limit, 0);
+        let hidden = page.total - page.items.len();
+        let items = page
+            .items
+            .into_iter()
+            .map(|e| {
+                let level = ds.escalation(&e, &today);
+                (e, level)
+            })
+            .collect::<Vec<(Entity, Escalation)>>();
+        // the past dates have moved on to the next occurrence
+        let dates = match past {
+            true => vec![],
+            false => ds.upcoming_dates(since, until)?,
+        };
+        // the open tasks, the overdue ones stay in the past bucket
+        let tasks = ds.tasks_due(since, until)?;
+        let (dates, tasks) = match filter.is_empty() {
+            true => (dates, tasks),
+            false => (
+                dates
+                    .into_iter()
+                    .filter(|(_, _, e)| filter.matches(e))
+                    .collect(),
+                tasks
+                    .into_iter()
+                    .filter(|(_, e)| filter.matches(e))
+                    .collect(),
+            ),
+        };
+        // the critical items get their own bucket above the past ones
+        let (critical, items): (Vec<_>, Vec<_>) = match past {
+            true => items
+                .into_iter()
+                .partition(|(_, l)| *l == Escalation::Critical),
+            false => (vec![], items),
+        };
+        for (label, show_all, items, dates, tasks) in vec![
+            (
+                &critical_bucket.label[..],
+                critical_bucket.show_all,
+                critical,
+                vec![],
+                vec![],
+            ),
+            (label, show_all, items, dates, tasks),
+        ] {
+            if items.is_empty() && dates.is_empty() && tasks.is_empty() {
+                continue;
+            }
+            // the entries past the page belong to the regular bucket
+            let more = if show_all { 0 } else { hidden };
+            // print header
+            p.head(vec![&format!(
+                " 📅 {} / {} entries",
+                label,
+                items.len() + more + dates.len() + tasks.len()
+            )]);
+            p.sep();
+            // print stuff
+            items.iter().for_each(|(e, level)| {
+                p.row(vec![
+                    Str(agenda_name(e, ds.out_of_touch(e, &today) > 0)),
+                    Str(e.state.emoji()),
+                    Str(e.quality.emoji()),
+                    Str(level.emoji()),
+                    Str(trend::sparkline(&trend::entity_trend(e, &today))),
+                    Cnt(ds.events(e, EventFilter::Actions).len()),
+                    Date(e.next_action_date),
+                    Str(e.get_next_action_headline()),
+                ])
+            });
+            dates.iter().for_each(|(o, d, e)| {
+                p.row(vec![
+                    Str(e.name.to_string()),
+                    Str("🎂".to_string()),
+                    Str(String::new()),
+                    Str(String::new()),
+                    Str(String::new()),
+                    Str(String::new()),
+                    Date(*o),
+                    Str(date_headline(o, d)),
+                ])
+            });
+            tasks.iter().for_each(|(t, e)| {
+                p.row(vec![
+                    Str(e.name.to_string()),
+                    Str("☑️".to_string()),
+                    Str(String::new()),
+                    Str(String::new()),
+                    Str(String::new()),
+                    Str(String::new()),
+                    Date(t.due),
+                    Str(task_headline(ds, t)),
+                ])
+            });
+            if more > 0 {
+                p.head(vec![&format!(" ... and {} more", more)]);
+            }
+            p.sep();
+        }
     }
 
     // separator
@@ -263,40 +3152,406 @@ fn show_agenda(ds: &DataStore) -> Result<(), DataError> {
     Ok(())
 }
 
+/// Returns the entities matching all the filters, a filter is
+/// either class:<class>, tag:<tag> or a search. A tag without
+/// a prefix matches the tags with any prefix, eg. tag:family
+/// matches group:family while tag:group:family does not match
+/// feat:family
+fn filter_entities<'a>(
+    ds: &DataStore,
+    filters: impl Iterator<Item = &'a str>,
+) -> Result<Vec<Entity>, Box<dyn error::Error>> {
+    let mut q = Query::default();
+    let mut tags = Vec::new();
+    let mut patterns = Vec::new();
+    for f in filters {
+        match utils::split_once(f, ':') {
+            Some(("class", c)) => q.class = Some(c.to_owned()),
+            Some(("tag", t)) if t.contains(':') => tags.push(t.parse::<Tag>()?),
+            Some(("tag", t)) => q.tags.push(t.to_owned()),
+            _ => patterns.push(f),
+        }
+    }
+    let mut found = match patterns.first() {
+        Some(p) => ds.search(p),
+        None => ds
+            .iter_entities()
+            .collect::<Result<Vec<Entity>, DataError>>()?,
+    };
+    for p in patterns.iter().skip(1) {
+        let matches = ds
+            .search(p)
+            .into_iter()
+            .map(|e| e.uid)
+            .collect::<HashSet<_>>();
+        found.retain(|e| matches.contains(&e.uid));
+    }
+    found.retain(|e| {
+        q.matches_entity(e)
+            && tags
+                .iter()
+                .all(|t| e.tags.values().any(|et| et.is_within(t)))
+    });
+    Ok(found)
+}
+
+/// Find the entity matching a reference, asking which one
+/// when there are more
+fn find_entity(ds: &DataStore, reference: &str) -> Option<Entity> {
+    let found = ds.resolve(reference);
+    match found.len() {
+        0 => None,
+        1 => Some(found[0].clone()),
+        _ => prompts::select_entity("which one?", &found).cloned(),
+    }
+}
+
+/// Parse a day of the agenda, it can be today, tomorrow, yesterday,
+/// a date or a time window from today, eg. +2w
+fn parse_day(s: &str, today: &NaiveDate) -> Result<NaiveDate, DataError> {
+    match s.trim() {
+        "today" => Ok(*today),
+        "yesterday" => Ok(*today - chrono::Duration::days(1)),
+        "tomorrow" => Ok(*today + chrono::Duration::days(1)),
+        d => match (utils::date_from_str(d), d.trim_start_matches('+')) {
+            (Some(date), _) => Ok(date),
+            (None, w) if w.starts_with(|c: char| c.is_ascii_digit()) => TimeWindow::from_str(w)
+                .map(|tw| tw.offset(today))
+                .map_err(|_| DataError::GenericError(format!("invalid date: {}", d))),
+            _ => Err(DataError::GenericError(format!("invalid date: {}", d))),
+        },
+    }
+}
+
+/// Parse the start of a period, it can be a date or a time
+/// window back from today, eg. 2w
+fn parse_since(s: &str, today: &NaiveDate) -> Result<NaiveDate, DataError> {
+    match utils::date_from_str(s.trim()) {
+        Some(date) => Ok(date),
+        None if s.trim().starts_with(|c: char| c.is_ascii_digit()) => {
+            TimeWindow::from_str(s.trim())
+                .map(|tw| *today - chrono::Duration::days(tw.get_days_since(today)))
+                .map_err(|_| DataError::GenericError(format!("invalid date: {}", s)))
+        }
+        None => Err(DataError::GenericError(format!("invalid date: {}", s))),
+    }
+}
+
+/// Print a goal with its progress
+fn print_goal(ds: &DataStore, g: &Goal, today: &NaiveDate) -> Result<(), DataError> {
+    let p = ds.goal_progress(g)?;
+    let last = p
+        .last
+        .map(|d| format!(", the last on {}", utils::human_date(&d)))
+        .unwrap_or_default();
+    let overdue = if g.is_overdue(today) { " ⏰" } else { "" };
+    println!("{}{}", g, overdue);
+    println!(
+        "  {} events, {} of {} entities touched{}",
+        p.events, p.touched, p.entities, last
+    );
+    Ok(())
+}
+
+/// The name of the entity in charge of a task, if any
+fn task_assignee(ds: &DataStore, t: &Task) -> Option<String> {
+    t.assignee
+        .and_then(|a| ds.get_by_uid(&utils::id(&a)).ok().flatten())
+        .map(|a| a.name)
+}
+
+/// Describe a task in the agenda, with its assignee if any
+fn task_headline(ds: &DataStore, t: &Task) -> String {
+    match task_assignee(ds, t) {
+        Some(a) => format!("{} → {}", t.title, a),
+        None => t.title.clone(),
+    }
+}
+
+/// Describe a task in a list, with its due date and assignee if any
+fn task_line(ds: &DataStore, t: &Task) -> String {
+    match task_assignee(ds, t) {
+        Some(a) => format!("{} → {}", t, a),
+        None => t.to_string(),
+    }
+}
+
+/// The name of an entity in the agenda, with its lifecycle state if
+/// any and a mark if it slipped past its contact cadence
+fn agenda_name(e: &Entity, out_of_touch: bool) -> String {
+    let name = match &e.stage {
+        Some(s) => format!("{} [{}]", e.name, s),
+        None => e.name.to_string(),
+    };
+    match out_of_touch {
+        true => format!("{} ⌛", name),
+        false => name,
+    }
+}
+
+/// Describe an occurrence of an important date, eg. birthday (41)
+fn date_headline(occurrence: &NaiveDate, d: &ImportantDate) -> String {
+    match d.count_at(occurrence) {
+        n if n > 0 => format!("{} ({})", d.label, n),
+        _ => d.label.to_string(),
+    }
+}
+
+/// Print the important dates coming up in the next days
+fn show_anniversaries(ds: &DataStore, days: i64) -> Result<(), DataError> {
+    let dates = ds.upcoming_dates(&utils::today(), &utils::today_plus(days))?;
+    if dates.is_empty() {
+        println!("nothing to celebrate in the next {} days", days);
+    }
+    for (o, d, e) in dates.iter() {
+        println!(
+            "🎂 {:12} {:30} {}",
+            utils::human_date(o),
+            e.name(),
+            date_headline(o, d)
+        );
+    }
+    Ok(())
+}
+
 fn inspect(ds: &DataStore) -> Result<(), DataError> {
     while let Some(e) = prompts::search(ds, "search (or enter for cancel)") {
-        println!("Name {}", e.name());
-        println!("{}", e.description);
+        print_entity(ds, &e, None);
+    }
+    Ok(())
+}
+
+fn print_entity(ds: &DataStore, e: &Entity, max_events: Option<usize>) {
+    println!("Name {}", e.name());
+    if e.priority != Priority::Normal {
+        println!("Priority {} {}", e.priority, e.priority.emoji());
+    }
+    if !e.aliases.is_empty() {
+        println!("Also known as {}", e.aliases.join(", "));
+    }
+    if let Some(s) = &e.stage {
+        println!("Stage {}", s);
+    }
+    if let Some(a) = &e.address {
+        match a.location {
+            Some((lat, lon)) => println!("Address {} ({:.4},{:.4})", a, lat, lon),
+            None => println!("Address {}", a),
+        }
+    }
+    if let Some(every) = &e.contact_every {
+        match ds.contact_due(e) {
+            Some(due) => println!("Get in touch every {}, next by {}", every, due),
+            None => println!("Get in touch every {}", every),
+        }
+    }
+    if let Some(d) = e.archived {
+        println!("Archived on {}", utils::human_date(&d));
+    }
+    println!("{}", e.description);
+    println!("---------------------------------------------");
+    println!("Next action on {}:", utils::human_date(&e.next_action_date));
+    println!("{}", e.next_action_note);
+    println!("---------------------------------------------");
+    println!(
+        "Quality {} {}",
+        e.quality.emoji(),
+        trend::sparkline(&trend::entity_trend(e, &utils::today()))
+    );
+    println!("Profile {}", ds.score(e));
+    let today = utils::today();
+    let s = ds.interaction_stats(
+        e,
+        &(today - chrono::Duration::days(365)),
+        &(today + chrono::Duration::days(1)),
+    );
+    if let Some(last) = s.last {
+        let channels = s
+            .by_channel
+            .iter()
+            .map(|(c, n)| format!("{} {}", n, c))
+            .collect::<Vec<String>>();
+        println!(
+            "Interactions {} in the last year ({}), {:.1} a month, the last on {}",
+            s.total,
+            channels.join(", "),
+            s.per_month,
+            utils::human_date(&last)
+        );
+    }
+    println!("---------------------------------------------");
+    println!("Handles");
+    for h in e.all_handles().iter() {
+        let primary = if h.primary { "primary" } else { "" };
+        println!("{:30}|{:30}|{}", h.label, h.value, primary);
+    }
+    let dates = e.important_dates();
+    if !dates.is_empty() {
         println!("---------------------------------------------");
-        println!("Next action on {}:", utils::human_date(&e.next_action_date));
-        println!("{}", e.next_action_note);
+        println!("Dates");
+        for d in dates.iter() {
+            println!("{}", d);
+        }
+    }
+    let tasks = ds
+        .tasks(e)
+        .into_iter()
+        .filter(|t| !t.done)
+        .collect::<Vec<Task>>();
+    if !tasks.is_empty() {
         println!("---------------------------------------------");
-        println!("Handles");
-        for (k, h) in e.handles.iter() {
-            println!("{:30}|{:30}", k, h);
+        println!("Tasks");
+        for t in tasks.iter() {
+            println!("{}", task_line(ds, t));
         }
+    }
+    if !e.attachments.is_empty() {
         println!("---------------------------------------------");
-        println!("Tags");
-        for t in e.get_tags() {
-            println!("{:30}", t);
+        println!("Attachments");
+        for a in e.attachments.iter() {
+            println!("{}", a);
         }
+    }
+    if !e.fields.is_empty() {
         println!("---------------------------------------------");
-        println!("Events");
-        for evt in ds.events(&e, EventFilter::Actions).iter() {
-            println!("recorded at {} from {}", evt.recorded_at, evt.kind);
-            match &evt.content {
-                Some(c) => println!("{}", c),
-                None => println!("-no content-"),
-            };
-            println!(">>>>>>>>>>>>");
-            println!("Actors");
-            for a in evt.actors.iter() {
-                let (title, uid) = a.role();
-                let ac = ds.get_by_uid(&utils::id(&uid)).unwrap().unwrap();
-                println!("{:10} - {}", title, ac.name());
-            }
+        println!("Fields");
+        for (k, v) in e.fields.iter() {
+            println!("{:30}|{:30}", k, v);
+        }
+    }
+    println!("---------------------------------------------");
+    println!("Tags");
+    for (depth, label) in Tag::tree(&e.tags.values().cloned().collect::<Vec<Tag>>()) {
+        println!("{}{}", "  ".repeat(depth), label);
+    }
+    println!("---------------------------------------------");
+    println!("Relationships");
+    for r in e.sorted_relations() {
+        if let Ok(Some(t)) = ds.get_by_uid(&utils::id(&r.target)) {
+            println!("{:30}|{:20}|{}", t.name(), r.kind.get_label(), r.weight);
+        }
+    }
+    println!("---------------------------------------------");
+    println!("Events");
+    let events = ds.events(e, EventFilter::Actions);
+    for evt in events.iter().take(max_events.unwrap_or(events.len())) {
+        println!("recorded at {} from {}", evt.recorded_at, evt.kind);
+        match &evt.content {
+            Some(c) => println!("{}", c),
+            None => println!("-no content-"),
+        };
+        println!(">>>>>>>>>>>>");
+        println!("Actors");
+        for a in evt.actors.iter() {
+            let (title, uid) = a.role();
+            let ac = ds.get_by_uid(&utils::id(&uid)).unwrap().unwrap();
+            println!("{:10} - {}", title, ac.name());
+        }
+    }
+    println!("---------------------------------------------");
+}
+
+/// Print the sponsorship tree of an entity, each entity
+/// shows the number of entities it has introduced down the branch
+fn print_tree(ds: &DataStore, root: &Entity, max_depth: usize) -> Result<(), DataError> {
+    let mut lines = Vec::new();
+    tree_lines(&ds.sponsorship_tree(&root.uid(), max_depth)?, &mut lines);
+    for l in lines {
+        println!("{}", l);
+    }
+    Ok(())
+}
+
+/// Collect the lines of a branch of the sponsorship tree,
+/// the branches cut by the max depth are marked with dots
+fn tree_lines(node: &SponsorshipNode, lines: &mut Vec<String>) {
+    let more = match node.is_truncated() {
+        true => " ...",
+        false => "",
+    };
+    lines.push(format!(
+        "{}{} ({}){}",
+        "  ".repeat(node.depth),
+        node.entity.name(),
+        node.descendants,
+        more
+    ));
+    for n in node.sponsored.iter() {
+        tree_lines(n, lines);
+    }
+}
+
+/// Print the changes of an import, with the
+/// field differences for a sample of the updates
+fn print_import_plan(plan: &ImportPlan) {
+    println!("{:10}{}", "new", plan.new.len());
+    println!("{:10}{}", "updated", plan.updated.len());
+    println!("{:10}{}", "unchanged", plan.unchanged);
+    println!("{:10}{}", "removed", plan.removed.len());
+    println!("{:10}{}", "conflicts", plan.conflicts.len());
+    for e in plan.new.iter().take(10) {
+        println!("  + {}", e.name());
+    }
+    if plan.new.len() > 10 {
+        println!("  ... and {} more", plan.new.len() - 10);
+    }
+    for (current, imported) in plan.updated.iter().take(5) {
+        println!("---------------------------------------------");
+        println!("{} ({})", imported.name(), imported.uid());
+        for (field, old, new) in current.diff(imported) {
+            println!("  {:25} {} -> {}", field, old, new);
         }
+    }
+    for (e, reason) in plan.conflicts.iter() {
         println!("---------------------------------------------");
+        println!("{} ({}) {}", e.name(), e.uid(), reason);
+    }
+    for (line, reason) in plan.skipped.iter() {
+        println!("line {}: {}", line, reason);
+    }
+}
+
+/// Walk through the entities that have not been reviewed
+/// in the last months, oldest first
+fn review(ds: &mut DataStore, months: i64) -> Result<(), DataError> {
+    let queue = ds.review_queue(&utils::today_plus(-30 * months));
+    if queue.is_empty() {
+        println!("nothing to review, well done!");
+        return Ok(());
+    }
+    let total = queue.len();
+    for (i, e) in queue.iter().enumerate() {
+        println!("reviewing {} of {}", i + 1, total);
+        print_entity(ds, e, Some(5));
+        let mut target = e.clone();
+        prompts::review_entity(&ds.settings(), &mut target);
+        ds.update(&target)?;
+        ds.mark_reviewed(&target)?;
+        if i + 1 < total && No == prompts::confirm("continue with the next one?", Yes) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Process the entities captured in the inbox
+fn triage(ds: &mut DataStore) -> Result<(), DataError> {
+    let inbox = ds.inbox();
+    if inbox.is_empty() {
+        println!("the inbox is empty");
+        return Ok(());
+    }
+    let total = inbox.len();
+    for (i, e) in inbox.iter().enumerate() {
+        println!("inbox item {} of {}", i + 1, total);
+        print_entity(ds, e, Some(3));
+        if Yes == prompts::confirm("process it now?", Yes) {
+            let mut target = e.clone();
+            prompts::triage_entity(ds, &mut target);
+            ds.triaged(&target)?;
+        }
+        if i + 1 < total && No == prompts::confirm("continue with the next one?", Yes) {
+            break;
+        }
     }
     Ok(())
 }
@@ -304,11 +3559,44 @@ fn inspect(ds: &DataStore) -> Result<(), DataError> {
 fn update_entity(ds: &mut DataStore, _principal: &Entity) -> Result<(), DataError> {
     while let Some(e) = prompts::search(ds, "search what you want to update") {
         let target = prompts::edit_entity(ds, &e);
-        ds.update(&target)?;
+        if let Err(e) = ds.update(&target) {
+            print_error(ds, &target, e)?;
+        }
     }
     Ok(())
 }
 
+/// Print an error of a write operation, explaining
+/// which entity owns the handles in case of conflicts
+fn print_error(ds: &DataStore, target: &Entity, err: DataError) -> Result<(), DataError> {
+    match err {
+        DataError::IDAlreadyTaken => {
+            println!("cannot save {}, some handles are taken:", target.name());
+            for (k, v, owner) in ds.handle_conflicts(target)? {
+                println!("{}:{} is owned by {} ({})", k, v, owner.name(), owner.uid());
+            }
+            Ok(())
+        }
+        DataError::HasDependents(deps) => {
+            println!("cannot remove {}, it is referenced by:", target.name());
+            for uid in deps.iter() {
+                if let Some(e) = ds.get_by_uid(&utils::id(uid))? {
+                    println!("{} ({})", e.name(), e.uid());
+                }
+            }
+            Ok(())
+        }
+        DataError::PermissionDenied => {
+            println!(
+                "cannot change {}, your role in this context does not allow it",
+                target.name()
+            );
+            Ok(())
+        }
+        _ => Err(err),
+    }
+}
+
 fn add_entity(ds: &mut DataStore, principal: &Entity) -> Result<(), DataError> {
     let name = match prompts::input_opt("name? (empty to cancel)") {
         Some(n) => n,
@@ -321,6 +3609,7 @@ fn add_entity(ds: &mut DataStore, principal: &Entity) -> Result<(), DataError> {
     match prompts::confirm("Do you want to add it?", Yes) {
         Yes => match ds.add(&new) {
             Ok(uid) => println!("added with uid {}", uid),
+            Err(DataError::IDAlreadyTaken) => print_error(ds, &new, DataError::IDAlreadyTaken)?,
             Err(e) => println!("something went wrong {}", e),
         },
         No => println!("ok, another time"),
@@ -329,7 +3618,10 @@ fn add_entity(ds: &mut DataStore, principal: &Entity) -> Result<(), DataError> {
 }
 
 fn edit_today(ds: &mut DataStore, principal: &Entity) -> Result<(), DataError> {
-    let mut items = ds.agenda_until(&utils::today(), 0, 0);
+    let today = ds.settings().today();
+    let mut items = ds
+        .agenda_until(&today, &AgendaFilter::default(), 0, 0)
+        .items;
     while !items.is_empty() {
         let target = match prompts::edit_entities(&items) {
             Some(t) => t,
@@ -340,8 +3632,69 @@ fn edit_today(ds: &mut DataStore, principal: &Entity) -> Result<(), DataError> {
             add_note(ds, principal, Some(&target))?;
         }
         let target = prompts::edit_entity(ds, target);
-        ds.update(&target)?;
-        items = ds.agenda_until(&utils::today(), 0, 0);
+        if let Err(e) = ds.update(&target) {
+            print_error(ds, &target, e)?;
+        }
+        items = ds
+            .agenda_until(&today, &AgendaFilter::default(), 0, 0)
+            .items;
+    }
+    Ok(())
+}
+
+/// Go through the today/overdue list acting with a single keypress
+fn rapid_triage(ds: &mut DataStore, principal: &Entity) -> Result<(), DataError> {
+    let settings = ds.settings();
+    let today = settings.today();
+    let mut items = ds
+        .agenda_until(&today, &AgendaFilter::default(), 0, 0)
+        .items;
+    let mut selected = 0;
+    while !items.is_empty() {
+        selected = selected.min(items.len() - 1);
+        let mut target = items[selected].clone();
+        let tw = match prompts::triage_key(&settings, &items, selected) {
+            TriageKey::Up => {
+                selected = selected.saturating_sub(1);
+                continue;
+            }
+            TriageKey::Down => {
+                selected += 1;
+                continue;
+            }
+            TriageKey::Quit => break,
+            TriageKey::Note => {
+                add_note(ds, principal, Some(&target))?;
+                continue;
+            }
+            TriageKey::Open => None,
+            TriageKey::Done => {
+                if let Err(e) = ds.complete_next_action(&target) {
+                    print_error(ds, &target, e)?;
+                }
+                items = ds
+                    .agenda_until(&today, &AgendaFilter::default(), 0, 0)
+                    .items;
+                continue;
+            }
+            TriageKey::Postpone => Some(settings.reminder.to_string()),
+            TriageKey::Snooze => Some("1d".to_owned()),
+        };
+        target = match tw {
+            Some(tw) => {
+                let nad = TimeWindow::from_str(&tw).unwrap().offset(&today);
+                let nan = target.next_action_note.clone();
+                target.next_action(nad, nan);
+                target
+            }
+            None => prompts::edit_entity(ds, &target),
+        };
+        if let Err(e) = ds.update(&target) {
+            print_error(ds, &target, e)?;
+        }
+        items = ds
+            .agenda_until(&today, &AgendaFilter::default(), 0, 0)
+            .items;
     }
     Ok(())
 }
@@ -432,6 +3785,7 @@ enum Cell {
 #[derive(Debug)]
 struct Printer {
     sizes: Vec<usize>,
+    date_format: String,
     data: Vec<Vec<Cell>>,
     col_sep: String,
     row_sep: char,
@@ -442,6 +3796,7 @@ impl Printer {
     pub fn new(col_sizes: Vec<usize>) -> Printer {
         Printer {
             sizes: col_sizes,
+            date_format: "%a, %d.%m.%y".to_string(),
             data: Vec::new(),
             row_sep: '-',
             progress: '▮',
@@ -449,6 +3804,11 @@ impl Printer {
         }
     }
 
+    pub fn with_date_format(mut self, format: &str) -> Printer {
+        self.date_format = format.to_string();
+        self
+    }
+
     pub fn row(&mut self, row_data: Vec<Cell>) {
         self.data.push(row_data);
     }
@@ -472,7 +3832,10 @@ impl Printer {
                         match c {
                             Str(v) => v.pad(s, ' ', Left, true),
                             Cnt(v) => format!("{}", v).pad(s, ' ', Right, false),
-                            Date(v) => utils::human_date(v).pad(s, ' ', Left, false),
+                            Date(v) => v
+                                .format(&self.date_format)
+                                .to_string()
+                                .pad(s, ' ', Left, false),
                             Sep => "".pad(s, self.row_sep, Alignment::Right, false),
                         }
                     })
@@ -515,4 +3878,83 @@ mod tests {
         p.sep();
         assert_eq!(p.data.len(), 6);
     }
+
+    #[test]
+    fn test_tree() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let owner = Entity::from("owner").unwrap().self_sponsored();
+        let bob = Entity::from("bob").unwrap().with_sponsor(&owner);
+        let alice = Entity::from("alice").unwrap().with_sponsor(&bob);
+        let carl = Entity::from("carl").unwrap().with_sponsor(&alice);
+        ds.init(&owner).unwrap();
+        for e in [&bob, &alice, &carl].iter() {
+            ds.add(e).unwrap();
+        }
+        let mut lines = Vec::new();
+        let tree = ds.sponsorship_tree(&owner.uid(), 1).unwrap();
+        tree_lines(&tree, &mut lines);
+        assert_eq!(tree.descendants, 3);
+        assert_eq!(lines, vec!["owner (3)", "  bob (2) ..."]);
+    }
+
+    #[test]
+    fn test_filter_entities() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let bob = Entity::from("bob").unwrap().self_sponsored();
+        ds.init(&bob).unwrap();
+        let family = Tag::Group("family".to_owned());
+        for (name, class, tag) in [
+            ("alice smith", "person", family.clone()),
+            ("carl smith", "person", Tag::Feature("family".to_owned())),
+            ("smith & co", "org", family.clone()),
+        ]
+        .iter()
+        {
+            let e = Entity::from(name)
+                .unwrap()
+                .with_sponsor(&bob)
+                .with_class(class)
+                .with_tag(tag.clone());
+            ds.add(&e).unwrap();
+        }
+        let names = |filters: &[&str]| {
+            let mut found = filter_entities(&ds, filters.iter().copied())
+                .unwrap()
+                .iter()
+                .map(|e| e.name().to_owned())
+                .collect::<Vec<String>>();
+            found.sort();
+            found
+        };
+        assert_eq!(names(&["class:person"]), vec!["alice smith", "carl smith"]);
+        assert_eq!(names(&["tag:family", "class:person"]).len(), 2);
+        assert_eq!(
+            names(&["tag:group:family"]),
+            vec!["alice smith", "smith & co"]
+        );
+        assert_eq!(
+            names(&["smith", "tag:group:family", "class:org"]),
+            vec!["smith & co"]
+        );
+        assert_eq!(names(&["class:project"]).len(), 0);
+    }
+
+    #[test]
+    fn test_parse_day() {
+        let today = utils::date(17, 3, 2021);
+        let tests = vec![
+            ("today", utils::date(17, 3, 2021)),
+            ("tomorrow", utils::date(18, 3, 2021)),
+            ("yesterday", utils::date(16, 3, 2021)),
+            ("+2w", utils::date(31, 3, 2021)),
+            ("3d", utils::date(20, 3, 2021)),
+            ("01.04.2021", utils::date(1, 4, 2021)),
+        ];
+        for (s, expected) in tests {
+            assert_eq!(parse_day(s, &today).unwrap(), expected, "{}", s);
+        }
+        assert_eq!(parse_day("someday", &today).is_err(), true);
+    }
 }
@@ -1,10 +1,14 @@
 use ::valis::data::{
     context::ContextManager,
-    ledger::DataStore,
-    model::{Actor, Entity, Rel, RelQuality, Tag, TimeWindow},
+    ledger::{DataStore, ImportConflict, Resolution, SearchResult, Settings},
+    model::{
+        Actor, Address, Entity, FieldValue, ImportantDate, Priority, Recurrence, Rel, RelQuality,
+        RelType, Tag, TimeWindow,
+    },
     utils,
 };
-use dialoguer::console::Term;
+use chrono::NaiveDate;
+use dialoguer::console::{Key, Term};
 use dialoguer::{theme::ColorfulTheme, Confirm, Editor, Input, Password, Select};
 use std::str::FromStr;
 use Feat::*;
@@ -98,6 +102,15 @@ pub fn select_opt<'a, T: ?Sized>(q: &str, opts: Vec<(&'a str, &'a T)>) -> Option
 }
 
 pub fn select<'a, T: ?Sized>(q: &str, opts: Vec<(&'a str, &'a T)>) -> &'a T {
+    select_default(q, opts, 0)
+}
+
+/// shortcut for Select with the option selected at start
+pub fn select_default<'a, T: ?Sized>(
+    q: &str,
+    opts: Vec<(&'a str, &'a T)>,
+    default: usize,
+) -> &'a T {
     opts[Select::with_theme(&ColorfulTheme::default())
         .with_prompt(q)
         .items(
@@ -106,7 +119,7 @@ pub fn select<'a, T: ?Sized>(q: &str, opts: Vec<(&'a str, &'a T)>) -> &'a T {
                 .map(|(l, _v)| l.to_string())
                 .collect::<Vec<String>>(),
         )
-        .default(0)
+        .default(default)
         .interact_on(&Term::stdout())
         .unwrap()]
     .1
@@ -114,6 +127,16 @@ pub fn select<'a, T: ?Sized>(q: &str, opts: Vec<(&'a str, &'a T)>) -> &'a T {
 
 /// shortcut for editor
 /// fn select(q: &str, opts: Vec<&'static str>) -> &'static str {
+/// Ask for a date until a valid one is entered
+pub fn input_date(q: &str) -> NaiveDate {
+    loop {
+        match utils::date_from_str(&input(q, Feat::NonEmpty)) {
+            Some(d) => return d,
+            None => println!("that's not a date, try again"),
+        }
+    }
+}
+
 pub fn editor(q: &str) -> Option<String> {
     Editor::new().edit(q).unwrap()
 }
@@ -126,6 +149,16 @@ pub fn password(question: &str) -> String {
         .unwrap()
 }
 
+/// Ask for a new password, with confirmation
+pub fn new_password(question: &str) -> String {
+    Password::with_theme(&ColorfulTheme::default())
+        .with_prompt(question)
+        .allow_empty_password(false)
+        .with_confirmation("repeat the password", "password doesn't match!")
+        .interact_on(&Term::stdout())
+        .unwrap()
+}
+
 pub fn principal_entity() -> Entity {
     let name = input("what's your name?", Feat::NonEmpty);
     // ask if they want a password
@@ -157,17 +190,22 @@ pub fn root_entity() -> Entity {
     Entity::from(&name).unwrap().with_class(class)
 }
 
-pub fn new_entity(name: &str, sponsor: &Entity) -> Entity {
-    // get the class
-    let class = select(
-        "how will describe that",
+/// shortcut to select the class of an entity
+pub fn select_class(q: &str) -> &'static str {
+    select(
+        q,
         vec![
             ("Person", "person"),
             ("Organization", "org"),
             ("Project", "project"),
             ("Thing", "thing"),
         ],
-    );
+    )
+}
+
+pub fn new_entity(name: &str, sponsor: &Entity) -> Entity {
+    // get the class
+    let class = select_class("how will describe that");
     // we have enough to create the entity
     Entity::from(&name)
         .unwrap()
@@ -201,14 +239,20 @@ pub fn new_entity_unless_exists(ds: &DataStore, name: &str, sponsor: &Entity) ->
 /// Will return an Option<(Entity, bool)> where the bool indicates
 /// if the entity returned is new (has been created)
 pub fn select_or_create(ds: &DataStore, name: &str, sponsor: &Entity) -> Option<(Entity, bool)> {
-    let res = ds.search(name);
+    // a single entity going by the name, eg. [[Bob]] for Robert
+    if let [e] = ds.resolve(name).as_slice() {
+        if e.is_named(name) {
+            return Some((e.clone(), false));
+        }
+    }
+    let res = ds.search_detailed(name);
     if res.is_empty() {
         if No == confirm("nothing found, add instead?", No) {
             return None;
         }
         return Some((new_entity(name, sponsor), true));
     }
-    if let Some(r) = select_entity("please select one  (or esc/q to cancel):", &res) {
+    if let Some(r) = select_result("please select one  (or esc/q to cancel):", &res) {
         return Some((r.clone(), false));
     }
     None
@@ -234,25 +278,37 @@ pub fn edit_entities(items: &[Entity]) -> Option<&Entity> {
     }
 }
 
-/// Edit the next action date and note
-fn edit_next_action(e: &mut Entity) {
+/// Edit the next action date and note, the reminder
+/// window of the settings is selected at start
+fn edit_next_action(settings: &Settings, e: &mut Entity) {
     let rtw = utils::random_timewindow(1, 12, Some('w'));
-    let tw = select(
+    let reminder = settings.reminder.to_string();
+    let default_label = format!("In {} (default)", reminder);
+    let mut opts = vec![
+        ("Today", "0d"),
+        ("Tomorrow", "1d"),
+        ("In 3 days", "3d"),
+        ("In a week", "1w"),
+        ("In two weeks", "2w"),
+        ("In one month", "1m"),
+        ("In three months", "3m"),
+        ("In six months", "6m"),
+        ("Later", &rtw[..]),
+    ];
+    let default = match opts.iter().position(|(_, w)| *w == reminder) {
+        Some(i) => i,
+        None => {
+            opts.insert(0, (&default_label[..], &reminder[..]));
+            0
+        }
+    };
+    let tw = select_default(
         &format!("when shall you be reminded about {}", e.name()),
-        vec![
-            ("Today", "0d"),
-            ("Tomorrow", "1d"),
-            ("In 3 days", "3d"),
-            ("In a week", "1w"),
-            ("In two weeks", "2w"),
-            ("In one month", "1m"),
-            ("In three months", "3m"),
-            ("In six months", "6m"),
-            ("Later", &rtw),
-        ],
+        opts,
+        default,
     );
 
-    let nad = TimeWindow::from_str(&tw).unwrap().offset(&utils::today());
+    let nad = TimeWindow::from_str(&tw).unwrap().offset(&settings.today());
     let nan = match editor("leave a note for the reminder") {
         Some(x) => x,
         None => e.next_action_note.clone(),
@@ -262,29 +318,57 @@ fn edit_next_action(e: &mut Entity) {
 
 pub fn postpone(e: &mut Entity) {}
 
-pub fn edit_data(ds: &mut DataStore, target: &mut Entity) {
-    // info
-    if let Yes = confirm("would you like to add some details?", No) {
-        if let Some(desc) = editor(&format!("write a note about {}", target.name())) {
-            target.description = desc;
-        }
-        // handles
-        while let Yes = confirm("add an handle?", Yes) {
-            let handles = vec![
-                ("Email", "email"),
-                ("Nickname", "nick"),
-                ("Website", "url"),
-                ("Telegram", "telegram"),
-                ("LinkedIn", "linkedin"),
-                ("Mobile", "mobile"),
-            ];
-            let prefix = select("what do you want to set", handles);
-            let label = input(&format!("what is the {} handle", prefix), Feat::NonEmpty);
-            target.add_handle(prefix, &label);
-        }
-    };
+/// The actions available in the rapid triage
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriageKey {
+    Done,
+    Postpone,
+    Snooze,
+    Note,
+    Open,
+    Up,
+    Down,
+    Quit,
+}
 
-    // ask for the quality
+/// Show the list with the highlighted item and wait for a keypress,
+/// the unknown keys are ignored
+pub fn triage_key(settings: &Settings, items: &[Entity], selected: usize) -> TriageKey {
+    let term = Term::stdout();
+    term.clear_screen().unwrap();
+    for (i, e) in items.iter().enumerate() {
+        let marker = if i == selected { ">" } else { " " };
+        println!(
+            "{} {:12} {:40} - {}",
+            marker,
+            settings.human_date(&e.next_action_date),
+            e.name(),
+            e.get_next_action_headline()
+        );
+    }
+    println!();
+    println!(
+        "d done · p postpone {} · s snooze 1d · n note · enter open · q quit",
+        settings.reminder
+    );
+    loop {
+        let k = match term.read_key() {
+            Ok(Key::Char('d')) => TriageKey::Done,
+            Ok(Key::Char('p')) => TriageKey::Postpone,
+            Ok(Key::Char('s')) => TriageKey::Snooze,
+            Ok(Key::Char('n')) => TriageKey::Note,
+            Ok(Key::Enter) => TriageKey::Open,
+            Ok(Key::ArrowUp) | Ok(Key::Char('k')) => TriageKey::Up,
+            Ok(Key::ArrowDown) | Ok(Key::Char('j')) => TriageKey::Down,
+            Ok(Key::Escape) | Ok(Key::Char('q')) | Err(_) => TriageKey::Quit,
+            _ => continue,
+        };
+        return k;
+    }
+}
+
+/// Ask to confirm or change the relationship quality
+pub fn edit_quality(target: &mut Entity) {
     let prompt = format!(
         "relationship is {}, is it still the case ?",
         target.quality.emoji(),
@@ -305,6 +389,252 @@ pub fn edit_data(ds: &mut DataStore, target: &mut Entity) {
             target.set_quality(q);
         }
     }
+}
+
+/// Add tags to an entity until the user is done
+pub fn edit_tags(target: &mut Entity) {
+    while let Yes = confirm("shall we add a tag?", No) {
+        let tags = vec![
+            ("Tag", "generic"),
+            ("Category", "category"),
+            ("Skill", "feat"),
+            ("Link", "link"),
+            ("Role", "role"),
+        ];
+        let prefix = select("tag type", tags);
+        let label = input("what is the tag label", Feat::NonEmpty);
+        target.add_tag(Tag::from(&prefix, &label));
+    }
+}
+
+/// Set custom fields on an entity until the user is done,
+/// an empty value removes the field
+pub fn edit_fields(target: &mut Entity) {
+    while let Yes = confirm("shall we set a custom field?", No) {
+        let name = input("what is the field name", Feat::NonEmpty);
+        let kinds = FieldValue::kinds();
+        let kind = select(
+            "what kind of value",
+            kinds.iter().map(|k| (*k, *k)).collect(),
+        );
+        loop {
+            let current = target
+                .get_field(&name)
+                .map(|v| v.to_string())
+                .unwrap_or_default();
+            let q = match current.is_empty() {
+                true => format!("what is the {}", name),
+                false => format!("what is the {} (now {})", name, current),
+            };
+            let value = match input_opt(&q) {
+                Some(v) => v,
+                None => {
+                    target.remove_field(&name);
+                    break;
+                }
+            };
+            match FieldValue::parse(kind, &value) {
+                Ok(v) => {
+                    target.set_field(&name, v);
+                    break;
+                }
+                Err(e) => println!("{}, try again", e),
+            }
+        }
+    }
+}
+
+/// Ask to confirm or change the priority
+pub fn edit_priority(target: &mut Entity) {
+    let prompt = format!(
+        "the priority is {}, is it still the case ?",
+        target.priority
+    );
+    if No == confirm(&prompt, Yes) {
+        let priorities = Priority::all();
+        let labels = priorities.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let p = select(
+            "how important is it?",
+            labels
+                .iter()
+                .map(|l| &l[..])
+                .zip(priorities.iter())
+                .collect(),
+        );
+        *target = target.clone().with_priority(*p);
+    }
+}
+
+/// Ask how often to get in touch with an entity
+pub fn edit_contact_every(target: &mut Entity) {
+    let prompt = match &target.contact_every {
+        Some(every) => format!("you get in touch every {}, is it still the case ?", every),
+        None => "do you want to get in touch regularly?".to_string(),
+    };
+    let tracked = target.contact_every.is_some();
+    let answer = confirm(&prompt, if tracked { Yes } else { No });
+    // change it when the answer is not the current state
+    if (answer == Yes) != tracked {
+        let every = input_opt("how often (eg. 2w, 3m), enter to stop tracking it");
+        target.set_contact_every(every.map(|w| TimeWindow::from_str(&w).unwrap()));
+    }
+}
+
+/// Edit the address of an entity, the coordinates are optional
+pub fn edit_address(target: &mut Entity) {
+    let prompt = match &target.address {
+        Some(a) => format!("the address is {}, do you want to change it?", a),
+        None => "do you want to set the address?".to_string(),
+    };
+    if No == confirm(&prompt, No) {
+        return;
+    }
+    let address = loop {
+        match input_opt("what is it (street, city, country), enter to remove it") {
+            Some(a) => match Address::from_str(&a) {
+                Ok(a) => break Some(a),
+                Err(e) => println!("{}, try again", e),
+            },
+            None => break None,
+        }
+    };
+    let address = address.map(|a| loop {
+        let at = match input_opt("where is it (lat,lon), enter to skip") {
+            Some(at) => at,
+            None => break a,
+        };
+        let coords = utils::split_once(&at, ',')
+            .and_then(|(lat, lon)| Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?)));
+        match coords.map(|(lat, lon)| a.clone().with_location(lat, lon)) {
+            Some(Ok(a)) => break a,
+            Some(Err(e)) => println!("{}, try again", e),
+            None => println!("invalid coordinates {}, try again", at),
+        }
+    });
+    target.set_address(address);
+}
+
+/// Add important dates to an entity until the user is done
+pub fn edit_dates(target: &mut Entity) {
+    while let Yes = confirm("shall we add an important date?", No) {
+        let label = input("what is it (eg. birthday, anniversary)", Feat::NonEmpty);
+        let date = input_date("when (dd.mm.yyyy)");
+        let recurrence = select(
+            "how often does it come back",
+            vec![
+                ("Every year", &Recurrence::Yearly),
+                ("Every month", &Recurrence::Monthly),
+                ("Just once", &Recurrence::Once),
+            ],
+        );
+        target.set_date(ImportantDate::new(&label, date, *recurrence));
+    }
+}
+
+/// Turn a captured entity into a proper one: class, sponsor, tags and next action
+pub fn triage_entity(ds: &DataStore, target: &mut Entity) {
+    let class = select_class(&format!("how will describe {}", target.name()));
+    *target = target.clone().with_class(class);
+    // check the sponsor
+    let prompt = match ds.get_by_uid(&target.sponsor_uid()) {
+        Ok(Some(s)) => format!(
+            "{} has been introduced by {}, is it right?",
+            target.name(),
+            s
+        ),
+        _ => format!("{} has no known sponsor, is it right?", target.name()),
+    };
+    if No == confirm(&prompt, Yes) {
+        if let Some(s) = search(ds, "who introduced it? (enter to cancel)") {
+            *target = target.clone().with_sponsor(&s);
+        }
+    }
+    edit_tags(target);
+    edit_next_action(&ds.settings(), target);
+}
+
+/// Walk through the review of an entity: quality, tags and next action
+pub fn review_entity(settings: &Settings, target: &mut Entity) {
+    edit_quality(target);
+    edit_tags(target);
+    if Yes == confirm("do you want to change the next action?", No) {
+        edit_next_action(settings, target);
+    }
+}
+
+/// Ask how to resolve an import conflict
+pub fn resolve_conflict(c: &ImportConflict) -> Resolution {
+    println!(
+        "conflict on {} ({}): {}",
+        c.imported.name(),
+        c.imported.uid(),
+        c.reason
+    );
+    for (field, local, imported) in c.local.diff(&c.imported) {
+        println!("  {:25} {} -> {}", field, local, imported);
+    }
+    let local = format!("keep {}", c.local.name());
+    let imported = format!("take the imported {}", c.imported.name());
+    let opts = vec![
+        (&local[..], "local"),
+        (&imported[..], "imported"),
+        ("merge field by field", "merge"),
+        ("skip the imported one", "skip"),
+    ];
+    match select("what shall we do?", opts) {
+        "local" => Resolution::KeepLocal,
+        "imported" => Resolution::TakeImported,
+        "merge" => Resolution::Merge(merge_entities(&c.local, &c.imported)),
+        _ => Resolution::Skip,
+    }
+}
+
+/// Merge two entities choosing for each field that differs
+/// between the local and the imported value
+pub fn merge_entities(local: &Entity, imported: &Entity) -> Entity {
+    let fields = local
+        .diff(imported)
+        .into_iter()
+        .filter(|(field, l, i)| {
+            let q = format!("{}: {} -> {}, take the imported?", field, l, i);
+            Yes == confirm(&q, No)
+        })
+        .map(|(field, _, _)| field)
+        .collect::<Vec<String>>();
+    local.merge_fields(imported, &fields)
+}
+
+pub fn edit_data(ds: &mut DataStore, target: &mut Entity) {
+    // info
+    if let Yes = confirm("would you like to add some details?", No) {
+        if let Some(desc) = editor(&format!("write a note about {}", target.name())) {
+            target.description = desc;
+        }
+        // handles
+        while let Yes = confirm("add an handle?", Yes) {
+            let handles = vec![
+                ("Email", "email"),
+                ("Nickname", "nick"),
+                ("Website", "url"),
+                ("Telegram", "telegram"),
+                ("LinkedIn", "linkedin"),
+                ("Mobile", "mobile"),
+            ];
+            let prefix = select("what do you want to set", handles);
+            loop {
+                let label = input(&format!("what is the {} handle", prefix), Feat::NonEmpty);
+                match target.add_handle(prefix, &label) {
+                    Ok(_) => break,
+                    Err(e) => println!("{}, try again", e),
+                }
+            }
+        }
+    };
+
+    // ask for the quality
+    edit_quality(target);
+    edit_priority(target);
+    edit_contact_every(target);
     // -- advanced editing
     if No == confirm("do you want to edit more details?", No) {
         println!("ok");
@@ -317,6 +647,9 @@ pub fn edit_data(ds: &mut DataStore, target: &mut Entity) {
             target.add_relation(&rel);
         }
     }
+    if target.relationships.len() > 1 && Yes == confirm("reorder the relationships?", No) {
+        reorder_relationships(ds, target);
+    }
     // handles
     while let Yes = confirm("add an handle?", Yes) {
         let handles = vec![
@@ -329,22 +662,22 @@ pub fn edit_data(ds: &mut DataStore, target: &mut Entity) {
             ("Github", "github"),
         ];
         let prefix = select("what do you want to set", handles);
-        let label = input(&format!("what is the {} handle", prefix), Feat::NonEmpty);
-        target.add_handle(prefix, &label);
+        loop {
+            let label = input(&format!("what is the {} handle", prefix), Feat::NonEmpty);
+            match target.add_handle(prefix, &label) {
+                Ok(_) => break,
+                Err(e) => println!("{}, try again", e),
+            }
+        }
     }
     //tags
-    while let Yes = confirm("shall we add a tag?", No) {
-        let tags = vec![
-            ("Tag", "generic"),
-            ("Category", "category"),
-            ("Skill", "feat"),
-            ("Link", "link"),
-            ("Role", "role"),
-        ];
-        let prefix = select("tag type", tags);
-        let label = input("what is the tag label", Feat::NonEmpty);
-        target.add_tag(Tag::from(&prefix, &label));
-    }
+    edit_tags(target);
+    // custom fields
+    edit_fields(target);
+    // important dates
+    edit_dates(target);
+    // address
+    edit_address(target);
     // description
     if Yes == confirm("do you want to edit the description?", No) {
         match editor(&target.description) {
@@ -357,6 +690,11 @@ pub fn edit_data(ds: &mut DataStore, target: &mut Entity) {
         let prompt = format!("what's the new name for {}?", target.name());
         target.name = input(&prompt, NonEmpty)
     }
+    // aliases
+    while let Yes = confirm("add another name it goes by?", No) {
+        let alias = input("what is the nickname or alternate name", NonEmpty);
+        target.add_alias(&alias);
+    }
     // save
     if Yes == confirm("shall I save the changes?", Yes) {
         ds.update(&target).ok();
@@ -370,10 +708,11 @@ pub fn edit_entity(ds: &mut DataStore, target: &Entity) -> Entity {
         vec![("Next action", "action"), ("Data", "data")],
     ) {
         Some("action") => {
-            edit_next_action(&mut target);
+            let settings = ds.settings();
+            edit_next_action(&settings, &mut target);
             println!(
                 "I'll remind you on {} about {} with:\n{}",
-                target.next_action_date,
+                settings.human_date(&target.next_action_date),
                 target.name(),
                 target.next_action_note
             );
@@ -407,11 +746,11 @@ pub fn search(ds: &DataStore, q: &str) -> Option<Entity> {
                 return None;
             }
             p => {
-                let res = ds.search(p);
+                let res = ds.search_detailed(p);
                 if res.is_empty() {
                     continue;
                 }
-                match select_entity("please select one  (or esc/q to cancel):", &res) {
+                match select_result("please select one  (or esc/q to cancel):", &res) {
                     Some(r) => return Some(r.clone()),
                     None => continue,
                 }
@@ -420,9 +759,79 @@ pub fn search(ds: &DataStore, q: &str) -> Option<Entity> {
     }
 }
 
+/// Ask for the kind of relationship with a target, the
+/// inverse relationship is added to the target when stored
 pub fn select_relationship(target: &Entity) -> Rel {
-    // TODO: implement this interaction
-    Rel::new(target)
+    let kind = select(
+        &format!("how is it related to {}?", target.name()),
+        vec![
+            ("Related to", "related_to"),
+            ("Works at", "works_at"),
+            ("Employs", "employs"),
+            ("Manager of", "manager_of"),
+            ("Reports to", "reports_to"),
+            ("Partner of", "partner_of"),
+            ("Client of", "client_of"),
+            ("Supplier of", "supplier_of"),
+            ("Member of", "member_of"),
+            ("Has a role", "role"),
+        ],
+    );
+    let kind = match kind {
+        "works_at" => RelType::WorksAt,
+        "employs" => RelType::Employs,
+        "manager_of" => RelType::ManagerOf,
+        "reports_to" => RelType::ReportsTo,
+        "partner_of" => RelType::PartnerOf,
+        "client_of" => RelType::ClientOf,
+        "supplier_of" => RelType::SupplierOf,
+        "member_of" => RelType::MemberOf(
+            input_date("member since (dd.mm.yyyy)"),
+            input_date("member until (dd.mm.yyyy)"),
+        ),
+        "role" => RelType::Role(
+            input("which role (eg. ceo)", Feat::NonEmpty),
+            utils::today(),
+            None,
+        ),
+        _ => RelType::RelatedTo,
+    };
+    Rel::new(target).with_kind(kind)
+}
+
+/// Change the weight of the relationships, the
+/// heaviest ones are listed first
+pub fn reorder_relationships(ds: &DataStore, target: &mut Entity) {
+    loop {
+        let labels = target
+            .relationships
+            .iter()
+            .map(|r| {
+                let name = match ds.get_by_uid(&utils::id(&r.target)) {
+                    Ok(Some(e)) => e.name().to_owned(),
+                    _ => utils::id(&r.target),
+                };
+                format!("{:30} {:15} weight {}", name, r.kind.get_label(), r.weight)
+            })
+            .collect::<Vec<String>>();
+        let idxs = (0..labels.len()).collect::<Vec<usize>>();
+        let opts = labels
+            .iter()
+            .zip(idxs.iter())
+            .map(|(l, i)| (&l[..], i))
+            .collect::<Vec<(&str, &usize)>>();
+        let idx = match select_opt("which one? (esc/q when done)", opts) {
+            Some(i) => *i,
+            None => break,
+        };
+        let current = target.relationships[idx].weight.to_string();
+        let weight = Input::<u32>::with_theme(&ColorfulTheme::default())
+            .with_prompt("weight (the higher the sooner)")
+            .with_initial_text(current)
+            .interact_text()
+            .unwrap();
+        target.set_relation_weight(idx, weight);
+    }
 }
 
 pub fn select_entity<'a>(q: &'a str, entities: &'a [Entity]) -> Option<&'a Entity> {
@@ -430,6 +839,22 @@ pub fn select_entity<'a>(q: &'a str, entities: &'a [Entity]) -> Option<&'a Entit
     select_opt(q, opts)
 }
 
+/// Select an entity from search results, showing which field has matched
+pub fn select_result<'a>(q: &str, results: &'a [SearchResult]) -> Option<&'a Entity> {
+    Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(q)
+        .items(
+            &results
+                .iter()
+                .map(|r| format!("{:40} ({})", r.entity.name(), r.field))
+                .collect::<Vec<String>>(),
+        )
+        .default(0)
+        .interact_on_opt(&Term::stdout())
+        .unwrap()
+        .map(|i| &results[i].entity)
+}
+
 pub fn select_context(context_manager: &ContextManager) -> String {
     select(
         "Which one?",
@@ -442,22 +867,35 @@ pub fn select_context(context_manager: &ContextManager) -> String {
     .to_owned()
 }
 
-pub fn menu() -> Option<String> {
-    // ask for the quality
-    match select_opt(
-        "hello there, what shall we do? esc/q to quit",
-        vec![
-            ("Quick note", "note"),
-            ("Agenda", "agenda"),
-            ("Dig up today", "today"),
-            ("Audit", "inspect"),
-            ("Update", "update"),
-            ("Add new", "add"),
-            ("Suggest what to do", "hint"),
-            ("Change context", "change_context"),
-            ("New context", "new_context"),
-        ],
-    ) {
+/// The actions of the interactive menu, in the default order
+pub const MENU: &[(&str, &str)] = &[
+    ("Quick note", "note"),
+    ("Inbox", "inbox"),
+    ("Agenda", "agenda"),
+    ("Dig up today", "today"),
+    ("Rapid triage", "rapid"),
+    ("Audit", "inspect"),
+    ("Review", "review"),
+    ("Update", "update"),
+    ("Add new", "add"),
+    ("Suggest what to do", "hint"),
+    ("Change context", "change_context"),
+    ("New context", "new_context"),
+    ("Lock and quit", "lock"),
+];
+
+/// Show the menu, the actions listed in the settings come in
+/// their order, when none is listed all of them are shown
+pub fn menu(settings: &Settings) -> Option<String> {
+    let mut opts = settings
+        .menu
+        .iter()
+        .filter_map(|a| MENU.iter().find(|(_, v)| v == a).copied())
+        .collect::<Vec<_>>();
+    if opts.is_empty() {
+        opts = MENU.to_vec();
+    }
+    match select_opt("hello there, what shall we do? esc/q to quit", opts) {
         Some(x) => Some(x.to_string()),
         _ => None,
     }
@@ -2,9 +2,46 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
 
+/// The credentials cached in the OS keyring, one per user uid
+#[cfg(feature = "keyring")]
+mod credentials {
+    const SERVICE: &str = "valis";
+
+    pub fn get(uid: &str) -> Option<String> {
+        keyring::Entry::new(SERVICE, uid)
+            .and_then(|e| e.get_password())
+            .ok()
+    }
+
+    pub fn set(uid: &str, pwd: &str) -> bool {
+        keyring::Entry::new(SERVICE, uid)
+            .and_then(|e| e.set_password(pwd))
+            .is_ok()
+    }
+
+    pub fn delete(uid: &str) {
+        let _ = keyring::Entry::new(SERVICE, uid).and_then(|e| e.delete_password());
+    }
+}
+
+/// Without the keyring the credentials stay in the config file
+#[cfg(not(feature = "keyring"))]
+mod credentials {
+    pub fn get(_uid: &str) -> Option<String> {
+        None
+    }
+
+    pub fn set(_uid: &str, _pwd: &str) -> bool {
+        false
+    }
+
+    pub fn delete(_uid: &str) {}
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct UserConfig {
     pub uid: String,
+    // the cached password hash, when the keyring is not available
     pub pwd: Option<String>,
     pub ctx: String,
 }
@@ -34,6 +71,38 @@ impl UserConfig {
         fs::write(path, toml::to_string(self).unwrap())?;
         Ok(self)
     }
+
+    /// Returns the cached password hash, if any
+    pub fn cached_pwd(&self) -> Option<String> {
+        self.pwd.clone().or_else(|| credentials::get(&self.uid))
+    }
+
+    /// Cache the password hash in the OS keyring,
+    /// or in the config file when the keyring is not available
+    pub fn cache_pwd(&mut self, pwd: &str) {
+        self.pwd = match credentials::set(&self.uid, pwd) {
+            true => None,
+            false => Some(pwd.to_owned()),
+        };
+    }
+
+    /// Forget the cached password hash
+    pub fn forget_pwd(&mut self) {
+        self.pwd = None;
+        credentials::delete(&self.uid);
+    }
+
+    /// Move the password hash cached in the config file to the
+    /// OS keyring, returns true if the config has changed
+    pub fn migrate_pwd(&mut self) -> bool {
+        match &self.pwd {
+            Some(pwd) if credentials::set(&self.uid, pwd) => {
+                self.pwd = None;
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -61,4 +130,18 @@ mod tests {
         assert_eq!(uc.pwd, None);
         assert_eq!(uc.uid, "xxx");
     }
+
+    #[cfg(not(feature = "keyring"))]
+    #[test]
+    fn test_cached_pwd() {
+        // without the keyring the hash stays in the config
+        let mut uc = UserConfig::new("xxx".to_owned(), "default".to_owned());
+        assert_eq!(uc.cached_pwd(), None);
+        uc.cache_pwd("hash");
+        assert_eq!(uc.pwd, Some("hash".to_owned()));
+        assert_eq!(uc.cached_pwd(), Some("hash".to_owned()));
+        assert_eq!(uc.migrate_pwd(), false);
+        uc.forget_pwd();
+        assert_eq!(uc.cached_pwd(), None);
+    }
 }
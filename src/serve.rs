@@ -0,0 +1,455 @@
+use ::valis::data::{
+    formats::entity_json,
+    ledger::{AgendaFilter, DataError, DataStore, EventFilter},
+    model::Entity,
+    query::Query,
+    utils,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tiny_http::{Header, Response, Server as HttpServer};
+
+/// How often the running flag is checked while waiting for requests
+const TICK: Duration = Duration::from_millis(500);
+/// The max size of a request body, the larger ones are refused
+const MAX_BODY: u64 = 1024 * 1024;
+
+/// The JSON API over the open context
+///
+/// Every request but the login needs a session token in the
+/// `Authorization: Bearer <token>` header, the request is then
+/// served with the role of the user of the session. The requests
+/// are served one at a time since the datastore is not shared.
+///
+/// The API is served over plain http, so the server listens only
+/// on the loopback addresses unless it is allowed otherwise
+pub struct Server {
+    /// the address to listen to, eg. 127.0.0.1:7878
+    pub listen: String,
+    /// allow to listen on the addresses other hosts can reach
+    pub insecure: bool,
+}
+
+/// A response, the status code and the JSON body
+type Reply = (u16, Value);
+
+impl Server {
+    pub fn new(listen: &str) -> Server {
+        Server {
+            listen: listen.to_owned(),
+            insecure: false,
+        }
+    }
+
+    /// Allow to listen on an address that is not a loopback one
+    pub fn with_insecure(mut self, insecure: bool) -> Server {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Serve the requests until the running flag is cleared
+    pub fn run(&self, ds: &mut DataStore, running: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+        if !self.insecure && !is_loopback(&self.listen)? {
+            return Err(format!(
+                "{} is reachable from other hosts and the API is served over plain http",
+                self.listen
+            )
+            .into());
+        }
+        let server = HttpServer::http(&self.listen).map_err(|e| e.to_string())?;
+        let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+        while running.load(Ordering::SeqCst) {
+            let mut req = match server.recv_timeout(TICK)? {
+                Some(r) => r,
+                None => continue,
+            };
+            let token = req
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv("Authorization"))
+                .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+                .map(|t| t.trim().to_owned());
+            let mut body = String::new();
+            let read = req.as_reader().take(MAX_BODY + 1).read_to_string(&mut body);
+            let (status, value) = match read {
+                Ok(n) if n as u64 > MAX_BODY => failure(413, "the request body is too large"),
+                Ok(_) => handle(
+                    ds,
+                    &req.method().to_string(),
+                    req.url(),
+                    token.as_deref(),
+                    &body,
+                ),
+                Err(e) => failure(400, &e.to_string()),
+            };
+            let res = Response::from_string(value.to_string())
+                .with_status_code(status)
+                .with_header(content_type.clone());
+            if let Err(e) = req.respond(res) {
+                println!("cannot send the response: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tells if all the addresses an address resolves to are loopback ones
+fn is_loopback(listen: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(listen.to_socket_addrs()?.all(|a| a.ip().is_loopback()))
+}
+
+fn failure(status: u16, msg: &str) -> Reply {
+    (status, json!({ "error": msg }))
+}
+
+/// Map the datastore errors to the http status codes
+fn error_reply(err: DataError) -> Reply {
+    let status = match err {
+        DataError::NotFound => 404,
+        DataError::PermissionDenied => 403,
+        DataError::InvalidToken => 401,
+        DataError::IDAlreadyTaken | DataError::HasDependents(_) => 409,
+        DataError::GenericError(_)
+        | DataError::InvalidHandle(_)
+        | DataError::InvalidSponsor
+        | DataError::BrokenReference => 400,
+        _ => 500,
+    };
+    failure(status, &err.to_string())
+}
+
+/// Decode a component of the query string
+fn decode(s: &str) -> String {
+    let bytes = s.replace('+', " ").into_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Split an url in the path segments and the query parameters
+fn parse_url(url: &str) -> (Vec<String>, HashMap<String, String>) {
+    let (path, query) = utils::split_once(url, '?').unwrap_or((url, ""));
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(decode)
+        .collect();
+    let params = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match utils::split_once(p, '=') {
+            Some((k, v)) => (decode(k), decode(v)),
+            None => (decode(p), String::new()),
+        })
+        .collect();
+    (segments, params)
+}
+
+fn list_json(entities: &[Entity]) -> Value {
+    Value::Array(entities.iter().map(entity_json).collect())
+}
+
+/// Serve a request, the token is the one of the Authorization header
+pub fn handle(
+    ds: &mut DataStore,
+    method: &str,
+    url: &str,
+    token: Option<&str>,
+    body: &str,
+) -> Reply {
+    let (segments, params) = parse_url(url);
+    let segments = segments.iter().map(|s| &s[..]).collect::<Vec<&str>>();
+    if segments.first() != Some(&"api") {
+        return failure(404, "not found");
+    }
+    let body = match body.trim().is_empty() {
+        true => Value::Null,
+        false => match serde_json::from_str::<Value>(body) {
+            Ok(v) => v,
+            Err(e) => return failure(400, &format!("invalid json: {}", e)),
+        },
+    };
+    // the login is the only way in without a session
+    if let (&["api", "login"], "POST") = (&segments[..], method) {
+        return login(ds, &body);
+    }
+    let (token, user) = match token.map(|t| (t, ds.resume(t))) {
+        Some((t, Ok(u))) => (t, u),
+        _ => return failure(401, "a valid session token is required"),
+    };
+    let out = match (method, &segments[1..]) {
+        ("POST", &["logout"]) => ds.logout(token).map(|_| (200, json!({}))),
+        ("GET", &["entities"]) => Ok((200, list_entities(ds, &params))),
+        ("POST", &["entities"]) => create_entity(ds, &user, &body),
+        ("GET", &["entities", uid]) => find(ds, uid).map(|e| (200, entity_json(&e))),
+        ("PUT", &["entities", uid]) => update_entity(ds, uid, body),
+        ("DELETE", &["entities", uid]) => find(ds, uid)
+            .and_then(|e| ds.remove(&e))
+            .map(|_| (200, json!({}))),
+        ("GET", &["entities", uid, "events"]) => find(ds, uid).map(|e| {
+            let events = ds.events(&e, EventFilter::Any);
+            (200, serde_json::to_value(&events).unwrap())
+        }),
+        ("GET", &["search"]) => match params.get("q") {
+            Some(q) => Ok((200, list_json(&ds.search(q)))),
+            None => Ok(failure(400, "the q parameter is required")),
+        },
+        ("GET", &["agenda"]) => agenda(ds, &params),
+        ("POST", &["notes"]) => record_note(ds, &user, &body),
+        _ => Ok(failure(404, "not found")),
+    };
+    out.unwrap_or_else(error_reply)
+}
+
+fn login(ds: &mut DataStore, body: &Value) -> Reply {
+    let (user, pwd) = match (body["user"].as_str(), body["password"].as_str()) {
+        (Some(u), Some(p)) => (u, p),
+        _ => return failure(400, "user and password are required"),
+    };
+    // the user can be referenced by uid, name or handle
+    let found = ds.resolve(user);
+    let uid = match found.as_slice() {
+        [u] => u.uid(),
+        _ => return failure(403, "invalid user or password"),
+    };
+    match ds.login(&uid, pwd) {
+        Ok(s) => (
+            200,
+            json!({
+                "token": s.token,
+                "user": utils::id(&s.user),
+                "expires": s.expires.to_rfc3339(),
+            }),
+        ),
+        Err(_) => failure(403, "invalid user or password"),
+    }
+}
+
+fn find(ds: &DataStore, uid: &str) -> Result<Entity, DataError> {
+    ds.get_by_uid(uid)?.ok_or(DataError::NotFound)
+}
+
+/// List the entities, filtered by class and tag when given
+fn list_entities(ds: &DataStore, params: &HashMap<String, String>) -> Value {
+    let q = Query {
+        class: params.get("class").cloned(),
+        tags: params.get("tag").into_iter().cloned().collect(),
+        ..Query::default()
+    };
+    let found = ds
+        .query(&q)
+        .into_iter()
+        .map(|(e, _)| e)
+        .collect::<Vec<Entity>>();
+    list_json(&found)
+}
+
+fn create_entity(ds: &mut DataStore, user: &Entity, body: &Value) -> Result<Reply, DataError> {
+    let name = match body["name"].as_str() {
+        Some(n) => n,
+        None => return Ok(failure(400, "the name is required")),
+    };
+    let mut e = Entity::from(name)
+        .map_err(|e| DataError::GenericError(e.to_string()))?
+        .with_sponsor(user);
+    if let Some(class) = body["class"].as_str() {
+        e = e.with_class(class);
+    }
+    if let Some(sponsor) = body["sponsor"].as_str() {
+        e = e.with_sponsor(&find(ds, sponsor)?);
+    }
+    ds.add(&e)?;
+    Ok((201, entity_json(&e)))
+}
+
+fn update_entity(ds: &mut DataStore, uid: &str, body: Value) -> Result<Reply, DataError> {
+    let mut e: Entity = match serde_json::from_value(body) {
+        Ok(e) => e,
+        Err(err) => return Ok(failure(400, &format!("invalid entity: {}", err))),
+    };
+    if e.uid() != uid {
+        return Ok(failure(400, "the uid does not match the url"));
+    }
+    // the password is never sent out, so keep the stored one
+    e.pass = find(ds, uid)?.pass;
+    ds.update(&e)?;
+    Ok((200, entity_json(&e)))
+}
+
+/// The agenda until a day, today by default, eg. ?until=+2w
+fn agenda(ds: &DataStore, params: &HashMap<String, String>) -> Result<Reply, DataError> {
+    let today = ds.settings().today();
+    let until = match params.get("until") {
+        Some(d) => super::parse_day(d, &today)?,
+        None => today,
+    };
+    let mut filter = AgendaFilter::default();
+    if let Some(class) = params.get("class") {
+        filter = filter.with_class(class);
+    }
+    if let Some(tag) = params.get("tag") {
+        filter = filter.with_tag_prefix(tag);
+    }
+    let page = ds.agenda_until(&until, &filter, 0, 0);
+    Ok((200, list_json(&page.items)))
+}
+
+fn record_note(ds: &mut DataStore, user: &Entity, body: &Value) -> Result<Reply, DataError> {
+    let text = match body["text"].as_str() {
+        Some(t) if !t.trim().is_empty() => t,
+        _ => return Ok(failure(400, "the text is required")),
+    };
+    let about = match body["about"].as_str() {
+        Some(uid) => Some(find(ds, uid)?),
+        None => None,
+    };
+    let report = ds.record_note(user, text, about.as_ref())?;
+    Ok((
+        201,
+        json!({
+            "event": utils::id(&report.event),
+            "captured": report.captured,
+            "ambiguous": report.ambiguous,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::valis::data::model::AccessRole;
+
+    #[test]
+    fn test_parse_url() {
+        let (segments, params) = parse_url("/api/search?q=jane%20doe&tag=a+b&flag");
+        assert_eq!(segments, vec!["api", "search"]);
+        assert_eq!(params.get("q").unwrap(), "jane doe");
+        assert_eq!(params.get("tag").unwrap(), "a b");
+        assert_eq!(params.get("flag").unwrap(), "");
+        assert_eq!(decode("100%"), "100%");
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        assert_eq!(is_loopback("127.0.0.1:7878").unwrap(), true);
+        assert_eq!(is_loopback("[::1]:7878").unwrap(), true);
+        assert_eq!(is_loopback("0.0.0.0:7878").unwrap(), false);
+        assert_eq!(is_loopback("192.168.1.10:7878").unwrap(), false);
+        assert_eq!(is_loopback("nowhere").is_err(), true);
+    }
+
+    #[test]
+    fn test_handle() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let owner = Entity::from("owner")
+            .unwrap()
+            .self_sponsored()
+            .with_tag(AccessRole::Owner.tag())
+            .with_password(Some(&"secret".to_string()));
+        ds.init(&owner).unwrap();
+        // no session, no access
+        let (status, _) = handle(&mut ds, "GET", "/api/entities", None, "");
+        assert_eq!(status, 401);
+        let (status, _) = handle(&mut ds, "GET", "/api/entities", Some("nope"), "");
+        assert_eq!(status, 401);
+        let (status, _) = handle(
+            &mut ds,
+            "POST",
+            "/api/login",
+            None,
+            r#"{"user": "owner", "password": "wrong"}"#,
+        );
+        assert_eq!(status, 403);
+        let (status, v) = handle(
+            &mut ds,
+            "POST",
+            "/api/login",
+            None,
+            r#"{"user": "owner", "password": "secret"}"#,
+        );
+        assert_eq!(status, 200);
+        let token = v["token"].as_str().unwrap().to_owned();
+        let t = Some(&token[..]);
+        // create, read and update
+        let (status, v) = handle(
+            &mut ds,
+            "POST",
+            "/api/entities",
+            t,
+            r#"{"name": "jane", "class": "person"}"#,
+        );
+        assert_eq!(status, 201);
+        let uid = v["uid"].as_str().unwrap().to_owned();
+        let jane = ds.get_by_uid(&uid).unwrap().unwrap();
+        assert_eq!(jane.class, "person");
+        assert_eq!(jane.sponsor, owner.uid);
+        let url = format!("/api/entities/{}", jane.uid());
+        let (status, v) = handle(&mut ds, "GET", &url, t, "");
+        assert_eq!(status, 200);
+        assert_eq!(v["name"], "jane");
+        assert_eq!(v["pass"], Value::Null);
+        let mut changed = jane.clone();
+        changed.description = "met at the fair".to_owned();
+        let body = serde_json::to_string(&changed).unwrap();
+        let (status, _) = handle(&mut ds, "PUT", &url, t, &body);
+        assert_eq!(status, 200);
+        assert_eq!(
+            ds.get_by_uid(&jane.uid()).unwrap().unwrap().description,
+            "met at the fair"
+        );
+        let (status, _) = handle(&mut ds, "PUT", "/api/entities/other", t, &body);
+        assert_eq!(status, 400);
+        // list, search and agenda
+        let (_, v) = handle(&mut ds, "GET", "/api/entities?class=person", t, "");
+        assert_eq!(v.as_array().unwrap().len(), 1);
+        let (_, v) = handle(&mut ds, "GET", "/api/search?q=jane", t, "");
+        assert_eq!(v[0]["name"], "jane");
+        let (status, _) = handle(&mut ds, "GET", "/api/agenda?until=%2B2w", t, "");
+        assert_eq!(status, 200);
+        let (status, _) = handle(&mut ds, "GET", "/api/agenda?until=someday", t, "");
+        assert_eq!(status, 400);
+        // notes and events
+        let events = format!("{}/events", url);
+        let (_, v) = handle(&mut ds, "GET", &events, t, "");
+        let before = v.as_array().unwrap().len();
+        let body = json!({ "text": "lunch with [[jane]]", "about": jane.uid() }).to_string();
+        let (status, v) = handle(&mut ds, "POST", "/api/notes", t, &body);
+        assert_eq!(status, 201);
+        assert_eq!(v["captured"].as_array().unwrap().len(), 0);
+        let (_, v) = handle(&mut ds, "GET", &events, t, "");
+        assert_eq!(v.as_array().unwrap().len(), before + 1);
+        // delete
+        let (status, _) = handle(&mut ds, "DELETE", &url, t, "");
+        assert_eq!(status, 200);
+        let (status, _) = handle(&mut ds, "GET", &url, t, "");
+        assert_eq!(status, 404);
+        // the token is gone after the logout
+        let (status, _) = handle(&mut ds, "POST", "/api/logout", t, "");
+        assert_eq!(status, 200);
+        let (status, _) = handle(&mut ds, "GET", "/api/entities", t, "");
+        assert_eq!(status, 401);
+    }
+}
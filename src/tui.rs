@@ -0,0 +1,496 @@
+use ::valis::data::{
+    ledger::{AgendaFilter, DataStore, EventFilter, Settings},
+    model::{Entity, Event, EventType},
+};
+use chrono::NaiveDate;
+use crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction as Axis, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+use std::error::Error;
+use std::io;
+
+/// The help shown in the status line while browsing
+const HELP: &str = "j/k move · tab switch pane · / search · e next action · r reload · q quit";
+
+/// The pane with the keyboard focus
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pane {
+    List,
+    Timeline,
+}
+
+/// What the keys do, the editing modes hold the input typed so far
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Browse,
+    /// the entities are searched while typing
+    Search(String),
+    /// the date of the next action, a date or a window from today
+    EditDate(String),
+    /// the note of the next action, once the date is chosen
+    EditNote(NaiveDate, String),
+}
+
+/// The full screen mode, with the agenda or the search results on the
+/// left and the detail and the timeline of the selected entity on the right
+pub struct Tui {
+    settings: Settings,
+    items: Vec<Entity>,
+    selected: usize,
+    /// the list holds the search results instead of the agenda
+    searching: bool,
+    focus: Pane,
+    /// the lines the timeline is scrolled by
+    scroll: u16,
+    mode: Mode,
+    status: String,
+}
+
+impl Tui {
+    pub fn new(ds: &DataStore) -> Tui {
+        let mut tui = Tui {
+            settings: ds.settings(),
+            items: Vec::new(),
+            selected: 0,
+            searching: false,
+            focus: Pane::List,
+            scroll: 0,
+            mode: Mode::Browse,
+            status: String::new(),
+        };
+        tui.load_agenda(ds);
+        tui
+    }
+
+    /// Load the entities with the next action within the agenda buckets
+    fn load_agenda(&mut self, ds: &DataStore) {
+        let mut until = self.settings.today();
+        for b in ds.agenda_buckets().iter() {
+            until = b.range(&until, &self.settings).1;
+        }
+        self.items = ds
+            .agenda_until(&until, &AgendaFilter::default(), 0, 0)
+            .items;
+        self.searching = false;
+        self.select(0);
+    }
+
+    fn select(&mut self, i: usize) {
+        self.selected = i.min(self.items.len().saturating_sub(1));
+        self.scroll = 0;
+    }
+
+    fn current(&self) -> Option<&Entity> {
+        self.items.get(self.selected)
+    }
+
+    /// React to a key, returns false when it is time to quit
+    fn handle_key(&mut self, ds: &mut DataStore, key: KeyCode) -> bool {
+        let mode = std::mem::replace(&mut self.mode, Mode::Browse);
+        self.mode = match mode {
+            Mode::Browse => return self.browse(ds, key),
+            Mode::Search(mut q) => match key {
+                KeyCode::Esc => {
+                    self.load_agenda(ds);
+                    Mode::Browse
+                }
+                KeyCode::Enter => Mode::Browse,
+                KeyCode::Backspace | KeyCode::Char(_) => {
+                    match key {
+                        KeyCode::Char(c) => q.push(c),
+                        _ => {
+                            q.pop();
+                        }
+                    }
+                    match q.trim().is_empty() {
+                        true => self.load_agenda(ds),
+                        false => {
+                            self.items = ds.search(q.trim());
+                            self.searching = true;
+                            self.select(0);
+                        }
+                    }
+                    Mode::Search(q)
+                }
+                _ => Mode::Search(q),
+            },
+            Mode::EditDate(mut s) => match key {
+                KeyCode::Esc => Mode::Browse,
+                KeyCode::Enter => match super::parse_day(&s, &self.settings.today()) {
+                    Ok(d) => {
+                        let note = self
+                            .current()
+                            .map(|e| e.next_action_note.clone())
+                            .unwrap_or_default();
+                        Mode::EditNote(d, note)
+                    }
+                    Err(e) => {
+                        self.status = e.to_string();
+                        Mode::EditDate(s)
+                    }
+                },
+                _ => {
+                    edit(&mut s, key);
+                    Mode::EditDate(s)
+                }
+            },
+            Mode::EditNote(d, mut s) => match key {
+                KeyCode::Esc => Mode::Browse,
+                KeyCode::Enter => {
+                    self.save_next_action(ds, d, s);
+                    Mode::Browse
+                }
+                _ => {
+                    edit(&mut s, key);
+                    Mode::EditNote(d, s)
+                }
+            },
+        };
+        true
+    }
+
+    /// The keys while browsing
+    fn browse(&mut self, ds: &mut DataStore, key: KeyCode) -> bool {
+        self.status.clear();
+        match (key, self.focus) {
+            (KeyCode::Char('q'), _) | (KeyCode::Esc, _) if self.searching => self.load_agenda(ds),
+            (KeyCode::Char('q'), _) | (KeyCode::Esc, _) => return false,
+            (KeyCode::Down, Pane::List) | (KeyCode::Char('j'), Pane::List) => {
+                self.select(self.selected + 1)
+            }
+            (KeyCode::Up, Pane::List) | (KeyCode::Char('k'), Pane::List) => {
+                self.select(self.selected.saturating_sub(1))
+            }
+            (KeyCode::Down, Pane::Timeline) | (KeyCode::Char('j'), Pane::Timeline) => {
+                self.scroll = self.scroll.saturating_add(1)
+            }
+            (KeyCode::Up, Pane::Timeline) | (KeyCode::Char('k'), Pane::Timeline) => {
+                self.scroll = self.scroll.saturating_sub(1)
+            }
+            (KeyCode::Tab, _) => {
+                self.focus = match self.focus {
+                    Pane::List => Pane::Timeline,
+                    Pane::Timeline => Pane::List,
+                }
+            }
+            (KeyCode::Char('/'), _) => self.mode = Mode::Search(String::new()),
+            (KeyCode::Char('e'), _) if self.current().is_some() => {
+                self.mode = Mode::EditDate(self.settings.reminder.to_string())
+            }
+            (KeyCode::Char('r'), _) => self.load_agenda(ds),
+            _ => {}
+        }
+        true
+    }
+
+    /// Store the next action of the selected entity
+    fn save_next_action(&mut self, ds: &mut DataStore, date: NaiveDate, note: String) {
+        let mut e = match self.current() {
+            Some(e) => e.clone(),
+            None => return,
+        };
+        e.next_action(date, note);
+        match ds.update(&e) {
+            Ok(_) => {
+                self.status = format!(
+                    "the next action of {} is on {}",
+                    e.name(),
+                    self.settings.human_date(&date)
+                );
+                match self.searching {
+                    true => self.items[self.selected] = e,
+                    false => {
+                        // the entity may have left the agenda
+                        let selected = self.selected;
+                        self.load_agenda(ds);
+                        self.select(selected);
+                    }
+                }
+            }
+            Err(err) => self.status = format!("cannot update {}: {}", e.name(), err),
+        }
+    }
+
+    fn draw(&self, f: &mut Frame, ds: &DataStore) {
+        let rows = Layout::default()
+            .direction(Axis::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(1)])
+            .split(f.size());
+        let cols = Layout::default()
+            .direction(Axis::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .split(rows[0]);
+        let panes = Layout::default()
+            .direction(Axis::Vertical)
+            .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+            .split(cols[1]);
+        let block = |title: String, pane: Option<Pane>| {
+            let b = Block::default().borders(Borders::ALL).title(title);
+            match pane == Some(self.focus) {
+                true => b.border_style(Style::default().add_modifier(Modifier::BOLD)),
+                false => b,
+            }
+        };
+        // the list
+        let title = match (&self.mode, self.searching) {
+            (Mode::Search(q), _) => format!("Search: {}", q),
+            (_, true) => "Search results".to_owned(),
+            _ => "Agenda".to_owned(),
+        };
+        let items = self
+            .items
+            .iter()
+            .map(|e| {
+                ListItem::new(format!(
+                    "{}  {}",
+                    self.settings.human_date(&e.next_action_date),
+                    e.name()
+                ))
+            })
+            .collect::<Vec<ListItem>>();
+        let list = List::new(items)
+            .block(block(title, Some(Pane::List)))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        let mut state = ListState::default();
+        state.select(self.current().map(|_| self.selected));
+        f.render_stateful_widget(list, cols[0], &mut state);
+        // the detail and the timeline
+        let (detail, timeline) = match self.current() {
+            Some(e) => (self.detail(e), timeline(ds, e, &self.settings)),
+            None => (vec![Line::from("nothing to show")], vec![]),
+        };
+        f.render_widget(
+            Paragraph::new(detail)
+                .block(block("Detail".to_owned(), None))
+                .wrap(Wrap { trim: false }),
+            panes[0],
+        );
+        f.render_widget(
+            Paragraph::new(timeline)
+                .block(block("Timeline".to_owned(), Some(Pane::Timeline)))
+                .wrap(Wrap { trim: false })
+                .scroll((self.scroll, 0)),
+            panes[1],
+        );
+        // the status line
+        let status = match &self.mode {
+            Mode::Browse if self.status.is_empty() => HELP.to_owned(),
+            Mode::Browse => self.status.clone(),
+            Mode::Search(q) => format!("/{}", q),
+            Mode::EditDate(s) if self.status.is_empty() => {
+                format!("next action on (date or window): {}", s)
+            }
+            Mode::EditDate(s) => format!("{}, next action on: {}", self.status, s),
+            Mode::EditNote(d, s) => format!("note for {}: {}", self.settings.human_date(d), s),
+        };
+        f.render_widget(Paragraph::new(status), rows[1]);
+    }
+
+    /// The lines describing an entity
+    fn detail(&self, e: &Entity) -> Vec<Line<'static>> {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let mut lines = vec![
+            Line::from(Span::styled(e.name().to_owned(), bold)),
+            Line::from(format!(
+                "{} {} {}",
+                e.class,
+                e.state.emoji(),
+                e.quality.emoji()
+            )),
+            Line::from(""),
+            Line::from(Span::styled(
+                format!(
+                    "Next action on {}",
+                    self.settings.human_date(&e.next_action_date)
+                ),
+                bold,
+            )),
+        ];
+        lines.extend(e.next_action_note.lines().map(|l| Line::from(l.to_owned())));
+        if !e.description.is_empty() {
+            lines.push(Line::from(""));
+            lines.extend(e.description.lines().map(|l| Line::from(l.to_owned())));
+        }
+        let handles = e.all_handles();
+        if !handles.is_empty() {
+            lines.push(Line::from(""));
+            for h in handles.iter() {
+                lines.push(Line::from(format!("{}: {}", h.label, h.value)));
+            }
+        }
+        if !e.tags.is_empty() {
+            let mut tags = e.tags.values().map(|t| t.to_string()).collect::<Vec<_>>();
+            tags.sort();
+            lines.push(Line::from(""));
+            lines.push(Line::from(format!("Tags {}", tags.join(", "))));
+        }
+        lines
+    }
+}
+
+/// Apply an editing key to an input
+fn edit(s: &mut String, key: KeyCode) {
+    match key {
+        KeyCode::Char(c) => s.push(c),
+        KeyCode::Backspace => {
+            s.pop();
+        }
+        _ => {}
+    }
+}
+
+/// A short label for the kind of an event
+fn kind_label(evt: &Event) -> String {
+    match &evt.kind {
+        EventType::Action(_, name, _) => name.to_owned(),
+        EventType::Log(msg) => msg.to_owned(),
+        EventType::Interaction(c, _, _) => c.to_string(),
+    }
+}
+
+/// The lines of the events of an entity, the most recent first
+fn timeline(ds: &DataStore, e: &Entity, settings: &Settings) -> Vec<Line<'static>> {
+    let mut events = ds.events(e, EventFilter::Any);
+    events.sort_by(|a, b| b.recorded_at.cmp(&a.recorded_at));
+    let mut lines = Vec::new();
+    for evt in events.iter() {
+        lines.push(Line::from(Span::styled(
+            format!(
+                "{} {}",
+                settings.human_date(&evt.recorded_at.naive_local().date()),
+                kind_label(evt)
+            ),
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        if let Some(c) = &evt.content {
+            lines.extend(c.lines().map(|l| Line::from(format!("  {}", l))));
+        }
+    }
+    if lines.is_empty() {
+        lines.push(Line::from("no events yet"));
+    }
+    lines
+}
+
+fn event_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    tui: &mut Tui,
+    ds: &mut DataStore,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        terminal.draw(|f| tui.draw(f, ds))?;
+        if let TermEvent::Key(k) = event::read()? {
+            // only the presses, some terminals report the releases too
+            if k.kind == KeyEventKind::Press && !tui.handle_key(ds, k.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Run the full screen mode until the user quits, the
+/// terminal is restored even if something goes wrong
+pub fn run(ds: &mut DataStore) -> Result<(), Box<dyn Error>> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut tui = Tui::new(ds);
+    let out = event_loop(&mut terminal, &mut tui, ds);
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::valis::data::model::TimeWindow;
+    use ratatui::backend::TestBackend;
+
+    fn press(tui: &mut Tui, ds: &mut DataStore, keys: &str) {
+        for c in keys.chars() {
+            tui.handle_key(ds, KeyCode::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_tui() {
+        let d = tempfile::TempDir::new().unwrap();
+        let mut ds = DataStore::open(d.path()).unwrap();
+        let owner = Entity::from("owner").unwrap().self_sponsored();
+        ds.init(&owner).unwrap();
+        let today = ds.settings().today();
+        let mut alice = Entity::from("alice").unwrap().with_sponsor(&owner);
+        alice.next_action(today, "call".to_owned());
+        let mut bob = Entity::from("bob").unwrap().with_sponsor(&owner);
+        bob.next_action(today + chrono::Duration::days(1), String::new());
+        let mut carl = Entity::from("carl").unwrap().with_sponsor(&owner);
+        carl.next_action(today + chrono::Duration::days(90), String::new());
+        for e in [&alice, &bob, &carl].iter() {
+            ds.add(e).unwrap();
+        }
+        // carl is past the agenda
+        let mut tui = Tui::new(&ds);
+        let names = |t: &Tui| {
+            t.items
+                .iter()
+                .map(|e| e.name().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&tui).contains(&"carl".to_owned()), false);
+        assert_eq!(names(&tui).contains(&"alice".to_owned()), true);
+        // navigation stays within the list
+        press(&mut tui, &mut ds, "kkk");
+        assert_eq!(tui.selected, 0);
+        press(&mut tui, &mut ds, "jjjjjjjjjj");
+        assert_eq!(tui.selected, tui.items.len() - 1);
+        tui.handle_key(&mut ds, KeyCode::Tab);
+        press(&mut tui, &mut ds, "jj");
+        assert_eq!(tui.scroll, 2);
+        assert_eq!(tui.selected, tui.items.len() - 1);
+        tui.handle_key(&mut ds, KeyCode::Tab);
+        // search while typing
+        press(&mut tui, &mut ds, "/carl");
+        assert_eq!(tui.searching, true);
+        assert_eq!(names(&tui)[0], "carl");
+        tui.handle_key(&mut ds, KeyCode::Enter);
+        assert_eq!(tui.mode, Mode::Browse);
+        // edit the next action, the reminder window is proposed
+        tui.handle_key(&mut ds, KeyCode::Char('e'));
+        assert_eq!(tui.mode, Mode::EditDate("1w".to_owned()));
+        tui.handle_key(&mut ds, KeyCode::Backspace);
+        press(&mut tui, &mut ds, "w");
+        tui.handle_key(&mut ds, KeyCode::Enter);
+        press(&mut tui, &mut ds, "lunch");
+        tui.handle_key(&mut ds, KeyCode::Enter);
+        let stored = ds.get_by_uid(&carl.uid()).unwrap().unwrap();
+        assert_eq!(stored.next_action_date, TimeWindow::Week(1).offset(&today));
+        assert_eq!(stored.next_action_note, "lunch");
+        // an invalid date is not accepted
+        press(&mut tui, &mut ds, "e");
+        tui.handle_key(&mut ds, KeyCode::Backspace);
+        tui.handle_key(&mut ds, KeyCode::Backspace);
+        press(&mut tui, &mut ds, "soon");
+        tui.handle_key(&mut ds, KeyCode::Enter);
+        assert_eq!(tui.mode, Mode::EditDate("soon".to_owned()));
+        tui.handle_key(&mut ds, KeyCode::Esc);
+        // back to the agenda, now with carl
+        tui.handle_key(&mut ds, KeyCode::Char('q'));
+        assert_eq!(tui.searching, false);
+        assert_eq!(names(&tui).contains(&"carl".to_owned()), true);
+        // it renders on a small screen
+        let mut terminal = Terminal::new(TestBackend::new(60, 12)).unwrap();
+        terminal.draw(|f| tui.draw(f, &ds)).unwrap();
+        assert_eq!(tui.handle_key(&mut ds, KeyCode::Char('q')), false);
+    }
+}
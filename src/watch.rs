@@ -0,0 +1,158 @@
+use ::valis::data::{
+    backup,
+    context::CtxError,
+    ledger::{AgendaFilter, DataError, DataStore, ExportFormat},
+    model::Entity,
+    utils,
+};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the running flag is checked while sleeping
+const TICK: Duration = Duration::from_millis(500);
+
+/// The watch mode keeps VALIS running, it checks the due
+/// actions every interval and reacts to the datastore changes
+///
+/// The datastore is open only while checking, between the checks
+/// it is closed so the other commands can be used. Since there is
+/// no handle to subscribe to, the changes are found comparing each
+/// check with the previous one
+pub struct Watch {
+    /// how often to check for due actions
+    pub interval: Duration,
+    /// an url that receives a POST for every due action
+    pub webhook: Option<String>,
+    /// an ics file kept up to date with the recorded events
+    pub feed: Option<PathBuf>,
+    /// the directory where to store the scheduled backups
+    pub backups: Option<PathBuf>,
+    /// the due actions already notified
+    notified: HashSet<String>,
+    /// the checksum of the events when the feed was written
+    feed_checksum: Option<u32>,
+}
+
+impl Watch {
+    pub fn new(interval: Duration) -> Watch {
+        Watch {
+            interval,
+            webhook: None,
+            feed: None,
+            backups: None,
+            notified: HashSet::new(),
+            feed_checksum: None,
+        }
+    }
+
+    pub fn with_webhook(mut self, url: Option<&str>) -> Self {
+        self.webhook = url.map(|u| u.to_owned());
+        self
+    }
+
+    pub fn with_feed(mut self, path: Option<&str>) -> Self {
+        self.feed = path.map(PathBuf::from);
+        self
+    }
+
+    pub fn with_backups(mut self, dir: PathBuf) -> Self {
+        self.backups = Some(dir);
+        self
+    }
+
+    /// Run the watch loop until the running flag is cleared
+    ///
+    /// The datastore is opened with `open` for every check and closed
+    /// right after, a check is skipped when the datastore is in use
+    pub fn run<F>(&mut self, open: F, running: Arc<AtomicBool>) -> Result<(), CtxError>
+    where
+        F: Fn() -> Result<DataStore, CtxError>,
+    {
+        while running.load(Ordering::SeqCst) {
+            match open() {
+                Ok(mut ds) => {
+                    self.check(&mut ds)?;
+                    ds.close()?;
+                }
+                Err(CtxError::DatasetLocked(_)) | Err(CtxError::DatasetInUse) => {}
+                Err(err) => return Err(err),
+            }
+            // wait for the next round
+            let start = Instant::now();
+            while running.load(Ordering::SeqCst) && start.elapsed() < self.interval {
+                thread::sleep(TICK);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the datastore once
+    fn check(&mut self, ds: &mut DataStore) -> Result<(), DataError> {
+        // the feed is written again when the events changed
+        let checksum = ds.events_checksum()?;
+        if self.feed_checksum != Some(checksum) {
+            self.refresh_feed(ds)?;
+            self.feed_checksum = Some(checksum);
+        }
+        // notify the due actions, an action that was moved
+        // or changed its note is notified again
+        let mut due = HashSet::new();
+        for e in ds
+            .agenda_until(&utils::today(), &AgendaFilter::default(), 0, 0)
+            .items
+        {
+            let key = format!("{}:{}:{}", e.uid(), e.next_action_date, e.next_action_note);
+            if !self.notified.contains(&key) {
+                self.notify(&e);
+            }
+            due.insert(key);
+        }
+        self.notified = due;
+        // take a backup if it is due
+        if let Some(dir) = &self.backups {
+            if ds.backup_due(&utils::now_local()) {
+                let p = ds.backup(dir)?;
+                backup::prune(dir, &ds.backup_retention())?;
+                println!("backup saved in {}", p.to_string_lossy());
+            }
+        }
+        Ok(())
+    }
+
+    /// Notify a due action on the console and to the webhook
+    fn notify(&self, e: &Entity) {
+        println!(
+            "{} due {}: {}",
+            e.name(),
+            utils::human_date(&e.next_action_date),
+            e.next_action_note
+        );
+        if let Some(url) = &self.webhook {
+            let payload = json!({
+                "uid": e.uid(),
+                "name": e.name(),
+                "date": e.next_action_date.to_string(),
+                "note": e.next_action_note,
+            });
+            if let Err(err) = ureq::post(url)
+                .set("Content-Type", "application/json")
+                .send_string(&payload.to_string())
+            {
+                eprintln!("webhook call to {} failed: {}", url, err);
+            }
+        }
+    }
+
+    /// Export the recorded events to the feed file, if any
+    fn refresh_feed(&self, ds: &DataStore) -> Result<(), DataError> {
+        if let Some(path) = &self.feed {
+            ds.export(path, ExportFormat::Ics)?;
+        }
+        Ok(())
+    }
+}